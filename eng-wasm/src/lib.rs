@@ -39,6 +39,7 @@ pub mod external {
         pub fn rand(payload: *const u8, payload_len: u32);
         pub fn encrypt(message: *const u8, message_len: u32, key: *const u8, payload: *const u8);
         pub fn decrypt(cipheriv: *const u8, cipheriv_len: u32, key: *const u8, payload: *const u8);
+        pub fn allow_caller(pubkey: *const u8);
     }
 }
 
@@ -96,6 +97,13 @@ pub fn write_ethereum_bridge(payload: &[u8], address: &Address) {
     unsafe { external::write_eth_bridge(payload.as_ptr(), payload.len() as u32, address.as_ptr()) };
 }
 
+/// Restricts the contract to the given callers, this can only be called from the constructor.
+/// `pubkey` is the 64 bytes public key a user sends in `NewTaskEncryptionKey`.
+/// A contract that never calls it can be called by anyone, once it does, tasks from other users fail as `Forbidden`.
+pub fn allow_caller(pubkey: &[u8; 64]) {
+    unsafe { external::allow_caller(pubkey.as_ptr()) };
+}
+
 #[macro_export]
 macro_rules! write_state {
      ( $($key: expr => $val: expr),+ ) => {
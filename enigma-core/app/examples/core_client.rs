@@ -0,0 +1,79 @@
+//! Deploys the `simplest` example contract on a running core and computes an addition on it, through `CoreClient`.
+//! ```sh
+//! cargo run --features client --example core_client -- tcp://localhost:5552
+//! ```
+//! The keys of the contract come from the principal node. Without one, the PTT round is answered here with the fake
+//! state keys of `cross-test-utils`, like in the integration tests.
+//! The output of the addition is taken from the envelope the enclave addressed to the user's key.
+
+extern crate cross_test_utils;
+extern crate enigma_core_app as app;
+extern crate enigma_crypto;
+extern crate ethabi;
+extern crate rmp_serde;
+extern crate rustc_hex;
+extern crate serde;
+
+use app::networking::client::{open_output_envelope, CoreClient};
+use app::networking::messages::IpcTask;
+use app::serde_json::Value;
+use cross_test_utils::{generate_contract_address, get_bytecode_from_path, make_encrypted_response};
+use enigma_crypto::{asymmetric::KeyPair, symmetric};
+use ethabi::{ParamType, Token};
+use rustc_hex::{FromHex, ToHex};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+const CONTRACT_PATH: &str = "../../examples/eng_wasm_contracts/simplest";
+const GAS_LIMIT: u64 = 100_000_000;
+
+fn task(pre_code: Option<Vec<u8>>, address: &str, callable: &str, args: &[Token], user: &KeyPair, key: &[u8; 32]) -> IpcTask {
+    IpcTask {
+        pre_code,
+        encrypted_args: symmetric::encrypt(&ethabi::encode(args), key).unwrap().to_hex(),
+        encrypted_fn: symmetric::encrypt(callable.as_bytes(), key).unwrap().to_hex(),
+        user_dhkey: user.get_pubkey().to_hex(),
+        gas_limit: GAS_LIMIT,
+        address: address.to_string(),
+        block_number: None,
+        epoch_nonce: None,
+        debug_trace: false,
+        expected_tip: None,
+        task_id: None,
+        output_envelope: false,
+    }
+}
+
+fn main() {
+    let addr = env::args().nth(1).unwrap_or_else(|| "tcp://localhost:5552".to_string());
+    let mut client = CoreClient::connect(&addr).expect("Can't connect to the core");
+    let address = generate_contract_address();
+
+    // The PTT round, the principal node's part.
+    let ptt = client.get_ptt_request().unwrap();
+    let packed: Vec<u8> = ptt["result"]["request"].as_str().unwrap().from_hex().unwrap();
+    let request: Value = Deserialize::deserialize(&mut rmp_serde::Deserializer::new(&packed[..])).unwrap();
+    let mut response = Vec::new();
+    make_encrypted_response(&request, vec![address], None).serialize(&mut rmp_serde::Serializer::new(&mut response)).unwrap();
+    client.ptt_response(&response.to_hex()).unwrap();
+
+    // The key the user encrypts the arguments of the tasks with.
+    let user = KeyPair::new().unwrap();
+    let res = client.new_task_encryption_key(&user.get_pubkey().to_hex()).unwrap();
+    let worker_key: Vec<u8> = res["result"]["workerEncryptionKey"].as_str().unwrap().from_hex().unwrap();
+    let mut worker_pubkey = [0u8; 64];
+    worker_pubkey.copy_from_slice(&worker_key);
+    let key = user.derive_key(&worker_pubkey).unwrap();
+
+    let pre_code = get_bytecode_from_path(CONTRACT_PATH);
+    let deploy = task(Some(pre_code), &address.to_hex(), "construct(uint)", &[Token::Uint(17.into())], &user, &key);
+    let deployed = client.deploy_secret_contract(deploy).unwrap();
+    println!("Deployed {}, used gas: {}", address.to_hex(), deployed["result"]["attested"]["usedGas"]);
+
+    let args = [Token::Uint(24.into()), Token::Uint(67.into())];
+    let compute = IpcTask { output_envelope: true, ..task(None, &address.to_hex(), "addition(uint,uint)", &args, &user, &key) };
+    let computed = client.compute_task(compute).unwrap();
+    let output = open_output_envelope(&computed, &user).expect("The envelope doesn't open");
+    let sum = ethabi::decode(&[ParamType::Uint(256)], &symmetric::decrypt(&output, &key).unwrap()).unwrap();
+    println!("24 + 67 = {}", sum[0]);
+}
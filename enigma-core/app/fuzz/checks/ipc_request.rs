@@ -0,0 +1,33 @@
+// The checks of the `ipc_request` fuzz target, `tests/fuzz_corpus.rs` runs them on the corpus too.
+
+use app::networking::ipc_listener::parse_request;
+use app::networking::messages::IpcMessageRequest;
+use app::networking::request_id::{self, MAX_ID_LEN};
+use app::serde_json;
+
+/// The frames of a multipart message are separated by a 0 byte in the fuzzed input.
+pub const FRAME_SEPARATOR: u8 = 0;
+
+/// Every frame is either parsed into a request or answered with an error, without panicking.
+/// A response only echoes an id of at most `MAX_ID_LEN` bytes, and its size is bounded by the size of the frame.
+pub fn check(data: &[u8]) {
+    for frame in data.split(|&byte| byte == FRAME_SEPARATOR) {
+        let id = request_id::frame_id(frame);
+        assert!(id.echo().len() <= MAX_ID_LEN);
+        match parse_request(frame) {
+            Ok(msg) => {
+                assert_eq!(msg.id, id.echo());
+                // What's accepted is sent back the same way by the p2p node and the client.
+                let encoded = serde_json::to_vec(&msg).unwrap();
+                let reparsed: IpcMessageRequest = serde_json::from_slice(&encoded).unwrap();
+                assert_eq!(reparsed.request.kind(), msg.request.kind());
+            }
+            Err(response) => {
+                assert!(response.id.len() <= MAX_ID_LEN);
+                let encoded = serde_json::to_vec(&response).unwrap();
+                // The error may quote the frame, escaped, next to the list of the request types.
+                assert!(encoded.len() <= 8 * 1024 + 6 * frame.len(), "{} bytes answering {} bytes", encoded.len(), frame.len());
+            }
+        }
+    }
+}
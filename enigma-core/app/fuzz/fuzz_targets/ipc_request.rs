@@ -0,0 +1,12 @@
+//! Feeds arbitrary multipart messages to the parsing of the IPC requests, see `checks/ipc_request.rs`.
+//! `cargo +nightly fuzz run ipc_request -- -malloc_limit_mb=256` from `app/`, the inputs it finds worth keeping
+//! go to `corpus/ipc_request/` and are replayed by `tests/fuzz_corpus.rs`.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate enigma_core_app as app;
+
+#[path = "../checks/ipc_request.rs"]
+mod checks;
+
+fuzz_target!(|data: &[u8]| checks::check(data));
@@ -28,6 +28,37 @@ extern "C" {
         address: *const ContractAddress,
         user_key: *mut [u8; 64usize],
         gas_limit: *const u64,
+        debug_trace: u8,
+        db_ptr: *const RawPointer,
+        result: *mut ExecuteResult,
+    ) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_deploy_begin(eid: sgx_enclave_id_t, retval: *mut EnclaveReturn, total_len: usize, handle: *mut u64) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_deploy_chunk(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        handle: u64,
+        chunk: *const u8,
+        chunk_len: usize,
+    ) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_deploy_finish(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        handle: u64,
+        bytecode_hash: *const [u8; 32usize],
+        construct: *const u8,
+        construct_len: usize,
+        args: *const u8,
+        args_len: usize,
+        address: *const ContractAddress,
+        user_key: *mut [u8; 64usize],
+        gas_limit: *const u64,
+        debug_trace: u8,
         db_ptr: *const RawPointer,
         result: *mut ExecuteResult,
     ) -> sgx_status_t;
@@ -45,23 +76,67 @@ extern "C" {
         pubkey: *mut [u8; 64usize],
         address: *const ContractAddress,
         gas_limit: *const u64,
+        debug_trace: u8,
+        output_envelope: u8,
         db_ptr: *const RawPointer,
         result: *mut ExecuteResult,
     ) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_replay(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        bytecode: *const u8,
+        bytecode_len: usize,
+        callable: *const u8,
+        callable_len: usize,
+        callable_args: *const u8,
+        callable_args_len: usize,
+        pubkey: *mut [u8; 64usize],
+        address: *const ContractAddress,
+        sealed_io_key: *const u8,
+        sealed_io_key_len: usize,
+        receipt: *const ReplayReceipt,
+        receipt_sig: *mut [u8; 65usize],
+        ethereum_payload: *const u8,
+        ethereum_payload_len: usize,
+        output: *const u8,
+        output_len: usize,
+        delta: *const u8,
+        delta_len: usize,
+        db_ptr: *const RawPointer,
+        result: *mut ReplayResult,
+    ) -> sgx_status_t;
+}
 extern "C" {
     pub fn ecall_get_signing_address(eid: sgx_enclave_id_t, arr: *mut [u8; 20usize]) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_set_network(eid: sgx_enclave_id_t, chain_id: u64) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_ping(eid: sgx_enclave_id_t) -> sgx_status_t;
+}
 extern "C" {
     pub fn ecall_ptt_req(
         eid: sgx_enclave_id_t,
         retval: *mut EnclaveReturn,
+        block_number: u64,
+        has_block_number: u8,
         sig: *mut [u8; 65usize],
         serialized_ptr: *mut u64,
     ) -> sgx_status_t;
 }
 extern "C" {
-    pub fn ecall_ptt_res(eid: sgx_enclave_id_t, retval: *mut EnclaveReturn, msg_ptr: *const u8, msg_len: usize) -> sgx_status_t;
+    pub fn ecall_ptt_res(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        msg_ptr: *const u8,
+        msg_len: usize,
+        addrs: *const u8,
+        addrs_len: usize,
+        has_addrs: u8,
+    ) -> sgx_status_t;
 }
 extern "C" {
     pub fn ecall_build_state(
@@ -71,6 +146,13 @@ extern "C" {
         failed_ptr: *mut u64,
     ) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_get_provisioned_addresses(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        addrs_ptr: *mut u64,
+    ) -> sgx_status_t;
+}
 extern "C" {
     pub fn ecall_get_user_key(
         eid: sgx_enclave_id_t,
@@ -80,3 +162,11 @@ extern "C" {
         serialized_ptr: *mut u64,
     ) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_export_audit_digest(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        sig: *mut [u8; 65usize],
+        serialized_ptr: *mut u64,
+    ) -> sgx_status_t;
+}
@@ -0,0 +1,23 @@
+//! Writes the wire format fixtures of this release, run it when cutting a release:
+//! `cargo run --bin gen-wire-fixtures [version] [dir]`,
+//! the version defaults to the version of the crate and the dir to `tests/wire_fixtures`.
+
+extern crate enigma_core_app;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use enigma_core_app::networking::wire_fixtures;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let version = args.next().unwrap_or_else(|| format!("v{}", env!("CARGO_PKG_VERSION")));
+    let dir = args.next().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/wire_fixtures"));
+
+    if let Err(e) = wire_fixtures::write(&dir, &version) {
+        eprintln!("Failed writing the fixtures of {} to {:?}: {}", version, dir, e);
+        process::exit(1);
+    }
+    println!("Wrote the fixtures of {} to {:?}", version, dir.join(&version));
+}
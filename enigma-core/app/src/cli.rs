@@ -4,18 +4,34 @@
 //! it uses rustdocs for the `--help` menu, and proc macros to get long/short and parsing methods. <br>
 //! it is used by running `let opt: Opt = Opt::from_args();` and then it will fill up the struct from the user inputs.
 //! (and of course fail if needed)
+//! The options can also come from a config file and the environment, see `config::resolve`.
 
+use std::fmt;
 use std::path::PathBuf;
+use serde::Serializer;
 use structopt::StructOpt;
+use common_u::network::Network;
+use config::REDACTED;
+use db::{HostingMode, MirrorMode, MirrorTarget, OrphanPolicy, RebuildScope, WarmupMode};
 
-#[derive(Debug, StructOpt)]
+// Serialized as the resolved config of `--print-config`, by the names of the fields.
+#[derive(Debug, StructOpt, Serialize)]
 #[structopt(name = "Enigma Core", about = "Enigma Core CLI commands.")]
 pub struct Opt {
+    /// Optional: a JSON file of options, by their names in `--print-config` (e.g. `{"port": 5552, "mirror_mode": "sync"}`).
+    /// The environment (`ENIGMA_CORE_<OPTION>`) overrides it and the command line overrides both
+    #[structopt(parse(from_os_str), long = "config")]
+    pub config: Option<PathBuf>,
+    /// Print the resolved options as JSON and exit, without starting anything
+    #[structopt(long = "print-config")]
+    #[serde(skip)]
+    pub print_config: bool,
     /// Specify data directory
     #[structopt(parse(from_os_str), long = "data-dir")]
     pub data_dir: Option<PathBuf>,
     /// Specify a different SPID to use for the Quote/Report
     #[structopt(long = "spid", default_value = "B0335FD3BC1CCA8F804EB98A6420592D")]
+    #[serde(serialize_with = "redacted")]
     pub spid: String,
     /// Select a port for the enigma-p2p listener
     #[structopt(long = "port", short = "p", default_value = "5552")]
@@ -26,4 +42,139 @@ pub struct Opt {
     /// Optional: change the minimum log level
     #[structopt(short = "l", long = "log-level", default_value = "info")]
     pub log_level: String,
-}
\ No newline at end of file
+    /// Optional: serve Prometheus metrics over HTTP on this address (e.g. 127.0.0.1:9100), disabled by default
+    #[structopt(long = "metrics-bind")]
+    pub metrics_bind: Option<String>,
+    /// Optional: the Ethereum network (mainnet, ropsten, kovan or a chain id), keeps the DB and the sealed keys in a
+    /// directory per network and refuses to open a DB that was created for another one
+    #[structopt(long = "network")]
+    #[serde(serialize_with = "display_opt")]
+    pub network: Option<Network>,
+    /// Optional: send the IPC statuses as the old integers (0 / -1) instead of strings, for p2p nodes that weren't updated yet
+    #[structopt(long = "legacy-status")]
+    pub legacy_status: bool,
+    /// Optional: how to preload the most executed contracts on startup (off, blocking or background)
+    #[structopt(long = "warmup", default_value = "background")]
+    #[serde(serialize_with = "display")]
+    pub warmup: WarmupMode,
+    /// Optional: how many blocks around an epoch transition tasks of the neighbouring epoch are still executed
+    #[structopt(long = "epoch-grace-blocks", default_value = "5")]
+    pub epoch_grace_blocks: u64,
+    /// Optional: refuse tasks more than this many blocks after the start of the last known epoch
+    #[structopt(long = "max-epoch-age")]
+    pub max_epoch_age: Option<u64>,
+    /// Optional: store the delta of every computed task right away, instead of waiting for the p2p node to send it back
+    #[structopt(long = "persist-task-deltas")]
+    pub persist_task_deltas: bool,
+    /// Optional: the state keys were lost, don't compute tasks until the KM node sent back the keys of all the hosted contracts
+    #[structopt(long = "recover")]
+    pub recover: bool,
+    /// Optional: how many seconds to wait for the keys in a recovery before accepting tasks anyway
+    #[structopt(long = "recover-timeout", default_value = "600")]
+    pub recover_timeout: u64,
+    /// Optional: how many of the past registrations (report, IAS signature, ...) are kept for audits
+    #[structopt(long = "registration-history", default_value = "100")]
+    pub registration_history: usize,
+    /// Optional: copy every write to the contracts to a standby, either a directory or the IPC endpoint of another core (tcp://...)
+    #[structopt(long = "mirror")]
+    #[serde(serialize_with = "display_opt")]
+    pub mirror: Option<MirrorTarget>,
+    /// Optional: whether a write waits for the standby (async or sync)
+    #[structopt(long = "mirror-mode", default_value = "async")]
+    #[serde(serialize_with = "display")]
+    pub mirror_mode: MirrorMode,
+    /// Optional: how many writes can wait for the standby before they're dropped
+    #[structopt(long = "mirror-buffer", default_value = "10000")]
+    pub mirror_buffer: usize,
+    /// Optional: whether deltas are stored before the bytecode of their contract (reject or allow),
+    /// a request can still allow them with `allowOrphan`
+    #[structopt(long = "orphan-deltas", default_value = "reject")]
+    #[serde(serialize_with = "display")]
+    pub orphan_deltas: OrphanPolicy,
+    /// Optional: refuse new contracts and deltas once the DB directory would grow over this many bytes
+    #[structopt(long = "max-db-bytes")]
+    pub max_db_bytes: Option<u64>,
+    /// Optional: refuse the deltas that would take the deltas of a contract over this many bytes
+    #[structopt(long = "max-contract-delta-bytes")]
+    pub max_contract_delta_bytes: Option<u64>,
+    /// Optional: refuse new contracts once this many are hosted
+    #[structopt(long = "max-contracts")]
+    pub max_contracts: Option<u64>,
+    /// Optional: which contracts are stored (all, selected-only or explicit-list), see `PTTResponse` and `SetHostedContracts`
+    #[structopt(long = "hosting-mode", default_value = "all")]
+    #[serde(serialize_with = "display")]
+    pub hosting_mode: HostingMode,
+    /// Optional: keep the known contract addresses in a bloom filter with this false positive rate instead of a set
+    #[structopt(long = "address-index-fp-rate")]
+    pub address_index_fp_rate: Option<f64>,
+    /// Optional: how many seconds between two pings of the enclave by the watchdog, 0 disables it
+    #[structopt(long = "watchdog-interval", default_value = "10")]
+    pub watchdog_interval: u64,
+    /// Optional: report the enclave as degraded while the p95 latency of the last pings is over this many milliseconds
+    #[structopt(long = "watchdog-p95-ms", default_value = "250")]
+    pub watchdog_p95_ms: u64,
+    /// Optional: a ping slower than this many milliseconds counts as failed
+    #[structopt(long = "watchdog-timeout-ms", default_value = "5000")]
+    pub watchdog_timeout_ms: u64,
+    /// Optional: re-create the enclave after this many failed pings in a row
+    #[structopt(long = "watchdog-failures", default_value = "3")]
+    pub watchdog_failures: u32,
+    /// Optional: how many requests can wait for the handlers, the ones that come when it's full are answered with Busy right away
+    #[structopt(long = "queue-capacity", default_value = "64")]
+    pub queue_capacity: usize,
+    /// Optional: compress the responses of at least this many bytes for the requests with `"accept_encoding": "zstd"`
+    #[structopt(long = "compression-threshold", default_value = "8192")]
+    pub compression_threshold: usize,
+    /// Optional: report the handlers as unhealthy after this many panics within `--panic-breaker-window`
+    #[structopt(long = "panic-breaker-count", default_value = "5")]
+    pub panic_breaker_count: usize,
+    /// Optional: the window of `--panic-breaker-count`, in seconds
+    #[structopt(long = "panic-breaker-window", default_value = "60")]
+    pub panic_breaker_window: u64,
+    /// Optional: prune the DB (expired registrations, stale hot set entries) and compact it every this many seconds, 0 disables it
+    #[structopt(long = "maintenance-interval", default_value = "3600")]
+    pub maintenance_interval: u64,
+    /// Optional: postpone the maintenance while more than this many requests wait for the handlers
+    #[structopt(long = "maintenance-max-queue-depth", default_value = "0")]
+    pub maintenance_max_queue_depth: usize,
+    /// Optional: a JSON file of token bucket limits per request type (and a global one), see `common_u::rate_limit`.
+    /// It's read again on the `ReloadConfig` request
+    #[structopt(parse(from_os_str), long = "rate-limits")]
+    pub rate_limits: Option<PathBuf>,
+    /// Optional: return the trace of a task (host calls, argument sizes, gas) when it asks for it with `debugTrace`,
+    /// only accepted by a debug build, for testing contracts on a developer's machine
+    #[structopt(long = "dev-mode")]
+    pub dev_mode: bool,
+}
+
+/// The commands run on the DB of a stopped core, `enigma-core-app db <command>`.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "db", about = "Commands run on the DB of a stopped core.")]
+pub enum DbCommand {
+    /// Regenerate the address index, the chain hashes, the gap index and the hot set from the raw data,
+    /// and print what was fixed as JSON
+    #[structopt(name = "rebuild")]
+    Rebuild {
+        /// Specify data directory
+        #[structopt(parse(from_os_str), long = "data-dir")]
+        data_dir: Option<PathBuf>,
+        /// Optional: the Ethereum network the DB was created for, like `--network` of the core
+        #[structopt(long = "network")]
+        network: Option<Network>,
+        /// Optional: only rebuild this structure (addressIndex, chainHashes, gapIndex or hotSet), can be repeated
+        #[structopt(long = "scope")]
+        scopes: Vec<RebuildScope>,
+    },
+}
+
+fn display<T: fmt::Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> { serializer.collect_str(value) }
+
+fn display_opt<T: fmt::Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
+
+// The secrets are never printed nor logged.
+fn redacted<T, S: Serializer>(_: &T, serializer: S) -> Result<S::Ok, S::Error> { serializer.serialize_str(REDACTED) }
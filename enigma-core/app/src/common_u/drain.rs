@@ -0,0 +1,102 @@
+//! # Drain mode.
+//! Empties the core before a rolling upgrade without killing it.
+//! After `Drain` the requests that write to the DB or run a task are refused with a `DrainingErr`, reads are still served.
+//! The mutating requests that were already queued are handled as usual, `GetDrainStatus` says how many of them are left,
+//! once it's 0 the core can be stopped. `Resume` accepts them again.
+//!
+//! Only the requests that went through the queue (see [`ipc_queue`](../../networking/ipc_queue/index.html)) are counted,
+//! the queue is where they are refused too.
+
+use crate::common_u::metrics::METRICS;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The request types refused while draining.
+/// `SetEpochParams` isn't one of them, a core resumed with a stale epoch would refuse the next tasks.
+pub const MUTATING_TYPES: [&str; 10] = [
+    "UpdateNewContract",
+    "UpdateNewContractOnDeployment",
+    "RemoveContract",
+    "UpdateDeltas",
+    "RemoveDeltas",
+    "NewTaskEncryptionKey",
+    "DeploySecretContract",
+    "ComputeTask",
+    "PTTResponse",
+    "RecoverKeys",
+];
+
+pub static DRAIN: DrainState = DrainState::new();
+
+pub fn is_mutating(kind: &str) -> bool { MUTATING_TYPES.contains(&kind) }
+
+#[derive(Debug)]
+pub struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl DrainState {
+    pub const fn new() -> Self { DrainState { draining: AtomicBool::new(false), in_flight: AtomicUsize::new(0) } }
+
+    /// Refuses the mutating requests from now on, returns how many are still in flight.
+    pub fn start(&self) -> usize {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            info!("Draining, the mutating requests are refused until Resume");
+        }
+        METRICS.set_draining(true);
+        self.in_flight()
+    }
+
+    pub fn resume(&self) {
+        if self.draining.swap(false, Ordering::SeqCst) {
+            info!("Resuming, the mutating requests are accepted again");
+        }
+        METRICS.set_draining(false);
+    }
+
+    pub fn is_draining(&self) -> bool { self.draining.load(Ordering::SeqCst) }
+
+    /// The mutating requests accepted and not answered yet.
+    pub fn in_flight(&self) -> usize { self.in_flight.load(Ordering::SeqCst) }
+
+    /// Counts `count` mutating requests accepted into the queue.
+    pub fn admitted(&self, count: usize) {
+        let in_flight = self.in_flight.fetch_add(count, Ordering::SeqCst) + count;
+        METRICS.set_in_flight(in_flight);
+    }
+
+    /// Counts `count` mutating requests answered, or given back because the queue was full.
+    pub fn finished(&self, count: usize) {
+        let in_flight = self.in_flight.fetch_sub(count, Ordering::SeqCst) - count;
+        METRICS.set_in_flight(in_flight);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_drain_state() {
+        let state = DrainState::new();
+        state.admitted(2);
+        assert!(!state.is_draining());
+        assert_eq!(state.start(), 2);
+        assert!(state.is_draining());
+        // Draining twice is the same as once.
+        assert_eq!(state.start(), 2);
+        state.finished(2);
+        assert_eq!(state.in_flight(), 0);
+        state.resume();
+        assert!(!state.is_draining());
+    }
+
+    #[test]
+    fn test_mutating_types() {
+        assert!(is_mutating("ComputeTask"));
+        assert!(is_mutating("UpdateDeltas"));
+        assert!(!is_mutating("GetAllTips"));
+        assert!(!is_mutating("SetEpochParams"));
+        assert!(!is_mutating("Drain"));
+    }
+}
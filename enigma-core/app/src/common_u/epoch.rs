@@ -0,0 +1,199 @@
+//! # Epoch tracking.
+//! The worker knows the active epoch only from what the p2p node sends it with `SetEpochParams`.
+//! A task that was assigned in another epoch was selected against other worker params, and the chain will reject
+//! its receipt, so `compute_task` refuses it with a `StaleEpochErr` telling the p2p node which epoch we know.
+//! The epoch known here is also returned with the PTT requests and the task results, see `IpcEpoch`.
+//! The last few epochs before it are kept too, so a PTT request can be pinned to the epoch that was active at a block.
+
+use common_u::errors::{StaleEpochErr, UnknownEpochErr};
+use enigma_types::Hash256;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// How many epochs before the current one are kept for `EpochTracker::at_block`.
+pub const PAST_EPOCHS: usize = 8;
+
+lazy_static! { pub static ref EPOCH: Mutex<EpochTracker> = Mutex::new(EpochTracker::default()); }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochParams {
+    pub nonce: u64,
+    pub first_block: u64,
+    /// The commitment to the seed the principal published for the epoch, if the p2p node sent it.
+    pub seed_commitment: Option<Hash256>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EpochTracker {
+    current: Option<EpochParams>,
+    // The epochs the current one replaced, oldest first.
+    past: VecDeque<EpochParams>,
+    // How many blocks around an epoch transition tasks of the neighbouring epoch are still accepted.
+    grace_blocks: u64,
+    // How many blocks an epoch is trusted for, without a newer one tasks from further ahead are refused.
+    max_age: Option<u64>,
+}
+
+impl EpochTracker {
+    pub fn new(grace_blocks: u64, max_age: Option<u64>) -> Self { EpochTracker { current: None, past: VecDeque::new(), grace_blocks, max_age } }
+
+    pub fn configure(&mut self, grace_blocks: u64, max_age: Option<u64>) {
+        self.grace_blocks = grace_blocks;
+        self.max_age = max_age;
+    }
+
+    pub fn current(&self) -> Option<EpochParams> { self.current }
+
+    /// Replaces the known epoch, an epoch older than the known one is ignored and `false` is returned.
+    pub fn set(&mut self, params: EpochParams) -> bool {
+        match self.current {
+            Some(current) if params.nonce < current.nonce => false,
+            Some(current) if params.nonce > current.nonce => {
+                if self.past.len() == PAST_EPOCHS {
+                    self.past.pop_front();
+                }
+                self.past.push_back(current);
+                self.current = Some(params);
+                true
+            }
+            _ => {
+                self.current = Some(params);
+                true
+            }
+        }
+    }
+
+    /// The epoch that was active at `block`: the newest known one that started at or before it.
+    pub fn at_block(&self, block: u64) -> Result<EpochParams, UnknownEpochErr> {
+        self.current
+            .iter()
+            .chain(self.past.iter().rev())
+            .find(|epoch| epoch.first_block <= block)
+            .copied()
+            .ok_or_else(|| UnknownEpochErr {
+                block_number: block,
+                earliest_block: self.past.front().or_else(|| self.current.as_ref()).map(|epoch| epoch.first_block),
+            })
+    }
+
+    /// Checks the epoch hints of a task against the known epoch.
+    /// Tasks without hints, or arriving before any epoch is known, are accepted like before.
+    pub fn check(&self, block_number: Option<u64>, task_nonce: Option<u64>) -> Result<(), StaleEpochErr> {
+        let epoch = match self.current {
+            Some(epoch) => epoch,
+            None => return Ok(()),
+        };
+        let stale = || Err(StaleEpochErr { task_nonce, known_nonce: epoch.nonce, seed_commitment: epoch.seed_commitment, block_number });
+
+        if let Some(block) = block_number {
+            if block.saturating_add(self.grace_blocks) < epoch.first_block {
+                return stale();
+            }
+            if let Some(max_age) = self.max_age {
+                if block >= epoch.first_block.saturating_add(max_age).saturating_add(self.grace_blocks) {
+                    return stale();
+                }
+            }
+        }
+        match task_nonce {
+            None => Ok(()),
+            Some(nonce) if nonce == epoch.nonce => Ok(()),
+            // A task of the previous epoch that was propagated late, the block check above already bounded it.
+            Some(nonce) if nonce.checked_add(1) == Some(epoch.nonce) && block_number.map_or(false, |block| block < epoch.first_block) => Ok(()),
+            Some(_) => stale(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tracker() -> EpochTracker {
+        let mut tracker = EpochTracker::new(5, Some(100));
+        assert!(tracker.set(EpochParams { nonce: 7, first_block: 1000, seed_commitment: Some([7u8; 32].into()) }));
+        tracker
+    }
+
+    #[test]
+    fn test_no_epoch_known() {
+        let tracker = EpochTracker::new(5, Some(100));
+        assert!(tracker.check(Some(1), Some(1)).is_ok());
+        assert!(tracker.check(None, None).is_ok());
+    }
+
+    #[test]
+    fn test_matching_epoch() {
+        let tracker = tracker();
+        assert!(tracker.check(None, None).is_ok());
+        assert!(tracker.check(Some(1000), None).is_ok());
+        assert!(tracker.check(Some(1050), Some(7)).is_ok());
+        assert!(tracker.check(None, Some(7)).is_ok());
+    }
+
+    #[test]
+    fn test_stale_task() {
+        let tracker = tracker();
+        let err = tracker.check(Some(900), None).unwrap_err();
+        assert_eq!(err, StaleEpochErr { task_nonce: None, known_nonce: 7, seed_commitment: Some([7u8; 32].into()), block_number: Some(900) });
+        let err = tracker.check(None, Some(6)).unwrap_err();
+        assert_eq!(err, StaleEpochErr { task_nonce: Some(6), known_nonce: 7, seed_commitment: Some([7u8; 32].into()), block_number: None });
+        assert!(tracker.check(Some(990), Some(6)).is_err());
+        // The block says it's in the current epoch, but the nonce doesn't.
+        assert!(tracker.check(Some(1001), Some(6)).is_err());
+    }
+
+    #[test]
+    fn test_future_task() {
+        let tracker = tracker();
+        let err = tracker.check(None, Some(8)).unwrap_err();
+        assert_eq!(err, StaleEpochErr { task_nonce: Some(8), known_nonce: 7, seed_commitment: Some([7u8; 32].into()), block_number: None });
+        assert!(tracker.check(Some(1105), None).is_err());
+        assert!(tracker.check(Some(1010), Some(8)).is_err());
+        // Any nonce a request can carry is only stale, it doesn't overflow.
+        assert!(tracker.check(Some(990), Some(u64::max_value())).is_err());
+    }
+
+    #[test]
+    fn test_within_grace() {
+        let tracker = tracker();
+        assert!(tracker.check(Some(995), None).is_ok());
+        assert!(tracker.check(Some(995), Some(6)).is_ok());
+        assert!(tracker.check(Some(994), Some(6)).is_err());
+        assert!(tracker.check(Some(1104), None).is_ok());
+    }
+
+    #[test]
+    fn test_older_epoch_ignored() {
+        let mut tracker = tracker();
+        assert!(!tracker.set(EpochParams { nonce: 6, first_block: 900, seed_commitment: None }));
+        assert_eq!(tracker.current(), Some(EpochParams { nonce: 7, first_block: 1000, seed_commitment: Some([7u8; 32].into()) }));
+        assert!(tracker.set(EpochParams { nonce: 8, first_block: 1100, seed_commitment: None }));
+        assert!(tracker.check(Some(1100), Some(8)).is_ok());
+    }
+
+    #[test]
+    fn test_at_block() {
+        assert_eq!(EpochTracker::new(5, Some(100)).at_block(10), Err(UnknownEpochErr { block_number: 10, earliest_block: None }));
+
+        let mut tracker = tracker();
+        assert!(tracker.set(EpochParams { nonce: 8, first_block: 1100, seed_commitment: None }));
+        assert_eq!(tracker.at_block(1150).unwrap().nonce, 8);
+        assert_eq!(tracker.at_block(1100).unwrap().nonce, 8);
+        assert_eq!(tracker.at_block(1099).unwrap().nonce, 7);
+        assert_eq!(tracker.at_block(1000).unwrap(), EpochParams { nonce: 7, first_block: 1000, seed_commitment: Some([7u8; 32].into()) });
+        assert_eq!(tracker.at_block(999), Err(UnknownEpochErr { block_number: 999, earliest_block: Some(1000) }));
+    }
+
+    #[test]
+    fn test_past_epochs_bounded() {
+        let mut tracker = EpochTracker::new(5, Some(100));
+        for nonce in 0..=PAST_EPOCHS as u64 {
+            assert!(tracker.set(EpochParams { nonce, first_block: nonce * 100, seed_commitment: None }));
+        }
+        assert_eq!(tracker.at_block(50).unwrap().nonce, 0);
+        assert!(tracker.set(EpochParams { nonce: PAST_EPOCHS as u64 + 1, first_block: 5000, seed_commitment: None }));
+        assert_eq!(tracker.at_block(50), Err(UnknownEpochErr { block_number: 50, earliest_block: Some(100) }));
+        assert_eq!(tracker.at_block(150).unwrap().nonce, 1);
+    }
+}
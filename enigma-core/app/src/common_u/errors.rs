@@ -2,6 +2,7 @@
 use sgx_types::*;
 use std::fmt;
 use failure::Error;
+use enigma_types::{EnclaveReturn, Hash256};
 
 // error while requesting to produce a quote (registration)
 #[derive(Fail, Debug)]
@@ -39,6 +40,128 @@ pub struct P2PErr {
     pub msg: String,
 }
 
+// the task was assigned in an epoch other than the one the worker knows about
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "The task isn't for the current epoch (nonce {}), task nonce: {:?}, task block: {:?}", known_nonce, task_nonce, block_number)]
+pub struct StaleEpochErr {
+    pub task_nonce: Option<u64>,
+    pub known_nonce: u64,
+    /// The seed commitment of the known epoch.
+    pub seed_commitment: Option<Hash256>,
+    pub block_number: Option<u64>,
+}
+
+// a PTT request was pinned to a block before the oldest epoch the worker knows (or before any epoch was set)
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "No known epoch was active at block {}, the earliest known epoch starts at {:?}", block_number, earliest_block)]
+pub struct UnknownEpochErr {
+    pub block_number: u64,
+    /// The first block of the oldest epoch still known, `None` if no epoch was set yet.
+    pub earliest_block: Option<u64>,
+}
+
+// the worker is waiting for the KM node to send back the state keys it lost
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "The worker is recovering its state keys ({}/{} contracts provisioned)", provisioned, total)]
+pub struct RecoveringErr {
+    pub provisioned: usize,
+    pub total: usize,
+}
+
+// the worker is at capacity and refused the request without doing any of it
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "The worker is busy, retry in {} ms", retry_after_ms)]
+pub struct BusyErr {
+    pub retry_after_ms: u64,
+}
+
+// the core is draining for an upgrade and refused a mutating request without doing any of it
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "The worker is draining, it doesn't accept tasks or writes until it's resumed")]
+pub struct DrainingErr;
+
+// `RebuildIndexes` while the core takes writes, it's only run once the core is draining with nothing in flight
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "The indexes can't be rebuilt while writes may run (draining: {}, in flight: {}), drain the core first", draining, in_flight)]
+pub struct NotDrainedErr {
+    pub draining: bool,
+    pub in_flight: usize,
+}
+
+// a task asked for a debug trace but the core isn't running in dev mode
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "Debug traces are only returned by a core built in debug and started with --dev-mode")]
+pub struct DebugTraceDisabledErr;
+
+// the task is for a contract whose bytecode isn't stored (or is empty), it was refused before reaching the enclave
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "The contract {} isn't deployed on this worker", address)]
+pub struct ContractNotFoundErr {
+    pub address: String,
+}
+
+// `ReplayTask` for a task that was never journaled, or was dropped from the journal since
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "The task {} isn't in the task journal", task_id)]
+pub struct TaskNotJournaledErr {
+    pub task_id: String,
+}
+
+// storing the data would take the node over one of its capacity caps (see `db::capacity`), nothing was written
+#[derive(Fail, Debug)]
+#[fail(display = "Capacity exceeded, {} is {} and the usage is {}", cap, limit, usage)]
+pub struct CapacityExceededErr {
+    /// The name of the cap, `maxDbBytes`, `maxContractDeltaBytes` or `maxContracts`.
+    pub cap: &'static str,
+    pub usage: u64,
+    pub limit: u64,
+    /// The contract, for the caps of a single contract.
+    pub address: Option<String>,
+}
+
+// the `id` of a request is missing or isn't 1 to 128 bytes of printable UTF-8 (see `networking::request_id`), it wasn't handled
+#[derive(Fail, Debug, Clone, Copy, PartialEq, Eq)]
+#[fail(display = "Invalid request id, {}", reason)]
+pub struct InvalidRequestIdErr {
+    pub reason: &'static str,
+}
+
+// a handler panicked, `msg` is the panic message without the secrets it may have contained
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "Internal error: {}", msg)]
+pub struct InternalErr {
+    pub msg: String,
+}
+
+// an option given in the config file, the environment or the command line is invalid, `field` is its name in `--print-config`
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "Invalid config option {}: {}", field, msg)]
+pub struct ConfigErr {
+    pub field: String,
+    pub msg: String,
+}
+
+// the local state of the task's contract isn't the one the task was assigned against, the p2p node should sync it first
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "The state of the contract {} doesn't match the task, local tip: {:?}, expected tip: {}", address, local_tip, expected_key)]
+pub struct StateBehindErr {
+    pub address: String,
+    /// The key and the chain hash of the last local delta, `None` if the contract has no deltas.
+    pub local_tip: Option<(u32, Hash256)>,
+    pub expected_key: u32,
+}
+
+// the deltas of the task's contract have gaps, its state can only be built up to the usable tip, not to the raw one
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+#[fail(display = "The deltas of the contract {} have gaps, usable tip: {:?}, raw tip: {}", address, usable_tip, raw_tip)]
+pub struct DeltaGapErr {
+    pub address: String,
+    /// The largest key such that all the deltas up to it are stored, `None` if delta 0 is missing.
+    pub usable_tip: Option<u32>,
+    /// The largest delta key stored.
+    pub raw_tip: u32,
+}
+
 #[derive(Fail, Debug)]
 #[fail(display = "Error while trying to {}, Because: {}", command, kind)]
 pub struct DBErr {
@@ -62,6 +185,10 @@ pub enum DBErrKind {
     MissingKey(String),
     UpdateError,
     MissingKeys,
+    /// The DB was created for the first network, but the node is configured for the second.
+    NetworkMismatch(String, String),
+    /// A delta of a contract that has no bytecode, see `db::orphans`.
+    MissingBytecode(String),
 }
 
 impl fmt::Display for DBErrKind {
@@ -73,14 +200,187 @@ impl fmt::Display for DBErrKind {
             DBErrKind::MissingKey(k) => format!("The following Key doesn't exist: {}", &k),
             DBErrKind::UpdateError => "Failed to update the key".into(),
             DBErrKind::MissingKeys => "No keys exist the DB".into(),
+            DBErrKind::NetworkMismatch(found, expected) =>
+                format!("The DB was created for the {} network, but the node is configured for {}", found, expected),
+            DBErrKind::MissingBytecode(addr) => format!("The contract {} has no bytecode, its deltas can't be stored before it", addr),
         };
         write!(f, "{}", printable)
     }
 }
 
+impl DBErrKind {
+    /// A short, stable name for the kind, without the key, used as a metrics label.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DBErrKind::KeyExists(_) => "key_exists",
+            DBErrKind::CreateError => "create",
+            DBErrKind::FetchError => "fetch",
+            DBErrKind::MissingKey(_) => "missing_key",
+            DBErrKind::UpdateError => "update",
+            DBErrKind::MissingKeys => "missing_keys",
+            DBErrKind::NetworkMismatch(..) => "network_mismatch",
+            DBErrKind::MissingBytecode(_) => "missing_bytecode",
+        }
+    }
+
+    /// Only the failures of the DB itself are worth retrying, a missing or existing key will stay that way.
+    pub fn retry(&self) -> Retry {
+        match self {
+            DBErrKind::CreateError | DBErrKind::FetchError | DBErrKind::UpdateError => Retry::After(Some(DB_RETRY_MS)),
+            DBErrKind::KeyExists(_) | DBErrKind::MissingKey(_) | DBErrKind::MissingKeys | DBErrKind::NetworkMismatch(..) => Retry::Never,
+            // It succeeds once the contract was stored, which the p2p node has to do first.
+            DBErrKind::MissingBytecode(_) => Retry::Never,
+        }
+    }
+}
+
 #[derive(Fail, Debug)]
 #[fail(display = "Error inside the Enclave = ({:?})", err)]
 pub struct EnclaveFailError {
     pub err: enigma_types::EnclaveReturn,
     pub status: sgx_status_t,
 }
+
+/// How long the p2p node should wait before retrying after a DB failure.
+pub const DB_RETRY_MS: u64 = 100;
+/// The enclave ran out of threads or memory for the moment.
+pub const ENCLAVE_BUSY_RETRY_MS: u64 = 100;
+/// After the enclave was lost it has to be re-created, that's not immediate.
+pub const ENCLAVE_LOST_RETRY_MS: u64 = 1_000;
+/// The attestation service and the quoting enclave are remote or shared, give them time to recover.
+pub const ATTESTATION_RETRY_MS: u64 = 5_000;
+/// Provisioning the recovered keys takes at least a round trip to the KM node.
+pub const RECOVERING_RETRY_MS: u64 = 5_000;
+
+/// Whether a failed request may succeed if it's sent again as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Retrying can't help, the request itself is wrong (bad input, unknown contract, a failing task...).
+    Never,
+    /// The failure is transient, with how long to wait before retrying if we have an idea.
+    After(Option<u64>),
+}
+
+impl Retry {
+    pub fn is_retryable(self) -> bool { self != Retry::Never }
+
+    pub fn retry_after_ms(self) -> Option<u64> {
+        match self {
+            Retry::After(after) => after,
+            Retry::Never => None,
+        }
+    }
+
+    /// Classifies an error returned by a handler, errors we don't know are assumed permanent
+    /// so an unexpected failure doesn't turn into a retry storm.
+    pub fn of(e: &Error) -> Retry {
+        use enigma_tools_u::common_u::errors as tools_errors;
+        if let Some(e) = e.downcast_ref::<BusyErr>() {
+            Retry::After(Some(e.retry_after_ms))
+        } else if e.downcast_ref::<RecoveringErr>().is_some() {
+            Retry::After(Some(RECOVERING_RETRY_MS))
+        } else if e.downcast_ref::<DrainingErr>().is_some() {
+            // Another worker can take it now, this one once it's resumed.
+            Retry::After(None)
+        } else if let Some(e) = e.downcast_ref::<NotDrainedErr>() {
+            // Once the requests in flight are done, a core that isn't draining needs a `Drain` first.
+            if e.draining { Retry::After(None) } else { Retry::Never }
+        } else if e.downcast_ref::<StaleEpochErr>().is_some() {
+            // It will succeed once the p2p node sent the new epoch with `SetEpochParams`.
+            Retry::After(None)
+        } else if e.downcast_ref::<StateBehindErr>().is_some() || e.downcast_ref::<DeltaGapErr>().is_some() {
+            // It will succeed once the p2p node sent the missing deltas.
+            Retry::After(None)
+        } else if let Some(e) = e.downcast_ref::<UnknownEpochErr>() {
+            // Before the first `SetEpochParams` it will succeed later, an epoch that was forgotten never comes back.
+            if e.earliest_block.is_none() { Retry::After(None) } else { Retry::Never }
+        } else if let Some(e) = e.downcast_ref::<EnclaveFailError>() {
+            Retry::of_enclave(e.err, e.status)
+        } else if let Some(e) = e.downcast_ref::<tools_errors::SgxError>() {
+            Retry::of_enclave(EnclaveReturn::Success, e.status)
+        } else if let Some(e) = e.downcast_ref::<GetRegisterKeyErr>() {
+            Retry::of_enclave(EnclaveReturn::Success, e.status)
+        } else if let Some(e) = e.downcast_ref::<DBErr>() {
+            e.kind.retry()
+        } else if e.downcast_ref::<::rocksdb::Error>().is_some() {
+            // Errors straight from rocksdb are I/O or lock failures, the malformed requests never get that far.
+            Retry::After(Some(DB_RETRY_MS))
+        } else if e.downcast_ref::<AttestationServiceErr>().is_some()
+            || e.downcast_ref::<tools_errors::AttestationServiceErr>().is_some()
+            || e.downcast_ref::<ProduceQuoteErr>().is_some()
+            // `retry_quote` gives up with a `QuoteErr` when the quoting enclave keeps failing.
+            || e.downcast_ref::<tools_errors::QuoteErr>().is_some() {
+            Retry::After(Some(ATTESTATION_RETRY_MS))
+        } else {
+            // `P2PErr`, our own `QuoteErr`, hex and serialization errors: the request or its data is malformed.
+            Retry::Never
+        }
+    }
+
+    /// A failed ecall, the SGX status says if the enclave itself is in trouble, otherwise the enclave's error says.
+    pub fn of_enclave(err: EnclaveReturn, status: sgx_status_t) -> Retry {
+        match status {
+            sgx_status_t::SGX_SUCCESS => (),
+            sgx_status_t::SGX_ERROR_ENCLAVE_LOST => return Retry::After(Some(ENCLAVE_LOST_RETRY_MS)),
+            sgx_status_t::SGX_ERROR_BUSY | sgx_status_t::SGX_ERROR_OUT_OF_TCS | sgx_status_t::SGX_ERROR_OUT_OF_MEMORY => {
+                return Retry::After(Some(ENCLAVE_BUSY_RETRY_MS))
+            }
+            _ => return Retry::Never,
+        }
+        match err {
+            // Failures of the untrusted side or of the SGX services the enclave calls into.
+            EnclaveReturn::SgxError | EnclaveReturn::OcallError | EnclaveReturn::OcallDBError | EnclaveReturn::RandUnavailable => {
+                Retry::After(Some(DB_RETRY_MS))
+            }
+            // The KM node doesn't have the keys or the workers yet, they may come with the next epoch.
+            EnclaveReturn::KeyProvisionError | EnclaveReturn::NoWorkersInEpoch => Retry::After(None),
+            EnclaveReturn::Success
+            | EnclaveReturn::TaskFailure
+            | EnclaveReturn::KeysError
+            | EnclaveReturn::EncryptionError
+            | EnclaveReturn::SigningError
+            | EnclaveReturn::RecoveringError
+            | EnclaveReturn::PermissionError
+            | EnclaveReturn::StateError
+            | EnclaveReturn::MessagingError
+            | EnclaveReturn::WorkerAuthError
+            | EnclaveReturn::InvalidWorkerParams
+            | EnclaveReturn::Forbidden
+            | EnclaveReturn::SigningKeyUninitialized
+            | EnclaveReturn::SeedNotRevealed
+            | EnclaveReturn::Other => Retry::Never,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_classes() {
+        let busy: Error = BusyErr { retry_after_ms: 250 }.into();
+        assert_eq!(Retry::of(&busy), Retry::After(Some(250)));
+
+        let lost: Error = EnclaveFailError { err: EnclaveReturn::Other, status: sgx_status_t::SGX_ERROR_ENCLAVE_LOST }.into();
+        assert_eq!(Retry::of(&lost), Retry::After(Some(ENCLAVE_LOST_RETRY_MS)));
+        let attestation: Error = AttestationServiceErr { message: "timeout".to_string() }.into();
+        assert_eq!(Retry::of(&attestation), Retry::After(Some(ATTESTATION_RETRY_MS)));
+        let recovering: Error = RecoveringErr { provisioned: 1, total: 2 }.into();
+        assert!(Retry::of(&recovering).is_retryable());
+        assert_eq!(Retry::of(&DrainingErr.into()), Retry::After(None));
+        assert_eq!(Retry::of(&NotDrainedErr { draining: true, in_flight: 1 }.into()), Retry::After(None));
+        assert_eq!(Retry::of(&NotDrainedErr { draining: false, in_flight: 0 }.into()), Retry::Never);
+        assert_eq!(Retry::of(&UnknownEpochErr { block_number: 5, earliest_block: None }.into()), Retry::After(None));
+        assert_eq!(Retry::of(&UnknownEpochErr { block_number: 5, earliest_block: Some(10) }.into()), Retry::Never);
+
+        let not_found: Error = DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey("00".to_string()) }.into();
+        assert_eq!(Retry::of(&not_found), Retry::Never);
+        let bad_input: Error = P2PErr { cmd: "GetTip".to_string(), msg: "bad hex".to_string() }.into();
+        assert_eq!(Retry::of(&bad_input), Retry::Never);
+        let task: Error = EnclaveFailError { err: EnclaveReturn::TaskFailure, status: sgx_status_t::SGX_SUCCESS }.into();
+        assert_eq!(Retry::of(&task), Retry::Never);
+        assert_eq!(Retry::of(&format_err!("Something unexpected")), Retry::Never);
+        assert_eq!(Retry::Never.retry_after_ms(), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! # Metrics Registry.
+//! A small in-process registry of counters, gauges and histograms describing what the node is doing.
+//! It is filled by the IPC handlers and the ecall wrappers, and rendered in the Prometheus text
+//! exposition format by [`networking::metrics_server`](../../networking/metrics_server/index.html).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use sgx_types::sgx_status_t;
+
+const BUCKETS_LEN: usize = 11;
+
+/// Upper bounds (in seconds) of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; BUCKETS_LEN] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+lazy_static! { pub static ref METRICS: Metrics = Metrics::default(); }
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS_LEN],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if secs <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    pub fn count(&self) -> u64 { self.count }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count);
+        let scope = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, scope, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, scope, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    requests: BTreeMap<&'static str, (u64, Histogram)>,
+    enclave_calls: BTreeMap<&'static str, Histogram>,
+    errors: BTreeMap<String, u64>,
+    task_gas: u64,
+    tasks: u64,
+    db_contracts: u64,
+    db_disk_bytes: u64,
+    enclave_healthy: bool,
+    enclave_degraded: bool,
+    enclave_reinits: u64,
+    queue_depth: u64,
+    queue_capacity: u64,
+    queue_wait: Histogram,
+    shed: BTreeMap<String, u64>,
+    throttled: BTreeMap<String, u64>,
+    rate_limit_tokens: BTreeMap<String, (f64, u32)>,
+    handler_panics: u64,
+    panic_breaker_tripped: bool,
+    draining: bool,
+    in_flight: u64,
+    maintenance_runs: u64,
+    maintenance_removed: BTreeMap<&'static str, u64>,
+    maintenance_reclaimed_bytes: u64,
+}
+
+/// The registry itself, all the recording functions take `&self` so it can live in a static.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    fn with<F: FnOnce(&mut Inner)>(&self, f: F) {
+        // A poisoned registry shouldn't take the node down with it, the counters are best effort.
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut guard)
+    }
+
+    /// Records a handled IPC request of type `kind`, and the time it took to answer it.
+    pub fn record_request(&self, kind: &'static str, elapsed: Duration) {
+        self.with(|m| {
+            let entry = m.requests.entry(kind).or_insert_with(Default::default);
+            entry.0 += 1;
+            entry.1.observe(elapsed);
+        })
+    }
+
+    /// Records a single ecall, a failing `sgx_status_t` marks the enclave as unhealthy.
+    pub fn record_enclave_call(&self, ecall: &'static str, elapsed: Duration, status: sgx_status_t) {
+        self.with(|m| {
+            m.enclave_calls.entry(ecall).or_insert_with(Default::default).observe(elapsed);
+            m.enclave_healthy = status == sgx_status_t::SGX_SUCCESS;
+        })
+    }
+
+    /// Counts an error returned to the caller, grouped by `code`.
+    pub fn record_error(&self, code: &str) {
+        self.with(|m| *m.errors.entry(code.to_string()).or_insert(0) += 1)
+    }
+
+    /// Adds the gas used by a finished (successful or failed) task.
+    pub fn record_task_gas(&self, used_gas: u64) {
+        self.with(|m| {
+            m.tasks += 1;
+            m.task_gas = m.task_gas.saturating_add(used_gas);
+        })
+    }
+
+    pub fn set_db_size(&self, contracts: u64, disk_bytes: u64) {
+        self.with(|m| {
+            m.db_contracts = contracts;
+            m.db_disk_bytes = disk_bytes;
+        })
+    }
+
+    /// The number of contracts and the size of the DB directory, as of the last sample of the `DbSizeSampler`.
+    pub fn db_size(&self) -> (u64, u64) {
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        (guard.db_contracts, guard.db_disk_bytes)
+    }
+
+    pub fn set_enclave_health(&self, healthy: bool) { self.with(|m| m.enclave_healthy = healthy) }
+
+    pub fn enclave_healthy(&self) -> bool { self.inner.lock().unwrap_or_else(|e| e.into_inner()).enclave_healthy }
+
+    /// Set by the watchdog while the enclave answers its pings too slowly.
+    pub fn set_enclave_degraded(&self, degraded: bool) { self.with(|m| m.enclave_degraded = degraded) }
+
+    pub fn enclave_degraded(&self) -> bool { self.inner.lock().unwrap_or_else(|e| e.into_inner()).enclave_degraded }
+
+    /// Counts an enclave re-created after it stopped answering.
+    pub fn record_enclave_reinit(&self) { self.with(|m| m.enclave_reinits += 1) }
+
+    /// Counts a panic caught in an IPC handler.
+    pub fn record_handler_panic(&self) { self.with(|m| m.handler_panics += 1) }
+
+    /// Set once the handlers panicked too often, see `common_u::panics`.
+    pub fn set_panic_breaker_tripped(&self) { self.with(|m| m.panic_breaker_tripped = true) }
+
+    pub fn panic_breaker_tripped(&self) -> bool { self.inner.lock().unwrap_or_else(|e| e.into_inner()).panic_breaker_tripped }
+
+    /// Set while the core refuses the mutating requests, see `common_u::drain`.
+    pub fn set_draining(&self, draining: bool) { self.with(|m| m.draining = draining) }
+
+    pub fn set_in_flight(&self, in_flight: usize) { self.with(|m| m.in_flight = in_flight as u64) }
+
+    /// Records a maintenance pass, see `db::maintenance`.
+    pub fn record_maintenance(&self, removed: &[(&'static str, usize)], reclaimed_bytes: u64) {
+        self.with(|m| {
+            m.maintenance_runs += 1;
+            for &(kind, count) in removed {
+                *m.maintenance_removed.entry(kind).or_insert(0) += count as u64;
+            }
+            m.maintenance_reclaimed_bytes += reclaimed_bytes;
+        })
+    }
+
+    pub fn set_queue_capacity(&self, capacity: usize) { self.with(|m| m.queue_capacity = capacity as u64) }
+
+    pub fn set_queue_depth(&self, depth: usize) { self.with(|m| m.queue_depth = depth as u64) }
+
+    /// Records how long a request waited in the queue before the handler thread took it, and the depth it left behind.
+    pub fn record_queue_wait(&self, wait: Duration, depth: usize) {
+        self.with(|m| {
+            m.queue_wait.observe(wait);
+            m.queue_depth = depth as u64;
+        })
+    }
+
+    /// Counts a request of type `kind` refused because the queue was full.
+    pub fn record_shed(&self, kind: &str) {
+        self.with(|m| *m.shed.entry(kind.to_string()).or_insert(0) += 1)
+    }
+
+    /// Counts a request of type `kind` refused because it was over its rate limit, see `common_u::rate_limit`.
+    pub fn record_throttled(&self, kind: &str) {
+        self.with(|m| *m.throttled.entry(kind.to_string()).or_insert(0) += 1)
+    }
+
+    /// The tokens left in a rate limit bucket (a request type or the global one) after the last request it admitted.
+    pub fn set_rate_limit_tokens(&self, bucket: &str, tokens: f64, burst: u32) {
+        self.with(|m| {
+            m.rate_limit_tokens.insert(bucket.to_string(), (tokens, burst));
+        })
+    }
+
+    /// Returns the number of requests refused so far because the queue was full.
+    pub fn shed_count(&self) -> u64 {
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        guard.shed.values().sum()
+    }
+
+    /// Returns the number of requests of type `kind` recorded so far.
+    pub fn request_count(&self, kind: &str) -> u64 {
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        guard.requests.get(kind).map(|(count, _)| *count).unwrap_or(0)
+    }
+
+    /// Renders the whole registry in the Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        out.push_str("# HELP enigma_ipc_requests_total Number of IPC requests handled, by request type.\n");
+        out.push_str("# TYPE enigma_ipc_requests_total counter\n");
+        for (kind, (count, _)) in &guard.requests {
+            let _ = writeln!(out, "enigma_ipc_requests_total{{type=\"{}\"}} {}", kind, count);
+        }
+
+        out.push_str("# HELP enigma_ipc_request_duration_seconds Time spent answering IPC requests, by request type.\n");
+        out.push_str("# TYPE enigma_ipc_request_duration_seconds histogram\n");
+        for (kind, (_, hist)) in &guard.requests {
+            hist.render(&mut out, "enigma_ipc_request_duration_seconds", &format!("type=\"{}\"", kind));
+        }
+
+        out.push_str("# HELP enigma_ipc_queue_depth Number of IPC requests waiting for the handler thread.\n");
+        out.push_str("# TYPE enigma_ipc_queue_depth gauge\n");
+        let _ = writeln!(out, "enigma_ipc_queue_depth {}", guard.queue_depth);
+        out.push_str("# HELP enigma_ipc_queue_capacity Number of IPC requests that can wait before new ones are refused.\n");
+        out.push_str("# TYPE enigma_ipc_queue_capacity gauge\n");
+        let _ = writeln!(out, "enigma_ipc_queue_capacity {}", guard.queue_capacity);
+        out.push_str("# HELP enigma_ipc_queue_wait_seconds Time IPC requests waited in the queue.\n");
+        out.push_str("# TYPE enigma_ipc_queue_wait_seconds histogram\n");
+        guard.queue_wait.render(&mut out, "enigma_ipc_queue_wait_seconds", "");
+        out.push_str("# HELP enigma_ipc_shed_total Number of IPC requests refused with Busy because the queue was full, by request type.\n");
+        out.push_str("# TYPE enigma_ipc_shed_total counter\n");
+        for (kind, count) in &guard.shed {
+            let _ = writeln!(out, "enigma_ipc_shed_total{{type=\"{}\"}} {}", kind, count);
+        }
+
+        out.push_str("# HELP enigma_ipc_throttled_total Number of IPC requests refused with Busy because of their rate limit, by request type.\n");
+        out.push_str("# TYPE enigma_ipc_throttled_total counter\n");
+        for (kind, count) in &guard.throttled {
+            let _ = writeln!(out, "enigma_ipc_throttled_total{{type=\"{}\"}} {}", kind, count);
+        }
+        out.push_str("# HELP enigma_ipc_rate_limit_tokens Tokens left in a rate limit bucket after the last request it admitted.\n");
+        out.push_str("# TYPE enigma_ipc_rate_limit_tokens gauge\n");
+        for (bucket, (tokens, _)) in &guard.rate_limit_tokens {
+            let _ = writeln!(out, "enigma_ipc_rate_limit_tokens{{bucket=\"{}\"}} {}", bucket, tokens);
+        }
+        out.push_str("# HELP enigma_ipc_rate_limit_burst Size of a rate limit bucket.\n");
+        out.push_str("# TYPE enigma_ipc_rate_limit_burst gauge\n");
+        for (bucket, (_, burst)) in &guard.rate_limit_tokens {
+            let _ = writeln!(out, "enigma_ipc_rate_limit_burst{{bucket=\"{}\"}} {}", bucket, burst);
+        }
+
+        out.push_str("# HELP enigma_enclave_call_duration_seconds Time spent inside ecalls, by ecall.\n");
+        out.push_str("# TYPE enigma_enclave_call_duration_seconds histogram\n");
+        for (ecall, hist) in &guard.enclave_calls {
+            hist.render(&mut out, "enigma_enclave_call_duration_seconds", &format!("ecall=\"{}\"", ecall));
+        }
+
+        out.push_str("# HELP enigma_errors_total Number of errors returned to the caller, by code.\n");
+        out.push_str("# TYPE enigma_errors_total counter\n");
+        for (code, count) in &guard.errors {
+            let _ = writeln!(out, "enigma_errors_total{{code=\"{}\"}} {}", code, count);
+        }
+
+        out.push_str("# HELP enigma_task_gas_used_total Total gas used by executed tasks.\n");
+        out.push_str("# TYPE enigma_task_gas_used_total counter\n");
+        let _ = writeln!(out, "enigma_task_gas_used_total {}", guard.task_gas);
+        out.push_str("# HELP enigma_tasks_total Number of executed tasks.\n");
+        out.push_str("# TYPE enigma_tasks_total counter\n");
+        let _ = writeln!(out, "enigma_tasks_total {}", guard.tasks);
+
+        out.push_str("# HELP enigma_db_contracts Number of contracts stored in the DB.\n");
+        out.push_str("# TYPE enigma_db_contracts gauge\n");
+        let _ = writeln!(out, "enigma_db_contracts {}", guard.db_contracts);
+        out.push_str("# HELP enigma_db_disk_bytes Size of the DB directory on disk.\n");
+        out.push_str("# TYPE enigma_db_disk_bytes gauge\n");
+        let _ = writeln!(out, "enigma_db_disk_bytes {}", guard.db_disk_bytes);
+
+        out.push_str("# HELP enigma_enclave_healthy Whether the last ecall returned SGX_SUCCESS.\n");
+        out.push_str("# TYPE enigma_enclave_healthy gauge\n");
+        let _ = writeln!(out, "enigma_enclave_healthy {}", guard.enclave_healthy as u8);
+        out.push_str("# HELP enigma_enclave_degraded Whether the p95 latency of the watchdog pings is over the threshold.\n");
+        out.push_str("# TYPE enigma_enclave_degraded gauge\n");
+        let _ = writeln!(out, "enigma_enclave_degraded {}", guard.enclave_degraded as u8);
+        out.push_str("# HELP enigma_enclave_reinits_total Number of times the enclave was re-created after failing the watchdog pings.\n");
+        out.push_str("# TYPE enigma_enclave_reinits_total counter\n");
+        let _ = writeln!(out, "enigma_enclave_reinits_total {}", guard.enclave_reinits);
+
+        out.push_str("# HELP enigma_handler_panics_total Number of IPC requests whose handler panicked.\n");
+        out.push_str("# TYPE enigma_handler_panics_total counter\n");
+        let _ = writeln!(out, "enigma_handler_panics_total {}", guard.handler_panics);
+        out.push_str("# HELP enigma_handler_panic_breaker_tripped Whether the handlers panicked too often and are reported unhealthy.\n");
+        out.push_str("# TYPE enigma_handler_panic_breaker_tripped gauge\n");
+        let _ = writeln!(out, "enigma_handler_panic_breaker_tripped {}", guard.panic_breaker_tripped as u8);
+        out.push_str("# HELP enigma_draining Whether the core is draining and refuses the mutating requests.\n");
+        out.push_str("# TYPE enigma_draining gauge\n");
+        let _ = writeln!(out, "enigma_draining {}", guard.draining as u8);
+        out.push_str("# HELP enigma_ipc_in_flight_mutating Number of mutating IPC requests accepted and not answered yet.\n");
+        out.push_str("# TYPE enigma_ipc_in_flight_mutating gauge\n");
+        let _ = writeln!(out, "enigma_ipc_in_flight_mutating {}", guard.in_flight);
+        out.push_str("# HELP enigma_maintenance_runs_total Number of DB maintenance passes.\n");
+        out.push_str("# TYPE enigma_maintenance_runs_total counter\n");
+        let _ = writeln!(out, "enigma_maintenance_runs_total {}", guard.maintenance_runs);
+        out.push_str("# HELP enigma_maintenance_removed_total Number of DB entries removed by the maintenance, by kind.\n");
+        out.push_str("# TYPE enigma_maintenance_removed_total counter\n");
+        for (kind, count) in &guard.maintenance_removed {
+            let _ = writeln!(out, "enigma_maintenance_removed_total{{kind=\"{}\"}} {}", kind, count);
+        }
+        out.push_str("# HELP enigma_maintenance_reclaimed_bytes_total Bytes of disk freed by the maintenance compactions.\n");
+        out.push_str("# TYPE enigma_maintenance_reclaimed_bytes_total counter\n");
+        let _ = writeln!(out, "enigma_maintenance_reclaimed_bytes_total {}", guard.maintenance_reclaimed_bytes);
+        out
+    }
+}
+
+/// Returns the label used in `enigma_errors_total` for an error returned by a handler.
+pub fn error_code(e: &failure::Error) -> String {
+    use crate::common_u::errors::{BusyErr, CapacityExceededErr, DBErr, DeltaGapErr, DrainingErr, EnclaveFailError, InternalErr, InvalidRequestIdErr, NotDrainedErr, P2PErr, RecoveringErr, StaleEpochErr, StateBehindErr, UnknownEpochErr};
+    if let Some(e) = e.downcast_ref::<EnclaveFailError>() {
+        format!("enclave_{:?}", e.err)
+    } else if let Some(e) = e.downcast_ref::<DBErr>() {
+        format!("db_{}", e.kind.code())
+    } else if e.downcast_ref::<StaleEpochErr>().is_some() {
+        "stale_epoch".to_string()
+    } else if e.downcast_ref::<UnknownEpochErr>().is_some() {
+        "unknown_epoch".to_string()
+    } else if e.downcast_ref::<StateBehindErr>().is_some() {
+        "state_behind".to_string()
+    } else if e.downcast_ref::<DeltaGapErr>().is_some() {
+        "delta_gap".to_string()
+    } else if e.downcast_ref::<RecoveringErr>().is_some() {
+        "recovering".to_string()
+    } else if e.downcast_ref::<BusyErr>().is_some() {
+        "busy".to_string()
+    } else if e.downcast_ref::<DrainingErr>().is_some() {
+        "draining".to_string()
+    } else if e.downcast_ref::<NotDrainedErr>().is_some() {
+        "not_drained".to_string()
+    } else if e.downcast_ref::<CapacityExceededErr>().is_some() {
+        "capacity_exceeded".to_string()
+    } else if e.downcast_ref::<InvalidRequestIdErr>().is_some() {
+        "invalid_id".to_string()
+    } else if e.downcast_ref::<P2PErr>().is_some() {
+        "p2p".to_string()
+    } else if e.downcast_ref::<InternalErr>().is_some() {
+        "internal".to_string()
+    } else {
+        "other".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut hist = Histogram::default();
+        hist.observe(Duration::from_millis(3));
+        hist.observe(Duration::from_secs(20));
+        assert_eq!(hist.count(), 2);
+        assert_eq!(hist.buckets[0], 0);
+        assert_eq!(hist.buckets[1], 1);
+        assert_eq!(hist.buckets[BUCKETS_LEN - 1], 1);
+    }
+
+    #[test]
+    fn test_render_families() {
+        let metrics = Metrics::default();
+        metrics.record_request("GetTip", Duration::from_millis(2));
+        metrics.record_error("p2p");
+        metrics.record_task_gas(42);
+        let text = metrics.render();
+        assert!(text.contains("enigma_ipc_requests_total{type=\"GetTip\"} 1"));
+        assert!(text.contains("enigma_ipc_request_duration_seconds_count{type=\"GetTip\"} 1"));
+        assert!(text.contains("enigma_errors_total{code=\"p2p\"} 1"));
+        assert!(text.contains("enigma_task_gas_used_total 42"));
+        assert!(text.contains("enigma_enclave_healthy 0"));
+    }
+
+    #[test]
+    fn test_render_queue() {
+        let metrics = Metrics::default();
+        metrics.set_queue_capacity(8);
+        metrics.record_queue_wait(Duration::from_millis(2), 3);
+        metrics.record_shed("ComputeTask");
+        metrics.record_shed("ComputeTask");
+        let text = metrics.render();
+        assert!(text.contains("enigma_ipc_queue_depth 3"));
+        assert!(text.contains("enigma_ipc_queue_capacity 8"));
+        assert!(text.contains("enigma_ipc_queue_wait_seconds_bucket{le=\"0.005\"} 1"));
+        assert!(text.contains("enigma_ipc_queue_wait_seconds_count 1"));
+        assert!(text.contains("enigma_ipc_shed_total{type=\"ComputeTask\"} 2"));
+        assert_eq!(metrics.shed_count(), 2);
+
+        metrics.set_draining(true);
+        metrics.set_in_flight(1);
+        let text = metrics.render();
+        assert!(text.contains("enigma_draining 1"));
+        assert!(text.contains("enigma_ipc_in_flight_mutating 1"));
+    }
+
+    #[test]
+    fn test_render_maintenance() {
+        let metrics = Metrics::default();
+        metrics.record_maintenance(&[("registrations", 3), ("hot_set", 0)], 4096);
+        metrics.record_maintenance(&[("registrations", 1), ("hot_set", 2)], 0);
+        let text = metrics.render();
+        assert!(text.contains("enigma_maintenance_runs_total 2"));
+        assert!(text.contains("enigma_maintenance_removed_total{kind=\"registrations\"} 4"));
+        assert!(text.contains("enigma_maintenance_removed_total{kind=\"hot_set\"} 2"));
+        assert!(text.contains("enigma_maintenance_reclaimed_bytes_total 4096"));
+    }
+
+    #[test]
+    fn test_render_rate_limits() {
+        let metrics = Metrics::default();
+        metrics.record_throttled("GetAllTips");
+        metrics.set_rate_limit_tokens("GetAllTips", 0.0, 5);
+        metrics.set_rate_limit_tokens("global", 12.5, 20);
+        let text = metrics.render();
+        assert!(text.contains("enigma_ipc_throttled_total{type=\"GetAllTips\"} 1"));
+        assert!(text.contains("enigma_ipc_rate_limit_tokens{bucket=\"GetAllTips\"} 0"));
+        assert!(text.contains("enigma_ipc_rate_limit_tokens{bucket=\"global\"} 12.5"));
+        assert!(text.contains("enigma_ipc_rate_limit_burst{bucket=\"global\"} 20"));
+    }
+}
@@ -1 +1,8 @@
+pub mod drain;
+pub mod epoch;
 pub mod errors;
+pub mod metrics;
+pub mod network;
+pub mod panics;
+pub mod rate_limit;
+pub mod recovery;
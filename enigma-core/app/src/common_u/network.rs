@@ -0,0 +1,83 @@
+//! # Network.
+//! The Ethereum network a node works against. It namespaces the DB and the sealed data directories,
+//! is recorded in the DB when it's created, and goes into the registration report so nodes can't mix networks.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Ropsten,
+    Kovan,
+    /// Any other chain, by its chain id.
+    Custom(u64),
+}
+
+impl Network {
+    pub fn chain_id(self) -> u64 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Ropsten => 3,
+            Network::Kovan => 42,
+            Network::Custom(chain_id) => chain_id,
+        }
+    }
+
+    pub fn from_chain_id(chain_id: u64) -> Self {
+        match chain_id {
+            1 => Network::Mainnet,
+            3 => Network::Ropsten,
+            42 => Network::Kovan,
+            chain_id => Network::Custom(chain_id),
+        }
+    }
+
+    /// The name used for the namespaced directories, e.g. `kovan` or `chain-1337`.
+    pub fn name(self) -> String {
+        match self {
+            Network::Mainnet => "mainnet".to_string(),
+            Network::Ropsten => "ropsten".to_string(),
+            Network::Kovan => "kovan".to_string(),
+            Network::Custom(chain_id) => format!("chain-{}", chain_id),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.name()) }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    /// Accepts `mainnet`, `ropsten`, `kovan`, or a chain id (as a number or as `chain-<id>`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "ropsten" => Ok(Network::Ropsten),
+            "kovan" => Ok(Network::Kovan),
+            other => other
+                .trim_start_matches("chain-")
+                .parse::<u64>()
+                .map(Network::from_chain_id)
+                .map_err(|_| format!("Unknown network: {}, expected mainnet, ropsten, kovan or a chain id", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_network() {
+        assert_eq!("kovan".parse::<Network>().unwrap(), Network::Kovan);
+        assert_eq!("Mainnet".parse::<Network>().unwrap(), Network::Mainnet);
+        assert_eq!("3".parse::<Network>().unwrap(), Network::Ropsten);
+        assert_eq!("1337".parse::<Network>().unwrap(), Network::Custom(1337));
+        assert_eq!(Network::Custom(1337).name().parse::<Network>().unwrap(), Network::Custom(1337));
+        assert!("rinkeby".parse::<Network>().is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! # Handler panics.
+//! A panic in a handler is caught for its request alone (see `ipc_listener::handle_message`), the request is answered
+//! with an `InternalErr` and the listener keeps serving the next ones.
+//! A handler that keeps panicking is more than a bad request though, after `max_panics` panics within `window`
+//! the breaker trips and `GetHealth` reports the handlers as unhealthy until the core is restarted.
+//!
+//! A panic can stop a handler halfway through updating what's kept in memory, so that's rebuilt from the DB before the
+//! next request (the breaker trips if it can't be). The global mutexes it held stay poisoned, they're taken with
+//! [`LockRecover`] and used as they were left: none of them is left inconsistent by a panic between two of their calls.
+//! The panic messages are scrubbed of secrets before they're logged, including by the hook [`install_hook`] sets.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, PanicInfo};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use common_u::epoch::EPOCH;
+use common_u::rate_limit::RATE_LIMITS;
+use common_u::recovery::RECOVERY;
+
+/// How many panics within `DEFAULT_PANIC_WINDOW` trip the breaker unless configured otherwise.
+pub const DEFAULT_MAX_PANICS: usize = 5;
+pub const DEFAULT_PANIC_WINDOW: Duration = Duration::from_secs(60);
+
+// Hex runs at least this long are keys, hashes or ciphertexts, they're dropped from the messages we return.
+const MIN_SECRET_HEX_LEN: usize = 32;
+const REDACTED: &str = "<redacted>";
+
+lazy_static! { pub static ref PANIC_BREAKER: Mutex<PanicBreaker> = Mutex::new(PanicBreaker::new(DEFAULT_MAX_PANICS, DEFAULT_PANIC_WINDOW)); }
+
+#[derive(Debug, Clone)]
+pub struct PanicBreaker {
+    max_panics: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+    tripped: bool,
+}
+
+impl PanicBreaker {
+    pub fn new(max_panics: usize, window: Duration) -> Self {
+        PanicBreaker { max_panics, window, recent: VecDeque::new(), tripped: false }
+    }
+
+    pub fn configure(&mut self, max_panics: usize, window: Duration) {
+        self.max_panics = max_panics;
+        self.window = window;
+    }
+
+    /// Records a caught panic, returns true if it tripped the breaker.
+    pub fn record(&mut self, now: Instant) -> bool {
+        while self.recent.front().map_or(false, |&at| now.duration_since(at) >= self.window) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        if self.tripped || self.recent.len() < self.max_panics {
+            return false;
+        }
+        self.tripped = true;
+        true
+    }
+
+    /// Trips the breaker regardless of the count, for a panic whose state couldn't be recovered.
+    pub fn trip(&mut self) { self.tripped = true; }
+
+    /// Once tripped it stays so, the handlers aren't trusted anymore.
+    pub fn tripped(&self) -> bool { self.tripped }
+}
+
+/// Like `LockExpectMutex`, but a mutex poisoned by a caught panic is taken anyway instead of panicking again.
+pub trait LockRecover<T> {
+    fn lock_recover(&self, name: &str) -> MutexGuard<T>;
+}
+
+impl<T> LockRecover<T> for Mutex<T> {
+    fn lock_recover(&self, name: &str) -> MutexGuard<T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            trace!("Taking the poisoned {} mutex", name);
+            poisoned.into_inner()
+        })
+    }
+}
+
+/// The global mutexes a handler panicked while holding.
+pub fn poisoned_mutexes() -> Vec<&'static str> {
+    let mutexes = [
+        ("Epoch", EPOCH.is_poisoned()),
+        ("Recovery", RECOVERY.is_poisoned()),
+        ("Rate limits", RATE_LIMITS.is_poisoned()),
+        ("Panic breaker", PANIC_BREAKER.is_poisoned()),
+    ];
+    mutexes.iter().filter(|(_, poisoned)| *poisoned).map(|(name, _)| *name).collect()
+}
+
+/// Replaces the default panic hook, which prints the raw message to stderr, with one logging the scrubbed message.
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info: &PanicInfo| {
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line())).unwrap_or_default();
+        error!("Panicked at {}: {}", location, panic_message(info.payload()));
+    }));
+}
+
+/// The message a panic was started with, without the secrets it may contain.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "Unknown panic payload"
+    };
+    scrub(msg)
+}
+
+/// Replaces the long hex runs (keys, hashes, encrypted data) and the byte arrays in `msg`.
+pub fn scrub(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut rest = msg;
+    while !rest.is_empty() {
+        let hex_len = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or_else(|| rest.len());
+        let whole_word = !rest[hex_len..].starts_with(|c: char| c.is_ascii_alphanumeric());
+        if rest.starts_with("0x") {
+            out.push_str("0x");
+            rest = &rest[2..];
+        } else if hex_len >= MIN_SECRET_HEX_LEN && whole_word {
+            out.push_str(REDACTED);
+            rest = &rest[hex_len..];
+        } else if let Some(array_len) = byte_array_len(rest) {
+            out.push_str(REDACTED);
+            rest = &rest[array_len..];
+        } else {
+            // The whole word, so the tail of a long word isn't taken for a run of its own.
+            let word_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or_else(|| rest.len()).max(1);
+            let word_len = (word_len..=rest.len()).find(|&i| rest.is_char_boundary(i)).unwrap_or_else(|| rest.len());
+            out.push_str(&rest[..word_len]);
+            rest = &rest[word_len..];
+        }
+    }
+    out
+}
+
+// The length of a `Debug` printed `[u8]` (`[1, 2, 3]`) at the start of `s`, only for the ones long enough to be a key.
+fn byte_array_len(s: &str) -> Option<usize> {
+    if !s.starts_with('[') {
+        return None;
+    }
+    let end = s.find(']')?;
+    let items: Vec<&str> = s[1..end].split(',').map(str::trim).collect();
+    if items.len() >= 16 && items.iter().all(|item| item.parse::<u8>().is_ok()) {
+        Some(end + 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn test_breaker() {
+        let start = Instant::now();
+        let mut breaker = PanicBreaker::new(3, Duration::from_secs(10));
+        assert!(!breaker.record(start));
+        assert!(!breaker.record(start + Duration::from_secs(5)));
+        // The first one is out of the window.
+        assert!(!breaker.record(start + Duration::from_secs(11)));
+        assert!(!breaker.tripped());
+        assert!(breaker.record(start + Duration::from_secs(12)));
+        assert!(breaker.tripped());
+        assert!(!breaker.record(start + Duration::from_secs(100)));
+        assert!(breaker.tripped());
+    }
+
+    #[test]
+    fn test_scrub() {
+        let key = "ab".repeat(32);
+        assert_eq!(scrub(&format!("Bad key {} for 0x{}", key, key)), "Bad key <redacted> for 0x<redacted>");
+        assert_eq!(scrub("called `Option::unwrap()` on a `None` value"), "called `Option::unwrap()` on a `None` value");
+        assert_eq!(scrub(&format!("state {:?} !", [7u8; 32])), "state <redacted> !");
+        assert_eq!(scrub("short [1, 2, 3] and deadbeef"), "short [1, 2, 3] and deadbeef");
+        // A long word that isn't all hex is left alone.
+        let word = format!("{}xyz", "a".repeat(40));
+        assert_eq!(scrub(&word), word);
+        assert_eq!(scrub("ünïcode ✓"), "ünïcode ✓");
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let secret = "cd".repeat(32);
+        let payload = panic::catch_unwind(|| panic!("leaking {}", "cd".repeat(32))).unwrap_err();
+        assert_eq!(panic_message(&*payload), "leaking <redacted>");
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "static");
+        assert!(!panic_message(&*panic::catch_unwind(|| panic!("{}", secret)).unwrap_err()).contains(&secret));
+    }
+}
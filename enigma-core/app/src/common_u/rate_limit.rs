@@ -0,0 +1,237 @@
+//! # IPC rate limits.
+//! Token buckets per request type, and one for all of them, checked by the IPC queue before a request is queued.
+//! A request over its limit is answered right away with a `Busy` error saying when its bucket has a token again,
+//! so a p2p loop flooding one request type can't starve the others. `GetHealth` and `ReloadConfig` are never limited.
+//!
+//! The limits are read from the JSON file given with `--rate-limits`, and read again on `ReloadConfig`:
+//! ```json
+//! { "global": { "perSecond": 200, "burst": 400 }, "types": { "GetAllTips": { "perSecond": 2, "burst": 5 } } }
+//! ```
+//! A request type without an entry is only limited by `global`, without a file nothing is limited.
+
+use common_u::metrics::METRICS;
+use failure::Error;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+lazy_static! { pub static ref RATE_LIMITS: Mutex<RateLimiter> = Mutex::new(RateLimiter::default()); }
+
+/// The request types that are never limited.
+pub const EXEMPT_TYPES: [&str; 2] = ["GetHealth", "ReloadConfig"];
+
+/// The label of the bucket shared by all the request types in the metrics.
+pub const GLOBAL_BUCKET: &str = "global";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    /// How many tokens are added back every second.
+    pub per_second: f64,
+    /// How many requests can be taken at once after a quiet period.
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub global: Option<RateLimit>,
+    #[serde(default)]
+    pub types: BTreeMap<String, RateLimit>,
+}
+
+impl RateLimitConfig {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config: RateLimitConfig = serde_json::from_slice(&fs::read(path)?)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let global = self.global.iter().map(|limit| (GLOBAL_BUCKET, limit));
+        for (name, limit) in global.chain(self.types.iter().map(|(kind, limit)| (kind.as_str(), limit))) {
+            if limit.per_second.is_nan() || limit.per_second <= 0.0 || limit.burst == 0 {
+                bail!("The rate limit of {} must have a positive perSecond and burst", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self { TokenBucket { limit, tokens: f64::from(limit.burst), refilled_at: now } }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.refilled_at {
+            let elapsed = now.duration_since(self.refilled_at);
+            let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            self.tokens = (self.tokens + secs * self.limit.per_second).min(f64::from(self.limit.burst));
+            self.refilled_at = now;
+        }
+    }
+
+    /// How many milliseconds until `count` tokens are available, 0 if they are now.
+    fn wait_ms(&self, count: usize) -> u64 {
+        let missing = count as f64 - self.tokens;
+        if missing <= 0.0 {
+            0
+        } else {
+            (missing / self.limit.per_second * 1000.0).ceil() as u64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    source: Option<PathBuf>,
+    config: RateLimitConfig,
+    global: Option<TokenBucket>,
+    buckets: BTreeMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Reads the limits from `path`, `reload` reads them from there again.
+    pub fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let config = RateLimitConfig::load(path)?;
+        self.source = Some(path.to_path_buf());
+        self.configure(config);
+        Ok(())
+    }
+
+    /// Reads the limits again from the file they were loaded from, the current ones are kept if it's invalid.
+    pub fn reload(&mut self) -> Result<RateLimitConfig, Error> {
+        let path = self.source.clone().ok_or_else(|| format_err!("No rate limits file was given with --rate-limits"))?;
+        let config = RateLimitConfig::load(&path)?;
+        self.configure(config.clone());
+        Ok(config)
+    }
+
+    /// Replaces the limits, every bucket starts full again.
+    pub fn configure(&mut self, config: RateLimitConfig) {
+        let now = Instant::now();
+        self.global = config.global.map(|limit| TokenBucket::new(limit, now));
+        self.buckets = config.types.iter().map(|(kind, limit)| (kind.clone(), TokenBucket::new(*limit, now))).collect();
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &RateLimitConfig { &self.config }
+
+    /// Takes a token for each of `kinds` (the request types of a single message), either all of them or none.
+    /// Returns how many milliseconds to wait before retrying if any of the buckets doesn't have enough tokens.
+    pub fn admit(&mut self, kinds: &[&str], now: Instant) -> Result<(), u64> {
+        let kinds: Vec<&str> = kinds.iter().cloned().filter(|kind| !EXEMPT_TYPES.contains(kind)).collect();
+        if kinds.is_empty() {
+            return Ok(());
+        }
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for kind in &kinds {
+            *counts.entry(*kind).or_insert(0) += 1;
+        }
+
+        let mut wait_ms = 0;
+        if let Some(global) = self.global.as_mut() {
+            global.refill(now);
+            wait_ms = global.wait_ms(kinds.len());
+        }
+        for (kind, count) in &counts {
+            if let Some(bucket) = self.buckets.get_mut(*kind) {
+                bucket.refill(now);
+                wait_ms = wait_ms.max(bucket.wait_ms(*count));
+            }
+        }
+        if wait_ms > 0 {
+            for kind in &kinds {
+                METRICS.record_throttled(kind);
+            }
+            return Err(wait_ms);
+        }
+
+        if let Some(global) = self.global.as_mut() {
+            global.tokens -= kinds.len() as f64;
+            METRICS.set_rate_limit_tokens(GLOBAL_BUCKET, global.tokens, global.limit.burst);
+        }
+        for (kind, count) in counts {
+            if let Some(bucket) = self.buckets.get_mut(kind) {
+                bucket.tokens -= count as f64;
+                METRICS.set_rate_limit_tokens(kind, bucket.tokens, bucket.limit.burst);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(global: Option<RateLimit>, types: &[(&str, RateLimit)]) -> RateLimiter {
+        let mut limiter = RateLimiter::default();
+        let types = types.iter().map(|(kind, limit)| (kind.to_string(), *limit)).collect();
+        limiter.configure(RateLimitConfig { global, types });
+        limiter
+    }
+
+    #[test]
+    fn test_throttles_one_type_only() {
+        let mut limiter = limiter(None, &[("GetAllTips", RateLimit { per_second: 10.0, burst: 5 })]);
+        let now = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(limiter.admit(&["GetAllTips"], now), Ok(()));
+        }
+        for _ in 0..100 {
+            assert_eq!(limiter.admit(&["GetAllTips"], now), Err(100));
+            assert_eq!(limiter.admit(&["GetTip"], now), Ok(()));
+        }
+        // A token is back after 100ms.
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.admit(&["GetAllTips"], later), Ok(()));
+        assert!(limiter.admit(&["GetAllTips"], later).is_err());
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let mut limiter = limiter(Some(RateLimit { per_second: 1.0, burst: 3 }), &[]);
+        let now = Instant::now();
+        assert_eq!(limiter.admit(&["GetTip", "GetTips"], now), Ok(()));
+        // The whole message is refused, nothing is taken from the buckets.
+        assert_eq!(limiter.admit(&["GetTip", "GetTip"], now), Err(1000));
+        assert_eq!(limiter.admit(&["ComputeTask"], now), Ok(()));
+        assert_eq!(limiter.admit(&["GetHealth", "ReloadConfig"], now), Ok(()));
+        assert!(limiter.admit(&["ComputeTask"], now).is_err());
+    }
+
+    #[test]
+    fn test_reconfigure_refills() {
+        let mut limiter = limiter(None, &[("GetAllTips", RateLimit { per_second: 1.0, burst: 1 })]);
+        let now = Instant::now();
+        assert!(limiter.admit(&["GetAllTips"], now).is_ok());
+        assert!(limiter.admit(&["GetAllTips"], now).is_err());
+        limiter.configure(RateLimitConfig::default());
+        assert!(limiter.admit(&["GetAllTips"], now).is_ok());
+        assert!(limiter.reload().is_err());
+    }
+
+    #[test]
+    fn test_config_json() {
+        let config: RateLimitConfig =
+            serde_json::from_str(r#"{"global":{"perSecond":200,"burst":400},"types":{"GetAllTips":{"perSecond":0.5,"burst":2}}}"#).unwrap();
+        assert_eq!(config.global, Some(RateLimit { per_second: 200.0, burst: 400 }));
+        assert_eq!(config.types["GetAllTips"], RateLimit { per_second: 0.5, burst: 2 });
+        assert!(config.validate().is_ok());
+
+        let config: RateLimitConfig = serde_json::from_str(r#"{"types":{"GetTip":{"perSecond":0,"burst":2}}}"#).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! # Contract key recovery.
+//! The state keys only live inside the enclave, a worker that lost them can't decrypt the state of the contracts it hosts.
+//! A recovery (started with `--recover` or `RecoverKeys`) asks the KM node for the keys again,
+//! and `ComputeTask` is refused with a `RecoveringErr` until the enclave holds a key for every hosted contract,
+//! or until the timeout passes, so we don't keep a worker down forever because of a single contract the KM won't give us.
+
+use common_u::errors::RecoveringErr;
+use enigma_types::ContractAddress;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a recovery blocks the tasks unless configured otherwise.
+pub const DEFAULT_RECOVERY_TIMEOUT: Duration = Duration::from_secs(600);
+
+lazy_static! { pub static ref RECOVERY: Mutex<Recovery> = Mutex::new(Recovery::new(DEFAULT_RECOVERY_TIMEOUT)); }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    pub provisioned: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pending: HashSet<ContractAddress>,
+    total: usize,
+    deadline: Option<Instant>,
+    timeout: Duration,
+}
+
+impl Recovery {
+    pub fn new(timeout: Duration) -> Self { Recovery { pending: HashSet::new(), total: 0, deadline: None, timeout } }
+
+    pub fn configure(&mut self, timeout: Duration) { self.timeout = timeout; }
+
+    /// Starts waiting for the keys of `addresses`, replacing any recovery in progress.
+    pub fn start<I: IntoIterator<Item = ContractAddress>>(&mut self, addresses: I) {
+        self.pending = addresses.into_iter().collect();
+        self.total = self.pending.len();
+        // Nothing to wait for if we don't host anything.
+        self.deadline = if self.pending.is_empty() { None } else { Some(Instant::now() + self.timeout) };
+        info!("Recovering the state keys of {} contracts", self.total);
+    }
+
+    /// Marks the contracts the enclave now holds keys for.
+    pub fn provisioned(&mut self, addresses: &[ContractAddress]) {
+        if self.deadline.is_none() {
+            return;
+        }
+        for address in addresses {
+            self.pending.remove(address);
+        }
+        if self.pending.is_empty() {
+            info!("Recovered the state keys of all {} contracts", self.total);
+            self.deadline = None;
+        }
+    }
+
+    /// `None` if no recovery was ever started.
+    pub fn progress(&self) -> Option<RecoveryProgress> {
+        if self.total == 0 && self.deadline.is_none() {
+            return None;
+        }
+        Some(RecoveryProgress { provisioned: self.total - self.pending.len(), total: self.total })
+    }
+
+    /// True until the keys of all the contracts arrived, even after the timeout.
+    pub fn is_waiting(&self) -> bool { !self.pending.is_empty() }
+
+    /// True while tasks are refused.
+    pub fn is_active(&self) -> bool { self.deadline.map_or(false, |deadline| Instant::now() < deadline) }
+
+    /// Fails while the recovery is still waiting for keys.
+    pub fn check(&self) -> Result<(), RecoveringErr> {
+        if self.is_active() {
+            let RecoveryProgress { provisioned, total } = self.progress().unwrap_or(RecoveryProgress { provisioned: 0, total: 0 });
+            return Err(RecoveringErr { provisioned, total });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addresses() -> Vec<ContractAddress> { (1..4u8).map(|i| [i; 32].into()).collect() }
+
+    #[test]
+    fn test_no_recovery() {
+        let recovery = Recovery::new(DEFAULT_RECOVERY_TIMEOUT);
+        assert!(recovery.check().is_ok());
+        assert_eq!(recovery.progress(), None);
+    }
+
+    #[test]
+    fn test_recovery_progress() {
+        let addresses = addresses();
+        let mut recovery = Recovery::new(DEFAULT_RECOVERY_TIMEOUT);
+        recovery.start(addresses.clone());
+        assert_eq!(recovery.check().unwrap_err(), RecoveringErr { provisioned: 0, total: 3 });
+
+        // Keys for contracts we weren't waiting for don't count.
+        recovery.provisioned(&[addresses[0], [9u8; 32].into()]);
+        assert_eq!(recovery.progress(), Some(RecoveryProgress { provisioned: 1, total: 3 }));
+        assert!(recovery.check().is_err());
+
+        recovery.provisioned(&addresses);
+        assert!(recovery.check().is_ok());
+        assert!(!recovery.is_active());
+        assert_eq!(recovery.progress(), Some(RecoveryProgress { provisioned: 3, total: 3 }));
+    }
+
+    #[test]
+    fn test_recovery_timeout() {
+        let mut recovery = Recovery::new(Duration::from_secs(0));
+        recovery.start(addresses());
+        assert!(recovery.check().is_ok());
+        assert_eq!(recovery.progress(), Some(RecoveryProgress { provisioned: 0, total: 3 }));
+        // Keys that arrive late still show up in the progress.
+        assert!(recovery.is_waiting());
+        recovery.provisioned(&addresses()[..1]);
+        assert_eq!(recovery.progress(), Some(RecoveryProgress { provisioned: 1, total: 3 }));
+    }
+
+    #[test]
+    fn test_recovery_restart() {
+        let addresses = addresses();
+        let mut recovery = Recovery::new(DEFAULT_RECOVERY_TIMEOUT);
+        recovery.start(addresses.clone());
+        recovery.provisioned(&addresses);
+        recovery.start(vec![addresses[1]]);
+        assert_eq!(recovery.check().unwrap_err(), RecoveringErr { provisioned: 0, total: 1 });
+    }
+}
@@ -0,0 +1,284 @@
+//! # Configuration.
+//! The options of `cli::Opt` come from three places, each one overriding the previous:
+//! 1. the JSON file given with `--config`, keyed by the names of `--print-config`: `{"port": 5552, "mirror_mode": "sync"}`,
+//! 2. the environment, `ENIGMA_CORE_<NAME>`: `ENIGMA_CORE_MIRROR_MODE=sync`,
+//! 3. the command line.
+//!
+//! An option given by none of them has its default. The values of the file and of the environment are parsed exactly
+//! like the command line ones, then [`validate`] checks what the parsing can't (the SPID, the bind addresses, ...).
+//! An invalid value is a `ConfigErr` naming the option and where its value came from.
+//!
+//! `--print-config` prints the resolved options as JSON and exits. The secrets (the SPID) are redacted there and in the
+//! startup banner.
+
+use cli::Opt;
+use common_u::errors::ConfigErr;
+use common_u::rate_limit::RateLimitConfig;
+use failure::Error;
+use log::LevelFilter;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::clap::{self, AppSettings, ErrorKind};
+use structopt::StructOpt;
+
+/// The prefix of the environment variables setting an option.
+pub const ENV_PREFIX: &str = "ENIGMA_CORE_";
+
+/// What the secrets are replaced with in the printed config.
+pub const REDACTED: &str = "<redacted>";
+
+// Only read from the command line, the file can't point to another file.
+const CLI_ONLY: &[&str] = &["config", "print_config"];
+
+/// The `ENIGMA_CORE_*` variables of this process.
+pub fn env_overrides() -> BTreeMap<String, String> { env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect() }
+
+/// Resolves the options from the command line `args` (the binary first), the `env` overrides and the config file.
+/// A command line clap can't parse (or `--help`) is returned as the `clap::Error`.
+pub fn resolve(args: &[OsString], env: &BTreeMap<String, String>) -> Result<Opt, Error> {
+    let cli = Opt::clap().get_matches_from_safe(args.iter().cloned())?;
+
+    // The option, its value, and where it came from.
+    let mut layered: BTreeMap<String, (String, String)> = BTreeMap::new();
+    let config_path = cli.value_of_os("config").map(PathBuf::from).or_else(|| env.get(&env_name("config")).map(PathBuf::from));
+    if let Some(path) = &config_path {
+        for (option, value) in read_file(path)? {
+            layered.insert(option, (value, format!("{}", path.display())));
+        }
+    }
+    for (name, value) in env {
+        let option = name[ENV_PREFIX.len()..].to_lowercase();
+        if option != "config" {
+            layered.insert(option, (value.clone(), name.clone()));
+        }
+    }
+
+    let mut args = args.to_vec();
+    for (option, (value, source)) in layered {
+        if cli.occurrences_of(&option) > 0 {
+            continue;
+        }
+        let option_args = option_args(&option, &value).map_err(|msg| ConfigErr { field: option.clone(), msg: format!("{} (from {})", msg, source) })?;
+        args.extend(option_args.into_iter().map(OsString::from));
+    }
+    let opt = Opt::from_clap(&Opt::clap().get_matches_from_safe(args)?);
+    validate(&opt)?;
+    Ok(opt)
+}
+
+/// The checks that need more than parsing a single option.
+pub fn validate(opt: &Opt) -> Result<(), ConfigErr> {
+    let invalid = |field: &str, msg: &str| Err(ConfigErr { field: field.to_string(), msg: msg.to_string() });
+    if LevelFilter::from_str(&opt.log_level).is_err() {
+        return invalid("log_level", "expected off, error, warn, info, debug or trace");
+    }
+    // Never echoed, it's a secret.
+    if opt.spid.len() != 32 || !opt.spid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return invalid("spid", "expected 32 hex characters");
+    }
+    if let Some(bind) = &opt.metrics_bind {
+        let mut parts = bind.rsplitn(2, ':');
+        let port = parts.next().and_then(|port| port.parse::<u16>().ok());
+        if port.is_none() || parts.next().map_or(true, str::is_empty) {
+            return invalid("metrics_bind", "expected <host>:<port>");
+        }
+    }
+    if opt.dev_mode && !cfg!(debug_assertions) {
+        // A release enclave never records traces, and a production node must not return them.
+        return invalid("dev_mode", "only accepted by a debug build");
+    }
+    for &(field, value) in &[("queue_capacity", opt.queue_capacity), ("mirror_buffer", opt.mirror_buffer), ("panic_breaker_count", opt.panic_breaker_count)] {
+        if value == 0 {
+            return invalid(field, "must be at least 1");
+        }
+    }
+    if let Some(rate) = opt.address_index_fp_rate {
+        if !(rate > 0.0 && rate < 1.0) {
+            return invalid("address_index_fp_rate", "expected a rate between 0 and 1");
+        }
+    }
+    if opt.watchdog_interval > 0 && opt.watchdog_failures == 0 {
+        return invalid("watchdog_failures", "must be at least 1 while the watchdog is on");
+    }
+    if let Some(path) = &opt.rate_limits {
+        if let Err(e) = RateLimitConfig::load(path) {
+            return invalid("rate_limits", &e.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// The resolved options as printed by `--print-config`.
+pub fn to_json(opt: &Opt) -> Value { serde_json::to_value(opt).expect("The options are always serializable") }
+
+fn env_name(option: &str) -> String { format!("{}{}", ENV_PREFIX, option.to_uppercase()) }
+
+// The options of the file, `null` is the same as leaving one out.
+fn read_file(path: &Path) -> Result<Vec<(String, String)>, ConfigErr> {
+    let invalid = |msg: String| ConfigErr { field: "config".to_string(), msg };
+    let file = fs::read(path).map_err(|e| invalid(format!("failed reading {}: {}", path.display(), e)))?;
+    let options: BTreeMap<String, Value> =
+        serde_json::from_slice(&file).map_err(|e| invalid(format!("{} isn't a JSON object: {}", path.display(), e)))?;
+    let mut values = Vec::with_capacity(options.len());
+    for (option, value) in options {
+        let value = match value {
+            Value::Null => continue,
+            Value::String(value) => value,
+            Value::Number(value) => value.to_string(),
+            Value::Bool(value) => value.to_string(),
+            other => {
+                let msg = format!("expected a string, a number or a boolean, got {} (from {})", other, path.display());
+                return Err(ConfigErr { field: option, msg });
+            }
+        };
+        values.push((option, value));
+    }
+    Ok(values)
+}
+
+// The command line arguments giving `value` to `option`, parsed on their own first so an error is about that option alone.
+fn option_args(option: &str, value: &str) -> Result<Vec<String>, String> {
+    if CLI_ONLY.contains(&option) {
+        return Err("only accepted on the command line".to_string());
+    }
+    let arg = format!("--{}", option.replace('_', "-"));
+    let args = match parse_alone(&[arg.clone()]) {
+        // A flag, set with a boolean.
+        Ok(()) => match value.trim().to_lowercase().as_str() {
+            "true" | "1" => vec![arg],
+            "false" | "0" | "" => Vec::new(),
+            _ => return Err(format!("expected true or false, got {:?}", value)),
+        },
+        Err(ref e) if e.kind == ErrorKind::UnknownArgument => return Err("unknown option".to_string()),
+        Err(_) => vec![format!("{}={}", arg, value)],
+    };
+    parse_alone(&args).map_err(|e| e.message.lines().next().unwrap_or_default().trim_start_matches("error: ").to_string())?;
+    Ok(args)
+}
+
+fn parse_alone(args: &[String]) -> Result<(), clap::Error> {
+    let args = iter::once("enigma-core".to_string()).chain(args.iter().cloned());
+    Opt::clap().setting(AppSettings::ColorNever).get_matches_from_safe(args).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn args(args: &[&str]) -> Vec<OsString> { iter::once("enigma-core").chain(args.iter().cloned()).map(OsString::from).collect() }
+
+    fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> { vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect() }
+
+    fn config_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn field(result: Result<Opt, Error>) -> String { result.unwrap_err().downcast::<ConfigErr>().unwrap().field }
+
+    #[test]
+    fn test_defaults() {
+        let opt = resolve(&args(&[]), &env(&[])).unwrap();
+        let config = to_json(&opt);
+        assert_eq!(config["port"], 5552);
+        assert_eq!(config["spid"], REDACTED);
+        assert_eq!(config["mirror_mode"], "async");
+        assert_eq!(config["warmup"], "background");
+        assert_eq!(config["network"], Value::Null);
+        assert!(config.get("print_config").is_none());
+    }
+
+    #[test]
+    fn test_layers() {
+        let file = config_file(r#"{"port": 6000, "retries": 3, "mirror_mode": "sync", "network": "kovan", "persist_task_deltas": true, "data_dir": null}"#);
+        let path = file.path().to_str().unwrap();
+        let env = env(&[("ENIGMA_CORE_RETRIES", "4"), ("ENIGMA_CORE_LOG_LEVEL", "debug"), ("ENIGMA_CORE_WARMUP", "off")]);
+        let opt = resolve(&args(&["--config", path, "--log-level", "warn", "-p", "7000"]), &env).unwrap();
+        let config = to_json(&opt);
+        assert_eq!(config, json!({
+            "config": path,
+            "data_dir": null,
+            "spid": REDACTED,
+            // The command line wins, even with the short name.
+            "port": 7000,
+            // Then the environment.
+            "retries": 4,
+            "log_level": "warn",
+            "metrics_bind": null,
+            "network": "kovan",
+            "legacy_status": false,
+            "warmup": "off",
+            "epoch_grace_blocks": 5,
+            "max_epoch_age": null,
+            "persist_task_deltas": true,
+            "recover": false,
+            "recover_timeout": 600,
+            "registration_history": 100,
+            "mirror": null,
+            "mirror_mode": "sync",
+            "mirror_buffer": 10000,
+            "orphan_deltas": "reject",
+            "max_db_bytes": null,
+            "max_contract_delta_bytes": null,
+            "max_contracts": null,
+            "hosting_mode": "all",
+            "address_index_fp_rate": null,
+            "watchdog_interval": 10,
+            "watchdog_p95_ms": 250,
+            "watchdog_timeout_ms": 5000,
+            "watchdog_failures": 3,
+            "queue_capacity": 64,
+            "compression_threshold": 8192,
+            "panic_breaker_count": 5,
+            "panic_breaker_window": 60,
+            "maintenance_interval": 3600,
+            "maintenance_max_queue_depth": 0,
+            "rate_limits": null,
+            "dev_mode": false
+        }));
+        assert_eq!(opt.spid, "B0335FD3BC1CCA8F804EB98A6420592D");
+
+        // The config file can come from the environment too, and a flag can be turned off again.
+        let env = self::env(&[("ENIGMA_CORE_CONFIG", path), ("ENIGMA_CORE_PERSIST_TASK_DELTAS", "false")]);
+        let opt = resolve(&args(&[]), &env).unwrap();
+        assert_eq!((opt.port, opt.retries, opt.persist_task_deltas), (6000, 3, false));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let file = config_file(r#"{"mirror_mode": "sometimes"}"#);
+        let path = file.path().to_str().unwrap();
+        let err = resolve(&args(&["--config", path]), &env(&[])).unwrap_err().downcast::<ConfigErr>().unwrap();
+        assert_eq!(err.field, "mirror_mode");
+        assert!(err.msg.contains(path), "{}", err.msg);
+
+        assert_eq!(field(resolve(&args(&[]), &env(&[("ENIGMA_CORE_PORT", "high")]))), "port");
+        assert_eq!(field(resolve(&args(&[]), &env(&[("ENIGMA_CORE_NO_SUCH_OPTION", "1")]))), "no_such_option");
+        assert_eq!(field(resolve(&args(&[]), &env(&[("ENIGMA_CORE_DEV_MODE", "maybe")]))), "dev_mode");
+        assert_eq!(field(resolve(&args(&[]), &env(&[("ENIGMA_CORE_PRINT_CONFIG", "true")]))), "print_config");
+        let nested = config_file(r#"{"port": [1]}"#);
+        assert_eq!(field(resolve(&args(&["--config", nested.path().to_str().unwrap()]), &env(&[]))), "port");
+        assert_eq!(field(resolve(&args(&["--config", "/no/such/config.json"]), &env(&[]))), "config");
+
+        // What only `validate` catches.
+        assert_eq!(field(resolve(&args(&["--spid", "1234"]), &env(&[]))), "spid");
+        assert_eq!(field(resolve(&args(&["--log-level", "loud"]), &env(&[]))), "log_level");
+        assert_eq!(field(resolve(&args(&["--metrics-bind", "9100"]), &env(&[]))), "metrics_bind");
+        assert_eq!(field(resolve(&args(&["--queue-capacity", "0"]), &env(&[]))), "queue_capacity");
+        assert_eq!(field(resolve(&args(&["--address-index-fp-rate", "1.5"]), &env(&[]))), "address_index_fp_rate");
+        assert_eq!(field(resolve(&args(&["--rate-limits", "/no/such/limits.json"]), &env(&[]))), "rate_limits");
+
+        // The command line itself is clap's to report.
+        assert!(resolve(&args(&["--port", "high"]), &env(&[])).unwrap_err().downcast::<clap::Error>().is_ok());
+    }
+}
@@ -0,0 +1,386 @@
+//! # Known addresses.
+//! Most of the lookups peers send a provider are for contracts it never stored. The index keeps the names of the
+//! column families of the contracts in memory, so `get_contract`, `get_tip`, `get_delta(s)` and the bytecode lookup
+//! of `ComputeTask` answer those with `MissingKey` (or `None`) without going to RocksDB.
+//!
+//! It's built from the column families when the DB is opened, so a DB restored from a copy or a mirror starts with
+//! the contracts it holds, and it's kept up to date as column families are created (`create`, `force_update`,
+//! `insert_tuples`) and dropped (`delete_contract`). It never misses a stored contract, a lookup it lets through
+//! is only answered by the DB as it was before the index.
+//!
+//! By default the names are kept in a set. With a false positive rate the set is replaced by a bloom filter, for the
+//! deployments with too many contracts to keep every name: a few lookups for unknown contracts then reach the DB.
+//! The filter is rebuilt from the column families when it outgrows its capacity and when a contract is removed.
+
+use failure::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::f64::consts::LN_2;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use db::DB;
+use rocksdb::DB as rocks_db;
+
+// The column family RocksDB always has, it's not a contract.
+pub(crate) const DEFAULT_CF: &str = "default";
+/// The bloom filter has room for at least this many contracts, and twice as many as it was built with.
+const MIN_BLOOM_CAPACITY: u64 = 1024;
+
+/// What `GetHealth` reports of the index.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressIndexStats {
+    /// The contracts in the index.
+    pub addresses: u64,
+    /// The false positive rate the bloom filter is sized for, `null` if the index is an exact set.
+    pub fp_rate: Option<f64>,
+    pub bloom_bits: Option<u64>,
+    pub bloom_hashes: Option<u32>,
+    /// The lookups answered from the index alone.
+    pub skipped_lookups: u64,
+    /// The lookups that went on to the DB.
+    pub store_lookups: u64,
+}
+
+struct Bloom {
+    words: Vec<u64>,
+    bits: u64,
+    hashes: u32,
+    capacity: u64,
+    fp_rate: f64,
+}
+
+impl Bloom {
+    fn new(addresses: u64, fp_rate: f64) -> Self {
+        let capacity = (addresses * 2).max(MIN_BLOOM_CAPACITY);
+        let bits = (-(capacity as f64) * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as u64;
+        let hashes = (bits as f64 / capacity as f64 * LN_2).round().max(1.0) as u32;
+        Bloom { words: vec![0; ((bits + 63) / 64) as usize], bits, hashes, capacity, fp_rate }
+    }
+
+    // Double hashing, the `hashes` positions are `h1 + i * h2`.
+    fn positions<'a>(&'a self, name: &str) -> impl Iterator<Item = u64> + 'a {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        let h1 = hasher.finish();
+        h1.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        (0..u64::from(self.hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bits)
+    }
+
+    fn insert(&mut self, name: &str) {
+        let positions: Vec<u64> = self.positions(name).collect();
+        for bit in positions {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool { self.positions(name).all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0) }
+}
+
+enum Filter {
+    Exact(HashSet<String>),
+    Bloom(Bloom),
+}
+
+struct IndexInner {
+    filter: Filter,
+    addresses: u64,
+}
+
+impl IndexInner {
+    fn build(names: &[String], fp_rate: Option<f64>) -> Self {
+        let names: Vec<&String> = names.iter().filter(|name| name.as_str() != DEFAULT_CF).collect();
+        let addresses = names.len() as u64;
+        let filter = match fp_rate {
+            None => Filter::Exact(names.into_iter().cloned().collect()),
+            Some(fp_rate) => {
+                let mut bloom = Bloom::new(addresses, fp_rate);
+                for name in names {
+                    bloom.insert(name);
+                }
+                Filter::Bloom(bloom)
+            }
+        };
+        IndexInner { filter, addresses }
+    }
+
+    fn fp_rate(&self) -> Option<f64> {
+        match &self.filter {
+            Filter::Exact(_) => None,
+            Filter::Bloom(bloom) => Some(bloom.fp_rate),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        match &self.filter {
+            Filter::Exact(names) => names.contains(name),
+            Filter::Bloom(bloom) => bloom.contains(name),
+        }
+    }
+}
+
+/// Shared between the DB and the health probe, so `GetHealth` can report it while the DB is busy.
+pub struct AddressIndex {
+    inner: Mutex<IndexInner>,
+    skipped_lookups: AtomicU64,
+    store_lookups: AtomicU64,
+}
+
+impl AddressIndex {
+    /// Indexes the column families `names` (the default one is skipped), in a set if `fp_rate` is `None`.
+    pub fn new(names: &[String], fp_rate: Option<f64>) -> Self {
+        AddressIndex { inner: Mutex::new(IndexInner::build(names, fp_rate)), skipped_lookups: AtomicU64::new(0), store_lookups: AtomicU64::new(0) }
+    }
+
+    fn lock(&self) -> MutexGuard<IndexInner> { self.inner.lock().unwrap_or_else(|e| e.into_inner()) }
+
+    /// Whether the contract with the column family `name` may be stored, `false` is certain. Counts the lookup.
+    pub fn lookup(&self, name: &str) -> bool {
+        let known = self.lock().contains(name);
+        let counter = if known { &self.store_lookups } else { &self.skipped_lookups };
+        counter.fetch_add(1, Ordering::SeqCst);
+        known
+    }
+
+    /// Adds a column family, `list` returns all of them in case the bloom filter has to grow.
+    pub fn insert<F: FnOnce() -> Result<Vec<String>, Error>>(&self, name: &str, list: F) {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        if inner.contains(name) {
+            return;
+        }
+        inner.addresses += 1;
+        let grow = match &mut inner.filter {
+            Filter::Exact(names) => {
+                names.insert(name.to_string());
+                None
+            }
+            Filter::Bloom(bloom) => {
+                bloom.insert(name);
+                if inner.addresses > bloom.capacity { Some(bloom.fp_rate) } else { None }
+            }
+        };
+        if let Some(fp_rate) = grow {
+            // The name is already in the filter, if listing fails it stays a little fuller than it should.
+            match list() {
+                Ok(names) => *inner = IndexInner::build(&names, Some(fp_rate)),
+                Err(e) => warn!("Failed growing the address index: {}", e),
+            }
+        }
+    }
+
+    /// Removes a column family, `list` returns the ones left since a bloom filter has to be rebuilt without it.
+    pub fn remove<F: FnOnce() -> Result<Vec<String>, Error>>(&self, name: &str, list: F) {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        let fp_rate = match &mut inner.filter {
+            Filter::Exact(names) => {
+                if names.remove(name) {
+                    inner.addresses -= 1;
+                }
+                return;
+            }
+            Filter::Bloom(bloom) => bloom.fp_rate,
+        };
+        // Until it's rebuilt the removed contract is only a false positive.
+        match list() {
+            Ok(names) => *inner = IndexInner::build(&names, Some(fp_rate)),
+            Err(e) => warn!("Failed rebuilding the address index: {}", e),
+        }
+    }
+
+    /// Replaces the index with one of `names`, the lookup counters are kept.
+    pub fn rebuild(&self, names: &[String], fp_rate: Option<f64>) { *self.lock() = IndexInner::build(names, fp_rate); }
+
+    /// Replaces the index with one of `names` of the same kind, returns how many of its entries disagreed with them:
+    /// the names it missed, the ones it held that aren't in `names` (for a bloom filter, as far as its count says),
+    /// and its count if it didn't match what it held.
+    pub fn repair(&self, names: &[String]) -> usize {
+        let mut inner = self.lock();
+        let rebuilt = IndexInner::build(names, inner.fp_rate());
+        let missed = names.iter().filter(|name| name.as_str() != DEFAULT_CF && !inner.contains(name)).count() as u64;
+        let held = match &inner.filter {
+            Filter::Exact(names) => names.len() as u64,
+            Filter::Bloom(_) => inner.addresses,
+        };
+        let stale = (held + missed).saturating_sub(rebuilt.addresses);
+        let miscounted = if inner.addresses != held { 1 } else { 0 };
+        *inner = rebuilt;
+        (missed + stale) as usize + miscounted
+    }
+
+    pub fn stats(&self) -> AddressIndexStats {
+        let inner = self.lock();
+        let (bloom_bits, bloom_hashes) = match &inner.filter {
+            Filter::Exact(_) => (None, None),
+            Filter::Bloom(bloom) => (Some(bloom.bits), Some(bloom.hashes)),
+        };
+        AddressIndexStats {
+            addresses: inner.addresses,
+            fp_rate: inner.fp_rate(),
+            bloom_bits,
+            bloom_hashes,
+            skipped_lookups: self.skipped_lookups.load(Ordering::SeqCst),
+            store_lookups: self.store_lookups.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl DB {
+    /// The address index of this DB, it can be handed to other threads.
+    pub fn address_index(&self) -> Arc<AddressIndex> { Arc::clone(&self.known) }
+
+    /// Rebuilds the address index as a bloom filter with this false positive rate, or as a set if it's `None`.
+    pub fn set_address_index_fp_rate(&mut self, fp_rate: Option<f64>) -> Result<(), Error> {
+        if let Some(rate) = fp_rate {
+            if !(rate > 0.0 && rate < 1.0) {
+                bail!("The false positive rate of the address index must be between 0 and 1, got {}", rate);
+            }
+        }
+        let names = rocks_db::list_cf(&self.options, &self.location)?;
+        self.known.rebuild(&names, fp_rate);
+        Ok(())
+    }
+
+    /// Whether the contract with the column family `name` may be stored, if not the lookup needn't go to RocksDB.
+    pub(crate) fn may_hold(&self, name: &str) -> bool { self.known.lookup(name) }
+
+    /// Indexes the column family `name`, after it was created or written to.
+    pub(crate) fn index_cf(&self, name: &str) {
+        let (options, location) = (&self.options, &self.location);
+        self.known.insert(name, || Ok(rocks_db::list_cf(options, location)?));
+    }
+
+    /// Drops the column family `name` from the index, after it was dropped from RocksDB.
+    pub(crate) fn unindex_cf(&self, name: &str) {
+        let (options, location) = (&self.options, &self.location);
+        self.known.remove(name, || Ok(rocks_db::list_cf(options, location)?));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, P2PCalls, Stype};
+    use enigma_types::ContractAddress;
+
+    fn names(count: usize) -> Vec<String> { (0..count).map(|i| format!("{:064x}", i)).collect() }
+
+    #[test]
+    fn test_negative_lookups_skip_the_store() {
+        for &fp_rate in &[None, Some(0.01)] {
+            let (mut db, _dir) = create_test_db();
+            db.set_address_index_fp_rate(fp_rate).unwrap();
+            let known: ContractAddress = [1u8; 32].into();
+            let unknown: ContractAddress = [2u8; 32].into();
+            db.create(&DeltaKey::new(known, Stype::ByteCode), &b"code"[..]).unwrap();
+            db.create(&DeltaKey::new(known, Stype::Delta(0)), &b"delta"[..]).unwrap();
+
+            let before = db.address_index().stats();
+            assert_eq!(db.find_contract(unknown).unwrap(), None);
+            assert!(db.get_contract(unknown).is_err());
+            assert!(db.find_contract_cached(unknown).unwrap().is_none());
+            assert!(db.get_tip::<DeltaKey>(&unknown).is_err());
+            assert!(db.get_delta(DeltaKey::new(unknown, Stype::Delta(0))).is_err());
+            assert!(db.get_contract_chunk(unknown, 0, 10).is_err());
+            let after = db.address_index().stats();
+            assert_eq!(after.store_lookups, before.store_lookups, "{:?}", fp_rate);
+            assert_eq!(after.skipped_lookups, before.skipped_lookups + 6, "{:?}", fp_rate);
+
+            assert_eq!(db.get_contract(known).unwrap(), b"code");
+            assert_eq!(db.get_tip::<DeltaKey>(&known).unwrap().1, b"delta");
+            assert_eq!(db.address_index().stats().store_lookups, after.store_lookups + 2);
+            assert_eq!(db.address_index().stats().fp_rate, fp_rate);
+        }
+    }
+
+    #[test]
+    fn test_index_follows_the_column_families() {
+        for &fp_rate in &[None, Some(0.01)] {
+            let (mut db, dir) = create_test_db();
+            db.set_address_index_fp_rate(fp_rate).unwrap();
+            let imported: ContractAddress = [3u8; 32].into();
+            let forced: ContractAddress = [4u8; 32].into();
+
+            let deltas = vec![(DeltaKey::new(imported, Stype::ByteCode), b"code".to_vec()), (DeltaKey::new(imported, Stype::Delta(0)), vec![1])];
+            assert!(db.insert_tuples(&deltas).into_iter().all(|res| res.is_ok()));
+            db.force_update(&DeltaKey::new(forced, Stype::ByteCode), &b"code"[..]).unwrap();
+            assert_eq!(db.get_contract(imported).unwrap(), b"code");
+            assert_eq!(db.get_tip::<DeltaKey>(&imported).unwrap().1, vec![1]);
+            assert_eq!(db.address_index().stats().addresses, 2);
+
+            db.delete_contract(&DeltaKey::new(imported, Stype::ByteCode)).unwrap();
+            assert_eq!(db.address_index().stats().addresses, 1);
+            let skipped = db.address_index().stats().skipped_lookups;
+            assert_eq!(db.find_contract(imported).unwrap(), None);
+            assert_eq!(db.address_index().stats().skipped_lookups, skipped + 1);
+
+            // A reopened DB indexes what it holds.
+            let location = db.location.clone();
+            drop(db);
+            let db = DB::new(&location, false).unwrap();
+            assert_eq!(db.address_index().stats().addresses, 1);
+            assert_eq!(db.get_contract(forced).unwrap(), b"code");
+            assert_eq!(db.find_contract(imported).unwrap(), None);
+            drop(dir);
+        }
+    }
+
+    #[test]
+    fn test_bloom_grows() {
+        let index = AddressIndex::new(&[], Some(0.01));
+        let all = names(5000);
+        for (i, name) in all.iter().enumerate() {
+            index.insert(name, || Ok(all[..=i].to_vec()));
+        }
+        let stats = index.stats();
+        assert_eq!(stats.addresses, 5000);
+        assert!(stats.bloom_bits.unwrap() > Bloom::new(0, 0.01).bits);
+        // Never a false negative.
+        assert!(all.iter().all(|name| index.lookup(name)));
+
+        let unknown: Vec<String> = (0..10_000).map(|i| format!("{:064x}", i + 1_000_000)).collect();
+        let false_positives = unknown.iter().filter(|name| index.lookup(name)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        // Removing rebuilds the filter from what's left.
+        index.remove(&all[0], || Ok(all[1..].to_vec()));
+        assert_eq!(index.stats().addresses, 4999);
+        assert!(all[1..].iter().all(|name| index.lookup(name)));
+    }
+
+    #[test]
+    fn test_exact_index() {
+        let index = AddressIndex::new(&[DEFAULT_CF.to_string(), "aa".to_string()], None);
+        assert_eq!(index.stats().addresses, 1);
+        assert!(index.lookup("aa") && !index.lookup(DEFAULT_CF) && !index.lookup("bb"));
+        index.insert("bb", || unreachable!());
+        index.insert("bb", || unreachable!());
+        index.remove("aa", || unreachable!());
+        index.remove("cc", || unreachable!());
+        let stats = index.stats();
+        assert_eq!((stats.addresses, stats.fp_rate, stats.bloom_bits), (1, None, None));
+        assert!(index.lookup("bb") && !index.lookup("aa"));
+        assert_eq!((stats.store_lookups, stats.skipped_lookups), (1, 2));
+    }
+
+    #[test]
+    fn test_repair() {
+        let all = names(3);
+        let index = AddressIndex::new(&all[..2], None);
+        index.insert("stale", || unreachable!());
+        // Misses the third, holds one that's gone.
+        assert_eq!(index.repair(&all), 2);
+        assert_eq!(index.stats().addresses, 3);
+        assert!(all.iter().all(|name| index.lookup(name)) && !index.lookup("stale"));
+        assert_eq!(index.repair(&all), 0);
+
+        let index = AddressIndex::new(&all[..1], Some(0.01));
+        assert_eq!(index.repair(&all), 2);
+        assert_eq!(index.stats().fp_rate, Some(0.01));
+        assert!(all.iter().all(|name| index.lookup(name)));
+    }
+}
@@ -0,0 +1,127 @@
+//! # Capacity.
+//! Caps on what the node hosts, so a few contracts with an unbounded state can't wedge a node with a small disk:
+//! the size of the DB directory, the bytes of the deltas of a single contract, and the number of contracts.
+//! A write that would go over one of them is refused with a `CapacityExceededErr`, and `GetHealth`/`GetContractStats`
+//! report the usage next to the caps so the p2p node can send the work elsewhere before that.
+//!
+//! The caps only gate new writes: the bytecode of a new contract, the deltas of `UpdateDeltas` and the delta a task
+//! produced when it's persisted. Nothing already stored is ever deleted to get back under a cap.
+
+use common_u::errors::CapacityExceededErr;
+use db::dal::DB;
+use db::iterator::P2PCalls;
+use db::key_encoding::{self, DELTA_PREFIX};
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+use std::collections::BTreeMap;
+
+/// The names of the caps, as they're reported in `CapacityExceededErr` and in `GetHealth`.
+pub const MAX_DB_BYTES: &str = "maxDbBytes";
+pub const MAX_CONTRACT_DELTA_BYTES: &str = "maxContractDeltaBytes";
+pub const MAX_CONTRACTS: &str = "maxContracts";
+
+/// The caps, `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CapacityLimits {
+    pub max_db_bytes: Option<u64>,
+    pub max_contract_delta_bytes: Option<u64>,
+    pub max_contracts: Option<u64>,
+}
+
+fn check(cap: &'static str, limit: Option<u64>, usage: u64, adding: u64, address: Option<ContractAddress>) -> Result<(), Error> {
+    match limit {
+        Some(limit) if usage.saturating_add(adding) > limit => {
+            Err(CapacityExceededErr { cap, usage, limit, address: address.map(|address| address.to_hex()) }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+impl DB {
+    pub fn set_capacity_limits(&mut self, limits: CapacityLimits) { self.capacity = limits; }
+
+    pub fn capacity_limits(&self) -> CapacityLimits { self.capacity }
+
+    /// The total length of the deltas of the contract, 0 if it has none.
+    pub fn contract_delta_bytes(&self, address: ContractAddress) -> Result<u64, Error> {
+        let cf = match self.database.cf_handle(&key_encoding::cf_name(&address)) {
+            Some(cf) => cf,
+            None => return Ok(0),
+        };
+        let mut bytes = 0u64;
+        for (_, value) in self.database.prefix_iterator_cf(cf, DELTA_PREFIX)? {
+            bytes += value.len() as u64;
+        }
+        Ok(bytes)
+    }
+
+    /// Refuses storing `bytecode_len` bytes of bytecode for `address` if it's a new contract the node can't host.
+    /// A contract that's already stored doesn't count against `max_contracts` again.
+    pub fn check_contract_capacity(&self, address: ContractAddress, bytecode_len: usize) -> Result<(), Error> {
+        check(MAX_DB_BYTES, self.capacity.max_db_bytes, self.disk_size(), bytecode_len as u64, None)?;
+        if self.capacity.max_contracts.is_some() && self.database.cf_handle(&key_encoding::cf_name(&address)).is_none() {
+            let contracts = self.get_all_addresses()?.len() as u64;
+            check(MAX_CONTRACTS, self.capacity.max_contracts, contracts, 1, Some(address))?;
+        }
+        Ok(())
+    }
+
+    /// Refuses storing the deltas, given as their contract and their length, if together they'd take the DB
+    /// or one of their contracts over its cap.
+    pub fn check_delta_capacity(&self, deltas: &[(ContractAddress, usize)]) -> Result<(), Error> {
+        let total: u64 = deltas.iter().map(|&(_, len)| len as u64).sum();
+        check(MAX_DB_BYTES, self.capacity.max_db_bytes, self.disk_size(), total, None)?;
+        if self.capacity.max_contract_delta_bytes.is_none() {
+            return Ok(());
+        }
+        let mut per_contract: BTreeMap<ContractAddress, u64> = BTreeMap::new();
+        for &(address, len) in deltas {
+            *per_contract.entry(address).or_insert(0) += len as u64;
+        }
+        for (address, adding) in per_contract {
+            let usage = self.contract_delta_bytes(address)?;
+            check(MAX_CONTRACT_DELTA_BYTES, self.capacity.max_contract_delta_bytes, usage, adding, Some(address))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, Stype};
+
+    fn cap_of(e: Error) -> (&'static str, u64, u64) {
+        let e = e.downcast::<CapacityExceededErr>().unwrap();
+        (e.cap, e.usage, e.limit)
+    }
+
+    #[test]
+    fn test_capacity_limits() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        db.create(&DeltaKey::new(address, Stype::Delta(0)), &[0u8; 10][..]).unwrap();
+        db.create(&DeltaKey::new(address, Stype::Delta(1)), &[0u8; 5][..]).unwrap();
+        assert_eq!(db.contract_delta_bytes(address).unwrap(), 15);
+        assert_eq!(db.contract_delta_bytes([2u8; 32].into()).unwrap(), 0);
+
+        // Unlimited by default
+        db.check_contract_capacity([2u8; 32].into(), 1 << 30).unwrap();
+        db.check_delta_capacity(&[(address, 1 << 30)]).unwrap();
+
+        db.set_capacity_limits(CapacityLimits { max_contracts: Some(1), max_contract_delta_bytes: Some(20), max_db_bytes: None });
+        // The hosted contract can be stored again, a new one can't.
+        db.check_contract_capacity(address, 4).unwrap();
+        assert_eq!(cap_of(db.check_contract_capacity([2u8; 32].into(), 4).unwrap_err()), (MAX_CONTRACTS, 1, 1));
+        db.check_delta_capacity(&[(address, 5), ([2u8; 32].into(), 20)]).unwrap();
+        assert_eq!(cap_of(db.check_delta_capacity(&[(address, 3), (address, 3)]).unwrap_err()), (MAX_CONTRACT_DELTA_BYTES, 15, 20));
+
+        let disk_size = db.disk_size();
+        db.set_capacity_limits(CapacityLimits { max_db_bytes: Some(disk_size), ..CapacityLimits::default() });
+        assert_eq!(cap_of(db.check_delta_capacity(&[(address, 1)]).unwrap_err()), (MAX_DB_BYTES, disk_size, disk_size));
+        // Nothing stored is removed by a cap.
+        assert_eq!(db.contract_delta_bytes(address).unwrap(), 15);
+    }
+}
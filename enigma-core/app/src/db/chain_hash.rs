@@ -0,0 +1,356 @@
+//! # Delta chain hash.
+//! The rolling hash `h_n = keccak256(h_{n-1} ‖ keccak256(delta_n))` over the deltas of a contract in key order,
+//! starting from a zeroed hash. It's stored in the contract's column family and kept up to date by the writes,
+//! so reading it doesn't require scanning the deltas.
+//!
+//! The hash is written in the same batch as the deltas, so a crash can't leave it behind them. Appending after the tip
+//! extends the stored hash, a write behind the tip recomputes it, and so does a removal. Removals come in ranges, so
+//! `delete_deltas` removes a whole range in one batch and recomputes the hash once for it. Only a write that failed
+//! updating the hash drops it, reads never write: until the next delta is written it's recomputed on every read.
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::{DB, SYNC};
+use db::key_encoding::{self, delta_index, encode_index_key, CHAIN_HASH_KEY, DELTA_PREFIX};
+use db::mirror::MirrorOp;
+use db::primitives::Stype;
+use enigma_crypto::hash::Keccak256;
+use enigma_types::{ContractAddress, Hash256};
+use failure::Error;
+use rocksdb::{WriteBatch, WriteOptions};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+const CHAIN_HASH_SIZE: usize = 32 + 4;
+
+/// The chain hash of a contract, and the index of the last delta it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainHash {
+    pub hash: Hash256,
+    pub tip: u32,
+}
+
+impl ChainHash {
+    pub(crate) fn append(prev: Option<ChainHash>, tip: u32, delta: &[u8]) -> ChainHash {
+        Self::append_hash(prev, tip, &delta.keccak256())
+    }
+
+    fn append_hash(prev: Option<ChainHash>, tip: u32, delta_hash: &Hash256) -> ChainHash {
+        let mut data = [0u8; 64];
+        if let Some(prev) = prev {
+            data[..32].copy_from_slice(&prev.hash[..]);
+        }
+        data[32..].copy_from_slice(&delta_hash[..]);
+        ChainHash { hash: data[..].keccak256(), tip }
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; CHAIN_HASH_SIZE] {
+        let mut bytes = [0u8; CHAIN_HASH_SIZE];
+        bytes[..32].copy_from_slice(&self.hash[..]);
+        bytes[32..].copy_from_slice(&self.tip.to_be_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<ChainHash> {
+        if bytes.len() != CHAIN_HASH_SIZE {
+            return None;
+        }
+        let mut hash = Hash256::default();
+        hash.copy_from_slice(&bytes[..32]);
+        let mut tip = [0u8; 4];
+        tip.copy_from_slice(&bytes[32..]);
+        Some(ChainHash { hash, tip: u32::from_be_bytes(tip) })
+    }
+}
+
+impl DB {
+    /// Returns the chain hash of the contract, `None` if it has no deltas.
+    pub fn get_chain_hash(&self, address: &ContractAddress) -> Result<Option<ChainHash>, Error> {
+        let cf_name = key_encoding::cf_name(address);
+        match self.read_chain_hash(&cf_name)? {
+            Some(chain) => Ok(Some(chain)),
+            None => self.compute_chain_hash_cf(&cf_name, &BTreeMap::new()),
+        }
+    }
+
+    /// Computes the chain hash of the contract by scanning all of its deltas, ignoring the stored one.
+    pub fn compute_chain_hash(&self, address: &ContractAddress) -> Result<Option<ChainHash>, Error> {
+        self.compute_chain_hash_cf(&key_encoding::cf_name(address), &BTreeMap::new())
+    }
+
+    /// Writes `value` under `index_key` in the column family of a contract, with the chain hash it results in.
+    pub(crate) fn put_with_chain_hash(&self, cf_name: &str, index_key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "update_chain_hash"))?;
+        let mut batch = WriteBatch::default();
+        batch.put_cf(cf, index_key, value)?;
+        if let Some(index) = delta_index(index_key) {
+            self.chain_hash_batch(&mut batch, cf_name, &[(index, value)])?;
+        }
+        self.write_batch(batch)
+    }
+
+    /// Removes `index_key` from the column family of a contract, with the chain hash it leaves if it's a delta.
+    pub(crate) fn delete_with_chain_hash(&self, cf_name: &str, index_key: &[u8]) -> Result<(), Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "update_chain_hash"))?;
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(cf, index_key)?;
+        if let Some(index) = delta_index(index_key) {
+            self.removal_chain_hash_batch(&mut batch, cf_name, &[index].iter().cloned().collect())?;
+        }
+        self.write_batch(batch)
+    }
+
+    /// Removes the deltas `range` of the contract in one batch, with the chain hash recomputed once for all of them.
+    /// Returns the ones in `range` that weren't stored.
+    pub fn delete_deltas(&mut self, address: &ContractAddress, range: Range<u32>) -> Result<Vec<u32>, Error> {
+        let cf_name = key_encoding::cf_name(address);
+        let cf = self.database.cf_handle(&cf_name).ok_or_else(|| Self::cf_missing(&cf_name, "delete_deltas"))?;
+        let mut batch = WriteBatch::default();
+        let (mut removed, mut missing) = (BTreeSet::new(), Vec::new());
+        for index in range {
+            let index_key = encode_index_key(Stype::Delta(index));
+            if self.database.get_cf(cf, &index_key)?.is_some() {
+                batch.delete_cf(cf, &index_key)?;
+                removed.insert(index);
+            } else {
+                missing.push(index);
+            }
+        }
+        if removed.is_empty() {
+            return Ok(missing);
+        }
+        self.removal_chain_hash_batch(&mut batch, &cf_name, &removed)?;
+        self.write_batch(batch)?;
+        for index in removed {
+            let index_key = encode_index_key(Stype::Delta(index));
+            self.delta_removed(&cf_name, &index_key);
+            self.mirror_write(|| MirrorOp::Delete { cf: cf_name.clone(), key: index_key })?;
+        }
+        Ok(missing)
+    }
+
+    // Adds the chain hash of the contract once the deltas `removed` are removed in `batch`, or removes it if none are left.
+    fn removal_chain_hash_batch(&self, batch: &mut WriteBatch, cf_name: &str, removed: &BTreeSet<u32>) -> Result<(), Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "update_chain_hash"))?;
+        let left = self.database.prefix_iterator_cf(cf, DELTA_PREFIX)?
+            .filter(|(key, _)| delta_index(key).map_or(true, |index| !removed.contains(&index)));
+        match chain_of(left)? {
+            Some(chain) => batch.put_cf(cf, CHAIN_HASH_KEY, &chain.to_bytes())?,
+            None => batch.delete_cf(cf, CHAIN_HASH_KEY)?,
+        }
+        Ok(())
+    }
+
+    /// Adds the chain hash of the contract after `deltas` (their indexes and values) are written to `batch`.
+    pub(crate) fn chain_hash_batch(&self, batch: &mut WriteBatch, cf_name: &str, deltas: &[(u32, &[u8])]) -> Result<(), Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "update_chain_hash"))?;
+        // Like in the batch, a later write of the same delta replaces an earlier one.
+        let pending: BTreeMap<u32, Hash256> = deltas.iter().map(|(index, delta)| (*index, delta.keccak256())).collect();
+        let first = match pending.keys().next() {
+            Some(first) => *first,
+            None => return Ok(()),
+        };
+        let chain = match self.read_chain_hash(cf_name)? {
+            Some(chain) if first > chain.tip => pending.iter().fold(Some(chain), |prev, (index, hash)| Some(ChainHash::append_hash(prev, *index, hash))),
+            Some(chain) => {
+                info!("Recomputing the chain hash of {}, delta {} was written behind the tip {}", cf_name, first, chain.tip);
+                self.compute_chain_hash_cf(cf_name, &pending)?
+            }
+            // Either the contract's first delta, or the hash was dropped by a removal.
+            None => self.compute_chain_hash_cf(cf_name, &pending)?,
+        };
+        if let Some(chain) = chain {
+            batch.put_cf(cf, CHAIN_HASH_KEY, &chain.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Removes the chain hash of the contract in `batch`, the reads recompute it until the next delta is written.
+    /// Only for a write that failed computing it.
+    pub(crate) fn drop_chain_hash(&self, batch: &mut WriteBatch, cf_name: &str) -> Result<(), Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "update_chain_hash"))?;
+        batch.delete_cf(cf, CHAIN_HASH_KEY)?;
+        Ok(())
+    }
+
+    fn cf_missing(cf_name: &str, command: &str) -> DBErr {
+        DBErr { command: command.to_string(), kind: DBErrKind::MissingKey(cf_name.to_string()) }
+    }
+
+    fn read_chain_hash(&self, cf_name: &str) -> Result<Option<ChainHash>, Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "get_chain_hash"))?;
+        match self.database.get_cf(cf, CHAIN_HASH_KEY)? {
+            Some(value) => {
+                let chain = ChainHash::from_bytes(&value)
+                    .ok_or(DBErr { command: "get_chain_hash".to_string(), kind: DBErrKind::FetchError })?;
+                Ok(Some(chain))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Error> {
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.write_opt(batch, &write_options)?;
+        Ok(())
+    }
+
+    /// The chain hash of the stored deltas with the hashes of `pending` in place of (or next to) them.
+    fn compute_chain_hash_cf(&self, cf_name: &str, pending: &BTreeMap<u32, Hash256>) -> Result<Option<ChainHash>, Error> {
+        let cf = self.database.cf_handle(cf_name).ok_or_else(|| Self::cf_missing(cf_name, "compute_chain_hash"))?;
+        if pending.is_empty() {
+            return chain_of(self.database.prefix_iterator_cf(cf, DELTA_PREFIX)?);
+        }
+        // Only the hashes are kept, the deltas of a contract can be too big to hold at once.
+        let mut hashes = BTreeMap::new();
+        for (key, value) in self.database.prefix_iterator_cf(cf, DELTA_PREFIX)? {
+            let index = delta_index(&key).ok_or(DBErr { command: "compute_chain_hash".to_string(), kind: DBErrKind::FetchError })?;
+            hashes.insert(index, value.keccak256());
+        }
+        hashes.extend(pending.iter().map(|(index, hash)| (*index, *hash)));
+        Ok(hashes.iter().fold(None, |prev, (index, hash)| Some(ChainHash::append_hash(prev, *index, hash))))
+    }
+}
+
+/// The chain hash of `deltas`, the delta keys of a contract and their values in key order.
+pub(crate) fn chain_of<K, V, I>(deltas: I) -> Result<Option<ChainHash>, Error>
+where K: AsRef<[u8]>, V: AsRef<[u8]>, I: IntoIterator<Item = (K, V)> {
+    let mut chain = None;
+    for (key, value) in deltas {
+        let index = delta_index(key.as_ref()).ok_or(DBErr { command: "compute_chain_hash".to_string(), kind: DBErrKind::FetchError })?;
+        chain = Some(ChainHash::append(chain, index, value.as_ref()));
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+    use self::rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, P2PCalls, Stype};
+
+    #[test]
+    fn test_chain_hash_definition() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [5u8; 32].into();
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        assert_eq!(db.get_chain_hash(&address).unwrap(), None);
+
+        db.create(&DeltaKey::new(address, Stype::Delta(0)), &b"first"[..]).unwrap();
+        db.force_update(&DeltaKey::new(address, Stype::Delta(1)), &b"second"[..]).unwrap();
+
+        let mut h0 = [0u8; 64];
+        h0[32..].copy_from_slice(&b"first"[..].keccak256()[..]);
+        let h0: Hash256 = h0[..].keccak256();
+        let mut h1 = [0u8; 64];
+        h1[..32].copy_from_slice(&h0[..]);
+        h1[32..].copy_from_slice(&b"second"[..].keccak256()[..]);
+        let expected = ChainHash { hash: h1[..].keccak256(), tip: 1 };
+        assert_eq!(db.get_chain_hash(&address).unwrap(), Some(expected));
+        // The bytecode and the state aren't part of the chain.
+        db.force_update(&DeltaKey::new(address, Stype::State), &b"state"[..]).unwrap();
+        assert_eq!(db.get_chain_hash(&address).unwrap(), Some(expected));
+    }
+
+    #[test]
+    fn test_chain_hash_written_with_the_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [6u8; 32].into();
+        let cf_name = key_encoding::cf_name(&address);
+        let stored = |db: &DB| db.read_chain_hash(&cf_name).unwrap();
+        let batch: Vec<_> = (0..3).map(|n| (DeltaKey::new(address, Stype::Delta(n)), vec![n as u8])).collect();
+        for res in db.insert_tuples(&batch) {
+            res.unwrap();
+        }
+        assert_eq!(stored(&db), db.compute_chain_hash(&address).unwrap());
+        assert_eq!(stored(&db).unwrap().tip, 2);
+
+        // A removal recomputes it.
+        db.delete(&DeltaKey::new(address, Stype::Delta(2))).unwrap();
+        assert_eq!(stored(&db), db.compute_chain_hash(&address).unwrap());
+        assert_eq!(stored(&db).unwrap().tip, 1);
+
+        // Rewriting behind the tip and appending in one batch.
+        let batch = vec![(DeltaKey::new(address, Stype::Delta(0)), vec![9u8]), (DeltaKey::new(address, Stype::Delta(2)), vec![8u8])];
+        for res in db.insert_tuples(&batch) {
+            res.unwrap();
+        }
+        assert_eq!(stored(&db), db.compute_chain_hash(&address).unwrap());
+        assert_eq!(stored(&db).unwrap().tip, 2);
+
+        // And so does a range, once for all of it, it's only gone with the last delta.
+        assert_eq!(db.delete_deltas(&address, 1..5).unwrap(), vec![3, 4]);
+        assert_eq!(stored(&db), db.compute_chain_hash(&address).unwrap());
+        assert_eq!(stored(&db).unwrap().tip, 0);
+        assert_eq!(db.delete_deltas(&address, 0..1).unwrap(), vec![]);
+        assert_eq!(stored(&db), None);
+        assert_eq!(db.get_chain_hash(&address).unwrap(), None);
+    }
+
+    // Random valid sequences of appends (one by one or in batches), rewrites and prunes of the tip,
+    // the stored hash must always match a full recomputation.
+    #[test]
+    fn test_chain_hash_matches_recomputed() {
+        let mut rng = StdRng::seed_from_u64(0x1418);
+        let (mut db, _dir) = create_test_db();
+        let addresses: Vec<ContractAddress> = (1..4u8).map(|i| [i; 32].into()).collect();
+        let mut tips: Vec<Option<u32>> = vec![None; addresses.len()];
+
+        for _ in 0..400 {
+            let i = rng.gen_range(0, addresses.len());
+            let address = addresses[i];
+            let next = tips[i].map_or(0, |tip| tip + 1);
+            let delta: Vec<u8> = (0..rng.gen_range(1, 64)).map(|_| rng.gen()).collect();
+            match rng.gen_range(0, 5) {
+                0 => db.create(&DeltaKey::new(address, Stype::Delta(next)), &delta[..]).unwrap(),
+                1 => db.force_update(&DeltaKey::new(address, Stype::Delta(next)), &delta[..]).unwrap(),
+                2 => {
+                    let batch: Vec<_> = (next..next + rng.gen_range(1, 4)).map(|n| (DeltaKey::new(address, Stype::Delta(n)), delta.clone())).collect();
+                    for res in db.insert_tuples(&batch) {
+                        res.unwrap();
+                    }
+                    tips[i] = Some(next + batch.len() as u32 - 1);
+                    continue;
+                }
+                // Rewriting an existing delta.
+                3 if tips[i].is_some() => {
+                    let n = rng.gen_range(0, next);
+                    db.force_update(&DeltaKey::new(address, Stype::Delta(n)), &delta[..]).unwrap();
+                    continue;
+                }
+                // Pruning the last few deltas, one by one or as a range.
+                _ if tips[i].is_some() => {
+                    let from = next.saturating_sub(rng.gen_range(1, 4));
+                    if rng.gen() {
+                        assert!(db.delete_deltas(&address, from..next).unwrap().is_empty());
+                    } else {
+                        for n in from..next {
+                            db.delete(&DeltaKey::new(address, Stype::Delta(n))).unwrap();
+                        }
+                    }
+                    tips[i] = from.checked_sub(1);
+                    // The hash is stored again right away, unless no delta is left.
+                    let stored = db.read_chain_hash(&key_encoding::cf_name(&address)).unwrap();
+                    assert_eq!(stored, db.compute_chain_hash(&address).unwrap());
+                    assert_eq!(stored.map(|chain| chain.tip), tips[i]);
+                    continue;
+                }
+                _ => continue,
+            }
+            tips[i] = Some(next);
+            let stored = db.read_chain_hash(&key_encoding::cf_name(&address)).unwrap();
+            assert_eq!(stored, db.compute_chain_hash(&address).unwrap());
+            assert_eq!(stored.map(|chain| chain.tip), tips[i]);
+        }
+        for (address, tip) in addresses.iter().zip(tips) {
+            let stored = db.read_chain_hash(&key_encoding::cf_name(address)).unwrap();
+            assert_eq!(stored, db.compute_chain_hash(address).unwrap());
+            assert_eq!(stored.map(|chain| chain.tip), tip);
+            if let Some(tip) = tip {
+                let (tip_key, _): (DeltaKey, Vec<u8>) = db.get_tip(address).unwrap();
+                assert_eq!(tip_key.key_type, Stype::Delta(tip));
+            }
+        }
+    }
+}
@@ -2,13 +2,26 @@ use failure::Error;
 use rocksdb::DB as rocks_db;
 use rocksdb::{Options, SliceTransform, WriteOptions, ColumnFamilyDescriptor};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use common_u::errors::{DBErr, DBErrKind};
+use common_u::network::Network;
+use db::address_index::AddressIndex;
+use db::capacity::CapacityLimits;
+use db::gaps::GapIndex;
+use db::hosting::HostingPolicy;
+use db::hot_set::ContractCache;
+use db::mirror::{Mirror, MirrorOp};
+use db::orphans::OrphanPolicy;
 use db::primitives::SplitKey;
 
 // These are global variables for Reade/Write/Create Options
-const SYNC: bool = true;
+pub(crate) const SYNC: bool = true;
 const PREFIX_SIZE: usize = 1;
+// Reserved keys in the default column family (the contracts each have their own column family)
+const NETWORK_KEY: &[u8] = b"network";
+pub(crate) const HOT_SET_KEY: &[u8] = b"hot_set";
+pub(crate) const HOSTED_KEY: &[u8] = b"hosted";
 
 pub struct DB {
     pub location: PathBuf,
@@ -18,6 +31,20 @@ pub struct DB {
     pub options: Options,
     // keeps track if the state needs to be rebuilt
     state_updated: bool,
+    // bytecode of recently executed contracts, see `db::hot_set`
+    pub(crate) contracts: Arc<ContractCache>,
+    // the standby the writes are copied to, see `db::mirror`
+    pub(crate) mirror: Option<Arc<Mirror>>,
+    // whether deltas are accepted before the bytecode of their contract, see `db::orphans`
+    pub(crate) orphan_policy: OrphanPolicy,
+    // the caps on new writes, see `db::capacity`
+    pub(crate) capacity: CapacityLimits,
+    // which contracts are stored, see `db::hosting`
+    pub(crate) hosting: HostingPolicy,
+    // the column families of the contracts, see `db::address_index`
+    pub(crate) known: Arc<AddressIndex>,
+    // the usable tips of the contracts, see `db::gaps`
+    pub(crate) gaps: GapIndex,
 }
 
 impl DB {
@@ -52,6 +79,7 @@ impl DB {
             Ok(list) => list,
             Err(_) => Vec::new(),
         };
+        let known = Arc::new(AddressIndex::new(&cf_list, None));
         // converts the Strings to descriptors (adds to each cf an options object)
         let cf_descriptors = cf_list.into_iter().map(|name| {
             let prefix_extractor = SliceTransform::create_fixed_prefix(PREFIX_SIZE);
@@ -63,10 +91,66 @@ impl DB {
         let location = location.as_ref().to_path_buf();
         // the state_updated is initialized to true since it won't be necessary to build
         // the state when the DB is empty.
-        let db_par = DB { location, database, options, state_updated: true};
+        let db_par = DB { location, database, options, state_updated: true, contracts: Arc::default(), mirror: None,
+                          orphan_policy: OrphanPolicy::default(), capacity: CapacityLimits::default(), hosting: HostingPolicy::default(), known,
+                          gaps: GapIndex::default() };
         Ok(db_par)
     }
 
+    /// Opens the DB like `DB::new`, and makes sure it belongs to `network`.
+    /// A new DB records the network it was created for, an existing one must match it.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate tempfile;
+    /// # extern crate enigma_core_app;
+    /// # use enigma_core_app::db::dal::DB;
+    /// # use enigma_core_app::common_u::network::Network;
+    ///
+    /// # let tempdir = tempfile::tempdir().unwrap();
+    /// let db = DB::new_for_network(tempdir.path(), Network::Kovan, true).unwrap();
+    /// assert_eq!(db.get_network().unwrap(), Some(Network::Kovan));
+    /// ```
+    pub fn new_for_network<P: AsRef<Path>>(location: P, network: Network, create_if_missing: bool) -> Result<DB, Error> {
+        let db = DB::new(location, create_if_missing)?;
+        match db.get_network()? {
+            Some(found) if found != network => {
+                return Err(DBErr { command: "open".to_string(), kind: DBErrKind::NetworkMismatch(found.to_string(), network.to_string()) }.into());
+            }
+            Some(_) => (),
+            None => {
+                let mut write_options = WriteOptions::default();
+                write_options.set_sync(SYNC);
+                db.database.put_opt(NETWORK_KEY, &network.chain_id().to_be_bytes(), &write_options)?;
+            }
+        }
+        Ok(db)
+    }
+
+    /// Returns the network recorded in the DB, if there is one.
+    pub fn get_network(&self) -> Result<Option<Network>, Error> {
+        match self.database.get(NETWORK_KEY)? {
+            Some(value) => {
+                if value.len() != 8 {
+                    return Err(DBErr { command: "get_network".to_string(), kind: DBErrKind::FetchError }.into());
+                }
+                let mut chain_id = [0u8; 8];
+                chain_id.copy_from_slice(&value);
+                Ok(Some(Network::from_chain_id(u64::from_be_bytes(chain_id))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Calls `f` with the value of `key` pinned in RocksDB's block cache, so a large value can be read in part
+    /// (or hashed) without copying all of it. `None` if the key doesn't exist.
+    pub fn read_pinned<K: SplitKey, T, F: FnMut(&[u8]) -> T>(&self, key: &K, mut f: F) -> Result<Option<T>, Error> {
+        key.as_split(|hash, index_key| {
+            let cf_key = self.database.cf_handle(&hash).ok_or(DBErr { command: "read_pinned".to_string(), kind: DBErrKind::MissingKey(hash.to_string()) })?;
+            Ok(self.database.get_pinned_cf(cf_key, &index_key)?.map(|value| f(&value)))
+        })
+    }
+
     /// updates the state_updated field according to the status of the state.
     /// every time the state is built (=true) and
     /// on the other hand when new deltas enter the DB (=false).
@@ -78,6 +162,22 @@ impl DB {
     pub fn get_state_status(& mut self) -> bool {
         self.state_updated
     }
+
+    /// Returns the total size in bytes of the files in the DB directory.
+    pub fn disk_size(&self) -> u64 { Self::disk_size_at(&self.location) }
+
+    /// Like `disk_size`, for the DB at `location` without going through it, so it can be called from any thread.
+    pub fn disk_size_at(location: &Path) -> u64 {
+        match std::fs::read_dir(location) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|meta| meta.is_file())
+                .map(|meta| meta.len())
+                .sum(),
+            Err(_) => 0,
+        }
+    }
 }
 
 pub trait CRUDInterface<E, K, T, V> {
@@ -204,15 +304,16 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
                 Some(cf) => cf,
                 None => self.database.create_cf(hash, &self.options)?,
             };
+            self.index_cf(hash);
 
             // verifies that the key inside the CF doesn't already exist
             match self.database.get_cf(cf_key, &index_key)? {
                 Some(_) => Err(DBErr { command: "create".to_string(), kind: DBErrKind::KeyExists(hash.to_string()) }.into()),
                 None => {
-                    let mut write_options = WriteOptions::default();
-                    write_options.set_sync(SYNC);
-                    self.database.put_cf_opt(cf_key, &index_key, &value, &write_options)?;
-                    Ok(())
+                    self.put_with_chain_hash(hash, index_key, value)?;
+                    self.delta_written(hash, index_key)?;
+                    self.bytecode_written(hash, index_key);
+                    self.mirror_write(|| MirrorOp::Put { cf: hash.to_string(), key: index_key.to_vec(), value: value.to_vec() })
                 }
             }
         })
@@ -238,10 +339,10 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
                 return Err(DBErr { command: "update".to_string(), kind: DBErrKind::MissingKey(hash.to_string()) }.into());
             }
 
-            let mut write_options = WriteOptions::default();
-            write_options.set_sync(SYNC);
-            self.database.put_cf_opt(cf_key, &index_key, value, &write_options)?;
-            Ok(())
+            self.put_with_chain_hash(hash, index_key, value)?;
+            self.delta_written(hash, index_key)?;
+            self.bytecode_written(hash, index_key);
+            self.mirror_write(|| MirrorOp::Put { cf: hash.to_string(), key: index_key.to_vec(), value: value.to_vec() })
         })
     }
 
@@ -254,8 +355,10 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
             if self.database.get_cf(cf_key, &index_key)?.is_none() {
                 return Err(DBErr { command: "delete".to_string(), kind: DBErrKind::MissingKey(hash.to_string()) }.into());
             }
-            self.database.delete_cf(cf_key, &index_key)?;
-            Ok(())
+            self.delete_with_chain_hash(hash, index_key)?;
+            self.delta_removed(hash, index_key);
+            self.bytecode_written(hash, index_key);
+            self.mirror_write(|| MirrorOp::Delete { cf: hash.to_string(), key: index_key.to_vec() })
         })
     }
 
@@ -264,7 +367,10 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
         key.as_split(|hash, _| {
             trace!("DB: Delete Contract: contract_address: {}", hash);
             self.database.drop_cf(&hash).
-                map_err(|_| DBErr { command: "delete_contract".to_string(), kind: DBErrKind::MissingKey(hash.to_string()) }.into())
+                map_err(|_| DBErr { command: "delete_contract".to_string(), kind: DBErrKind::MissingKey(hash.to_string()) })?;
+            self.unindex_cf(hash);
+            self.forget_gaps(hash);
+            self.mirror_write(|| MirrorOp::DeleteContract { cf: hash.to_string() })
         })
     }
 
@@ -273,14 +379,14 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
         key.as_split(|hash, index_key| {
             trace!("DB: Force Update: contract_address: {}, key: {:?}, value: {:?}", hash, index_key, value);
             // if the address does not exist, in force update, we would like to write it anyways.
-            let cf_key = match self.database.cf_handle(hash) {
-                Some(cf) => cf,
-                None => self.database.create_cf(hash, &self.options)?,
-            };
-            let mut write_options = WriteOptions::default();
-            write_options.set_sync(SYNC);
-            self.database.put_cf_opt(cf_key, &index_key, value, &write_options)?;
-            Ok(())
+            if self.database.cf_handle(hash).is_none() {
+                self.database.create_cf(hash, &self.options)?;
+            }
+            self.index_cf(hash);
+            self.put_with_chain_hash(hash, index_key, value)?;
+            self.delta_written(hash, index_key)?;
+            self.bytecode_written(hash, index_key);
+            self.mirror_write(|| MirrorOp::Put { cf: hash.to_string(), key: index_key.to_vec(), value: value.to_vec() })
         })
     }
 }
@@ -288,7 +394,10 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
 #[cfg(test)]
 mod test {
 
-    use crate::db::{tests::create_test_db, dal::CRUDInterface, primitives::{Array32u8, DeltaKey, Stype}};
+    extern crate tempfile;
+    use crate::db::{tests::create_test_db, dal::{CRUDInterface, DB}, primitives::{Array32u8, DeltaKey, Stype}, P2PCalls};
+    use crate::common_u::errors::{DBErr, DBErrKind};
+    use crate::common_u::network::Network;
     use hex::ToHex;
     use enigma_types::ContractAddress;
 
@@ -297,6 +406,38 @@ mod test {
         let (_db, _dir) = create_test_db();
     }
 
+    #[test]
+    fn test_network_mismatch() {
+        let tempdir = tempfile::tempdir().unwrap();
+        {
+            let db = DB::new_for_network(tempdir.path(), Network::Mainnet, true).unwrap();
+            assert_eq!(db.get_network().unwrap(), Some(Network::Mainnet));
+        }
+        // Reopening for the same network is fine.
+        DB::new_for_network(tempdir.path(), Network::Mainnet, false).unwrap();
+
+        let err = DB::new_for_network(tempdir.path(), Network::Kovan, false).err().unwrap();
+        match err.downcast_ref::<DBErr>() {
+            Some(DBErr { kind: DBErrKind::NetworkMismatch(found, expected), .. }) => {
+                assert_eq!(found, "mainnet");
+                assert_eq!(expected, "kovan");
+            }
+            other => panic!("Expected a NetworkMismatch error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_network_not_an_address() {
+        let (mut db, _dir) = create_test_db();
+        assert_eq!(db.get_network().unwrap(), None);
+        db.create(&Array32u8([7u8; 32]), &b"Enigma"[..]).unwrap();
+        let path = db.location.clone();
+        drop(db);
+        let db = DB::new_for_network(&path, Network::Custom(1337), false).unwrap();
+        // The reserved key isn't a contract.
+        assert_eq!(db.get_all_addresses().unwrap().len(), 1);
+    }
+
     #[test]
     fn test_create_read() {
         let (mut db, _dir) = create_test_db();
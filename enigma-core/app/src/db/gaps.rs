@@ -0,0 +1,205 @@
+//! # Delta gaps.
+//! A partial sync can leave holes in the deltas of a contract, and then the largest key stored isn't one the state
+//! can be built up to. A contract has two tips:
+//! * the raw tip, the largest delta key stored, what `get_tip` returns.
+//! * the usable tip, the largest key such that the deltas `0..=key` are all stored, `None` while delta 0 is missing.
+//!
+//! The usable tips are kept in memory, a contract's is computed on its first read by scanning its deltas up to the
+//! first gap. The writes keep it up to date without a full scan: a delta right after the usable tip extends it over
+//! the deltas that follow (the ones that were behind the gap), a delta further ahead doesn't change it, and removing
+//! a delta at or behind it moves it back to just before the removed one.
+
+use db::dal::DB;
+use db::iterator::P2PCalls;
+use db::key_encoding::{self, delta_index};
+use db::primitives::{DeltaKey, Stype};
+use common_u::errors::{DBErr, DBErrKind};
+use common_u::panics::LockRecover;
+use enigma_types::ContractAddress;
+use failure::Error;
+use rocksdb::{Direction, IteratorMode};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The two tips of a contract's deltas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaTips {
+    /// The largest delta key stored.
+    pub raw: u32,
+    /// The largest key such that all the deltas up to it are stored, `None` if delta 0 is missing.
+    pub usable: Option<u32>,
+}
+
+impl DeltaTips {
+    pub fn has_gaps(&self) -> bool { self.usable != Some(self.raw) }
+}
+
+/// The usable tips of the contracts read since the DB was opened, by column family.
+#[derive(Debug, Default)]
+pub struct GapIndex {
+    pub(crate) usable: Mutex<HashMap<String, Option<u32>>>,
+}
+
+impl DB {
+    /// The raw and usable tips of the contract, `None` if it has no deltas.
+    pub fn get_delta_tips(&self, address: &ContractAddress) -> Result<Option<DeltaTips>, Error> {
+        // The last delta key, the chain hash may be behind it or missing.
+        let raw = match self.get_tip::<DeltaKey>(address) {
+            Ok((key, _)) => key.key_type.unwrap_delta(),
+            Err(e) => match e.downcast_ref::<DBErr>() {
+                Some(DBErr { kind: DBErrKind::MissingKey(_), .. }) => return Ok(None),
+                _ => return Err(e),
+            },
+        };
+        let usable = self.get_usable_tip(address)?;
+        Ok(Some(DeltaTips { raw, usable }))
+    }
+
+    /// The largest key such that the deltas `0..=key` of the contract are all stored, `None` if delta 0 isn't.
+    pub fn get_usable_tip(&self, address: &ContractAddress) -> Result<Option<u32>, Error> {
+        let cf_name = key_encoding::cf_name(address);
+        let mut usable = self.gaps.usable.lock_recover("Gap index");
+        if let Some(tip) = usable.get(&cf_name) {
+            return Ok(*tip);
+        }
+        let tip = self.contiguous_from(&cf_name, 0)?;
+        usable.insert(cf_name, tip);
+        Ok(tip)
+    }
+
+    /// Called after the delta under `index_key` was written in the column family of a contract.
+    pub(crate) fn delta_written(&self, cf_name: &str, index_key: &[u8]) -> Result<(), Error> {
+        let index = match delta_index(index_key) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let mut usable = self.gaps.usable.lock_recover("Gap index");
+        let tip = match usable.get(cf_name) {
+            Some(tip) => *tip,
+            // Not read yet, it's computed on the first read.
+            None => return Ok(()),
+        };
+        if index == tip.map_or(0, |tip| tip + 1) {
+            let extended = self.contiguous_from(cf_name, index)?;
+            usable.insert(cf_name.to_string(), extended);
+        }
+        Ok(())
+    }
+
+    /// Called after the key `index_key` was removed from the column family of a contract.
+    pub(crate) fn delta_removed(&self, cf_name: &str, index_key: &[u8]) {
+        let index = match delta_index(index_key) {
+            Some(index) => index,
+            None => return,
+        };
+        let mut usable = self.gaps.usable.lock_recover("Gap index");
+        if let Some(tip) = usable.get_mut(cf_name) {
+            if tip.map_or(false, |tip| index <= tip) {
+                *tip = index.checked_sub(1);
+            }
+        }
+    }
+
+    /// Called after the column family of a contract was dropped.
+    pub(crate) fn forget_gaps(&self, cf_name: &str) { self.gaps.usable.lock_recover("Gap index").remove(cf_name); }
+
+    // The last key of the run of consecutive deltas starting at `from`, `None` if `from` isn't stored.
+    fn contiguous_from(&self, cf_name: &str, from: u32) -> Result<Option<u32>, Error> {
+        let cf = self.database.cf_handle(cf_name)
+            .ok_or_else(|| DBErr { command: "get_usable_tip".to_string(), kind: DBErrKind::MissingKey(cf_name.to_string()) })?;
+        let start = key_encoding::encode_index_key(Stype::Delta(from));
+        let keys = self.database.iterator_cf(cf, IteratorMode::From(&start, Direction::Forward))?.map(|(key, _)| key);
+        Ok(contiguous_run(keys, from))
+    }
+}
+
+/// The last key of the run of consecutive deltas starting at `from`, in `keys`: the keys of a contract in order from
+/// delta `from` on. `None` if the first one isn't delta `from`.
+pub(crate) fn contiguous_run<K: AsRef<[u8]>, I: IntoIterator<Item = K>>(keys: I, from: u32) -> Option<u32> {
+    let mut tip = None;
+    for key in keys {
+        let expected = match tip {
+            None => from,
+            Some(tip) if tip < u32::max_value() => tip + 1,
+            Some(_) => break,
+        };
+        // The keys after the deltas (the state, the bytecode...) aren't deltas and end the run too.
+        if delta_index(key.as_ref()) != Some(expected) {
+            break;
+        }
+        tip = Some(expected);
+    }
+    tip
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+    use self::rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, P2PCalls};
+
+    // The usable tip by reading every delta from 0.
+    fn scanned(db: &DB, address: ContractAddress) -> Option<u32> {
+        let mut tip = None;
+        while db.get_delta(DeltaKey::new(address, Stype::Delta(tip.map_or(0, |tip: u32| tip + 1)))).is_ok() {
+            tip = Some(tip.map_or(0, |tip| tip + 1));
+        }
+        tip
+    }
+
+    #[test]
+    fn test_delta_tips() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        assert_eq!(db.get_delta_tips(&address).unwrap(), None);
+        assert_eq!(db.get_usable_tip(&address).unwrap(), None);
+
+        // Deltas 1, 2 and 5 without 0.
+        for &key in &[1, 2, 5] {
+            db.create(&DeltaKey::new(address, Stype::Delta(key)), &[key as u8][..]).unwrap();
+        }
+        let tips = db.get_delta_tips(&address).unwrap().unwrap();
+        assert_eq!(tips, DeltaTips { raw: 5, usable: None });
+        assert!(tips.has_gaps());
+
+        // Filling 0 extends over 1 and 2, up to the next gap.
+        db.create(&DeltaKey::new(address, Stype::Delta(0)), &[0u8][..]).unwrap();
+        assert_eq!(db.get_delta_tips(&address).unwrap(), Some(DeltaTips { raw: 5, usable: Some(2) }));
+        db.insert_tuples(&[(DeltaKey::new(address, Stype::Delta(3)), vec![3u8]), (DeltaKey::new(address, Stype::Delta(4)), vec![4u8])]);
+        let tips = db.get_delta_tips(&address).unwrap().unwrap();
+        assert_eq!(tips, DeltaTips { raw: 5, usable: Some(5) });
+        assert!(!tips.has_gaps());
+
+        db.delete(&DeltaKey::new(address, Stype::Delta(3))).unwrap();
+        assert_eq!(db.get_delta_tips(&address).unwrap(), Some(DeltaTips { raw: 5, usable: Some(2) }));
+        // The state and the bytecode aren't deltas.
+        db.force_update(&DeltaKey::new(address, Stype::State), &b"state"[..]).unwrap();
+        assert_eq!(db.get_usable_tip(&address).unwrap(), Some(2));
+
+        db.delete_contract(&DeltaKey::new(address, Stype::ByteCode)).unwrap();
+        assert!(db.get_usable_tip(&address).is_err());
+    }
+
+    // Random writes and removals, the maintained usable tip must always match a scan from 0.
+    #[test]
+    fn test_usable_tip_matches_scan() {
+        let mut rng = StdRng::seed_from_u64(0x1463);
+        let (mut db, _dir) = create_test_db();
+        let addresses: Vec<ContractAddress> = (1..4u8).map(|i| [i; 32].into()).collect();
+        for address in &addresses {
+            db.create(&DeltaKey::new(*address, Stype::ByteCode), &b"code"[..]).unwrap();
+        }
+        for _ in 0..500 {
+            let address = addresses[rng.gen_range(0, addresses.len())];
+            let key = DeltaKey::new(address, Stype::Delta(rng.gen_range(0, 24)));
+            match rng.gen_range(0, 4) {
+                0 => { let _ = db.delete(&key); }
+                1 => { db.insert_tuples(&[(key, vec![1u8])]); }
+                _ => db.force_update(&key, &[1u8][..]).unwrap(),
+            }
+            assert_eq!(db.get_usable_tip(&address).unwrap(), scanned(&db, address));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! # Hosting policy.
+//! Which contracts this worker stores, for workers that don't want to mirror every contract the p2p node pushes at them:
+//! - `all`: every contract, like before.
+//! - `selected-only`: the contracts the worker was selected for. The core doesn't have the worker params to run the
+//!   selection itself, but the KM node only gives a worker the keys of the contracts it was selected for, so these are
+//!   the contracts the enclave was provisioned a key for by the last `PTTResponse`. Until one came every contract is
+//!   hosted, like every task is accepted before any epoch is known.
+//! - `explicit-list`: the contracts set with `SetHostedContracts`, none before the first one. They're kept in the DB,
+//!   so a restarted worker hosts the same contracts.
+//!
+//! `UpdateNewContract`, `UpdateNewContractOnDeployment` and `UpdateDeltas` answer the other contracts with `not_hosted`
+//! without storing anything. The KM node sends every key of the worker with a `PTTResponse`, in `explicit-list` mode the
+//! enclave only keeps those of the hosted contracts, in `selected-only` mode it keeps them all to learn the selection.
+//! Nothing already stored is removed when a contract stops being hosted.
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::{DB, HOSTED_KEY, SYNC};
+use enigma_types::ContractAddress;
+use failure::Error;
+use rocksdb::WriteOptions;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostingMode {
+    All,
+    SelectedOnly,
+    ExplicitList,
+}
+
+impl Default for HostingMode {
+    fn default() -> Self { HostingMode::All }
+}
+
+impl FromStr for HostingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => Ok(HostingMode::All),
+            "selected-only" => Ok(HostingMode::SelectedOnly),
+            "explicit-list" => Ok(HostingMode::ExplicitList),
+            other => Err(format!("Unknown hosting mode: {}, expected all, selected-only or explicit-list", other)),
+        }
+    }
+}
+
+impl fmt::Display for HostingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostingMode::All => write!(f, "all"),
+            HostingMode::SelectedOnly => write!(f, "selected-only"),
+            HostingMode::ExplicitList => write!(f, "explicit-list"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HostingPolicy {
+    mode: HostingMode,
+    // The contracts the enclave was provisioned a key for, `None` before the first PTT response.
+    selected: Option<HashSet<ContractAddress>>,
+    explicit: HashSet<ContractAddress>,
+}
+
+impl HostingPolicy {
+    pub fn new(mode: HostingMode) -> Self { HostingPolicy { mode, ..HostingPolicy::default() } }
+
+    pub fn configure(&mut self, mode: HostingMode) { self.mode = mode; }
+
+    pub fn mode(&self) -> HostingMode { self.mode }
+
+    /// Replaces the contracts the worker was selected for, called with each PTT response.
+    pub fn set_selected<I: IntoIterator<Item = ContractAddress>>(&mut self, addresses: Option<I>) {
+        self.selected = addresses.map(|addresses| addresses.into_iter().collect());
+    }
+
+    /// Replaces the contracts hosted in `explicit-list` mode.
+    pub fn set_explicit<I: IntoIterator<Item = ContractAddress>>(&mut self, addresses: I) {
+        self.explicit = addresses.into_iter().collect();
+    }
+
+    pub fn is_hosted(&self, address: &ContractAddress) -> bool {
+        match self.mode {
+            HostingMode::All => true,
+            HostingMode::SelectedOnly => self.selected.as_ref().map_or(true, |selected| selected.contains(address)),
+            HostingMode::ExplicitList => self.explicit.contains(address),
+        }
+    }
+
+    /// The contracts the enclave keeps the keys of from a PTT response in order, `None` to keep every key.
+    pub fn kept_keys(&self) -> Option<Vec<ContractAddress>> {
+        if self.mode != HostingMode::ExplicitList {
+            return None;
+        }
+        let mut hosted: Vec<_> = self.explicit.iter().cloned().collect();
+        hosted.sort();
+        Some(hosted)
+    }
+}
+
+impl DB {
+    pub fn set_hosting_mode(&mut self, mode: HostingMode) { self.hosting.configure(mode); }
+
+    pub fn hosting(&self) -> &HostingPolicy { &self.hosting }
+
+    pub fn hosting_mut(&mut self) -> &mut HostingPolicy { &mut self.hosting }
+
+    /// Replaces the contracts hosted in `explicit-list` mode, and keeps them in the DB.
+    pub fn set_hosted_contracts(&mut self, addresses: Vec<ContractAddress>) -> Result<(), Error> {
+        let value: Vec<u8> = addresses.iter().flat_map(|address| address.iter().cloned()).collect();
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.put_opt(HOSTED_KEY, &value, &write_options)?;
+        self.hosting.set_explicit(addresses);
+        Ok(())
+    }
+
+    /// Loads the contracts hosted in `explicit-list` mode from the DB, and returns how many there are.
+    pub fn load_hosted_contracts(&mut self) -> Result<usize, Error> {
+        let value = match self.database.get(HOSTED_KEY)? {
+            Some(value) => value,
+            None => return Ok(0),
+        };
+        if value.len() % 32 != 0 {
+            return Err(DBErr { command: "load_hosted_contracts".to_string(), kind: DBErrKind::FetchError }.into());
+        }
+        let addresses: Vec<ContractAddress> = value.chunks(32).map(|chunk| {
+            let mut address = [0u8; 32];
+            address.copy_from_slice(chunk);
+            address.into()
+        }).collect();
+        let loaded = addresses.len();
+        self.hosting.set_explicit(addresses);
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(b: u8) -> ContractAddress { [b; 32].into() }
+
+    #[test]
+    fn test_hosting_modes() {
+        let mut policy = HostingPolicy::default();
+        assert!(policy.is_hosted(&addr(1)));
+        assert_eq!(policy.kept_keys(), None);
+
+        policy.configure(HostingMode::SelectedOnly);
+        // Nothing selected yet.
+        assert!(policy.is_hosted(&addr(1)));
+        policy.set_selected(Some(vec![addr(2), addr(1)]));
+        assert!(policy.is_hosted(&addr(1)) && !policy.is_hosted(&addr(3)));
+        // Every key is kept, a contract selected in a new epoch would be dropped otherwise.
+        assert_eq!(policy.kept_keys(), None);
+        policy.set_selected(None::<Vec<ContractAddress>>);
+        assert!(policy.is_hosted(&addr(3)));
+
+        let mut policy = HostingPolicy::new(HostingMode::ExplicitList);
+        assert!(!policy.is_hosted(&addr(1)));
+        assert_eq!(policy.kept_keys(), Some(vec![]));
+        policy.set_explicit(vec![addr(3), addr(2)]);
+        assert!(policy.is_hosted(&addr(3)) && !policy.is_hosted(&addr(1)));
+        assert_eq!(policy.kept_keys(), Some(vec![addr(2), addr(3)]));
+    }
+
+    #[test]
+    fn test_hosted_contracts_persisted() {
+        let tempdir = tempfile::tempdir().unwrap();
+        {
+            let mut db = DB::new(tempdir.path(), true).unwrap();
+            assert_eq!(db.load_hosted_contracts().unwrap(), 0);
+            db.set_hosted_contracts(vec![addr(1), addr(2)]).unwrap();
+        }
+        let mut db = DB::new(tempdir.path(), true).unwrap();
+        db.set_hosting_mode(HostingMode::ExplicitList);
+        assert!(!db.hosting().is_hosted(&addr(1)));
+        assert_eq!(db.load_hosted_contracts().unwrap(), 2);
+        assert!(db.hosting().is_hosted(&addr(1)) && db.hosting().is_hosted(&addr(2)) && !db.hosting().is_hosted(&addr(3)));
+    }
+
+    #[test]
+    fn test_hosting_mode_names() {
+        for mode in &[HostingMode::All, HostingMode::SelectedOnly, HostingMode::ExplicitList] {
+            assert_eq!(mode.to_string().parse::<HostingMode>().unwrap(), *mode);
+        }
+        assert!("some".parse::<HostingMode>().is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! # Hot Contracts.
+//! An in-memory cache of contract bytecode in front of the DB, and the "hot set": the most executed contracts.
+//! The hot set is persisted in the DB, so after a restart the node can load their bytecode before it's
+//! hit with the burst of tasks that follows rejoining the network.
+//!
+//! The wasm module itself is built and validated inside the enclave on every execution, so all the warmup can
+//! prepare outside of it is the bytecode, and a structural check that drops contracts which can't be executed anyway.
+
+use failure::Error;
+use lru_cache::LruCache;
+use rocksdb::WriteOptions;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::{dal::HOT_SET_KEY, key_encoding::{self, BYTECODE_TAG}, DeltaKey, P2PCalls, Stype, DB};
+use enigma_crypto::hash::Keccak256;
+use enigma_types::{ContractAddress, Hash256};
+use hex::ToHex;
+
+/// The number of contracts kept in the persisted hot set.
+pub const HOT_SET_SIZE: usize = 64;
+/// The number of bytecodes kept in memory, bigger than the hot set so a burst of new contracts doesn't evict it.
+const CACHE_SIZE: usize = 128;
+/// The hot set is written back to the DB once every this many executions.
+const FLUSH_INTERVAL: u64 = 16;
+// Every entry is the address followed by the big endian execution count.
+const ENTRY_SIZE: usize = 32 + 8;
+// `\0asm` followed by version 1.
+const WASM_HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+/// When the hot set is loaded on startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmupMode {
+    /// Don't preload anything, every contract is read from the DB on its first task.
+    Off,
+    /// Finish the warmup before the listener starts accepting requests.
+    Blocking,
+    /// Read the bytecode and accept requests right away, the rest of the warmup runs on its own thread.
+    Background,
+}
+
+impl FromStr for WarmupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Ok(WarmupMode::Off),
+            "blocking" => Ok(WarmupMode::Blocking),
+            "background" => Ok(WarmupMode::Background),
+            other => Err(format!("Unknown warmup mode: {}, expected off, blocking or background", other)),
+        }
+    }
+}
+
+impl fmt::Display for WarmupMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarmupMode::Off => write!(f, "off"),
+            WarmupMode::Blocking => write!(f, "blocking"),
+            WarmupMode::Background => write!(f, "background"),
+        }
+    }
+}
+
+/// A range of the bytecode of a contract, see `DB::get_contract_chunk`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractChunk {
+    pub bytes: Vec<u8>,
+    /// The length of the whole bytecode.
+    pub total_len: u64,
+    /// The keccak256 of the whole bytecode, to check it after putting the chunks back together.
+    pub hash: Hash256,
+}
+
+impl ContractChunk {
+    fn slice(bytecode: &[u8], offset: u64, max_bytes: u64) -> Self {
+        let start = offset.min(bytecode.len() as u64) as usize;
+        let end = offset.saturating_add(max_bytes).min(bytecode.len() as u64) as usize;
+        ContractChunk { bytes: bytecode[start..end].to_vec(), total_len: bytecode.len() as u64, hash: bytecode.keccak256() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Executions {
+    count: u64,
+    // Breaks ties between equal counts in favour of the most recently executed contract.
+    last_seen: u64,
+}
+
+struct CacheInner {
+    bytecode: LruCache<ContractAddress, Arc<Vec<u8>>>,
+    executions: HashMap<ContractAddress, Executions>,
+    clock: u64,
+}
+
+/// Shared between the DB and the warmup thread, so the warmup can fill it while the listener is already running.
+pub struct ContractCache {
+    inner: Mutex<CacheInner>,
+    warmup_complete: AtomicBool,
+    misses: AtomicU64,
+}
+
+impl Default for ContractCache {
+    fn default() -> Self {
+        let inner = CacheInner { bytecode: LruCache::new(CACHE_SIZE), executions: HashMap::new(), clock: 0 };
+        ContractCache { inner: Mutex::new(inner), warmup_complete: AtomicBool::new(false), misses: AtomicU64::new(0) }
+    }
+}
+
+impl ContractCache {
+    fn lock(&self) -> MutexGuard<CacheInner> { self.inner.lock().unwrap_or_else(|e| e.into_inner()) }
+
+    pub fn get(&self, address: &ContractAddress) -> Option<Arc<Vec<u8>>> { self.lock().bytecode.get_mut(address).cloned() }
+
+    pub fn insert(&self, address: ContractAddress, bytecode: Arc<Vec<u8>>) { self.lock().bytecode.insert(address, bytecode); }
+
+    /// Drops the cached bytecode of the contract but keeps its executions, for when its bytecode is overwritten.
+    fn evict(&self, address: &ContractAddress) { self.lock().bytecode.remove(address); }
+
+    /// Drops all the cached bytecode but keeps the executions.
+    pub(crate) fn clear(&self) { self.lock().bytecode.clear(); }
+
+    /// Drops the contract from both the cache and the hot set, for when it's removed from the DB.
+    pub fn remove(&self, address: &ContractAddress) {
+        let mut inner = self.lock();
+        inner.bytecode.remove(address);
+        inner.executions.remove(address);
+    }
+
+    /// The contracts whose executions are counted, the hot set is taken out of them.
+    pub(crate) fn tracked(&self) -> Vec<ContractAddress> { self.lock().executions.keys().cloned().collect() }
+
+    /// Whether the hot set was loaded (always true if the warmup is off).
+    pub fn warmup_complete(&self) -> bool { self.warmup_complete.load(Ordering::SeqCst) }
+
+    /// The number of bytecode lookups that had to go to the DB.
+    pub fn misses(&self) -> u64 { self.misses.load(Ordering::SeqCst) }
+
+    /// Counts an execution of `address`, returns a running counter of the recorded executions.
+    fn record(&self, address: ContractAddress) -> u64 {
+        let mut inner = self.lock();
+        inner.clock += 1;
+        let last_seen = inner.clock;
+        let entry = inner.executions.entry(address).or_insert_with(Default::default);
+        entry.count += 1;
+        entry.last_seen = last_seen;
+        last_seen
+    }
+
+    /// The `HOT_SET_SIZE` most executed contracts, with their execution counts.
+    pub fn hot_set(&self) -> Vec<(ContractAddress, u64)> {
+        let inner = self.lock();
+        let mut hot: Vec<_> = inner.executions.iter().map(|(addr, ex)| (*addr, *ex)).collect();
+        hot.sort_by(|(_, a), (_, b)| (b.count, b.last_seen).cmp(&(a.count, a.last_seen)));
+        hot.into_iter().take(HOT_SET_SIZE).map(|(addr, ex)| (addr, ex.count)).collect()
+    }
+
+    /// Seeds the execution counts from a persisted hot set, so the counts keep growing across restarts.
+    fn seed(&self, hot_set: &[(ContractAddress, u64)]) {
+        let mut inner = self.lock();
+        // The set is stored hottest first, replay it backwards so the hottest is also the most recent.
+        for (address, count) in hot_set.iter().rev() {
+            inner.clock += 1;
+            let last_seen = inner.clock;
+            let entry = inner.executions.entry(*address).or_insert_with(Default::default);
+            entry.count += count;
+            entry.last_seen = last_seen;
+        }
+    }
+
+    fn prepare(&self, contracts: Vec<(ContractAddress, Vec<u8>)>) {
+        let total = contracts.len();
+        let mut loaded = 0;
+        for (address, bytecode) in contracts {
+            if !bytecode.starts_with(&WASM_HEADER) {
+                warn!("Not warming up contract {}, its bytecode isn't a wasm module", address.to_hex());
+                continue;
+            }
+            self.insert(address, Arc::new(bytecode));
+            loaded += 1;
+        }
+        self.warmup_complete.store(true, Ordering::SeqCst);
+        info!("Warmup complete, loaded {} of {} hot contracts", loaded, total);
+    }
+}
+
+impl DB {
+    /// The bytecode cache of this DB, it can be handed to other threads.
+    pub fn contract_cache(&self) -> Arc<ContractCache> { Arc::clone(&self.contracts) }
+
+    /// Called after the key `index_key` was written to or removed from the column family of a contract,
+    /// a cached bytecode that was overwritten would otherwise keep being executed.
+    pub(crate) fn bytecode_written(&self, cf_name: &str, index_key: &[u8]) {
+        if index_key != &[BYTECODE_TAG][..] {
+            return;
+        }
+        match ContractAddress::from_hex(cf_name) {
+            Ok(address) => self.contracts.evict(&address),
+            Err(e) => warn!("Not evicting the bytecode of {}, it isn't a contract address: {}", cf_name, e),
+        }
+    }
+
+    /// Returns the bytecode of a contract, going to the DB only if it isn't cached.
+    pub fn get_contract_cached(&self, address: ContractAddress) -> Result<Arc<Vec<u8>>, Error> {
+        self.find_contract_cached(address)?
+            .ok_or_else(|| DBErr { command: "get_contract_cached".to_string(), kind: DBErrKind::MissingKey(address.to_hex()) }.into())
+    }
+
+    /// Like `get_contract_cached`, with `None` if the contract isn't stored. A contract that isn't there isn't cached either.
+    pub fn find_contract_cached(&self, address: ContractAddress) -> Result<Option<Arc<Vec<u8>>>, Error> {
+        if let Some(bytecode) = self.contracts.get(&address) {
+            return Ok(Some(bytecode));
+        }
+        self.contracts.misses.fetch_add(1, Ordering::SeqCst);
+        let bytecode = match self.find_contract(address)? {
+            Some(bytecode) => Arc::new(bytecode),
+            None => return Ok(None),
+        };
+        self.contracts.insert(address, Arc::clone(&bytecode));
+        Ok(Some(bytecode))
+    }
+
+    /// Returns up to `max_bytes` of the bytecode of a contract from `offset` (nothing past its end).
+    /// A cached bytecode is sliced in memory, otherwise only the chunk is copied out of the DB, and it isn't cached:
+    /// the big contracts fetched in chunks are the ones being synced, not executed.
+    pub fn get_contract_chunk(&self, address: ContractAddress, offset: u64, max_bytes: u64) -> Result<ContractChunk, Error> {
+        if let Some(bytecode) = self.contracts.get(&address) {
+            return Ok(ContractChunk::slice(&bytecode, offset, max_bytes));
+        }
+        let key = DeltaKey::new(address, Stype::ByteCode);
+        if !self.may_hold(&key_encoding::cf_name(&address)) {
+            return Err(DBErr { command: "get_contract_chunk".to_string(), kind: DBErrKind::MissingKey(address.to_hex()) }.into());
+        }
+        self.read_pinned(&key, |bytecode| ContractChunk::slice(bytecode, offset, max_bytes))?
+            .ok_or_else(|| DBErr { command: "get_contract_chunk".to_string(), kind: DBErrKind::MissingKey(address.to_hex()) }.into())
+    }
+
+    /// Counts an execution of the contract towards the hot set, and persists the set every `FLUSH_INTERVAL` executions.
+    pub fn record_execution(&mut self, address: ContractAddress) {
+        if self.contracts.record(address) % FLUSH_INTERVAL == 0 {
+            if let Err(e) = self.save_hot_set() {
+                warn!("Failed saving the hot set: {}", e);
+            }
+        }
+    }
+
+    /// Writes the current hot set to the DB.
+    pub fn save_hot_set(&self) -> Result<(), Error> {
+        let value = encode_hot_set(&self.contracts.hot_set());
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(false);
+        self.database.put_opt(HOT_SET_KEY, &value, &write_options)?;
+        Ok(())
+    }
+
+    /// Drops the contracts that aren't stored anymore from the hot set, and persists it if any was.
+    /// A removed contract is only dropped by the core that removed it, one seeded from a persisted set stays otherwise.
+    /// Returns how many were dropped.
+    pub fn prune_hot_set(&self) -> Result<usize, Error> {
+        let mut removed = 0;
+        for address in self.contracts.tracked() {
+            if self.find_contract(address)?.is_none() {
+                self.contracts.remove(&address);
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            self.save_hot_set()?;
+        }
+        Ok(removed)
+    }
+
+    /// Reads the persisted hot set, hottest first. A DB that never saved one has an empty hot set.
+    pub fn load_hot_set(&self) -> Result<Vec<(ContractAddress, u64)>, Error> {
+        let value = match self.database.get(HOT_SET_KEY)? {
+            Some(value) => value,
+            None => return Ok(Vec::new()),
+        };
+        decode_hot_set(&value).ok_or_else(|| DBErr { command: "load_hot_set".to_string(), kind: DBErrKind::FetchError }.into())
+    }
+
+    /// Loads the bytecode of the persisted hot set into the cache.
+    /// The DB reads always happen on the calling thread, in `Background` mode the preparation is done on a new
+    /// thread whose handle is returned, until it's done tasks for hot contracts are simply served from the DB.
+    pub fn warmup(&mut self, mode: WarmupMode) -> Result<Option<JoinHandle<()>>, Error> {
+        if mode == WarmupMode::Off {
+            self.contracts.warmup_complete.store(true, Ordering::SeqCst);
+            return Ok(None);
+        }
+        let hot_set = self.load_hot_set()?;
+        self.contracts.seed(&hot_set);
+        // A contract might have been removed since the set was saved, it just won't be warm.
+        let contracts: Vec<_> = hot_set.into_iter()
+            .filter_map(|(address, _)| self.get_contract(address).ok().map(|bytecode| (address, bytecode)))
+            .collect();
+        let cache = self.contract_cache();
+        match mode {
+            WarmupMode::Background => {
+                let handle = thread::Builder::new().name("warmup".to_string()).spawn(move || cache.prepare(contracts))?;
+                Ok(Some(handle))
+            }
+            _ => {
+                cache.prepare(contracts);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The persisted form of a hot set, see `ENTRY_SIZE`.
+pub(crate) fn encode_hot_set(hot_set: &[(ContractAddress, u64)]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(hot_set.len() * ENTRY_SIZE);
+    for (address, count) in hot_set {
+        value.extend_from_slice(&address[..]);
+        value.extend_from_slice(&count.to_be_bytes());
+    }
+    value
+}
+
+/// The inverse of [`encode_hot_set`], `None` if `value` isn't a whole number of entries.
+pub(crate) fn decode_hot_set(value: &[u8]) -> Option<Vec<(ContractAddress, u64)>> {
+    if value.len() % ENTRY_SIZE != 0 {
+        return None;
+    }
+    Some(value.chunks(ENTRY_SIZE).map(|entry| {
+        let mut address = [0u8; 32];
+        address.copy_from_slice(&entry[..32]);
+        let mut count = [0u8; 8];
+        count.copy_from_slice(&entry[32..]);
+        (address.into(), u64::from_be_bytes(count))
+    }).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, Stype};
+
+    fn bytecode(n: u8) -> Vec<u8> {
+        let mut bytecode = WASM_HEADER.to_vec();
+        bytecode.push(n);
+        bytecode
+    }
+
+    fn add_contract(db: &mut DB, address: ContractAddress, bytecode: &[u8]) {
+        db.create(&DeltaKey::new(address, Stype::ByteCode), bytecode).unwrap();
+    }
+
+    #[test]
+    fn test_hot_set_order() {
+        let (mut db, _dir) = create_test_db();
+        let (a, b, c): (ContractAddress, ContractAddress, ContractAddress) = ([1u8; 32].into(), [2u8; 32].into(), [3u8; 32].into());
+        for address in &[a, b, b, c, a, b] {
+            db.record_execution(*address);
+        }
+        assert_eq!(db.contract_cache().hot_set(), vec![(b, 3), (a, 2), (c, 1)]);
+
+        db.save_hot_set().unwrap();
+        assert_eq!(db.load_hot_set().unwrap(), vec![(b, 3), (a, 2), (c, 1)]);
+    }
+
+    #[test]
+    fn test_warm_start() {
+        let (mut db, dir) = create_test_db();
+        let hot: ContractAddress = [1u8; 32].into();
+        let cold: ContractAddress = [2u8; 32].into();
+        let invalid: ContractAddress = [3u8; 32].into();
+        add_contract(&mut db, hot, &bytecode(1));
+        add_contract(&mut db, cold, &bytecode(2));
+        add_contract(&mut db, invalid, b"not wasm");
+        for _ in 0..3 {
+            db.record_execution(hot);
+        }
+        db.record_execution(invalid);
+        db.save_hot_set().unwrap();
+        drop(db);
+
+        // A restart without a warmup goes to the DB.
+        let mut db = DB::new(dir.path(), false).unwrap();
+        db.warmup(WarmupMode::Off).unwrap();
+        assert!(db.contract_cache().warmup_complete());
+        db.get_contract_cached(hot).unwrap();
+        assert_eq!(db.contract_cache().misses(), 1);
+        drop(db);
+
+        let mut db = DB::new(dir.path(), false).unwrap();
+        assert!(!db.contract_cache().warmup_complete());
+        db.warmup(WarmupMode::Background).unwrap().unwrap().join().unwrap();
+        assert!(db.contract_cache().warmup_complete());
+        assert_eq!(*db.get_contract_cached(hot).unwrap(), bytecode(1));
+        assert_eq!(db.contract_cache().misses(), 0);
+        // The invalid contract wasn't loaded, and a contract that isn't hot is read like before.
+        db.get_contract_cached(invalid).unwrap();
+        db.get_contract_cached(cold).unwrap();
+        assert_eq!(db.contract_cache().misses(), 2);
+        // The persisted counts carry on.
+        assert_eq!(db.contract_cache().hot_set()[0], (hot, 3));
+    }
+
+    #[test]
+    fn test_overwritten_bytecode_evicted() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        let key = DeltaKey::new(address, Stype::ByteCode);
+        add_contract(&mut db, address, &bytecode(1));
+        // Cached by the first read.
+        assert_eq!(*db.get_contract_cached(address).unwrap(), bytecode(1));
+
+        db.update(&key, &bytecode(2)[..]).unwrap();
+        assert_eq!(*db.get_contract_cached(address).unwrap(), bytecode(2));
+
+        db.force_update(&key, &bytecode(3)[..]).unwrap();
+        assert_eq!(*db.get_contract_cached(address).unwrap(), bytecode(3));
+
+        let res = db.insert_tuples(&[(key, bytecode(4))]);
+        assert!(res.iter().all(Result::is_ok));
+        assert_eq!(*db.get_contract_cached(address).unwrap(), bytecode(4));
+    }
+
+    #[test]
+    fn test_contract_chunk() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [4u8; 32].into();
+        let code = bytecode(4);
+        add_contract(&mut db, address, &code);
+
+        let from_db = db.get_contract_chunk(address, 2, 4).unwrap();
+        assert_eq!(from_db, ContractChunk { bytes: code[2..6].to_vec(), total_len: code.len() as u64, hash: code.keccak256() });
+        // Reading a chunk doesn't cache the contract.
+        assert!(db.contract_cache().get(&address).is_none());
+
+        db.get_contract_cached(address).unwrap();
+        assert_eq!(db.get_contract_chunk(address, 2, 4).unwrap(), from_db);
+        // Nothing past the end.
+        assert_eq!(db.get_contract_chunk(address, 6, u64::max_value()).unwrap().bytes, code[6..].to_vec());
+        assert!(db.get_contract_chunk(address, 100, 4).unwrap().bytes.is_empty());
+        assert!(db.get_contract_chunk([5u8; 32].into(), 0, 4).is_err());
+    }
+}
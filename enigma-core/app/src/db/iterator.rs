@@ -1,13 +1,15 @@
 use common_u::errors::{DBErr, DBErrKind};
 use db::dal::{CRUDInterface, DB};
+use db::key_encoding::{self, delta_index, DELTA_PREFIX};
+use db::mirror::MirrorOp;
 use db::primitives::{DeltaKey, SplitKey, Stype};
 use enigma_types::ContractAddress;
 use failure::Error;
 use hex::{FromHex, ToHex};
 use rocksdb::DB as rocks_db;
-use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
-
-const DELTA_PREFIX: &[u8] = &[1];
+use rocksdb::{Direction, IteratorMode, Options, ReadOptions, WriteBatch};
+use std::collections::HashMap;
+use std::path::Path;
 
 type ResultVec<T> = Result<Vec<T>, Error>;
 pub type ResultTypeVec<T> = Result<ResultType<Vec<T>>, Error>;
@@ -167,6 +169,10 @@ pub trait P2PCalls {
     /// ```
     fn get_contract(&self, address: ContractAddress) -> ResultVec<u8>;
 
+    /// get the contract of the required address, `None` if it isn't stored.
+    /// Unlike `get_contract`, a failure to read the DB is an error and not a missing contract.
+    fn find_contract(&self, address: ContractAddress) -> Result<Option<Vec<u8>>, Error>;
+
     /// returns a list of the latest deltas for all addresses that exist in the DB.
     /// # Examples
     /// ```
@@ -262,9 +268,12 @@ impl P2PCalls for DB {
     #[logfn(TRACE)]
     fn get_tip<K: SplitKey>(&self, address: &ContractAddress) -> Result<(K, Vec<u8>), Error> {
         // check and extract the CF from the DB
-        // to_hex converts the [u8] to str
-        let str_addr = address.to_hex();
+        // the name of the contract's CF, see `db::key_encoding`
+        let str_addr = key_encoding::cf_name(address);
         trace!("DB: Get Tip: cf: {}, ", str_addr);
+        if !self.may_hold(&str_addr) {
+            return Err(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey(str_addr) }.into());
+        }
         let cf_key =
             self.database.cf_handle(&str_addr).ok_or(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey(str_addr.clone()) })?;
 
@@ -292,37 +301,15 @@ impl P2PCalls for DB {
     #[logfn(TRACE)]
     fn get_all_addresses(&self) -> Result<Vec<ContractAddress>, Error> {
         trace!("DB: Get all addresses");
-        // get a list of all CF's (addresses) in our DB
-        let mut cf_list = rocks_db::list_cf(&self.options, &self.location)?;
-        match cf_list.len() {
-            // list_cf returns "Default" as the first CF,
-            // so we remove it if we have elements other than that in the DB.
-            l if l > 1 => cf_list.remove(0),
-            _ => return Err(DBErr { command: "get_all_addresses".to_string(), kind: DBErrKind::MissingKeys }.into()),
-        };
-        // convert all addresses from strings to slices.
-        // filter_map filters all None types from the iterator,
-        // therefore we return Option type for each item in the closure
-        let addr_list = cf_list
-            .iter()
-            .filter_map(|address_str| {
-                let mut address = ContractAddress::default();
-                let slice_address = match address_str.from_hex() {
-                    Ok(slice) => slice,
-                    // if the address is not a correct hex then it is not a correct address.
-                    Err(_) => return None,
-                };
-                address.copy_from_slice(&slice_address);
-                Some(address)
-            })
-            .collect::<Vec<_>>();
-
-        trace!("DB: Continue Get all addresses, list: {:?}", addr_list);
-        Ok(addr_list)
+        list_addresses(&self.options, &self.location)
     }
 
     #[logfn(TRACE)]
     fn get_delta<K: SplitKey>(&self, key: K) -> ResultVec<u8> {
+        let known = key.as_split(|addr, _| self.may_hold(addr));
+        if !known {
+            return Err(key.as_split(|addr, _| DBErr { command: "get_delta".to_string(), kind: DBErrKind::MissingKey(addr.to_string()) }).into());
+        }
         Ok(self.read(&key).map_err(|_|
             key.as_split(| addr, _ | {
                 DBErr { command: "get_delta".to_string(), kind: DBErrKind::MissingKey(addr.to_string()) }
@@ -331,8 +318,22 @@ impl P2PCalls for DB {
 
     #[logfn(TRACE)]
     fn get_contract(&self, contract_address: ContractAddress) -> ResultVec<u8> {
-        let key = DeltaKey { contract_address, key_type: Stype::ByteCode };
-        Ok(self.read(&key).map_err(|_| DBErr { command: "get_contract".to_string(), kind: DBErrKind::MissingKey(contract_address.to_hex()) })?)
+        self.find_contract(contract_address)?
+            .ok_or_else(|| DBErr { command: "get_contract".to_string(), kind: DBErrKind::MissingKey(contract_address.to_hex()) }.into())
+    }
+
+    #[logfn(TRACE)]
+    fn find_contract(&self, contract_address: ContractAddress) -> Result<Option<Vec<u8>>, Error> {
+        DeltaKey { contract_address, key_type: Stype::ByteCode }.as_split(|hash, index_key| -> Result<Option<Vec<u8>>, Error> {
+            // A contract that was never stored has no column family
+            if !self.may_hold(hash) {
+                return Ok(None);
+            }
+            match self.database.cf_handle(&hash) {
+                Some(cf_key) => Ok(self.database.get_cf(cf_key, &index_key)?.map(|value| value.to_vec())),
+                None => Ok(None),
+            }
+        })
     }
 
     #[logfn(TRACE)]
@@ -349,6 +350,9 @@ impl P2PCalls for DB {
         // convert the key to the rocksdb representation
         from.as_split(|from_hash, from_key| {
             // make sure the address exists as a CF in the DB
+            if !self.may_hold(from_hash) {
+                return Err(DBErr { command: "get_deltas".to_string(), kind: DBErrKind::MissingKey(from_hash.to_string()) }.into());
+            }
             let cf_key = self.database.cf_handle(&from_hash).
                     ok_or(DBErr{ command: "get_deltas".to_string(), kind: DBErrKind::MissingKey(from_hash.to_string()) })?;
 
@@ -395,24 +399,88 @@ impl P2PCalls for DB {
     fn insert_tuples<K: SplitKey, S: AsRef<[u8]>>(&mut self, key_vals: &[(K, S)]) -> Vec<Result<(), Error>> {
         let mut res = Vec::with_capacity(key_vals.len());
         let mut batch = WriteBatch::default();
+        // The deltas of every contract in the batch, their chain hashes are written with them.
+        let mut deltas: HashMap<String, Vec<(u32, &[u8])>> = HashMap::new();
         for (key, val) in key_vals {
+            let val = val.as_ref();
             let tmp_res = key.as_split(|cf_str, key_slice| -> Result<(), Error> {
                 let cf = match self.database.cf_handle(cf_str) {
                     Some(cf) => cf,
                     None => self.database.create_cf(cf_str, &self.options)?,
                 };
+                self.index_cf(cf_str);
                 batch.put_cf(cf, key_slice, val)?;
+                self.bytecode_written(cf_str, key_slice);
+                if let Some(index) = delta_index(key_slice) {
+                    deltas.entry(cf_str.to_string()).or_insert_with(Vec::new).push((index, val));
+                }
                 Ok(())
             });
             res.push(tmp_res);
         }
-        match self.database.write(batch) {
-            Ok(_) => res,
-            Err(e) => vec![Err(e.into())],
+        for (cf_str, written) in &deltas {
+            if let Err(e) = self.chain_hash_batch(&mut batch, cf_str, written) {
+                // Dropped in the same batch instead, so the reads recompute it.
+                warn!("Failed updating the chain hash of {}: {}", cf_str, e);
+                if let Err(e) = self.drop_chain_hash(&mut batch, cf_str) {
+                    return vec![Err(e)];
+                }
+            }
+        }
+        if let Err(e) = self.database.write(batch) {
+            return vec![Err(e.into())];
+        }
+        for ((key, val), r) in key_vals.iter().zip(res.iter_mut()).filter(|(_, r)| r.is_ok()) {
+            key.as_split(|cf_str, key_slice| {
+                if let Err(e) = self.delta_written(cf_str, key_slice) {
+                    warn!("Failed updating the usable tip of {}: {}", cf_str, e);
+                    self.forget_gaps(cf_str);
+                }
+                let op = || MirrorOp::Put { cf: cf_str.to_string(), key: key_slice.to_vec(), value: val.as_ref().to_vec() };
+                if let Err(e) = self.mirror_write(op) {
+                    *r = Err(e);
+                }
+            });
         }
+        res
     }
 }
 
+impl DB {
+    /// Like `get_all_addresses`, for the DB at `location` without going through it, so it can be called from any thread.
+    pub fn addresses_at(location: &Path) -> Result<Vec<ContractAddress>, Error> { list_addresses(&Options::default(), location) }
+}
+
+fn list_addresses(options: &Options, location: &Path) -> Result<Vec<ContractAddress>, Error> {
+    // get a list of all CF's (addresses) in our DB
+    let mut cf_list = rocks_db::list_cf(options, location)?;
+    match cf_list.len() {
+        // list_cf returns "Default" as the first CF,
+        // so we remove it if we have elements other than that in the DB.
+        l if l > 1 => cf_list.remove(0),
+        _ => return Err(DBErr { command: "get_all_addresses".to_string(), kind: DBErrKind::MissingKeys }.into()),
+    };
+    // convert all addresses from strings to slices.
+    // filter_map filters all None types from the iterator,
+    // therefore we return Option type for each item in the closure
+    let addr_list = cf_list
+        .iter()
+        .filter_map(|address_str| {
+            let mut address = ContractAddress::default();
+            let slice_address = match address_str.from_hex() {
+                Ok(slice) => slice,
+                // if the address is not a correct hex then it is not a correct address.
+                Err(_) => return None,
+            };
+            address.copy_from_slice(&slice_address);
+            Some(address)
+        })
+        .collect::<Vec<_>>();
+
+    trace!("DB: Continue Get all addresses, list: {:?}", addr_list);
+    Ok(addr_list)
+}
+
 #[cfg(test)]
 mod test {
     use db::{CRUDInterface, P2PCalls, tests::create_test_db};
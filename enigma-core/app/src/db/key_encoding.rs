@@ -0,0 +1,165 @@
+//! # DB key encoding.
+//! How a `DeltaKey` is laid out on disk, every read and write of the contracts goes through here.
+//!
+//! Every contract has a column family of its own, named after the lowercase hex of its address.
+//! Inside it a key starts with a one byte tag:
+//!
+//! | key type        | key in the column family     |
+//! |-----------------|------------------------------|
+//! | `Delta(n)`      | `0x01 \|\| u32_be(n)`        |
+//! | `State`         | `0x02`                       |
+//! | `ByteCode`      | `0x03`                       |
+//! | the chain hash  | `0x04` (not a `DeltaKey`)    |
+//!
+//! The indexes are big endian so the deltas of a contract sort numerically, the range scans and
+//! `get_tip` depend on it. The tags follow the order of `Stype`, so the keys of a contract sort like their `DeltaKey`s.
+//! [`encode_key`] is the same key with the address in front, in place of the column family,
+//! it sorts by (address, type, index) exactly like `DeltaKey`.
+//!
+//! The layout can't change without migrating the stored keys, any change must bump [`KEY_SCHEMA_VERSION`].
+
+use db::primitives::{DeltaKey, Stype};
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+
+/// The version of the layout above.
+pub const KEY_SCHEMA_VERSION: u32 = 1;
+
+pub const DELTA_TAG: u8 = 1;
+pub const STATE_TAG: u8 = 2;
+pub const BYTECODE_TAG: u8 = 3;
+pub const CHAIN_HASH_TAG: u8 = 4;
+
+/// The prefix of every delta in the column family of a contract.
+pub(crate) const DELTA_PREFIX: &[u8] = &[DELTA_TAG];
+/// Where the chain hash of the deltas is kept in the column family of a contract, see `db::chain_hash`.
+pub(crate) const CHAIN_HASH_KEY: &[u8] = &[CHAIN_HASH_TAG];
+
+const ADDRESS_LEN: usize = 32;
+const DELTA_KEY_LEN: usize = 5;
+
+/// The name of the column family of a contract.
+pub fn cf_name(address: &ContractAddress) -> String { address.to_hex() }
+
+/// The key of `key_type` inside the column family of its contract.
+pub fn encode_index_key(key_type: Stype) -> Vec<u8> {
+    match key_type {
+        Stype::Delta(index) => {
+            let mut key = Vec::with_capacity(DELTA_KEY_LEN);
+            key.push(DELTA_TAG);
+            key.extend_from_slice(&index.to_be_bytes());
+            key
+        }
+        Stype::State => vec![STATE_TAG],
+        Stype::ByteCode => vec![BYTECODE_TAG],
+    }
+}
+
+/// The inverse of [`encode_index_key`], any other bytes (a chain hash key, a truncated delta key, ...) are an error.
+pub fn decode_index_key(index_key: &[u8]) -> Result<Stype, Error> {
+    match (index_key.first(), index_key.len()) {
+        (Some(&DELTA_TAG), DELTA_KEY_LEN) => Ok(Stype::Delta(delta_index(index_key).expect("a delta key of the right length"))),
+        (Some(&DELTA_TAG), len) => bail!("A delta key must be {} bytes, got {}", DELTA_KEY_LEN, len),
+        (Some(&STATE_TAG), 1) => Ok(Stype::State),
+        (Some(&BYTECODE_TAG), 1) => Ok(Stype::ByteCode),
+        _ => bail!("Failed parsing the Key, key does not contain a correct index"),
+    }
+}
+
+/// The index of the delta stored under `index_key`, `None` if it isn't a delta key.
+pub fn delta_index(index_key: &[u8]) -> Option<u32> {
+    if index_key.len() != DELTA_KEY_LEN || index_key[..1] != *DELTA_PREFIX {
+        return None;
+    }
+    let mut index = [0u8; 4];
+    index.copy_from_slice(&index_key[1..]);
+    Some(u32::from_be_bytes(index))
+}
+
+/// The whole key as a single byte string: `address || index key`.
+pub fn encode_key(key: &DeltaKey) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(ADDRESS_LEN + DELTA_KEY_LEN);
+    encoded.extend_from_slice(&key.contract_address[..]);
+    encoded.extend_from_slice(&encode_index_key(key.key_type));
+    encoded
+}
+
+/// The inverse of [`encode_key`].
+pub fn decode_key(encoded: &[u8]) -> Result<DeltaKey, Error> {
+    if encoded.len() <= ADDRESS_LEN {
+        bail!("A key must be longer than the {} bytes of its address, got {}", ADDRESS_LEN, encoded.len());
+    }
+    let mut address = [0u8; ADDRESS_LEN];
+    address.copy_from_slice(&encoded[..ADDRESS_LEN]);
+    let key_type = decode_index_key(&encoded[ADDRESS_LEN..])?;
+    Ok(DeltaKey::new(address.into(), key_type))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+    use self::rand::{Rng, SeedableRng, rngs::StdRng};
+    use super::*;
+
+    fn random_key(rng: &mut StdRng) -> DeltaKey {
+        // Few addresses and small indexes, so the comparisons often get past the address and the tag.
+        let mut address = [rng.gen_range(0, 3); ADDRESS_LEN];
+        address[ADDRESS_LEN - 1] = rng.gen();
+        let key_type = match rng.gen_range(0, 4) {
+            0 => Stype::State,
+            1 => Stype::ByteCode,
+            2 => Stype::Delta(rng.gen_range(0, 600)),
+            _ => Stype::Delta(rng.gen()),
+        };
+        DeltaKey::new(address.into(), key_type)
+    }
+
+    #[test]
+    fn test_layout() {
+        let address: ContractAddress = [7u8; 32].into();
+        assert_eq!(encode_index_key(Stype::Delta(0x0102_0304)), vec![1, 1, 2, 3, 4]);
+        assert_eq!(encode_index_key(Stype::State), vec![2]);
+        assert_eq!(encode_index_key(Stype::ByteCode), vec![3]);
+        assert_eq!(cf_name(&address), "07".repeat(32));
+        let encoded = encode_key(&DeltaKey::new(address, Stype::Delta(256)));
+        assert_eq!(encoded[..32], [7u8; 32]);
+        assert_eq!(encoded[32..], [1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0x1442);
+        for _ in 0..2000 {
+            let key = random_key(&mut rng);
+            assert_eq!(decode_key(&encode_key(&key)).unwrap(), key);
+            assert_eq!(decode_index_key(&encode_index_key(key.key_type)).unwrap(), key.key_type);
+        }
+        for edge in &[0, 1, 255, 256, u32::max_value()] {
+            assert_eq!(decode_index_key(&encode_index_key(Stype::Delta(*edge))).unwrap(), Stype::Delta(*edge));
+        }
+    }
+
+    #[test]
+    fn test_order_matches_keys() {
+        let mut rng = StdRng::seed_from_u64(0x2442);
+        for _ in 0..5000 {
+            let (a, b) = (random_key(&mut rng), random_key(&mut rng));
+            assert_eq!(encode_key(&a).cmp(&encode_key(&b)), a.cmp(&b), "{:?} vs {:?}", a, b);
+            if a.contract_address == b.contract_address {
+                assert_eq!(encode_index_key(a.key_type).cmp(&encode_index_key(b.key_type)), a.key_type.cmp(&b.key_type));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_foreign_keys() {
+        assert!(decode_index_key(CHAIN_HASH_KEY).is_err());
+        assert!(decode_index_key(&[]).is_err());
+        assert!(decode_index_key(&[DELTA_TAG, 0, 1]).is_err());
+        assert!(decode_index_key(&[STATE_TAG, 0]).is_err());
+        assert!(decode_key(&[1u8; 32]).is_err());
+        assert_eq!(delta_index(&[DELTA_TAG, 0, 0, 0, 9]), Some(9));
+        assert_eq!(delta_index(CHAIN_HASH_KEY), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! # DB maintenance.
+//! What piles up in the DB besides the contracts is pruned from time to time, instead of by the handlers writing it,
+//! so a request never pays for it. A pass runs on the IPC handler thread like any request, on the schedule of
+//! [`maintenance`](../../networking/maintenance/index.html) or on a `RunMaintenance` request. It removes:
+//! - the registrations over the retention of `--registration-history`, left behind when the retention was lowered,
+//! - the hot set entries of the contracts that aren't stored anymore, they'd be persisted again and again otherwise,
+//!
+//! and then compacts the DB, so what was deleted (removed contracts and deltas included) leaves the disk too.
+
+use failure::Error;
+use db::{key_encoding, P2PCalls, DB};
+
+/// What a maintenance pass removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub registrations: usize,
+    #[serde(rename = "hotSet")]
+    pub hot_set: usize,
+    /// How much smaller the DB directory got with the compaction.
+    #[serde(rename = "reclaimedBytes")]
+    pub reclaimed_bytes: u64,
+}
+
+impl MaintenanceReport {
+    /// The removed entries by kind, as labelled in the metrics.
+    pub fn removed(&self) -> [(&'static str, usize); 2] { [("registrations", self.registrations), ("hot_set", self.hot_set)] }
+}
+
+impl DB {
+    /// Runs a maintenance pass, keeping at most `registration_cap` registrations.
+    pub fn run_maintenance(&self, registration_cap: usize) -> Result<MaintenanceReport, Error> {
+        let registrations = self.prune_registrations(registration_cap)?;
+        let hot_set = self.prune_hot_set()?;
+        let before = self.disk_size();
+        self.compact();
+        Ok(MaintenanceReport { registrations, hot_set, reclaimed_bytes: before.saturating_sub(self.disk_size()) })
+    }
+
+    /// Compacts the default column family and the ones of the contracts.
+    pub fn compact(&self) {
+        self.database.compact_range(None::<&[u8]>, None::<&[u8]>);
+        // An empty DB has no contracts, and nothing else to compact.
+        for address in self.get_all_addresses().unwrap_or_default() {
+            if let Some(cf) = self.database.cf_handle(&key_encoding::cf_name(&address)) {
+                self.database.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::tests::create_test_db;
+    use crate::db::{CRUDInterface, DeltaKey, RegistrationRecord, Stype};
+    use enigma_types::ContractAddress;
+
+    fn record(n: u64) -> RegistrationRecord {
+        RegistrationRecord {
+            index: 0,
+            timestamp: 1_500_000_000 + n,
+            signing_key: format!("{:040x}", n),
+            report: format!("report{}", n),
+            signature: format!("signature{}", n),
+            mr_enclave: format!("{:064x}", n),
+        }
+    }
+
+    #[test]
+    fn test_maintenance_removes_expired() {
+        let (mut db, _dir) = create_test_db();
+        // Appended under a retention of 5, which is now 2.
+        for n in 0..5 {
+            db.append_registration(record(n), 5).unwrap();
+        }
+        let (kept, removed): (ContractAddress, ContractAddress) = ([1u8; 32].into(), [2u8; 32].into());
+        for address in &[kept, removed] {
+            db.create(&DeltaKey::new(*address, Stype::ByteCode), &[0, 97, 115, 109][..]).unwrap();
+            db.record_execution(*address);
+        }
+        db.save_hot_set().unwrap();
+        db.delete(&DeltaKey::new(removed, Stype::ByteCode)).unwrap();
+
+        let report = db.run_maintenance(2).unwrap();
+        assert_eq!((report.registrations, report.hot_set), (3, 1));
+        let indexes: Vec<u64> = db.get_registrations(None).unwrap().iter().map(|r| r.index).collect();
+        assert_eq!(indexes, vec![4, 3]);
+        assert_eq!(db.load_hot_set().unwrap(), vec![(kept, 1)]);
+
+        // Nothing is left to remove.
+        let report = db.run_maintenance(2).unwrap();
+        assert_eq!((report.registrations, report.hot_set), (0, 0));
+    }
+}
@@ -0,0 +1,476 @@
+//! # Write mirroring.
+//! Copies every write to the contracts (bytecode, deltas and state) to a hot standby, so failing over to it
+//! doesn't require a full resync. The writes are mirrored only after they're durable locally, in the order they
+//! happened, to either another local DB or a remote core over the usual `UpdateNewContract`/`UpdateDeltas` IPC.
+//!
+//! The writes wait in a bounded queue until the target took them, and are retried until it does.
+//! In `Async` mode a failing mirror never fails the local write, in `Sync` mode the write returns the mirror's error
+//! (it's still applied locally, and still retried). If the queue is full the write isn't mirrored at all and the
+//! standby needs a full resync, `dropped` in the health response says so.
+//!
+//! The node's own metadata (hot set, registrations, ...) isn't mirrored, and a remote core rebuilds the state from the deltas.
+
+use failure::Error;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use db::{CRUDInterface, DeltaKey, SplitKey, Stype, DB};
+use networking::messages::{IpcDelta, IpcDeltasRange, IpcMessageRequest, IpcRequest, Status};
+
+/// How many writes can wait for the mirror unless configured otherwise.
+pub const DEFAULT_MIRROR_BUFFER: usize = 10_000;
+/// How long the mirror waits before retrying after the target failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a remote target has to answer a write.
+const REMOTE_TIMEOUT_MS: i32 = 5000;
+
+/// Whether a write waits for the mirror.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorMode {
+    Async,
+    Sync,
+}
+
+impl FromStr for MirrorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "async" => Ok(MirrorMode::Async),
+            "sync" => Ok(MirrorMode::Sync),
+            other => Err(format!("Unknown mirror mode: {}, expected async or sync", other)),
+        }
+    }
+}
+
+impl fmt::Display for MirrorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MirrorMode::Async => write!(f, "async"),
+            MirrorMode::Sync => write!(f, "sync"),
+        }
+    }
+}
+
+/// Where the writes are mirrored to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorTarget {
+    /// Another DB on this machine.
+    Directory(PathBuf),
+    /// The IPC endpoint of another core, e.g. `tcp://standby:5552`.
+    Remote(String),
+}
+
+/// A single write to the column family of a contract, as it was done locally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorOp {
+    Put { cf: String, key: Vec<u8>, value: Vec<u8> },
+    Delete { cf: String, key: Vec<u8> },
+    DeleteContract { cf: String },
+}
+
+/// The pending and dropped writes, for the health response.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MirrorStatus {
+    pub pending: usize,
+    pub dropped: u64,
+}
+
+/// Applies the mirrored writes on the standby.
+pub trait MirrorSink: Send {
+    fn apply(&mut self, op: &MirrorOp) -> Result<(), Error>;
+}
+
+/// A key in the column family of a contract as raw bytes, whatever kind of key it was written with.
+#[derive(Debug)]
+struct RawKey<'a> {
+    cf: &'a str,
+    key: &'a [u8],
+}
+
+impl<'a> SplitKey for RawKey<'a> {
+    fn as_split<T, F: FnMut(&str, &[u8]) -> T>(&self, mut f: F) -> T { f(self.cf, self.key) }
+
+    fn from_split(_hash: &str, _key_type: &[u8]) -> Result<Self, Error> { bail!("A RawKey only borrows its parts") }
+}
+
+/// A local standby DB, written through its own write paths so its chain hashes stay correct.
+/// Replaying a write is harmless, so a write that's retried after it was actually applied doesn't break anything.
+impl MirrorSink for DB {
+    fn apply(&mut self, op: &MirrorOp) -> Result<(), Error> {
+        match op {
+            MirrorOp::Put { cf, key, value } => self.force_update(&RawKey { cf, key }, value),
+            MirrorOp::Delete { cf, key } => {
+                if self.read(&RawKey { cf, key }).is_ok() {
+                    self.delete(&RawKey { cf, key })?;
+                }
+                Ok(())
+            }
+            MirrorOp::DeleteContract { cf } => {
+                if self.database.cf_handle(cf).is_some() {
+                    self.delete_contract(&RawKey { cf, key: &[] })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<S: MirrorSink> MirrorSink for Arc<Mutex<S>> {
+    fn apply(&mut self, op: &MirrorOp) -> Result<(), Error> { self.lock().unwrap_or_else(|e| e.into_inner()).apply(op) }
+}
+
+/// Another core, it gets the writes like it would get them from the p2p node.
+pub struct RemoteSink {
+    context: zmq::Context,
+    endpoint: String,
+    socket: Option<zmq::Socket>,
+    next_id: u64,
+}
+
+impl RemoteSink {
+    pub fn new(endpoint: &str) -> Self {
+        RemoteSink { context: zmq::Context::new(), endpoint: endpoint.to_string(), socket: None, next_id: 0 }
+    }
+
+    fn connect(&self) -> Result<zmq::Socket, Error> {
+        let socket = self.context.socket(zmq::REQ)?;
+        socket.set_linger(0)?;
+        socket.set_sndtimeo(REMOTE_TIMEOUT_MS)?;
+        socket.set_rcvtimeo(REMOTE_TIMEOUT_MS)?;
+        socket.connect(&self.endpoint)?;
+        Ok(socket)
+    }
+
+    fn request(&mut self, request: IpcRequest) -> Result<(), Error> {
+        if self.socket.is_none() {
+            self.socket = Some(self.connect()?);
+        }
+        self.next_id += 1;
+        let msg = IpcMessageRequest::from_request(request, format!("mirror-{}", self.next_id));
+        let socket = self.socket.as_ref().unwrap();
+        let reply = socket.send(serde_json::to_vec(&msg)?, 0).and_then(|_| socket.recv_bytes(0));
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => {
+                // A REQ socket that didn't get its reply can't send again, start over on the next attempt.
+                self.socket = None;
+                return Err(e.into());
+            }
+        };
+        let reply: serde_json::Value = serde_json::from_slice(&reply)?;
+        check_reply(msg.request.kind(), &reply)
+    }
+}
+
+/// Fails unless the standby stored every write of the request: the status of the reply and of each of its items must be
+/// a success, or `duplicate` for a write it already had (it's retried until the reply says so).
+/// A standby answering with the legacy statuses can't tell a duplicate from an error, its retries fail until resynced.
+fn check_reply(kind: &str, reply: &serde_json::Value) -> Result<(), Error> {
+    if reply["type"] == "Error" {
+        bail!("The mirror refused {}: {}", kind, reply["msg"]);
+    }
+    let result = &reply["result"];
+    let items = result["errors"].as_array().map(|items| items.iter().map(|item| &item["status"]).collect()).unwrap_or_else(Vec::new);
+    for status in Some(&result["status"]).into_iter().chain(items) {
+        let parsed: Status = serde_json::from_value(status.clone())
+            .map_err(|_| format_err!("The mirror answered {} with an unknown status: {}", kind, status))?;
+        if parsed.is_failure() && parsed != Status::Duplicate {
+            bail!("The mirror failed {}: {}", kind, result);
+        }
+    }
+    Ok(())
+}
+
+impl MirrorSink for RemoteSink {
+    fn apply(&mut self, op: &MirrorOp) -> Result<(), Error> {
+        let request = match op {
+            MirrorOp::Put { cf, key, value } => match DeltaKey::from_split(cf, key)?.key_type {
+                Stype::ByteCode => IpcRequest::UpdateNewContract { address: cf.clone(), bytecode: value.clone() },
+                Stype::Delta(index) => {
+                    let delta = IpcDelta { contract_address: Some(cf.clone()), key: index, data: Some(value.clone()), chain_hash: None, bounds: None };
+                    // The primary already accepted it, the standby stores the same, orphan or not.
+                    IpcRequest::UpdateDeltas { deltas: vec![delta], allow_orphan: true }
+                }
+                Stype::State => return Ok(()),
+            },
+            MirrorOp::Delete { cf, key } => match DeltaKey::from_split(cf, key)?.key_type {
+                Stype::Delta(index) => IpcRequest::RemoveDeltas { input: vec![IpcDeltasRange { address: cf.clone(), from: index, to: index + 1 }] },
+                _ => return Ok(()),
+            },
+            MirrorOp::DeleteContract { cf } => IpcRequest::RemoveContract { address: cf.clone() },
+        };
+        self.request(request)
+    }
+}
+
+/// The queue of writes waiting for the standby, shared between the DB and the retry thread.
+pub struct Mirror {
+    mode: MirrorMode,
+    capacity: usize,
+    queue: Mutex<VecDeque<MirrorOp>>,
+    pending: Condvar,
+    // Only the holder of the sink pops the queue, so the front can't change while it's being applied.
+    sink: Mutex<Box<dyn MirrorSink>>,
+    dropped: AtomicU64,
+}
+
+impl Mirror {
+    pub fn new(sink: Box<dyn MirrorSink>, mode: MirrorMode, capacity: usize) -> Self {
+        Mirror { mode, capacity, queue: Mutex::default(), pending: Condvar::new(), sink: Mutex::new(sink), dropped: AtomicU64::new(0) }
+    }
+
+    /// Opens the sink for `target`.
+    pub fn open(target: &MirrorTarget, mode: MirrorMode, capacity: usize) -> Result<Self, Error> {
+        let sink: Box<dyn MirrorSink> = match target {
+            MirrorTarget::Directory(path) => Box::new(DB::new(path, true)?),
+            MirrorTarget::Remote(endpoint) => Box::new(RemoteSink::new(endpoint)),
+        };
+        Ok(Mirror::new(sink, mode, capacity))
+    }
+
+    fn lock_queue(&self) -> MutexGuard<VecDeque<MirrorOp>> { self.queue.lock().unwrap_or_else(|e| e.into_inner()) }
+
+    pub fn status(&self) -> MirrorStatus {
+        MirrorStatus { pending: self.lock_queue().len(), dropped: self.dropped.load(Ordering::SeqCst) }
+    }
+
+    /// Queues a write that was already done locally, in `Sync` mode also waits until the standby has it.
+    pub fn submit(&self, op: MirrorOp) -> Result<(), Error> {
+        {
+            let mut queue = self.lock_queue();
+            if queue.len() >= self.capacity {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+                error!("The mirror queue is full, dropped a write, the standby needs a full resync");
+                return Ok(());
+            }
+            queue.push_back(op);
+        }
+        self.pending.notify_one();
+        match self.mode {
+            MirrorMode::Async => Ok(()),
+            MirrorMode::Sync => self.flush(),
+        }
+    }
+
+    /// Applies the queued writes in order, stops at the first one the standby fails to take.
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let op = match self.lock_queue().front() {
+                Some(op) => op.clone(),
+                None => return Ok(()),
+            };
+            sink.apply(&op)?;
+            self.lock_queue().pop_front();
+        }
+    }
+
+    /// Starts the thread that keeps retrying the queued writes, it ends when the mirror is dropped.
+    pub fn spawn(mirror: &Arc<Mirror>) -> Result<JoinHandle<()>, Error> {
+        let weak: Weak<Mirror> = Arc::downgrade(mirror);
+        let handle = thread::Builder::new().name("mirror".to_string()).spawn(move || loop {
+            let mirror = match weak.upgrade() {
+                Some(mirror) => mirror,
+                None => return,
+            };
+            {
+                let queue = mirror.lock_queue();
+                if queue.is_empty() {
+                    let _ = mirror.pending.wait_timeout(queue, RETRY_INTERVAL);
+                }
+            }
+            if let Err(e) = mirror.flush() {
+                warn!("Failed mirroring a write, {} pending: {}", mirror.status().pending, e);
+                drop(mirror);
+                thread::sleep(RETRY_INTERVAL);
+            }
+        })?;
+        Ok(handle)
+    }
+}
+
+impl DB {
+    /// Mirrors every following write to the contracts with `mirror`.
+    pub fn set_mirror(&mut self, mirror: Arc<Mirror>) { self.mirror = Some(mirror); }
+
+    /// `None` if the writes aren't mirrored.
+    pub fn mirror_status(&self) -> Option<MirrorStatus> { self.mirror.as_ref().map(|mirror| mirror.status()) }
+
+    /// Called after a write to the contracts is durable.
+    pub(crate) fn mirror_write<F: FnOnce() -> MirrorOp>(&self, op: F) -> Result<(), Error> {
+        match &self.mirror {
+            Some(mirror) => mirror.submit(op()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for MirrorTarget {
+    type Err = String;
+
+    /// `tcp://...` (or any other zmq endpoint) is a remote core, anything else a directory.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains("://") {
+            Ok(MirrorTarget::Remote(s.to_string()))
+        } else if s.is_empty() {
+            Err("The mirror target can't be empty".to_string())
+        } else {
+            Ok(MirrorTarget::Directory(PathBuf::from(s)))
+        }
+    }
+}
+
+impl fmt::Display for MirrorTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MirrorTarget::Directory(path) => write!(f, "{}", path.display()),
+            MirrorTarget::Remote(endpoint) => write!(f, "{}", endpoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, P2PCalls};
+    use enigma_types::ContractAddress;
+    use hex::ToHex;
+    use rocksdb::IteratorMode;
+    use serde_json::json;
+    use std::sync::atomic::AtomicBool;
+
+    // The standby, and a switch to take it down.
+    struct Standby {
+        db: Arc<Mutex<DB>>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl MirrorSink for Standby {
+        fn apply(&mut self, op: &MirrorOp) -> Result<(), Error> {
+            if self.down.load(Ordering::SeqCst) {
+                bail!("The standby is down");
+            }
+            self.db.apply(op)
+        }
+    }
+
+    fn mirrored(mode: MirrorMode, capacity: usize) -> (DB, Arc<Mutex<DB>>, Arc<AtomicBool>, Vec<tempfile::TempDir>) {
+        let (mut primary, primary_dir) = create_test_db();
+        let (standby, standby_dir) = create_test_db();
+        let standby = Arc::new(Mutex::new(standby));
+        let down = Arc::new(AtomicBool::new(false));
+        let sink = Standby { db: Arc::clone(&standby), down: Arc::clone(&down) };
+        primary.set_mirror(Arc::new(Mirror::new(Box::new(sink), mode, capacity)));
+        (primary, standby, down, vec![primary_dir, standby_dir])
+    }
+
+    // Everything stored for the contracts, including the chain hashes.
+    fn contents(db: &DB) -> Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let mut addresses: Vec<String> = db.get_all_addresses().unwrap().iter().map(|a| a.to_hex()).collect();
+        addresses.sort();
+        addresses.into_iter().map(|cf_name| {
+            let cf = db.database.cf_handle(&cf_name).unwrap();
+            let kv = db.database.iterator_cf(cf, IteratorMode::Start).unwrap().map(|(k, v)| (k.to_vec(), v.to_vec())).collect();
+            (cf_name, kv)
+        }).collect()
+    }
+
+    fn flush(db: &DB) -> Result<(), Error> { db.mirror.as_ref().unwrap().flush() }
+
+    #[test]
+    fn test_mirror_converges() {
+        let (mut primary, standby, down, _dirs) = mirrored(MirrorMode::Async, DEFAULT_MIRROR_BUFFER);
+        let a: ContractAddress = [1u8; 32].into();
+        let b: ContractAddress = [2u8; 32].into();
+        primary.create(&DeltaKey::new(a, Stype::ByteCode), &b"code a"[..]).unwrap();
+        primary.create(&DeltaKey::new(b, Stype::ByteCode), &b"code b"[..]).unwrap();
+        let deltas: Vec<_> = (0..3).map(|i| (DeltaKey::new(a, Stype::Delta(i)), vec![i as u8; 8])).collect();
+        for res in primary.insert_tuples(&deltas) {
+            res.unwrap();
+        }
+        primary.force_update(&DeltaKey::new(a, Stype::State), &b"state"[..]).unwrap();
+        flush(&primary).unwrap();
+        assert_eq!(primary.mirror_status(), Some(MirrorStatus { pending: 0, dropped: 0 }));
+        assert_eq!(contents(&standby.lock().unwrap()), contents(&primary));
+
+        // An outage doesn't fail the writes, they wait for the standby to come back.
+        down.store(true, Ordering::SeqCst);
+        primary.create(&DeltaKey::new(a, Stype::Delta(3)), &b"during outage"[..]).unwrap();
+        primary.delete(&DeltaKey::new(a, Stype::Delta(0))).unwrap();
+        primary.delete_contract(&DeltaKey::new(b, Stype::ByteCode)).unwrap();
+        assert!(flush(&primary).is_err());
+        assert_eq!(primary.mirror_status().unwrap().pending, 3);
+        assert_ne!(contents(&standby.lock().unwrap()), contents(&primary));
+
+        down.store(false, Ordering::SeqCst);
+        flush(&primary).unwrap();
+        assert_eq!(primary.mirror_status().unwrap().pending, 0);
+        assert_eq!(contents(&standby.lock().unwrap()), contents(&primary));
+        let standby = standby.lock().unwrap();
+        assert_eq!(standby.get_chain_hash(&a).unwrap(), primary.get_chain_hash(&a).unwrap());
+    }
+
+    #[test]
+    fn test_mirror_sync_and_overflow() {
+        let (mut primary, standby, down, _dirs) = mirrored(MirrorMode::Sync, 2);
+        let address: ContractAddress = [3u8; 32].into();
+        primary.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        // Sync writes are on the standby as soon as they return.
+        assert_eq!(contents(&standby.lock().unwrap()), contents(&primary));
+
+        down.store(true, Ordering::SeqCst);
+        // The write is done locally, but the caller learns the standby doesn't have it.
+        assert!(primary.create(&DeltaKey::new(address, Stype::Delta(0)), &b"0"[..]).is_err());
+        assert_eq!(primary.read(&DeltaKey::new(address, Stype::Delta(0))).unwrap(), b"0".to_vec());
+        assert!(primary.create(&DeltaKey::new(address, Stype::Delta(1)), &b"1"[..]).is_err());
+        // The queue is full, this one is dropped.
+        assert!(primary.create(&DeltaKey::new(address, Stype::Delta(2)), &b"2"[..]).is_ok());
+        assert_eq!(primary.mirror_status(), Some(MirrorStatus { pending: 2, dropped: 1 }));
+
+        down.store(false, Ordering::SeqCst);
+        flush(&primary).unwrap();
+        let standby = standby.lock().unwrap();
+        assert_eq!(standby.read(&DeltaKey::new(address, Stype::Delta(1))).unwrap(), b"1".to_vec());
+        assert!(standby.read(&DeltaKey::new(address, Stype::Delta(2))).is_err());
+    }
+
+    #[test]
+    fn test_check_reply() {
+        let deltas = |overall: &str, item: &str| json!({
+            "id": "mirror-1",
+            "type": "UpdateDeltas",
+            "result": { "status": overall, "errors": [{ "address": "aa", "key": 1, "status": item }] },
+        });
+        assert!(check_reply("UpdateDeltas", &deltas("ok", "ok")).is_ok());
+        assert!(check_reply("UpdateDeltas", &deltas("duplicate", "duplicate")).is_ok());
+        for failure in &["error", "gap", "missing_bytecode", "not_hosted"] {
+            assert!(check_reply("UpdateDeltas", &deltas(failure, failure)).is_err(), "{}", failure);
+        }
+        // Whatever the overall status says.
+        assert!(check_reply("UpdateDeltas", &deltas("ok", "error")).is_err());
+        assert!(check_reply("UpdateDeltas", &deltas("ok", "retry")).is_err());
+
+        let contract = |status: serde_json::Value| json!({ "id": "mirror-2", "type": "UpdateNewContract", "address": "aa", "result": { "status": status } });
+        assert!(check_reply("UpdateNewContract", &contract(json!("ok"))).is_ok());
+        assert!(check_reply("UpdateNewContract", &contract(json!(0))).is_ok());
+        assert!(check_reply("UpdateNewContract", &contract(json!("not_hosted"))).is_err());
+        assert!(check_reply("UpdateNewContract", &contract(json!(-1))).is_err());
+        assert!(check_reply("UpdateNewContract", &json!({ "id": "mirror-3", "type": "UpdateNewContract", "address": "aa" })).is_err());
+        assert!(check_reply("RemoveDeltas", &json!({ "id": "mirror-4", "type": "RemoveDeltas", "result": { "status": "ok", "errors": [{ "address": "aa", "key": 1, "status": "not_found" }] } })).is_ok());
+        assert!(check_reply("UpdateDeltas", &json!({ "id": "mirror-5", "type": "Error", "msg": "Busy" })).is_err());
+    }
+
+    #[test]
+    fn test_mirror_target() {
+        assert_eq!("tcp://standby:5552".parse(), Ok(MirrorTarget::Remote("tcp://standby:5552".to_string())));
+        assert_eq!("/var/enigma/standby".parse(), Ok(MirrorTarget::Directory(PathBuf::from("/var/enigma/standby"))));
+        assert!("".parse::<MirrorTarget>().is_err());
+    }
+}
@@ -1,10 +1,37 @@
+pub mod address_index;
+pub mod capacity;
+pub mod chain_hash;
 pub mod dal;
+pub mod gaps;
+pub mod hosting;
+pub mod hot_set;
 pub mod iterator;
+pub mod key_encoding;
+pub mod maintenance;
+pub mod mirror;
+pub mod orphans;
 pub mod primitives;
+pub mod rebuild;
+pub mod registration_log;
+pub mod sandbox;
+pub mod task_journal;
 
+pub use crate::db::address_index::*;
+pub use crate::db::capacity::*;
+pub use crate::db::chain_hash::*;
 pub use crate::db::dal::*;
+pub use crate::db::gaps::*;
+pub use crate::db::hosting::*;
+pub use crate::db::hot_set::*;
 pub use crate::db::iterator::*;
+pub use crate::db::maintenance::*;
+pub use crate::db::mirror::*;
+pub use crate::db::orphans::*;
 pub use crate::db::primitives::*;
+pub use crate::db::rebuild::*;
+pub use crate::db::registration_log::*;
+pub use crate::db::sandbox::*;
+pub use crate::db::task_journal::*;
 
 
 #[cfg(test)]
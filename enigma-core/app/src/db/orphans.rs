@@ -0,0 +1,180 @@
+//! # Orphan deltas.
+//! A contract's deltas are only meaningful after its bytecode, but the p2p node can get them from its peers
+//! in any order. Deltas stored before the bytecode ("orphans") make the contract look deployed to the tip queries
+//! while it can't be executed, so by default they're refused and the p2p node is told to fetch the contract first.
+//!
+//! Providers that sync the deltas first on purpose can still store them, either per request (`allowOrphan`)
+//! or for the whole node (`--orphan-deltas allow`).
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::DB;
+use db::iterator::P2PCalls;
+use db::primitives::{DeltaKey, SplitKey, Stype};
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do with the deltas of a contract that has no bytecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// Refuse them with `DBErrKind::MissingBytecode`, unless the request allows it.
+    Reject,
+    /// Store them like any other delta.
+    Allow,
+}
+
+impl Default for OrphanPolicy {
+    fn default() -> Self { OrphanPolicy::Reject }
+}
+
+impl FromStr for OrphanPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" => Ok(OrphanPolicy::Reject),
+            "allow" => Ok(OrphanPolicy::Allow),
+            other => Err(format!("Unknown orphan deltas policy: {}, expected reject or allow", other)),
+        }
+    }
+}
+
+impl fmt::Display for OrphanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrphanPolicy::Reject => write!(f, "reject"),
+            OrphanPolicy::Allow => write!(f, "allow"),
+        }
+    }
+}
+
+impl DB {
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) { self.orphan_policy = policy; }
+
+    pub fn orphan_policy(&self) -> OrphanPolicy { self.orphan_policy }
+
+    /// Returns true if the bytecode of the contract is stored, without reading it.
+    pub fn has_bytecode(&self, address: ContractAddress) -> Result<bool, Error> {
+        DeltaKey::new(address, Stype::ByteCode).as_split(|cf_str, key| -> Result<bool, Error> {
+            match self.database.cf_handle(cf_str) {
+                Some(cf) => Ok(self.database.get_cf(cf, key)?.is_some()),
+                None => Ok(false),
+            }
+        })
+    }
+
+    /// Returns true if the contract has deltas but no bytecode.
+    pub fn is_orphan(&self, address: ContractAddress) -> Result<bool, Error> {
+        Ok(!self.has_bytecode(address)? && self.get_tip::<DeltaKey>(&address).is_ok())
+    }
+
+    /// Stores the deltas like `insert_tuples`, with a result for every one of them, in the same order.
+    /// Unless `allow_orphan` is set or the policy is `Allow`, the deltas of contracts without bytecode
+    /// fail with `DBErrKind::MissingBytecode` and the others are still stored.
+    pub fn insert_deltas(&mut self, deltas: &[(DeltaKey, Vec<u8>)], allow_orphan: bool) -> Vec<Result<(), Error>> {
+        if allow_orphan || self.orphan_policy == OrphanPolicy::Allow {
+            return self.insert_tuples(deltas);
+        }
+        let mut deployed = HashMap::new();
+        let mut accepted = Vec::with_capacity(deltas.len());
+        let mut results: Vec<Option<Result<(), Error>>> = Vec::with_capacity(deltas.len());
+        for (key, data) in deltas {
+            let address = key.contract_address;
+            let has_bytecode = match deployed.get(&address) {
+                Some(has_bytecode) => *has_bytecode,
+                None => match self.has_bytecode(address) {
+                    Ok(has_bytecode) => *deployed.entry(address).or_insert(has_bytecode),
+                    Err(e) => {
+                        results.push(Some(Err(e)));
+                        continue;
+                    }
+                },
+            };
+            if has_bytecode {
+                accepted.push((*key, data.as_slice()));
+                results.push(None);
+            } else {
+                let kind = DBErrKind::MissingBytecode(address.to_hex());
+                results.push(Some(Err(DBErr { command: "insert_deltas".to_string(), kind }.into())));
+            }
+        }
+
+        let mut inserted = self.insert_tuples(&accepted);
+        // A failed batch comes back as a single error, every delta of it failed.
+        if inserted.len() != accepted.len() {
+            if let Some(Err(e)) = inserted.pop() {
+                warn!("Failed storing a batch of {} deltas: {}", accepted.len(), e);
+            }
+            inserted = accepted.iter().map(|_| Err(DBErr { command: "insert_deltas".to_string(), kind: DBErrKind::UpdateError }.into())).collect();
+        }
+        let mut inserted = inserted.into_iter();
+        results.into_iter().map(|res| res.unwrap_or_else(|| inserted.next().unwrap())).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface};
+
+    fn deltas(address: ContractAddress, keys: &[u32]) -> Vec<(DeltaKey, Vec<u8>)> {
+        keys.iter().map(|&key| (DeltaKey::new(address, Stype::Delta(key)), vec![key as u8; 4])).collect()
+    }
+
+    fn missing_bytecode(res: &Result<(), Error>) -> bool {
+        match res {
+            Err(e) => match e.downcast_ref::<DBErr>() {
+                Some(DBErr { kind: DBErrKind::MissingBytecode(_), .. }) => true,
+                _ => false,
+            },
+            Ok(()) => false,
+        }
+    }
+
+    #[test]
+    fn test_rejects_deltas_without_bytecode() {
+        let (mut db, _dir) = create_test_db();
+        let deployed: ContractAddress = [1u8; 32].into();
+        let orphan: ContractAddress = [2u8; 32].into();
+        db.create(&DeltaKey::new(deployed, Stype::ByteCode), &b"code"[..]).unwrap();
+
+        let mut batch = deltas(deployed, &[0]);
+        batch.extend(deltas(orphan, &[0, 1]));
+        batch.extend(deltas(deployed, &[1]));
+        let results = db.insert_deltas(&batch, false);
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok() && results[3].is_ok());
+        assert!(missing_bytecode(&results[1]) && missing_bytecode(&results[2]));
+        assert_eq!(db.get_tip::<DeltaKey>(&deployed).unwrap().0, DeltaKey::new(deployed, Stype::Delta(1)));
+        assert!(db.get_tip::<DeltaKey>(&orphan).is_err());
+        assert!(!db.is_orphan(orphan).unwrap());
+    }
+
+    #[test]
+    fn test_allow_orphan() {
+        let (mut db, _dir) = create_test_db();
+        let orphan: ContractAddress = [3u8; 32].into();
+
+        assert!(db.insert_deltas(&deltas(orphan, &[0]), true).iter().all(Result::is_ok));
+        assert!(db.is_orphan(orphan).unwrap());
+
+        db.set_orphan_policy(OrphanPolicy::Allow);
+        assert!(db.insert_deltas(&deltas(orphan, &[1]), false).iter().all(Result::is_ok));
+        assert_eq!(db.get_tip::<DeltaKey>(&orphan).unwrap().0, DeltaKey::new(orphan, Stype::Delta(1)));
+
+        // Once the bytecode came it's a regular contract.
+        db.create(&DeltaKey::new(orphan, Stype::ByteCode), &b"code"[..]).unwrap();
+        assert!(!db.is_orphan(orphan).unwrap());
+    }
+
+    #[test]
+    fn test_orphan_policy_from_str() {
+        assert_eq!("Allow".parse::<OrphanPolicy>().unwrap(), OrphanPolicy::Allow);
+        assert_eq!(" reject".parse::<OrphanPolicy>().unwrap(), OrphanPolicy::Reject);
+        assert!("drop".parse::<OrphanPolicy>().is_err());
+    }
+}
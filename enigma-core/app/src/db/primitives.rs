@@ -1,3 +1,4 @@
+use db::key_encoding::{self, STATE_TAG};
 use enigma_types::ContractAddress;
 use failure::Error;
 use hex::{FromHex, ToHex};
@@ -60,33 +61,14 @@ impl DeltaKey {
 }
 
 impl SplitKey for DeltaKey {
+    // The layout is defined in `db::key_encoding`.
     fn as_split<T, F: FnMut(&str, &[u8]) -> T>(&self, mut f: F) -> T {
-        // converts the [u8; 32] to a str.
-        let cf = &self.contract_address.to_hex();
-        let mut key = Vec::new();
-        match &self.key_type {
-            Stype::Delta(num) => {
-                key.push(1); //type
-                             // TODO: think of a better way to handle the possibility of error here.
-                key.extend_from_slice(&num.to_be_bytes());
-            }
-            Stype::State => key.push(2),    //type
-            Stype::ByteCode => key.push(3), //type
-        }
-        f(&cf, &key)
+        let cf = key_encoding::cf_name(&self.contract_address);
+        f(&cf, &key_encoding::encode_index_key(self.key_type))
     }
 
     fn from_split(_hash: &str, _key_type: &[u8]) -> Result<Self, Error> {
-        let key_type = match _key_type[0] {
-            1 => {
-                let mut be_bytes = [0u8; 4];
-                be_bytes.copy_from_slice(&_key_type[1..]);
-                Stype::Delta(u32::from_be_bytes(be_bytes))
-            },
-            2 => Stype::State,
-            3 => Stype::ByteCode,
-            _ => bail!("Failed parsing the Key, key does not contain a correct index"),
-        };
+        let key_type = key_encoding::decode_index_key(_key_type)?;
         // if the address is not a correct hex then it not a correct address.
         let contract_address = ContractAddress::from_hex(&_hash)?;
         Ok(DeltaKey { contract_address, key_type })
@@ -94,7 +76,7 @@ impl SplitKey for DeltaKey {
 }
 
 impl SplitKey for Array32u8 {
-    fn as_split<T, F: FnMut(&str, &[u8]) -> T>(&self, mut f: F) -> T { f(&self.0.to_hex(), &[2]) }
+    fn as_split<T, F: FnMut(&str, &[u8]) -> T>(&self, mut f: F) -> T { f(&self.0.to_hex(), &[STATE_TAG]) }
 
     fn from_split(_hash: &str, _key_type: &[u8]) -> Result<Self, Error> {
         let hex: Vec<u8> = _hash.from_hex()?;
@@ -0,0 +1,350 @@
+//! # Rebuilding the derived structures.
+//! Next to the contracts the DB keeps what's derived from them, and a restore from a partial backup can leave the two
+//! disagreeing. A rebuild scans the raw keys under a snapshot and regenerates the structures of the selected scopes:
+//! - `addressIndex`: the address index and its count of contracts, from the column families,
+//! - `chainHashes`: the stored chain hash of every contract, from its deltas,
+//! - `gapIndex`: the usable tips kept in memory, from the deltas,
+//! - `hotSet`: the persisted hot set and the execution counts, without the contracts that aren't stored
+//!   (a persisted hot set that can't be read is removed).
+//!
+//! What's written to the DB goes in one batch, and the structures in memory are only replaced once it's written,
+//! so a rebuild that fails changes nothing. It must not race the writes: on a running core the `RebuildIndexes` request
+//! is refused until the core is drained (see [`drain`](../../common_u/drain/index.html)), `db rebuild` runs on a stopped one.
+//! On a large DB the scan takes a while, `GetDrainStatus` reports how many contracts it went through.
+
+use failure::Error;
+use rocksdb::DB as rocks_db;
+use rocksdb::{Direction, IteratorMode, WriteBatch, WriteOptions};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use db::address_index::DEFAULT_CF;
+use db::chain_hash::{chain_of, ChainHash};
+use db::dal::{DB, HOT_SET_KEY, SYNC};
+use db::gaps::contiguous_run;
+use db::hot_set::{decode_hot_set, encode_hot_set};
+use db::key_encoding::{self, CHAIN_HASH_KEY, DELTA_PREFIX};
+use common_u::panics::LockRecover;
+
+pub static REBUILD: RebuildState = RebuildState::new();
+
+/// A derived structure a rebuild can regenerate.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum RebuildScope {
+    AddressIndex,
+    ChainHashes,
+    GapIndex,
+    HotSet,
+}
+
+impl RebuildScope {
+    pub const ALL: [RebuildScope; 4] = [RebuildScope::AddressIndex, RebuildScope::ChainHashes, RebuildScope::GapIndex, RebuildScope::HotSet];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RebuildScope::AddressIndex => "addressIndex",
+            RebuildScope::ChainHashes => "chainHashes",
+            RebuildScope::GapIndex => "gapIndex",
+            RebuildScope::HotSet => "hotSet",
+        }
+    }
+}
+
+impl FromStr for RebuildScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RebuildScope::ALL.iter().cloned().find(|scope| scope.name() == s.trim()).ok_or_else(|| {
+            format!("Unknown rebuild scope: {}, expected addressIndex, chainHashes, gapIndex or hotSet", s)
+        })
+    }
+}
+
+impl fmt::Display for RebuildScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.name()) }
+}
+
+/// What a rebuild fixed: the entries that disagreed with the raw data in every scope, `None` if it wasn't selected.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RebuildReport {
+    /// The contracts scanned.
+    pub contracts: usize,
+    pub address_index: Option<usize>,
+    pub chain_hashes: Option<usize>,
+    pub gap_index: Option<usize>,
+    pub hot_set: Option<usize>,
+}
+
+impl RebuildReport {
+    pub fn fixed_in(&self, scope: RebuildScope) -> Option<usize> {
+        match scope {
+            RebuildScope::AddressIndex => self.address_index,
+            RebuildScope::ChainHashes => self.chain_hashes,
+            RebuildScope::GapIndex => self.gap_index,
+            RebuildScope::HotSet => self.hot_set,
+        }
+    }
+
+    /// The fixed entries of the selected scopes, by name.
+    pub fn fixed(&self) -> Vec<(&'static str, usize)> {
+        RebuildScope::ALL.iter().filter_map(|scope| self.fixed_in(*scope).map(|fixed| (scope.name(), fixed))).collect()
+    }
+}
+
+/// How far the running rebuild got.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebuildProgress {
+    pub scanned: usize,
+    pub contracts: usize,
+}
+
+/// The progress of a rebuild, readable from another thread while it runs.
+#[derive(Debug)]
+pub struct RebuildState {
+    running: AtomicBool,
+    scanned: AtomicUsize,
+    contracts: AtomicUsize,
+}
+
+impl RebuildState {
+    pub const fn new() -> Self {
+        RebuildState { running: AtomicBool::new(false), scanned: AtomicUsize::new(0), contracts: AtomicUsize::new(0) }
+    }
+
+    /// `None` unless a rebuild is running.
+    pub fn progress(&self) -> Option<RebuildProgress> {
+        if !self.running.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(RebuildProgress { scanned: self.scanned.load(Ordering::SeqCst), contracts: self.contracts.load(Ordering::SeqCst) })
+    }
+
+    fn start(&self, contracts: usize) {
+        self.scanned.store(0, Ordering::SeqCst);
+        self.contracts.store(contracts, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    fn finish(&self) { self.running.store(false, Ordering::SeqCst); }
+}
+
+impl DB {
+    /// Regenerates the structures of `scopes` from the raw data, reporting the progress to `progress`.
+    pub fn rebuild_indexes(&self, scopes: &[RebuildScope], progress: &RebuildState) -> Result<RebuildReport, Error> {
+        let report = self.rebuild(scopes, progress);
+        progress.finish();
+        report
+    }
+
+    /// Drops what a handler that panicked may have left half updated in memory: the address and gap indexes are rebuilt,
+    /// the cached bytecode is dropped, and the state is built again in the enclave on the next task.
+    pub fn reset_after_panic(&mut self) -> Result<RebuildReport, Error> {
+        self.contracts.clear();
+        self.update_state_status(false);
+        self.rebuild_indexes(&[RebuildScope::AddressIndex, RebuildScope::GapIndex], &RebuildState::new())
+    }
+
+    fn rebuild(&self, scopes: &[RebuildScope], progress: &RebuildState) -> Result<RebuildReport, Error> {
+        let selected = |scope| scopes.contains(&scope);
+        let names = rocks_db::list_cf(&self.options, &self.location)?;
+        let contracts: Vec<&String> = names.iter().filter(|name| name.as_str() != DEFAULT_CF).collect();
+        progress.start(contracts.len());
+        let mut report = RebuildReport { contracts: contracts.len(), ..RebuildReport::default() };
+        let mut chain_hashes = 0;
+
+        let snapshot = self.database.snapshot();
+        let mut batch = WriteBatch::default();
+        let mut usable = HashMap::new();
+        for name in &contracts {
+            let cf = self.database.cf_handle(name)
+                .ok_or_else(|| format_err!("The column family {} is on disk but wasn't opened", name))?;
+            if selected(RebuildScope::ChainHashes) {
+                let deltas = snapshot.iterator_cf(cf, IteratorMode::From(DELTA_PREFIX, Direction::Forward))?
+                    .take_while(|(key, _)| key.starts_with(DELTA_PREFIX));
+                let chain = chain_of(deltas)?;
+                // A missing chain hash is recomputed by the reads, only a wrong one needs fixing.
+                if let Some(stored) = snapshot.get_cf(cf, CHAIN_HASH_KEY)? {
+                    let stored = ChainHash::from_bytes(&stored);
+                    if stored.is_none() || stored != chain {
+                        match chain {
+                            Some(chain) => batch.put_cf(cf, CHAIN_HASH_KEY, &chain.to_bytes())?,
+                            None => batch.delete_cf(cf, CHAIN_HASH_KEY)?,
+                        }
+                        chain_hashes += 1;
+                    }
+                }
+            }
+            if selected(RebuildScope::GapIndex) {
+                let keys = snapshot.iterator_cf(cf, IteratorMode::From(DELTA_PREFIX, Direction::Forward))?.map(|(key, _)| key);
+                usable.insert(name.to_string(), contiguous_run(keys, 0));
+            }
+            progress.scanned.fetch_add(1, Ordering::SeqCst);
+        }
+        if selected(RebuildScope::ChainHashes) {
+            report.chain_hashes = Some(chain_hashes);
+        }
+
+        let stored: HashSet<&str> = contracts.iter().map(|name| name.as_str()).collect();
+        let mut removed_hot = Vec::new();
+        if selected(RebuildScope::HotSet) {
+            let mut hot_fixed = 0;
+            if let Some(value) = snapshot.get(HOT_SET_KEY)? {
+                match decode_hot_set(&value) {
+                    Some(entries) => {
+                        let kept: Vec<_> = entries.iter().cloned().filter(|(address, _)| stored.contains(key_encoding::cf_name(address).as_str())).collect();
+                        if kept.len() != entries.len() {
+                            hot_fixed += entries.len() - kept.len();
+                            batch.put(HOT_SET_KEY, &encode_hot_set(&kept))?;
+                        }
+                    }
+                    None => {
+                        hot_fixed += 1;
+                        batch.delete(HOT_SET_KEY)?;
+                    }
+                }
+            }
+            removed_hot = self.contracts.tracked().into_iter().filter(|address| !stored.contains(key_encoding::cf_name(address).as_str())).collect();
+            hot_fixed += removed_hot.len();
+            report.hot_set = Some(hot_fixed);
+        }
+        drop(snapshot);
+
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.write_opt(batch, &write_options)?;
+
+        // The batch is written, the structures in memory can follow.
+        for address in &removed_hot {
+            self.contracts.remove(address);
+        }
+        if selected(RebuildScope::GapIndex) {
+            let mut cached = self.gaps.usable.lock_recover("Gap index");
+            let stale = cached.iter().filter(|(name, tip)| usable.get(*name) != Some(*tip)).count();
+            report.gap_index = Some(stale);
+            *cached = usable;
+        }
+        if selected(RebuildScope::AddressIndex) {
+            report.address_index = Some(self.known.repair(&names));
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, P2PCalls, Stype};
+    use enigma_types::ContractAddress;
+
+    // The usable tip by reading every delta from 0, like the gap index tests do.
+    fn scanned(db: &DB, address: ContractAddress) -> Option<u32> {
+        let mut tip = None;
+        while db.get_delta(DeltaKey::new(address, Stype::Delta(tip.map_or(0, |tip: u32| tip + 1)))).is_ok() {
+            tip = Some(tip.map_or(0, |tip| tip + 1));
+        }
+        tip
+    }
+
+    // What the tests of every structure check against the raw data.
+    fn assert_consistent(db: &DB, addresses: &[ContractAddress]) {
+        assert_eq!(db.address_index().stats().addresses, db.get_all_addresses().unwrap().len() as u64);
+        for address in addresses {
+            assert!(db.may_hold(&key_encoding::cf_name(address)));
+            assert_eq!(db.get_chain_hash(address).unwrap(), db.compute_chain_hash(address).unwrap());
+            assert_eq!(db.get_usable_tip(address).unwrap(), scanned(db, *address));
+        }
+        let stored = db.get_all_addresses().unwrap();
+        assert!(db.load_hot_set().unwrap().iter().all(|(address, _)| stored.contains(address)));
+        assert!(db.contract_cache().hot_set().iter().all(|(address, _)| stored.contains(address)));
+    }
+
+    fn setup(db: &mut DB) -> Vec<ContractAddress> {
+        let addresses: Vec<ContractAddress> = (1..4u8).map(|i| [i; 32].into()).collect();
+        for address in &addresses {
+            db.create(&DeltaKey::new(*address, Stype::ByteCode), &b"code"[..]).unwrap();
+            for key in &[0, 1, 3] {
+                db.create(&DeltaKey::new(*address, Stype::Delta(*key)), &[*key as u8][..]).unwrap();
+            }
+            db.get_usable_tip(address).unwrap();
+            db.record_execution(*address);
+        }
+        db.save_hot_set().unwrap();
+        addresses
+    }
+
+    #[test]
+    fn test_rebuild_fixes_every_scope() {
+        let (mut db, _dir) = create_test_db();
+        let addresses = setup(&mut db);
+        let progress = RebuildState::new();
+        let report = db.rebuild_indexes(&RebuildScope::ALL, &progress).unwrap();
+        assert_eq!(report.contracts, 3);
+        assert!(report.fixed().iter().all(|(_, fixed)| *fixed == 0), "{:?}", report);
+        assert_eq!(report.fixed().len(), 4);
+        assert_eq!(progress.progress(), None);
+
+        // The address index lost a contract and holds one that isn't stored.
+        db.known.rebuild(&[key_encoding::cf_name(&addresses[1])], None);
+        db.index_cf(&"ff".repeat(32));
+        // A wrong chain hash, and one that can't be decoded.
+        let (first, second) = (key_encoding::cf_name(&addresses[0]), key_encoding::cf_name(&addresses[1]));
+        let bogus = ChainHash { hash: [7u8; 32].into(), tip: 1 };
+        db.database.put_cf(db.database.cf_handle(&first).unwrap(), CHAIN_HASH_KEY, &bogus.to_bytes()).unwrap();
+        db.database.put_cf(db.database.cf_handle(&second).unwrap(), CHAIN_HASH_KEY, b"garbage").unwrap();
+        // Usable tips past the gap, and one of a contract that isn't stored.
+        {
+            let mut usable = db.gaps.usable.lock_recover("Gap index");
+            usable.insert(first.clone(), Some(3));
+            usable.insert("ee".repeat(32), Some(0));
+        }
+        // A persisted hot set with a contract that isn't stored.
+        let mut hot_set = db.load_hot_set().unwrap();
+        hot_set.push(([9u8; 32].into(), 5));
+        db.database.put(HOT_SET_KEY, &encode_hot_set(&hot_set)).unwrap();
+
+        let report = db.rebuild_indexes(&RebuildScope::ALL, &progress).unwrap();
+        assert_eq!(report.fixed(), vec![("addressIndex", 3), ("chainHashes", 2), ("gapIndex", 2), ("hotSet", 1)]);
+        assert_consistent(&db, &addresses);
+        assert!(!db.may_hold(&"ff".repeat(32)));
+
+        // Everything agrees after one pass.
+        let report = db.rebuild_indexes(&RebuildScope::ALL, &progress).unwrap();
+        assert!(report.fixed().iter().all(|(_, fixed)| *fixed == 0), "{:?}", report);
+    }
+
+    #[test]
+    fn test_rebuild_selected_scopes() {
+        let (mut db, _dir) = create_test_db();
+        let addresses = setup(&mut db);
+        let name = key_encoding::cf_name(&addresses[0]);
+        db.database.put_cf(db.database.cf_handle(&name).unwrap(), CHAIN_HASH_KEY, b"garbage").unwrap();
+        db.gaps.usable.lock_recover("Gap index").insert(name.clone(), Some(3));
+        // An unreadable hot set, and a tracked contract that was removed behind the core's back.
+        db.database.put(HOT_SET_KEY, b"garbage").unwrap();
+        db.record_execution([8u8; 32].into());
+
+        let report = db.rebuild_indexes(&[RebuildScope::ChainHashes, RebuildScope::HotSet], &RebuildState::new()).unwrap();
+        assert_eq!(report.fixed(), vec![("chainHashes", 1), ("hotSet", 2)]);
+        assert_eq!(report.gap_index, None);
+        assert_eq!(db.get_chain_hash(&addresses[0]).unwrap(), db.compute_chain_hash(&addresses[0]).unwrap());
+        assert_eq!(db.load_hot_set().unwrap(), Vec::new());
+        // The gap index wasn't selected.
+        assert_eq!(db.get_usable_tip(&addresses[0]).unwrap(), Some(3));
+
+        db.rebuild_indexes(&[RebuildScope::GapIndex], &RebuildState::new()).unwrap();
+        assert_consistent(&db, &addresses);
+    }
+
+    #[test]
+    fn test_scope_names() {
+        for scope in &RebuildScope::ALL {
+            assert_eq!(scope.to_string().parse::<RebuildScope>().unwrap(), *scope);
+            assert_eq!(serde_json::to_value(scope).unwrap(), scope.name());
+        }
+        assert!("addresses".parse::<RebuildScope>().is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! # Registration log.
+//! Every registration the core handed out, with the exact report and signature that were submitted on chain,
+//! so a registration can still be audited long after the worker registered.
+//!
+//! The records are kept in the default column family under `REGISTRATION_PREFIX` followed by a big endian sequence
+//! number. They can only be appended, never updated, the only other write is dropping the oldest ones past the cap.
+
+use failure::Error;
+use rocksdb::{WriteBatch, WriteOptions};
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::{DB, SYNC};
+
+/// How many registrations are kept unless configured otherwise.
+pub const DEFAULT_REGISTRATION_LOG_CAP: usize = 100;
+const REGISTRATION_PREFIX: &[u8] = b"registration";
+
+/// A registration as it was returned by `GetRegistrationParams`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationRecord {
+    /// The position of the record in the log, set when it's appended.
+    #[serde(default)]
+    pub index: u64,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub signing_key: String,
+    pub report: String,
+    pub signature: String,
+    pub mr_enclave: String,
+}
+
+fn record_key(index: u64) -> Vec<u8> {
+    let mut key = REGISTRATION_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn fetch_error() -> DBErr { DBErr { command: "get_registrations".to_string(), kind: DBErrKind::FetchError } }
+
+impl DB {
+    /// Appends a registration to the log and drops the oldest ones so at most `cap` are kept.
+    /// Returns the index the record was stored under.
+    pub fn append_registration(&self, mut record: RegistrationRecord, cap: usize) -> Result<u64, Error> {
+        let existing = self.registration_keys()?;
+        let index = existing.last().map_or(0, |last| last + 1);
+        record.index = index;
+        if cap == 0 {
+            return Ok(index);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.put(&record_key(index), &serde_json::to_vec(&record)?)?;
+        let excess = (existing.len() + 1).saturating_sub(cap);
+        for old in &existing[..excess] {
+            batch.delete(&record_key(*old))?;
+        }
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.write_opt(batch, &write_options)?;
+        Ok(index)
+    }
+
+    /// Drops the oldest registrations so at most `cap` are kept, for when the retention was lowered since they were appended.
+    /// Returns how many were dropped.
+    pub fn prune_registrations(&self, cap: usize) -> Result<usize, Error> {
+        let existing = self.registration_keys()?;
+        let excess = existing.len().saturating_sub(cap);
+        if excess == 0 {
+            return Ok(0);
+        }
+        let mut batch = WriteBatch::default();
+        for old in &existing[..excess] {
+            batch.delete(&record_key(*old))?;
+        }
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.write_opt(batch, &write_options)?;
+        Ok(excess)
+    }
+
+    /// Returns up to `limit` registrations (all of them if `None`), newest first.
+    pub fn get_registrations(&self, limit: Option<usize>) -> Result<Vec<RegistrationRecord>, Error> {
+        let mut records = Vec::new();
+        for (key, value) in self.database.prefix_iterator(REGISTRATION_PREFIX) {
+            if !key.starts_with(REGISTRATION_PREFIX) {
+                continue;
+            }
+            records.push(serde_json::from_slice::<RegistrationRecord>(&value).map_err(|_| fetch_error())?);
+        }
+        records.reverse();
+        records.truncate(limit.unwrap_or(records.len()));
+        Ok(records)
+    }
+
+    // The indexes of the stored records, oldest first.
+    fn registration_keys(&self) -> Result<Vec<u64>, Error> {
+        let mut indexes = Vec::new();
+        for (key, _) in self.database.prefix_iterator(REGISTRATION_PREFIX) {
+            if !key.starts_with(REGISTRATION_PREFIX) {
+                continue;
+            }
+            if key.len() != REGISTRATION_PREFIX.len() + 8 {
+                return Err(fetch_error().into());
+            }
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key[REGISTRATION_PREFIX.len()..]);
+            indexes.push(u64::from_be_bytes(index));
+        }
+        Ok(indexes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::tests::create_test_db;
+
+    fn record(n: u64) -> RegistrationRecord {
+        RegistrationRecord {
+            index: 0,
+            timestamp: 1_500_000_000 + n,
+            signing_key: format!("{:040x}", n),
+            report: format!("report{}", n),
+            signature: format!("signature{}", n),
+            mr_enclave: format!("{:064x}", n),
+        }
+    }
+
+    #[test]
+    fn test_registration_log_order() {
+        let (db, _dir) = create_test_db();
+        assert!(db.get_registrations(None).unwrap().is_empty());
+        assert_eq!(db.append_registration(record(1), 10).unwrap(), 0);
+        assert_eq!(db.append_registration(record(2), 10).unwrap(), 1);
+
+        let records = db.get_registrations(None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], RegistrationRecord { index: 1, ..record(2) });
+        assert_eq!(records[1], RegistrationRecord { index: 0, ..record(1) });
+        assert_eq!(db.get_registrations(Some(1)).unwrap(), vec![records[0].clone()]);
+    }
+
+    #[test]
+    fn test_registration_log_cap() {
+        let (db, _dir) = create_test_db();
+        for n in 0..5 {
+            db.append_registration(record(n), 3).unwrap();
+        }
+        let indexes: Vec<u64> = db.get_registrations(None).unwrap().iter().map(|r| r.index).collect();
+        assert_eq!(indexes, vec![4, 3, 2]);
+        // The indexes keep growing after the oldest were dropped.
+        assert_eq!(db.append_registration(record(5), 3).unwrap(), 5);
+    }
+}
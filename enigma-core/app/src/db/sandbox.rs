@@ -0,0 +1,108 @@
+//! # Sandbox.
+//! A throwaway copy of a contract as it was at some delta, for `ReplayTask`. The bytecode and the deltas up to the
+//! tip are copied to a new DB in the temporary directory, the enclave builds the state from it and executes the task
+//! again there, so nothing it does reaches the node's own DB. The directory is removed when the sandbox is dropped.
+
+use failure::Error;
+use std::env;
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::{CRUDInterface, DeltaKey, P2PCalls, ResultType, Stype, DB};
+use enigma_types::ContractAddress;
+use hex::ToHex;
+
+// Tells apart the sandboxes of the same process.
+static SANDBOX_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Sandbox {
+    // Only `None` while it's dropped.
+    db: Option<DB>,
+    dir: PathBuf,
+}
+
+impl Deref for Sandbox {
+    type Target = DB;
+    fn deref(&self) -> &DB { self.db.as_ref().expect("The sandbox is alive") }
+}
+
+impl DerefMut for Sandbox {
+    fn deref_mut(&mut self) -> &mut DB { self.db.as_mut().expect("The sandbox is alive") }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        // RocksDB has to let go of its files first.
+        self.db.take();
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn!("Failed removing the sandbox at {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+impl DB {
+    /// Copies the bytecode of `address` and its deltas up to `tip` (included) to a new sandbox,
+    /// `None` copies only the bytecode.
+    pub fn sandbox(&self, address: ContractAddress, tip: Option<u32>) -> Result<Sandbox, Error> {
+        let bytecode = self.get_contract(address)?;
+        let deltas = match tip {
+            Some(tip) => {
+                let from = DeltaKey::new(address, Stype::Delta(0));
+                let to = DeltaKey::new(address, Stype::Delta(tip + 1));
+                match self.get_deltas(from, to)? {
+                    ResultType::Full(deltas) | ResultType::Partial(deltas) => deltas,
+                    ResultType::None => Vec::new(),
+                }
+            }
+            None => Vec::new(),
+        };
+        let expected = tip.map_or(0, |tip| tip as usize + 1);
+        if deltas.len() != expected {
+            let missing = format!("{} (deltas 0 to {:?}, found {})", address.to_hex(), tip, deltas.len());
+            return Err(DBErr { command: "sandbox".to_string(), kind: DBErrKind::MissingKey(missing) }.into());
+        }
+
+        let counter = SANDBOX_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir().join(format!("enigma-sandbox-{}-{}", process::id(), counter));
+        let mut sandbox = Sandbox { db: Some(DB::new(&dir, true)?), dir };
+        sandbox.create(&DeltaKey::new(address, Stype::ByteCode), &bytecode[..])?;
+        for res in sandbox.insert_tuples(&deltas) {
+            res?;
+        }
+        Ok(sandbox)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::tests::create_test_db;
+
+    #[test]
+    fn test_sandbox_copies_up_to_tip() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        for i in 0..3u32 {
+            db.create(&DeltaKey::new(address, Stype::Delta(i)), &[i as u8][..]).unwrap();
+        }
+
+        let mut sandbox = db.sandbox(address, Some(1)).unwrap();
+        let path = sandbox.dir.clone();
+        assert_eq!(sandbox.get_contract(address).unwrap(), b"code".to_vec());
+        let (tip, _): (DeltaKey, Vec<u8>) = sandbox.get_tip(&address).unwrap();
+        assert_eq!(tip.key_type, Stype::Delta(1));
+        // Writing to the sandbox leaves the DB alone.
+        sandbox.create(&DeltaKey::new(address, Stype::Delta(5)), &[5u8][..]).unwrap();
+        assert!(db.read(&DeltaKey::new(address, Stype::Delta(5))).is_err());
+        drop(sandbox);
+        assert!(!path.exists());
+
+        assert!(db.sandbox(address, Some(3)).is_err());
+        assert!(db.sandbox([4u8; 32].into(), None).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! # Task journal.
+//! What's needed to execute a compute task again for `ReplayTask`: the encrypted inputs as they were received with the
+//! IO key they were encrypted with, sealed by the enclave, the key of the contract's last delta when the task ran, and
+//! the receipt the enclave signed for it with its signature.
+//!
+//! Only the tasks sent with a `taskId` are journaled. The entries are kept in the default column family under
+//! `TASK_JOURNAL_PREFIX` followed by a big endian sequence number, like the [`registration_log`](../registration_log/index.html),
+//! and the oldest are dropped past the cap.
+
+use failure::Error;
+use rocksdb::{WriteBatch, WriteOptions};
+
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::{DB, SYNC};
+
+/// How many tasks are kept in the journal.
+pub const DEFAULT_TASK_JOURNAL_CAP: usize = 1000;
+const TASK_JOURNAL_PREFIX: &[u8] = b"task_journal";
+
+/// A successful compute task, everything in hex as it was on the wire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct JournaledTask {
+    /// The position of the entry in the journal, set when it's appended.
+    #[serde(default)]
+    pub index: u64,
+    pub task_id: String,
+    pub address: String,
+    /// The key of the contract's last delta when the task ran, `None` if it had none.
+    pub tip: Option<u32>,
+    pub callable: String,
+    pub args: String,
+    pub user_pubkey: String,
+    pub gas_limit: u64,
+    /// The encrypted output, `output_hash` is its hash.
+    pub output: String,
+    // The receipt.
+    pub inputs_hash: String,
+    pub exe_code_hash: String,
+    pub prev_delta_hash: String,
+    pub delta_hash: String,
+    pub output_hash: String,
+    pub used_gas: u64,
+    // Empty in the entries journaled before they were, those tasks can't be replayed.
+    #[serde(default)]
+    pub ethereum_payload: String,
+    #[serde(default)]
+    pub ethereum_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelope_hash: Option<String>,
+    /// `r || s || v` with `v` 27/28, the enclave only replays a task whose receipt it signed.
+    #[serde(default)]
+    pub signature: String,
+    /// Only the enclave can open it, see `WasmTaskResult::sealed_io_key`.
+    #[serde(default)]
+    pub sealed_io_key: String,
+}
+
+fn entry_key(index: u64) -> Vec<u8> {
+    let mut key = TASK_JOURNAL_PREFIX.to_vec();
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+fn fetch_error() -> DBErr { DBErr { command: "get_journaled_task".to_string(), kind: DBErrKind::FetchError } }
+
+impl DB {
+    /// Appends a task to the journal and drops the oldest ones so at most `cap` are kept.
+    pub fn journal_task(&self, mut task: JournaledTask, cap: usize) -> Result<u64, Error> {
+        let existing = self.journal_keys()?;
+        let index = existing.last().map_or(0, |last| last + 1);
+        task.index = index;
+        if cap == 0 {
+            return Ok(index);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.put(&entry_key(index), &serde_json::to_vec(&task)?)?;
+        let excess = (existing.len() + 1).saturating_sub(cap);
+        for old in &existing[..excess] {
+            batch.delete(&entry_key(*old))?;
+        }
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(SYNC);
+        self.database.write_opt(batch, &write_options)?;
+        Ok(index)
+    }
+
+    /// The last task journaled with `task_id`, if it's still in the journal.
+    pub fn get_journaled_task(&self, task_id: &str) -> Result<Option<JournaledTask>, Error> {
+        let mut found = None;
+        for (key, value) in self.database.prefix_iterator(TASK_JOURNAL_PREFIX) {
+            if !key.starts_with(TASK_JOURNAL_PREFIX) {
+                continue;
+            }
+            let task = serde_json::from_slice::<JournaledTask>(&value).map_err(|_| fetch_error())?;
+            if task.task_id == task_id {
+                found = Some(task);
+            }
+        }
+        Ok(found)
+    }
+
+    // The indexes of the journaled tasks, oldest first.
+    fn journal_keys(&self) -> Result<Vec<u64>, Error> {
+        let mut indexes = Vec::new();
+        for (key, _) in self.database.prefix_iterator(TASK_JOURNAL_PREFIX) {
+            if !key.starts_with(TASK_JOURNAL_PREFIX) {
+                continue;
+            }
+            if key.len() != TASK_JOURNAL_PREFIX.len() + 8 {
+                return Err(fetch_error().into());
+            }
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key[TASK_JOURNAL_PREFIX.len()..]);
+            indexes.push(u64::from_be_bytes(index));
+        }
+        Ok(indexes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::tests::create_test_db;
+
+    fn task(task_id: &str) -> JournaledTask {
+        JournaledTask {
+            index: 0,
+            task_id: task_id.to_string(),
+            address: "11".repeat(32),
+            tip: Some(0),
+            callable: "aa".to_string(),
+            args: "bb".to_string(),
+            user_pubkey: "cc".repeat(64),
+            gas_limit: 100,
+            output: "dd".to_string(),
+            inputs_hash: "01".repeat(32),
+            exe_code_hash: "02".repeat(32),
+            prev_delta_hash: "03".repeat(32),
+            delta_hash: "04".repeat(32),
+            output_hash: "05".repeat(32),
+            used_gas: 10,
+            ethereum_payload: String::new(),
+            ethereum_address: "00".repeat(20),
+            envelope_hash: None,
+            signature: "06".repeat(65),
+            sealed_io_key: "07".repeat(92),
+        }
+    }
+
+    #[test]
+    fn test_task_journal() {
+        let (db, _dir) = create_test_db();
+        assert_eq!(db.get_journaled_task("a").unwrap(), None);
+        for id in &["a", "b", "c", "a"] {
+            db.journal_task(task(id), 3).unwrap();
+        }
+        // The first "a" was dropped, and the last one wins anyway.
+        assert_eq!(db.get_journaled_task("a").unwrap(), Some(JournaledTask { index: 3, ..task("a") }));
+        assert_eq!(db.get_journaled_task("b").unwrap().map(|t| t.index), Some(1));
+        db.journal_task(task("d"), 3).unwrap();
+        assert_eq!(db.get_journaled_task("b").unwrap(), None);
+    }
+}
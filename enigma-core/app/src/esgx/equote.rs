@@ -2,7 +2,10 @@ use common_u::errors;
 use failure::Error;
 use sgx_types::*;
 use std::str;
-use crate::auto_ffi::ecall_get_signing_address;
+use crate::auto_ffi::{ecall_get_signing_address, ecall_set_network};
+use common_u::network::Network;
+use enigma_tools_u::attestation_service::service::Quote;
+use enigma_tools_u::esgx::equote as equote_tools;
 // this struct is returned during the process registration back to the surface.
 // quote: the base64 encoded quote
 // address : the clear text public key for ecdsa signing and registration
@@ -25,6 +28,22 @@ pub fn get_register_signing_address(eid: sgx_enclave_id_t) -> Result<[u8; 20], E
     }
 }
 
+// sets the network that goes into the registration report (after the signing address)
+#[logfn(TRACE)]
+pub fn set_network(eid: sgx_enclave_id_t, network: Network) -> Result<(), Error> {
+    let status = unsafe { ecall_set_network(eid, network.chain_id()) };
+    if status == sgx_status_t::SGX_SUCCESS {
+        Ok(())
+    } else {
+        Err(errors::ProduceQuoteErr { status, message: String::from("error in set_network") }.into())
+    }
+}
+
+// the MRENCLAVE of the running enclave, read from a quote of it without going to the attestation service
+pub fn get_mr_enclave(eid: sgx_enclave_id_t, spid: &str) -> Result<[u8; 32], Error> {
+    let quote = equote_tools::retry_quote(eid, spid, 18)?;
+    Ok(Quote::from_base64(&quote)?.report_body.mr_enclave)
+}
 
 #[cfg(test)]
 mod test {
@@ -1,18 +1,151 @@
-use enigma_tools_u::{self, esgx::general::storage_dir};
+use enigma_tools_u::{self, esgx::general::enclave_storage_dir};
 use sgx_types::*;
 use sgx_urts::SgxEnclave;
 use std::fs;
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use log;
 
 static ENCLAVE_FILE: &'static str = "../bin/enclave.signed.so";
 pub static ENCLAVE_DIR: &'static str = ".enigma";
+/// Has to match `TCSNum` in `enclave/Enclave.config.xml`, an ecall beyond it fails with `SGX_ERROR_OUT_OF_TCS`.
+pub const TCS_NUM: usize = 1;
+
+lazy_static! { static ref ENCLAVE: Mutex<Option<Arc<EnclaveHandle>>> = Mutex::new(None); }
 
 #[logfn(INFO)]
 pub fn init_enclave_wrapper() -> SgxResult<SgxEnclave> {
     // Create a folder for storage (Sealed, etc)
     // If the storage folder is inaccessible, the enclave would not be able to seal info
-    let storage_path = storage_dir(ENCLAVE_DIR).unwrap();
+    let storage_path = enclave_storage_dir(ENCLAVE_DIR).unwrap();
     fs::create_dir_all(&storage_path).map_err(|e| { format_err!("Unable to create storage directory {}: {}", storage_path.display(), e) }).unwrap();
 
     enigma_tools_u::esgx::init_enclave(&ENCLAVE_FILE)
 }
+
+/// Returns the process wide enclave, creating it on the first call.
+/// Concurrent callers block until it's created and all get the same handle.
+pub fn get_or_init_enclave() -> SgxResult<Arc<EnclaveHandle>> {
+    let mut guard = ENCLAVE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(handle) = &*guard {
+        return Ok(Arc::clone(handle));
+    }
+    let handle = Arc::new(EnclaveHandle::new(init_enclave_wrapper()?));
+    *guard = Some(Arc::clone(&handle));
+    Ok(handle)
+}
+
+/// Replaces the process wide enclave with a fresh one, this is meant only for crash recovery.
+/// See `EnclaveHandle::reinit`.
+pub fn reinit() -> SgxResult<sgx_enclave_id_t> { get_or_init_enclave()?.reinit() }
+
+/// A counting semaphore with a permit per TCS, so we never enter the enclave more times than it can take.
+struct TcsSemaphore {
+    available: Mutex<usize>,
+    cvar: Condvar,
+}
+
+impl TcsSemaphore {
+    fn new(permits: usize) -> Self { TcsSemaphore { available: Mutex::new(permits), cvar: Condvar::new() } }
+
+    fn acquire(&self, permits: usize) {
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available < permits {
+            available = self.cvar.wait(available).unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= permits;
+    }
+
+    fn release(&self, permits: usize) {
+        *self.available.lock().unwrap_or_else(|e| e.into_inner()) += permits;
+        self.cvar.notify_all();
+    }
+}
+
+/// A shared handle to the enclave, the eid it returns always points to the live enclave even after a `reinit`.
+pub struct EnclaveHandle {
+    enclave: Mutex<SgxEnclave>,
+    eid: AtomicU64,
+    generation: AtomicUsize,
+    tcs: TcsSemaphore,
+}
+
+impl EnclaveHandle {
+    fn new(enclave: SgxEnclave) -> Self {
+        let eid = enclave.geteid();
+        EnclaveHandle { enclave: Mutex::new(enclave), eid: AtomicU64::new(eid), generation: AtomicUsize::new(1), tcs: TcsSemaphore::new(TCS_NUM) }
+    }
+
+    /// The eid of the current enclave.
+    pub fn geteid(&self) -> sgx_enclave_id_t { self.eid.load(Ordering::SeqCst) }
+
+    /// How many enclaves were created by this handle (1 + the number of successful `reinit`s).
+    pub fn generation(&self) -> usize { self.generation.load(Ordering::SeqCst) }
+
+    /// Takes a TCS permit for the duration of the returned guard, ecalls should only be made while holding it.
+    /// `reinit` waits for all permits to be returned before destroying the enclave.
+    pub fn enter(&self) -> EcallPermit {
+        self.tcs.acquire(1);
+        EcallPermit { handle: self, eid: self.geteid() }
+    }
+
+    /// Creates a new enclave and atomically swaps the eid, then destroys the old enclave.
+    /// Blocks until all in-flight ecalls are done, so it must not be called while holding an `EcallPermit`.
+    /// If the new enclave can't be created the old one is kept.
+    pub fn reinit(&self) -> SgxResult<sgx_enclave_id_t> {
+        self.tcs.acquire(TCS_NUM);
+        let res = init_enclave_wrapper().map(|new_enclave| {
+            let eid = new_enclave.geteid();
+            let mut enclave = self.enclave.lock().unwrap_or_else(|e| e.into_inner());
+            let old_enclave = mem::replace(&mut *enclave, new_enclave);
+            self.eid.store(eid, Ordering::SeqCst);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            info!("Re-initialized the enclave, old eid: {}, new eid: {}", old_enclave.geteid(), eid);
+            old_enclave.destroy();
+            eid
+        });
+        self.tcs.release(TCS_NUM);
+        res
+    }
+}
+
+/// A TCS permit, see `EnclaveHandle::enter`.
+pub struct EcallPermit<'a> {
+    handle: &'a EnclaveHandle,
+    eid: sgx_enclave_id_t,
+}
+
+impl<'a> EcallPermit<'a> {
+    /// The eid to use for the ecalls made with this permit, it can't change while the permit is held.
+    pub fn geteid(&self) -> sgx_enclave_id_t { self.eid }
+}
+
+impl<'a> Drop for EcallPermit<'a> {
+    fn drop(&mut self) { self.handle.tcs.release(1) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_get_or_init_and_reinit() {
+        let threads: Vec<_> = (0..8).map(|_| thread::spawn(|| get_or_init_enclave().unwrap())).collect();
+        let handles: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        let first = &handles[0];
+        assert!(handles.iter().all(|h| Arc::ptr_eq(h, first)));
+        assert_eq!(first.generation(), 1);
+        let eid = first.geteid();
+
+        let new_eid = reinit().unwrap();
+        assert_ne!(eid, new_eid);
+        assert_eq!(first.generation(), 2);
+        assert!(handles.iter().all(|h| h.geteid() == new_eid));
+        assert_eq!(get_or_init_enclave().unwrap().geteid(), new_eid);
+
+        let permit = first.enter();
+        assert_eq!(permit.geteid(), new_eid);
+    }
+}
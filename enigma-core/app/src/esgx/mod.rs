@@ -1,3 +1,4 @@
 pub mod equote;
 pub mod general;
 pub mod ocalls_u;
+pub mod watchdog;
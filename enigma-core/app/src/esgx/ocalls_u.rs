@@ -1,10 +1,11 @@
 #![allow(unused_attributes)]
 use crate::db::{CRUDInterface, DeltaKey, P2PCalls, ResultType, ResultTypeVec, Stype, DB};
-use enigma_tools_m::utils::LockExpectMutex;
+use common_u::panics::LockRecover;
 use enigma_crypto::hash::Sha256;
 use enigma_types::{ContractAddress, EnclaveReturn, Hash256, RawPointer};
 use lru_cache::LruCache;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{ptr, slice};
 use common_u::errors;
 
@@ -73,7 +74,7 @@ pub unsafe extern "C" fn ocall_get_state_size(db_ptr: *const RawPointer, addr: &
             let state_len = state.len();
             *state_size = state_len;
             cache_id.extend_from_slice(&state_len.to_be_bytes());
-            DELTAS_CACHE.lock_expect("DeltaCache").insert(cache_id.sha256(), vec![state]);
+            DELTAS_CACHE.lock_recover("DeltaCache").insert(cache_id.sha256(), vec![state]);
             EnclaveReturn::Success
         }
         Err(_) => EnclaveReturn::OcallDBError,
@@ -95,7 +96,7 @@ pub unsafe extern "C" fn ocall_get_state(db_ptr: *const RawPointer, addr: &Contr
     };
 
 
-    match DELTAS_CACHE.lock_expect("DeltaCache").remove(&cache_id.sha256()) {
+    match DELTAS_CACHE.lock_recover("DeltaCache").remove(&cache_id.sha256()) {
         Some(state) => {
             enigma_types::write_ptr(&state[0][..], state_ptr, state_size);
             EnclaveReturn::Success
@@ -149,7 +150,7 @@ pub unsafe extern "C" fn ocall_get_deltas_sizes(db_ptr: *const RawPointer, addr:
         },
         Err(_) => return EnclaveReturn::OcallDBError,
     };
-    DELTAS_CACHE.lock_expect("DeltaCache").insert(cache_id.sha256(), deltas_vec);
+    DELTAS_CACHE.lock_recover("DeltaCache").insert(cache_id.sha256(), deltas_vec);
     enigma_types::write_ptr(&sizes, res_ptr, res_len);
     EnclaveReturn::Success
 }
@@ -172,7 +173,7 @@ pub unsafe extern "C" fn ocall_get_deltas(db_ptr: *const RawPointer, addr: &Cont
     };
 
 
-    match DELTAS_CACHE.lock_expect("DeltaCache").remove(&cache_id.sha256()) {
+    match DELTAS_CACHE.lock_recover("DeltaCache").remove(&cache_id.sha256()) {
         Some(deltas_vec) => {
             // The results here are flatten to one big array.
             // The Enclave needs to separate them back to the original.
@@ -223,6 +224,12 @@ pub unsafe extern "C" fn ocall_remove_delta(db_ptr: *const RawPointer,
     }
 }
 
+/// Seconds since the unix epoch, the enclave stamps its audit events with it.
+#[no_mangle]
+pub extern "C" fn ocall_get_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 fn get_deltas(db: &mut DB, addr: ContractAddress, start: u32, end: u32) -> ResultTypeVec<(DeltaKey, Vec<u8>)> {
     let key_start = DeltaKey::new(addr, Stype::Delta(start));
     let key_end = DeltaKey::new(addr, Stype::Delta(end));
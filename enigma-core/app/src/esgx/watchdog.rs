@@ -0,0 +1,283 @@
+//! # Enclave Watchdog.
+//! A degraded SGX driver or AESM doesn't make the ecalls fail, it makes them slow, and it used to be noticed only
+//! when the tasks started timing out. The watchdog makes a trivial ecall every few seconds and keeps the latency of
+//! the last `WINDOW` pings: while their p95 is over the threshold the node reports itself as degraded (`GetHealth`
+//! and the `enigma_enclave_degraded` gauge). A ping that fails, or takes longer than the timeout, counts as a failure,
+//! and after `max_failures` failures in a row the enclave is re-created like after a crash. The new enclave has none of
+//! the state keys, so like on startup with `--recover` the tasks wait for a recovery of the stored contracts.
+//!
+//! The pings take a TCS permit like any other ecall, so they wait for the real work instead of competing with it.
+//! A ping that never returns can't be interrupted, the requests stuck behind the same enclave show it anyway.
+
+use crate::auto_ffi::ecall_ping;
+use common_u::errors::{DBErr, DBErrKind};
+use common_u::metrics::METRICS;
+use common_u::network::Network;
+use common_u::panics::LockRecover;
+use common_u::recovery::RECOVERY;
+use enigma_types::ContractAddress;
+use enigma_tools_u::common_u::errors::SgxError;
+use esgx::equote;
+use esgx::general::EnclaveHandle;
+use failure::Error;
+use sgx_types::sgx_status_t;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The number of pings the p95 is computed over.
+const WINDOW: usize = 20;
+/// The node isn't reported as degraded before this many pings.
+const MIN_SAMPLES: usize = 5;
+
+/// What the watchdog needs from the enclave.
+pub trait Pinger: Send + 'static {
+    /// Makes the trivial ecall, and returns how long it took once the TCS permit was taken.
+    fn ping(&mut self) -> Result<Duration, Error>;
+    /// Replaces the enclave after it stopped answering the pings.
+    fn reinit(&mut self) -> Result<(), Error>;
+}
+
+/// Lists the contracts stored in the DB, see `DB::addresses_at`.
+pub type StoredContracts = Box<dyn Fn() -> Result<Vec<ContractAddress>, Error> + Send>;
+
+/// Pings the process wide enclave.
+pub struct EnclavePinger {
+    handle: Arc<EnclaveHandle>,
+    // A new enclave doesn't know the network yet, it has to be set again like on startup.
+    network: Option<Network>,
+    // The contracts whose keys a new enclave has to recover.
+    stored: StoredContracts,
+}
+
+impl EnclavePinger {
+    pub fn new(handle: Arc<EnclaveHandle>, network: Option<Network>, stored: StoredContracts) -> Self {
+        EnclavePinger { handle, network, stored }
+    }
+}
+
+impl Pinger for EnclavePinger {
+    fn ping(&mut self) -> Result<Duration, Error> {
+        let permit = self.handle.enter();
+        let start = Instant::now();
+        let status = unsafe { ecall_ping(permit.geteid()) };
+        let elapsed = start.elapsed();
+        METRICS.record_enclave_call("ecall_ping", elapsed, status);
+        if status != sgx_status_t::SGX_SUCCESS {
+            return Err(SgxError { status, function: "ecall_ping" }.into());
+        }
+        Ok(elapsed)
+    }
+
+    fn reinit(&mut self) -> Result<(), Error> {
+        self.handle.reinit().map_err(|status| SgxError { status, function: "reinit" })?;
+        if let Some(network) = self.network {
+            let permit = self.handle.enter();
+            equote::set_network(permit.geteid(), network)?;
+        }
+        // Before the enclave is reported healthy, so no task runs without the keys.
+        let addresses = match (self.stored)() {
+            Ok(addresses) => addresses,
+            Err(e) => match e.downcast_ref::<DBErr>() {
+                Some(DBErr { kind: DBErrKind::MissingKeys, .. }) => Vec::new(),
+                _ => return Err(e),
+            },
+        };
+        RECOVERY.lock_recover("Recovery").start(addresses);
+        METRICS.set_enclave_health(true);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchdogConfig {
+    /// The time between two pings.
+    pub interval: Duration,
+    /// The node is degraded while the p95 of the last pings is over this.
+    pub p95_threshold: Duration,
+    /// A ping slower than this counts as a failure.
+    pub ping_timeout: Duration,
+    /// The failures in a row that re-create the enclave.
+    pub max_failures: u32,
+}
+
+pub struct Watchdog<P: Pinger> {
+    pinger: P,
+    config: WatchdogConfig,
+    latencies: VecDeque<Duration>,
+    failures: u32,
+    degraded: bool,
+}
+
+impl<P: Pinger> Watchdog<P> {
+    pub fn new(pinger: P, config: WatchdogConfig) -> Self {
+        Watchdog { pinger, config, latencies: VecDeque::with_capacity(WINDOW), failures: 0, degraded: false }
+    }
+
+    pub fn degraded(&self) -> bool { self.degraded }
+
+    /// Pings the enclave every `interval` on its own thread.
+    pub fn spawn(mut self) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new().name("watchdog".to_string()).spawn(move || loop {
+            thread::sleep(self.config.interval);
+            self.tick();
+        })
+    }
+
+    /// Pings the enclave once and updates the degraded flag.
+    pub fn tick(&mut self) {
+        match self.pinger.ping() {
+            Ok(latency) => {
+                self.record_latency(latency);
+                if latency > self.config.ping_timeout {
+                    warn!("The enclave took {:?} to answer a ping", latency);
+                    self.failed();
+                } else {
+                    self.failures = 0;
+                }
+            }
+            Err(e) => {
+                warn!("The enclave failed a ping: {}", e);
+                self.failed();
+            }
+        }
+        self.update_degraded();
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        if self.latencies.len() == WINDOW {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    fn failed(&mut self) {
+        self.failures += 1;
+        if self.failures < self.config.max_failures {
+            return;
+        }
+        error!("The enclave failed {} pings in a row, re-creating it", self.failures);
+        match self.pinger.reinit() {
+            Ok(()) => {
+                METRICS.record_enclave_reinit();
+                self.failures = 0;
+                // The latencies were the old enclave's.
+                self.latencies.clear();
+            }
+            // The failures aren't reset, so the next failed ping tries again.
+            Err(e) => error!("Failed re-creating the enclave: {}", e),
+        }
+    }
+
+    fn p95(&self) -> Option<Duration> {
+        if self.latencies.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.latencies.iter().cloned().collect();
+        sorted.sort();
+        let rank = (sorted.len() * 95 + 99) / 100;
+        Some(sorted[rank - 1])
+    }
+
+    fn update_degraded(&mut self) {
+        let degraded = self.p95().map_or(false, |p95| p95 > self.config.p95_threshold);
+        if degraded == self.degraded {
+            return;
+        }
+        if degraded {
+            warn!("The enclave is degraded, the p95 of its pings is {:?}", self.p95().unwrap_or_default());
+        } else {
+            info!("The enclave isn't degraded anymore");
+        }
+        self.degraded = degraded;
+        METRICS.set_enclave_degraded(degraded);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Answers every ping after `latency`, or fails it while `failing` is set.
+    struct MockPinger {
+        latency: Duration,
+        failing: bool,
+        reinits: usize,
+    }
+
+    impl Pinger for MockPinger {
+        fn ping(&mut self) -> Result<Duration, Error> {
+            if self.failing {
+                bail!("ecall_ping failed");
+            }
+            Ok(self.latency)
+        }
+
+        fn reinit(&mut self) -> Result<(), Error> {
+            self.reinits += 1;
+            self.failing = false;
+            Ok(())
+        }
+    }
+
+    fn watchdog(latency: Duration) -> Watchdog<MockPinger> {
+        let config = WatchdogConfig {
+            interval: Duration::from_secs(1),
+            p95_threshold: Duration::from_millis(50),
+            ping_timeout: Duration::from_secs(5),
+            max_failures: 3,
+        };
+        Watchdog::new(MockPinger { latency, failing: false, reinits: 0 }, config)
+    }
+
+    #[test]
+    fn test_degraded_on_slow_pings() {
+        let mut watchdog = watchdog(Duration::from_millis(1));
+        for _ in 0..WINDOW {
+            watchdog.tick();
+        }
+        assert!(!watchdog.degraded());
+
+        // One slow ping in the window is under the p95, two aren't.
+        watchdog.pinger.latency = Duration::from_millis(200);
+        watchdog.tick();
+        assert!(!watchdog.degraded());
+        watchdog.tick();
+        assert!(watchdog.degraded());
+        assert!(METRICS.enclave_degraded());
+        assert_eq!(watchdog.pinger.reinits, 0);
+
+        watchdog.pinger.latency = Duration::from_millis(1);
+        for _ in 0..WINDOW {
+            watchdog.tick();
+        }
+        assert!(!watchdog.degraded());
+        assert!(!METRICS.enclave_degraded());
+    }
+
+    #[test]
+    fn test_reinit_after_failures() {
+        let mut watchdog = watchdog(Duration::from_millis(1));
+        watchdog.pinger.failing = true;
+        watchdog.tick();
+        watchdog.tick();
+        assert_eq!(watchdog.pinger.reinits, 0);
+        watchdog.tick();
+        assert_eq!(watchdog.pinger.reinits, 1);
+
+        // A successful ping resets the count.
+        watchdog.tick();
+        watchdog.pinger.failing = true;
+        watchdog.tick();
+        watchdog.tick();
+        assert_eq!(watchdog.pinger.reinits, 1);
+
+        // A ping over the timeout is a failure, even if it eventually returned.
+        watchdog.pinger.failing = false;
+        watchdog.pinger.latency = Duration::from_secs(6);
+        watchdog.tick();
+        assert_eq!(watchdog.pinger.reinits, 2);
+    }
+}
@@ -5,8 +5,15 @@ use crate::db::DB;
 use enigma_types::traits::SliceCPtr;
 use enigma_types::{EnclaveReturn, ContractAddress, PubKey, RawPointer};
 use failure::Error;
+use serde_json;
 use sgx_types::{sgx_enclave_id_t, sgx_status_t};
-use crate::auto_ffi::{ecall_ptt_req, ecall_ptt_res, ecall_build_state, ecall_get_user_key};
+use crate::auto_ffi::{ecall_ptt_req, ecall_ptt_res, ecall_build_state, ecall_get_provisioned_addresses, ecall_get_user_key,
+                      ecall_export_audit_digest};
+use enigma_tools_m::audit::AuditExport;
+use enigma_tools_m::signable::to_ethereum;
+use crate::common_u::metrics::METRICS;
+use std::mem;
+use std::time::Instant;
 
 /// This function builds the states that it received in ptt_req and ptt_res
 /// It returns a Vec of the failed contract addresses
@@ -17,55 +24,92 @@ pub fn ptt_build_state(db: &mut DB, eid: sgx_enclave_id_t) -> Result<Vec<Contrac
 
     let db_ptr = unsafe { RawPointer::new_mut(db) };
 
+    let start = Instant::now();
     let status = unsafe {
         ecall_build_state(eid,
                           &mut ret as *mut EnclaveReturn,
                           &db_ptr as *const RawPointer,
                           &mut failed_ptr as *mut u64) };
+    METRICS.record_enclave_call("ecall_build_state", start.elapsed(), status);
 
     if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
         return Err(EnclaveFailError { err: ret, status }.into());
     }
-    let box_ptr = failed_ptr as *mut Box<[u8]>;
-    let part = unsafe { Box::from_raw(box_ptr) };
-    let part: Vec<ContractAddress> = part
-        .chunks(32)
+    Ok(unsafe { addresses_from_ptr(failed_ptr) })
+}
+
+/// Takes back the flattened addresses the enclave saved to untrusted memory.
+unsafe fn addresses_from_ptr(ptr: u64) -> Vec<ContractAddress> {
+    let box_ptr = ptr as *mut Box<[u8]>;
+    let part = Box::from_raw(box_ptr);
+    part.chunks(32)
         .map(|s| {
             let mut arr = ContractAddress::default();
             arr.copy_from_slice(s);
             arr
         })
-        .collect();
-    Ok(part)
+        .collect()
+}
+
+/// Returns the addresses of the contracts the enclave has state keys for.
+pub fn provisioned_addresses(eid: sgx_enclave_id_t) -> Result<Vec<ContractAddress>, Error> {
+    let mut ret = EnclaveReturn::Success;
+    let mut addrs_ptr = 0u64;
+    let start = Instant::now();
+    let status = unsafe { ecall_get_provisioned_addresses(eid, &mut ret as *mut EnclaveReturn, &mut addrs_ptr as *mut u64) };
+    METRICS.record_enclave_call("ecall_get_provisioned_addresses", start.elapsed(), status);
+    if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: ret, status }.into());
+    }
+    Ok(unsafe { addresses_from_ptr(addrs_ptr) })
 }
 
-pub fn ptt_res(eid: sgx_enclave_id_t, msg: &[u8]) -> Result<(), Error> {
+/// Gives the enclave the state keys of a PTT response, only those of the contracts in `hosted` if they're given.
+pub fn ptt_res(eid: sgx_enclave_id_t, msg: &[u8], hosted: Option<&[ContractAddress]>) -> Result<(), Error> {
     let mut ret = EnclaveReturn::Success;
-    let status = unsafe { ecall_ptt_res(eid, &mut ret as *mut EnclaveReturn, msg.as_c_ptr(), msg.len()) };
+    let addrs = hosted.unwrap_or_default();
+    let start = Instant::now();
+    let status = unsafe {
+        ecall_ptt_res(eid,
+                      &mut ret as *mut EnclaveReturn,
+                      msg.as_c_ptr(),
+                      msg.len(),
+                      addrs.as_c_ptr() as *const u8,
+                      addrs.len() * mem::size_of::<ContractAddress>(),
+                      hosted.is_some() as u8,
+        )
+    };
+    METRICS.record_enclave_call("ecall_ptt_res", start.elapsed(), status);
     if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
         return Err(EnclaveFailError { err: ret, status }.into());
     }
     Ok(())
 }
 
-pub fn ptt_req(eid: sgx_enclave_id_t) -> Result<(Box<[u8]>, [u8; 65]), Error> {
+/// Generates a signed request for the state keys, for the epoch that was active at `block_number`
+/// or for the latest epoch without it.
+pub fn ptt_req(eid: sgx_enclave_id_t, block_number: Option<u64>) -> Result<(Box<[u8]>, [u8; 65]), Error> {
     let mut sig = [0u8; 65];
     let mut ret = EnclaveReturn::default();
     let mut serialized_ptr = 0u64;
 
+    let start = Instant::now();
     let status = unsafe {
         ecall_ptt_req(eid,
                       &mut ret as *mut EnclaveReturn,
+                      block_number.unwrap_or_default(),
+                      block_number.is_some() as u8,
                       &mut sig,
                       &mut serialized_ptr as *mut u64,
         )
     };
+    METRICS.record_enclave_call("ecall_ptt_req", start.elapsed(), status);
     if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
         return Err(EnclaveFailError { err: ret, status }.into());
     }
     let box_ptr = serialized_ptr as *mut Box<[u8]>;
     let part = unsafe { Box::from_raw(box_ptr) };
-    Ok((*part, sig))
+    Ok((*part, to_ethereum(&sig)?))
 }
 
 pub fn get_user_key(eid: sgx_enclave_id_t, user_pubkey: &PubKey) -> Result<(Box<[u8]>, [u8; 65]), Error> {
@@ -73,15 +117,35 @@ pub fn get_user_key(eid: sgx_enclave_id_t, user_pubkey: &PubKey) -> Result<(Box<
     let mut ret = EnclaveReturn::Success;
     let mut serialized_ptr = 0u64;
 
+    let start = Instant::now();
     let status = unsafe {
         ecall_get_user_key(eid, &mut ret as *mut EnclaveReturn, &mut sig, user_pubkey.as_ptr() as _, &mut serialized_ptr as *mut u64)
     };
+    METRICS.record_enclave_call("ecall_get_user_key", start.elapsed(), status);
+    if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: ret, status }.into());
+    }
+    let box_ptr = serialized_ptr as *mut Box<[u8]>;
+    let part = unsafe { Box::from_raw(box_ptr) };
+    Ok((*part, to_ethereum(&sig)?))
+}
+
+/// Returns the audit log of the keys the enclave derived, and the enclave's signature of its `AuditDigest`.
+/// Like every signature this module returns, it's converted to `SigFormat::Ethereum`.
+pub fn export_audit_digest(eid: sgx_enclave_id_t) -> Result<(AuditExport, [u8; 65]), Error> {
+    let mut sig = [0u8; 65];
+    let mut ret = EnclaveReturn::Success;
+    let mut serialized_ptr = 0u64;
+
+    let start = Instant::now();
+    let status = unsafe { ecall_export_audit_digest(eid, &mut ret as *mut EnclaveReturn, &mut sig, &mut serialized_ptr as *mut u64) };
+    METRICS.record_enclave_call("ecall_export_audit_digest", start.elapsed(), status);
     if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
         return Err(EnclaveFailError { err: ret, status }.into());
     }
     let box_ptr = serialized_ptr as *mut Box<[u8]>;
     let part = unsafe { Box::from_raw(box_ptr) };
-    Ok((*part, sig))
+    Ok((serde_json::from_slice(&part)?, to_ethereum(&sig)?))
 }
 
 #[cfg(test)]
@@ -90,7 +154,7 @@ pub mod tests {
     extern crate cross_test_utils;
     extern crate itertools;
 
-    use super::{ptt_build_state, ptt_req, ptt_res};
+    use super::{export_audit_digest, ptt_build_state, ptt_req, ptt_res};
     use crate::db::{CRUDInterface, DeltaKey, DB,
                     Stype::{Delta, State}, tests::create_test_db};
     use crate::esgx::{general::init_enclave_wrapper, equote};
@@ -103,6 +167,9 @@ pub mod tests {
     use sgx_types::sgx_enclave_id_t;
     use self::ethabi::{Token};
     use self::itertools::{Itertools, EitherOrBoth::*};
+    use enigma_tools_m::audit::AuditEventKind;
+    use enigma_tools_m::primitives::km_primitives::PrincipalMessage;
+    use enigma_tools_m::signable::Signable;
 
     const PUBKEY_DUMMY: [u8; 64] = [ 27, 132, 197, 86, 123, 18, 100, 64, 153, 93, 62, 213, 170, 186, 5, 101, 215, 30, 24, 52, 96, 72, 25, 255, 156, 23, 245, 233, 213, 221, 7, 143, 112, 190, 175, 143, 88, 139, 84, 21, 7, 254, 214, 166, 66, 197, 171, 66, 223, 223, 129, 32, 167, 246, 57, 222, 81, 34, 212, 122, 105, 168, 232, 209];
 
@@ -163,16 +230,85 @@ pub mod tests {
         assert_eq!(recovered.keccak256()[12..32], signing_key);
     }
 
+    #[test]
+    fn test_export_audit_digest() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let signing_key = equote::get_register_signing_address(enclave.geteid()).unwrap();
+        let (first, _) = export_audit_digest(enclave.geteid()).unwrap();
+        let (keys, ..) = exchange_keys(enclave.geteid());
+        exchange_keys(enclave.geteid());
+        let (second, sig) = export_audit_digest(enclave.geteid()).unwrap();
+
+        assert!(second.verify());
+        assert!(second.extends(&first.digest()));
+        assert_eq!(second.digest().count, first.digest().count + 2);
+        // The event is about the user, the key itself never leaves the enclave.
+        let event = second.events[second.events.len() - 2];
+        assert_eq!(event.kind, AuditEventKind::UserKey);
+        assert_eq!(event.subject, keys.get_pubkey()[..].keccak256());
+
+        let recovered = KeyPair::recover(&second.digest().to_signable_bytes(), sig).unwrap();
+        assert_eq!(recovered.keccak256()[12..32], signing_key);
+    }
+
     #[test]
     fn test_ptt_req() {
         let enclave = init_enclave_wrapper().unwrap();
-        let (msg, sig) = ptt_req(enclave.geteid()).unwrap();
+        let (msg, sig) = ptt_req(enclave.geteid(), None).unwrap();
         assert_ne!(msg.len(), 0);
         assert_ne!(sig.to_vec(), vec![0u8; 64]);
+        let req = PrincipalMessage::from_message(&msg).unwrap();
+        assert_eq!(req.get_block_number(), None);
+    }
+
+    #[test]
+    fn test_ptt_req_at_block() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let (msg, sig) = ptt_req(enclave.geteid(), Some(250)).unwrap();
+        let signing_key = equote::get_register_signing_address(enclave.geteid()).unwrap();
+        let req = PrincipalMessage::from_message(&msg).unwrap();
+        assert_eq!(req.get_block_number(), Some(250));
+        let recovered = KeyPair::recover(&req.to_sign().unwrap(), sig).unwrap();
+        assert_eq!(recovered.keccak256()[12..32], signing_key);
+
+        // The block is signed along with the rest of the request.
+        let mut unpinned = serde_json::to_value(&req).unwrap();
+        unpinned.as_object_mut().unwrap().remove("block_number");
+        let unpinned: PrincipalMessage = serde_json::from_value(unpinned).unwrap();
+        let recovered = KeyPair::recover(&unpinned.to_sign().unwrap(), sig).unwrap();
+        assert_ne!(recovered.keccak256()[12..32], signing_key);
+    }
+
+    #[test]
+    fn test_ptt_res_hosted() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let addresses = vec![b"first".sha256(), b"second".sha256()];
+        let response = encrypted_ptt_response(addresses.clone(), enclave.geteid());
+        ptt_res(enclave.geteid(), &response, Some(&addresses[1..])).unwrap();
+        assert_eq!(super::provisioned_addresses(enclave.geteid()).unwrap(), vec![addresses[1]]);
+    }
+
+    #[test]
+    fn test_provisioned_addresses() {
+        let enclave = init_enclave_wrapper().unwrap();
+        assert!(super::provisioned_addresses(enclave.geteid()).unwrap().is_empty());
+        let addresses = vec![b"first".sha256(), b"second".sha256()];
+        instantiate_encryption_key(addresses.clone(), enclave.geteid());
+        let mut provisioned = super::provisioned_addresses(enclave.geteid()).unwrap();
+        provisioned.sort();
+        let mut expected = addresses;
+        expected.sort();
+        assert_eq!(provisioned, expected);
     }
 
     pub fn instantiate_encryption_key(addresses: Vec<ContractAddress>, eid: sgx_enclave_id_t) {
-        let req = ptt_req(eid).unwrap();
+        let response = encrypted_ptt_response(addresses, eid);
+        ptt_res(eid, &response, None).unwrap();
+    }
+
+    /// A KM node's response with the keys of `addresses` to a new PTT request of the enclave.
+    pub fn encrypted_ptt_response(addresses: Vec<ContractAddress>, eid: sgx_enclave_id_t) -> Vec<u8> {
+        let req = ptt_req(eid, None).unwrap();
 
         let mut des = Deserializer::new(&req.0[..]);
         let req_val: Value = Deserialize::deserialize(&mut des).unwrap();
@@ -181,8 +317,7 @@ pub mod tests {
 
         let mut serialized_enc_response = Vec::new();
         enc_response.serialize(&mut Serializer::new(&mut serialized_enc_response)).unwrap();
-
-        ptt_res(eid, &serialized_enc_response).unwrap();
+        serialized_enc_response
     }
 
     #[test]
@@ -200,7 +335,7 @@ pub mod tests {
                 }
             }).collect();
         let enclave = init_enclave_wrapper().unwrap();
-        let req = ptt_req(enclave.geteid()).unwrap();
+        let req = ptt_req(enclave.geteid(), None).unwrap();
         // serializing the result from the request
         let mut des = Deserializer::new(&req.0[..]);
         let req_val: Value = Deserialize::deserialize(&mut des).unwrap();
@@ -212,7 +347,7 @@ pub mod tests {
         let mut serialized_enc_response = Vec::new();
         enc_response.serialize(&mut Serializer::new(&mut serialized_enc_response)).unwrap();
 
-        ptt_res(enclave.geteid(), &serialized_enc_response).unwrap();
+        ptt_res(enclave.geteid(), &serialized_enc_response, None).unwrap();
 
         let address_result = ptt_build_state(&mut db, enclave.geteid()).unwrap();
         assert_eq!(address_result, vec![addresses[2]]);
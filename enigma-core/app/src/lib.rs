@@ -11,6 +11,7 @@ extern crate rmp_serde;
 pub extern crate serde_json;
 extern crate tokio_zmq;
 extern crate zmq;
+extern crate zstd;
 #[macro_use]
 extern crate failure;
 pub extern crate enigma_tools_u;
@@ -21,7 +22,6 @@ extern crate rustc_hex as hex;
 extern crate lru_cache;
 #[macro_use]
 extern crate serde;
-extern crate serde_repr;
 #[macro_use]
 pub extern crate log;
 #[macro_use]
@@ -35,6 +35,7 @@ pub mod km_u;
 pub mod networking;
 pub mod wasm_u;
 pub mod cli;
+pub mod config;
 pub mod auto_ffi;
 
 #[cfg(feature = "cross-test-utils")]
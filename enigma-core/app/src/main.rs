@@ -3,46 +3,201 @@ extern crate enigma_core_app;
 extern crate log;
 extern crate log_derive;
 
-use log::{debug, info};
+use log::info;
 
+use std::env;
+use std::ffi::OsString;
+use std::process;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub use enigma_core_app::*;
 pub use esgx::ocalls_u::{ocall_get_deltas, ocall_get_deltas_sizes, ocall_get_state, ocall_get_state_size,
-                                ocall_new_delta, ocall_update_state, ocall_remove_delta};
+                                ocall_new_delta, ocall_update_state, ocall_remove_delta, ocall_get_time};
 
 pub use enigma_tools_u::esgx::ocalls_u::{ocall_get_home, ocall_save_to_memory};
 use enigma_tools_u::common_u::logging;
 use enigma_tools_u::common_u::os;
 
-use networking::{ipc_listener, IpcListener};
-use db::DB;
-use cli::Opt;
-use structopt::StructOpt;
+use networking::{compression, ipc_listener, messages, IpcListener, MetricsServer};
+use networking::maintenance::MaintenanceSchedule;
+use networking::metrics_server::{DbSizeSampler, DEFAULT_DB_SIZE_INTERVAL};
+use common_u::epoch::EPOCH;
+use common_u::recovery::RECOVERY;
+use common_u::metrics::METRICS;
+use common_u::panics::{self, PANIC_BREAKER};
+use common_u::rate_limit::RATE_LIMITS;
+use db::{key_encoding, CapacityLimits, HostingMode, Mirror, P2PCalls, RebuildScope, DB, REBUILD};
+use esgx::watchdog::{EnclavePinger, Watchdog, WatchdogConfig};
 use futures::Future;
+use structopt::{clap, StructOpt};
 
 
 fn main() {
-    let opt: Opt = Opt::from_args();
+    let args: Vec<OsString> = env::args_os().collect();
+    // `db` commands run without the enclave nor the listener, before the options of the core are parsed.
+    if args.get(1).map_or(false, |arg| arg == "db") {
+        run_db_command(cli::DbCommand::from_iter(&args[1..]));
+        return;
+    }
+    let opt = match config::resolve(&args, &config::env_overrides()) {
+        Ok(opt) => opt,
+        Err(e) => match e.downcast::<clap::Error>() {
+            Ok(e) => e.exit(),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+    };
+    if opt.print_config {
+        println!("{}", serde_json::to_string_pretty(&config::to_json(&opt)).unwrap());
+        return;
+    }
 
     let log_level = log::LevelFilter::from_str(&opt.log_level).unwrap();
 
     let datadir = opt.data_dir.clone().unwrap_or_else(|| dirs::home_dir().unwrap().join(".enigma"));
     let hostname = os::hostname();
     let _handler = logging::init_logger(log_level, &datadir, hostname);
+    // After the logger, the hook logs the panics instead of printing them.
+    panics::install_hook();
 
-    debug!("CLI params: {:?}", opt);
+    info!("Starting Enigma Core v{} with {}", env!("CARGO_PKG_VERSION"), config::to_json(&opt));
+    messages::set_legacy_status(opt.legacy_status);
+    ipc_listener::set_persist_task_deltas(opt.persist_task_deltas);
+    ipc_listener::set_registration_log_cap(opt.registration_history);
+    compression::set_threshold(opt.compression_threshold);
+    PANIC_BREAKER.lock().unwrap().configure(opt.panic_breaker_count, Duration::from_secs(opt.panic_breaker_window));
+    if opt.dev_mode {
+        // `config::validate` refused it in a release build.
+        warn!("Running in dev mode, the tasks can ask for debug traces");
+        ipc_listener::set_dev_mode(true);
+    }
+    EPOCH.lock().unwrap().configure(opt.epoch_grace_blocks, opt.max_epoch_age);
+    RECOVERY.lock().unwrap().configure(Duration::from_secs(opt.recover_timeout));
+    if let Some(path) = &opt.rate_limits {
+        RATE_LIMITS.lock().unwrap().load(path).map_err(|e| error!("Failed loading the rate limits: {}", e)).unwrap();
+        info!("Rate limiting the IPC requests with {:?}", path);
+    }
 
+    // Each network gets its own DB and sealed keys, so the same machine can run against several of them.
+    let db_dir = match opt.network {
+        Some(network) => {
+            info!("Running on the {} network (chain id {})", network, network.chain_id());
+            enigma_tools_u::esgx::general::set_storage_namespace(Some(network.name()));
+            datadir.join(network.name())
+        }
+        None => datadir,
+    };
 
-    let enclave = esgx::general::init_enclave_wrapper().map_err(|e| {error!("Init Enclave Failed {:?}", e);}).unwrap();
+    let enclave = esgx::general::get_or_init_enclave().map_err(|e| {error!("Init Enclave Failed {:?}", e);}).unwrap();
     let eid = enclave.geteid();
     info!("Init Enclave Successful. Enclave id {}", eid);
+    match esgx::equote::get_mr_enclave(eid, &opt.spid) {
+        Ok(mr_enclave) => info!("Enclave MRENCLAVE: {}", mr_enclave.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        Err(e) => warn!("Failed reading the MRENCLAVE of the enclave: {}", e),
+    }
+    METRICS.set_enclave_health(true);
 
-    let mut db = DB::new(datadir, true).expect("Failed initializing the DB");
-    let server = IpcListener::new(&format!("tcp://*:{}", opt.port));
+    let mut db = match opt.network {
+        Some(network) => {
+            esgx::equote::set_network(eid, network).expect("Failed setting the network in the enclave");
+            DB::new_for_network(db_dir, network, true).map_err(|e| error!("{}", e)).expect("Failed initializing the DB")
+        }
+        None => DB::new(db_dir, true).expect("Failed initializing the DB"),
+    };
+    info!("Opened the DB, key schema version {}", key_encoding::KEY_SCHEMA_VERSION);
+    if let Some(target) = &opt.mirror {
+        let mirror = Mirror::open(target, opt.mirror_mode, opt.mirror_buffer).expect("Failed opening the mirror");
+        let mirror = Arc::new(mirror);
+        Mirror::spawn(&mirror).expect("Failed spawning the mirror thread");
+        info!("Mirroring the writes to {:?} ({:?})", target, opt.mirror_mode);
+        db.set_mirror(mirror);
+    }
+    db.set_orphan_policy(opt.orphan_deltas);
+    db.set_capacity_limits(CapacityLimits {
+        max_db_bytes: opt.max_db_bytes,
+        max_contract_delta_bytes: opt.max_contract_delta_bytes,
+        max_contracts: opt.max_contracts,
+    });
+    db.set_hosting_mode(opt.hosting_mode);
+    let hosted = db.load_hosted_contracts().expect("Failed loading the hosted contracts");
+    if opt.hosting_mode == HostingMode::ExplicitList {
+        info!("Hosting the {} contracts of the explicit list", hosted);
+    } else {
+        info!("Hosting {} contracts", opt.hosting_mode);
+    }
+    if opt.address_index_fp_rate.is_some() {
+        db.set_address_index_fp_rate(opt.address_index_fp_rate).expect("Failed building the address index");
+    }
+    info!("Indexed {} contract addresses", db.address_index().stats().addresses);
+    if opt.recover {
+        // The p2p node sends the PTT request it gets from `RecoverKeys`/`GetPTTRequest` to the KM node as usual.
+        let addresses = db.get_all_addresses().expect("Failed listing the hosted contracts");
+        RECOVERY.lock().unwrap().start(addresses);
+    }
+    if let Err(e) = db.warmup(opt.warmup) {
+        warn!("Failed warming up the hot contracts: {}", e);
+    }
+
+    DbSizeSampler::new(&db, DEFAULT_DB_SIZE_INTERVAL).spawn().expect("Failed spawning the DB size thread");
+    if let Some(bind) = &opt.metrics_bind {
+        let metrics = MetricsServer::bind(bind.as_str()).expect("Failed binding the metrics listener");
+        info!("Serving metrics on http://{}/metrics", bind);
+        metrics.spawn().expect("Failed spawning the metrics listener");
+    }
+    if opt.watchdog_interval > 0 {
+        let config = WatchdogConfig {
+            interval: Duration::from_secs(opt.watchdog_interval),
+            p95_threshold: Duration::from_millis(opt.watchdog_p95_ms),
+            ping_timeout: Duration::from_millis(opt.watchdog_timeout_ms),
+            max_failures: opt.watchdog_failures,
+        };
+        let location = db.location.clone();
+        let pinger = EnclavePinger::new(Arc::clone(&enclave), opt.network, Box::new(move || DB::addresses_at(&location)));
+        Watchdog::new(pinger, config).spawn().expect("Failed spawning the watchdog thread");
+    }
+    let endpoint = format!("tcp://*:{}", opt.port);
+    info!("Listening for the p2p node on {}", endpoint);
+    let mut server = IpcListener::new(&endpoint);
+    if opt.maintenance_interval > 0 {
+        let interval = Duration::from_secs(opt.maintenance_interval);
+        server = server.with_maintenance(MaintenanceSchedule { interval, max_queue_depth: opt.maintenance_max_queue_depth });
+    }
+    let probe = ipc_listener::HealthProbe::new(&db);
 
     server
-        .run(move |multi| ipc_listener::handle_message(&mut db, multi, &opt.spid, eid, opt.retries))
+        .serve(opt.queue_capacity, probe, move |multi| {
+            let permit = enclave.enter();
+            ipc_listener::handle_message(&mut db, multi, &opt.spid, permit.geteid(), opt.retries)
+        })
         .wait()
         .unwrap();
-}
\ No newline at end of file
+}
+
+fn run_db_command(command: cli::DbCommand) {
+    match command {
+        cli::DbCommand::Rebuild { data_dir, network, scopes } => {
+            let datadir = data_dir.unwrap_or_else(|| dirs::home_dir().unwrap().join(".enigma"));
+            // The DB of a running core is locked, opening it fails.
+            let db = match network {
+                Some(network) => DB::new_for_network(datadir.join(network.name()), network, false),
+                None => DB::new(datadir, false),
+            };
+            let db = db.unwrap_or_else(|e| {
+                eprintln!("Failed opening the DB: {}", e);
+                process::exit(1);
+            });
+            let scopes = if scopes.is_empty() { RebuildScope::ALL.to_vec() } else { scopes };
+            match db.rebuild_indexes(&scopes, &REBUILD) {
+                Ok(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                Err(e) => {
+                    eprintln!("Failed rebuilding the indexes: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
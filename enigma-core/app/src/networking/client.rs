@@ -0,0 +1,460 @@
+//! # IPC client.
+//! A blocking client of the IPC protocol for Rust tooling, built with the `client` feature.
+//! It takes care of the framing, of the ids and of matching the replies to them, and of retrying:
+//! - an error the core marks as `retryable` is retried, after its `retryAfterMs` if it gave one,
+//! - a request that timed out is retried only if it doesn't write anything (see [`drain::MUTATING_TYPES`]),
+//!   a mutating one may have been applied and is returned as a `Timeout` to the caller.
+//!
+//! A retry is sent with the id of the first attempt, so it can be told apart from a new request in the logs of the core.
+//! The responses are returned as they came, as a `Value`, errors are mapped to a `ClientError::Server`.
+//!
+//! [`drain::MUTATING_TYPES`]: ../../common_u/drain/constant.MUTATING_TYPES.html
+
+use crate::common_u::drain;
+use crate::db::RebuildScope;
+use crate::networking::messages::*;
+use enigma_crypto::{hash::Keccak256, KeyPair};
+use enigma_tools_m::envelope::OutputEnvelope;
+use enigma_types::Hash256;
+use hex::{FromHex, ToHex};
+use serde_json::{self, Value};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, i32};
+use zmq;
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// How long to wait for each reply.
+    pub timeout: Duration,
+    /// How many times a request is sent again before its error is returned.
+    pub retries: u32,
+    /// How long to wait before sending again, when the core didn't say.
+    pub backoff: Duration,
+    /// The version of the responses to ask for, the core answers as for `DEFAULT_PROTOCOL_VERSION` if not given.
+    pub protocol_version: Option<u32>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions { timeout: Duration::from_secs(30), retries: 3, backoff: Duration::from_millis(500), protocol_version: None }
+    }
+}
+
+#[derive(Fail, Debug)]
+pub enum ClientError {
+    #[fail(display = "No reply from the core within {:?}", _0)]
+    Timeout(Duration),
+    #[fail(display = "The core returned an error: {}", _0)]
+    Server(ServerError),
+    #[fail(display = "Invalid reply from the core: {}", _0)]
+    Protocol(String),
+    #[fail(display = "The output envelope doesn't open: {}", _0)]
+    Envelope(String),
+    #[fail(display = "ZMQ error: {}", _0)]
+    Zmq(#[cause] zmq::Error),
+}
+
+impl From<zmq::Error> for ClientError {
+    fn from(e: zmq::Error) -> Self { ClientError::Zmq(e) }
+}
+
+/// An `Error` response of the core.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    pub msg: String,
+    pub retryable: bool,
+    pub retry_after: Option<Duration>,
+    /// `None` for the errors without details, and for the codes this client doesn't know, see `code`.
+    pub details: Option<IpcErrorDetails>,
+    /// The `code` of the details as sent, known or not.
+    pub code: Option<String>,
+    /// The whole response.
+    pub response: Value,
+}
+
+impl ServerError {
+    fn from_response(response: Value) -> Result<Self, ClientError> {
+        let msg = match response["msg"].as_str() {
+            Some(msg) => msg.to_string(),
+            None => return Err(ClientError::Protocol(format!("An error without msg: {}", response))),
+        };
+        let details = &response["details"];
+        Ok(ServerError {
+            msg,
+            retryable: response["retryable"].as_bool().unwrap_or(false),
+            retry_after: response["retryAfterMs"].as_u64().map(Duration::from_millis),
+            // A newer core may send codes this client doesn't know yet.
+            details: if details.is_null() { None } else { serde_json::from_value(details.clone()).ok() },
+            code: details["code"].as_str().map(str::to_string),
+            response,
+        })
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({})", self.msg, code),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+pub struct CoreClient {
+    context: zmq::Context,
+    socket: zmq::Socket,
+    addr: String,
+    options: ClientOptions,
+    /// Makes the ids of this client unlikely to collide with the ones of another client of the same core.
+    session: String,
+    next_id: u64,
+}
+
+impl CoreClient {
+    /// Connects to a core listening on `addr`, e.g. `tcp://localhost:5552`.
+    pub fn connect(addr: &str) -> Result<Self, ClientError> {
+        let context = zmq::Context::new();
+        let options = ClientOptions::default();
+        let socket = Self::socket(&context, addr, &options)?;
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or_default();
+        let session = format!("{:x}{:08x}", process::id(), nanos);
+        Ok(CoreClient { context, socket, addr: addr.to_string(), options, session, next_id: 0 })
+    }
+
+    pub fn with_options(mut self, options: ClientOptions) -> Result<Self, ClientError> {
+        self.socket = Self::socket(&self.context, &self.addr, &options)?;
+        self.options = options;
+        Ok(self)
+    }
+
+    pub fn options(&self) -> &ClientOptions { &self.options }
+
+    fn socket(context: &zmq::Context, addr: &str, options: &ClientOptions) -> Result<zmq::Socket, ClientError> {
+        let timeout = options.timeout.as_millis().min(i32::MAX as u128) as i32;
+        let socket = context.socket(zmq::REQ)?;
+        socket.set_rcvtimeo(timeout)?;
+        socket.set_sndtimeo(timeout)?;
+        // Don't keep a request that was given up on around when the socket is replaced.
+        socket.set_linger(0)?;
+        socket.connect(addr)?;
+        Ok(socket)
+    }
+
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{}-{}", self.session, self.next_id)
+    }
+
+    /// Sends `request` and returns the response, retrying as described in the module docs.
+    pub fn send(&mut self, request: IpcRequest) -> Result<Value, ClientError> {
+        let id = self.next_id();
+        self.send_with_id(request, id)
+    }
+
+    /// Like `send`, with an id of the caller's, it has to be 1 to 128 bytes of printable UTF-8
+    /// (see [`request_id`](../request_id/index.html)) and unique among the requests in flight.
+    pub fn send_with_id(&mut self, request: IpcRequest, id: String) -> Result<Value, ClientError> {
+        let safe = !drain::is_mutating(request.kind());
+        let mut msg = IpcMessageRequest::from_request(request, id);
+        msg.protocol_version = self.options.protocol_version;
+        let body = serde_json::to_vec(&msg).expect("A request always serializes");
+        let mut attempt = 0;
+        loop {
+            let result = self.round_trip(&msg.id, &body);
+            let wait = match &result {
+                Err(ClientError::Server(e)) if e.retryable => e.retry_after.unwrap_or(self.options.backoff),
+                Err(ClientError::Timeout(_)) if safe => self.options.backoff,
+                _ => return result,
+            };
+            if attempt == self.options.retries {
+                return result;
+            }
+            attempt += 1;
+            debug!("Retrying {} ({}/{}) in {:?}", msg.id, attempt, self.options.retries, wait);
+            thread::sleep(wait);
+        }
+    }
+
+    fn round_trip(&mut self, id: &str, body: &[u8]) -> Result<Value, ClientError> {
+        let mut reply = zmq::Message::new();
+        let sent = self.socket.send(body, 0).and_then(|_| self.socket.recv(&mut reply, 0));
+        match sent {
+            Ok(()) => (),
+            // A REQ socket can't send again before it got its reply, the lazy pirate way is to replace it.
+            Err(zmq::Error::EAGAIN) => {
+                self.socket = Self::socket(&self.context, &self.addr, &self.options)?;
+                return Err(ClientError::Timeout(self.options.timeout));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let response: Value =
+            serde_json::from_slice(&reply).map_err(|e| ClientError::Protocol(format!("The reply isn't JSON: {}", e)))?;
+        if response["id"] != id {
+            return Err(ClientError::Protocol(format!("Expected the reply to {}, got the one to {}", id, response["id"])));
+        }
+        if response["type"] == "Error" {
+            return Err(ClientError::Server(ServerError::from_response(response)?));
+        }
+        Ok(response)
+    }
+
+    pub fn get_registration_params(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetRegistrationParams) }
+
+    pub fn get_registration_history(&mut self, limit: Option<usize>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetRegistrationHistory { limit })
+    }
+
+    pub fn get_tip(&mut self, address: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetTip { input: address.to_string() })
+    }
+
+    pub fn get_tips(&mut self, addresses: Vec<String>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetTips { input: addresses })
+    }
+
+    pub fn get_all_tips(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetAllTips) }
+
+    pub fn get_all_addrs(&mut self, flag_orphans: bool) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetAllAddrs { flag_orphans })
+    }
+
+    pub fn get_delta(&mut self, address: &str, key: u32) -> Result<Value, ClientError> {
+        let input = IpcDelta { contract_address: Some(address.to_string()), key, ..Default::default() };
+        self.send(IpcRequest::GetDelta { input })
+    }
+
+    pub fn get_deltas(&mut self, ranges: Vec<IpcDeltasRange>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetDeltas { input: ranges })
+    }
+
+    pub fn get_contract(&mut self, address: &str, offset: Option<u64>, max_bytes: Option<u64>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetContract { input: address.to_string(), offset, max_bytes })
+    }
+
+    pub fn update_new_contract(&mut self, address: &str, bytecode: Vec<u8>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::UpdateNewContract { address: address.to_string(), bytecode })
+    }
+
+    pub fn update_new_contract_on_deployment(&mut self, address: &str, bytecode: &str, delta: IpcDelta) -> Result<Value, ClientError> {
+        self.send(IpcRequest::UpdateNewContractOnDeployment { address: address.to_string(), bytecode: bytecode.to_string(), delta })
+    }
+
+    pub fn remove_contract(&mut self, address: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::RemoveContract { address: address.to_string() })
+    }
+
+    pub fn update_deltas(&mut self, deltas: Vec<IpcDelta>, allow_orphan: bool) -> Result<Value, ClientError> {
+        self.send(IpcRequest::UpdateDeltas { deltas, allow_orphan })
+    }
+
+    pub fn remove_deltas(&mut self, ranges: Vec<IpcDeltasRange>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::RemoveDeltas { input: ranges })
+    }
+
+    pub fn new_task_encryption_key(&mut self, user_pubkey: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::NewTaskEncryptionKey { user_pubkey: user_pubkey.to_string() })
+    }
+
+    pub fn deploy_secret_contract(&mut self, task: IpcTask) -> Result<Value, ClientError> {
+        self.send(IpcRequest::DeploySecretContract { input: task })
+    }
+
+    pub fn compute_task(&mut self, task: IpcTask) -> Result<Value, ClientError> { self.send(IpcRequest::ComputeTask { input: task }) }
+
+    pub fn get_ptt_request(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetPTTRequest { block_number: None }) }
+
+    /// A PTT request for the epoch that was active at `block_number`.
+    pub fn get_ptt_request_at(&mut self, block_number: u64) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetPTTRequest { block_number: Some(block_number) })
+    }
+
+    pub fn ptt_response(&mut self, response: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::PTTResponse { input: PrincipalResponse { response: response.to_string() } })
+    }
+
+    pub fn recover_keys(&mut self, addresses: Option<Vec<String>>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::RecoverKeys { addresses })
+    }
+
+    pub fn get_health(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetHealth) }
+
+    pub fn set_epoch_params(&mut self, nonce: u64, first_block: u64, seed_commitment: Option<String>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::SetEpochParams { nonce, first_block, seed_commitment })
+    }
+
+    pub fn set_hosted_contracts(&mut self, addresses: Vec<String>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::SetHostedContracts { addresses })
+    }
+
+    pub fn get_contract_stats(&mut self, address: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::GetContractStats { input: address.to_string() })
+    }
+
+    pub fn verify_task_receipt(&mut self, receipt: IpcTaskReceipt) -> Result<Value, ClientError> {
+        self.send(IpcRequest::VerifyTaskReceipt { receipt })
+    }
+
+    pub fn get_audit_digest(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetAuditDigest) }
+
+    pub fn reload_config(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::ReloadConfig) }
+
+    pub fn drain(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::Drain) }
+
+    pub fn resume(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::Resume) }
+
+    pub fn get_drain_status(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::GetDrainStatus) }
+
+    pub fn run_maintenance(&mut self) -> Result<Value, ClientError> { self.send(IpcRequest::RunMaintenance) }
+
+    pub fn replay_task(&mut self, task_id: &str) -> Result<Value, ClientError> {
+        self.send(IpcRequest::ReplayTask { task_id: task_id.to_string() })
+    }
+
+    /// Refused unless the core was drained first, see `drain`.
+    pub fn rebuild_indexes(&mut self, scopes: Option<Vec<RebuildScope>>) -> Result<Value, ClientError> {
+        self.send(IpcRequest::RebuildIndexes { scopes })
+    }
+}
+
+/// Opens the envelope of a `ComputeTask` response to a task sent with `outputEnvelope`, with the secret key of the user
+/// who submitted it, and returns the encrypted output, which decrypts with the DH key of the task like `output` does.
+/// The envelope has to be the one `attested.envelopeHash` is the hash of, and what it holds the output of `attested.outputHash`.
+/// Whether the enclave signed `attested` is up to the caller, with `IpcComputeAttested::to_receipt`.
+pub fn open_output_envelope(response: &Value, user_key: &KeyPair) -> Result<Vec<u8>, ClientError> {
+    let result = &response["result"];
+    let attested: IpcComputeAttested = serde_json::from_value(result["attested"].clone())
+        .map_err(|e| ClientError::Protocol(format!("A compute result without attested fields: {}", e)))?;
+    let hex_field = |value: &Value, name: &str| -> Result<Vec<u8>, ClientError> {
+        let hex = value.as_str().ok_or_else(|| ClientError::Protocol(format!("A compute result without {}", name)))?;
+        hex.from_hex().map_err(|e| ClientError::Protocol(format!("{} isn't hex: {}", name, e)))
+    };
+    let envelope_hash = match &attested.envelope_hash {
+        Some(hash) => Hash256::from_hex(hash).map_err(|e| ClientError::Protocol(format!("envelopeHash isn't a hash: {}", e)))?,
+        None => return Err(ClientError::Protocol("The task wasn't sent with outputEnvelope".to_string())),
+    };
+    let bytes = hex_field(&result["envelope"], "envelope")?;
+    if bytes.keccak256() != envelope_hash {
+        return Err(ClientError::Envelope(format!("It isn't the one the receipt signed, {}", envelope_hash.to_hex())));
+    }
+    let envelope = OutputEnvelope::from_bytes(&bytes).map_err(|e| ClientError::Envelope(e.to_string()))?;
+    let output = envelope.open(user_key).map_err(|e| ClientError::Envelope(e.to_string()))?;
+    if output.keccak256().to_hex() != attested.output_hash {
+        return Err(ClientError::Envelope("It doesn't hold the output the receipt signed".to_string()));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// A core answering the requests with `replies` in turn, it sends the ids it got back when it's done.
+    fn fake_core(port: u16, replies: Vec<Value>) -> Receiver<Vec<String>> {
+        let (done, ids) = channel();
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::REP).unwrap();
+        socket.bind(&format!("tcp://*:{}", port)).unwrap();
+        thread::spawn(move || {
+            let _context = context;
+            let mut seen = Vec::new();
+            for mut reply in replies {
+                let msg: Value = serde_json::from_slice(&socket.recv_bytes(0).unwrap()).unwrap();
+                seen.push(msg["id"].as_str().unwrap().to_string());
+                reply["id"] = msg["id"].clone();
+                socket.send(&serde_json::to_vec(&reply).unwrap(), 0).unwrap();
+            }
+            done.send(seen).unwrap();
+        });
+        ids
+    }
+
+    fn client(port: u16) -> CoreClient {
+        let options = ClientOptions { timeout: Duration::from_millis(200), backoff: Duration::from_millis(1), ..Default::default() };
+        CoreClient::connect(&format!("tcp://localhost:{}", port)).unwrap().with_options(options).unwrap()
+    }
+
+    #[test]
+    fn test_client_retries_retryable() {
+        let busy = json!({"type": "Error", "msg": "busy", "retryable": true, "retryAfterMs": 1, "details": {"code": "Busy"}});
+        let ids = fake_core(2457, vec![busy.clone(), busy, json!({"type": "GetAllTips", "result": {"tips": []}})]);
+        let response = client(2457).get_all_tips().unwrap();
+        assert_eq!(response["result"]["tips"], json!([]));
+        // The same request all along.
+        let ids = ids.recv().unwrap();
+        assert!(ids.iter().all(|id| *id == ids[0]));
+    }
+
+    #[test]
+    fn test_client_maps_errors() {
+        let error = json!({"type": "Error", "msg": "unknown", "details": {"code": "ContractNotFound", "address": "00"}});
+        let unknown = json!({"type": "Error", "msg": "newer", "details": {"code": "SomethingNew"}});
+        let _ids = fake_core(2458, vec![error, unknown]);
+        let mut client = client(2458);
+        match client.get_contract("00", None, None) {
+            Err(ClientError::Server(e)) => {
+                assert!(!e.retryable);
+                assert_eq!(e.details, Some(IpcErrorDetails::ContractNotFound { address: "00".to_string() }));
+            }
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+        match client.get_health() {
+            Err(ClientError::Server(e)) => assert_eq!((e.details, e.code), (None, Some("SomethingNew".to_string()))),
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_open_output_envelope() {
+        let user = KeyPair::new().unwrap();
+        let output = b"encrypted output".to_vec();
+        let envelope = OutputEnvelope::seal(&output, &user.get_pubkey()).unwrap();
+        let response = |envelope: &OutputEnvelope, envelope_hash: Hash256| {
+            json!({"type": "ComputeTask", "result": {
+                "attested": {"exeCodeHash": "00", "inputsHash": "00", "prevDeltaHash": "00", "deltaHash": "00",
+                             "outputHash": output.keccak256().to_hex(), "gasLimit": 1, "usedGas": 1,
+                             "ethereumPayload": "", "ethereumAddress": "00", "envelopeHash": envelope_hash.to_hex()},
+                "envelope": envelope.to_bytes().to_hex(),
+            }})
+        };
+        assert_eq!(open_output_envelope(&response(&envelope, envelope.hash()), &user).unwrap(), output);
+
+        // Someone else's key.
+        match open_output_envelope(&response(&envelope, envelope.hash()), &KeyPair::new().unwrap()) {
+            Err(ClientError::Envelope(_)) => (),
+            other => panic!("Expected an envelope error, got {:?}", other),
+        }
+        // Another envelope than the signed one, or one whose hash was replaced along with it.
+        let mut tampered = envelope.clone();
+        tampered.ciphertext[0] ^= 1;
+        for (envelope, hash) in vec![(&tampered, envelope.hash()), (&tampered, tampered.hash())] {
+            match open_output_envelope(&response(envelope, hash), &user) {
+                Err(ClientError::Envelope(_)) => (),
+                other => panic!("Expected an envelope error, got {:?}", other),
+            }
+        }
+        // Sealed again around another output.
+        let other = OutputEnvelope::seal(b"another output", &user.get_pubkey()).unwrap();
+        assert!(open_output_envelope(&response(&other, other.hash()), &user).is_err());
+        assert!(open_output_envelope(&json!({"type": "ComputeTask", "result": {}}), &user).is_err());
+    }
+
+    #[test]
+    fn test_client_timeouts() {
+        // Nothing listens there.
+        let mut client = client(2459);
+        match client.get_all_tips() {
+            Err(ClientError::Timeout(_)) => (),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+        // A mutating request isn't sent twice, and the client can still be used afterwards.
+        let start = std::time::Instant::now();
+        assert!(client.remove_contract("00").is_err());
+        assert!(start.elapsed() < Duration::from_millis(200) * 2);
+        let _ids = fake_core(2459, vec![json!({"type": "GetAllTips", "result": {"tips": []}})]);
+        assert!(client.get_all_tips().is_ok());
+    }
+}
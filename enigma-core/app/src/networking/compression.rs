@@ -0,0 +1,120 @@
+//! # Response compression.
+//! A request can ask for a compressed response with `"accept_encoding": "zstd"`.
+//! The deltas are encrypted so they don't compress themselves, but their JSON encoding (an array of numbers) does, a lot.
+//!
+//! A response of at least [`threshold`] bytes is then sent as two frames: the [`ZSTD_MARKER`] frame and the compressed JSON.
+//! Every other response is the plain JSON frame as before, so a client that asks for compression must expect both.
+//! The marker can't be taken for a response, a JSON response always starts with `{`.
+//! A response that doesn't get smaller is never compressed.
+
+use failure::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zmq::Message;
+use zstd;
+
+/// The frame sent before a compressed response.
+pub const ZSTD_MARKER: &[u8] = b"zstd";
+
+/// Responses smaller than this are never compressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 8 * 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+static COMPRESSION_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_COMPRESSION_THRESHOLD);
+
+/// Responses of at least `threshold` bytes are compressed for the requests that accept it.
+pub fn set_threshold(threshold: usize) { COMPRESSION_THRESHOLD.store(threshold, Ordering::SeqCst) }
+
+pub fn threshold() -> usize { COMPRESSION_THRESHOLD.load(Ordering::SeqCst) }
+
+/// The encodings a request can accept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Zstd,
+}
+
+/// The frames to send for a serialized response.
+pub fn encode(response: Vec<u8>, accept: Option<Encoding>) -> Vec<Message> {
+    encode_above(response, accept, threshold())
+}
+
+fn encode_above(response: Vec<u8>, accept: Option<Encoding>, threshold: usize) -> Vec<Message> {
+    if accept != Some(Encoding::Zstd) || response.len() < threshold {
+        return vec![Message::from(&response)];
+    }
+    match zstd::encode_all(&response[..], ZSTD_LEVEL) {
+        Ok(ref compressed) if compressed.len() < response.len() => vec![Message::from(ZSTD_MARKER), Message::from(compressed)],
+        Ok(_) => vec![Message::from(&response)],
+        Err(e) => {
+            warn!("Failed compressing a response, sending it as is: {}", e);
+            vec![Message::from(&response)]
+        }
+    }
+}
+
+/// The inverse of [`encode`] for a client, the serialized responses of the frames of a reply.
+pub fn decode<'a, I: IntoIterator<Item = &'a [u8]>>(frames: I) -> Result<Vec<Vec<u8>>, Error> {
+    let mut responses = Vec::new();
+    let mut frames = frames.into_iter();
+    while let Some(frame) = frames.next() {
+        if frame == ZSTD_MARKER {
+            let compressed = frames.next().ok_or_else(|| format_err!("A compression marker without a response after it"))?;
+            responses.push(zstd::decode_all(compressed)?);
+        } else {
+            responses.push(frame.to_vec());
+        }
+    }
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate rand;
+    use self::rand::{RngCore, SeedableRng, rngs::StdRng};
+    use super::*;
+    use serde_json;
+
+    fn frames(messages: &[Message]) -> Vec<&[u8]> { messages.iter().map(|m| &m[..]).collect() }
+
+    #[test]
+    fn test_encode_decode() {
+        let response = format!("{{\"id\":\"1\",\"data\":{:?}}}", vec![7u8; 4096]).into_bytes();
+
+        let plain = encode_above(response.clone(), None, 0);
+        assert_eq!(frames(&plain), vec![&response[..]]);
+
+        let compressed = encode_above(response.clone(), Some(Encoding::Zstd), 0);
+        assert_eq!(compressed.len(), 2);
+        assert_eq!(&compressed[0][..], ZSTD_MARKER);
+        assert!(compressed[1].len() < response.len());
+        assert_eq!(decode(frames(&compressed)).unwrap(), vec![response.clone()]);
+
+        // Below the threshold.
+        assert_eq!(frames(&encode_above(response.clone(), Some(Encoding::Zstd), response.len() + 1)), vec![&response[..]]);
+    }
+
+    #[test]
+    fn test_skips_incompressible() {
+        // Like an encrypted payload.
+        let mut response = vec![0u8; 4096];
+        StdRng::seed_from_u64(0x1444).fill_bytes(&mut response);
+        let encoded = encode_above(response.clone(), Some(Encoding::Zstd), 0);
+        assert_eq!(frames(&encoded), vec![&response[..]]);
+    }
+
+    #[test]
+    fn test_decode_mixed() {
+        let mut messages = encode_above(b"{\"a\":1}".to_vec(), Some(Encoding::Zstd), 1 << 20);
+        messages.extend(encode_above(vec![b'1'; 1000], Some(Encoding::Zstd), 0));
+        let decoded = decode(frames(&messages)).unwrap();
+        assert_eq!(decoded, vec![b"{\"a\":1}".to_vec(), vec![b'1'; 1000]]);
+        assert!(decode(vec![ZSTD_MARKER]).is_err());
+    }
+
+    #[test]
+    fn test_accept_encoding() {
+        assert_eq!(serde_json::from_str::<Encoding>("\"zstd\"").unwrap(), Encoding::Zstd);
+        assert!(serde_json::from_str::<Encoding>("\"gzip\"").is_err());
+    }
+}
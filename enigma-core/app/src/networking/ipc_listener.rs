@@ -1,78 +1,307 @@
 use crate::networking::messages::*;
-use crate::db::DB;
+use crate::common_u::metrics::{self, METRICS};
+use crate::db::{AddressIndex, CapacityLimits, ContractCache, Mirror, MirrorStatus, P2PCalls, DB, DEFAULT_REGISTRATION_LOG_CAP};
+use crate::common_u::drain::DRAIN;
+use crate::common_u::errors::InternalErr;
+use crate::common_u::panics::{self, LockRecover, PANIC_BREAKER};
+use crate::networking::compression::{self, Encoding};
+use serde_json;
+use crate::networking::ipc_queue::IpcQueue;
+use crate::networking::maintenance::MaintenanceSchedule;
+use crate::networking::request_id;
+use futures::sync::mpsc::unbounded;
 use futures::{Future, Stream};
 use sgx_types::sgx_enclave_id_t;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio_zmq::prelude::*;
-use tokio_zmq::{Error, Multipart, Rep};
+use tokio_zmq::{Error, Multipart, Rep, Router};
+use zmq::Message;
+
+static PERSIST_TASK_DELTAS: AtomicBool = AtomicBool::new(false);
+
+/// Makes `ComputeTask` store the delta it produced itself,
+/// instead of leaving it to the p2p node to send it back with `UpdateDeltas`.
+pub fn set_persist_task_deltas(persist: bool) { PERSIST_TASK_DELTAS.store(persist, Ordering::SeqCst) }
+
+static REGISTRATION_LOG_CAP: AtomicUsize = AtomicUsize::new(DEFAULT_REGISTRATION_LOG_CAP);
+
+/// How many of the registrations `GetRegistrationParams` returned are kept for `GetRegistrationHistory`.
+pub fn set_registration_log_cap(cap: usize) { REGISTRATION_LOG_CAP.store(cap, Ordering::SeqCst) }
+
+static DEV_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Lets the tasks ask for a debug trace with `debugTrace`, only meant for a debug build on a developer's machine.
+pub fn set_dev_mode(dev_mode: bool) { DEV_MODE.store(dev_mode, Ordering::SeqCst) }
 
 pub struct IpcListener {
     _context: Arc<zmq::Context>,
-    rep_future: Box<dyn Future<Item = Rep, Error = Error>>,
+    conn_str: String,
+    maintenance: Option<MaintenanceSchedule>,
 }
 
 impl IpcListener {
     pub fn new(conn_str: &str) -> Self {
         let _context = Arc::new(zmq::Context::new());
-        let rep_future = Rep::builder(_context.clone()).bind(conn_str).build();
-        debug!("Binded to socket: {}", conn_str);
-        IpcListener { _context, rep_future }
+        IpcListener { _context, conn_str: conn_str.to_string(), maintenance: None }
     }
 
+    /// Queues a maintenance pass on `schedule` once serving, see [`maintenance`](../maintenance/index.html).
+    pub fn with_maintenance(mut self, schedule: MaintenanceSchedule) -> Self {
+        self.maintenance = Some(schedule);
+        self
+    }
+
+    /// Answers the requests one after the other on the calling thread.
     pub fn run<F>(self, f: F) -> impl Future<Item = (), Error = Error>
     where F: FnMut(Multipart) -> Multipart {
-        self.rep_future.and_then(|rep| {
+        let rep_future = Rep::builder(self._context.clone()).bind(&self.conn_str).build();
+        debug!("Binded to socket: {}", self.conn_str);
+        rep_future.and_then(|rep| {
             let (sink, stream) = rep.sink_stream(25).split();
             stream.map(f).forward(sink).map(|(_stream, _sink)| ())
         })
     }
+
+    /// Answers the requests with `f` on a thread of its own, behind a queue of at most `capacity` requests,
+    /// see [`ipc_queue`](../ipc_queue/index.html). `GetHealth` is answered from `probe` without entering the queue,
+    /// and so are `ReloadConfig` and the drain requests.
+    pub fn serve<F>(self, capacity: usize, probe: HealthProbe, f: F) -> impl Future<Item = (), Error = Error>
+    where F: FnMut(Multipart) -> Multipart + Send + 'static {
+        let (replies, reply_stream) = unbounded();
+        let queue = IpcQueue::spawn(capacity, f, replies).expect("Failed spawning the IPC handler thread");
+        if let Some(schedule) = self.maintenance {
+            schedule.spawn(queue.handle()).expect("Failed spawning the maintenance thread");
+        }
+        // A ROUTER socket, unlike a REP one, can take the next request before the last one was answered.
+        let router_future = Router::builder(self._context.clone()).bind(&self.conn_str).build();
+        debug!("Binded to socket: {} (queue of {})", self.conn_str, capacity);
+        router_future.and_then(move |router| {
+            let (sink, stream) = router.sink_stream(25).split();
+            let replies = reply_stream.map_err(|()| -> Error { unreachable!("An unbounded receiver never fails") });
+            stream
+                .filter_map(move |multipart| queue.admit(multipart, |body| handle_bypass(&probe, body)))
+                .select(replies)
+                .forward(sink)
+                .map(|(_stream, _sink)| ())
+        })
+    }
+}
+
+/// What `GetHealth` reads, it can be answered from another thread while the DB is busy with other requests.
+#[derive(Clone)]
+pub struct HealthProbe {
+    contracts: Arc<ContractCache>,
+    mirror: Option<Arc<Mirror>>,
+    capacity: CapacityLimits,
+    addresses: Arc<AddressIndex>,
+}
+
+impl HealthProbe {
+    pub fn new(db: &DB) -> Self {
+        HealthProbe { contracts: db.contract_cache(), mirror: db.mirror.clone(), capacity: db.capacity_limits(), addresses: db.address_index() }
+    }
+
+    fn warmup_complete(&self) -> bool { self.contracts.warmup_complete() }
+
+    fn mirror_status(&self) -> Option<MirrorStatus> { self.mirror.as_ref().map(|mirror| mirror.status()) }
+
+    // The usage is the one the `DbSizeSampler` last saw, the DB may be busy.
+    fn capacity(&self) -> IpcCapacity {
+        let (contracts, db_bytes) = METRICS.db_size();
+        IpcCapacity {
+            db_bytes,
+            max_db_bytes: self.capacity.max_db_bytes,
+            contracts,
+            max_contracts: self.capacity.max_contracts,
+            max_contract_delta_bytes: self.capacity.max_contract_delta_bytes,
+        }
+    }
 }
 
+/// Answers every frame of `request`, a frame whose handler panicked is answered with an `InternalErr`
+/// and the next ones are handled as usual, see [`panics`](../../common_u/panics/index.html).
 pub fn handle_message(db: &mut DB, request: Multipart, spid: &str, eid: sgx_enclave_id_t, retries: u32) -> Multipart {
     let mut responses = Multipart::new();
-    for msg in request {
-        let msg: IpcMessageRequest = msg.into();
-        let id = msg.id.clone();
+    for frame in request {
+        // Every rocksdb write is atomic, but a handler can make several and panic between two of them. The writes that
+        // went through stay, like for a handler that fails halfway, what it kept in memory is rebuilt before the next frame.
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| handle_frame(db, &frame, spid, eid, retries)));
+        let (response, accept_encoding) = handled.unwrap_or_else(|payload| (handler_panicked(db, &frame, &*payload), None));
+        let response = serde_json::to_vec(&response).unwrap();
+        for encoded in compression::encode(response, accept_encoding) {
+            responses.push_back(encoded);
+        }
+    }
+    responses
+}
+
+fn handler_panicked(db: &mut DB, frame: &Message, payload: &(dyn Any + Send)) -> IpcMessageResponse {
+    let msg = panics::panic_message(payload);
+    error!("An IPC handler panicked: {}", msg);
+    METRICS.record_handler_panic();
+    let poisoned = panics::poisoned_mutexes();
+    if !poisoned.is_empty() {
+        warn!("The handler panicked holding the {} mutexes, they're used as they were left", poisoned.join(", "));
+    }
+    let mut breaker = PANIC_BREAKER.lock_recover("Panic breaker");
+    if let Err(e) = db.reset_after_panic() {
+        error!("Failed rebuilding the state of the handlers after a panic: {}", e);
+        breaker.trip();
+        METRICS.set_panic_breaker_tripped();
+    }
+    if breaker.record(Instant::now()) {
+        error!("The IPC handlers keep panicking, reporting them as unhealthy");
+        METRICS.set_panic_breaker_tripped();
+    }
+    drop(breaker);
+    let err: failure::Error = InternalErr { msg }.into();
+    METRICS.record_error(&metrics::error_code(&err));
+    let response: Result<IpcResponse, failure::Error> = Err(err);
+    // Only a request with a valid id gets to its handler.
+    let id = request_id::frame_id(frame).echo().to_string();
+    IpcMessageResponse::from_response(response.unwrap_or_error(), id)
+}
+
+/// The request of a frame, or the response refusing it if its id is invalid or it doesn't parse.
+/// It's the only parsing of the frames the socket receives, it must not panic whatever the bytes, see `fuzz/`.
+pub fn parse_request(frame: &[u8]) -> Result<IpcMessageRequest, IpcMessageResponse> {
+    // Before the request is parsed, so a request that doesn't parse is still answered under its id.
+    let id = request_id::checked_id(frame)?;
+    match serde_json::from_slice::<IpcMessageRequest>(frame) {
+        // The id that was checked, even if the parser would have read another one.
+        Ok(msg) => Ok(IpcMessageRequest { id, ..msg }),
+        Err(e) => {
+            METRICS.record_error("invalid_request");
+            let response: Result<IpcResponse, failure::Error> = Err(format_err!("Failed parsing the request: {}", e));
+            Err(IpcMessageResponse::from_response(response.unwrap_or_error(), id))
+        }
+    }
+}
+
+fn handle_frame(db: &mut DB, frame: &Message, spid: &str, eid: sgx_enclave_id_t, retries: u32) -> (IpcMessageResponse, Option<Encoding>) {
+    let msg = match parse_request(frame) {
+        Ok(msg) => msg,
+        Err(response) => return (response, None),
+    };
+    let id = msg.id;
+    let accept_encoding = msg.accept_encoding;
+    let protocol_version = msg.protocol_version.unwrap_or(DEFAULT_PROTOCOL_VERSION);
+    let kind = msg.request.kind();
+    let start = Instant::now();
+    let response_msg = match msg.request {
+        IpcRequest::GetRegistrationParams => {
+            let cap = REGISTRATION_LOG_CAP.load(Ordering::SeqCst);
+            handling::get_registration_params(db, eid, spid, retries, cap)
+        }
+        IpcRequest::GetRegistrationHistory { limit } => handling::get_registration_history(db, limit),
+        IpcRequest::GetTip { input } => handling::get_tip(db, &input),
+        IpcRequest::GetTips { input } => handling::get_tips(db, &input),
+        IpcRequest::GetAllTips => handling::get_all_tips(db),
+        IpcRequest::GetAllAddrs { flag_orphans } => handling::get_all_addrs(db, flag_orphans),
+        IpcRequest::GetDelta { input } => handling::get_delta(db, input),
+        IpcRequest::GetDeltas { input } => handling::get_deltas(db, &input),
+        IpcRequest::GetContract { input, offset, max_bytes } => handling::get_contract(db, &input, offset, max_bytes),
+        IpcRequest::UpdateNewContract { address, bytecode } => handling::update_new_contract(db, address, &bytecode),
+        IpcRequest::UpdateNewContractOnDeployment { address, bytecode, delta } => handling::update_new_contract_on_deployment(db, address, &bytecode, delta),
+        IpcRequest::RemoveContract {address } => handling::remove_contract(db, address),
+        IpcRequest::UpdateDeltas { deltas, allow_orphan } => handling::update_deltas(db, deltas, allow_orphan),
+        IpcRequest::RemoveDeltas { input } => handling::remove_deltas(db, input),
+        IpcRequest::NewTaskEncryptionKey { user_pubkey } => handling::get_dh_user_key( &user_pubkey, eid),
+        IpcRequest::DeploySecretContract { input } => handling::deploy_contract(db, input, eid),
+        IpcRequest::ComputeTask { input } => handling::compute_task(db, input, eid),
+        IpcRequest::GetPTTRequest { block_number } => handling::get_ptt_req(db, block_number, eid),
+        IpcRequest::PTTResponse { input } => handling::ptt_response(db, &input, eid),
+        IpcRequest::RecoverKeys { addresses } => handling::recover_keys(db, addresses, eid),
+        IpcRequest::GetHealth => handling::get_health(&HealthProbe::new(db)),
+        IpcRequest::SetEpochParams { nonce, first_block, seed_commitment } => {
+            handling::set_epoch_params(nonce, first_block, seed_commitment)
+        }
+        IpcRequest::SetHostedContracts { addresses } => handling::set_hosted_contracts(db, &addresses),
+        IpcRequest::GetContractStats { input } => handling::get_contract_stats(db, &input),
+        IpcRequest::VerifyTaskReceipt { receipt } => handling::verify_task_receipt(db, receipt),
+        IpcRequest::GetAuditDigest => handling::get_audit_digest(eid),
+        IpcRequest::ReloadConfig => handling::reload_config(),
+        IpcRequest::Drain => handling::drain(),
+        IpcRequest::Resume => handling::resume(),
+        IpcRequest::GetDrainStatus => handling::get_drain_status(),
+        IpcRequest::RunMaintenance => handling::run_maintenance(db, REGISTRATION_LOG_CAP.load(Ordering::SeqCst)),
+        IpcRequest::ReplayTask { task_id } => handling::replay_task(db, &task_id, eid),
+        IpcRequest::RebuildIndexes { scopes } => handling::rebuild_indexes(db, &DRAIN, scopes),
+        #[cfg(test)]
+        IpcRequest::TestPanic { message } => panic!("{}", message),
+    };
+    record_metrics(kind, start, &response_msg);
+    let response = response_msg.unwrap_or_error().for_protocol(protocol_version);
+    (IpcMessageResponse::from_response(response, id), accept_encoding)
+}
+
+/// Answers the requests that bypass the queue, the only ones `IpcQueue` hands to it.
+pub fn handle_bypass(probe: &HealthProbe, request: Multipart) -> Multipart {
+    let mut responses = Multipart::new();
+    for frame in request {
+        let msg = match parse_request(&frame) {
+            Ok(msg) => msg,
+            Err(response) => {
+                responses.push_back(response.into());
+                continue;
+            }
+        };
+        let id = msg.id;
+        let kind = msg.request.kind();
+        let start = Instant::now();
         let response_msg = match msg.request {
-            IpcRequest::GetRegistrationParams => handling::get_registration_params(eid, spid, retries),
-            IpcRequest::GetTip { input } => handling::get_tip(db, &input),
-            IpcRequest::GetTips { input } => handling::get_tips(db, &input),
-            IpcRequest::GetAllTips => handling::get_all_tips(db),
-            IpcRequest::GetAllAddrs => handling::get_all_addrs(db),
-            IpcRequest::GetDelta { input } => handling::get_delta(db, input),
-            IpcRequest::GetDeltas { input } => handling::get_deltas(db, &input),
-            IpcRequest::GetContract { input } => handling::get_contract(db, &input),
-            IpcRequest::UpdateNewContract { address, bytecode } => handling::update_new_contract(db, address, &bytecode),
-            IpcRequest::UpdateNewContractOnDeployment { address, bytecode, delta } => handling::update_new_contract_on_deployment(db, address, &bytecode, delta),
-            IpcRequest::RemoveContract {address } => handling::remove_contract(db, address),
-            IpcRequest::UpdateDeltas { deltas } => handling::update_deltas(db, deltas),
-            IpcRequest::RemoveDeltas { input } => handling::remove_deltas(db, input),
-            IpcRequest::NewTaskEncryptionKey { user_pubkey } => handling::get_dh_user_key( &user_pubkey, eid),
-            IpcRequest::DeploySecretContract { input } => handling::deploy_contract(db, input, eid),
-            IpcRequest::ComputeTask { input } => handling::compute_task(db, input, eid),
-            IpcRequest::GetPTTRequest => handling::get_ptt_req(eid),
-            IpcRequest::PTTResponse { input } => handling::ptt_response(db, &input, eid),
+            IpcRequest::GetHealth => handling::get_health(probe),
+            IpcRequest::ReloadConfig => handling::reload_config(),
+            IpcRequest::Drain => handling::drain(),
+            IpcRequest::Resume => handling::resume(),
+            IpcRequest::GetDrainStatus => handling::get_drain_status(),
+            _ => unreachable!("{} doesn't bypass the queue", kind),
         };
+        METRICS.record_request(kind, start.elapsed());
         let msg = IpcMessageResponse::from_response(response_msg.unwrap_or_error(), id);
         responses.push_back(msg.into());
     }
     responses
 }
 
+fn record_metrics(kind: &'static str, start: Instant, response: &Result<IpcResponse, failure::Error>) {
+    METRICS.record_request(kind, start.elapsed());
+    match response {
+        Err(e) => METRICS.record_error(&metrics::error_code(e)),
+        Ok(IpcResponse::ComputeTask { result: IpcResults::ComputeResult { attested, .. } }) => METRICS.record_task_gas(attested.used_gas),
+        Ok(IpcResponse::DeploySecretContract { result: IpcResults::DeployResult { attested, .. } }) => METRICS.record_task_gas(attested.used_gas),
+        Ok(IpcResponse::FailedTask { result: IpcResults::FailedTask { used_gas, .. } }) => METRICS.record_task_gas(*used_gas),
+        Ok(_) => (),
+    }
+}
+
 
 // TODO: Make sure that every ? that doesn't require responding with a empty Message is replaced with an appropriate handling
 pub(self) mod handling {
     #![allow(clippy::needless_pass_by_value)]
-    use crate::common_u::errors::P2PErr;
-    use crate::db::{CRUDInterface, DeltaKey, P2PCalls, Stype, DB};
+    use super::{HealthProbe, DEV_MODE, PERSIST_TASK_DELTAS};
+    use crate::common_u::errors::{DBErr, DBErrKind, NotDrainedErr, P2PErr};
+    use crate::db::{CRUDInterface, Delta, DeltaKey, HostingMode, JournaledTask, P2PCalls, RebuildScope, RegistrationRecord, Stype, DB,
+                    DEFAULT_TASK_JOURNAL_CAP, REBUILD};
+    use crate::common_u::drain::{DrainState, DRAIN};
+    use crate::common_u::epoch::{EpochParams, EPOCH};
+    use crate::common_u::recovery::RECOVERY;
+    use crate::common_u::metrics::METRICS;
+    use crate::common_u::rate_limit::RATE_LIMITS;
     use crate::km_u;
     use crate::networking::messages::*;
     use crate::esgx::equote;
     use crate::wasm_u::*;
     use enigma_crypto::hash::Keccak256;
+    use enigma_tools_m::signable::{ExecuteReceipt, Signable};
+    use crate::common_u::panics::LockRecover;
     use enigma_tools_u::esgx::equote as equote_tools;
-    use enigma_tools_u::attestation_service::{service::AttestationService, constants::ATTESTATION_SERVICE_URL};
-    use enigma_types::ContractAddress;
+    use enigma_tools_u::attestation_service::{service::{AttestationService, Quote}, constants::ATTESTATION_SERVICE_URL};
+    use enigma_types::{ContractAddress, Hash256, PubKey, ReplayReceipt, ReplayResult};
     use failure::Error;
     use hex::{FromHex, ToHex};
     use rmp_serde::Deserializer;
@@ -80,6 +309,8 @@ pub(self) mod handling {
     use serde_json::Value;
     use sgx_types::sgx_enclave_id_t;
     use std::str;
+    use std::sync::atomic::Ordering;
+    use std::time::{SystemTime, UNIX_EPOCH};
     use common_u::errors;
 
     type ResponseResult = Result<IpcResponse, Error>;
@@ -93,43 +324,198 @@ pub(self) mod handling {
                 used_gas: self.used_gas,
                 output: self.output.to_hex(),
                 signature: self.signature.to_hex(),
+                sig_forms: IpcSigForms::default(),
+                forbidden: self.forbidden,
+                debug_trace: self.trace,
+                epoch: IpcEpoch::current(),
             };
             IpcResponse::FailedTask { result }
         }
     }
 
     impl WasmTaskResult {
-        pub fn into_execute_response(self) -> IpcResponse {
+        /// `delta` is what `task_delta` made of `self.delta`.
+        pub fn into_execute_response(self, delta: Option<IpcDelta>) -> IpcResponse {
             let result = IpcResults::ComputeResult {
-                used_gas: self.used_gas,
-                output: self.output.to_hex(),
-                delta: self.delta.into(),
-                ethereum_address: self.eth_contract_addr.to_hex(),
-                ethereum_payload: self.eth_payload.to_hex(),
+                attested: (&self.execute_receipt()).into(),
                 signature: self.signature.to_hex(),
+                sig_forms: IpcSigForms::default(),
+                output: self.output.to_hex(),
+                envelope: self.envelope.as_ref().map(|envelope| envelope.to_hex()),
+                delta,
+                supplemental: IpcTaskSupplemental { epoch: IpcEpoch::current(), pre_code_hash: None },
+                debug_trace: self.trace,
+                flat: None,
             };
             IpcResponse::ComputeTask { result }
         }
 
-        pub fn into_deploy_response(self, bytecode: &[u8]) -> IpcResponse {
+        pub fn into_deploy_response(self) -> IpcResponse {
             let result = IpcResults::DeployResult {
-                pre_code_hash: bytecode.keccak256().to_hex(),
-                used_gas: self.used_gas,
-                output: self.output.to_hex(), // TODO: Return output
-                delta: self.delta.into(),
-                ethereum_address: self.eth_contract_addr.to_hex(),
-                ethereum_payload: self.eth_payload.to_hex(),
+                attested: IpcDeployAttested::from(&self.deploy_receipt()),
                 signature: self.signature.to_hex(),
+                sig_forms: IpcSigForms::default(),
+                output: self.output.to_hex(),
+                delta: self.delta.into(),
+                supplemental: IpcTaskSupplemental { epoch: IpcEpoch::current(), pre_code_hash: Some(self.pre_code_hash.to_hex()) },
+                debug_trace: self.trace,
+                flat: None,
             };
             IpcResponse::DeploySecretContract { result }
         }
     }
 
+    /// Refuses a task assigned against other deltas than the local ones, its delta would conflict with the chain.
+    fn check_expected_tip(db: &DB, address: ContractAddress, expected: &IpcTipRef) -> Result<(), Error> {
+        let expected_hash = Hash256::from_hex(&expected.hash)?;
+        let local = db.get_chain_hash(&address)?;
+        match local {
+            Some(chain) if chain.tip == expected.key && chain.hash == expected_hash => Ok(()),
+            _ => {
+                let local_tip = local.map(|chain| (chain.tip, chain.hash));
+                Err(errors::StateBehindErr { address: address.to_hex(), local_tip, expected_key: expected.key }.into())
+            }
+        }
+    }
+
+    /// The public key of a user from its hex, it's refused unless it's 64 bytes.
+    fn user_pubkey(hex: &str) -> Result<[u8; 64], Error> {
+        let bytes: Vec<u8> = hex.from_hex()?;
+        if bytes.len() != 64 {
+            bail!("A user public key is 64 bytes, got {}", bytes.len());
+        }
+        let mut pubkey = [0u8; 64];
+        pubkey.copy_from_slice(&bytes);
+        Ok(pubkey)
+    }
+
+    /// A trace can only be asked for in dev mode, in production the task is refused before it runs.
+    fn check_debug_trace(requested: bool) -> Result<(), Error> {
+        if requested && !DEV_MODE.load(Ordering::SeqCst) {
+            return Err(errors::DebugTraceDisabledErr.into());
+        }
+        Ok(())
+    }
+
+    /// The delta of a task as it's sent to the p2p node, `None` if the task didn't change the state.
+    /// `tip` is the key of the last delta of the contract when the task was executed, the produced delta must follow it.
+    /// If `persist` is set the delta is also stored.
+    pub(crate) fn task_delta(db: &mut DB, tip: Option<u32>, delta: &Delta, persist: bool) -> Result<Option<IpcDelta>, Error> {
+        if delta.value.is_empty() {
+            return Ok(None);
+        }
+        let key = delta.key.key_type.unwrap_delta();
+        let expected = tip.map_or(0, |tip| tip + 1);
+        if key != expected {
+            bail!("The task produced delta {} of {}, but the next delta is {}", key, delta.key.contract_address.to_hex(), expected);
+        }
+        if persist {
+            db.check_delta_capacity(&[(delta.key.contract_address, delta.value.len())])?;
+            db.create(&delta.key, &delta.value[..])?;
+        }
+        Ok(Some(delta.clone().into()))
+    }
+
     #[logfn(TRACE)]
-    pub fn get_registration_params(eid: sgx_enclave_id_t, spid: &str, retries: u32) -> ResponseResult {
+    pub fn get_health(probe: &HealthProbe) -> ResponseResult {
+        let result = IpcResults::Health {
+            enclave_healthy: METRICS.enclave_healthy(),
+            enclave_degraded: METRICS.enclave_degraded(),
+            warmup_complete: probe.warmup_complete(),
+            recovery: RECOVERY.lock_recover("Recovery").progress(),
+            mirror: probe.mirror_status(),
+            handlers_healthy: !METRICS.panic_breaker_tripped(),
+            draining: DRAIN.is_draining(),
+            capacity: probe.capacity(),
+            address_index: probe.addresses.stats(),
+        };
+        Ok(IpcResponse::GetHealth { result })
+    }
+
+    fn parse_addresses(addresses: &[String]) -> Result<Vec<ContractAddress>, Error> {
+        Ok(addresses.iter().map(|a| ContractAddress::from_hex(a)).collect::<Result<Vec<_>, _>>()?)
+    }
+
+    #[logfn(TRACE)]
+    pub fn set_epoch_params(nonce: u64, first_block: u64, seed_commitment: Option<String>) -> ResponseResult {
+        let seed_commitment = match seed_commitment {
+            Some(commitment) => Some(Hash256::from_hex(&commitment)?),
+            None => None,
+        };
+        let status = if EPOCH.lock_recover("Epoch").set(EpochParams { nonce, first_block, seed_commitment }) {
+            Status::Ok
+        } else {
+            warn!("Ignoring the params of epoch {}, a newer epoch is already known", nonce);
+            Status::Error
+        };
+        Ok(IpcResponse::SetEpochParams { result: IpcResults::Status(status) })
+    }
+
+    #[logfn(TRACE)]
+    pub fn set_hosted_contracts(db: &mut DB, addresses: &[String]) -> ResponseResult {
+        let addresses = parse_addresses(addresses)?;
+        if db.hosting().mode() != HostingMode::ExplicitList {
+            warn!("Setting the hosted contracts in {} mode, they're only used in explicit-list mode", db.hosting().mode());
+        }
+        info!("Hosting {} contracts", addresses.len());
+        db.set_hosted_contracts(addresses)?;
+        Ok(IpcResponse::SetHostedContracts { result: IpcResults::Status(Status::Ok) })
+    }
+
+    pub fn reload_config() -> ResponseResult {
+        let config = RATE_LIMITS.lock_recover("Rate limits").reload()?;
+        info!("Reloaded the rate limits: {:?}", config);
+        Ok(IpcResponse::ReloadConfig { result: IpcResults::RateLimits(config) })
+    }
+
+    #[logfn(TRACE)]
+    pub fn drain() -> ResponseResult {
+        let in_flight = DRAIN.start();
+        Ok(IpcResponse::Drain { result: IpcResults::DrainStatus { draining: true, in_flight, rebuild: REBUILD.progress() } })
+    }
+
+    #[logfn(TRACE)]
+    pub fn resume() -> ResponseResult {
+        DRAIN.resume();
+        let result = IpcResults::DrainStatus { draining: false, in_flight: DRAIN.in_flight(), rebuild: REBUILD.progress() };
+        Ok(IpcResponse::Resume { result })
+    }
+
+    #[logfn(TRACE)]
+    pub fn get_drain_status() -> ResponseResult {
+        let result = IpcResults::DrainStatus { draining: DRAIN.is_draining(), in_flight: DRAIN.in_flight(), rebuild: REBUILD.progress() };
+        Ok(IpcResponse::GetDrainStatus { result })
+    }
+
+    #[logfn(TRACE)]
+    pub fn run_maintenance(db: &DB, registration_cap: usize) -> ResponseResult {
+        let report = db.run_maintenance(registration_cap)?;
+        info!("Maintenance removed {:?}, reclaimed {} bytes", report.removed(), report.reclaimed_bytes);
+        METRICS.record_maintenance(&report.removed(), report.reclaimed_bytes);
+        Ok(IpcResponse::RunMaintenance { result: IpcResults::Maintenance(report) })
+    }
+
+    /// Refused unless the core is drained and no mutating request is left in the queue, a write landing in the middle
+    /// of the scan would be lost or undone by the batch.
+    #[logfn(TRACE)]
+    pub fn rebuild_indexes(db: &DB, drain: &DrainState, scopes: Option<Vec<RebuildScope>>) -> ResponseResult {
+        let (draining, in_flight) = (drain.is_draining(), drain.in_flight());
+        if !draining || in_flight > 0 {
+            return Err(NotDrainedErr { draining, in_flight }.into());
+        }
+        let scopes = scopes.filter(|scopes| !scopes.is_empty()).unwrap_or_else(|| RebuildScope::ALL.to_vec());
+        info!("Rebuilding {:?}", scopes);
+        let report = db.rebuild_indexes(&scopes, &REBUILD)?;
+        info!("Rebuilt the indexes of {} contracts, fixed {:?}", report.contracts, report.fixed());
+        Ok(IpcResponse::RebuildIndexes { result: IpcResults::Rebuild(report) })
+    }
+
+    #[logfn(TRACE)]
+    pub fn get_registration_params(db: &DB, eid: sgx_enclave_id_t, spid: &str, retries: u32, log_cap: usize) -> ResponseResult {
         let sigining_key = equote::get_register_signing_address(eid)?;
 
         let enc_quote = equote_tools::retry_quote(eid, spid, 18)?;
+        let mr_enclave = Quote::from_base64(&enc_quote)?.report_body.mr_enclave;
 
         // *Important* `option_env!()` runs on *Compile* time.
         // This means that if you want Simulation mode you need to run `export SGX_MODE=SW` Before compiling.
@@ -145,28 +531,149 @@ pub(self) mod handling {
             (sig, report)
         };
 
-        let result = IpcResults::RegistrationParams { signing_key: sigining_key.to_hex(), report: report_hex, signature };
+        let record = RegistrationRecord {
+            index: 0,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            signing_key: sigining_key.to_hex(),
+            report: report_hex,
+            signature,
+            mr_enclave: mr_enclave.to_hex(),
+        };
+        record_registration(db, &record, log_cap);
 
+        let result = IpcResults::RegistrationParams { signing_key: record.signing_key, report: record.report, signature: record.signature };
         Ok(IpcResponse::GetRegistrationParams { result })
     }
 
+    /// Failing to log a registration doesn't fail it, the worker can still register.
+    pub(crate) fn record_registration(db: &DB, record: &RegistrationRecord, log_cap: usize) {
+        match db.append_registration(record.clone(), log_cap) {
+            Ok(index) => info!("Recorded registration {} with signing key {}", index, record.signing_key),
+            Err(e) => error!("Failed recording the registration with signing key {}: {}", record.signing_key, e),
+        }
+    }
+
+    #[logfn(TRACE)]
+    pub fn get_registration_history(db: &DB, limit: Option<usize>) -> ResponseResult {
+        let result = IpcResults::RegistrationHistory(db.get_registrations(limit)?);
+        Ok(IpcResponse::GetRegistrationHistory { result })
+    }
+
     #[logfn(TRACE)]
     pub fn get_tip(db: &DB, input: &str) -> ResponseResult {
         let address = ContractAddress::from_hex(&input)?;
         let (tip_key, tip_data) = db.get_tip::<DeltaKey>(&address)?;
 
         let key = tip_key.key_type.unwrap_delta();
-        let delta = IpcDelta { contract_address: None, key, data: Some(tip_data) };
+        let chain_hash = db.get_chain_hash(&address)?.map(|chain| chain.hash.to_hex());
+        let bounds = db.get_delta_tips(&address)?.map(Into::into);
+        let delta = IpcDelta { contract_address: None, key, data: Some(tip_data), chain_hash, bounds };
         Ok(IpcResponse::GetTip { result: delta })
     }
 
+    #[logfn(TRACE)]
+    pub fn get_contract_stats(db: &DB, input: &str) -> ResponseResult {
+        let address = ContractAddress::from_hex(&input)?;
+        let bytecode_size = db.get_contract_cached(address)?.len() as u64;
+        let chain = db.get_chain_hash(&address)?;
+        let result = IpcResults::ContractStats {
+            address: input.to_string(),
+            bytecode_size,
+            tip: chain.map(|chain| chain.tip),
+            chain_hash: chain.map(|chain| chain.hash.to_hex()),
+            delta_bytes: db.contract_delta_bytes(address)?,
+            max_delta_bytes: db.capacity_limits().max_contract_delta_bytes,
+        };
+        Ok(IpcResponse::GetContractStats { result })
+    }
+
+    #[logfn(TRACE)]
+    pub fn verify_task_receipt(db: &DB, receipt: IpcTaskReceipt) -> ResponseResult {
+        let verdict = receipt_verdict(db, &receipt)?;
+        let result = IpcResults::ReceiptVerdict { task_id: receipt.task_id, verdict };
+        Ok(IpcResponse::VerifyTaskReceipt { result })
+    }
+
+    fn decode_exact(value: &str, out: &mut [u8], field: &str) -> Result<(), Error> {
+        let bytes: Vec<u8> = value.from_hex()?;
+        if bytes.len() != out.len() {
+            bail!("{} must be {} bytes, got {}", field, out.len(), bytes.len());
+        }
+        out.copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Rebuilds the `ExecuteReceipt` the worker signed, filling in what's missing from the stored contract,
+    /// and checks it against the stored deltas. Errors are for receipts that can't be checked at all.
+    pub(crate) fn receipt_verdict(db: &DB, input: &IpcTaskReceipt) -> Result<ReceiptVerdict, Error> {
+        let address = ContractAddress::from_hex(&input.address)?;
+        let stored_delta = |key: u32| db.read(&DeltaKey::new(address, Stype::Delta(key))).ok();
+
+        let mut signer = [0u8; 20];
+        match &input.worker_address {
+            Some(worker) => decode_exact(worker, &mut signer, "workerAddress")?,
+            None => {
+                let registrations = db.get_registrations(Some(1))?;
+                let last = registrations.first().ok_or_else(|| format_err!("No registration was recorded, workerAddress is required"))?;
+                decode_exact(&last.signing_key, &mut signer, "The registered signing key")?;
+            }
+        }
+        let exe_code_hash = match &input.exe_code_hash {
+            Some(hash) => Hash256::from_hex(hash)?,
+            None => db.get_contract(address)?.keccak256(),
+        };
+        let prev_delta_hash = match (&input.prev_delta_hash, input.delta_key) {
+            (Some(hash), _) => Hash256::from_hex(hash)?,
+            (None, Some(key)) if key > 0 => match stored_delta(key - 1) {
+                Some(prev) => prev.keccak256(),
+                None => return Ok(ReceiptVerdict::UnknownDelta),
+            },
+            (None, _) => bail!("prevDeltaHash is required without a deltaKey that follows a stored delta"),
+        };
+        let mut ethereum_address = [0u8; 20];
+        if let Some(eth_address) = &input.ethereum_address {
+            decode_exact(eth_address, &mut ethereum_address, "ethereumAddress")?;
+        }
+        let receipt = ExecuteReceipt {
+            exe_code_hash,
+            inputs_hash: Hash256::from_hex(&input.inputs_hash)?,
+            prev_delta_hash,
+            delta_hash: Hash256::from_hex(&input.delta_hash)?,
+            output_hash: Hash256::from_hex(&input.output_hash)?,
+            gas_limit: input.gas_limit,
+            used_gas: input.used_gas,
+            ethereum_payload: input.ethereum_payload.from_hex()?,
+            ethereum_address,
+            envelope_hash: match &input.envelope_hash {
+                Some(hash) => Some(Hash256::from_hex(hash)?),
+                None => None,
+            },
+        };
+        let mut signature = [0u8; 65];
+        decode_exact(&input.signature, &mut signature, "signature")?;
+        // A signature that doesn't even recover is as bad as one by someone else.
+        if !receipt.verify(&signature, &signer).unwrap_or(false) {
+            return Ok(ReceiptVerdict::BadSignature);
+        }
+
+        if let Some(key) = input.delta_key {
+            match stored_delta(key) {
+                None => return Ok(ReceiptVerdict::UnknownDelta),
+                Some(delta) if delta.keccak256() != receipt.delta_hash => return Ok(ReceiptVerdict::DeltaMismatch),
+                Some(_) => (),
+            }
+        }
+        Ok(ReceiptVerdict::Valid)
+    }
+
     #[logfn(TRACE)]
     pub fn get_tips(db: &DB, input: &[String]) -> ResponseResult {
         let mut tips_results = Vec::with_capacity(input.len());
-        let addresses : Vec<ContractAddress> = input.iter().map(|data| ContractAddress::from_hex(&data).unwrap()).collect();
+        let addresses = input.iter().map(|data| ContractAddress::from_hex(&data)).collect::<Result<Vec<_>, _>>()?;
         let tips = db.get_tips::<DeltaKey>(&addresses)?;
         for (key, data) in tips {
-            let delta = IpcDelta::from_delta_key(key, &data)?;
+            let bounds = db.get_delta_tips(&key.contract_address)?.map(Into::into);
+            let delta = IpcDelta { bounds, ..IpcDelta::from_delta_key(key, &data)? };
             tips_results.push(delta);
         }
         Ok(IpcResponse::GetTips { result: IpcResults::Tips(tips_results) })
@@ -177,16 +684,30 @@ pub(self) mod handling {
         let tips = db.get_all_tips::<DeltaKey>().unwrap_or_default();
         let mut tips_results = Vec::with_capacity(tips.len());
         for (key, data) in tips {
-            let delta = IpcDelta::from_delta_key(key, &data)?;
+            // Like the tips themselves, a contract whose bounds can't be read is still listed.
+            let bounds = db.get_delta_tips(&key.contract_address).ok().and_then(|tips| tips.map(Into::into));
+            let delta = IpcDelta { bounds, ..IpcDelta::from_delta_key(key, &data)? };
             tips_results.push(delta);
         }
         Ok(IpcResponse::GetAllTips { result: IpcResults::Tips(tips_results) })
     }
 
     #[logfn(TRACE)]
-    pub fn get_all_addrs(db: &DB) -> ResponseResult {
-        let addresses: Vec<String> = db.get_all_addresses().unwrap_or_default().iter().map(|addr| addr.to_hex()).collect();
-        Ok(IpcResponse::GetAllAddrs { result: IpcResults::Addresses(addresses) })
+    pub fn get_all_addrs(db: &DB, flag_orphans: bool) -> ResponseResult {
+        let all_addresses = db.get_all_addresses().unwrap_or_default();
+        let orphans = if flag_orphans {
+            let mut orphans = Vec::new();
+            for addr in &all_addresses {
+                if db.is_orphan(*addr)? {
+                    orphans.push(addr.to_hex());
+                }
+            }
+            Some(orphans)
+        } else {
+            None
+        };
+        let addresses: Vec<String> = all_addresses.iter().map(|addr| addr.to_hex()).collect();
+        Ok(IpcResponse::GetAllAddrs { result: IpcResults::Addresses(addresses), orphans })
     }
 
     #[logfn(TRACE)]
@@ -221,24 +742,43 @@ pub(self) mod handling {
     }
 
     #[logfn(TRACE)]
-    pub fn get_contract(db: &DB, input: &str) -> ResponseResult {
+    pub fn get_contract(db: &DB, input: &str, offset: Option<u64>, max_bytes: Option<u64>) -> ResponseResult {
         let address = ContractAddress::from_hex(&input)?;
-        let data = db.get_contract(address).unwrap_or_default();
-        Ok(IpcResponse::GetContract { result: IpcResults::GetContract{address: address.to_hex(), bytecode: data} })
+        let result = if offset.is_none() && max_bytes.is_none() {
+            // An unknown contract is still answered with an empty bytecode, but a failed read is an error
+            let data = db.find_contract(address)?.unwrap_or_default();
+            IpcResults::GetContract { address: address.to_hex(), bytecode: data, total_len: None, bytecode_hash: None }
+        } else {
+            let chunk = db.get_contract_chunk(address, offset.unwrap_or(0), max_bytes.unwrap_or(u64::max_value()))?;
+            IpcResults::GetContract {
+                address: address.to_hex(),
+                bytecode: chunk.bytes,
+                total_len: Some(chunk.total_len),
+                bytecode_hash: Some(chunk.hash.to_hex()),
+            }
+        };
+        Ok(IpcResponse::GetContract { result })
     }
 
     #[logfn(TRACE)]
     pub fn update_new_contract(db: &mut DB, address: String, bytecode: &[u8]) -> ResponseResult {
         let address_arr = ContractAddress::from_hex(&address)?;
+        if !db.hosting().is_hosted(&address_arr) {
+            return Ok(IpcResponse::UpdateNewContract { address, result: IpcResults::Status(Status::NotHosted) });
+        }
         let delta_key = DeltaKey::new(address_arr, Stype::ByteCode);
+        db.check_contract_capacity(address_arr, bytecode.len())?;
         db.force_update(&delta_key, bytecode)?;
-        Ok(IpcResponse::UpdateNewContract { address, result: IpcResults::Status(Status::Passed) })
+        Ok(IpcResponse::UpdateNewContract { address, result: IpcResults::Status(Status::Ok) })
     }
 
     #[logfn(TRACE)]
     pub fn update_new_contract_on_deployment(db: &mut DB, address: String, bytecode: &str, delta: IpcDelta) -> ResponseResult {
         let mut tuples = Vec::with_capacity(DEPLOYMENT_VALS_LEN);
         let address_arr = ContractAddress::from_hex(&address)?;
+        if !db.hosting().is_hosted(&address_arr) {
+            return Ok(IpcResponse::UpdateNewContractOnDeployment { address, result: IpcResults::Status(Status::NotHosted) });
+        }
 
         let bytecode = bytecode.from_hex()?;
         let bytecode_delta_key = DeltaKey::new(address_arr, Stype::ByteCode);
@@ -247,11 +787,13 @@ pub(self) mod handling {
         let data = delta.data.ok_or(P2PErr { cmd: "UpdateNewContractOnDeployment".to_string(), msg: "Delta Data Missing".to_string() })?;
         let delta_key = DeltaKey::new(address_arr, Stype::Delta(delta.key));
         tuples.push((delta_key, &data));
+        db.check_contract_capacity(address_arr, bytecode.len())?;
+        db.check_delta_capacity(&[(address_arr, data.len())])?;
 
         let results = db.insert_tuples(&tuples);
-        let mut status = Status::Passed;
+        let mut status = Status::Ok;
         if results.into_iter().any(| result | result.is_err()) {
-            status = Status::Failed;
+            status = Status::Error;
         }
         // since a new delta and bytecode were added, the state is no longer updated
         db.update_state_status(false);
@@ -264,12 +806,13 @@ pub(self) mod handling {
         let addr_arr = ContractAddress::from_hex(&address)?;
         // the key_type of dk is irrelevant since we are removing all the contract data
         let dk = DeltaKey::new(addr_arr, Stype::ByteCode);
+        db.contract_cache().remove(&addr_arr);
         let result = match db.delete_contract(&dk) {
-            Ok(_) => IpcResults::Status(Status::Passed),
+            Ok(_) => IpcResults::Status(Status::Ok),
             Err(e) => {
                 match errors::is_db_err_type(e) {
-                    Ok(_) => IpcResults::Status(Status::Passed),
-                    Err(_) => IpcResults::Status(Status::Failed),
+                    Ok(_) => IpcResults::Status(Status::NotFound),
+                    Err(_) => IpcResults::Status(Status::Error),
                 }
             },
         };
@@ -278,8 +821,9 @@ pub(self) mod handling {
     }
 
     #[logfn(TRACE)]
-    pub fn update_deltas(db: &mut DB, deltas: Vec<IpcDelta>) -> ResponseResult {
+    pub fn update_deltas(db: &mut DB, deltas: Vec<IpcDelta>, allow_orphan: bool) -> ResponseResult {
         let mut tuples = Vec::with_capacity(deltas.len());
+        let mut not_hosted = Vec::new();
 
         for delta in deltas.into_iter() {
             let address = delta.contract_address.ok_or(P2PErr { cmd: "UpdateDeltas".to_string(), msg: "Address Missing".to_string() })?;
@@ -287,18 +831,35 @@ pub(self) mod handling {
             let data =
                 delta.data.ok_or(P2PErr { cmd: "UpdateDeltas".to_string(), msg: "Delta Data Missing".to_string() })?;
             let delta_key = DeltaKey::new(address, Stype::Delta(delta.key));
-            tuples.push((delta_key, data));
-        }
-        let results = db.insert_tuples(&tuples);
-        let mut errors = Vec::with_capacity(tuples.len());
-        let mut overall_status = Status::Passed;
-        for ((deltakey, _), res) in tuples.into_iter().zip(results.into_iter()) {
-            let status = if res.is_err() {
-                overall_status = Status::Failed;
-                Status::Failed
+            // The deltas of the other contracts are answered without being stored.
+            if db.hosting().is_hosted(&address) {
+                tuples.push((delta_key, data));
             } else {
-                Status::Passed
+                not_hosted.push(delta_key);
+            }
+        }
+        let lengths: Vec<_> = tuples.iter().map(|(key, data)| (key.contract_address, data.len())).collect();
+        db.check_delta_capacity(&lengths)?;
+        let results = db.insert_deltas(&tuples, allow_orphan);
+        let mut errors = Vec::with_capacity(tuples.len() + not_hosted.len());
+        let mut overall_status = Status::Ok;
+        let not_hosted = not_hosted.into_iter().map(|deltakey| (deltakey, Status::NotHosted));
+        let stored = tuples.into_iter().zip(results.into_iter()).map(|((deltakey, _), res)| {
+            let status = match res {
+                Ok(()) => Status::Ok,
+                Err(e) => match e.downcast_ref::<DBErr>() {
+                    Some(DBErr { kind: DBErrKind::MissingBytecode(_), .. }) => Status::MissingBytecode,
+                    Some(DBErr { kind: DBErrKind::KeyExists(_), .. }) => Status::Duplicate,
+                    _ => Status::Error,
+                },
             };
+            (deltakey, status)
+        });
+        for (deltakey, status) in stored.chain(not_hosted) {
+            // A DB failure outweighs the deltas that wait for their contract.
+            if status.is_failure() && overall_status != Status::Error {
+                overall_status = status;
+            }
             let key = Some(deltakey.key_type.unwrap_delta() as i64);
             let address = deltakey.contract_address.to_hex();
             let delta = IpcStatusResult { address, key, status };
@@ -310,15 +871,15 @@ pub(self) mod handling {
         Ok(IpcResponse::UpdateDeltas {result})
     }
 
-    fn delete_data_from_db(db: &mut DB, addr: &str, key_type: Stype) -> Result<IpcResults, Error> {
+    fn delete_data_from_db(db: &mut DB, addr: &str, key_type: Stype) -> Result<Status, Error> {
         let addr_arr = ContractAddress::from_hex(addr)?;
         let dk = DeltaKey::new(addr_arr, key_type);
         match db.delete(&dk) {
-            Ok(_) => Ok(IpcResults::Status(Status::Passed)),
+            Ok(_) => Ok(Status::Ok),
             Err(e) => {
                 match errors::is_db_err_type(e) {
-                    Ok(_) =>  Ok(IpcResults::Status(Status::Passed)),
-                    Err(_) => Ok(IpcResults::Status(Status::Failed)),
+                    Ok(_) =>  Ok(Status::NotFound),
+                    Err(_) => Ok(Status::Error),
                 }
             },
         }
@@ -327,21 +888,27 @@ pub(self) mod handling {
     #[logfn(TRACE)]
     pub fn remove_deltas(db: &mut DB, input: Vec<IpcDeltasRange>) -> ResponseResult {
         let mut errors = Vec::new();
-        let mut overall_status = Status::Passed;
+        let mut overall_status = Status::Ok;
         for addr_deltas in input {
-            for key in addr_deltas.from..addr_deltas.to {
-                let delta_res = delete_data_from_db(db,&addr_deltas.address.clone(), Stype::Delta(key))?;
-                if let IpcResults::Status(Status::Failed) = delta_res {
-                    let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(key as i64), status: Status::Failed };
-                    errors.push(failed_delta);
-                    overall_status = Status::Failed;
+            let address = ContractAddress::from_hex(&addr_deltas.address)?;
+            // The whole range in one batch, the chain hash of the contract is recomputed once for it.
+            let failed: Vec<(u32, Status)> = match db.delete_deltas(&address, addr_deltas.from..addr_deltas.to) {
+                Ok(missing) => missing.into_iter().map(|key| (key, Status::NotFound)).collect(),
+                Err(e) => {
+                    let status = if errors::is_db_err_type(e).is_ok() { Status::NotFound } else { Status::Error };
+                    (addr_deltas.from..addr_deltas.to).map(|key| (key, status)).collect()
                 }
+            };
+            for (key, status) in failed {
+                let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(key as i64), status };
+                errors.push(failed_delta);
+                overall_status = Status::Error;
             }
-            let status_res = delete_data_from_db(db,&addr_deltas.address, Stype::State)?;
-            if let IpcResults::Status(Status::Failed) = status_res {
-                let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(FAILED_STATE), status: Status::Failed };
+            let status = delete_data_from_db(db,&addr_deltas.address, Stype::State)?;
+            if status.is_failure() {
+                let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(FAILED_STATE), status };
                 errors.push(failed_delta);
-                overall_status = Status::Failed;
+                overall_status = Status::Error;
             }
         }
         db.update_state_status(false);
@@ -351,8 +918,7 @@ pub(self) mod handling {
 
     #[logfn(TRACE)]
     pub fn get_dh_user_key(_user_pubkey: &str, eid: sgx_enclave_id_t) -> ResponseResult {
-        let mut user_pubkey = [0u8; 64];
-        user_pubkey.clone_from_slice(&_user_pubkey.from_hex().unwrap());
+        let user_pubkey = user_pubkey(_user_pubkey)?;
 
         let (msg, sig) = km_u::get_user_key(eid, &user_pubkey)?;
 
@@ -360,15 +926,43 @@ pub(self) mod handling {
         let res: Value = Deserialize::deserialize(&mut des).unwrap();
         let pubkey = serde_json::from_value::<Vec<u8>>(res["pubkey"].clone())?;
 
-        let result = IpcResults::DHKey {dh_key: pubkey.to_hex(), sig: sig.to_hex() };
+        let result = IpcResults::DHKey { dh_key: pubkey.to_hex(), sig: sig.to_hex(), sig_forms: IpcSigForms::default() };
 
         Ok(IpcResponse::NewTaskEncryptionKey {result})
     }
 
     #[logfn(TRACE)]
-    pub fn get_ptt_req(eid: sgx_enclave_id_t) -> ResponseResult {
-        let (data, sig) = km_u::ptt_req(eid)?;
-        let result = IpcResults::Request { request: data.to_hex(), sig: sig.to_hex() };
+    pub fn get_audit_digest(eid: sgx_enclave_id_t) -> ResponseResult {
+        let (export, sig) = km_u::export_audit_digest(eid)?;
+        let digest = export.digest();
+        let result = IpcResults::AuditDigest {
+            base_count: export.base_count,
+            base: export.base.to_hex(),
+            events: export.events.into_iter().map(IpcAuditEvent::from).collect(),
+            count: digest.count,
+            head: digest.head.to_hex(),
+            signature: sig.to_hex(),
+            sig_forms: IpcSigForms::default(),
+        };
+        Ok(IpcResponse::GetAuditDigest { result })
+    }
+
+    #[logfn(TRACE)]
+    pub fn get_ptt_req(db: &DB, block_number: Option<u64>, eid: sgx_enclave_id_t) -> ResponseResult {
+        // Resolved before asking the enclave, a block without a known epoch doesn't leave a pending DH key behind.
+        let epoch = match block_number {
+            Some(block) => IpcEpoch::from(Some(EPOCH.lock_recover("Epoch").at_block(block)?)),
+            None => IpcEpoch::current(),
+        };
+        let (data, sig) = km_u::ptt_req(eid, block_number)?;
+        let addresses = db.hosting().kept_keys().map(|addresses| addresses.iter().map(|a| a.to_hex()).collect());
+        let result = IpcResults::Request {
+            request: data.to_hex(),
+            sig: sig.to_hex(),
+            epoch,
+            sig_forms: IpcSigForms::default(),
+            addresses,
+        };
 
         Ok(IpcResponse::GetPTTRequest {result})
     }
@@ -376,26 +970,67 @@ pub(self) mod handling {
     #[logfn(TRACE)]
     pub fn ptt_response(db: &mut DB, response: &PrincipalResponse, eid: sgx_enclave_id_t) -> ResponseResult {
         let msg = response.response.from_hex()?;
-        km_u::ptt_res(eid, &msg)?;
+        // The KM node sends every key of this worker, the enclave drops those of the contracts it doesn't host.
+        let hosted = db.hosting().kept_keys();
+        km_u::ptt_res(eid, &msg, hosted.as_ref().map(|addresses| &addresses[..]))?;
         let res = km_u::ptt_build_state(db, eid)?;
         db.update_state_status(true);
+        select_provisioned(db, eid)?;
+        let mut recovery = RECOVERY.lock_recover("Recovery");
+        if recovery.is_waiting() {
+            // A key that failed decrypting the state isn't the one we lost.
+            let provisioned: Vec<_> = km_u::provisioned_addresses(eid)?.into_iter().filter(|a| !res.contains(a)).collect();
+            recovery.provisioned(&provisioned);
+        }
         let result: Vec<_> = res
             .into_iter()
-            .map(|a| IpcStatusResult{ address: a.to_hex(), status: Status::Failed, key: None })
+            .map(|a| IpcStatusResult{ address: a.to_hex(), status: Status::Error, key: None })
             .collect();
 
         let result = IpcResults::Errors(result);
         Ok(IpcResponse::PTTResponse {result})
     }
 
+    /// The KM node only provisions the keys of the contracts this worker was selected for, in `selected-only` mode
+    /// those are the hosted ones.
+    pub fn select_provisioned(db: &mut DB, eid: sgx_enclave_id_t) -> Result<(), Error> {
+        if db.hosting().mode() == HostingMode::SelectedOnly {
+            let provisioned = km_u::provisioned_addresses(eid)?;
+            info!("Hosting the {} contracts this worker was given a key for", provisioned.len());
+            db.hosting_mut().set_selected(Some(provisioned));
+        }
+        Ok(())
+    }
+
+    #[logfn(TRACE)]
+    pub fn recover_keys(db: &DB, addresses: Option<Vec<String>>, eid: sgx_enclave_id_t) -> ResponseResult {
+        let addresses = match addresses {
+            Some(addresses) => addresses.iter().map(|a| ContractAddress::from_hex(a)).collect::<Result<Vec<_>, _>>()?,
+            None => db.get_all_addresses()?,
+        };
+        // The request doesn't depend on which keys the enclave already has, the KM node sends all the keys of this worker.
+        let (data, sig) = km_u::ptt_req(eid, None)?;
+        RECOVERY.lock_recover("Recovery").start(addresses);
+        let result = IpcResults::Request {
+            request: data.to_hex(),
+            sig: sig.to_hex(),
+            epoch: IpcEpoch::current(),
+            sig_forms: IpcSigForms::default(),
+            addresses: db.hosting().kept_keys().map(|addresses| addresses.iter().map(|a| a.to_hex()).collect()),
+        };
+        Ok(IpcResponse::RecoverKeys { result })
+    }
+
     pub fn deploy_contract(db: &mut DB, input: IpcTask, eid: sgx_enclave_id_t) -> ResponseResult {
-        let bytecode = input.pre_code.expect("Bytecode Missing");
+        check_debug_trace(input.debug_trace)?;
+        let bytecode = input.pre_code.ok_or_else(|| format_err!("The deployment has no preCode"))?;
         let contract_address = ContractAddress::from_hex(&input.address)?;
+        // Checked against the pre-code, the deployed code isn't known before running the constructor.
+        db.check_contract_capacity(contract_address, bytecode.len())?;
         let enc_args = input.encrypted_args.from_hex()?;
         let constructor = input.encrypted_fn.from_hex()?;
-        let mut user_pubkey = [0u8; 64];
-        user_pubkey.clone_from_slice(&input.user_dhkey.from_hex()?);
-        let result = wasm::deploy(
+        let user_pubkey = user_pubkey(&input.user_dhkey)?;
+        let result = wasm::deploy_traced(
             db,
             eid,
             &bytecode,
@@ -403,14 +1038,15 @@ pub(self) mod handling {
             &enc_args,
             &contract_address,
             &user_pubkey,
-            input.gas_limit)?;
+            input.gas_limit,
+            input.debug_trace)?;
 
         match result {
             WasmResult::WasmTaskResult(v) => {
                 // Save the ExeCode into the DB.
                 let key = DeltaKey::new(contract_address, Stype::ByteCode);
                 db.create(&key, &v.output)?;
-                let ipc_response = v.into_deploy_response(&bytecode);
+                let ipc_response = v.into_deploy_response();
                 debug!("deploy_contract() => Ok({})", ipc_response.display_without_bytecode());
                 Ok(ipc_response)
             },
@@ -422,48 +1058,1062 @@ pub(self) mod handling {
         }
     }
 
+    /// A `ComputeTask` with its hex fields decoded.
+    pub struct ComputeInput {
+        pub address: ContractAddress,
+        pub callable: Vec<u8>,
+        pub args: Vec<u8>,
+        pub user_pubkey: PubKey,
+        pub gas_limit: u64,
+        pub debug_trace: bool,
+        pub output_envelope: bool,
+    }
+
+    /// A journaled task to execute again, with the receipt the enclave signed for it.
+    pub struct ReplayInput {
+        /// The inputs as they were received.
+        pub task: ComputeInput,
+        /// The IO key of the task, sealed by the enclave that executed it.
+        pub sealed_io_key: Vec<u8>,
+        pub receipt: ReplayReceipt,
+        pub receipt_sig: [u8; 65],
+        pub ethereum_payload: Vec<u8>,
+        /// The encrypted output the task produced.
+        pub output: Vec<u8>,
+        /// The delta the task stored, empty if it didn't produce one.
+        pub delta: Vec<u8>,
+    }
+
+    /// The ecalls `compute_task` makes, the enclave itself outside of the tests.
+    pub trait TaskEnclave {
+        /// Builds the state of the contracts, needed again every time new deltas were stored.
+        fn build_state(&mut self, db: &mut DB) -> Result<(), Error>;
+        fn execute(&mut self, db: &mut DB, bytecode: &[u8], input: &ComputeInput) -> Result<WasmResult, Error>;
+        /// Executes the task again without storing anything, and compares it with the output and the delta it produced.
+        /// Refused unless the inputs, `bytecode` and the state in `db` are the ones of the receipt.
+        fn replay(&mut self, db: &mut DB, bytecode: &[u8], input: &ReplayInput) -> Result<ReplayResult, Error>;
+    }
+
+    pub struct SgxTaskEnclave(pub sgx_enclave_id_t);
+
+    impl TaskEnclave for SgxTaskEnclave {
+        fn build_state(&mut self, db: &mut DB) -> Result<(), Error> { km_u::ptt_build_state(db, self.0).map(|_| ()) }
+
+        fn execute(&mut self, db: &mut DB, bytecode: &[u8], input: &ComputeInput) -> Result<WasmResult, Error> {
+            wasm::execute_traced(db, self.0, bytecode, &input.callable, &input.args, &input.user_pubkey, &input.address,
+                                 input.gas_limit, input.debug_trace, input.output_envelope)
+        }
+
+        fn replay(&mut self, db: &mut DB, bytecode: &[u8], input: &ReplayInput) -> Result<ReplayResult, Error> {
+            let task = &input.task;
+            wasm::replay(db, self.0, bytecode, &task.callable, &task.args, &task.user_pubkey, &task.address, &input.sealed_io_key,
+                         &input.receipt, &input.receipt_sig, &input.ethereum_payload, &input.output, &input.delta)
+        }
+    }
+
     #[logfn(DEBUG)]
     pub fn compute_task(db: &mut DB, input: IpcTask, eid: sgx_enclave_id_t) -> ResponseResult {
-        let enc_args = input.encrypted_args.from_hex()?;
-        let address = ContractAddress::from_hex(&input.address)?;
-        let callable = input.encrypted_fn.from_hex()?;
-        let mut user_pubkey = [0u8; 64];
-        user_pubkey.clone_from_slice(&input.user_dhkey.from_hex()?);
+        compute_task_on(db, input, &mut SgxTaskEnclave(eid))
+    }
+
+    pub fn compute_task_on<E: TaskEnclave>(db: &mut DB, input: IpcTask, enclave: &mut E) -> ResponseResult {
+        RECOVERY.lock_recover("Recovery").check()?;
+        EPOCH.lock_recover("Epoch").check(input.block_number, input.epoch_nonce)?;
+        check_debug_trace(input.debug_trace)?;
+        let user_pubkey = user_pubkey(&input.user_dhkey)?;
+        let task = ComputeInput {
+            address: ContractAddress::from_hex(&input.address)?,
+            callable: input.encrypted_fn.from_hex()?,
+            args: input.encrypted_args.from_hex()?,
+            user_pubkey,
+            gas_limit: input.gas_limit,
+            debug_trace: input.debug_trace,
+            output_envelope: input.output_envelope,
+        };
+        let address = task.address;
+        let expected_tip = input.expected_tip;
+
+        // Checked before any ecall, the enclave would only fail on the empty bytecode with a confusing error.
+        // `UpdateNewContract` doesn't refuse an empty bytecode, it can't be executed any more than a missing one.
+        let bytecode = db.find_contract_cached(address)?
+            .filter(|bytecode| !bytecode.is_empty())
+            .ok_or_else(|| errors::ContractNotFoundErr { address: address.to_hex() })?;
+        // The state can only be built up to the usable tip, the task would run on a state behind the raw one.
+        let tip = match db.get_delta_tips(&address)? {
+            Some(tips) if tips.has_gaps() => {
+                return Err(errors::DeltaGapErr { address: address.to_hex(), usable_tip: tips.usable, raw_tip: tips.raw }.into());
+            }
+            tips => tips.and_then(|tips| tips.usable),
+        };
+        if let Some(expected) = expected_tip {
+            check_expected_tip(db, address, &expected)?;
+        }
 
         if !db.get_state_status() {
-            let _res = km_u::ptt_build_state(db, eid)?;
+            enclave.build_state(db)?;
             db.update_state_status(true);
         }
-        let bytecode = db.get_contract(address)?;
 
-
-        let result = wasm::execute(
-            db,
-            eid,
-            &bytecode,
-            &callable,
-            &enc_args,
-            &user_pubkey,
-            &address,
-            input.gas_limit)?;
+        let result = enclave.execute(db, &bytecode, &task)?;
+        db.record_execution(address);
 
         match result {
-            WasmResult::WasmTaskResult(v) => Ok(v.into_execute_response()),
+            WasmResult::WasmTaskResult(v) => {
+                let delta = task_delta(db, tip, &v.delta, PERSIST_TASK_DELTAS.load(Ordering::SeqCst))?;
+                if let Some(task_id) = input.task_id {
+                    // The task succeeded whether or not it can be replayed later.
+                    if let Err(e) = db.journal_task(journal_entry(task_id, &task, tip, &v), DEFAULT_TASK_JOURNAL_CAP) {
+                        warn!("Failed journaling the task: {}", e);
+                    }
+                }
+                Ok(v.into_execute_response(delta))
+            }
             WasmResult::WasmTaskFailure(v) => Ok(v.into())
         }
     }
 
+    fn journal_entry(task_id: String, task: &ComputeInput, tip: Option<u32>, result: &WasmTaskResult) -> JournaledTask {
+        JournaledTask {
+            index: 0,
+            task_id,
+            address: task.address.to_hex(),
+            tip,
+            callable: task.callable.to_hex(),
+            args: task.args.to_hex(),
+            user_pubkey: task.user_pubkey.to_hex(),
+            gas_limit: task.gas_limit,
+            output: result.output.to_hex(),
+            inputs_hash: result.inputs_hash.to_hex(),
+            exe_code_hash: result.exe_code_hash.to_hex(),
+            prev_delta_hash: result.prev_delta_hash.to_hex(),
+            delta_hash: result.delta_hash.to_hex(),
+            output_hash: result.output_hash.to_hex(),
+            used_gas: result.used_gas,
+            ethereum_payload: result.eth_payload.to_hex(),
+            ethereum_address: result.eth_contract_addr.to_hex(),
+            envelope_hash: result.envelope_hash.map(|hash| hash.to_hex()),
+            signature: result.signature.to_hex(),
+            sealed_io_key: result.sealed_io_key.as_ref().map(|key| key.to_hex()).unwrap_or_default(),
+        }
+    }
+
+    #[logfn(DEBUG)]
+    pub fn replay_task(db: &mut DB, task_id: &str, eid: sgx_enclave_id_t) -> ResponseResult {
+        replay_task_on(db, task_id, &mut SgxTaskEnclave(eid))
+    }
+
+    /// Executes a journaled task again in a sandbox holding the contract as it was when the task ran,
+    /// and compares the result field by field with the receipt and what the task stored.
+    pub fn replay_task_on<E: TaskEnclave>(db: &mut DB, task_id: &str, enclave: &mut E) -> ResponseResult {
+        let recorded = db.get_journaled_task(task_id)?.ok_or_else(|| errors::TaskNotJournaledErr { task_id: task_id.to_string() })?;
+        if recorded.sealed_io_key.is_empty() {
+            bail!("The task {} was journaled without its IO key, it can't be replayed", task_id);
+        }
+        let user_pubkey = user_pubkey(&recorded.user_pubkey)?;
+        let task = ComputeInput {
+            address: ContractAddress::from_hex(&recorded.address)?,
+            callable: recorded.callable.from_hex()?,
+            args: recorded.args.from_hex()?,
+            user_pubkey,
+            gas_limit: recorded.gas_limit,
+            debug_trace: false,
+            output_envelope: false,
+        };
+        let mut receipt = ReplayReceipt {
+            inputs_hash: *Hash256::from_hex(&recorded.inputs_hash)?,
+            exe_code_hash: *Hash256::from_hex(&recorded.exe_code_hash)?,
+            prev_delta_hash: *Hash256::from_hex(&recorded.prev_delta_hash)?,
+            delta_hash: *Hash256::from_hex(&recorded.delta_hash)?,
+            output_hash: *Hash256::from_hex(&recorded.output_hash)?,
+            gas_limit: recorded.gas_limit,
+            used_gas: recorded.used_gas,
+            ..Default::default()
+        };
+        decode_exact(&recorded.ethereum_address, &mut receipt.ethereum_address, "The journaled ethereumAddress")?;
+        if let Some(hash) = &recorded.envelope_hash {
+            receipt.envelope_hash = *Hash256::from_hex(hash)?;
+            receipt.has_envelope = 1;
+        }
+        let mut receipt_sig = [0u8; 65];
+        decode_exact(&recorded.signature, &mut receipt_sig, "The journaled signature")?;
+        let bytecode = db.get_contract(task.address)?;
+        // The delta as it's stored now, tampered with or not. A task without a delta signs the zero hash.
+        let delta = if recorded.delta_hash == Hash256::default().to_hex() {
+            Vec::new()
+        } else {
+            db.read(&DeltaKey::new(task.address, Stype::Delta(recorded.tip.map_or(0, |tip| tip + 1))))?
+        };
+        let input = ReplayInput {
+            task,
+            sealed_io_key: recorded.sealed_io_key.from_hex()?,
+            receipt,
+            receipt_sig,
+            ethereum_payload: recorded.ethereum_payload.from_hex()?,
+            output: recorded.output.from_hex()?,
+            delta,
+        };
+
+        let replayed = {
+            let mut sandbox = db.sandbox(input.task.address, recorded.tip)?;
+            enclave.build_state(&mut sandbox)?;
+            enclave.replay(&mut sandbox, &bytecode, &input)?
+        };
+
+        let stored_delta_hash = if input.delta.is_empty() { Hash256::default() } else { input.delta.keccak256() };
+        let fields = vec![
+            replay_field("status", "success".to_string(), if replayed.failed == 0 { "success" } else { "failure" }.to_string()),
+            replay_field("inputsHash", recorded.inputs_hash, replayed.inputs_hash.to_hex()),
+            replay_field("exeCodeHash", recorded.exe_code_hash, replayed.exe_code_hash.to_hex()),
+            replay_field("prevDeltaHash", recorded.prev_delta_hash, replayed.prev_delta_hash.to_hex()),
+            replay_field("usedGas", recorded.used_gas.to_string(), replayed.used_gas.to_string()),
+            // Only whether they match, the enclave compared them decrypted.
+            IpcReplayField {
+                field: "output".to_string(),
+                matches: replayed.output_matches != 0 && input.output.keccak256().to_hex() == recorded.output_hash,
+                recorded: None,
+                replayed: None,
+            },
+            IpcReplayField {
+                field: "delta".to_string(),
+                matches: replayed.delta_matches != 0 && stored_delta_hash.to_hex() == recorded.delta_hash,
+                recorded: None,
+                replayed: None,
+            },
+        ];
+        let matches = fields.iter().all(|field| field.matches);
+        if !matches {
+            let diverged: Vec<&str> = fields.iter().filter(|field| !field.matches).map(|field| field.field.as_str()).collect();
+            warn!("The replay of task {} diverged on {:?}", task_id, diverged);
+        }
+        Ok(IpcResponse::ReplayTask { result: IpcResults::ReplayReport { task_id: task_id.to_string(), matches, fields } })
+    }
+
+    fn replay_field(field: &str, recorded: String, replayed: String) -> IpcReplayField {
+        IpcReplayField { field: field.to_string(), matches: recorded == replayed, recorded: Some(recorded), replayed: Some(replayed) }
+    }
+
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::db::{DeltaKey, P2PCalls, Stype, tests::create_test_db};
-    use serde_json::Value;
-    use enigma_types::ContractAddress;
+    use crate::common_u::drain::DrainState;
+    use crate::db::{CRUDInterface, Delta, DeltaKey, HostingMode, P2PCalls, RebuildScope, RegistrationRecord, Stype, DEFAULT_TASK_JOURNAL_CAP,
+                    tests::create_test_db};
+    use crate::wasm_u::{WasmResult, WasmTaskResult};
+    use serde_json::{json, Value};
+    use enigma_crypto::{hash::Keccak256, KeyPair};
+    use enigma_tools_m::signable::{ExecuteReceipt, Signable};
+    use enigma_tools_m::utils::EthereumAddress;
+    use enigma_types::{ContractAddress, Hash256, ReplayResult};
+    use hex::{FromHex, ToHex};
+    use zmq::Message;
+    use common_u::errors;
 
     pub const SPID: &str = "B0335FD3BC1CCA8F804EB98A6420592D";
     pub const RETRIES: u32 = 10;
+    fn contract_with_tip(db: &mut DB, address: ContractAddress, tip: u32) {
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &b"code"[..]).unwrap();
+        for key in 0..=tip {
+            db.create(&DeltaKey::new(address, Stype::Delta(key)), &[key as u8 + 1][..]).unwrap();
+        }
+    }
+
+    /// Counts the ecalls instead of making them.
+    #[derive(Default)]
+    struct CountingEnclave {
+        ecalls: usize,
+    }
+
+    impl handling::TaskEnclave for CountingEnclave {
+        fn build_state(&mut self, _db: &mut DB) -> Result<(), failure::Error> {
+            self.ecalls += 1;
+            Ok(())
+        }
+
+        fn execute(&mut self, _db: &mut DB, _bytecode: &[u8], _input: &handling::ComputeInput) -> Result<WasmResult, failure::Error> {
+            self.ecalls += 1;
+            bail!("Not an enclave")
+        }
+
+        fn replay(&mut self, _db: &mut DB, _bytecode: &[u8], _input: &handling::ReplayInput) -> Result<ReplayResult, failure::Error> {
+            self.ecalls += 1;
+            bail!("Not an enclave")
+        }
+    }
+
+    /// Executes the tasks deterministically from their inputs and the last delta, and stores the delta like the enclave does.
+    struct ReplayingEnclave;
+
+    impl ReplayingEnclave {
+        fn run(db: &DB, bytecode: &[u8], input: &handling::ComputeInput) -> WasmTaskResult {
+            let tip = db.get_tip::<DeltaKey>(&input.address).ok();
+            let prev_delta_hash = tip.as_ref().map_or_else(Hash256::default, |(_, delta)| delta.keccak256());
+            let next = tip.map_or(0, |(key, _)| key.key_type.unwrap_delta() + 1);
+            let output = [&input.args[..], &prev_delta_hash[..]].concat().keccak256().to_vec();
+            let delta = [&input.callable[..], &prev_delta_hash[..]].concat().keccak256().to_vec();
+            WasmTaskResult {
+                inputs_hash: [&input.callable[..], &input.args[..]].concat().keccak256(),
+                exe_code_hash: bytecode.keccak256(),
+                prev_delta_hash,
+                delta_hash: delta.keccak256(),
+                output_hash: output.keccak256(),
+                output: output.into_boxed_slice(),
+                sealed_io_key: Some(input.user_pubkey[..].keccak256().to_vec().into_boxed_slice()),
+                delta: Delta { key: DeltaKey::new(input.address, Stype::Delta(next)), value: delta },
+                gas_limit: input.gas_limit,
+                used_gas: 10,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl handling::TaskEnclave for ReplayingEnclave {
+        fn build_state(&mut self, _db: &mut DB) -> Result<(), failure::Error> { Ok(()) }
+
+        fn execute(&mut self, db: &mut DB, bytecode: &[u8], input: &handling::ComputeInput) -> Result<WasmResult, failure::Error> {
+            let result = Self::run(db, bytecode, input);
+            db.create(&result.delta.key, &result.delta.value[..])?;
+            Ok(WasmResult::WasmTaskResult(result))
+        }
+
+        fn replay(&mut self, db: &mut DB, bytecode: &[u8], input: &handling::ReplayInput) -> Result<ReplayResult, failure::Error> {
+            let result = Self::run(db, bytecode, &input.task);
+            // Like the enclave, only on what the receipt was signed for and with the key sealed for the task.
+            if *result.inputs_hash != input.receipt.inputs_hash || *result.exe_code_hash != input.receipt.exe_code_hash
+                || *result.prev_delta_hash != input.receipt.prev_delta_hash {
+                bail!("Not the inputs, bytecode and state of the receipt");
+            }
+            if result.sealed_io_key.as_ref().map(|key| &key[..]) != Some(&input.sealed_io_key[..]) {
+                bail!("Not the IO key of the task");
+            }
+            Ok(ReplayResult {
+                inputs_hash: *result.inputs_hash,
+                exe_code_hash: *result.exe_code_hash,
+                prev_delta_hash: *result.prev_delta_hash,
+                used_gas: result.used_gas,
+                output_matches: (result.output[..] == input.output[..]) as u8,
+                delta_matches: (result.delta.value[..] == input.delta[..]) as u8,
+                failed: 0,
+            })
+        }
+    }
+
+    fn compute_input(address: ContractAddress) -> IpcTask {
+        IpcTask {
+            pre_code: None,
+            encrypted_args: "00".to_string(),
+            encrypted_fn: "00".to_string(),
+            user_dhkey: "00".repeat(64),
+            gas_limit: 100,
+            address: address.to_hex(),
+            block_number: None,
+            epoch_nonce: None,
+            debug_trace: false,
+            expected_tip: None,
+            task_id: None,
+            output_envelope: false,
+        }
+    }
+
+    #[test]
+    fn test_compute_unknown_contract() {
+        let (mut db, _dir) = create_test_db();
+        let unknown: ContractAddress = [9u8; 32].into();
+        let empty: ContractAddress = [10u8; 32].into();
+        db.create(&DeltaKey::new(empty, Stype::ByteCode), &b""[..]).unwrap();
+
+        for address in vec![unknown, empty] {
+            let mut enclave = CountingEnclave::default();
+            let response = handling::compute_task_on(&mut db, compute_input(address), &mut enclave).unwrap_or_error();
+            assert_eq!(enclave.ecalls, 0);
+            let response = serde_json::to_value(&response).unwrap();
+            assert_eq!(response["details"], json!({ "code": "ContractNotFound", "address": address.to_hex() }));
+            assert_eq!(response["retryable"], false);
+        }
+
+        // A deployed contract gets as far as the enclave
+        let deployed: ContractAddress = [11u8; 32].into();
+        db.create(&DeltaKey::new(deployed, Stype::ByteCode), &b"code"[..]).unwrap();
+        let mut enclave = CountingEnclave::default();
+        assert!(handling::compute_task_on(&mut db, compute_input(deployed), &mut enclave).is_err());
+        assert!(enclave.ecalls > 0);
+    }
+
+    #[test]
+    fn test_compute_expected_tip() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [13u8; 32].into();
+        contract_with_tip(&mut db, address, 3);
+        let chain = db.get_chain_hash(&address).unwrap().unwrap();
+        let task = |key, hash: Hash256| IpcTask { expected_tip: Some(IpcTipRef { key, hash: hash.to_hex() }), ..compute_input(address) };
+
+        // The same tip gets to the enclave.
+        let mut enclave = CountingEnclave::default();
+        let err = handling::compute_task_on(&mut db, task(3, chain.hash), &mut enclave).unwrap_err();
+        assert_eq!(err.to_string(), "Not an enclave");
+        assert!(enclave.ecalls > 0);
+
+        // The p2p node knows two more deltas.
+        let mut enclave = CountingEnclave::default();
+        let response = handling::compute_task_on(&mut db, task(5, [1u8; 32].into()), &mut enclave).unwrap_or_error();
+        assert_eq!(enclave.ecalls, 0);
+        let response = serde_json::to_value(&response).unwrap();
+        assert_eq!(response["details"], json!({
+            "code": "StateBehind",
+            "address": address.to_hex(),
+            "localTip": { "key": 3, "hash": chain.hash.to_hex() },
+            "expectedKey": 5,
+        }));
+        assert_eq!(response["retryable"], true);
+
+        // Same key, other deltas.
+        let err = handling::compute_task_on(&mut db, task(3, [1u8; 32].into()), &mut CountingEnclave::default()).unwrap_err();
+        assert!(err.downcast_ref::<errors::StateBehindErr>().is_some());
+
+        // An unknown contract isn't behind, it's missing.
+        let unknown: ContractAddress = [14u8; 32].into();
+        let task = IpcTask { expected_tip: Some(IpcTipRef { key: 3, hash: chain.hash.to_hex() }), ..compute_input(unknown) };
+        let err = handling::compute_task_on(&mut db, task, &mut CountingEnclave::default()).unwrap_err();
+        assert!(err.downcast_ref::<errors::ContractNotFoundErr>().is_some());
+    }
+
+    #[test]
+    fn test_compute_gapped_chain() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [16u8; 32].into();
+        contract_with_tip(&mut db, address, 1);
+        db.create(&DeltaKey::new(address, Stype::Delta(4)), &[5u8][..]).unwrap();
+
+        let tip = handling::get_tip(&db, &address.to_hex()).unwrap();
+        let tip = serde_json::to_value(&tip.for_protocol(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(tip["result"]["key"], 4);
+        assert_eq!(tip["result"]["bounds"], json!({ "rawTip": 4, "usableTip": 1 }));
+
+        // Even against the raw tip the task would run on the state of delta 1.
+        let chain = db.get_chain_hash(&address).unwrap().unwrap();
+        let task = IpcTask { expected_tip: Some(IpcTipRef { key: 4, hash: chain.hash.to_hex() }), ..compute_input(address) };
+        let mut enclave = CountingEnclave::default();
+        let response = handling::compute_task_on(&mut db, task, &mut enclave).unwrap_or_error();
+        assert_eq!(enclave.ecalls, 0);
+        let response = serde_json::to_value(&response).unwrap();
+        assert_eq!(response["details"], json!({ "code": "DeltaGap", "address": address.to_hex(), "usableTip": 1, "rawTip": 4 }));
+        assert_eq!(response["retryable"], true);
+
+        // Once the missing deltas are synced it gets to the enclave.
+        db.insert_tuples(&[(DeltaKey::new(address, Stype::Delta(2)), vec![3u8]), (DeltaKey::new(address, Stype::Delta(3)), vec![4u8])]);
+        let mut enclave = CountingEnclave::default();
+        let err = handling::compute_task_on(&mut db, compute_input(address), &mut enclave).unwrap_err();
+        assert_eq!(err.to_string(), "Not an enclave");
+        assert!(enclave.ecalls > 0);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let msg = parse_request(br#"{"id":"a","type":"GetTip","input":"aa"}"#).unwrap();
+        assert_eq!(msg.id, "a");
+        assert_eq!(msg.request.kind(), "GetTip");
+
+        // Answered under their id, the last one has none that can be read.
+        let frames: &[(&[u8], &str)] = &[
+            (br#"{"id":"b","type":"GetTip""#, "b"),
+            (br#"{"id":"c","type":"GetHealth","protocol_version":"4"}"#, "c"),
+            (b"\xff\xfe{", ""),
+        ];
+        for (frame, id) in frames {
+            let response = serde_json::to_value(&parse_request(frame).unwrap_err()).unwrap();
+            assert_eq!(response["type"], "Error");
+            assert_eq!(response["id"], *id);
+        }
+    }
+
+    #[test]
+    fn test_malformed_inputs() {
+        let (mut db, _dir) = create_test_db();
+        assert!(handling::get_tips(&db, &["not hex".to_string()]).is_err());
+
+        let address: ContractAddress = [17u8; 32].into();
+        contract_with_tip(&mut db, address, 0);
+        let task = IpcTask { user_dhkey: "00".repeat(63), ..compute_input(address) };
+        let mut enclave = CountingEnclave::default();
+        let err = handling::compute_task_on(&mut db, task, &mut enclave).unwrap_err();
+        assert_eq!(err.to_string(), "A user public key is 64 bytes, got 63");
+        assert_eq!(enclave.ecalls, 0);
+    }
+
+    #[test]
+    fn test_replay_task() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [15u8; 32].into();
+        contract_with_tip(&mut db, address, 1);
+        let task = IpcTask { task_id: Some("task".to_string()), ..compute_input(address) };
+        handling::compute_task_on(&mut db, task, &mut ReplayingEnclave).unwrap();
+        // A later task doesn't change what the first one is replayed on, and one without id isn't journaled.
+        handling::compute_task_on(&mut db, compute_input(address), &mut ReplayingEnclave).unwrap();
+
+        let replay_on = |db: &mut DB| handling::replay_task_on(db, "task", &mut ReplayingEnclave);
+        let replay = |db: &mut DB| serde_json::to_value(replay_on(db).unwrap()).unwrap();
+        let diverged = |report: &Value| -> Vec<String> {
+            report["result"]["fields"].as_array().unwrap().iter()
+                .filter(|field| field["matches"] == false)
+                .map(|field| field["field"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let report = replay(&mut db);
+        assert_eq!(report["result"]["taskId"], "task");
+        assert_eq!(report["result"]["matches"], true);
+        assert!(diverged(&report).is_empty());
+        let fields = report["result"]["fields"].as_array().unwrap();
+        assert_eq!(fields.len(), 7);
+        for field in fields.iter().filter(|field| field["field"] == "output" || field["field"] == "delta") {
+            assert!(field.get("recorded").is_none() && field.get("replayed").is_none());
+        }
+        // Nothing was stored by the replay.
+        let (tip, _): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!(tip.key_type, Stype::Delta(3));
+
+        db.update(&DeltaKey::new(address, Stype::Delta(2)), &b"tampered"[..]).unwrap();
+        let report = replay(&mut db);
+        assert_eq!(report["result"]["matches"], false);
+        assert_eq!(diverged(&report), vec!["delta".to_string()]);
+
+        // Not on another state than the one of the receipt.
+        db.update(&DeltaKey::new(address, Stype::Delta(1)), &b"tampered"[..]).unwrap();
+        assert!(replay_on(&mut db).is_err());
+
+        let err = handling::replay_task_on(&mut db, "unknown", &mut ReplayingEnclave).unwrap_err();
+        assert!(err.downcast_ref::<errors::TaskNotJournaledErr>().is_some());
+
+        // Nor without the key sealed for the task.
+        let mut journaled = db.get_journaled_task("task").unwrap().unwrap();
+        journaled.task_id = "unsealed".to_string();
+        journaled.sealed_io_key = String::new();
+        db.journal_task(journaled, DEFAULT_TASK_JOURNAL_CAP).unwrap();
+        assert!(handling::replay_task_on(&mut db, "unsealed", &mut ReplayingEnclave).is_err());
+    }
+
+    #[test]
+    fn test_task_delta_schema() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [7u8; 32].into();
+        contract_with_tip(&mut db, address, 1);
+
+        let mut result = WasmTaskResult::default();
+        result.delta = Delta { key: DeltaKey::new(address, Stype::Delta(2)), value: vec![1, 2, 3] };
+        let delta = handling::task_delta(&mut db, Some(1), &result.delta, false).unwrap();
+        let response = serde_json::to_value(result.into_execute_response(delta)).unwrap();
+        assert_eq!(response["result"]["delta"], json!({ "address": address.to_hex(), "key": 2, "data": [1, 2, 3] }));
+
+        // A read-only task
+        let mut result = WasmTaskResult::default();
+        result.delta = Delta { key: DeltaKey::new(address, Stype::Delta(0)), value: vec![] };
+        let delta = handling::task_delta(&mut db, Some(1), &result.delta, false).unwrap();
+        let response = serde_json::to_value(result.into_execute_response(delta)).unwrap();
+        assert!(response["result"].as_object().unwrap().contains_key("delta"));
+        assert_eq!(response["result"]["delta"], Value::Null);
+    }
+
+    fn receipt_fields(result: &mut WasmTaskResult) {
+        result.inputs_hash = [1u8; 32].into();
+        result.exe_code_hash = b"code".keccak256();
+        result.pre_code_hash = b"pre code".keccak256();
+        result.prev_delta_hash = [2u8; 32].into();
+        result.delta_hash = [3u8; 32].into();
+        result.output_hash = [4u8; 32].into();
+        result.gas_limit = 1000;
+        result.used_gas = 40;
+        result.eth_payload = vec![5, 6].into_boxed_slice();
+        result.eth_contract_addr = [7u8; 20];
+    }
+
+    fn response_signature(response: &Value) -> [u8; 65] {
+        let mut signature = [0u8; 65];
+        signature.copy_from_slice(&response["result"]["signature"].as_str().unwrap().from_hex().unwrap());
+        signature
+    }
+
+    #[test]
+    fn test_task_result_attested() {
+        let keys = KeyPair::new().unwrap();
+        let signer = keys.get_pubkey().address();
+        let mut result = WasmTaskResult::default();
+        receipt_fields(&mut result);
+        result.signature = keys.sign(&result.execute_receipt().to_signable_bytes()).unwrap();
+
+        // The signed bytes come from `attested` alone.
+        let response = serde_json::to_value(result.clone().into_execute_response(None).for_protocol(PROTOCOL_VERSION)).unwrap();
+        let mut attested: IpcComputeAttested = serde_json::from_value(response["result"]["attested"].clone()).unwrap();
+        let signature = response_signature(&response);
+        assert!(attested.to_receipt().unwrap().verify(&signature, &signer).unwrap());
+        attested.used_gas += 1;
+        assert!(!attested.to_receipt().unwrap().verify(&signature, &signer).unwrap());
+        assert!(response["result"].get("usedGas").is_none());
+
+        // Version 1 repeats the fields flat.
+        let response = serde_json::to_value(result.into_execute_response(None).for_protocol(DEFAULT_PROTOCOL_VERSION)).unwrap();
+        assert_eq!(response["result"]["usedGas"], 40);
+        assert_eq!(response["result"]["ethereumPayload"], response["result"]["attested"]["ethereumPayload"]);
+        assert_eq!(response["result"]["epochNonce"], response["result"]["supplemental"]["epochNonce"]);
+
+        // A deployment, the pre-code hash isn't signed so it's supplemental.
+        let mut result = WasmTaskResult::default();
+        receipt_fields(&mut result);
+        result.signature = keys.sign(&result.deploy_receipt().to_signable_bytes()).unwrap();
+        let response = serde_json::to_value(result.into_deploy_response().for_protocol(DEFAULT_PROTOCOL_VERSION)).unwrap();
+        let attested: IpcDeployAttested = serde_json::from_value(response["result"]["attested"].clone()).unwrap();
+        assert!(attested.to_receipt().unwrap().verify(&response_signature(&response), &signer).unwrap());
+        assert!(response["result"]["attested"].get("preCodeHash").is_none());
+        assert_eq!(response["result"]["supplemental"]["preCodeHash"], json!(b"pre code".keccak256().to_hex()));
+        assert_eq!(response["result"]["preCodeHash"], response["result"]["supplemental"]["preCodeHash"]);
+    }
+
+    #[test]
+    fn test_task_delta_persistence() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [8u8; 32].into();
+        contract_with_tip(&mut db, address, 0);
+
+        let delta = Delta { key: DeltaKey::new(address, Stype::Delta(1)), value: vec![4, 5, 6] };
+        handling::task_delta(&mut db, Some(0), &delta, false).unwrap();
+        let (tip, _): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!(tip.key_type, Stype::Delta(0));
+
+        handling::task_delta(&mut db, Some(0), &delta, true).unwrap();
+        let (tip, data): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!((tip.key_type, data), (Stype::Delta(1), vec![4, 5, 6]));
+
+        // The delta has to follow the tip the task was executed on.
+        let skipping = Delta { key: DeltaKey::new(address, Stype::Delta(3)), value: vec![7] };
+        assert!(handling::task_delta(&mut db, Some(1), &skipping, true).is_err());
+        assert!(handling::task_delta(&mut db, Some(1), &delta, true).is_err());
+        let (tip, _): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!(tip.key_type, Stype::Delta(1));
+    }
+
+    #[test]
+    fn test_orphan_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let deployed: ContractAddress = [9u8; 32].into();
+        let orphan: ContractAddress = [10u8; 32].into();
+        contract_with_tip(&mut db, deployed, 0);
+        let delta = |address: ContractAddress, key| IpcDelta { contract_address: Some(address.to_hex()), key, data: Some(vec![key as u8]), chain_hash: None, bounds: None };
+
+        let response = handling::update_deltas(&mut db, vec![delta(deployed, 1), delta(orphan, 0)], false).unwrap();
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["result"]["status"], "missing_bytecode");
+        assert_eq!(response["result"]["errors"][0]["status"], "ok");
+        assert_eq!(response["result"]["errors"][1], json!({ "address": orphan.to_hex(), "key": 0, "status": "missing_bytecode" }));
+
+        let response = handling::update_deltas(&mut db, vec![delta(orphan, 0)], true).unwrap();
+        assert_eq!(serde_json::to_value(response).unwrap()["result"]["status"], "ok");
+
+        let response = serde_json::to_value(handling::get_all_addrs(&db, true).unwrap()).unwrap();
+        assert_eq!(response["result"]["addresses"].as_array().unwrap().len(), 2);
+        assert_eq!(response["orphans"], json!([orphan.to_hex()]));
+        let response = serde_json::to_value(handling::get_all_addrs(&db, false).unwrap()).unwrap();
+        assert!(response.get("orphans").is_none());
+    }
+
+    #[test]
+    fn test_capacity_caps() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [20u8; 32].into();
+        let other: ContractAddress = [21u8; 32].into();
+        contract_with_tip(&mut db, address, 0);
+        db.set_capacity_limits(CapacityLimits { max_db_bytes: None, max_contract_delta_bytes: Some(4), max_contracts: Some(1) });
+        let details = |response: Result<IpcResponse, failure::Error>| serde_json::to_value(response.unwrap_or_error()).unwrap()["details"].clone();
+        let exceeded = |cap: &str, usage: u64, limit: u64, address: ContractAddress| {
+            json!({ "code": "CapacityExceeded", "cap": cap, "usage": usage, "limit": limit, "address": address.to_hex() })
+        };
+
+        // Refused before reaching the enclave.
+        let deploy = IpcTask { pre_code: Some(b"code".to_vec()), ..compute_input(other) };
+        assert_eq!(details(handling::deploy_contract(&mut db, deploy, 0)), exceeded("maxContracts", 1, 1, other));
+
+        let delta = IpcDelta { contract_address: Some(address.to_hex()), key: 1, data: Some(vec![1, 2, 3, 4]), chain_hash: None, bounds: None };
+        assert_eq!(details(handling::update_deltas(&mut db, vec![delta], false)), exceeded("maxContractDeltaBytes", 1, 4, address));
+
+        let delta = Delta { key: DeltaKey::new(address, Stype::Delta(1)), value: vec![1, 2, 3, 4] };
+        let err = handling::task_delta(&mut db, Some(0), &delta, true).unwrap_err();
+        assert_eq!(details(Err(err)), exceeded("maxContractDeltaBytes", 1, 4, address));
+        // Without persisting it the delta is only sent back.
+        handling::task_delta(&mut db, Some(0), &delta, false).unwrap();
+        assert_eq!(db.contract_delta_bytes(address).unwrap(), 1);
+
+        let stats = serde_json::to_value(handling::get_contract_stats(&db, &address.to_hex()).unwrap()).unwrap();
+        assert_eq!((&stats["result"]["result"]["deltaBytes"], &stats["result"]["result"]["maxDeltaBytes"]), (&json!(1), &json!(4)));
+        // The usage comes from the metrics, shared with the other tests.
+        let health = serde_json::to_value(handling::get_health(&HealthProbe::new(&db)).unwrap()).unwrap();
+        assert_eq!(health["result"]["capacity"]["maxContracts"], 1);
+        assert_eq!(health["result"]["capacity"]["maxDbBytes"], Value::Null);
+    }
+
+    #[test]
+    fn test_hosting_modes() {
+        let (mut db, _dir) = create_test_db();
+        let selected: ContractAddress = [30u8; 32].into();
+        let other: ContractAddress = [31u8; 32].into();
+        contract_with_tip(&mut db, selected, 0);
+        contract_with_tip(&mut db, other, 0);
+        let new_contract = |db: &mut DB, address: ContractAddress| {
+            let response = handling::update_new_contract(db, address.to_hex(), b"code").unwrap();
+            serde_json::to_value(response).unwrap()["result"]["status"].clone()
+        };
+        let delta = |address: ContractAddress| IpcDelta { contract_address: Some(address.to_hex()), key: 1, data: Some(vec![1]), chain_hash: None, bounds: None };
+        let on_deployment = |db: &mut DB, address: ContractAddress| {
+            let delta = IpcDelta { contract_address: None, key: 0, data: Some(vec![1]), chain_hash: None, bounds: None };
+            let response = handling::update_new_contract_on_deployment(db, address.to_hex(), "aabb", delta).unwrap();
+            serde_json::to_value(response).unwrap()["result"]["status"].clone()
+        };
+        let enclave = crate::esgx::general::init_enclave_wrapper().unwrap();
+        let ptt_request = |db: &DB| {
+            let response = serde_json::to_value(handling::get_ptt_req(db, None, enclave.geteid()).unwrap()).unwrap();
+            response["result"]["addresses"].clone()
+        };
+
+        // Everything is hosted by default.
+        assert_eq!(new_contract(&mut db, other), "ok");
+        assert_eq!(ptt_request(&db), Value::Null);
+
+        db.set_hosting_mode(HostingMode::SelectedOnly);
+        // Until the KM node provisioned the keys.
+        assert_eq!(new_contract(&mut db, other), "ok");
+        crate::km_u::tests::instantiate_encryption_key(vec![selected], enclave.geteid());
+        handling::select_provisioned(&mut db, enclave.geteid()).unwrap();
+        assert_eq!(new_contract(&mut db, selected), "ok");
+        assert_eq!(new_contract(&mut db, other), "not_hosted");
+        assert_eq!(on_deployment(&mut db, other), "not_hosted");
+        // Every key is still kept, a contract selected in a new epoch wouldn't be otherwise.
+        assert_eq!(ptt_request(&db), Value::Null);
+
+        let response = handling::update_deltas(&mut db, vec![delta(selected), delta(other)], false).unwrap();
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["result"]["status"], "not_hosted");
+        assert_eq!(response["result"]["errors"][0], json!({ "address": selected.to_hex(), "key": 1, "status": "ok" }));
+        assert_eq!(response["result"]["errors"][1], json!({ "address": other.to_hex(), "key": 1, "status": "not_hosted" }));
+        assert_eq!(db.get_tip::<DeltaKey>(&other).unwrap().0.key_type.unwrap_delta(), 0);
+
+        db.set_hosting_mode(HostingMode::ExplicitList);
+        assert_eq!(ptt_request(&db), json!([]));
+        assert_eq!(new_contract(&mut db, selected), "not_hosted");
+        handling::set_hosted_contracts(&mut db, &[other.to_hex()]).unwrap();
+        assert_eq!(new_contract(&mut db, other), "ok");
+        assert_eq!(on_deployment(&mut db, selected), "not_hosted");
+        let deployed: ContractAddress = [33u8; 32].into();
+        handling::set_hosted_contracts(&mut db, &[other.to_hex(), deployed.to_hex()]).unwrap();
+        assert_eq!(on_deployment(&mut db, deployed), "ok");
+        assert_eq!(ptt_request(&db), json!([other.to_hex(), deployed.to_hex()]));
+        // Only the keys of the hosted contracts are kept.
+        let dropped: ContractAddress = [34u8; 32].into();
+        let response = crate::km_u::tests::encrypted_ptt_response(vec![deployed, dropped], enclave.geteid());
+        handling::ptt_response(&mut db, &PrincipalResponse { response: response.to_hex() }, enclave.geteid()).unwrap();
+        let provisioned = crate::km_u::provisioned_addresses(enclave.geteid()).unwrap();
+        assert!(provisioned.contains(&deployed) && !provisioned.contains(&dropped));
+        // Nothing stored is removed.
+        assert_eq!(db.get_all_addresses().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_address_index() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [32u8; 32].into();
+        let stats = |db: &DB| db.address_index().stats();
+        let bytecode = |db: &DB| match handling::get_contract(db, &address.to_hex(), None, None).unwrap() {
+            IpcResponse::GetContract { result: IpcResults::GetContract { bytecode, .. } } => bytecode,
+            other => panic!("Unexpected response: {:?}", other),
+        };
+
+        assert!(bytecode(&db).is_empty());
+        assert!(handling::get_tip(&db, &address.to_hex()).is_err());
+        assert_eq!(stats(&db).store_lookups, 0);
+        assert_eq!(stats(&db).skipped_lookups, 2);
+
+        handling::update_new_contract(&mut db, address.to_hex(), b"code").unwrap();
+        assert_eq!(bytecode(&db), b"code");
+        let health = serde_json::to_value(handling::get_health(&HealthProbe::new(&db)).unwrap()).unwrap();
+        assert_eq!(health["result"]["addressIndex"]["addresses"], 1);
+        assert_eq!(health["result"]["addressIndex"]["fpRate"], Value::Null);
+
+        handling::remove_contract(&mut db, address.to_hex()).unwrap();
+        assert!(bytecode(&db).is_empty());
+        assert_eq!(stats(&db).addresses, 0);
+    }
+
+    #[test]
+    fn test_rebuild_indexes() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [33u8; 32].into();
+        handling::update_new_contract(&mut db, address.to_hex(), b"code").unwrap();
+        db.address_index().rebuild(&[], None);
+        let details = |response: Result<IpcResponse, failure::Error>| serde_json::to_value(response.unwrap_or_error()).unwrap()["details"].clone();
+
+        // A local state, the global one would refuse the writes of the other tests.
+        let drain = DrainState::new();
+        let refused = handling::rebuild_indexes(&db, &drain, None);
+        assert_eq!(details(refused), json!({ "code": "NotDrained", "draining": false, "inFlight": 0 }));
+        drain.start();
+        drain.admitted(1);
+        let refused = handling::rebuild_indexes(&db, &drain, None);
+        assert_eq!(details(refused), json!({ "code": "NotDrained", "draining": true, "inFlight": 1 }));
+        drain.finished(1);
+
+        let scopes = Some(vec![RebuildScope::AddressIndex]);
+        let response = serde_json::to_value(handling::rebuild_indexes(&db, &drain, scopes).unwrap()).unwrap();
+        assert_eq!(response["result"]["rebuild"], json!({ "contracts": 1, "addressIndex": 1, "chainHashes": null, "gapIndex": null, "hotSet": null }));
+        assert_eq!(db.address_index().stats().addresses, 1);
+        let response = serde_json::to_value(handling::rebuild_indexes(&db, &drain, None).unwrap()).unwrap();
+        assert_eq!(response["result"]["rebuild"]["addressIndex"], 0);
+    }
+
+    #[test]
+    fn test_contract_chunks() {
+        const MB: usize = 1 << 20;
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [11u8; 32].into();
+        let bytecode: Vec<u8> = (0..6 * MB).map(|i| (i % 251) as u8).collect();
+        db.create(&DeltaKey::new(address, Stype::ByteCode), &bytecode[..]).unwrap();
+
+        let mut reassembled = Vec::with_capacity(bytecode.len());
+        let mut hashes = Vec::new();
+        loop {
+            let response = handling::get_contract(&db, &address.to_hex(), Some(reassembled.len() as u64), Some(MB as u64)).unwrap();
+            let (chunk, total_len, hash) = match response {
+                IpcResponse::GetContract { result: IpcResults::GetContract { bytecode, total_len, bytecode_hash, .. } } => (bytecode, total_len, bytecode_hash),
+                other => panic!("Unexpected response: {:?}", other),
+            };
+            assert_eq!(total_len, Some(bytecode.len() as u64));
+            hashes.push(hash.unwrap());
+            if chunk.is_empty() {
+                break;
+            }
+            assert!(chunk.len() <= MB);
+            reassembled.extend_from_slice(&chunk);
+        }
+        assert_eq!(hashes.len(), 7);
+        assert!(hashes.iter().all(|hash| *hash == hashes[0]));
+        assert_eq!(reassembled.keccak256().to_hex(), hashes[0]);
+
+        // Without the chunk parameters it's the whole bytecode, like before.
+        let response = serde_json::to_value(handling::get_contract(&db, &address.to_hex(), None, None).unwrap()).unwrap();
+        assert_eq!(response["result"]["bytecode"].as_array().unwrap().len(), bytecode.len());
+        assert!(response["result"].get("totalLen").is_none());
+    }
+
+    #[test]
+    fn test_compressed_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [12u8; 32].into();
+        for key in 0..20u32 {
+            // Looks encrypted, nothing to compress in the bytes themselves.
+            let data: Vec<u8> = (0..32u8).flat_map(|i| [key as u8, i][..].keccak256().to_vec()).collect();
+            db.create(&DeltaKey::new(address, Stype::Delta(key)), &data[..]).unwrap();
+        }
+        let request = |accept: &str| {
+            let msg = format!(r#"{{"id":"1",{}"type":"GetDeltas","input":[{{"address":"{}","from":0,"to":20}}]}}"#, accept, address.to_hex());
+            let mut multipart = Multipart::new();
+            multipart.push_back(Message::from(msg.as_str()));
+            let reply = handle_message(&mut db, multipart, "", 0, 0);
+            let frames: Vec<Vec<u8>> = reply.iter().map(|frame| frame.to_vec()).collect();
+            frames
+        };
+
+        let plain = request("");
+        assert_eq!(plain.len(), 1);
+        let compressed = request(r#""accept_encoding":"zstd","#);
+        assert_eq!(compressed.len(), 2);
+        assert_eq!(&compressed[0][..], compression::ZSTD_MARKER);
+        assert!(compressed[1].len() * 2 < plain[0].len(), "{} bytes compressed to {}", plain[0].len(), compressed[1].len());
+
+        let decoded = compression::decode(compressed.iter().map(|frame| &frame[..])).unwrap();
+        assert_eq!(decoded, plain);
+        let response: Value = serde_json::from_slice(&decoded[0]).unwrap();
+        assert_eq!(response["result"]["deltas"].as_array().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [19u8; 32].into();
+        contract_with_tip(&mut db, address, 0);
+        db.get_contract_cached(address).unwrap();
+        db.update_state_status(true);
+        let secret = [0xabu8; 32].to_hex();
+        let mut request = Multipart::new();
+        request.push_back(Message::from(format!(r#"{{"id":"p","type":"TestPanic","message":"bad key {}"}}"#, secret).as_str()));
+        request.push_back(Message::from(r#"{"id":"t","type":"GetAllTips"}"#));
+        let reply = handle_message(&mut db, request, "", 0, 0);
+        let responses: Vec<Value> = reply.iter().map(|frame| serde_json::from_slice(frame).unwrap()).collect();
+        assert_eq!(responses.len(), 2);
+
+        assert_eq!(responses[0]["id"], "p");
+        assert_eq!(responses[0]["type"], "Error");
+        assert_eq!(responses[0]["msg"], "Internal error: bad key <redacted>");
+        assert_eq!(responses[0]["details"], json!({ "code": "Internal" }));
+        assert_eq!(responses[0]["retryable"], false);
+        // The frame after the panic is handled as usual.
+        assert_eq!(responses[1]["id"], "t");
+        assert_eq!(responses[1]["type"], "GetAllTips");
+        // What the handlers keep in memory was rebuilt, not reused.
+        assert!(!db.get_state_status());
+        let misses = db.contract_cache().misses();
+        db.get_contract_cached(address).unwrap();
+        assert_eq!(db.contract_cache().misses(), misses + 1);
+
+        // And so are the next requests.
+        let mut request = Multipart::new();
+        request.push_back(Message::from(r#"{"id":"n","type":"GetAllTips"}"#));
+        let reply = handle_message(&mut db, request, "", 0, 0);
+        let response: Value = serde_json::from_slice(&reply[0]).unwrap();
+        assert_eq!(response["type"], "GetAllTips");
+    }
+
+    #[test]
+    fn test_request_ids() {
+        let (mut db, _dir) = create_test_db();
+        let long_id = "l".repeat(200);
+        let frames: Vec<Vec<u8>> = vec![
+            br#"{"id":"","type":"GetAllTips"}"#.to_vec(),
+            format!(r#"{{"id":"{}","type":"GetAllTips"}}"#, long_id).into_bytes(),
+            br#"{"id":"m","type":"GetTip","input":"#.to_vec(),
+            b"{\"id\":\"u\",\"type\":\"GetTip\",\"input\":\"\xff\"}".to_vec(),
+            r#"{"id":"é \"q\"","type":"GetAllTips"}"#.as_bytes().to_vec(),
+        ];
+        let mut request = Multipart::new();
+        for frame in &frames {
+            request.push_back(Message::from(&frame[..]));
+        }
+        let reply = handle_message(&mut db, request, "", 0, 0);
+        let responses: Vec<Value> = reply.iter().map(|frame| serde_json::from_slice(frame).unwrap()).collect();
+        assert_eq!(responses.len(), frames.len());
+
+        // Refused without being handled, with what could be read of the id.
+        assert_eq!(responses[0]["id"], "");
+        assert_eq!(responses[0]["invalid_id"], true);
+        assert_eq!(responses[0]["type"], "Error");
+        assert_eq!(responses[1]["id"], long_id[..request_id::MAX_ID_LEN]);
+        assert_eq!(responses[1]["invalid_id"], true);
+        // The requests that don't parse keep their id.
+        for (response, id) in responses[2..4].iter().zip(&["m", "u"]) {
+            assert_eq!(response["id"], *id);
+            assert_eq!(response["type"], "Error");
+            assert!(response.get("invalid_id").is_none());
+        }
+        assert_eq!(responses[4]["id"], "é \"q\"");
+        assert_eq!(responses[4]["type"], "GetAllTips");
+        assert!(responses[4].get("invalid_id").is_none());
+    }
+
+    #[test]
+    fn test_registration_history() {
+        let (db, _dir) = create_test_db();
+        // What `get_registration_params` records after the IAS answered.
+        for n in 1..=2u8 {
+            let record = RegistrationRecord {
+                index: 0,
+                timestamp: 1_560_000_000 + u64::from(n),
+                signing_key: [n; 20].to_hex(),
+                report: format!("{{\"id\":\"{}\"}}", n).as_bytes().to_hex(),
+                signature: [n; 256].to_hex(),
+                mr_enclave: [n; 32].to_hex(),
+            };
+            handling::record_registration(&db, &record, 10);
+        }
+
+        let response = serde_json::to_value(handling::get_registration_history(&db, None).unwrap()).unwrap();
+        assert_eq!(response["type"], "GetRegistrationHistory");
+        let history = response["result"]["registrationHistory"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["index"], 1);
+        assert_eq!(history[0]["signingKey"], [2u8; 20].to_hex());
+        assert_eq!(history[0]["mrEnclave"], [2u8; 32].to_hex());
+        assert_eq!(history[1]["index"], 0);
+        assert_eq!(history[1]["signature"], [1u8; 256].to_hex());
+
+        let response = serde_json::to_value(handling::get_registration_history(&db, Some(1)).unwrap()).unwrap();
+        assert_eq!(response["result"]["registrationHistory"].as_array().unwrap().len(), 1);
+    }
+
+    fn signed_receipt(keys: &KeyPair, address: ContractAddress, prev_delta: &[u8], delta: &[u8], delta_key: u32,
+                      envelope_hash: Option<Hash256>) -> IpcTaskReceipt {
+        let receipt = ExecuteReceipt {
+            exe_code_hash: b"code".keccak256(),
+            inputs_hash: [1u8; 32].into(),
+            prev_delta_hash: prev_delta.keccak256(),
+            delta_hash: delta.keccak256(),
+            output_hash: [2u8; 32].into(),
+            gas_limit: 1000,
+            used_gas: 40,
+            ethereum_payload: Vec::new(),
+            ethereum_address: [0u8; 20],
+            envelope_hash,
+        };
+        IpcTaskReceipt {
+            address: address.to_hex(),
+            task_id: "task".to_string(),
+            inputs_hash: receipt.inputs_hash.to_hex(),
+            exe_code_hash: None,
+            delta_key: Some(delta_key),
+            prev_delta_hash: None,
+            delta_hash: receipt.delta_hash.to_hex(),
+            output_hash: receipt.output_hash.to_hex(),
+            gas_limit: receipt.gas_limit,
+            used_gas: receipt.used_gas,
+            ethereum_payload: String::new(),
+            ethereum_address: None,
+            envelope_hash: envelope_hash.map(|hash| hash.to_hex()),
+            signature: keys.sign(&receipt.to_signable_bytes()).unwrap().to_hex(),
+            worker_address: Some(keys.get_pubkey().address().to_hex()),
+        }
+    }
+
+    #[test]
+    fn test_verify_task_receipt() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [9u8; 32].into();
+        // Stores the deltas [1] and [2].
+        contract_with_tip(&mut db, address, 1);
+        let keys = KeyPair::new().unwrap();
+
+        let receipt = signed_receipt(&keys, address, &[1], &[2], 1, None);
+        assert_eq!(handling::receipt_verdict(&db, &receipt).unwrap(), ReceiptVerdict::Valid);
+        let response = serde_json::to_value(handling::verify_task_receipt(&db, receipt.clone()).unwrap()).unwrap();
+        assert_eq!(response["result"], json!({ "taskId": "task", "verdict": "valid" }));
+
+        // Against the registered signing address.
+        let no_worker = IpcTaskReceipt { worker_address: None, ..receipt.clone() };
+        assert!(handling::receipt_verdict(&db, &no_worker).is_err());
+        let record = RegistrationRecord {
+            index: 0,
+            timestamp: 0,
+            signing_key: keys.get_pubkey().address().to_hex(),
+            report: String::new(),
+            signature: String::new(),
+            mr_enclave: String::new(),
+        };
+        handling::record_registration(&db, &record, 10);
+        assert_eq!(handling::receipt_verdict(&db, &no_worker).unwrap(), ReceiptVerdict::Valid);
+
+        let tampered = IpcTaskReceipt { used_gas: 41, ..receipt.clone() };
+        assert_eq!(handling::receipt_verdict(&db, &tampered).unwrap(), ReceiptVerdict::BadSignature);
+        let other_worker = IpcTaskReceipt { worker_address: Some([3u8; 20].to_hex()), ..receipt.clone() };
+        assert_eq!(handling::receipt_verdict(&db, &other_worker).unwrap(), ReceiptVerdict::BadSignature);
+
+        // A delta that was never sent to this node.
+        let unknown = signed_receipt(&keys, address, &[2], &[3], 2, None);
+        assert_eq!(handling::receipt_verdict(&db, &unknown).unwrap(), ReceiptVerdict::UnknownDelta);
+        // A validly signed receipt for another delta than the stored one.
+        let mismatch = signed_receipt(&keys, address, &[1], &[7], 1, None);
+        assert_eq!(handling::receipt_verdict(&db, &mismatch).unwrap(), ReceiptVerdict::DeltaMismatch);
+
+        // The hash of an envelope is signed too, it can't be dropped or swapped.
+        let enveloped = signed_receipt(&keys, address, &[1], &[2], 1, Some([6u8; 32].into()));
+        assert_eq!(handling::receipt_verdict(&db, &enveloped).unwrap(), ReceiptVerdict::Valid);
+        let dropped = IpcTaskReceipt { envelope_hash: None, ..enveloped.clone() };
+        assert_eq!(handling::receipt_verdict(&db, &dropped).unwrap(), ReceiptVerdict::BadSignature);
+        let swapped = IpcTaskReceipt { envelope_hash: Some(Hash256::from([7u8; 32]).to_hex()), ..enveloped.clone() };
+        assert_eq!(handling::receipt_verdict(&db, &swapped).unwrap(), ReceiptVerdict::BadSignature);
+    }
+
     #[ignore]
     #[test]
     fn test_the_listener() {
@@ -0,0 +1,302 @@
+//! # IPC Queue.
+//! The bounded queue between the socket and the thread running the IPC handlers.
+//! When it's full a request is answered right away with a `Busy` error instead of waiting behind the others,
+//! so an overloaded node keeps a bounded memory and the p2p node knows within milliseconds that it should retry.
+//! A request over its rate limit (see [`rate_limit`](../../common_u/rate_limit/index.html)) is answered the same way.
+//! `GetHealth` and `ReloadConfig` never enter the queue, so monitoring keeps working while the handlers are saturated,
+//! and limits that are too tight can always be reloaded. Neither do the drain requests, see [`drain`](../../common_u/drain/index.html),
+//! while draining the mutating requests are answered right away with a `Draining` error.
+
+use crate::common_u::drain::{self, DRAIN};
+use crate::common_u::errors::{BusyErr, DrainingErr, Retry, ENCLAVE_BUSY_RETRY_MS};
+use crate::common_u::metrics::{self, METRICS};
+use crate::common_u::rate_limit::RATE_LIMITS;
+use crate::networking::messages::*;
+use crate::networking::request_id::{self, FrameId};
+use crate::common_u::panics::LockRecover;
+use failure::Error;
+use futures::sync::mpsc::UnboundedSender;
+use serde_json;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio_zmq::Multipart;
+
+/// How many requests can wait for the handler thread by default.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// The request types answered on the socket thread, they never wait for the DB or the enclave.
+pub const BYPASS_TYPES: [&str; 5] = ["GetHealth", "ReloadConfig", "Drain", "Resume", "GetDrainStatus"];
+
+// Only the fields needed to route a request, the rest is parsed by the handler.
+struct RequestHeader {
+    id: FrameId,
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct RequestKind {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A request waiting for the handler thread, with the routing frames its reply has to carry.
+struct Job {
+    // Empty for a request of the core itself, nobody waits for its reply.
+    envelope: Multipart,
+    body: Multipart,
+    enqueued_at: Instant,
+    // How many of its requests are mutating ones, they're in flight until the reply is sent.
+    mutating: usize,
+}
+
+pub struct IpcQueue {
+    sender: SyncSender<Job>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl IpcQueue {
+    /// Starts the thread running `handler` on the queued requests, their replies are sent to `replies`.
+    /// At most `capacity` requests wait besides the one being handled, with 0 a request is only taken if the thread is idle.
+    pub fn spawn<F>(capacity: usize, mut handler: F, replies: UnboundedSender<Multipart>) -> io::Result<Self>
+    where F: FnMut(Multipart) -> Multipart + Send + 'static {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let handler_depth = Arc::clone(&depth);
+        thread::Builder::new().name("ipc-handler".to_string()).spawn(move || {
+            for Job { envelope, body, enqueued_at, mutating } in receiver {
+                let depth = handler_depth.fetch_sub(1, Ordering::SeqCst) - 1;
+                METRICS.record_queue_wait(enqueued_at.elapsed(), depth);
+                let reply = handler(body);
+                // Before the reply, a client polling `GetDrainStatus` once it got it sees them done.
+                DRAIN.finished(mutating);
+                if envelope.is_empty() {
+                    continue;
+                }
+                let reply = join(envelope, reply);
+                if replies.unbounded_send(reply).is_err() {
+                    debug!("The IPC socket is gone, stopping the handler thread");
+                    break;
+                }
+            }
+        })?;
+        METRICS.set_queue_capacity(capacity);
+        Ok(IpcQueue { sender, depth })
+    }
+
+    /// Queues a request as received by a ROUTER socket.
+    /// Returns the reply to send right away if the request bypasses the queue (answered with `bypass`),
+    /// if it's mutating while draining, if it's over its rate limit or if the queue is full.
+    /// A message of several requests is refused whole if one of them is.
+    pub fn admit<B>(&self, multipart: Multipart, bypass: B) -> Option<Multipart>
+    where B: FnOnce(Multipart) -> Multipart {
+        let (envelope, body) = split_envelope(multipart);
+        if body.is_empty() {
+            warn!("Dropping an IPC message without a body");
+            return None;
+        }
+        let headers = headers(&body);
+        // A frame without a header goes to the handler thread, the bypass only answers the bypass types.
+        let bypasses = headers.len() == body.len() && headers.iter().all(|header| BYPASS_TYPES.contains(&header.kind.as_str()));
+        if bypasses {
+            return Some(join(envelope, bypass(body)));
+        }
+        let kinds: Vec<&str> = headers.iter().map(|header| header.kind.as_str()).collect();
+        let mutating = kinds.iter().filter(|kind| drain::is_mutating(kind)).count();
+        // The drain requests are handled on this thread too, none can slip in between the check and the count.
+        if mutating > 0 && DRAIN.is_draining() {
+            return Some(join(envelope, refuse(&headers, DrainingErr.into())));
+        }
+        if let Err(retry_after_ms) = RATE_LIMITS.lock_recover("Rate limits").admit(&kinds, Instant::now()) {
+            return Some(join(envelope, busy(&headers, retry_after_ms)));
+        }
+
+        // Counted before it's sent, the handler thread may take it out before `try_send` even returns.
+        let depth = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        DRAIN.admitted(mutating);
+        match self.sender.try_send(Job { envelope, body, enqueued_at: Instant::now(), mutating }) {
+            Ok(()) => {
+                METRICS.set_queue_depth(depth);
+                None
+            }
+            Err(TrySendError::Full(job)) => {
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+                DRAIN.finished(mutating);
+                for header in &headers {
+                    METRICS.record_shed(&header.kind);
+                }
+                Some(join(job.envelope, busy(&headers, ENCLAVE_BUSY_RETRY_MS)))
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("The IPC handler thread died"),
+        }
+    }
+
+    /// A handle for the other threads of the core to queue requests of their own.
+    pub fn handle(&self) -> QueueHandle { QueueHandle { sender: self.sender.clone(), depth: Arc::clone(&self.depth) } }
+}
+
+#[derive(Clone)]
+pub struct QueueHandle {
+    sender: SyncSender<Job>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl QueueHandle {
+    /// Queues a request of the core itself, its reply is dropped.
+    /// It's only queued if at most `max_depth` requests are waiting, returns whether it was.
+    pub fn submit(&self, body: Multipart, max_depth: usize) -> bool {
+        if self.depth.load(Ordering::SeqCst) > max_depth {
+            return false;
+        }
+        let depth = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.sender.try_send(Job { envelope: Multipart::new(), body, enqueued_at: Instant::now(), mutating: 0 }) {
+            Ok(()) => {
+                METRICS.set_queue_depth(depth);
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("The IPC handler thread died"),
+        }
+    }
+}
+
+/// Answers each of the requests with `Busy`, without logging them one by one, under overload there can be many.
+fn busy(headers: &[RequestHeader], retry_after_ms: u64) -> Multipart { refuse(headers, BusyErr { retry_after_ms }.into()) }
+
+/// Answers each of the requests with `err`.
+fn refuse(headers: &[RequestHeader], err: Error) -> Multipart {
+    let retry = Retry::of(&err);
+    let code = metrics::error_code(&err);
+    let mut responses = Multipart::new();
+    for header in headers {
+        let id = match &header.id {
+            FrameId::Valid(id) => id.clone(),
+            FrameId::Invalid { echo, reason } => {
+                responses.push_back(request_id::invalid_id_response(echo.clone(), *reason).into());
+                continue;
+            }
+        };
+        METRICS.record_error(&code);
+        let response = IpcResponse::Error {
+            msg: err.to_string(),
+            retryable: retry.is_retryable(),
+            retry_after_ms: retry.retry_after_ms(),
+            details: IpcErrorDetails::from_error(&err),
+        };
+        responses.push_back(IpcMessageResponse::from_response(response, id).into());
+    }
+    responses
+}
+
+// A request that isn't valid JSON gets no header, the handler will answer it as it always did.
+fn headers(body: &Multipart) -> Vec<RequestHeader> {
+    body.iter()
+        .filter_map(|msg| {
+            let RequestKind { kind } = serde_json::from_slice(msg).ok()?;
+            Some(RequestHeader { id: request_id::frame_id(msg), kind })
+        })
+        .collect()
+}
+
+/// Splits the routing frames the ROUTER socket added (the peer identity and the empty delimiter of a REQ socket) from the request.
+fn split_envelope(mut multipart: Multipart) -> (Multipart, Multipart) {
+    let mut envelope = Multipart::new();
+    while let Some(frame) = multipart.pop_front() {
+        let delimiter = frame.is_empty();
+        envelope.push_back(frame);
+        if delimiter {
+            break;
+        }
+    }
+    (envelope, multipart)
+}
+
+fn join(mut envelope: Multipart, body: Multipart) -> Multipart {
+    for frame in body {
+        envelope.push_back(frame);
+    }
+    envelope
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::sync::mpsc::unbounded;
+    use futures::Stream;
+    use serde_json::Value;
+    use std::sync::mpsc::channel;
+    use zmq::Message;
+
+    fn request(kind: &str) -> Multipart {
+        let mut multipart = Multipart::new();
+        multipart.push_back(Message::from("peer"));
+        multipart.push_back(Message::from(""));
+        multipart.push_back(Message::from(format!("{{\"id\":\"{}\",\"type\":\"{}\"}}", kind, kind).as_str()));
+        multipart
+    }
+
+    fn response(mut reply: Multipart) -> Value {
+        assert_eq!(reply.pop_front().unwrap().as_str(), Some("peer"));
+        assert!(reply.pop_front().unwrap().is_empty());
+        serde_json::from_str(reply.pop_front().unwrap().as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_split_envelope() {
+        let (envelope, body) = split_envelope(request("GetTip"));
+        assert_eq!(envelope.len(), 2);
+        assert_eq!(body.len(), 1);
+        assert_eq!(headers(&body)[0].kind, "GetTip");
+    }
+
+    #[test]
+    fn test_bypass_whole_messages_only() {
+        let (replies, reply_stream) = unbounded();
+        let queue = IpcQueue::spawn(1, |body| body, replies).unwrap();
+        // A frame that isn't JSON has no header, it's left to the handler thread with the health check next to it.
+        let mut multipart = request("GetHealth");
+        multipart.push_back(Message::from("{\"id\":\"x\",\"type\":"));
+        assert!(queue.admit(multipart, |_| unreachable!()).is_none());
+        let reply = reply_stream.wait().next().unwrap().unwrap();
+        assert_eq!(reply.len(), 4);
+    }
+
+    #[test]
+    fn test_sheds_when_full() {
+        let (replies, reply_stream) = unbounded();
+        let (release, released) = channel::<()>();
+        let queue = IpcQueue::spawn(1, move |body| {
+            released.recv().unwrap();
+            body
+        }, replies).unwrap();
+
+        // The first one is taken by the handler thread, the second waits.
+        assert!(queue.admit(request("GetTip"), |_| unreachable!()).is_none());
+        while queue.depth.load(Ordering::SeqCst) != 0 {
+            thread::yield_now();
+        }
+        assert!(queue.admit(request("GetTips"), |_| unreachable!()).is_none());
+
+        let busy = response(queue.admit(request("ComputeTask"), |_| unreachable!()).unwrap());
+        assert_eq!(busy["id"], "ComputeTask");
+        assert_eq!(busy["type"], "Error");
+        assert_eq!(busy["retryAfterMs"], ENCLAVE_BUSY_RETRY_MS);
+        assert_eq!(busy["details"]["code"], "Busy");
+
+        // Health checks don't wait behind the others.
+        let health = queue.admit(request("GetHealth"), |body| body).unwrap();
+        assert_eq!(response(health)["type"], "GetHealth");
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        let handled: Vec<Value> = reply_stream.wait().take(2).map(|reply| response(reply.unwrap())).collect();
+        assert_eq!(handled[0]["type"], "GetTip");
+        assert_eq!(handled[1]["type"], "GetTips");
+    }
+}
@@ -0,0 +1,74 @@
+//! # Maintenance scheduler.
+//! Queues a `RunMaintenance` request every `interval`, see [`maintenance`](../../db/maintenance/index.html).
+//! It goes through the IPC queue like the requests of the p2p node, so the pass runs on the thread owning the DB,
+//! never at the same time as a handler. It waits for a quiet moment: while more than `max_queue_depth` requests are
+//! waiting it's retried a few seconds later, so a loaded node doesn't pay for a compaction on top of its work.
+
+use crate::networking::ipc_queue::QueueHandle;
+use crate::networking::messages::{IpcMessageRequest, IpcRequest};
+use serde_json;
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio_zmq::Multipart;
+use zmq::Message;
+
+/// How often a pass runs by default.
+pub const DEFAULT_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long a pass waits for the queue to get quiet before trying again.
+const LOADED_RETRY: Duration = Duration::from_secs(5);
+/// The id of the requests of the scheduler, as seen in the logs.
+const MAINTENANCE_REQUEST_ID: &str = "maintenance";
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenanceSchedule {
+    pub interval: Duration,
+    /// A pass is only queued if at most this many requests are waiting.
+    pub max_queue_depth: usize,
+}
+
+impl MaintenanceSchedule {
+    /// Queues the passes on a background thread until the process exits.
+    pub fn spawn(self, queue: QueueHandle) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new().name("maintenance".to_string()).spawn(move || loop {
+            thread::sleep(self.interval);
+            while !queue.submit(request(), self.max_queue_depth) {
+                debug!("The IPC queue is busy, postponing the maintenance");
+                thread::sleep(LOADED_RETRY.min(self.interval));
+            }
+        })
+    }
+}
+
+fn request() -> Multipart {
+    let msg = IpcMessageRequest::from_request(IpcRequest::RunMaintenance, MAINTENANCE_REQUEST_ID.to_string());
+    let mut body = Multipart::new();
+    body.push_back(Message::from(&serde_json::to_vec(&msg).expect("A request always serializes")));
+    body
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::sync::mpsc::unbounded;
+    use crate::networking::ipc_queue::IpcQueue;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_schedule_queues_maintenance() {
+        let (replies, _reply_stream) = unbounded();
+        let (handled, handled_rx) = channel();
+        let queue = IpcQueue::spawn(4, move |body: Multipart| {
+            let msg: IpcMessageRequest = serde_json::from_slice(&body[0]).unwrap();
+            // The scheduler outlives the test.
+            let _ = handled.send(msg.request.kind());
+            Multipart::new()
+        }, replies).unwrap();
+
+        let schedule = MaintenanceSchedule { interval: Duration::from_millis(10), max_queue_depth: 0 };
+        schedule.spawn(queue.handle()).unwrap();
+        assert_eq!(handled_rx.recv_timeout(Duration::from_secs(5)).unwrap(), "RunMaintenance");
+        // And again after the next interval.
+        assert_eq!(handled_rx.recv_timeout(Duration::from_secs(5)).unwrap(), "RunMaintenance");
+    }
+}
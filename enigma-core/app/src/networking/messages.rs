@@ -1,21 +1,158 @@
 use serde_json;
-use serde_repr::{Serialize_repr, Deserialize_repr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use zmq::Message;
-use crate::db::{Delta, Stype, DeltaKey};
-use hex::ToHex;
+use crate::common_u::epoch::{EpochParams, EPOCH};
+use crate::common_u::errors::{BusyErr, CapacityExceededErr, ContractNotFoundErr, DebugTraceDisabledErr, DeltaGapErr, DrainingErr, InternalErr, NotDrainedErr, RecoveringErr, Retry, StaleEpochErr, StateBehindErr, UnknownEpochErr};
+use crate::common_u::rate_limit::RateLimitConfig;
+use crate::common_u::recovery::RecoveryProgress;
+use crate::db::{AddressIndexStats, Delta, DeltaTips, Stype, DeltaKey, MaintenanceReport, MirrorStatus, RebuildProgress, RebuildReport, RebuildScope, RegistrationRecord};
+use crate::networking::compression::Encoding;
+use hex::{FromHex, ToHex};
+use enigma_tools_m::audit::{AuditEvent, AuditEventKind};
+use enigma_tools_m::signable::{to_compact, DeployReceipt, ExecuteReceipt, SigFormat};
+use enigma_tools_m::trace::ExecutionTrace;
+use crate::common_u::panics::LockRecover;
+use enigma_types::Hash256;
 use failure::Error;
 
-// These attributes enable the status to be casted as an i8 object as well
-#[derive(Serialize_repr, Deserialize_repr, Clone, Debug)]
-#[repr(i8)]
+/// The version of the wire format this core speaks, a request can ask for an older one with `protocol_version`.
+/// Since 2 the fields of a task result are only in its `attested` and `supplemental` sections.
+/// Since 3 the enclave signatures are also given split in `compactSig` and `recoveryId`, see `IpcSigForms`.
+/// Since 4 the tips also give their `bounds`, the raw and the usable tip, see `IpcTipBounds`.
+pub const PROTOCOL_VERSION: u32 = 4;
+/// The version of a request that doesn't say, version 1 also has the fields of the task result sections flat.
+pub const DEFAULT_PROTOCOL_VERSION: u32 = 1;
+
+static LEGACY_STATUS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // The format `with_legacy_status` serializes with on this thread, instead of the configured one.
+    static STATUS_FORMAT: Cell<Option<bool>> = Cell::new(None);
+}
+
+/// Makes every `Status` serialize as the old integers (0 / -1) instead of strings,
+/// for p2p nodes that weren't updated yet. It's the node's configuration, set once at startup.
+pub fn set_legacy_status(legacy: bool) { LEGACY_STATUS.store(legacy, Ordering::SeqCst) }
+
+/// Runs `f` with the statuses it serializes in the given format, whatever `set_legacy_status` configured.
+/// Only the current thread is affected.
+pub fn with_legacy_status<T, F: FnOnce() -> T>(legacy: bool, f: F) -> T {
+    let previous = STATUS_FORMAT.with(|format| format.replace(Some(legacy)));
+    let res = f();
+    STATUS_FORMAT.with(|format| format.set(previous));
+    res
+}
+
+fn legacy_status() -> bool {
+    STATUS_FORMAT.with(Cell::get).unwrap_or_else(|| LEGACY_STATUS.load(Ordering::SeqCst))
+}
+
+/// The outcome of a single write/remove, serialized as a string (`"ok"`, `"error"`, ...),
+/// or as the old integers in the legacy format, see `set_legacy_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Status {
-    Failed = -1,
-    Passed = 0,
+    Ok,
+    Error,
+    /// The key didn't exist, a remove of it is still a success.
+    NotFound,
+    /// The key already exists with a different value.
+    Duplicate,
+    /// The delta isn't consecutive to the ones already stored.
+    Gap,
+    /// The contract of the delta has no bytecode yet, the p2p node should fetch the contract first.
+    MissingBytecode,
+    /// The worker doesn't host the contract, see [`hosting`](../../db/hosting/index.html). Nothing was stored.
+    NotHosted,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Error => "error",
+            Status::NotFound => "not_found",
+            Status::Duplicate => "duplicate",
+            Status::Gap => "gap",
+            Status::MissingBytecode => "missing_bytecode",
+            Status::NotHosted => "not_hosted",
+        }
+    }
+
+    /// The integer the legacy format used: 0 if the DB ended up as requested, -1 otherwise.
+    pub fn legacy_code(self) -> i8 {
+        match self {
+            Status::Ok | Status::NotFound => 0,
+            Status::Error | Status::Duplicate | Status::Gap | Status::MissingBytecode | Status::NotHosted => -1,
+        }
+    }
+
+    pub fn is_failure(self) -> bool { self.legacy_code() != 0 }
+}
+
+impl Serialize for Status {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if legacy_status() {
+            serializer.serialize_i8(self.legacy_code())
+        } else {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Status {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StatusVisitor;
+
+        impl<'de> de::Visitor<'de> for StatusVisitor {
+            type Value = Status;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("a status string or a legacy status integer") }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Status, E> {
+                match v {
+                    "ok" => Ok(Status::Ok),
+                    "error" => Ok(Status::Error),
+                    "not_found" => Ok(Status::NotFound),
+                    "duplicate" => Ok(Status::Duplicate),
+                    "gap" => Ok(Status::Gap),
+                    "missing_bytecode" => Ok(Status::MissingBytecode),
+                    "not_hosted" => Ok(Status::NotHosted),
+                    _ => Err(E::unknown_variant(v, &["ok", "error", "not_found", "duplicate", "gap", "missing_bytecode", "not_hosted"])),
+                }
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Status, E> {
+                match v {
+                    0 => Ok(Status::Ok),
+                    -1 => Ok(Status::Error),
+                    _ => Err(E::invalid_value(de::Unexpected::Signed(v), &self)),
+                }
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Status, E> {
+                match v {
+                    0 => Ok(Status::Ok),
+                    _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(StatusVisitor)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IpcMessageRequest {
     pub id: String,
+    /// Lets the response be compressed, see [`compression`](../compression/index.html).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accept_encoding: Option<Encoding>,
+    /// The version of the wire format to answer in, `DEFAULT_PROTOCOL_VERSION` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
     #[serde(flatten)]
     pub request: IpcRequest
 }
@@ -23,6 +160,10 @@ pub struct IpcMessageRequest {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IpcMessageResponse {
     pub id: String,
+    /// Set when the request was refused for its id, `id` is then only what could be read of it,
+    /// see [`request_id`](../request_id/index.html).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub invalid_id: bool,
     #[serde(flatten)]
     pub response: IpcResponse
 }
@@ -31,10 +172,16 @@ pub struct IpcMessageResponse {
 #[serde(tag = "type")]
 pub enum IpcResponse {
     GetRegistrationParams { #[serde(flatten)] result: IpcResults },
+    GetRegistrationHistory { result: IpcResults },
     GetTip { result: IpcDelta },
     GetTips { result: IpcResults },
     GetAllTips { result: IpcResults },
-    GetAllAddrs { result: IpcResults },
+    GetAllAddrs {
+        result: IpcResults,
+        /// The addresses that have deltas but no bytecode, only if the request asked for them.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        orphans: Option<Vec<String>>,
+    },
     GetDelta { result: IpcResults },
     GetDeltas { result: IpcResults },
     GetContract { #[serde(flatten)] result: IpcResults },
@@ -49,21 +196,111 @@ pub enum IpcResponse {
     FailedTask { #[serde(flatten)] result: IpcResults },
     GetPTTRequest { #[serde(flatten)] result: IpcResults },
     PTTResponse { result: IpcResults },
-    Error { msg: String },
+    RecoverKeys { #[serde(flatten)] result: IpcResults },
+    GetHealth { #[serde(flatten)] result: IpcResults },
+    SetEpochParams { result: IpcResults },
+    SetHostedContracts { result: IpcResults },
+    GetContractStats { result: IpcResults },
+    VerifyTaskReceipt { #[serde(flatten)] result: IpcResults },
+    GetAuditDigest { result: IpcResults },
+    ReloadConfig { result: IpcResults },
+    Drain { #[serde(flatten)] result: IpcResults },
+    Resume { #[serde(flatten)] result: IpcResults },
+    GetDrainStatus { #[serde(flatten)] result: IpcResults },
+    RunMaintenance { result: IpcResults },
+    ReplayTask { #[serde(flatten)] result: IpcResults },
+    RebuildIndexes { result: IpcResults },
+    Error {
+        msg: String,
+        /// Whether sending the same request again may succeed, see `Retry`.
+        #[serde(default)]
+        retryable: bool,
+        /// How long to wait before retrying, if the core has an idea.
+        #[serde(rename = "retryAfterMs", default, skip_serializing_if = "Option::is_none")]
+        retry_after_ms: Option<u64>,
+        /// Set for errors the p2p node can act on.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<IpcErrorDetails>,
+    },
 }
 
 impl IpcResponse {
+    /// The response as a client of protocol `version` expects it,
+    /// before version 2 the fields of the task result sections are repeated flat,
+    /// since version 3 the signature is also given in its compact form,
+    /// since version 4 the tips give their raw and usable bounds.
+    pub fn for_protocol(mut self, version: u32) -> Self {
+        if version < 4 {
+            self.remove_tip_bounds();
+        }
+        if version >= 3 {
+            self.add_compact_signature();
+        }
+        if version >= 2 {
+            return self;
+        }
+        match &mut self {
+            IpcResponse::ComputeTask { result: IpcResults::ComputeResult { attested, supplemental, flat, .. } } => {
+                *flat = Some(IpcTaskFlatView {
+                    pre_code_hash: None,
+                    used_gas: attested.used_gas,
+                    ethereum_address: attested.ethereum_address.clone(),
+                    ethereum_payload: attested.ethereum_payload.clone(),
+                    epoch: Some(supplemental.epoch.clone()),
+                });
+            }
+            IpcResponse::DeploySecretContract { result: IpcResults::DeployResult { attested, supplemental, flat, .. } } => {
+                *flat = Some(IpcTaskFlatView {
+                    pre_code_hash: supplemental.pre_code_hash.clone(),
+                    used_gas: attested.used_gas,
+                    ethereum_address: attested.ethereum_address.clone(),
+                    ethereum_payload: attested.ethereum_payload.clone(),
+                    epoch: None,
+                });
+            }
+            _ => (),
+        }
+        self
+    }
+
+    fn remove_tip_bounds(&mut self) {
+        match self {
+            IpcResponse::GetTip { result } => result.bounds = None,
+            IpcResponse::GetTips { result: IpcResults::Tips(tips) } | IpcResponse::GetAllTips { result: IpcResults::Tips(tips) } => {
+                tips.iter_mut().for_each(|tip| tip.bounds = None)
+            }
+            _ => (),
+        }
+    }
+
+    fn add_compact_signature(&mut self) {
+        let (signature, sig_forms) = match self {
+            IpcResponse::ComputeTask { result }
+            | IpcResponse::DeploySecretContract { result }
+            | IpcResponse::FailedTask { result }
+            | IpcResponse::NewTaskEncryptionKey { result }
+            | IpcResponse::GetPTTRequest { result }
+            | IpcResponse::RecoverKeys { result }
+            | IpcResponse::GetAuditDigest { result } => match result {
+                IpcResults::ComputeResult { signature, sig_forms, .. }
+                | IpcResults::DeployResult { signature, sig_forms, .. }
+                | IpcResults::FailedTask { signature, sig_forms, .. }
+                | IpcResults::AuditDigest { signature, sig_forms, .. }
+                | IpcResults::DHKey { sig: signature, sig_forms, .. }
+                | IpcResults::Request { sig: signature, sig_forms, .. } => (signature, sig_forms),
+                _ => return,
+            },
+            _ => return,
+        };
+        sig_forms.add_compact(signature);
+    }
+
     pub fn display_without_bytecode(&self) -> String {
         match self {
             IpcResponse::DeploySecretContract {result: e} => {
                 match e {
-                    IpcResults::DeployResult {  used_gas,
-                                                delta,
-                                                ethereum_address,
-                                                ethereum_payload,
-                                                signature, .. } =>
-                        format!("IpcResponse {{ used_gas: {}, delta: {:?}, ethereum_address: {}, ethereum_payload: {}, signature: {} }}",
-                        used_gas, delta, ethereum_address, ethereum_payload, signature),
+                    IpcResults::DeployResult { attested, delta, signature, .. } =>
+                        format!("IpcResponse {{ attested: {:?}, delta: {:?}, signature: {} }}", attested, delta, signature),
                     _ => "".to_string(),
                 }
             },
@@ -90,79 +327,626 @@ impl IpcResponse {
 pub enum IpcResults {
     Errors(Vec<IpcStatusResult>),
     #[serde(rename = "result")]
-    Request { request: String, #[serde(rename = "workerSig")] sig: String },
+    Request {
+        request: String,
+        #[serde(rename = "workerSig")]
+        sig: String,
+        #[serde(flatten)]
+        epoch: IpcEpoch,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+        /// The contracts the worker keeps the keys of, only set in `explicit-list` hosting mode. They aren't signed
+        /// with the request, the enclave drops the keys of the other contracts from the response.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        addresses: Option<Vec<String>>,
+    },
     Addresses(Vec<String>),
     Delta(String),
     Deltas(Vec<IpcDelta>),
     #[serde(rename = "result")]
     GetContract {
         address: String,
+        /// The whole bytecode, or only the requested chunk of it.
         bytecode: Vec<u8>,
+        /// The length of the whole bytecode, only for a chunk.
+        #[serde(rename = "totalLen", default, skip_serializing_if = "Option::is_none")]
+        total_len: Option<u64>,
+        /// The keccak256 of the whole bytecode, only for a chunk.
+        #[serde(rename = "bytecodeHash", default, skip_serializing_if = "Option::is_none")]
+        bytecode_hash: Option<String>,
     },
     Status(Status),
     Tips(Vec<IpcDelta>),
     #[serde(rename = "result")]
     DeltasResult { status: Status, errors: Vec<IpcStatusResult> },
     #[serde(rename = "result")]
-    DHKey { #[serde(rename = "workerEncryptionKey")] dh_key: String, #[serde(rename = "workerSig")] sig: String },
+    ContractStats {
+        address: String,
+        #[serde(rename = "bytecodeSize")]
+        bytecode_size: u64,
+        /// The key of the last delta, if the contract has any.
+        tip: Option<u32>,
+        #[serde(rename = "chainHash")]
+        chain_hash: Option<String>,
+        /// The total length of the deltas, and the cap on it (`null` if unlimited).
+        #[serde(rename = "deltaBytes", default)]
+        delta_bytes: u64,
+        #[serde(rename = "maxDeltaBytes", default)]
+        max_delta_bytes: Option<u64>,
+    },
+    #[serde(rename = "result")]
+    Health {
+        #[serde(rename = "enclaveHealthy")]
+        enclave_healthy: bool,
+        /// The pings of the watchdog got slow, see `esgx::watchdog`.
+        #[serde(rename = "enclaveDegraded", default, skip_serializing_if = "std::ops::Not::not")]
+        enclave_degraded: bool,
+        /// False until the hot contracts were loaded after a restart, see `db::hot_set`.
+        #[serde(rename = "warmupComplete")]
+        warmup_complete: bool,
+        /// How many of the hosted contracts have their state keys back, `null` if no recovery was started.
+        recovery: Option<RecoveryProgress>,
+        /// The writes still waiting for the standby, `null` if the writes aren't mirrored.
+        mirror: Option<MirrorStatus>,
+        /// False once the handlers panicked too often, see `common_u::panics`.
+        #[serde(rename = "handlersHealthy")]
+        handlers_healthy: bool,
+        /// Set while the mutating requests are refused, see `common_u::drain`.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        draining: bool,
+        /// The size of the DB and the number of contracts next to their caps, see `db::capacity`.
+        #[serde(default)]
+        capacity: IpcCapacity,
+        /// The contracts in the address index and its lookups, see `db::address_index`.
+        #[serde(rename = "addressIndex", default)]
+        address_index: AddressIndexStats,
+    },
+    #[serde(rename = "result")]
+    DrainStatus {
+        draining: bool,
+        /// The mutating requests accepted before the drain and not answered yet, the core can be stopped once it's 0.
+        #[serde(rename = "inFlight")]
+        in_flight: usize,
+        /// How far the running `RebuildIndexes` got, only while there's one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rebuild: Option<RebuildProgress>,
+    },
+    #[serde(rename = "result")]
+    AuditDigest {
+        /// The events before `events` that the enclave doesn't keep anymore, and the head of the chain after them.
+        #[serde(rename = "baseCount")]
+        base_count: u64,
+        base: String,
+        /// Oldest first.
+        events: Vec<IpcAuditEvent>,
+        /// What the enclave signed, see `enigma_tools_m::audit::AuditDigest`.
+        count: u64,
+        head: String,
+        signature: String,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+    },
+    #[serde(rename = "result")]
+    DHKey {
+        #[serde(rename = "workerEncryptionKey")]
+        dh_key: String,
+        #[serde(rename = "workerSig")]
+        sig: String,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+    },
     #[serde(rename = "result")]
     RegistrationParams { #[serde(rename = "signingKey")] signing_key: String, report: String, signature: String },
+    /// Newest first.
+    RegistrationHistory(Vec<RegistrationRecord>),
+    /// The limits in effect after a `ReloadConfig`.
+    RateLimits(RateLimitConfig),
+    /// What a `RunMaintenance` removed.
+    Maintenance(MaintenanceReport),
+    /// What a `RebuildIndexes` fixed.
+    Rebuild(RebuildReport),
+    #[serde(rename = "result")]
+    ReplayReport {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        /// Whether every field matches.
+        matches: bool,
+        fields: Vec<IpcReplayField>,
+    },
+    #[serde(rename = "result")]
+    ReceiptVerdict { #[serde(rename = "taskId")] task_id: String, verdict: ReceiptVerdict },
     #[serde(rename = "result")]
     ComputeResult {
-        #[serde(rename = "usedGas")]
-        used_gas: u64,
-        output: String,
-        delta: IpcDelta,
-        #[serde(rename = "ethereumAddress")]
-        ethereum_address: String,
-        #[serde(rename = "ethereumPayload")]
-        ethereum_payload: String,
+        /// Everything the signature is over, nothing else in the result is.
+        attested: IpcComputeAttested,
+        /// 65 bytes `r || s || v`, `s` is always low and `v` is 27/28 (what `ecrecover` expects).
         signature: String,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+        /// The encrypted output, `attested.outputHash` is its hash.
+        output: String,
+        /// Only if the task asked for it, see `IpcTask::output_envelope`: the encoded `OutputEnvelope` of `output`,
+        /// `attested.envelopeHash` is its hash.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        envelope: Option<String>,
+        /// `{ address, key, data }` of the delta the task produced, its key is always the tip it was executed on + 1.
+        /// `null` if the task didn't change the state.
+        delta: Option<IpcDelta>,
+        supplemental: IpcTaskSupplemental,
+        /// Only if the task asked for it, see `IpcTask::debug_trace`.
+        #[serde(rename = "debugTrace", default, skip_serializing_if = "Option::is_none")]
+        debug_trace: Option<ExecutionTrace>,
+        /// Only for protocol version 1, see `IpcResponse::for_protocol`.
+        #[serde(flatten, default)]
+        flat: Option<IpcTaskFlatView>,
     },
     #[serde(rename = "result")]
     DeployResult {
-        #[serde(rename = "preCodeHash")]
-        pre_code_hash: String,
-        #[serde(rename = "usedGas")]
-        used_gas: u64,
+        /// Everything the signature is over, nothing else in the result is.
+        attested: IpcDeployAttested,
+        /// 65 bytes `r || s || v`, `s` is always low and `v` is 27/28 (what `ecrecover` expects).
+        signature: String,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+        /// The deployed bytecode, `attested.exeCodeHash` is its hash.
         output: String,
         delta: IpcDelta,
-        #[serde(rename = "ethereumAddress")]
-        ethereum_address: String,
-        #[serde(rename = "ethereumPayload")]
-        ethereum_payload: String,
-        signature: String,
+        supplemental: IpcTaskSupplemental,
+        /// Only if the task asked for it, see `IpcTask::debug_trace`.
+        #[serde(rename = "debugTrace", default, skip_serializing_if = "Option::is_none")]
+        debug_trace: Option<ExecutionTrace>,
+        /// Only for protocol version 1, see `IpcResponse::for_protocol`.
+        #[serde(flatten, default)]
+        flat: Option<IpcTaskFlatView>,
     },
     #[serde(rename = "result")]
     FailedTask {
         output: String,
         #[serde(rename = "usedGas")]
         used_gas: u64,
+        /// Same format as the `ComputeResult` signature.
         signature: String,
+        #[serde(flatten)]
+        sig_forms: IpcSigForms,
+        /// Set when the task was refused by the contract's access list, the failure is still signed, with the `Forbidden` status.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        forbidden: bool,
+        /// The host calls up to the failure, only if the task asked for them.
+        #[serde(rename = "debugTrace", default, skip_serializing_if = "Option::is_none")]
+        debug_trace: Option<ExecutionTrace>,
+        #[serde(flatten)]
+        epoch: IpcEpoch,
     },
 }
 
+/// The receipt the enclave signed for a compute task, field by field.
+/// `signature` is over `to_receipt().to_signable_bytes()`, so a client can check it without trusting the host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcComputeAttested {
+    pub exe_code_hash: String,
+    pub inputs_hash: String,
+    pub prev_delta_hash: String,
+    pub delta_hash: String,
+    pub output_hash: String,
+    pub gas_limit: u64,
+    pub used_gas: u64,
+    pub ethereum_payload: String,
+    pub ethereum_address: String,
+    /// Only if the task asked for an envelope, the hash of `ComputeResult::envelope`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelope_hash: Option<String>,
+}
+
+impl IpcComputeAttested {
+    pub fn to_receipt(&self) -> Result<ExecuteReceipt, Error> {
+        Ok(ExecuteReceipt {
+            exe_code_hash: Hash256::from_hex(&self.exe_code_hash)?,
+            inputs_hash: Hash256::from_hex(&self.inputs_hash)?,
+            prev_delta_hash: Hash256::from_hex(&self.prev_delta_hash)?,
+            delta_hash: Hash256::from_hex(&self.delta_hash)?,
+            output_hash: Hash256::from_hex(&self.output_hash)?,
+            gas_limit: self.gas_limit,
+            used_gas: self.used_gas,
+            ethereum_payload: self.ethereum_payload.from_hex()?,
+            ethereum_address: ethereum_address(&self.ethereum_address)?,
+            envelope_hash: match &self.envelope_hash {
+                Some(hash) => Some(Hash256::from_hex(hash)?),
+                None => None,
+            },
+        })
+    }
+}
+
+impl<'a> From<&'a ExecuteReceipt> for IpcComputeAttested {
+    fn from(receipt: &ExecuteReceipt) -> Self {
+        IpcComputeAttested {
+            exe_code_hash: receipt.exe_code_hash.to_hex(),
+            inputs_hash: receipt.inputs_hash.to_hex(),
+            prev_delta_hash: receipt.prev_delta_hash.to_hex(),
+            delta_hash: receipt.delta_hash.to_hex(),
+            output_hash: receipt.output_hash.to_hex(),
+            gas_limit: receipt.gas_limit,
+            used_gas: receipt.used_gas,
+            ethereum_payload: receipt.ethereum_payload.to_hex(),
+            ethereum_address: receipt.ethereum_address.to_hex(),
+            envelope_hash: receipt.envelope_hash.map(|hash| hash.to_hex()),
+        }
+    }
+}
+
+/// The receipt the enclave signed for a deployment, field by field, and the hash of the deployed bytecode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcDeployAttested {
+    pub inputs_hash: String,
+    pub exe_code_hash: String,
+    pub delta_hash: String,
+    pub gas_limit: u64,
+    pub used_gas: u64,
+    pub ethereum_payload: String,
+    pub ethereum_address: String,
+}
+
+impl<'a> From<&'a DeployReceipt> for IpcDeployAttested {
+    fn from(receipt: &DeployReceipt) -> Self {
+        IpcDeployAttested {
+            inputs_hash: receipt.inputs_hash.to_hex(),
+            exe_code_hash: receipt.exe_code_hash.to_hex(),
+            delta_hash: receipt.delta_hash.to_hex(),
+            gas_limit: receipt.gas_limit,
+            used_gas: receipt.used_gas,
+            ethereum_payload: receipt.ethereum_payload.to_hex(),
+            ethereum_address: receipt.ethereum_address.to_hex(),
+        }
+    }
+}
+
+impl IpcDeployAttested {
+    pub fn to_receipt(&self) -> Result<DeployReceipt, Error> {
+        Ok(DeployReceipt {
+            inputs_hash: Hash256::from_hex(&self.inputs_hash)?,
+            exe_code_hash: Hash256::from_hex(&self.exe_code_hash)?,
+            delta_hash: Hash256::from_hex(&self.delta_hash)?,
+            gas_limit: self.gas_limit,
+            used_gas: self.used_gas,
+            ethereum_payload: self.ethereum_payload.from_hex()?,
+            ethereum_address: ethereum_address(&self.ethereum_address)?,
+        })
+    }
+}
+
+fn ethereum_address(hex: &str) -> Result<[u8; 20], Error> {
+    let bytes: Vec<u8> = hex.from_hex()?;
+    if bytes.len() != 20 {
+        bail!("ethereumAddress must be 20 bytes, got {}", bytes.len());
+    }
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&bytes);
+    Ok(address)
+}
+
+/// What the host added to a task result, not covered by the signature.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IpcTaskSupplemental {
+    /// The epoch the task was checked against (a deployment isn't checked, it's the epoch known when it ran).
+    #[serde(flatten)]
+    pub epoch: IpcEpoch,
+    /// Only for a deployment. The receipt doesn't sign it, only the `inputsHash` over it (with the constructor,
+    /// its args and the user key), so it's only as good as the host that reports it.
+    #[serde(rename = "preCodeHash", default, skip_serializing_if = "Option::is_none")]
+    pub pre_code_hash: Option<String>,
+}
+
+/// The fields of a task result as protocol version 1 has them, flat next to the sections they're copied from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IpcTaskFlatView {
+    /// Only for a deployment.
+    #[serde(rename = "preCodeHash", default, skip_serializing_if = "Option::is_none")]
+    pub pre_code_hash: Option<String>,
+    #[serde(rename = "usedGas")]
+    pub used_gas: u64,
+    #[serde(rename = "ethereumAddress")]
+    pub ethereum_address: String,
+    #[serde(rename = "ethereumPayload")]
+    pub ethereum_payload: String,
+    /// Only for a compute task, a deployment never had it.
+    #[serde(flatten, default)]
+    pub epoch: Option<IpcEpoch>,
+}
+
+/// How the enclave signature next to it (`signature` or `workerSig`) is given, see `enigma_tools_m::signable::SigFormat`.
+/// The signature is always in the `ethereum` format. Since protocol version 3 it's also split in the 64 bytes `r || s`
+/// and the recovery id (0/1), for the libraries that take them apart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct IpcSigForms {
+    #[serde(rename = "sigFormat")]
+    pub sig_format: SigFormat,
+    #[serde(rename = "compactSig", skip_serializing_if = "Option::is_none")]
+    pub compact: Option<String>,
+    #[serde(rename = "recoveryId", skip_serializing_if = "Option::is_none")]
+    pub recovery_id: Option<u8>,
+}
+
+impl Default for IpcSigForms {
+    fn default() -> Self { IpcSigForms { sig_format: SigFormat::Ethereum, compact: None, recovery_id: None } }
+}
+
+impl IpcSigForms {
+    /// Adds the compact form of `signature`, the hex of a 65 bytes signature. Nothing is added if it isn't one.
+    fn add_compact(&mut self, signature: &str) {
+        let bytes: Vec<u8> = match signature.from_hex() {
+            Ok(bytes) => bytes,
+            Err(_) => return,
+        };
+        if bytes.len() != 65 {
+            return;
+        }
+        let mut sig = [0u8; 65];
+        sig.copy_from_slice(&bytes);
+        if let Ok((compact, recovery_id)) = to_compact(&sig) {
+            self.compact = Some(compact.to_hex());
+            self.recovery_id = Some(recovery_id);
+        }
+    }
+}
+
+/// The epoch the core believes is active, as set by the last `SetEpochParams`.
+/// Both fields are `null` (never omitted) until an epoch is known, so the p2p node can tell a core without epoch from an old core.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IpcEpoch {
+    #[serde(rename = "epochNonce", default)]
+    pub nonce: Option<u64>,
+    /// The hex of the commitment to the epoch's seed, `null` if it wasn't sent with the epoch params.
+    #[serde(rename = "seedCommitment", default)]
+    pub seed_commitment: Option<String>,
+}
+
+impl IpcEpoch {
+    /// The epoch known right now.
+    pub fn current() -> Self { EPOCH.lock_recover("Epoch").current().into() }
+}
+
+impl From<Option<EpochParams>> for IpcEpoch {
+    fn from(params: Option<EpochParams>) -> Self {
+        match params {
+            Some(params) => IpcEpoch { nonce: Some(params.nonce), seed_commitment: params.seed_commitment.map(|c| c.to_hex()) },
+            None => IpcEpoch::default(),
+        }
+    }
+}
+
+/// The machine readable part of an `IpcResponse::Error`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "code")]
+pub enum IpcErrorDetails {
+    /// The task isn't for the epoch this worker knows, the p2p node should refresh the epoch params.
+    StaleEpoch {
+        #[serde(rename = "taskNonce")]
+        task_nonce: Option<u64>,
+        #[serde(rename = "knownNonce")]
+        known_nonce: u64,
+        /// The seed commitment of the known epoch, `null` if the p2p node didn't send it.
+        #[serde(rename = "seedCommitment", default)]
+        seed_commitment: Option<String>,
+    },
+    /// The PTT request is pinned to a block before the oldest epoch this worker knows, `earliestBlock` is the first block
+    /// of that epoch, `null` if the p2p node didn't send any epoch yet.
+    UnknownEpoch {
+        #[serde(rename = "blockNumber")]
+        block_number: u64,
+        #[serde(rename = "earliestBlock")]
+        earliest_block: Option<u64>,
+    },
+    /// The worker is waiting for its state keys, the task should be retried later or sent to another worker.
+    Recovering { provisioned: usize, total: usize },
+    /// The worker refused the request because it's at capacity, `retryAfterMs` says when to try again.
+    Busy,
+    /// The task asked for a debug trace from a core that isn't in dev mode, it has to be sent again without it.
+    DebugTraceDisabled,
+    /// The task's contract isn't stored here, the p2p node should fetch it with its bytecode first.
+    ContractNotFound { address: String },
+    /// The local deltas of the task's contract don't end at the task's `expectedTip`, the p2p node should sync them first.
+    StateBehind {
+        address: String,
+        /// `null` if the contract has no deltas here.
+        #[serde(rename = "localTip")]
+        local_tip: Option<IpcTipRef>,
+        #[serde(rename = "expectedKey")]
+        expected_key: u32,
+    },
+    /// The deltas of the task's contract have gaps, the p2p node should sync the missing ones first.
+    DeltaGap {
+        address: String,
+        /// `null` if delta 0 is missing.
+        #[serde(rename = "usableTip")]
+        usable_tip: Option<u32>,
+        #[serde(rename = "rawTip")]
+        raw_tip: u32,
+    },
+    /// The worker is draining for an upgrade, the request should be sent to another worker or retried once it's resumed.
+    Draining,
+    /// `RebuildIndexes` was sent before the core was drained, or while the requests accepted before are still in flight.
+    NotDrained {
+        draining: bool,
+        #[serde(rename = "inFlight")]
+        in_flight: usize,
+    },
+    /// Storing the data would take the worker over one of its caps, the request should be sent to another worker.
+    CapacityExceeded {
+        /// `maxDbBytes`, `maxContractDeltaBytes` or `maxContracts`.
+        cap: String,
+        usage: u64,
+        limit: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+    },
+    /// The handler panicked, `msg` says where. It's a bug of the core, sending the same request again will likely fail again.
+    Internal,
+}
+
+impl IpcErrorDetails {
+    pub fn from_error(e: &Error) -> Option<Self> {
+        if let Some(e) = e.downcast_ref::<StaleEpochErr>() {
+            Some(IpcErrorDetails::StaleEpoch {
+                task_nonce: e.task_nonce,
+                known_nonce: e.known_nonce,
+                seed_commitment: e.seed_commitment.map(|c| c.to_hex()),
+            })
+        } else if let Some(e) = e.downcast_ref::<UnknownEpochErr>() {
+            Some(IpcErrorDetails::UnknownEpoch { block_number: e.block_number, earliest_block: e.earliest_block })
+        } else if let Some(e) = e.downcast_ref::<RecoveringErr>() {
+            Some(IpcErrorDetails::Recovering { provisioned: e.provisioned, total: e.total })
+        } else if e.downcast_ref::<BusyErr>().is_some() {
+            Some(IpcErrorDetails::Busy)
+        } else if e.downcast_ref::<DrainingErr>().is_some() {
+            Some(IpcErrorDetails::Draining)
+        } else if let Some(e) = e.downcast_ref::<NotDrainedErr>() {
+            Some(IpcErrorDetails::NotDrained { draining: e.draining, in_flight: e.in_flight })
+        } else if e.downcast_ref::<DebugTraceDisabledErr>().is_some() {
+            Some(IpcErrorDetails::DebugTraceDisabled)
+        } else if let Some(e) = e.downcast_ref::<ContractNotFoundErr>() {
+            Some(IpcErrorDetails::ContractNotFound { address: e.address.clone() })
+        } else if let Some(e) = e.downcast_ref::<StateBehindErr>() {
+            Some(IpcErrorDetails::StateBehind {
+                address: e.address.clone(),
+                local_tip: e.local_tip.map(|(key, hash)| IpcTipRef { key, hash: hash.to_hex() }),
+                expected_key: e.expected_key,
+            })
+        } else if let Some(e) = e.downcast_ref::<DeltaGapErr>() {
+            Some(IpcErrorDetails::DeltaGap { address: e.address.clone(), usable_tip: e.usable_tip, raw_tip: e.raw_tip })
+        } else if let Some(e) = e.downcast_ref::<CapacityExceededErr>() {
+            Some(IpcErrorDetails::CapacityExceeded { cap: e.cap.to_string(), usage: e.usage, limit: e.limit, address: e.address.clone() })
+        } else if e.downcast_ref::<InternalErr>().is_some() {
+            Some(IpcErrorDetails::Internal)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum IpcRequest {
     GetRegistrationParams,
+    /// The last `limit` registrations (all the retained ones if not given), newest first.
+    GetRegistrationHistory { #[serde(default)] limit: Option<usize> },
     GetTip { input: String },
     GetTips { input: Vec<String> },
     GetAllTips,
-    GetAllAddrs,
+    /// With `flagOrphans` the response also lists the addresses that have deltas but no bytecode.
+    GetAllAddrs { #[serde(rename = "flagOrphans", default, skip_serializing_if = "std::ops::Not::not")] flag_orphans: bool },
     GetDelta { input: IpcDelta },
     GetDeltas { input: Vec<IpcDeltasRange> },
-    GetContract { input: String },
+    /// With `offset` and/or `maxBytes` only that chunk of the bytecode is returned, for contracts too big for one message.
+    GetContract {
+        input: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+        #[serde(rename = "maxBytes", default, skip_serializing_if = "Option::is_none")]
+        max_bytes: Option<u64>,
+    },
     UpdateNewContract { address: String, bytecode: Vec<u8> },
     UpdateNewContractOnDeployment {address: String, bytecode: String, delta: IpcDelta},
     RemoveContract { address: String },
-    UpdateDeltas { deltas: Vec<IpcDelta> },
+    /// The deltas of a contract without bytecode are refused with `missing_bytecode`,
+    /// unless `allowOrphan` is set or the core was started with `--orphan-deltas allow`.
+    UpdateDeltas {
+        deltas: Vec<IpcDelta>,
+        #[serde(rename = "allowOrphan", default, skip_serializing_if = "std::ops::Not::not")]
+        allow_orphan: bool,
+    },
     RemoveDeltas { input: Vec<IpcDeltasRange> },
     NewTaskEncryptionKey { #[serde(rename = "userPubKey")] user_pubkey: String },
     DeploySecretContract { input: IpcTask},
     ComputeTask { input: IpcTask },
-    GetPTTRequest,
+    /// With `blockNumber` the request is for the epoch that was active at that block, otherwise for the latest one.
+    GetPTTRequest {
+        #[serde(rename = "blockNumber", default, skip_serializing_if = "Option::is_none")]
+        block_number: Option<u64>,
+    },
     PTTResponse {  input: PrincipalResponse },
+    /// Like `GetPTTRequest`, but also waits for the keys of `addresses` (all the hosted contracts if not given).
+    RecoverKeys { #[serde(default)] addresses: Option<Vec<String>> },
+    GetHealth,
+    /// `seedCommitment` is the hex of the commitment the principal published for the epoch, it's returned with the responses.
+    SetEpochParams {
+        nonce: u64,
+        #[serde(rename = "firstBlock")]
+        first_block: u64,
+        #[serde(rename = "seedCommitment", default, skip_serializing_if = "Option::is_none")]
+        seed_commitment: Option<String>,
+    },
+    /// Replaces the contracts hosted in `explicit-list` mode, they're kept across restarts.
+    SetHostedContracts { addresses: Vec<String> },
+    GetContractStats { input: String },
+    /// Checks a compute receipt against the stored state, without executing anything.
+    VerifyTaskReceipt { #[serde(flatten)] receipt: IpcTaskReceipt },
+    /// The log of the keys the enclave derived, signed by the enclave.
+    GetAuditDigest,
+    /// Reads the rate limits again from the file given with `--rate-limits`, it's never limited itself.
+    ReloadConfig,
+    /// Refuses the mutating requests from now on and returns how many are still in flight, for a rolling upgrade.
+    Drain,
+    /// Accepts the mutating requests again after a `Drain`.
+    Resume,
+    /// Whether the core is draining and how many of the mutating requests are still in flight.
+    GetDrainStatus,
+    /// Runs a DB maintenance pass right away instead of waiting for the schedule, see `db::maintenance`.
+    RunMaintenance,
+    /// Executes a journaled task again on a copy of the state it ran on, and compares the result with its receipt.
+    /// The task is decrypted with the IO key the enclave sealed for it when it ran, which is journaled with it.
+    ReplayTask { #[serde(rename = "taskId")] task_id: String },
+    /// Regenerates the derived structures of `scopes` (all of them if not given) from the raw data, see `db::rebuild`.
+    /// Only run once the core is drained with nothing in flight.
+    RebuildIndexes { #[serde(default, skip_serializing_if = "Option::is_none")] scopes: Option<Vec<RebuildScope>> },
+    /// Panics in the handler, for testing that a panic doesn't take the listener down.
+    #[cfg(test)]
+    TestPanic { message: String },
+}
+
+impl IpcRequest {
+    /// The `type` tag of the request, as it appears on the wire.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IpcRequest::GetRegistrationParams => "GetRegistrationParams",
+            IpcRequest::GetRegistrationHistory { .. } => "GetRegistrationHistory",
+            IpcRequest::GetTip { .. } => "GetTip",
+            IpcRequest::GetTips { .. } => "GetTips",
+            IpcRequest::GetAllTips => "GetAllTips",
+            IpcRequest::GetAllAddrs { .. } => "GetAllAddrs",
+            IpcRequest::GetDelta { .. } => "GetDelta",
+            IpcRequest::GetDeltas { .. } => "GetDeltas",
+            IpcRequest::GetContract { .. } => "GetContract",
+            IpcRequest::UpdateNewContract { .. } => "UpdateNewContract",
+            IpcRequest::UpdateNewContractOnDeployment { .. } => "UpdateNewContractOnDeployment",
+            IpcRequest::RemoveContract { .. } => "RemoveContract",
+            IpcRequest::UpdateDeltas { .. } => "UpdateDeltas",
+            IpcRequest::RemoveDeltas { .. } => "RemoveDeltas",
+            IpcRequest::NewTaskEncryptionKey { .. } => "NewTaskEncryptionKey",
+            IpcRequest::DeploySecretContract { .. } => "DeploySecretContract",
+            IpcRequest::ComputeTask { .. } => "ComputeTask",
+            IpcRequest::GetPTTRequest { .. } => "GetPTTRequest",
+            IpcRequest::PTTResponse { .. } => "PTTResponse",
+            IpcRequest::RecoverKeys { .. } => "RecoverKeys",
+            IpcRequest::GetHealth => "GetHealth",
+            IpcRequest::SetEpochParams { .. } => "SetEpochParams",
+            IpcRequest::SetHostedContracts { .. } => "SetHostedContracts",
+            IpcRequest::GetContractStats { .. } => "GetContractStats",
+            IpcRequest::VerifyTaskReceipt { .. } => "VerifyTaskReceipt",
+            IpcRequest::GetAuditDigest => "GetAuditDigest",
+            IpcRequest::ReloadConfig => "ReloadConfig",
+            IpcRequest::Drain => "Drain",
+            IpcRequest::Resume => "Resume",
+            IpcRequest::GetDrainStatus => "GetDrainStatus",
+            IpcRequest::RunMaintenance => "RunMaintenance",
+            IpcRequest::ReplayTask { .. } => "ReplayTask",
+            IpcRequest::RebuildIndexes { .. } => "RebuildIndexes",
+            #[cfg(test)]
+            IpcRequest::TestPanic { .. } => "TestPanic",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -180,6 +964,123 @@ pub struct IpcTask {
     pub gas_limit: u64,
     #[serde(rename = "contractAddress")]
     pub address: String,
+    /// The block the task was submitted in, checked against the epoch set with `SetEpochParams`.
+    #[serde(rename = "blockNumber", default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    /// The nonce of the epoch the task was assigned in.
+    #[serde(rename = "epochNonce", default, skip_serializing_if = "Option::is_none")]
+    pub epoch_nonce: Option<u64>,
+    /// Returns the host calls of the task with its result, for debugging a contract. Refused unless the core runs in dev mode.
+    #[serde(rename = "debugTrace", default, skip_serializing_if = "std::ops::Not::not")]
+    pub debug_trace: bool,
+    /// The last delta of the contract the task was assigned against, the task is refused with `StateBehind` if it isn't the local one.
+    #[serde(rename = "expectedTip", alias = "expected_tip", default, skip_serializing_if = "Option::is_none")]
+    pub expected_tip: Option<IpcTipRef>,
+    /// The id the task has on the network. A compute task sent with one is journaled, so it can be replayed with `ReplayTask`.
+    #[serde(rename = "taskId", alias = "taskID", default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    /// Also seals the encrypted output in an envelope addressed to `userDHKey`, whose hash the receipt signs.
+    #[serde(rename = "outputEnvelope", default, skip_serializing_if = "std::ops::Not::not")]
+    pub output_envelope: bool,
+}
+
+/// The usage of the worker next to its caps, a `null` cap is unlimited. The usage is the one after the last write.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcCapacity {
+    pub db_bytes: u64,
+    pub max_db_bytes: Option<u64>,
+    pub contracts: u64,
+    pub max_contracts: Option<u64>,
+    pub max_contract_delta_bytes: Option<u64>,
+}
+
+/// A delta of a contract by its key and the chain hash of the deltas up to it, see `db::chain_hash`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IpcTipRef {
+    pub key: u32,
+    pub hash: String,
+}
+
+/// The receipt of a compute task as some worker produced it, everything `ExecuteReceipt` signs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcTaskReceipt {
+    pub address: String,
+    /// Only echoed back with the verdict.
+    pub task_id: String,
+    pub inputs_hash: String,
+    /// Defaults to the hash of the stored bytecode of `address`.
+    #[serde(default)]
+    pub exe_code_hash: Option<String>,
+    /// The key of the delta the task produced, the stored delta is compared against `deltaHash`.
+    #[serde(default)]
+    pub delta_key: Option<u32>,
+    /// Defaults to the hash of the stored delta before `deltaKey`.
+    #[serde(default)]
+    pub prev_delta_hash: Option<String>,
+    pub delta_hash: String,
+    pub output_hash: String,
+    pub gas_limit: u64,
+    pub used_gas: u64,
+    #[serde(default)]
+    pub ethereum_payload: String,
+    #[serde(default)]
+    pub ethereum_address: Option<String>,
+    /// Only for a task that asked for an envelope.
+    #[serde(default)]
+    pub envelope_hash: Option<String>,
+    pub signature: String,
+    /// The worker that signed the receipt, defaults to the signing address of the last registration of this node.
+    #[serde(default)]
+    pub worker_address: Option<String>,
+}
+
+/// A field of the receipt compared by `ReplayTask`. The hashes and the gas are returned both ways,
+/// `output` and `delta` only say whether they match, never what the enclave decrypted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IpcReplayField {
+    pub field: String,
+    pub matches: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replayed: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReceiptVerdict {
+    Valid,
+    /// The receipt wasn't signed by the worker, or something in it was changed.
+    BadSignature,
+    /// A delta the receipt refers to isn't stored here.
+    UnknownDelta,
+    /// The stored delta isn't the one the receipt signs.
+    DeltaMismatch,
+}
+
+/// An `enigma_tools_m::audit::AuditEvent` with its hashes in hex.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpcAuditEvent {
+    pub counter: u64,
+    #[serde(rename = "type")]
+    pub kind: AuditEventKind,
+    pub subject: String,
+    pub timestamp: u64,
+    pub chain: String,
+}
+
+impl From<AuditEvent> for IpcAuditEvent {
+    fn from(event: AuditEvent) -> Self {
+        IpcAuditEvent {
+            counter: event.counter,
+            kind: event.kind,
+            subject: event.subject.to_hex(),
+            timestamp: event.timestamp,
+            chain: event.chain.to_hex(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -198,6 +1099,28 @@ pub struct IpcDelta {
     pub key: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<Vec<u8>>,
+    /// The chain hash of the contract's deltas up to this one, only set on tips.
+    #[serde(rename = "chainHash", default, skip_serializing_if = "Option::is_none")]
+    pub chain_hash: Option<String>,
+    /// Only set on tips, since protocol 4.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<IpcTipBounds>,
+}
+
+/// The two tips of a contract, see `db::gaps`. The `key` of a tip is the raw one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct IpcTipBounds {
+    /// The largest delta key stored.
+    #[serde(rename = "rawTip")]
+    pub raw_tip: u32,
+    /// The largest key such that the deltas up to it are all stored, `null` if delta 0 is missing.
+    /// A task can only be executed once it's the raw tip.
+    #[serde(rename = "usableTip")]
+    pub usable_tip: Option<u32>,
+}
+
+impl From<DeltaTips> for IpcTipBounds {
+    fn from(tips: DeltaTips) -> Self { IpcTipBounds { raw_tip: tips.raw, usable_tip: tips.usable } }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -226,12 +1149,12 @@ impl std::ops::Deref for Addresses {
 
 impl IpcMessageResponse {
     pub fn from_response(response: IpcResponse, id: String) -> Self {
-        Self { id, response }
+        Self { id, invalid_id: false, response }
     }
 }
 impl IpcMessageRequest {
     pub fn from_request(request: IpcRequest, id: String) -> Self {
-        Self { id, request }
+        Self { id, accept_encoding: None, protocol_version: None, request }
     }
 }
 
@@ -240,7 +1163,7 @@ impl IpcMessageRequest {
 impl IpcDelta {
     pub fn from_delta_key(k: DeltaKey, v: &[u8]) -> Result<Self, Error> {
         if let Stype::Delta(indx) = k.key_type {
-            Ok( IpcDelta { contract_address: Some(k.contract_address.to_hex()), key: indx, data: Some(v.to_vec()) } )
+            Ok( IpcDelta { contract_address: Some(k.contract_address.to_hex()), key: indx, data: Some(v.to_vec()), chain_hash: None, bounds: None } )
         } else {
             bail!("This isn't a delta")
         }
@@ -252,15 +1175,7 @@ impl From<Delta> for IpcDelta {
         let data = if delta.value.len() == 0 { None } else { Some ( delta.value ) };
         let key = delta.key.key_type.unwrap_delta();
 
-        IpcDelta { contract_address: None, key, data }
-    }
-}
-
-impl From<Message> for IpcMessageRequest {
-    fn from(msg: Message) -> Self {
-        let msg_str = msg.as_str().unwrap();
-        let req: Self = serde_json::from_str(msg_str).expect(msg_str);
-        req
+        IpcDelta { contract_address: Some(delta.key.contract_address.to_hex()), key, data, chain_hash: None, bounds: None }
     }
 }
 
@@ -275,14 +1190,172 @@ pub(crate) trait UnwrapError<T> {
     fn unwrap_or_error(self) -> T;
 }
 
-impl<E: std::fmt::Display> UnwrapError<IpcResponse> for Result<IpcResponse, E> {
+impl UnwrapError<IpcResponse> for Result<IpcResponse, Error> {
     fn unwrap_or_error(self) -> IpcResponse {
         match self {
             Ok(m) => m,
             Err(e) => {
                 error!("Unwrapped p2p Message failed: {}", e);
-                IpcResponse::Error {msg: format!("{}", e)}
+                let retry = Retry::of(&e);
+                IpcResponse::Error {
+                    msg: format!("{}", e),
+                    retryable: retry.is_retryable(),
+                    retry_after_ms: retry.retry_after_ms(),
+                    details: IpcErrorDetails::from_error(&e),
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use enigma_crypto::KeyPair;
+    use enigma_tools_m::signable::{from_compact, to_ethereum};
+    use serde_json::json;
+
+    const ALL: [(Status, &str, i8); 7] = [
+        (Status::Ok, "ok", 0),
+        (Status::Error, "error", -1),
+        (Status::NotFound, "not_found", 0),
+        (Status::Duplicate, "duplicate", -1),
+        (Status::Gap, "gap", -1),
+        (Status::MissingBytecode, "missing_bytecode", -1),
+        (Status::NotHosted, "not_hosted", -1),
+    ];
+
+    #[test]
+    fn test_status_fixtures() {
+        for &(status, name, _) in ALL.iter() {
+            let result = serde_json::to_value(&IpcResults::Status(status)).unwrap();
+            assert_eq!(result, json!({ "status": name }));
+            assert_eq!(serde_json::from_value::<Status>(json!(name)).unwrap(), status);
+        }
+        let deltas = IpcResults::DeltasResult { status: Status::Gap, errors: vec![IpcStatusResult { address: "aa".to_string(), key: Some(2), status: Status::Duplicate }] };
+        assert_eq!(serde_json::to_value(&deltas).unwrap(), json!({ "result": { "status": "gap", "errors": [{ "address": "aa", "key": 2, "status": "duplicate" }] } }));
+
+        for &(status, _, code) in ALL.iter() {
+            let result = with_legacy_status(true, || serde_json::to_value(&IpcResults::Status(status)).unwrap());
+            assert_eq!(result, json!({ "status": code }));
+        }
+        let legacy_deltas = with_legacy_status(true, || serde_json::to_value(&deltas).unwrap());
+        assert_eq!(legacy_deltas, json!({ "result": { "status": -1, "errors": [{ "address": "aa", "key": 2, "status": -1 }] } }));
+
+        // The legacy integers are still accepted when reading.
+        assert_eq!(serde_json::from_value::<Status>(json!(0)).unwrap(), Status::Ok);
+        assert_eq!(serde_json::from_value::<Status>(json!(-1)).unwrap(), Status::Error);
+        assert!(serde_json::from_value::<Status>(json!(1)).is_err());
+        assert!(serde_json::from_value::<Status>(json!("passed")).is_err());
+    }
+
+    #[test]
+    fn test_stale_epoch_details() {
+        let err: Result<IpcResponse, Error> = Err(StaleEpochErr { task_nonce: Some(3), known_nonce: 4, seed_commitment: None, block_number: None }.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["type"], "Error");
+        assert_eq!(response["details"], json!({ "code": "StaleEpoch", "taskNonce": 3, "knownNonce": 4, "seedCommitment": null }));
+        let commitment = [0xabu8; 32];
+        let err: Result<IpcResponse, Error> = Err(StaleEpochErr { task_nonce: None, known_nonce: 4, seed_commitment: Some(commitment.into()), block_number: Some(9) }.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["details"]["seedCommitment"], commitment.to_hex());
+
+        let err: Result<IpcResponse, Error> = Err(RecoveringErr { provisioned: 1, total: 2 }.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["details"], json!({ "code": "Recovering", "provisioned": 1, "total": 2 }));
+
+        let err: Result<IpcResponse, Error> = Err(UnknownEpochErr { block_number: 50, earliest_block: Some(100) }.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["details"], json!({ "code": "UnknownEpoch", "blockNumber": 50, "earliestBlock": 100 }));
+        assert_eq!(response["retryable"], false);
+
+        let err: Result<IpcResponse, Error> = Err(failure::err_msg("other"));
+        assert!(serde_json::to_value(&err.unwrap_or_error()).unwrap().get("details").is_none());
+    }
+
+    #[test]
+    fn test_epoch_fields() {
+        let request = IpcResults::Request {
+            request: "aa".to_string(),
+            sig: "bb".to_string(),
+            epoch: IpcEpoch::default(),
+            sig_forms: IpcSigForms::default(),
+            addresses: None,
+        };
+        assert_eq!(serde_json::to_value(&request).unwrap(),
+                   json!({ "result": { "request": "aa", "workerSig": "bb", "epochNonce": null, "seedCommitment": null, "sigFormat": "ethereum" } }));
+
+        let params = EpochParams { nonce: 7, first_block: 100, seed_commitment: Some([1u8; 32].into()) };
+        let epoch = IpcEpoch::from(Some(params));
+        assert_eq!(epoch, IpcEpoch { nonce: Some(7), seed_commitment: Some("01".repeat(32)) });
+        let response = IpcResponse::GetPTTRequest {
+            result: IpcResults::Request { request: "aa".to_string(), sig: "bb".to_string(), epoch, sig_forms: IpcSigForms::default(), addresses: None },
+        };
+        let response = serde_json::to_value(&response).unwrap();
+        assert_eq!(response["result"]["epochNonce"], 7);
+        assert_eq!(response["result"]["seedCommitment"], "01".repeat(32));
+    }
+
+    #[test]
+    fn test_sig_forms() {
+        let keys = KeyPair::from_slice(&[3u8; 32]).unwrap();
+        let msg = b"Enigma";
+        let sig = keys.sign(msg).unwrap();
+        let dh_key = |sig: String| IpcResponse::NewTaskEncryptionKey {
+            result: IpcResults::DHKey { dh_key: "aa".to_string(), sig, sig_forms: IpcSigForms::default() },
+        };
+
+        let v2 = serde_json::to_value(dh_key(sig.to_hex()).for_protocol(2)).unwrap();
+        assert_eq!(v2["workerSig"], sig.to_hex());
+        assert_eq!(v2["sigFormat"], "ethereum");
+        assert!(v2.get("compactSig").is_none() && v2.get("recoveryId").is_none());
+
+        let v3 = serde_json::to_value(dh_key(sig.to_hex()).for_protocol(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(v3["workerSig"], v2["workerSig"]);
+        assert_eq!(v3["compactSig"], sig[..64].to_hex());
+        assert_eq!(v3["recoveryId"], u64::from(sig[64] - 27));
+        // Both forms recover the signer.
+        let mut compact = [0u8; 64];
+        compact.copy_from_slice(&v3["compactSig"].as_str().unwrap().from_hex().unwrap());
+        let rebuilt = to_ethereum(&from_compact(&compact, v3["recoveryId"].as_u64().unwrap() as u8).unwrap()).unwrap();
+        assert_eq!(KeyPair::recover_canonical(msg, rebuilt).unwrap()[..], keys.get_pubkey()[..]);
+
+        // Only a 65 bytes signature is split.
+        let v3 = serde_json::to_value(dh_key("bb".to_string()).for_protocol(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(v3["sigFormat"], "ethereum");
+        assert!(v3.get("compactSig").is_none());
+    }
+
+    #[test]
+    fn test_tip_bounds() {
+        let bounds = Some(IpcTipBounds::from(DeltaTips { raw: 5, usable: None }));
+        let tip = IpcDelta { contract_address: None, key: 5, data: Some(vec![1]), chain_hash: None, bounds };
+        let tips = || IpcResponse::GetAllTips { result: IpcResults::Tips(vec![tip.clone()]) };
+
+        let v4 = serde_json::to_value(tips().for_protocol(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(v4["result"]["tips"][0]["bounds"], json!({ "rawTip": 5, "usableTip": null }));
+        let v3 = serde_json::to_value(tips().for_protocol(3)).unwrap();
+        assert!(v3["result"]["tips"][0].get("bounds").is_none());
+        assert_eq!(v3["result"]["tips"][0]["key"], 5);
+    }
+
+    #[test]
+    fn test_retry_hints() {
+        let err: Result<IpcResponse, Error> = Err(BusyErr { retry_after_ms: 250 }.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["retryable"], true);
+        assert_eq!(response["retryAfterMs"], 250);
+        assert_eq!(response["details"], json!({ "code": "Busy" }));
+
+        let err: Result<IpcResponse, Error> = Err(failure::err_msg("bad input"));
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["retryable"], false);
+        assert!(response.get("retryAfterMs").is_none());
+
+        // Outside of dev mode a traced task fails the same way every time.
+        let err: Result<IpcResponse, Error> = Err(DebugTraceDisabledErr.into());
+        let response = serde_json::to_value(&err.unwrap_or_error()).unwrap();
+        assert_eq!(response["retryable"], false);
+        assert_eq!(response["details"], json!({ "code": "DebugTraceDisabled" }));
+    }
+}
@@ -0,0 +1,155 @@
+//! # Metrics HTTP listener.
+//! A minimal HTTP/1.0 server, on its own thread, that answers `GET /metrics` with the registry in the
+//! Prometheus text format. It never touches the ZMQ socket or the DB, so a slow scraper can't stall the IPC loop.
+//! The DB size gauges are refreshed by a `DbSizeSampler` on a thread of its own, the handlers don't pay for them.
+
+use crate::common_u::metrics::METRICS;
+use crate::db::{AddressIndex, DB};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the DB size gauges are refreshed by default.
+pub const DEFAULT_DB_SIZE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(MetricsServer { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> { self.listener.local_addr() }
+
+    /// Serves scrapes on a background thread until the process exits.
+    pub fn spawn(self) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new().name("metrics".to_string()).spawn(move || {
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = handle_connection(stream) {
+                            debug!("Failed answering a metrics scrape: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed accepting a metrics connection: {}", e),
+                }
+            }
+        })
+    }
+}
+
+/// Refreshes the DB size gauges (and the usage `GetHealth` reports) every `interval`. The contracts are counted
+/// by the address index as they're created and dropped, only the DB directory is read.
+pub struct DbSizeSampler {
+    location: PathBuf,
+    addresses: Arc<AddressIndex>,
+    interval: Duration,
+}
+
+impl DbSizeSampler {
+    pub fn new(db: &DB, interval: Duration) -> Self {
+        DbSizeSampler { location: db.location.clone(), addresses: db.address_index(), interval }
+    }
+
+    pub fn sample(&self) { METRICS.set_db_size(self.addresses.stats().addresses, DB::disk_size_at(&self.location)); }
+
+    /// Samples right away, then on a background thread until the process exits.
+    pub fn spawn(self) -> io::Result<JoinHandle<()>> {
+        self.sample();
+        thread::Builder::new().name("db-size".to_string()).spawn(move || loop {
+            thread::sleep(self.interval);
+            self.sample();
+        })
+    }
+}
+
+fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", METRICS.render()),
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    write!(stream, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, CONTENT_TYPE, body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{tests::create_test_db, CRUDInterface, DeltaKey, Stype};
+    use crate::networking::ipc_listener::handle_message;
+    use crate::networking::messages::{IpcMessageRequest, IpcRequest};
+    use serde_json;
+    use std::io::Read;
+    use tokio_zmq::Multipart;
+    use zmq::Message;
+
+    fn scrape(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn request(request: IpcRequest) -> Message {
+        let msg = IpcMessageRequest::from_request(request, "metrics".to_string());
+        Message::from(&serde_json::to_vec(&msg).unwrap())
+    }
+
+    #[test]
+    fn test_scrape_metrics() {
+        let (mut db, _dir) = create_test_db();
+        let mut multipart = Multipart::new();
+        multipart.push_back(request(IpcRequest::GetAllAddrs { flag_orphans: false }));
+        multipart.push_back(request(IpcRequest::GetTip { input: "00".repeat(32) }));
+        multipart.push_back(request(IpcRequest::RemoveContract { address: "11".repeat(32) }));
+        // The enclave isn't needed for any of the requests above.
+        handle_message(&mut db, multipart, "", 0, 0);
+
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        server.spawn().unwrap();
+
+        let response = scrape(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+        for family in &["enigma_ipc_requests_total", "enigma_ipc_request_duration_seconds", "enigma_enclave_call_duration_seconds",
+                        "enigma_errors_total", "enigma_task_gas_used_total", "enigma_db_contracts", "enigma_db_disk_bytes",
+                        "enigma_enclave_healthy"] {
+            assert!(response.contains(&format!("# TYPE {} ", family)), "missing family {}", family);
+        }
+        for kind in &["GetAllAddrs", "GetTip", "RemoveContract"] {
+            assert!(response.contains(&format!("enigma_ipc_requests_total{{type=\"{}\"}}", kind)));
+            assert!(response.contains(&format!("enigma_ipc_request_duration_seconds_bucket{{type=\"{}\",le=\"+Inf\"}}", kind)));
+        }
+        // The tip of an unknown contract is a DB error.
+        assert!(response.contains("enigma_errors_total{code=\"db_"));
+
+        let not_found = scrape(addr, "/");
+        assert!(not_found.starts_with("HTTP/1.0 404"));
+    }
+
+    #[test]
+    fn test_db_size_sampler() {
+        let (mut db, _dir) = create_test_db();
+        db.create(&DeltaKey::new([7u8; 32].into(), Stype::ByteCode), &b"code"[..]).unwrap();
+        DbSizeSampler::new(&db, DEFAULT_DB_SIZE_INTERVAL).sample();
+        let (contracts, disk_bytes) = METRICS.db_size();
+        assert_eq!(contracts, 1);
+        assert!(disk_bytes > 0);
+    }
+}
@@ -1,4 +1,13 @@
+#[cfg(feature = "client")]
+pub mod client;
+pub mod compression;
 pub mod ipc_listener;
+pub mod ipc_queue;
+pub mod maintenance;
 pub mod messages;
+pub mod metrics_server;
+pub mod request_id;
+pub mod wire_fixtures;
 
 pub use self::ipc_listener::IpcListener;
+pub use self::metrics_server::MetricsServer;
@@ -0,0 +1,178 @@
+//! # Request ids.
+//! The p2p node matches the responses to its requests by their `id`, so every response carries the id of its request.
+//! The id is read from the frame before the request itself is parsed, a request that doesn't parse is still answered
+//! under its id, even when the frame isn't valid JSON or UTF-8, as long as the id itself can be found.
+//!
+//! A valid id is 1 to `MAX_ID_LEN` bytes of printable UTF-8, it's echoed exactly as it came.
+//! A request with any other id isn't handled, it's answered with an error flagged `invalid_id: true`
+//! that echoes what could be read of the id, without its control characters and cut to `MAX_ID_LEN` bytes.
+
+use crate::common_u::errors::InvalidRequestIdErr;
+use crate::common_u::metrics::METRICS;
+use crate::networking::messages::{IpcMessageResponse, IpcResponse, UnwrapError};
+use serde_json::{self, Value};
+
+/// The longest id accepted, in bytes.
+pub const MAX_ID_LEN: usize = 128;
+
+/// The id of a request frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameId {
+    Valid(String),
+    Invalid {
+        /// What could be read of the id, made safe to echo, maybe empty.
+        echo: String,
+        reason: &'static str,
+    },
+}
+
+impl FrameId {
+    /// The id to put in the response.
+    pub fn echo(&self) -> &str {
+        match self {
+            FrameId::Valid(id) => id,
+            FrameId::Invalid { echo, .. } => echo,
+        }
+    }
+}
+
+// Only the id, the rest of the request is skipped without being checked.
+#[derive(Deserialize)]
+struct IdField {
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+/// Reads and validates the id of a request frame.
+pub fn frame_id(frame: &[u8]) -> FrameId {
+    match serde_json::from_slice::<IdField>(frame) {
+        Ok(IdField { id: Some(Value::String(id)) }) => validate(id),
+        Ok(IdField { id: Some(Value::Null) }) | Ok(IdField { id: None }) => invalid("", "the request has no id"),
+        Ok(IdField { id: Some(other) }) => invalid(&other.to_string(), "the id isn't a string"),
+        Err(_) => match scan_id(frame) {
+            Some(raw) => match serde_json::from_slice::<String>(raw) {
+                Ok(id) => validate(id),
+                Err(_) => invalid(&String::from_utf8_lossy(&raw[1..raw.len() - 1]), "the id isn't a valid UTF-8 string"),
+            },
+            None => invalid("", "the request has no id"),
+        },
+    }
+}
+
+/// The id of a request frame, or the response refusing the request for it.
+pub fn checked_id(frame: &[u8]) -> Result<String, IpcMessageResponse> {
+    match frame_id(frame) {
+        FrameId::Valid(id) => Ok(id),
+        FrameId::Invalid { echo, reason } => Err(invalid_id_response(echo, reason)),
+    }
+}
+
+/// The error response to a request with an invalid id.
+pub fn invalid_id_response(echo: String, reason: &'static str) -> IpcMessageResponse {
+    METRICS.record_error("invalid_id");
+    let response: Result<IpcResponse, failure::Error> = Err(InvalidRequestIdErr { reason }.into());
+    IpcMessageResponse { invalid_id: true, ..IpcMessageResponse::from_response(response.unwrap_or_error(), echo) }
+}
+
+fn validate(id: String) -> FrameId {
+    if id.is_empty() {
+        invalid(&id, "the id is empty")
+    } else if id.len() > MAX_ID_LEN {
+        invalid(&id, "the id is too long")
+    } else if id.chars().any(char::is_control) {
+        invalid(&id, "the id has non printable characters")
+    } else {
+        FrameId::Valid(id)
+    }
+}
+
+fn invalid(id: &str, reason: &'static str) -> FrameId {
+    let mut echo = String::new();
+    for c in id.chars().filter(|c| !c.is_control()) {
+        if echo.len() + c.len_utf8() > MAX_ID_LEN {
+            break;
+        }
+        echo.push(c);
+    }
+    FrameId::Invalid { echo, reason }
+}
+
+/// Finds the raw JSON string of the `id` in a frame that doesn't parse, quotes included.
+/// It's a best effort, an `"id"` inside a string value that's followed by a colon is taken for the key.
+fn scan_id(frame: &[u8]) -> Option<&[u8]> {
+    const KEY: &[u8] = b"\"id\"";
+    let skip_spaces = |mut i: usize| {
+        while i < frame.len() && frame[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    let mut from = 0;
+    while let Some(pos) = frame[from..].windows(KEY.len()).position(|window| window == KEY) {
+        let key_end = from + pos + KEY.len();
+        from = key_end;
+        let colon = skip_spaces(key_end);
+        if frame.get(colon) != Some(&b':') {
+            continue;
+        }
+        let start = skip_spaces(colon + 1);
+        if frame.get(start) != Some(&b'"') {
+            return None;
+        }
+        let mut i = start + 1;
+        while i < frame.len() {
+            match frame[i] {
+                b'\\' => i += 2,
+                b'"' => return Some(&frame[start..=i]),
+                _ => i += 1,
+            }
+        }
+        return None;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn invalid_echo(frame: &[u8]) -> String {
+        match frame_id(frame) {
+            FrameId::Invalid { echo, .. } => echo,
+            valid => panic!("{:?} was accepted", valid),
+        }
+    }
+
+    #[test]
+    fn test_frame_id() {
+        assert_eq!(frame_id(br#"{"id":"a-1","type":"GetAllTips"}"#), FrameId::Valid("a-1".to_string()));
+        assert_eq!(frame_id("{\"id\":\"\\u00e9\u{1F600} \\\"x\\\"\"}".as_bytes()), FrameId::Valid("é\u{1F600} \"x\"".to_string()));
+        let longest = "x".repeat(MAX_ID_LEN);
+        assert_eq!(frame_id(format!(r#"{{"id":"{}"}}"#, longest).as_bytes()), FrameId::Valid(longest.clone()));
+
+        // The id is read even if the request doesn't parse.
+        assert_eq!(frame_id(br#"{"type":"GetTip","id" : "b-2","input":"#), FrameId::Valid("b-2".to_string()));
+        assert_eq!(frame_id(b"{\"input\":\"\xff\",\"id\":\"c-3\"}"), FrameId::Valid("c-3".to_string()));
+        assert_eq!(frame_id(br#"{"input":"id","id":"d-4""#), FrameId::Valid("d-4".to_string()));
+
+        assert_eq!(invalid_echo(br#"{"type":"GetAllTips"}"#), "");
+        assert_eq!(invalid_echo(br#"{"id":null}"#), "");
+        assert_eq!(invalid_echo(br#"{"id":""}"#), "");
+        assert_eq!(invalid_echo(br#"{"id":12}"#), "12");
+        assert_eq!(invalid_echo(b"not json"), "");
+        assert_eq!(invalid_echo(br#"{"id":"a\u0000b\nc"}"#), "abc");
+        assert_eq!(invalid_echo(b"{\"id\":\"a\xffb\""), "a\u{FFFD}b");
+        // Cut on a character boundary.
+        let long = format!("{}{}", "x".repeat(MAX_ID_LEN - 1), "é");
+        assert_eq!(invalid_echo(format!(r#"{{"id":"{}"}}"#, long).as_bytes()), "x".repeat(MAX_ID_LEN - 1));
+    }
+
+    #[test]
+    fn test_invalid_id_response() {
+        let response = serde_json::to_value(&invalid_id_response("abc".to_string(), "the id is too long")).unwrap();
+        assert_eq!(response["id"], "abc");
+        assert_eq!(response["invalid_id"], true);
+        assert_eq!(response["type"], "Error");
+        assert_eq!(response["retryable"], false);
+    }
+}
@@ -0,0 +1,502 @@
+//! # Wire format fixtures.
+//! A sample of every request and response the core speaks, as it's serialized on the wire.
+//! At release time `cargo run --bin gen-wire-fixtures` writes them to `tests/wire_fixtures/<version>/`,
+//! and the `wire_compat` tests check against every release that was checked in that:
+//! * the requests of the release still parse,
+//! * every response of the release is still produced, with all its stable fields at the same place and of the same JSON type.
+//!
+//! The `id` of every message names the sample, so the samples of different releases can be matched.
+//! A field is stable unless its path (`<id><JSON pointer>`) is in `UNSTABLE_FIELDS`,
+//! the list is saved with the fixtures so every release keeps the promises it made.
+
+use std::fs;
+use std::path::Path;
+
+use failure::Error;
+use serde_json::{self, Value};
+
+use crate::common_u::errors::{Retry, ENCLAVE_BUSY_RETRY_MS, RECOVERING_RETRY_MS};
+use crate::common_u::rate_limit::{RateLimit, RateLimitConfig};
+use crate::common_u::recovery::RecoveryProgress;
+use crate::db::{AddressIndexStats, MaintenanceReport, MirrorStatus, RebuildProgress, RebuildReport, RebuildScope, RegistrationRecord};
+use super::compression::Encoding;
+use super::messages::*;
+use enigma_crypto::hash::Keccak256;
+use enigma_tools_m::audit::AuditEventKind;
+use enigma_tools_m::envelope::OutputEnvelope;
+use enigma_tools_m::signable::{DeployReceipt, ExecuteReceipt};
+use enigma_types::Hash256;
+use hex::{FromHex, ToHex};
+
+/// The fields the p2p node shouldn't rely on, they may change in any release.
+pub const UNSTABLE_FIELDS: &[&str] = &["GetHealth/result/recovery", "GetHealth/result/mirror"];
+
+pub const REQUESTS_FILE: &str = "requests.json";
+pub const RESPONSES_FILE: &str = "responses.json";
+
+/// The messages of one release, as they're stored in the fixture files.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fixtures {
+    pub version: String,
+    #[serde(default)]
+    pub unstable: Vec<String>,
+    pub messages: Vec<Value>,
+}
+
+const ADDRESS: &str = "4a6f2c1a8b3e5d7f9021436587a9cbed0f1e2d3c4b5a69788796a5b4c3d2e1f0";
+const OTHER_ADDRESS: &str = "0b1c2d3e4f5061728394a5b6c7d8e9fa0b1c2d3e4f5061728394a5b6c7d8e9fa";
+const HASH: &str = "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470";
+const PUBKEY: &str = "2ea8e4cefb78efd0725ed12b23b05079a0a433cc8a656f212accf58672fee44a20cfcaa50466237273e762e49ec912be61358d5e90bff56a53a0ed42abfe27e3";
+const ETH_ADDRESS: &str = "5ed8cee6b63b1c6afce3ad7c92f4fd7e1b8fad9f";
+const SIGNATURE: &str = "a8b5f2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f\
+                         10213243546576879809a0b1c2d3e4f5061728394a5b6c7d8e9f0a1b2c3d4e5f1b";
+
+fn delta(address: Option<&str>, key: u32) -> IpcDelta {
+    IpcDelta { contract_address: address.map(str::to_string), key, data: Some(vec![11, 2, 3, 5, 41, 44]), chain_hash: None, bounds: None }
+}
+
+// A tip with both bounds, `usable` is behind `raw` if the contract has gaps.
+fn tip(address: Option<&str>, raw: u32, usable: Option<u32>) -> IpcDelta {
+    IpcDelta { bounds: Some(IpcTipBounds { raw_tip: raw, usable_tip: usable }), ..delta(address, raw) }
+}
+
+fn get_tip() -> IpcResponse { IpcResponse::GetTip { result: IpcDelta { chain_hash: Some(HASH.to_string()), ..tip(None, 3, Some(3)) } } }
+
+fn get_tips() -> IpcResponse { IpcResponse::GetTips { result: IpcResults::Tips(vec![tip(Some(ADDRESS), 3, Some(3))]) } }
+
+fn get_all_tips() -> IpcResponse {
+    IpcResponse::GetAllTips { result: IpcResults::Tips(vec![tip(Some(ADDRESS), 3, Some(3)), tip(Some(OTHER_ADDRESS), 1, None)]) }
+}
+
+fn range() -> IpcDeltasRange { IpcDeltasRange { address: ADDRESS.to_string(), from: 1, to: 3 } }
+
+fn task(pre_code: Option<Vec<u8>>) -> IpcTask {
+    IpcTask {
+        pre_code,
+        encrypted_args: "e8ab5e8e6c8d2ea3b0ac9d5f7c4b1e2f0a9d8c7b6a5f4e3d".to_string(),
+        encrypted_fn: "de9bc270f4c5a2e1b0ac9d5f7c4b1e2f".to_string(),
+        user_dhkey: PUBKEY.to_string(),
+        gas_limit: 100_000,
+        address: ADDRESS.to_string(),
+        block_number: Some(1042),
+        epoch_nonce: Some(3),
+        debug_trace: false,
+        expected_tip: None,
+        task_id: None,
+        output_envelope: false,
+    }
+}
+
+fn deploy_result(epoch: &IpcEpoch) -> IpcResponse {
+    let receipt = DeployReceipt {
+        inputs_hash: [1u8; 32].into(),
+        exe_code_hash: Vec::<u8>::new().keccak256(),
+        delta_hash: [2u8; 32].into(),
+        gas_limit: 100_000,
+        used_gas: 2_400,
+        ethereum_payload: Vec::new(),
+        ethereum_address: [0u8; 20],
+    };
+    IpcResponse::DeploySecretContract {
+        result: IpcResults::DeployResult {
+            attested: IpcDeployAttested::from(&receipt),
+            signature: SIGNATURE.to_string(),
+            sig_forms: IpcSigForms::default(),
+            output: String::new(),
+            delta: delta(Some(ADDRESS), 0),
+            supplemental: IpcTaskSupplemental { epoch: epoch.clone(), pre_code_hash: Some(HASH.to_string()) },
+            debug_trace: None,
+            flat: None,
+        },
+    }
+}
+
+// A well formed envelope of `output`, it doesn't open.
+fn envelope(output: &[u8]) -> OutputEnvelope {
+    let mut recipient_pubkey = [0u8; 64];
+    recipient_pubkey.copy_from_slice(&PUBKEY.from_hex().unwrap());
+    OutputEnvelope { recipient_pubkey, ephemeral_pubkey: [7u8; 64], nonce: [8u8; 12], ciphertext: output.to_vec(), mac: [9u8; 16] }
+}
+
+fn compute_result(epoch: &IpcEpoch, with_envelope: bool) -> IpcResponse {
+    let output = "0000000000000000000000000000000000000000000000000000000000000001";
+    let envelope = if with_envelope { Some(envelope(&output.from_hex().unwrap())) } else { None };
+    let mut ethereum_address = [0u8; 20];
+    ethereum_address.copy_from_slice(&ETH_ADDRESS.from_hex().unwrap());
+    let receipt = ExecuteReceipt {
+        exe_code_hash: Hash256::from_hex(HASH).unwrap(),
+        inputs_hash: [1u8; 32].into(),
+        prev_delta_hash: [3u8; 32].into(),
+        delta_hash: [4u8; 32].into(),
+        output_hash: output.from_hex().unwrap().keccak256(),
+        gas_limit: 100_000,
+        used_gas: 1_200,
+        ethereum_payload: vec![0xa9, 0x05, 0x9c, 0xbb],
+        ethereum_address,
+        envelope_hash: envelope.as_ref().map(OutputEnvelope::hash),
+    };
+    IpcResponse::ComputeTask {
+        result: IpcResults::ComputeResult {
+            attested: (&receipt).into(),
+            signature: SIGNATURE.to_string(),
+            sig_forms: IpcSigForms::default(),
+            output: output.to_string(),
+            envelope: envelope.map(|envelope| envelope.to_bytes().to_hex()),
+            delta: Some(delta(Some(ADDRESS), 2)),
+            supplemental: IpcTaskSupplemental { epoch: epoch.clone(), pre_code_hash: None },
+            debug_trace: None,
+            flat: None,
+        },
+    }
+}
+
+fn request(id: &str, request: IpcRequest) -> IpcMessageRequest { IpcMessageRequest::from_request(request, id.to_string()) }
+
+fn response(id: &str, response: IpcResponse) -> IpcMessageResponse { IpcMessageResponse::from_response(response, id.to_string()) }
+
+/// One sample of every request.
+pub fn requests() -> Vec<IpcMessageRequest> {
+    vec![
+        request("GetRegistrationParams", IpcRequest::GetRegistrationParams),
+        request("GetRegistrationHistory", IpcRequest::GetRegistrationHistory { limit: Some(5) }),
+        request("GetTip", IpcRequest::GetTip { input: ADDRESS.to_string() }),
+        request("GetTips", IpcRequest::GetTips { input: vec![ADDRESS.to_string(), OTHER_ADDRESS.to_string()] }),
+        request("GetAllTips", IpcRequest::GetAllTips),
+        request("GetAllAddrs", IpcRequest::GetAllAddrs { flag_orphans: false }),
+        request("GetDelta", IpcRequest::GetDelta { input: IpcDelta { data: None, ..delta(Some(ADDRESS), 1) } }),
+        request("GetDeltas", IpcRequest::GetDeltas { input: vec![range()] }),
+        IpcMessageRequest { accept_encoding: Some(Encoding::Zstd), ..request("GetDeltas-zstd", IpcRequest::GetDeltas { input: vec![range()] }) },
+        request("GetContract", IpcRequest::GetContract { input: ADDRESS.to_string(), offset: None, max_bytes: None }),
+        request("UpdateNewContract", IpcRequest::UpdateNewContract { address: ADDRESS.to_string(), bytecode: vec![0, 97, 115, 109] }),
+        request("UpdateNewContractOnDeployment", IpcRequest::UpdateNewContractOnDeployment {
+            address: ADDRESS.to_string(),
+            bytecode: "0061736d".to_string(),
+            delta: delta(Some(ADDRESS), 0),
+        }),
+        request("RemoveContract", IpcRequest::RemoveContract { address: ADDRESS.to_string() }),
+        request("UpdateDeltas", IpcRequest::UpdateDeltas { deltas: vec![delta(Some(ADDRESS), 1), delta(Some(ADDRESS), 2)], allow_orphan: false }),
+        request("RemoveDeltas", IpcRequest::RemoveDeltas { input: vec![range()] }),
+        request("NewTaskEncryptionKey", IpcRequest::NewTaskEncryptionKey { user_pubkey: PUBKEY.to_string() }),
+        request("DeploySecretContract", IpcRequest::DeploySecretContract { input: task(Some(vec![0, 97, 115, 109])) }),
+        request("ComputeTask", IpcRequest::ComputeTask { input: IpcTask { expected_tip: Some(IpcTipRef { key: 1, hash: HASH.to_string() }), ..task(None) } }),
+        IpcMessageRequest { protocol_version: Some(2), ..request("ComputeTask-v2", IpcRequest::ComputeTask { input: IpcTask { task_id: Some(HASH.to_string()), ..task(None) } }) },
+        IpcMessageRequest { protocol_version: Some(PROTOCOL_VERSION), ..request("ComputeTask-v3", IpcRequest::ComputeTask { input: task(None) }) },
+        IpcMessageRequest {
+            protocol_version: Some(PROTOCOL_VERSION),
+            ..request("ComputeTask-envelope", IpcRequest::ComputeTask { input: IpcTask { output_envelope: true, ..task(None) } })
+        },
+        request("GetPTTRequest", IpcRequest::GetPTTRequest { block_number: None }),
+        request("GetPTTRequest-block", IpcRequest::GetPTTRequest { block_number: Some(150) }),
+        request("PTTResponse", IpcRequest::PTTResponse { input: PrincipalResponse { response: "84a46461746181a75265717565737491".to_string() } }),
+        request("RecoverKeys", IpcRequest::RecoverKeys { addresses: Some(vec![ADDRESS.to_string()]) }),
+        request("GetHealth", IpcRequest::GetHealth),
+        request("SetEpochParams", IpcRequest::SetEpochParams {
+            nonce: 3,
+            first_block: 1000,
+            seed_commitment: Some(HASH.to_string()),
+        }),
+        request("SetHostedContracts", IpcRequest::SetHostedContracts { addresses: vec![ADDRESS.to_string(), OTHER_ADDRESS.to_string()] }),
+        request("GetContractStats", IpcRequest::GetContractStats { input: ADDRESS.to_string() }),
+        request("VerifyTaskReceipt", IpcRequest::VerifyTaskReceipt {
+            receipt: IpcTaskReceipt {
+                address: ADDRESS.to_string(),
+                task_id: HASH.to_string(),
+                inputs_hash: HASH.to_string(),
+                exe_code_hash: Some(HASH.to_string()),
+                delta_key: Some(2),
+                prev_delta_hash: Some(HASH.to_string()),
+                delta_hash: HASH.to_string(),
+                output_hash: HASH.to_string(),
+                gas_limit: 100_000,
+                used_gas: 1_200,
+                ethereum_payload: String::new(),
+                ethereum_address: None,
+                envelope_hash: None,
+                signature: SIGNATURE.to_string(),
+                worker_address: Some(ETH_ADDRESS.to_string()),
+            },
+        }),
+        request("GetAuditDigest", IpcRequest::GetAuditDigest),
+        request("ReloadConfig", IpcRequest::ReloadConfig),
+        request("Drain", IpcRequest::Drain),
+        request("Resume", IpcRequest::Resume),
+        request("GetDrainStatus", IpcRequest::GetDrainStatus),
+        request("RunMaintenance", IpcRequest::RunMaintenance),
+        request("ReplayTask", IpcRequest::ReplayTask { task_id: HASH.to_string() }),
+        request("RebuildIndexes", IpcRequest::RebuildIndexes { scopes: Some(vec![RebuildScope::ChainHashes, RebuildScope::GapIndex]) }),
+    ]
+}
+
+/// One sample of every response, and of every error the p2p node can act on.
+pub fn responses() -> Vec<IpcMessageResponse> {
+    let status = |address: &str, key| IpcStatusResult { address: address.to_string(), key, status: Status::Ok };
+    let record = RegistrationRecord {
+        index: 4,
+        timestamp: 1_560_000_000,
+        signing_key: ETH_ADDRESS.to_string(),
+        report: "7b226964223a22313030".to_string(),
+        signature: "9a8b7c6d".to_string(),
+        mr_enclave: HASH.to_string(),
+    };
+    let epoch = IpcEpoch { nonce: Some(3), seed_commitment: Some(HASH.to_string()) };
+    let ptt_request = |epoch: IpcEpoch, addresses: Option<Vec<String>>| {
+        IpcResults::Request { request: "84a46461746181".to_string(), sig: SIGNATURE.to_string(), epoch, sig_forms: IpcSigForms::default(), addresses }
+    };
+    let error = |id: &str, msg: &str, retry: Retry, details: Option<IpcErrorDetails>| {
+        let (retryable, retry_after_ms) = (retry.is_retryable(), retry.retry_after_ms());
+        response(id, IpcResponse::Error { msg: msg.to_string(), retryable, retry_after_ms, details })
+    };
+
+    vec![
+        response("GetRegistrationParams", IpcResponse::GetRegistrationParams {
+            result: IpcResults::RegistrationParams { signing_key: record.signing_key.clone(), report: record.report.clone(), signature: record.signature.clone() },
+        }),
+        response("GetRegistrationHistory", IpcResponse::GetRegistrationHistory { result: IpcResults::RegistrationHistory(vec![record]) }),
+        response("GetTip", get_tip().for_protocol(DEFAULT_PROTOCOL_VERSION)),
+        response("GetTip-v4", get_tip().for_protocol(PROTOCOL_VERSION)),
+        response("GetTips", get_tips().for_protocol(DEFAULT_PROTOCOL_VERSION)),
+        response("GetTips-v4", get_tips().for_protocol(PROTOCOL_VERSION)),
+        response("GetAllTips", get_all_tips().for_protocol(DEFAULT_PROTOCOL_VERSION)),
+        response("GetAllTips-v4", get_all_tips().for_protocol(PROTOCOL_VERSION)),
+        response("GetAllAddrs", IpcResponse::GetAllAddrs { result: IpcResults::Addresses(vec![ADDRESS.to_string(), OTHER_ADDRESS.to_string()]), orphans: None }),
+        response("GetDelta", IpcResponse::GetDelta { result: IpcResults::Delta("0b020305292c".to_string()) }),
+        response("GetDeltas", IpcResponse::GetDeltas { result: IpcResults::Deltas(vec![delta(Some(ADDRESS), 1), delta(Some(ADDRESS), 2)]) }),
+        response("GetContract", IpcResponse::GetContract { result: IpcResults::GetContract {
+            address: ADDRESS.to_string(),
+            bytecode: vec![0, 97, 115, 109],
+            total_len: None,
+            bytecode_hash: None,
+        } }),
+        response("UpdateNewContract", IpcResponse::UpdateNewContract { address: ADDRESS.to_string(), result: IpcResults::Status(Status::Ok) }),
+        response("UpdateNewContract-NotHosted", IpcResponse::UpdateNewContract {
+            address: OTHER_ADDRESS.to_string(),
+            result: IpcResults::Status(Status::NotHosted),
+        }),
+        response("UpdateNewContractOnDeployment", IpcResponse::UpdateNewContractOnDeployment {
+            address: ADDRESS.to_string(),
+            result: IpcResults::Status(Status::Ok),
+        }),
+        response("RemoveContract", IpcResponse::RemoveContract { address: ADDRESS.to_string(), result: IpcResults::Status(Status::Ok) }),
+        response("UpdateDeltas", IpcResponse::UpdateDeltas {
+            result: IpcResults::DeltasResult { status: Status::Error, errors: vec![IpcStatusResult { status: Status::Gap, ..status(ADDRESS, Some(5)) }] },
+        }),
+        response("RemoveDeltas", IpcResponse::RemoveDeltas { result: IpcResults::DeltasResult { status: Status::Ok, errors: vec![] } }),
+        response("NewTaskEncryptionKey", IpcResponse::NewTaskEncryptionKey { result: IpcResults::DHKey {
+            dh_key: PUBKEY.to_string(),
+            sig: SIGNATURE.to_string(),
+            sig_forms: IpcSigForms::default(),
+        } }),
+        response("DeploySecretContract", deploy_result(&epoch).for_protocol(DEFAULT_PROTOCOL_VERSION)),
+        response("DeploySecretContract-v2", deploy_result(&epoch).for_protocol(2)),
+        response("DeploySecretContract-v3", deploy_result(&epoch).for_protocol(PROTOCOL_VERSION)),
+        response("ComputeTask", compute_result(&epoch, false).for_protocol(DEFAULT_PROTOCOL_VERSION)),
+        response("ComputeTask-v2", compute_result(&epoch, false).for_protocol(2)),
+        response("ComputeTask-v3", compute_result(&epoch, false).for_protocol(PROTOCOL_VERSION)),
+        response("ComputeTask-envelope", compute_result(&epoch, true).for_protocol(PROTOCOL_VERSION)),
+        response("FailedTask", IpcResponse::FailedTask {
+            result: IpcResults::FailedTask { output: "4f7574206f6620676173".to_string(), used_gas: 100_000, signature: SIGNATURE.to_string(),
+                                            sig_forms: IpcSigForms::default(), forbidden: false,
+                                            debug_trace: None, epoch: epoch.clone() },
+        }),
+        response("GetPTTRequest", IpcResponse::GetPTTRequest { result: ptt_request(epoch.clone(), None) }),
+        // A worker that doesn't host every contract.
+        response("GetPTTRequest-hosted", IpcResponse::GetPTTRequest { result: ptt_request(epoch, Some(vec![ADDRESS.to_string()])) }),
+        response("PTTResponse", IpcResponse::PTTResponse { result: IpcResults::Errors(vec![status(ADDRESS, None)]) }),
+        // Before any `SetEpochParams`.
+        response("RecoverKeys", IpcResponse::RecoverKeys { result: ptt_request(IpcEpoch::default(), None) }),
+        response("GetHealth", IpcResponse::GetHealth {
+            result: IpcResults::Health {
+                enclave_healthy: true,
+                enclave_degraded: false,
+                warmup_complete: true,
+                recovery: Some(RecoveryProgress { provisioned: 2, total: 3 }),
+                mirror: Some(MirrorStatus { pending: 0, dropped: 0 }),
+                handlers_healthy: true,
+                draining: false,
+                capacity: IpcCapacity { db_bytes: 4096, max_db_bytes: Some(1 << 30), contracts: 2, max_contracts: None, max_contract_delta_bytes: Some(1 << 20) },
+                address_index: AddressIndexStats {
+                    addresses: 2,
+                    fp_rate: Some(0.01),
+                    bloom_bits: Some(19_631),
+                    bloom_hashes: Some(7),
+                    skipped_lookups: 40,
+                    store_lookups: 12,
+                },
+            },
+        }),
+        response("SetEpochParams", IpcResponse::SetEpochParams { result: IpcResults::Status(Status::Ok) }),
+        response("SetHostedContracts", IpcResponse::SetHostedContracts { result: IpcResults::Status(Status::Ok) }),
+        response("GetContractStats", IpcResponse::GetContractStats {
+            result: IpcResults::ContractStats {
+                address: ADDRESS.to_string(),
+                bytecode_size: 4,
+                tip: Some(3),
+                chain_hash: Some(HASH.to_string()),
+                delta_bytes: 120,
+                max_delta_bytes: Some(1 << 20),
+            },
+        }),
+        response("VerifyTaskReceipt", IpcResponse::VerifyTaskReceipt { result: IpcResults::ReceiptVerdict { task_id: HASH.to_string(), verdict: ReceiptVerdict::Valid } }),
+        response("GetAuditDigest", IpcResponse::GetAuditDigest {
+            result: IpcResults::AuditDigest {
+                base_count: 0,
+                base: Hash256::default().to_hex(),
+                events: vec![IpcAuditEvent { counter: 0, kind: AuditEventKind::StateKey, subject: ADDRESS.to_string(), timestamp: 1_560_000_000, chain: HASH.to_string() }],
+                count: 1,
+                head: HASH.to_string(),
+                signature: SIGNATURE.to_string(),
+                sig_forms: IpcSigForms::default(),
+            },
+        }),
+        response("ReloadConfig", IpcResponse::ReloadConfig {
+            result: IpcResults::RateLimits(RateLimitConfig {
+                global: Some(RateLimit { per_second: 200.0, burst: 400 }),
+                types: vec![("GetAllTips".to_string(), RateLimit { per_second: 2.0, burst: 5 })].into_iter().collect(),
+            }),
+        }),
+        response("Drain", IpcResponse::Drain { result: IpcResults::DrainStatus { draining: true, in_flight: 2, rebuild: None } }),
+        response("Resume", IpcResponse::Resume { result: IpcResults::DrainStatus { draining: false, in_flight: 0, rebuild: None } }),
+        response("GetDrainStatus", IpcResponse::GetDrainStatus { result: IpcResults::DrainStatus { draining: true, in_flight: 0, rebuild: None } }),
+        response("GetDrainStatus-rebuilding", IpcResponse::GetDrainStatus {
+            result: IpcResults::DrainStatus { draining: true, in_flight: 0, rebuild: Some(RebuildProgress { scanned: 120, contracts: 300 }) },
+        }),
+        response("RunMaintenance", IpcResponse::RunMaintenance {
+            result: IpcResults::Maintenance(MaintenanceReport { registrations: 3, hot_set: 1, reclaimed_bytes: 65_536 }),
+        }),
+        response("ReplayTask", IpcResponse::ReplayTask {
+            result: IpcResults::ReplayReport {
+                task_id: HASH.to_string(),
+                matches: false,
+                fields: vec![
+                    IpcReplayField { field: "inputsHash".to_string(), matches: true, recorded: Some(HASH.to_string()), replayed: Some(HASH.to_string()) },
+                    IpcReplayField { field: "usedGas".to_string(), matches: true, recorded: Some("1200".to_string()), replayed: Some("1200".to_string()) },
+                    IpcReplayField { field: "delta".to_string(), matches: false, recorded: None, replayed: None },
+                ],
+            },
+        }),
+        response("RebuildIndexes", IpcResponse::RebuildIndexes {
+            result: IpcResults::Rebuild(RebuildReport { contracts: 300, address_index: None, chain_hashes: Some(2), gap_index: Some(0), hot_set: None }),
+        }),
+        error("Error", "Missing field `input`", Retry::Never, None),
+        error("Error-StaleEpoch", "The task is for epoch 2, but the current epoch is 3", Retry::After(None),
+              Some(IpcErrorDetails::StaleEpoch { task_nonce: Some(2), known_nonce: 3, seed_commitment: Some(HASH.to_string()) })),
+        error("Error-UnknownEpoch", "No known epoch was active at block 50, the earliest known epoch starts at Some(100)", Retry::Never,
+              Some(IpcErrorDetails::UnknownEpoch { block_number: 50, earliest_block: Some(100) })),
+        error("Error-Recovering", "Recovering the state keys, 2 of 3 contracts provisioned", Retry::After(Some(RECOVERING_RETRY_MS)),
+              Some(IpcErrorDetails::Recovering { provisioned: 2, total: 3 })),
+        error("Error-Busy", "The worker is at capacity", Retry::After(Some(ENCLAVE_BUSY_RETRY_MS)), Some(IpcErrorDetails::Busy)),
+        error("Error-ContractNotFound", &format!("The contract {} isn't deployed on this worker", ADDRESS), Retry::Never,
+              Some(IpcErrorDetails::ContractNotFound { address: ADDRESS.to_string() })),
+        error("Error-StateBehind", &format!("The state of the contract {} doesn't match the task, local tip: Some((1, {})), expected tip: 3", ADDRESS, HASH),
+              Retry::After(None),
+              Some(IpcErrorDetails::StateBehind { address: ADDRESS.to_string(), local_tip: Some(IpcTipRef { key: 1, hash: HASH.to_string() }), expected_key: 3 })),
+        error("Error-DeltaGap", &format!("The deltas of the contract {} have gaps, usable tip: Some(1), raw tip: 4", ADDRESS), Retry::After(None),
+              Some(IpcErrorDetails::DeltaGap { address: ADDRESS.to_string(), usable_tip: Some(1), raw_tip: 4 })),
+        error("Error-Draining", "The worker is draining, it doesn't accept tasks or writes until it's resumed", Retry::After(None),
+              Some(IpcErrorDetails::Draining)),
+        error("Error-NotDrained", "The indexes can't be rebuilt while writes may run (draining: true, in flight: 2), drain the core first",
+              Retry::After(None), Some(IpcErrorDetails::NotDrained { draining: true, in_flight: 2 })),
+        error("Error-CapacityExceeded", "Capacity exceeded, maxContracts is 2 and the usage is 2", Retry::Never,
+              Some(IpcErrorDetails::CapacityExceeded { cap: "maxContracts".to_string(), usage: 2, limit: 2, address: Some(ADDRESS.to_string()) })),
+        IpcMessageResponse { invalid_id: true, ..error("Error-InvalidId", "Invalid request id, the id is too long", Retry::Never, None) },
+        error("Error-Internal", "Internal error: called `Option::unwrap()` on a `None` value", Retry::Never, Some(IpcErrorDetails::Internal)),
+    ]
+}
+
+fn fixtures<T: ::serde::Serialize>(version: &str, messages: &[T]) -> Result<Fixtures, Error> {
+    let messages = messages.iter().map(serde_json::to_value).collect::<Result<Vec<Value>, _>>()?;
+    Ok(Fixtures { version: version.to_string(), unstable: UNSTABLE_FIELDS.iter().map(|f| f.to_string()).collect(), messages })
+}
+
+/// Writes the samples of this release to `<dir>/<version>/`, replacing the ones already there.
+pub fn write(dir: &Path, version: &str) -> Result<(), Error> {
+    let dir = dir.join(version);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(REQUESTS_FILE), serde_json::to_string_pretty(&fixtures(version, &requests())?)?)?;
+    fs::write(dir.join(RESPONSES_FILE), serde_json::to_string_pretty(&fixtures(version, &responses())?)?)?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Fixtures, Error> { Ok(serde_json::from_slice(&fs::read(path)?)?) }
+
+/// The variant tags, a response where they changed is a different message and can't be compatible.
+const TAGS: &[&str] = &["type", "code"];
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Checks that a client that parses `old` can parse `new`, the mismatches are returned as `<path>: <reason>`.
+/// A `null` in `old` is an optional field, any value is fine there, but a field that was set can't become `null`.
+pub fn compatibility_errors(old: &Value, new: &Value, path: &str, unstable: &[String]) -> Vec<String> {
+    if unstable.iter().any(|u| u == path) || old.is_null() {
+        return Vec::new();
+    }
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => old
+            .iter()
+            .flat_map(|(key, old_value)| {
+                let field = format!("{}/{}", path, key);
+                match new.get(key) {
+                    None if unstable.contains(&field) => Vec::new(),
+                    None => vec![format!("{}: missing", field)],
+                    Some(new_value) if TAGS.contains(&key.as_str()) && old_value != new_value => {
+                        vec![format!("{}: was {}, now {}", field, old_value, new_value)]
+                    }
+                    Some(new_value) => compatibility_errors(old_value, new_value, &field, unstable),
+                }
+            })
+            .collect(),
+        // The elements of a sample all have the same shape, the first one is enough.
+        (Value::Array(old), Value::Array(new)) => match (old.first(), new.first()) {
+            (Some(old), Some(new)) => compatibility_errors(old, new, &format!("{}/0", path), unstable),
+            _ => Vec::new(),
+        },
+        _ if json_type(old) == json_type(new) => Vec::new(),
+        _ => vec![format!("{}: was a {}, now a {}", path, json_type(old), json_type(new))],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    fn ids(messages: &[Value]) -> Vec<String> { messages.iter().map(|m| m["id"].as_str().unwrap().to_string()).collect() }
+
+    #[test]
+    fn test_samples_roundtrip() {
+        let requests = fixtures("test", &requests()).unwrap();
+        for message in &requests.messages {
+            let parsed: IpcMessageRequest = serde_json::from_value(message.clone()).unwrap();
+            assert_eq!(message["type"], parsed.request.kind());
+            assert_eq!(&serde_json::to_value(&parsed).unwrap(), message);
+        }
+        let ids = ids(&fixtures("test", &responses()).unwrap().messages);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    }
+
+    #[test]
+    fn test_compatibility_errors() {
+        let old = json!({"id": "a", "type": "GetHealth", "result": {"enclaveHealthy": true, "recovery": null, "mirror": {"pending": 1}}});
+        let unstable = vec!["a/result/mirror".to_string()];
+        let same = json!({"id": "a", "type": "GetHealth", "result": {"enclaveHealthy": false, "recovery": {"total": 1}, "new": 1}});
+        assert!(compatibility_errors(&old, &same, "a", &unstable).is_empty());
+
+        let changed = json!({"id": "a", "type": "GetTip", "result": {"enclaveHealthy": "true"}});
+        assert_eq!(compatibility_errors(&old, &changed, "a", &unstable), vec![
+            "a/type: was \"GetHealth\", now \"GetTip\"".to_string(),
+            "a/result/enclaveHealthy: was a bool, now a string".to_string(),
+        ]);
+        // Removing a stable field breaks the format.
+        assert_eq!(compatibility_errors(&old, &same, "a", &[]), vec!["a/result/mirror: missing".to_string()]);
+    }
+}
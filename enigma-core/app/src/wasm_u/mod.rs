@@ -3,8 +3,11 @@ pub mod wasm;
 use crate::common_u::errors::EnclaveFailError;
 use crate::db::{Delta, DeltaKey, Stype};
 use std::{fmt, convert::TryFrom};
-use enigma_types::{EnclaveReturn, ExecuteResult, ContractAddress};
+use enigma_types::{EnclaveReturn, ExecuteResult, ContractAddress, Hash256};
+use enigma_tools_m::signable::{to_ethereum, DeployReceipt, ExecuteReceipt};
+use enigma_tools_m::trace::ExecutionTrace;
 use failure::Error;
+use serde_json;
 use sgx_types::*;
 
 #[derive(Clone)]
@@ -14,14 +17,35 @@ pub struct WasmTaskResult {
     pub delta: Delta,
     pub eth_payload: Box<[u8]>,
     pub eth_contract_addr: [u8; 20],
+    /// `r || s || v` with `v` 27/28, converted from the form the enclave hands out.
     pub signature: [u8; 65],
     pub used_gas: u64,
+    /// The host calls of the task, only if a trace was asked for.
+    pub trace: Option<ExecutionTrace>,
+    /// The rest of the signed receipt, as the enclave reported it, see `execute_receipt` and `deploy_receipt`.
+    pub inputs_hash: Hash256,
+    pub exe_code_hash: Hash256,
+    /// Only for a deployment, the inputs hash is over it.
+    pub pre_code_hash: Hash256,
+    pub prev_delta_hash: Hash256,
+    pub delta_hash: Hash256,
+    pub output_hash: Hash256,
+    pub gas_limit: u64,
+    /// The encoded `OutputEnvelope` of the output and its hash, only if an envelope was asked for.
+    pub envelope: Option<Box<[u8]>>,
+    pub envelope_hash: Option<Hash256>,
+    /// The IO key of a compute task as the enclave sealed it, only it can open it to replay the task.
+    pub sealed_io_key: Option<Box<[u8]>>,
 }
 
 pub struct WasmTaskFailure {
     pub output: Box<[u8]>,
     pub signature: [u8; 65],
     pub used_gas: u64,
+    /// The caller isn't in the contract's access list.
+    pub forbidden: bool,
+    /// The host calls up to the failure, only if a trace was asked for.
+    pub trace: Option<ExecutionTrace>,
 }
 
 #[derive(Debug)]
@@ -39,7 +63,49 @@ impl Default for WasmTaskResult {
             eth_payload: Default::default(),
             eth_contract_addr: Default::default(),
             signature: [0u8; 65],
-            used_gas: Default::default()
+            used_gas: Default::default(),
+            trace: None,
+            inputs_hash: Default::default(),
+            exe_code_hash: Default::default(),
+            pre_code_hash: Default::default(),
+            prev_delta_hash: Default::default(),
+            delta_hash: Default::default(),
+            output_hash: Default::default(),
+            gas_limit: Default::default(),
+            envelope: None,
+            envelope_hash: None,
+            sealed_io_key: None,
+        }
+    }
+}
+
+impl WasmTaskResult {
+    /// What the enclave signed for a compute task.
+    pub fn execute_receipt(&self) -> ExecuteReceipt {
+        ExecuteReceipt {
+            exe_code_hash: self.exe_code_hash,
+            inputs_hash: self.inputs_hash,
+            prev_delta_hash: self.prev_delta_hash,
+            delta_hash: self.delta_hash,
+            output_hash: self.output_hash,
+            gas_limit: self.gas_limit,
+            used_gas: self.used_gas,
+            ethereum_payload: self.eth_payload.to_vec(),
+            ethereum_address: self.eth_contract_addr,
+            envelope_hash: self.envelope_hash,
+        }
+    }
+
+    /// What the enclave signed for a deployment.
+    pub fn deploy_receipt(&self) -> DeployReceipt {
+        DeployReceipt {
+            inputs_hash: self.inputs_hash,
+            exe_code_hash: self.exe_code_hash,
+            delta_hash: self.delta_hash,
+            gas_limit: self.gas_limit,
+            used_gas: self.used_gas,
+            ethereum_payload: self.eth_payload.to_vec(),
+            ethereum_address: self.eth_contract_addr,
         }
     }
 }
@@ -49,7 +115,9 @@ impl Default for  WasmTaskFailure {
         WasmTaskFailure {
             output: Default::default(),
             signature: [0u8; 65],
-            used_gas: Default::default()
+            used_gas: Default::default(),
+            forbidden: false,
+            trace: None,
         }
     }
 }
@@ -64,6 +132,17 @@ impl fmt::Debug for WasmTaskResult {
         debug_builder.field("eth_contract_addr", &self.eth_contract_addr);
         debug_builder.field("signature", &(&self.signature[..]));
         debug_builder.field("used_gas", &self.used_gas);
+        debug_builder.field("trace", &self.trace);
+        debug_builder.field("inputs_hash", &self.inputs_hash);
+        debug_builder.field("exe_code_hash", &self.exe_code_hash);
+        debug_builder.field("pre_code_hash", &self.pre_code_hash);
+        debug_builder.field("prev_delta_hash", &self.prev_delta_hash);
+        debug_builder.field("delta_hash", &self.delta_hash);
+        debug_builder.field("output_hash", &self.output_hash);
+        debug_builder.field("gas_limit", &self.gas_limit);
+        debug_builder.field("envelope", &self.envelope);
+        debug_builder.field("envelope_hash", &self.envelope_hash);
+        debug_builder.field("sealed_io_key", &self.sealed_io_key);
         debug_builder.finish()
     }
 }
@@ -74,6 +153,8 @@ impl fmt::Debug for WasmTaskFailure{
         debug_builder.field("output", &self.output);
         debug_builder.field("signature", &(&self.signature[..]));
         debug_builder.field("used_gas", &self.used_gas);
+        debug_builder.field("forbidden", &self.forbidden);
+        debug_builder.field("trace", &self.trace);
         debug_builder.finish()
     }
 }
@@ -89,11 +170,21 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
             let output = unsafe { Box::from_raw(box_ptr) };
             Ok(*output)
         };
-        if exec.2 == EnclaveReturn::TaskFailure {
+        let get_trace = |exec_result: ExecuteResult| -> Result<Option<ExecutionTrace>, Self::Error> {
+            if exec_result.trace_ptr.is_null() {
+                return Ok(None);
+            }
+            let box_ptr = exec_result.trace_ptr as *mut Box<[u8]>;
+            let trace = unsafe { Box::from_raw(box_ptr) };
+            Ok(Some(serde_json::from_slice(&trace)?))
+        };
+        if exec.2 == EnclaveReturn::TaskFailure || exec.2 == EnclaveReturn::Forbidden {
             let mut result: WasmTaskFailure = Default::default();
             result.output = get_output(exec.0)?;
-            result.signature = exec.0.signature;
+            result.signature = to_ethereum(&exec.0.signature)?;
             result.used_gas = exec.0.used_gas;
+            result.forbidden = exec.2 == EnclaveReturn::Forbidden;
+            result.trace = get_trace(exec.0)?;
             Ok(WasmResult::WasmTaskFailure(result))
         }
         else if exec.2 != EnclaveReturn::Success || exec.3 != sgx_status_t::SGX_SUCCESS {
@@ -107,8 +198,27 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
             let mut result: WasmTaskResult = Default::default();
             // If execution does not return any result, then `output` points to empty array []
             result.output = get_output(exec.0)?;
-            result.signature = exec.0.signature;
+            result.signature = to_ethereum(&exec.0.signature)?;
             result.used_gas = exec.0.used_gas;
+            result.trace = get_trace(exec.0)?;
+            result.inputs_hash = exec.0.inputs_hash.into();
+            result.exe_code_hash = exec.0.exe_code_hash.into();
+            result.pre_code_hash = exec.0.pre_code_hash.into();
+            result.prev_delta_hash = exec.0.prev_delta_hash.into();
+            result.delta_hash = exec.0.delta_hash.into();
+            result.output_hash = exec.0.output_hash.into();
+            result.gas_limit = exec.0.gas_limit;
+            if !exec.0.envelope_ptr.is_null() {
+                let box_envelope_ptr = exec.0.envelope_ptr as *mut Box<[u8]>;
+                let envelope = unsafe { Box::from_raw(box_envelope_ptr) };
+                result.envelope = Some(*envelope);
+                result.envelope_hash = Some(exec.0.envelope_hash.into());
+            }
+            if !exec.0.sealed_io_key_ptr.is_null() {
+                let box_key_ptr = exec.0.sealed_io_key_ptr as *mut Box<[u8]>;
+                let sealed_io_key = unsafe { Box::from_raw(box_key_ptr) };
+                result.sealed_io_key = Some(*sealed_io_key);
+            }
 
             // If there is no call to any ethereum contract in the execution, then
             // `eth_contract_addr` is all zeros
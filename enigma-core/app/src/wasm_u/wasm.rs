@@ -1,18 +1,43 @@
-use enigma_types::{ContractAddress, EnclaveReturn, ExecuteResult, PubKey, RawPointer, traits::SliceCPtr};
+use enigma_types::{ContractAddress, EnclaveReturn, ExecuteResult, PubKey, RawPointer, ReplayReceipt, ReplayResult, traits::SliceCPtr};
 use super::WasmResult;
 use crate::db::DB;
 use std::convert::TryInto;
 use failure::Error;
 use sgx_types::*;
-use crate::auto_ffi::{ecall_deploy, ecall_execute};
+use crate::auto_ffi::{ecall_deploy, ecall_deploy_begin, ecall_deploy_chunk, ecall_deploy_finish, ecall_execute, ecall_replay};
+use crate::common_u::errors::EnclaveFailError;
+use crate::common_u::metrics::METRICS;
+use enigma_crypto::hash::Keccak256;
+use std::time::Instant;
+
+/// Bytecode longer than this is streamed into the enclave in chunks instead of being passed to `ecall_deploy` at once.
+pub const CHUNKED_DEPLOY_THRESHOLD: usize = 1024 * 1024;
+const DEPLOY_CHUNK_SIZE: usize = 256 * 1024;
 
 #[logfn(TRACE)]
 pub fn deploy(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
               contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64)-> Result<WasmResult, Error> {
+    deploy_traced(db, eid, bytecode, constructor, args, contract_address, user_pubkey, gas_limit, false)
+}
+
+/// The same as `deploy`, with `debug_trace` the result also carries the trace of the constructor if the enclave is a debug build.
+#[logfn(TRACE)]
+pub fn deploy_traced(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
+                     contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64, debug_trace: bool)-> Result<WasmResult, Error> {
+    if bytecode.len() > CHUNKED_DEPLOY_THRESHOLD {
+        deploy_chunked(db, eid, bytecode, constructor, args, contract_address, user_pubkey, gas_limit, DEPLOY_CHUNK_SIZE, debug_trace)
+    } else {
+        deploy_contiguous(db, eid, bytecode, constructor, args, contract_address, user_pubkey, gas_limit, debug_trace)
+    }
+}
+
+pub(crate) fn deploy_contiguous(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
+                                contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64, debug_trace: bool)-> Result<WasmResult, Error> {
     let mut retval = EnclaveReturn::Success;
     let mut result = ExecuteResult::default();
     let db_ptr = unsafe { RawPointer::new_mut(db) };
 
+    let start = Instant::now();
     let status = unsafe {
         ecall_deploy(eid,
                      &mut retval,
@@ -25,19 +50,78 @@ pub fn deploy(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor:
                      contract_address,
                      user_pubkey.as_ptr() as _,
                      &gas_limit as *const u64,
+                     debug_trace as u8,
                      &db_ptr as *const RawPointer,
                      &mut result)
     };
+    METRICS.record_enclave_call("ecall_deploy", start.elapsed(), status);
+    (result, *contract_address, retval, status).try_into()
+}
+
+/// Uploads the bytecode `chunk_size` bytes at a time, and deploys it once the enclave checked it against its hash.
+pub(crate) fn deploy_chunked(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
+                             contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64, chunk_size: usize,
+                             debug_trace: bool)-> Result<WasmResult, Error> {
+    let mut retval = EnclaveReturn::Success;
+    let mut handle = 0u64;
+
+    let start = Instant::now();
+    let status = unsafe { ecall_deploy_begin(eid, &mut retval, bytecode.len(), &mut handle) };
+    METRICS.record_enclave_call("ecall_deploy_begin", start.elapsed(), status);
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: retval, status }.into());
+    }
+
+    for chunk in bytecode.chunks(chunk_size) {
+        let start = Instant::now();
+        let status = unsafe { ecall_deploy_chunk(eid, &mut retval, handle, chunk.as_c_ptr(), chunk.len()) };
+        METRICS.record_enclave_call("ecall_deploy_chunk", start.elapsed(), status);
+        if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveFailError { err: retval, status }.into());
+        }
+    }
+
+    let bytecode_hash = bytecode.keccak256();
+    let mut result = ExecuteResult::default();
+    let db_ptr = unsafe { RawPointer::new_mut(db) };
+    let start = Instant::now();
+    let status = unsafe {
+        ecall_deploy_finish(eid,
+                            &mut retval,
+                            handle,
+                            &*bytecode_hash,
+                            constructor.as_c_ptr() as *const u8,
+                            constructor.len(),
+                            args.as_c_ptr(),
+                            args.len(),
+                            contract_address,
+                            user_pubkey.as_ptr() as _,
+                            &gas_limit as *const u64,
+                            debug_trace as u8,
+                            &db_ptr as *const RawPointer,
+                            &mut result)
+    };
+    METRICS.record_enclave_call("ecall_deploy_finish", start.elapsed(), status);
     (result, *contract_address, retval, status).try_into()
 }
 
 #[logfn(TRACE)]
 pub fn execute(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &[u8], args: &[u8],
                user_pubkey: &PubKey, contract_address: &ContractAddress, gas_limit: u64)-> Result<WasmResult,Error> {
+    execute_traced(db, eid, bytecode, callable, args, user_pubkey, contract_address, gas_limit, false, false)
+}
+
+/// The same as `execute`, with `debug_trace` the result also carries the trace of the execution if the enclave is a debug build.
+/// With `output_envelope` the encrypted output is also sealed in an envelope addressed to `user_pubkey`, see `OutputEnvelope`.
+#[logfn(TRACE)]
+pub fn execute_traced(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &[u8], args: &[u8],
+                      user_pubkey: &PubKey, contract_address: &ContractAddress, gas_limit: u64, debug_trace: bool,
+                      output_envelope: bool)-> Result<WasmResult,Error> {
     let mut retval = EnclaveReturn::Success;
     let mut result = ExecuteResult::default();
     let db_ptr = unsafe { RawPointer::new_mut(db) };
 
+    let start = Instant::now();
     let status = unsafe {
         ecall_execute(eid,
                       &mut retval,
@@ -50,13 +134,61 @@ pub fn execute(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &
                       user_pubkey.as_ptr() as _,
                       contract_address,
                       &gas_limit as *const u64,
+                      debug_trace as u8,
+                      output_envelope as u8,
                       &db_ptr as *const RawPointer,
                       &mut result)
     };
+    METRICS.record_enclave_call("ecall_execute", start.elapsed(), status);
 
     (result, *contract_address, retval, status).try_into()
 }
 
+/// Executes a compute task again on the state in `db` and compares it with the `output` and `delta` it produced, see `ReplayResult`.
+/// `db` should be a sandbox, the enclave doesn't store anything but building the state does.
+/// The enclave decrypts the task with `sealed_io_key`, the key the execution handed out sealed (see `WasmTaskResult::sealed_io_key`),
+/// and only executes it on the bytecode and the state of the signed `receipt` (`receipt_sig` is `r || s || v` with `v` 27/28).
+#[logfn(TRACE)]
+pub fn replay(db: &mut DB, eid: sgx_enclave_id_t, bytecode: &[u8], callable: &[u8], args: &[u8], user_pubkey: &PubKey,
+              contract_address: &ContractAddress, sealed_io_key: &[u8], receipt: &ReplayReceipt, receipt_sig: &[u8; 65],
+              ethereum_payload: &[u8], output: &[u8], delta: &[u8]) -> Result<ReplayResult, Error> {
+    let mut retval = EnclaveReturn::Success;
+    let mut result = ReplayResult::default();
+    let db_ptr = unsafe { RawPointer::new_mut(db) };
+
+    let start = Instant::now();
+    let status = unsafe {
+        ecall_replay(eid,
+                     &mut retval,
+                     bytecode.as_c_ptr() as *const u8,
+                     bytecode.len(),
+                     callable.as_c_ptr() as *const u8,
+                     callable.len(),
+                     args.as_c_ptr() as *const u8,
+                     args.len(),
+                     user_pubkey.as_ptr() as _,
+                     contract_address,
+                     sealed_io_key.as_c_ptr() as *const u8,
+                     sealed_io_key.len(),
+                     receipt,
+                     receipt_sig.as_ptr() as _,
+                     ethereum_payload.as_c_ptr() as *const u8,
+                     ethereum_payload.len(),
+                     output.as_c_ptr() as *const u8,
+                     output.len(),
+                     delta.as_c_ptr() as *const u8,
+                     delta.len(),
+                     &db_ptr as *const RawPointer,
+                     &mut result)
+    };
+    METRICS.record_enclave_call("ecall_replay", start.elapsed(), status);
+
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: retval, status }.into());
+    }
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     extern crate ethabi;
@@ -70,7 +202,7 @@ mod tests {
     use crate::wasm_u::wasm;
     use self::ethabi::{Contract, Token, token::{LenientTokenizer, Tokenizer}};
     use enigma_types::{ContractAddress, DhKey, PubKey};
-    use enigma_crypto::symmetric;
+    use enigma_crypto::{hash::Keccak256, symmetric};
     use hex::FromHex;
     use sgx_types::*;
     use std::fs::File;
@@ -359,6 +491,85 @@ mod tests {
         }
     }
 
+    // Pads a contract with a custom section so it's over the chunked deploy threshold.
+    fn pad_bytecode(mut bytecode: Vec<u8>, padding: usize) -> Vec<u8> {
+        let name = b"padding";
+        let mut size = name.len() + 1 + padding;
+        bytecode.push(0);
+        loop {
+            let byte = (size & 0x7f) as u8;
+            size >>= 7;
+            if size == 0 {
+                bytecode.push(byte);
+                break;
+            }
+            bytecode.push(byte | 0x80);
+        }
+        bytecode.push(name.len() as u8);
+        bytecode.extend_from_slice(name);
+        bytecode.extend((0..padding).map(|i| i as u8));
+        bytecode
+    }
+
+    #[test]
+    fn test_chunked_deploy() {
+        let (mut db, _dir) = create_test_db();
+        let enclave = init_enclave_wrapper().unwrap();
+        let bytecode = get_bytecode_from_path("../../examples/eng_wasm_contracts/simplest");
+        let bytecode = pad_bytecode(bytecode, wasm::CHUNKED_DEPLOY_THRESHOLD + 12_345);
+
+        let addresses = [generate_contract_address(), generate_contract_address()];
+        instantiate_encryption_key(addresses.to_vec(), enclave.geteid());
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_construct = symmetric::encrypt(b"construct(uint)", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[Token::Uint(17.into())]), &shared_key).unwrap();
+
+        let contiguous = wasm::deploy_contiguous(&mut db, enclave.geteid(), &bytecode, &encrypted_construct, &encrypted_args,
+                                                 &addresses[0], &keys.get_pubkey(), GAS_LIMIT, false).unwrap().unwrap_result();
+        let chunked = wasm::deploy_chunked(&mut db, enclave.geteid(), &bytecode, &encrypted_construct, &encrypted_args,
+                                           &addresses[1], &keys.get_pubkey(), GAS_LIMIT, 100_000, false).unwrap().unwrap_result();
+        // The deltas are encrypted with a random IV, everything else must be the same.
+        assert_eq!(contiguous.output, chunked.output);
+        assert_eq!(contiguous.used_gas, chunked.used_gas);
+        assert_eq!(contiguous.eth_payload, chunked.eth_payload);
+    }
+
+    #[test]
+    fn test_access_list() {
+        let (mut db, _dir) = create_test_db();
+        let contract_address = generate_contract_address();
+        let enclave = init_enclave_wrapper().unwrap();
+        instantiate_encryption_key(vec![contract_address], enclave.geteid());
+
+        let (allowed_keys, allowed_shared_key, _, _) = exchange_keys(enclave.geteid());
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_construct = symmetric::encrypt(b"construct(bytes)", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[Token::Bytes(allowed_keys.get_pubkey().to_vec())]), &shared_key).unwrap();
+        let exe_code = compile_and_deploy_wasm_contract(&mut db, enclave.geteid(), "../../examples/eng_wasm_contracts/access_list",
+                                                        contract_address, &encrypted_construct, &encrypted_args, &keys.get_pubkey())
+            .unwrap_result().output;
+
+        let call = |db: &mut DB, pubkey: &PubKey, shared_key: &DhKey| {
+            let encrypted_callable = symmetric::encrypt(b"increment()", shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), shared_key).unwrap();
+            wasm::execute(db, enclave.geteid(), &exe_code, &encrypted_callable, &encrypted_args, pubkey, &contract_address, GAS_LIMIT).unwrap()
+        };
+
+        let allowed = call(&mut db, &allowed_keys.get_pubkey(), &allowed_shared_key).unwrap_result();
+        let output: Vec<u8> = symmetric::decrypt(&allowed.output, &allowed_shared_key).unwrap();
+        assert_eq!(output, ethabi::encode(&[Token::Uint(1.into())]));
+
+        let (other_keys, other_shared_key, _, _) = exchange_keys(enclave.geteid());
+        match call(&mut db, &other_keys.get_pubkey(), &other_shared_key) {
+            WasmResult::WasmTaskFailure(failure) => {
+                assert!(failure.forbidden);
+                assert_eq!(failure.used_gas, 0);
+                assert_ne!(&failure.signature[..], &[0u8; 65][..]);
+            }
+            WasmResult::WasmTaskResult(_) => panic!("A caller outside the access list executed the task"),
+        }
+    }
+
     #[test]
     fn test_print_simple() {
         let (mut db, _dir) = create_test_db();
@@ -486,6 +697,55 @@ mod tests {
         assert_eq!(&(decoded_output.clone().to_bytes().unwrap())[..], b"157");
     }
 
+    #[test]
+    fn test_execution_trace() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, exe_code, untraced, _) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/simplest",
+            address,
+            "construct(uint)",
+            &[Token::Uint(17.into())],
+            "write()",
+            &[]
+        );
+        assert!(untraced.trace.is_none());
+
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_callable = symmetric::encrypt(b"write()", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), &shared_key).unwrap();
+        let result = wasm::execute_traced(
+            &mut db,
+            enclave.geteid(),
+            &exe_code,
+            &encrypted_callable,
+            &encrypted_args,
+            &keys.get_pubkey(),
+            &address,
+            GAS_LIMIT,
+            true,
+            false
+        ).expect("Execution failed").unwrap_result();
+        let trace = result.trace.expect("No trace, the enclave has to be a debug build");
+
+        let names: Vec<&str> = trace.calls.iter().map(|call| call.name.as_str()).collect();
+        let expected = ["fetch_function_name", "fetch_args", "write_state", "read_state", "ret"];
+        let mut calls = names.iter();
+        assert!(expected.iter().all(|name| calls.any(|call| call == name)), "Unexpected calls: {:?}", names);
+        assert_eq!(trace.dropped, 0);
+
+        // Only the sizes of `"157"` and the hash of its key are recorded, never the value.
+        let write = trace.calls.iter().find(|call| call.name == "write_state").unwrap();
+        assert_eq!(write.sizes, vec![4, 5]);
+        assert_eq!(write.key_hash, Some(b"code".keccak256()));
+
+        assert!(trace.calls.windows(2).all(|calls| calls[0].gas <= calls[1].gas));
+        assert_eq!(trace.used_gas, result.used_gas);
+        // Writing the same value again doesn't change the state.
+        assert_eq!(trace.delta_size, None);
+    }
+
     // address is defined in our protocol as ethereum's H256/bytes32
     #[test]
     fn test_single_address() {
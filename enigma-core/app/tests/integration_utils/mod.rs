@@ -1,6 +1,5 @@
 pub extern crate enigma_core_app as app;
 
-extern crate zmq;
 extern crate regex;
 pub extern crate ethabi;
 pub extern crate serde;
@@ -11,7 +10,6 @@ extern crate rustc_hex as hex;
 pub extern crate cross_test_utils;
 extern crate futures;
 extern crate dirs;
-extern crate rand;
 extern crate tempfile;
 
 use self::cross_test_utils::{generate_contract_address, generate_user_address, make_encrypted_response,
@@ -20,17 +18,19 @@ use self::cross_test_utils::{generate_contract_address, generate_user_address, m
 use self::app::*;
 use self::futures::Future;
 use self::app::networking::*;
+use self::app::networking::client::{ClientError, ClientOptions, CoreClient};
+use self::app::networking::messages::{IpcDelta, IpcDeltasRange, IpcTask};
 use self::serde::{Deserialize, Serialize};
 use self::rmps::{Deserializer, Serializer};
 use self::app::serde_json;
 use app::serde_json::*;
 use std::thread;
+use std::time::Duration;
 use self::regex::Regex;
 use self::hex::{ToHex, FromHex};
 use self::ethabi::{Token};
 use self::enigma_crypto::{asymmetric::KeyPair, symmetric};
 use self::enigma_types::Hash256;
-use self::rand::{thread_rng, Rng};
 use app::db::DB;
 use self::tempfile::TempDir;
 
@@ -58,89 +58,51 @@ pub fn run_core(port: &'static str) {
     });
 }
 
-pub fn generate_job_id() -> String {
-    let mut rng = thread_rng();
-    let id: u32 = rng.gen();
-    id.to_string()
-}
-
 pub fn is_hex(msg: &str) -> bool {
     let re = Regex::new(r"^(0x|0X)?[0-9a-fA-F]*$").unwrap();
     re.is_match(msg)
 }
 
-pub fn conn_and_call_ipc(msg: &str, port: &'static str) -> Value {
-    const TIMEOUT: i32 = 30_000; // Socket timeout - 30 seconds.
-    let context = zmq::Context::new();
-    let requester = context.socket(zmq::REQ).unwrap();
-    requester.set_rcvtimeo(TIMEOUT).unwrap();
-    requester.set_sndtimeo(TIMEOUT).unwrap();
-    assert!(requester.connect(&format!("tcp://localhost:{}", port)).is_ok());
-
-    requester.send(msg, 0).unwrap();
-    let mut msg = zmq::Message::new();
-    requester.recv(&mut msg, 0).unwrap();
-    serde_json::from_str(msg.as_str().unwrap()).unwrap()
-}
-pub fn get_simple_msg_format(msg_type: &str) -> Value {
-    json!({"id": &generate_job_id(), "type": msg_type})
-}
-
-pub fn get_msg_format_with_input(type_tip: &str, input: &str) -> Value {
-    json!({"id": &generate_job_id(), "type": type_tip, "input": input})
-}
-
-pub fn get_encryption_msg(user_pubkey: [u8; 64]) -> Value {
-    json!({"id" : &generate_job_id(), "type" : "NewTaskEncryptionKey", "userPubKey": user_pubkey.to_hex()})
-}
-
-pub fn get_ptt_req_msg() -> Value {
-    json!({"id" : &generate_job_id(), "type" : "GetPTTRequest"})
-}
-
-pub fn get_ptt_res_msg(response: &[u8]) -> Value {
-    json!({"id" : &generate_job_id(), "type" : "PTTResponse", "input": {"response": response.to_hex() }})
+/// A client of the core on `port`, it doesn't retry so the tests see the errors as the core sent them.
+pub fn client(port: &'static str) -> CoreClient {
+    let options = ClientOptions { timeout: Duration::from_secs(30), retries: 0, ..Default::default() };
+    CoreClient::connect(&format!("tcp://localhost:{}", port)).unwrap().with_options(options).unwrap()
 }
 
-pub fn get_deploy_msg(pre_code: &[u8], args: &str, callable: &str, usr_pubkey: &str, gas_limit: u64, addr: &str) -> Value {
-    json!({"id" : &generate_job_id(), "type" : "DeploySecretContract", "input":
-            {"preCode": &pre_code, "encryptedArgs": args,
-            "encryptedFn": callable, "userDHKey": usr_pubkey,
-            "gasLimit": gas_limit, "contractAddress": addr}
-            })
+/// The response of the core, an error response included.
+pub fn response(res: Result<Value, ClientError>) -> Value {
+    match res {
+        Ok(v) => v,
+        Err(ClientError::Server(e)) => e.response,
+        Err(e) => panic!("No response from the core: {}", e),
+    }
 }
 
-pub fn get_compute_msg(task_id: &str, callable: &str, args: &str, user_pubkey: &str, gas_limit: u64, con_addr: &str) -> Value {
-    json!({"id": &generate_job_id(), "type": "ComputeTask", "input": { "taskID": task_id, "encryptedArgs": args,
-    "encryptedFn": callable, "userDHKey": user_pubkey, "gasLimit": gas_limit, "contractAddress": con_addr}})
+pub fn ipc_deltas(input: &[(String, u64, Vec<u8>)]) -> Vec<IpcDelta> {
+    input.iter()
+        .map(|(addr, key, data)| IpcDelta { contract_address: Some(addr.clone()), key: *key as u32, data: Some(data.clone()), ..Default::default() })
+        .collect()
 }
 
-pub fn get_get_tips_msg(input: &[String]) -> Value {
-    json!({"id": &generate_job_id(), "type": "GetTips", "input": input.to_vec()})
+pub fn ipc_ranges(input: &[(String, u64, u64)]) -> Vec<IpcDeltasRange> {
+    input.iter().map(|(addr, from, to)| IpcDeltasRange { address: addr.clone(), from: *from as u32, to: *to as u32 }).collect()
 }
 
-pub fn get_delta_msg(addr: &str, key: u64) -> Value {
-    json!({"id": &generate_job_id(), "type": "GetDelta", "input": {"address": addr, "key": key}})
-}
-
-pub fn deltas_msg(input: &[(String, u64, u64)], msg_type: &str) -> Value {
-    let input: Vec<Value> = input.iter().map(|(addr, from, to)| json!({"address": addr, "from": from, "to": to})).collect();
-    json!({"id": &generate_job_id(), "type": msg_type, "input": input})
-}
-
-pub fn get_msg_format_update_contract(addr: &str, bytecode: Vec<u8>) -> Value {
-    json!({"id": &generate_job_id(), "type": "UpdateNewContract", "address": addr, "bytecode": bytecode})
-}
-
-pub fn get_msg_format_update_contract_on_deployment(addr: &str, bytecode: &str, delta: &(String, u64, Vec<u8>)) -> Value {
-    let (address, key, data) = delta;
-    let delta_json = json!({"address": address, "key": key, "data": data});
-    json!({"id": &generate_job_id(), "type": "UpdateNewContractOnDeployment", "address": addr, "bytecode": bytecode, "delta": delta_json})
-}
-
-pub fn get_update_deltas_msg(_input: &[(String, u64, Vec<u8>)]) -> Value {
-    let input: Vec<Value> = _input.iter().map(|(addr, key, data)| json!({"address": addr, "key": key, "data": data})).collect();
-    json!({"id": &generate_job_id(), "type": "UpdateDeltas", "deltas": input})
+pub fn ipc_task(pre_code: Option<Vec<u8>>, callable: &[u8], args: &[u8], user_pubkey: &[u8; 64], gas_limit: u64, addr: &str) -> IpcTask {
+    IpcTask {
+        pre_code,
+        encrypted_args: args.to_hex(),
+        encrypted_fn: callable.to_hex(),
+        user_dhkey: user_pubkey.to_hex(),
+        gas_limit,
+        address: addr.to_string(),
+        block_number: None,
+        epoch_nonce: None,
+        debug_trace: false,
+        expected_tip: None,
+        task_id: None,
+        output_envelope: false,
+    }
 }
 
 pub fn parse_packed_msg(msg: &str) -> Value {
@@ -159,30 +121,30 @@ pub fn mock_principal_res(msg: &str, addrs: Vec<ContractAddress>) -> Vec<u8> {
 }
 
 pub fn run_ptt_round(port: &'static str, addrs: Vec<ContractAddress>) -> Value {
-
+    let mut client = client(port);
     // set encrypted request message to send to the principal node
-    let msg_req = get_ptt_req_msg();
-    let req_val: Value = conn_and_call_ipc(&msg_req.to_string(), port);
+    let req_val: Value = response(client.get_ptt_request());
     let packed_msg = req_val["result"]["request"].as_str().unwrap();
 
     let enc_response = mock_principal_res(packed_msg, addrs);
-    let msg = get_ptt_res_msg(&enc_response);
-    conn_and_call_ipc(&msg.to_string(), port)
+    response(client.ptt_response(&enc_response.to_hex()))
 }
 
 pub fn produce_shared_key(port: &'static str) -> ([u8; 32], [u8; 64]) {
-    // get core's pubkey
     let keys = KeyPair::new().unwrap();
-    let msg = get_encryption_msg(keys.get_pubkey());
+    (produce_shared_key_with(port, &keys), keys.get_pubkey())
+}
 
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+/// The DH key of the core and of the user holding `keys`.
+pub fn produce_shared_key_with(port: &'static str, keys: &KeyPair) -> [u8; 32] {
+    // get core's pubkey
+    let v: Value = response(client(port).new_task_encryption_key(&keys.get_pubkey().to_hex()));
     let core_pubkey: String = serde_json::from_value(v["result"]["workerEncryptionKey"].clone()).unwrap();
     let _pubkey_vec: Vec<u8> = core_pubkey.from_hex().unwrap();
     let mut pubkey_arr = [0u8; 64];
     pubkey_arr.copy_from_slice(&_pubkey_vec);
 
-    let shared_key = keys.derive_key(&pubkey_arr).unwrap();
-    (shared_key, keys.get_pubkey())
+    keys.derive_key(&pubkey_arr).unwrap()
 }
 
 pub fn full_erc20_deployment(port: &'static str, owner: ERC20UserAddress, total_supply: Option<u64>, gas_limit: Option<u64>) -> (Value, [u8; 32], [u8; 32]) {
@@ -200,9 +162,8 @@ pub fn full_erc20_deployment(port: &'static str, owner: ERC20UserAddress, total_
     let (encrypted_callable, encrypted_args) = encrypt_args(&args_deploy, fn_deploy, _shared_key.clone());
     let gas_limit = gas_limit.unwrap_or(100_000_000);
 
-    let msg = get_deploy_msg(&pre_code, &encrypted_args.to_hex(),
-                             &encrypted_callable.to_hex(), &_user_pubkey.to_hex(), gas_limit, &address.to_hex());
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let task = ipc_task(Some(pre_code), &encrypted_callable, &encrypted_args, &_user_pubkey, gas_limit, &address.to_hex());
+    let v: Value = response(client(port).deploy_secret_contract(task));
 
     (v, _shared_key, address.into())
 }
@@ -217,9 +178,8 @@ pub fn erc20_deployment_without_ptt_to_addr(port: &'static str, _address: &str)
     let (encrypted_callable, encrypted_args) = encrypt_args(&args_deploy, fn_deploy, shared_key);
     let gas_limit = 100_000_000;
 
-    let msg = get_deploy_msg(&pre_code, &encrypted_args.to_hex(),
-                             &encrypted_callable.to_hex(), &user_pubkey.to_hex(), gas_limit, _address);
-    conn_and_call_ipc(&msg.to_string(), port)
+    let task = ipc_task(Some(pre_code), &encrypted_callable, &encrypted_args, &user_pubkey, gas_limit, _address);
+    response(client(port).deploy_secret_contract(task))
 }
 
 pub fn full_simple_deployment(port: &'static str) -> (Value, [u8; 32]) {
@@ -236,9 +196,8 @@ pub fn full_simple_deployment(port: &'static str) -> (Value, [u8; 32]) {
     let (encrypted_callable, encrypted_args) = encrypt_args(&args_deploy, fn_deploy, shared_key);
     let gas_limit = 100_000_000;
 
-    let msg = get_deploy_msg(&pre_code, &encrypted_args.to_hex(),
-                             &encrypted_callable.to_hex(), &user_pubkey.to_hex(), gas_limit, &address.to_hex());
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let task = ipc_task(Some(pre_code), &encrypted_callable, &encrypted_args, &user_pubkey, gas_limit, &address.to_hex());
+    let v: Value = response(client(port).deploy_secret_contract(task));
 
     (v, address.into())
 }
@@ -275,16 +234,14 @@ pub fn contract_compute(port: &'static str,  contract_addr: [u8; 32], args: &[To
     // WUKE- get the arguments encryption key
     let (shared_key, user_pubkey) = produce_shared_key(port);
 
-    let task_id: String = generate_contract_address().to_hex();
     let (encrypted_callable, encrypted_args) = encrypt_args(args, callable, shared_key);
     let gas_limit = 100_000_000;
 
-    let msg = get_compute_msg(&task_id, &encrypted_callable.to_hex(), &encrypted_args.to_hex(),
-                              &user_pubkey.to_hex(), gas_limit, &contract_addr.to_hex());
-    (conn_and_call_ipc(&msg.to_string(), port), shared_key)
+    let task = ipc_task(None, &encrypted_callable, &encrypted_args, &user_pubkey, gas_limit, &contract_addr.to_hex());
+    (response(client(port).compute_task(task)), shared_key)
 }
 
-fn encrypt_args( args:&[Token], callable: &str, key: [u8;32]) -> (Vec<u8>, Vec<u8>) {
+pub fn encrypt_args( args:&[Token], callable: &str, key: [u8;32]) -> (Vec<u8>, Vec<u8>) {
     (symmetric::encrypt(callable.as_bytes(), &key).unwrap(),
      symmetric::encrypt(&ethabi::encode(args), &key).unwrap())
 }
@@ -327,25 +284,22 @@ pub fn deploy_and_compute_few_contracts(port: &'static str) -> Vec<[u8; 32]> {
 }
 
 pub fn send_update_contract(port: &'static str,  addr: &str, bytecode: Vec<u8>) -> Value {
-    let msg = get_msg_format_update_contract(addr, bytecode);
-    conn_and_call_ipc(&msg.to_string(), port)
+    response(client(port).update_new_contract(addr, bytecode))
 }
 
 pub fn send_update_contract_on_deployment(port: &'static str,  addr: &str, bytecode: &str, delta: &(String, u64, Vec<u8>)) -> Value {
-    let msg = get_msg_format_update_contract_on_deployment(addr, bytecode, delta);
-    conn_and_call_ipc(&msg.to_string(), port)
+    let delta = ipc_deltas(&[delta.clone()]).pop().unwrap();
+    response(client(port).update_new_contract_on_deployment(addr, bytecode, delta))
 }
 
-pub fn get_remove_contract_msg(addr: &str) -> Value {
-    json!({"id": &generate_job_id(), "type": "RemoveContract", "address": addr})
+pub fn send_update_deltas(port: &'static str, deltas: &[(String, u64, Vec<u8>)]) -> Value {
+    response(client(port).update_deltas(ipc_deltas(deltas), false))
 }
 
 pub fn remove_contract(port: &'static str, addr: &str) -> Value {
-    let msg = get_remove_contract_msg(addr);
-    conn_and_call_ipc(&msg.to_string(), port)
+    response(client(port).remove_contract(addr))
 }
 
 pub fn remove_deltas(port: &'static str, input: &[(String, u64, u64)]) -> Value {
-    let msg = deltas_msg(input, "RemoveDeltas");
-    conn_and_call_ipc(&msg.to_string(), port)
+    response(client(port).remove_deltas(ipc_ranges(input)))
 }
@@ -4,17 +4,23 @@ pub extern crate ethabi;
 extern crate rustc_hex as hex;
 extern crate cross_test_utils;
 extern crate enigma_types;
+extern crate enigma_tools_m;
 
-use integration_utils::{conn_and_call_ipc, is_hex, run_core, get_encryption_msg, full_simple_deployment,
-                        send_update_contract, run_ptt_round, contract_compute, get_update_deltas_msg,
+use integration_utils::{client, response, is_hex, run_core, full_simple_deployment,
+                        send_update_contract, run_ptt_round, contract_compute, send_update_deltas,
                         decrypt_addr_delta, encrypt_addr_delta, replace_previous_hash_in_delta_data,
-                        full_supply_compute, full_addition_compute, decrypt_output_to_uint};
+                        full_supply_compute, full_addition_compute, decrypt_output_to_uint,
+                        produce_shared_key_with, encrypt_args, ipc_task};
+use app::networking::client::{open_output_envelope, ClientError};
+use app::networking::messages::{IpcComputeAttested, IpcTask};
+use enigma_tools_m::envelope::OutputEnvelope;
+use enigma_tools_m::signable::Signable;
 use cross_test_utils::generate_contract_address;
 use self::app::serde_json;
 use app::serde_json::*;
 use hex::{ToHex, FromHex};
 use integration_utils::ethabi::{Token};
-use integration_utils::enigma_crypto::{asymmetric::KeyPair, hash::Keccak256};
+use integration_utils::enigma_crypto::{asymmetric::{is_canonical, KeyPair}, hash::Keccak256};
 
 #[test]
 fn test_new_task_encryption_key(){
@@ -22,9 +28,7 @@ fn test_new_task_encryption_key(){
     run_core(port);
 
     let keys = KeyPair::new().unwrap();
-    let msg = get_encryption_msg(keys.get_pubkey());
-
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let v: Value = response(client(port).new_task_encryption_key(&keys.get_pubkey().to_hex()));
     let result_key = v["result"]["workerEncryptionKey"].as_str().unwrap();
     let result_sig = v["result"]["workerSig"].as_str().unwrap();
 
@@ -43,6 +47,9 @@ fn test_deploy_secret_contract() {
     let sig = res["result"]["signature"].as_str().unwrap();
     assert_eq!("DeploySecretContract", type_res);
     assert!(is_hex(sig));
+    let mut sig_arr = [0u8; 65];
+    sig_arr.copy_from_slice(&sig.from_hex().unwrap());
+    assert!(is_canonical(&sig_arr));
     assert!(accepted_used_gas > 0);
 }
 
@@ -61,6 +68,73 @@ fn test_compute_task() {
     assert_eq!("ComputeTask", type_accepted);
 }
 
+#[test]
+fn test_compute_output_envelope() {
+    let port = "5594";
+    run_core(port);
+    let (_, contract_addr): (_, [u8; 32]) = full_simple_deployment(port);
+
+    // The user's key is known, only its holder can open the envelope.
+    let user = KeyPair::from_slice(&[0x11; 32]).unwrap();
+    let shared_key = produce_shared_key_with(port, &user);
+    let (callable, args) = encrypt_args(&[Token::Uint(24.into()), Token::Uint(67.into())], "addition(uint,uint)", shared_key);
+    let task = IpcTask { output_envelope: true, ..ipc_task(None, &callable, &args, &user.get_pubkey(), 100_000_000, &contract_addr.to_hex()) };
+    let res = response(client(port).compute_task(task));
+    assert_eq!(res["type"], "ComputeTask");
+
+    let encrypted_output = open_output_envelope(&res, &user).unwrap();
+    assert_eq!(res["result"]["output"], encrypted_output.to_hex());
+    assert_eq!(decrypt_output_to_uint(&encrypted_output, &shared_key).to_uint().unwrap().as_u64(), 24 + 67);
+
+    // The enclave signed the envelope hash with the rest of the receipt.
+    let registration = response(client(port).get_registration_params());
+    let mut signer = [0u8; 20];
+    signer.copy_from_slice(&registration["result"]["signingKey"].as_str().unwrap().from_hex().unwrap());
+    let mut sig = [0u8; 65];
+    sig.copy_from_slice(&res["result"]["signature"].as_str().unwrap().from_hex().unwrap());
+    let attested: IpcComputeAttested = serde_json::from_value(res["result"]["attested"].clone()).unwrap();
+    assert!(attested.envelope_hash.is_some());
+    assert!(attested.to_receipt().unwrap().verify(&sig, &signer).unwrap());
+
+    // A bit flipped in the envelope: the MAC check fails, and so does the check against the receipt.
+    let mut bytes: Vec<u8> = res["result"]["envelope"].as_str().unwrap().from_hex().unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    let tampered = OutputEnvelope::from_bytes(&bytes).unwrap();
+    assert!(tampered.open(&user).is_err());
+    let mut tampered_res = res.clone();
+    tampered_res["result"]["envelope"] = json!(bytes.to_hex());
+    match open_output_envelope(&tampered_res, &user) {
+        Err(ClientError::Envelope(_)) => (),
+        other => panic!("Expected an envelope error, got {:?}", other),
+    }
+    // With the hash replaced too, it's the signature that doesn't match anymore.
+    let swapped = IpcComputeAttested { envelope_hash: Some(tampered.hash().to_hex()), ..attested };
+    assert!(!swapped.to_receipt().unwrap().verify(&sig, &signer).unwrap());
+    // Nobody else opens it.
+    assert!(open_output_envelope(&res, &KeyPair::new().unwrap()).is_err());
+}
+
+#[test]
+fn test_epoch_in_task_round_trip() {
+    let port = "5590";
+    run_core(port);
+
+    let mut client = client(port);
+    let commitment: String = [0x42u8; 32].to_hex();
+    assert_eq!(response(client.set_epoch_params(12, 1200, Some(commitment.clone())))["result"]["status"], "ok");
+
+    let ptt = response(client.get_ptt_request());
+    assert_eq!(ptt["result"]["epochNonce"], 12);
+    assert_eq!(ptt["result"]["seedCommitment"], commitment);
+
+    // The PTT round, the deployment and the task all see the same epoch.
+    let (res, _, _) = full_addition_compute(port, 1, 2);
+    assert_eq!(res["type"], "ComputeTask");
+    assert_eq!(res["result"]["epochNonce"], ptt["result"]["epochNonce"]);
+    assert_eq!(res["result"]["seedCommitment"], ptt["result"]["seedCommitment"]);
+}
+
 #[test]
 fn test_compute_task_no_delta() {
     let port =  "5560";
@@ -70,12 +144,12 @@ fn test_compute_task_no_delta() {
     let (res, key, _): (Value, [u8;32], _) = full_supply_compute(port, supply);
 
     let output: String = serde_json::from_value(res["result"]["output"].clone()).unwrap();
-    let delta = res["result"]["delta"].as_object().unwrap();
     let type_accepted = res["type"].as_str().unwrap();
     let accepted_supply: Token = decrypt_output_to_uint(&output.from_hex().unwrap(), &key);
 
-    // check that the data field does not exist in the delta object.
-    assert!(delta.keys().find(|&k| *k == "data").is_none());
+    // A task that doesn't change the state has an explicit null delta.
+    assert!(res["result"].as_object().unwrap().contains_key("delta"));
+    assert!(res["result"]["delta"].is_null());
     assert_eq!(accepted_supply.to_uint().unwrap().as_u64(), supply);
     assert_eq!("ComputeTask", type_accepted);
 }
@@ -112,8 +186,7 @@ fn test_execute_on_existing_contract_with_constructor() {
         (new_addr.to_hex(), delta0["key"].as_u64().unwrap(), encrypted_delta0_data_new),
         (new_addr.to_hex(),add_delta["key"].as_u64().unwrap(), encrypted_delta1_data_new)
     ];
-    let msg = get_update_deltas_msg(&deltas);
-    let _update_deltas_res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let _update_deltas_res: Value = send_update_deltas(port, &deltas);
 
     let _res_b = run_ptt_round(port, vec![new_addr]);
 
@@ -4,7 +4,7 @@ extern crate cross_test_utils;
 extern crate rustc_hex;
 extern crate ethabi;
 
-use integration_utils::{get_simple_msg_format, conn_and_call_ipc, is_hex, run_core, erc20_deployment_without_ptt_to_addr,
+use integration_utils::{client, response, is_hex, run_core, erc20_deployment_without_ptt_to_addr,
                         run_ptt_round, contract_compute, full_simple_deployment, full_erc20_deployment};
 use cross_test_utils::generate_contract_address;
 use rustc_hex::{ToHex, FromHex};
@@ -20,8 +20,7 @@ fn test_registration_params() {
 
     run_core(port);
     let type_req = "GetRegistrationParams";
-    let msg = get_simple_msg_format(type_req);
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let v: Value = response(client(port).get_registration_params());
 
     let result_key = v["result"]["signingKey"].as_str().unwrap();
     let result_rep= v["result"]["report"].as_str().unwrap();
@@ -34,6 +33,29 @@ fn test_registration_params() {
     assert!(is_hex(result_sig));
 }
 
+#[test]
+fn test_registration_history() {
+    let port = "5582";
+    run_core(port);
+    let mut client = client(port);
+    let registrations: Vec<Value> = (0..2).map(|_| response(client.get_registration_params())).collect();
+
+    let v: Value = response(client.get_registration_history(None));
+    assert_eq!(v["type"].as_str().unwrap(), "GetRegistrationHistory");
+    let history = v["result"]["registrationHistory"].as_array().unwrap();
+    assert_eq!(history.len(), 2);
+    // Newest first.
+    for (entry, registration) in history.iter().zip(registrations.iter().rev()) {
+        assert_eq!(entry["report"], registration["result"]["report"]);
+        assert_eq!(entry["signature"], registration["result"]["signature"]);
+        assert_eq!(entry["signingKey"], registration["result"]["signingKey"]);
+        assert!(is_hex(entry["mrEnclave"].as_str().unwrap()));
+    }
+    assert_eq!(history[0]["index"], 1);
+    assert_eq!(history[1]["index"], 0);
+    assert!(history[0]["timestamp"].as_u64().unwrap() >= history[1]["timestamp"].as_u64().unwrap());
+}
+
 #[test]
 fn test_deploy_with_no_ptt() {
     let port = "5575";
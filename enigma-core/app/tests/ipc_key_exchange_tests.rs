@@ -3,8 +3,7 @@ pub extern crate enigma_core_app as app;
 pub extern crate cross_test_utils;
 extern crate rustc_hex as hex;
 
-use integration_utils::{conn_and_call_ipc, is_hex, run_core, run_ptt_round,
-                        get_ptt_req_msg, parse_packed_msg};
+use integration_utils::{client, response, is_hex, run_core, run_ptt_round, parse_packed_msg};
 use self::cross_test_utils::{generate_contract_address};
 use self::app::serde_json;
 use app::serde_json::*;
@@ -14,8 +13,7 @@ fn test_get_ptt_request() {
     let port = "5558";
     run_core(port);
 
-    let msg = get_ptt_req_msg();
-    let v: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let v: Value = response(client(port).get_ptt_request());
 
     let packed_msg = v["result"]["request"].as_str().unwrap();
     let result_sig = v["result"]["workerSig"].as_str().unwrap();
@@ -34,4 +32,29 @@ fn test_ptt_response() {
 
     let errors: Vec<u8> = serde_json::from_value(res_val["result"]["errors"].clone()).unwrap();
     assert_eq!(errors.len(), 0);
-}
\ No newline at end of file
+}
+#[test]
+fn test_get_ptt_request_at_block() {
+    let port = "5592";
+    run_core(port);
+    let mut client = client(port);
+    assert_eq!(response(client.set_epoch_params(1, 100, None))["result"]["status"], "ok");
+    assert_eq!(response(client.set_epoch_params(2, 200, None))["result"]["status"], "ok");
+
+    // The current epoch, and the one before it.
+    for &(block, nonce) in &[(250, 2), (200, 2), (150, 1)] {
+        let v = response(client.get_ptt_request_at(block));
+        assert_eq!(v["result"]["epochNonce"], nonce, "block {}", block);
+        let msg = parse_packed_msg(v["result"]["request"].as_str().unwrap());
+        assert_eq!(msg["block_number"], block);
+        assert!(is_hex(v["result"]["workerSig"].as_str().unwrap()));
+    }
+    let v = response(client.get_ptt_request());
+    assert_eq!(v["result"]["epochNonce"], 2);
+    assert!(parse_packed_msg(v["result"]["request"].as_str().unwrap()).get("block_number").is_none());
+
+    let v = response(client.get_ptt_request_at(50));
+    assert_eq!(v["type"], "Error");
+    assert_eq!(v["details"], json!({ "code": "UnknownEpoch", "blockNumber": 50, "earliestBlock": 100 }));
+    assert_eq!(v["retryable"], false);
+}
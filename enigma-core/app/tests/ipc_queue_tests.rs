@@ -0,0 +1,117 @@
+pub mod integration_utils;
+
+pub extern crate enigma_core_app as app;
+extern crate futures;
+
+use app::common_u::metrics::METRICS;
+use app::networking::ipc_listener::{self, HealthProbe};
+use app::networking::IpcListener;
+use app::serde_json::*;
+use futures::Future;
+use integration_utils::{client, create_test_db, response};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HANDLER_DELAY_MS: u64 = 500;
+
+/// A core that takes `HANDLER_DELAY_MS` to answer every request, so a few concurrent ones saturate it.
+fn run_slow_core(port: &'static str, capacity: usize) {
+    thread::spawn(move || {
+        let (mut db, _datadir) = create_test_db();
+        let probe = HealthProbe::new(&db);
+        IpcListener::new(&format!("tcp://*:{}", port))
+            .serve(capacity, probe, move |multi| {
+                thread::sleep(Duration::from_millis(HANDLER_DELAY_MS));
+                // Only DB reads are sent, the enclave isn't needed.
+                ipc_listener::handle_message(&mut db, multi, "", 0, 0)
+            })
+            .wait()
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_sheds_load_when_saturated() {
+    let port = "5583";
+    let capacity = 2;
+    let delay = Duration::from_millis(HANDLER_DELAY_MS);
+    run_slow_core(port, capacity);
+
+    let clients: Vec<_> = (0..10)
+        .map(|_| {
+            thread::spawn(move || {
+                let start = Instant::now();
+                let res = response(client(port).get_all_tips());
+                (res, start.elapsed())
+            })
+        })
+        .collect();
+
+    // Monitoring still gets through while the handler is saturated.
+    thread::sleep(delay / 2);
+    let start = Instant::now();
+    let health = response(client(port).get_health());
+    assert_eq!(health["type"], "GetHealth");
+    assert!(start.elapsed() < delay);
+
+    let results: Vec<(Value, Duration)> = clients.into_iter().map(|client| client.join().unwrap()).collect();
+    let (busy, handled): (Vec<_>, Vec<_>) = results.iter().partition(|(res, _)| res["type"] == "Error");
+
+    // One request is handled and `capacity` wait, whatever the load, the rest never takes any memory.
+    assert!(handled.len() <= capacity + 1);
+    assert!(handled.iter().all(|(res, _)| res["type"] == "GetAllTips"));
+    assert!(!busy.is_empty());
+    for (res, elapsed) in &busy {
+        assert_eq!(res["details"]["code"], "Busy");
+        assert_eq!(res["retryable"], true);
+        assert!(res["retryAfterMs"].as_u64().is_some());
+        // Refused right away, not after waiting behind the others.
+        assert!(*elapsed < delay, "Busy took {:?}", elapsed);
+    }
+    assert!(METRICS.shed_count() >= busy.len() as u64);
+}
+
+#[test]
+fn test_drain_and_resume() {
+    let port = "5584";
+    let delay = Duration::from_millis(HANDLER_DELAY_MS);
+    run_slow_core(port, 4);
+    let write = move |addr: &str| response(client(port).update_new_contract(addr, vec![0, 97, 115, 109]));
+
+    let slow_write = thread::spawn(move || write(&"11".repeat(32)));
+    thread::sleep(delay / 2);
+
+    let mut client = client(port);
+    let drain = response(client.drain());
+    assert_eq!(drain["type"], "Drain");
+    assert_eq!(drain["result"]["draining"], true);
+    assert_eq!(drain["result"]["inFlight"], 1);
+
+    // New writes are refused right away, reads are still served.
+    let start = Instant::now();
+    let refused = write(&"22".repeat(32));
+    assert!(start.elapsed() < delay);
+    assert_eq!(refused["type"], "Error");
+    assert_eq!(refused["details"]["code"], "Draining");
+    assert_eq!(refused["retryable"], true);
+    let health = response(client.get_health());
+    assert_eq!(health["result"]["draining"], true);
+    assert_eq!(response(client.get_all_tips())["type"], "GetAllTips");
+
+    // The write accepted before the drain still completes.
+    let written = slow_write.join().unwrap();
+    assert_eq!(written["type"], "UpdateNewContract");
+    assert_eq!(written["result"]["status"], "ok");
+    let status = response(client.get_drain_status());
+    assert_eq!(status["result"]["draining"], true);
+    assert_eq!(status["result"]["inFlight"], 0);
+    assert!(METRICS.render().contains("enigma_draining 1"));
+
+    let resume = response(client.resume());
+    assert_eq!(resume["result"]["draining"], false);
+    let written = write(&"22".repeat(32));
+    assert_eq!(written["type"], "UpdateNewContract");
+    assert_eq!(written["result"]["status"], "ok");
+    let health = response(client.get_health());
+    assert!(health["result"].get("draining").is_none());
+}
@@ -1,8 +1,7 @@
 pub mod integration_utils;
 
 use integration_utils::{run_core, full_simple_deployment, deploy_and_compute_few_contracts,
-                        conn_and_call_ipc, get_msg_format_with_input, get_get_tips_msg, get_delta_msg,
-                        deltas_msg, get_simple_msg_format, decrypt_delta_to_value};
+                        client, response, ipc_ranges, decrypt_delta_to_value};
 pub extern crate enigma_core_app as app;
 extern crate serde;
 extern crate rustc_hex as hex;
@@ -10,6 +9,9 @@ extern crate rustc_hex as hex;
 use self::app::serde_json;
 use app::serde_json::*;
 use hex::{ToHex, FromHex};
+use app::networking::client::ClientOptions;
+use app::networking::messages::PROTOCOL_VERSION;
+use std::time::Duration;
 
 #[test]
 fn test_ipc_get_tip() {
@@ -18,8 +20,7 @@ fn test_ipc_get_tip() {
 
     let (_, contract_address): (_, [u8; 32]) = full_simple_deployment(port);
     let type_tip = "GetTip";
-    let msg = get_msg_format_with_input(type_tip, &contract_address.to_hex());
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_tip(&contract_address.to_hex()));
 
     let type_accepted = res["type"].as_str().unwrap();
     let _delta: Vec<u8> = serde_json::from_value(res["result"]["data"].clone()).unwrap();
@@ -29,6 +30,24 @@ fn test_ipc_get_tip() {
     assert_eq!(key, 0);
 }
 
+#[test]
+fn test_ipc_get_tip_bounds() {
+    let port =  "5593";
+    run_core(port);
+
+    let (_, contract_address): (_, [u8; 32]) = full_simple_deployment(port);
+    let res: Value = response(client(port).get_tip(&contract_address.to_hex()));
+    assert!(res["result"].get("bounds").is_none());
+
+    let options = ClientOptions { timeout: Duration::from_secs(30), retries: 0, protocol_version: Some(PROTOCOL_VERSION), ..Default::default() };
+    let mut client = client(port).with_options(options).unwrap();
+    let res: Value = response(client.get_tip(&contract_address.to_hex()));
+    assert_eq!(res["result"]["key"], 0);
+    assert_eq!(res["result"]["bounds"], json!({ "rawTip": 0, "usableTip": 0 }));
+    let res: Value = response(client.get_all_tips());
+    assert_eq!(res["result"]["tips"][0]["bounds"], json!({ "rawTip": 0, "usableTip": 0 }));
+}
+
 #[test]
 fn test_ipc_get_tips() {
     let port =  "5562";
@@ -38,8 +57,7 @@ fn test_ipc_get_tips() {
 
     let missing_addr = _addresses.pop().unwrap().to_hex();
     let _addresses: Vec<String> = _addresses.iter().map(|addr| addr.to_hex()).collect();
-    let _msg = get_get_tips_msg(&_addresses);
-    let res: Value = conn_and_call_ipc(&_msg.to_string(), port);
+    let res: Value = response(client(port).get_tips(_addresses));
 
     let tips = res["result"]["tips"].as_array().unwrap();
 
@@ -61,8 +79,7 @@ fn test_ipc_get_all_tips() {
     let _addresses = deploy_and_compute_few_contracts(port);
 
     let type_tips = "GetAllTips";
-    let msg = get_simple_msg_format(type_tips);
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_all_tips());
     let type_accepted = res["type"].as_str().unwrap();
     let tips = res["result"]["tips"].as_array().unwrap();
     assert_eq!(tips.len(), 3);
@@ -78,9 +95,7 @@ fn test_ipc_all_addrs() {
     run_core(port);
     let _addresses = deploy_and_compute_few_contracts(port);
     let addresses: Vec<String> = _addresses.iter().map(|addr| {addr.to_hex()}).collect();
-    let type_addrs = "GetAllAddrs";
-    let msg = get_simple_msg_format(type_addrs);
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_all_addrs(false));
     let _addrs = res["result"]["addresses"].as_array().unwrap();
     let addrs: Vec<String> = _addrs.iter().map(|addr| serde_json::from_value(addr.clone()).unwrap()).collect();
     assert!(addresses.iter().zip(addrs.iter()).all(|(expected, accepted)| expected == accepted));
@@ -93,8 +108,7 @@ fn test_ipc_get_delta() {
 
     let addresses = deploy_and_compute_few_contracts(port);
 
-    let msg = get_delta_msg(&addresses[1].to_hex(), 1);
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_delta(&addresses[1].to_hex(), 1));
     let delta_accepted = res["result"]["delta"].as_str().unwrap();
     let decrypted_delta = decrypt_delta_to_value(addresses[1], &delta_accepted.from_hex().unwrap());
     let add_result = decrypted_delta[0][0][2].as_u64().unwrap();
@@ -111,8 +125,7 @@ fn test_ipc_get_deltas() {
 
     // receives only delta 2 from address 1 and delta 1 from address 0
     let input = vec![(addresses[1].to_hex(), 1, 2), (addresses[0].to_hex(), 0, 1)];
-    let msg = deltas_msg(&input, "GetDeltas");
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_deltas(ipc_ranges(&input)));
     let deltas_accepted = res["result"]["deltas"].as_array().unwrap();
     let first_address = deltas_accepted[0]["address"].as_str().unwrap();
     let second_address = deltas_accepted[1]["address"].as_str().unwrap();
@@ -133,8 +146,7 @@ fn test_ipc_get_contract() {
     let (deployed_res, address) = full_simple_deployment(port);
 
     let type_msg = "GetContract";
-    let msg = get_msg_format_with_input(type_msg, &address.to_hex());
-    let res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let res: Value = response(client(port).get_contract(&address.to_hex(), None, None));
     let type_accepted = res["type"].as_str().unwrap();
     let accepted_bytecode: Vec<u8> = serde_json::from_value(res["result"]["bytecode"].clone()).unwrap();
     let deployed_bytecode = deployed_res["result"]["output"].as_str().unwrap();
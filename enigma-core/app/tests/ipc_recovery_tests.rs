@@ -0,0 +1,47 @@
+pub mod integration_utils;
+
+use integration_utils::{run_core, full_simple_deployment, client, response, send_update_contract, contract_compute,
+                        mock_principal_res};
+pub extern crate enigma_core_app as app;
+extern crate rustc_hex as hex;
+
+use app::serde_json::*;
+use hex::{ToHex, FromHex};
+use integration_utils::cross_test_utils::generate_contract_address;
+
+fn recovery_progress(port: &'static str) -> Value {
+    let health: Value = response(client(port).get_health());
+    health["result"]["recovery"].clone()
+}
+
+#[test]
+fn test_recover_keys() {
+    let port = "5581";
+    run_core(port);
+    assert!(recovery_progress(port).is_null());
+
+    // The enclave has the key of the deployed contract, but not of the one that was only copied to the DB.
+    let (deployed_res, deployed_addr) = full_simple_deployment(port);
+    let deployed_bytecode = deployed_res["result"]["output"].as_str().unwrap();
+    let lost_addr = generate_contract_address();
+    send_update_contract(port, &lost_addr.to_hex(), deployed_bytecode.from_hex().unwrap());
+
+    let res: Value = response(client(port).recover_keys(None));
+    assert_eq!(res["type"], "RecoverKeys");
+    let request = res["result"]["request"].as_str().unwrap();
+    assert_eq!(recovery_progress(port), json!({ "provisioned": 0, "total": 2 }));
+
+    let (res, _) = contract_compute(port, deployed_addr, &[], "get_last_sum()");
+    assert_eq!(res["type"], "Error");
+    assert_eq!(res["details"], json!({ "code": "Recovering", "provisioned": 0, "total": 2 }));
+    assert_eq!(res["retryable"], true);
+    assert!(res["retryAfterMs"].as_u64().is_some());
+
+    let principal_res = mock_principal_res(request, vec![lost_addr]);
+    let res: Value = response(client(port).ptt_response(&principal_res.to_hex()));
+    assert_eq!(res["type"], "PTTResponse");
+    assert_eq!(recovery_progress(port), json!({ "provisioned": 2, "total": 2 }));
+
+    let (res, _) = contract_compute(port, deployed_addr, &[], "get_last_sum()");
+    assert_eq!(res["type"], "ComputeTask");
+}
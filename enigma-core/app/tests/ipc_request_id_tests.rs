@@ -0,0 +1,52 @@
+pub mod integration_utils;
+
+pub extern crate enigma_core_app as app;
+extern crate rand;
+
+use app::networking::request_id::MAX_ID_LEN;
+use app::networking::messages::IpcRequest;
+use app::networking::wire_fixtures;
+use integration_utils::{client, response, run_core};
+use rand::Rng;
+
+// Printable characters of 1 to 4 bytes, and the ones JSON has to escape.
+const CHARS: &[char] = &['a', 'Z', '0', '-', ' ', '"', '\\', '/', '{', '\'', 'é', 'ж', '中', '€', '\u{1F600}'];
+
+/// A valid id of 1 to `MAX_ID_LEN` bytes.
+fn random_id<R: Rng>(rng: &mut R) -> String {
+    let len = rng.gen_range(1, MAX_ID_LEN + 1);
+    let mut id = String::new();
+    loop {
+        let c = if rng.gen() { CHARS[rng.gen_range(0, CHARS.len())] } else { rng.gen_range(0x20u8, 0x7f) as char };
+        if id.len() + c.len_utf8() > len {
+            break;
+        }
+        id.push(c);
+    }
+    if id.is_empty() {
+        id.push('x');
+    }
+    id
+}
+
+#[test]
+fn test_ids_echoed() {
+    let port = "5591";
+    run_core(port);
+    let mut client = client(port);
+    let mut rng = rand::thread_rng();
+    // Every request type, whether it succeeds or not.
+    for round in 0..3 {
+        for msg in wire_fixtures::requests() {
+            let id = random_id(&mut rng);
+            let res = response(client.send_with_id(msg.request.clone(), id.clone()));
+            assert_eq!(res["id"], id, "{} (round {})", msg.request.kind(), round);
+            assert!(res.get("invalid_id").is_none());
+        }
+    }
+
+    let res = response(client.send_with_id(IpcRequest::GetAllTips, String::new()));
+    assert_eq!(res["id"], "");
+    assert_eq!(res["invalid_id"], true);
+    assert_eq!(res["type"], "Error");
+}
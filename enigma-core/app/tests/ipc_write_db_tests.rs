@@ -1,7 +1,7 @@
 pub mod integration_utils;
 
-use integration_utils::{run_core, full_simple_deployment, conn_and_call_ipc,
-                        send_update_contract, get_update_deltas_msg, contract_compute,
+use integration_utils::{run_core, full_simple_deployment,
+                        send_update_contract, send_update_deltas, contract_compute,
                         send_update_contract_on_deployment, remove_contract, remove_deltas};
 pub extern crate enigma_core_app as app;
 extern crate serde;
@@ -24,10 +24,10 @@ fn test_ipc_update_contract() {
     let new_addr = generate_contract_address();
     let res: Value = send_update_contract(port, &new_addr.to_hex(), deployed_bytecode.from_hex().unwrap());
 
-    let updated: u64 = serde_json::from_value(res["result"]["status"].clone()).unwrap();
+    let updated = res["result"]["status"].as_str().unwrap();
     let updated_addr = res["address"].as_str().unwrap();
 
-    assert_eq!(updated, 0);
+    assert_eq!(updated, "ok");
     assert_eq!(updated_addr, new_addr.to_hex());
 }
 
@@ -38,10 +38,10 @@ fn test_ipc_remove_contract() {
 
     let (_, address) = full_simple_deployment(port);
     let res = remove_contract(port, &address.to_hex());
-    let status: u64 = serde_json::from_value(res["result"]["status"].clone()).unwrap();
+    let status = res["result"]["status"].as_str().unwrap();
     let accepted_addr: &str = res["address"].as_str().unwrap();
 
-    assert_eq!(status, 0);
+    assert_eq!(status, "ok");
     assert_eq!(accepted_addr, address.to_hex());
 }
 
@@ -52,10 +52,10 @@ fn test_ipc_remove_contract_no_addr() {
 
     let addr = generate_contract_address();
     let res = remove_contract(port, &addr.to_hex());
-    let status: u64 = serde_json::from_value(res["result"]["status"].clone()).unwrap();
+    let status = res["result"]["status"].as_str().unwrap();
     let accepted_addr: &str = res["address"].as_str().unwrap();
 
-    assert_eq!(status, 0);
+    assert_eq!(status, "not_found");
     assert_eq!(accepted_addr, addr.to_hex());
 }
 
@@ -70,10 +70,10 @@ fn test_ipc_update_contract_on_deployment() {
     let new_addr = generate_contract_address();
     let delta_to_update = (new_addr.to_hex(), deployed_delta["key"].as_u64().unwrap(), serde_json::from_value(deployed_delta["data"].clone()).unwrap());
     let res: Value = send_update_contract_on_deployment(port, &new_addr.to_hex(), deployed_bytecode, &delta_to_update);
-    let updated: i8 = serde_json::from_value(res["result"]["status"].clone()).unwrap();
+    let updated = res["result"]["status"].as_str().unwrap();
     let updated_addr = res["address"].as_str().unwrap();
 
-    assert_eq!(updated, 0);
+    assert_eq!(updated, "ok");
     assert_eq!(updated_addr, new_addr.to_hex());
 }
 
@@ -103,15 +103,14 @@ fn test_ipc_update_deltas() {
     deltas.push((new_addr_a.to_hex(), deployed_delta_a["key"].as_u64().unwrap(), serde_json::from_value(deployed_delta_a["data"].clone()).unwrap()));
     deltas.push((new_addr_b.to_hex(), serde_json::from_value(deployed_delta_b["key"].clone()).unwrap(), serde_json::from_value(deployed_delta_b["data"].clone()).unwrap()));
     deltas.push((new_addr_a.to_hex(), serde_json::from_value(computed_delta_a["key"].clone()).unwrap(), serde_json::from_value(computed_delta_a["data"].clone()).unwrap()));
-    let msg = get_update_deltas_msg(&deltas);
-    let update_deltas_res: Value = conn_and_call_ipc(&msg.to_string(), port);
+    let update_deltas_res: Value = send_update_deltas(port, &deltas);
 
-    let updated: u64 = serde_json::from_value(update_deltas_res["result"]["status"].clone()).unwrap();
+    let updated = update_deltas_res["result"]["status"].as_str().unwrap();
     let errors = update_deltas_res["result"].as_object().unwrap()["errors"].as_array().unwrap();
     for err in errors {
-        assert_eq!(err["status"].as_u64().unwrap(), 0);
+        assert_eq!(err["status"].as_str().unwrap(), "ok");
     }
-    assert_eq!(updated, 0);
+    assert_eq!(updated, "ok");
 }
 
 #[test]
@@ -133,7 +132,7 @@ fn test_ipc_remove_deltas() {
     input.push((address_b.to_hex(), deployed_delta_num_b, deployed_delta_num_b + 1));
     let res = remove_deltas(port, &input);
     let errors = res["result"]["errors"].as_array().unwrap();
-    let status = res["result"]["status"].as_u64().unwrap();
+    let status = res["result"]["status"].as_str().unwrap();
     assert_eq!(errors.len(), 0);
-    assert_eq!(status, 0);
+    assert_eq!(status, "ok");
 }
\ No newline at end of file
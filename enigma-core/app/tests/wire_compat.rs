@@ -0,0 +1,50 @@
+pub extern crate enigma_core_app as app;
+
+use std::fs;
+use std::path::PathBuf;
+
+use app::networking::messages::IpcMessageRequest;
+use app::networking::wire_fixtures::{self, Fixtures, REQUESTS_FILE, RESPONSES_FILE};
+use app::serde_json::{self, Value};
+
+// Every release that was checked in, oldest first.
+fn releases() -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/wire_fixtures");
+    let mut releases: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.is_dir()).collect();
+    releases.sort();
+    assert!(!releases.is_empty(), "No fixtures in {:?}", dir);
+    releases
+}
+
+fn read(path: PathBuf) -> Fixtures {
+    let fixtures = wire_fixtures::read(&path).unwrap_or_else(|e| panic!("Can't read {:?}: {}", path, e));
+    assert_eq!(Some(fixtures.version.as_str()), path.parent().unwrap().file_name().unwrap().to_str(), "{:?}", path);
+    fixtures
+}
+
+#[test]
+fn test_old_requests_parse() {
+    for release in releases() {
+        let fixtures = read(release.join(REQUESTS_FILE));
+        for message in fixtures.messages {
+            if let Err(e) = serde_json::from_value::<IpcMessageRequest>(message.clone()) {
+                panic!("The {} request {} doesn't parse anymore: {}", fixtures.version, message, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_responses_keep_old_format() {
+    let current: Vec<Value> = wire_fixtures::responses().iter().map(|response| serde_json::to_value(response).unwrap()).collect();
+    for release in releases() {
+        let fixtures = read(release.join(RESPONSES_FILE));
+        for old in &fixtures.messages {
+            let id = old["id"].as_str().unwrap();
+            let new = current.iter().find(|new| new["id"] == id)
+                .unwrap_or_else(|| panic!("The {} response {} isn't produced anymore", fixtures.version, id));
+            let errors = wire_fixtures::compatibility_errors(old, new, id, &fixtures.unstable);
+            assert!(errors.is_empty(), "The responses changed since {}:\n{}", fixtures.version, errors.join("\n"));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Audit log of the keys the enclave gets hold of, see `enigma_tools_m::audit`.
+//! The events are kept in memory, the count and head of the chain are sealed to disk every `SEAL_INTERVAL` events
+//! and on every export, so a restart continues the chain from the last sealed head.
+//! Events recorded after it are lost on a crash, the next export then starts from the sealed head.
+
+use crate::SIGNING_KEY;
+use enigma_crypto::hash::Keccak256;
+use enigma_tools_m::audit::{AuditEvent, AuditEventKind, AuditExport};
+use enigma_tools_m::signable::Signable;
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::common::errors_t::{EnclaveError::{self, SystemError}, EnclaveSystemError::MessagingError};
+use enigma_tools_t::document_storage_t::{is_document, load_sealed_document, save_sealed_document, SealedDocumentStorage, SEAL_LOG_SIZE};
+use enigma_tools_t::esgx::ocalls_t;
+use enigma_types::{ContractAddress, Hash256, PubKey};
+use sgx_types::sgx_status_t;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::string::ToString;
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+/// How many events can be lost on a crash.
+const SEAL_INTERVAL: u64 = 16;
+/// The events kept for the exports, older ones are folded into the base of the chain.
+const MAX_EVENTS: usize = 4096;
+const AUDIT_FILE: &str = "audit-log.sealed";
+
+extern "C" {
+    fn ocall_get_time(retval: *mut u64) -> sgx_status_t;
+}
+
+/// What is sealed, the head of the chain after `count` events.
+#[derive(Clone, Copy, Default)]
+struct Checkpoint {
+    count: u64,
+    head: Hash256,
+}
+
+struct AuditLog {
+    base_count: u64,
+    base: Hash256,
+    events: VecDeque<AuditEvent>,
+    sealed_count: u64,
+}
+
+lazy_static! { static ref AUDIT_LOG: SgxMutex<AuditLog> = SgxMutex::new(AuditLog::load()); }
+
+impl AuditLog {
+    fn path() -> Result<PathBuf, EnclaveError> { Ok(ocalls_t::get_home_path()?.join(AUDIT_FILE)) }
+
+    // A checkpoint that can't be read starts a new chain, an export shows it as a log that starts at 0 again.
+    fn load() -> Self {
+        let checkpoint = match Self::load_checkpoint() {
+            Ok(checkpoint) => checkpoint.unwrap_or_default(),
+            Err(e) => {
+                debug_println!("Failed loading the audit log checkpoint, starting a new chain: {}", e);
+                Checkpoint::default()
+            }
+        };
+        AuditLog { base_count: checkpoint.count, base: checkpoint.head, events: VecDeque::new(), sealed_count: checkpoint.count }
+    }
+
+    fn load_checkpoint() -> Result<Option<Checkpoint>, EnclaveError> {
+        let path = Self::path()?;
+        if !is_document(&path) {
+            return Ok(None);
+        }
+        let mut sealed_log = [0u8; SEAL_LOG_SIZE];
+        load_sealed_document(&path, &mut sealed_log)?;
+        Ok(SealedDocumentStorage::<Checkpoint>::unseal(&mut sealed_log)?.map(|doc| doc.data))
+    }
+
+    fn count(&self) -> u64 { self.base_count + self.events.len() as u64 }
+
+    fn head(&self) -> Hash256 { self.events.back().map(|event| event.chain).unwrap_or(self.base) }
+
+    fn seal(&mut self) -> Result<(), EnclaveError> {
+        let doc = SealedDocumentStorage { version: 1, data: Checkpoint { count: self.count(), head: self.head() } };
+        let mut sealed_log = [0u8; SEAL_LOG_SIZE];
+        doc.seal(&mut sealed_log)?;
+        save_sealed_document(&Self::path()?, &sealed_log)?;
+        self.sealed_count = self.count();
+        Ok(())
+    }
+
+    fn record(&mut self, kind: AuditEventKind, subject: Hash256) {
+        let event = AuditEvent::new(&self.head(), self.count(), kind, subject, host_time());
+        self.events.push_back(event);
+        if self.events.len() > MAX_EVENTS {
+            let folded = self.events.pop_front().unwrap();
+            self.base_count += 1;
+            self.base = folded.chain;
+        }
+        if self.count() - self.sealed_count >= SEAL_INTERVAL {
+            // The key was already derived, failing the call now wouldn't take it back.
+            if let Err(e) = self.seal() {
+                debug_println!("Failed sealing the audit log: {}", e);
+            }
+        }
+    }
+}
+
+fn host_time() -> u64 {
+    let mut time = 0u64;
+    match unsafe { ocall_get_time(&mut time as *mut u64) } {
+        sgx_status_t::SGX_SUCCESS => time,
+        _ => 0,
+    }
+}
+
+/// Records the state key of `address` the KM node provisioned.
+pub(crate) fn record_state_key(address: ContractAddress) { AUDIT_LOG.lock_expect("Audit Log").record(AuditEventKind::StateKey, address) }
+
+/// Records the DH key derived with the user of `user_pubkey`.
+pub(crate) fn record_user_key(user_pubkey: &PubKey) {
+    AUDIT_LOG.lock_expect("Audit Log").record(AuditEventKind::UserKey, user_pubkey[..].keccak256())
+}
+
+/// Seals the log and returns the serialized `AuditExport` with the signature of its `AuditDigest`.
+pub(crate) fn ecall_export_audit_digest_internal(sig: &mut [u8; 65]) -> Result<Vec<u8>, EnclaveError> {
+    let mut log = AUDIT_LOG.lock_expect("Audit Log");
+    log.seal()?;
+    let export = AuditExport { base_count: log.base_count, base: log.base, events: log.events.iter().cloned().collect() };
+    *sig = SIGNING_KEY.sign_recoverable(&export.digest().to_signable_bytes())?;
+    serde_json::to_vec(&export).map_err(|e| SystemError(MessagingError { err: e.to_string() }))
+}
+
+#[cfg(debug_assertions)]
+pub mod tests {
+    use super::*;
+
+    pub fn test_audit_chain() {
+        let mut sig = [0u8; 65];
+        let first: AuditExport = serde_json::from_slice(&ecall_export_audit_digest_internal(&mut sig).unwrap()).unwrap();
+        record_state_key([1u8; 32].into());
+        record_user_key(&[2u8; 64]);
+        let second: AuditExport = serde_json::from_slice(&ecall_export_audit_digest_internal(&mut sig).unwrap()).unwrap();
+
+        assert!(second.verify());
+        assert!(second.extends(&first.digest()));
+        assert_eq!(second.digest().count, first.digest().count + 2);
+        let last = second.events[second.events.len() - 1];
+        assert_eq!(last.kind, AuditEventKind::UserKey);
+        assert_eq!(last.subject, [2u8; 64][..].keccak256());
+    }
+}
@@ -29,6 +29,9 @@ extern "C" {
 extern "C" {
     pub fn ocall_save_to_memory(retval: *mut u64, data_ptr: *const u8, data_len: usize) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ocall_get_time(retval: *mut u64) -> sgx_status_t;
+}
 extern "C" {
     pub fn ocall_get_deltas_sizes(
         retval: *mut EnclaveReturn,
@@ -0,0 +1,113 @@
+//! Chunked transfer of the deploy bytecode into the enclave.
+//! A big contract is sent with `ecall_deploy_begin`, a few `ecall_deploy_chunk` and `ecall_deploy_finish`,
+//! so neither side has to copy the whole bytecode across the boundary at once.
+//! `finish` only hands out the bytecode if it's exactly `total_len` bytes and matches the keccak the caller gave,
+//! a lost, duplicated or reordered chunk fails the deployment instead of deploying something else.
+
+use enigma_crypto::hash::Keccak256;
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::common::errors_t::{EnclaveError::{self, SystemError}, EnclaveSystemError::MessagingError};
+use enigma_types::Hash256;
+use std::collections::HashMap;
+use std::string::ToString;
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+/// An upload that isn't finished is dropped when this many newer ones were started.
+const MAX_UPLOADS: usize = 4;
+/// The biggest bytecode that can be uploaded, it has to fit in the enclave heap (`HeapMaxSize`) next to the module built from it.
+pub(crate) const MAX_BYTECODE_LEN: usize = 16 * 1024 * 1024;
+
+struct Upload {
+    total_len: usize,
+    bytes: Vec<u8>,
+}
+
+lazy_static! {
+    static ref UPLOADS: SgxMutex<(u64, HashMap<u64, Upload>)> = SgxMutex::new((0, HashMap::new()));
+}
+
+fn upload_error(err: &str) -> EnclaveError { SystemError(MessagingError { err: err.to_string() }) }
+
+/// Starts an upload of `total_len` bytes and returns its handle.
+pub(crate) fn begin(total_len: usize) -> Result<u64, EnclaveError> {
+    if total_len == 0 || total_len > MAX_BYTECODE_LEN {
+        return Err(upload_error("Invalid bytecode length"));
+    }
+    let mut guard = UPLOADS.lock_expect("Uploads");
+    let (next_handle, uploads) = &mut *guard;
+    if uploads.len() >= MAX_UPLOADS {
+        // Handles only grow, so the smallest one is the oldest.
+        let oldest = *uploads.keys().min().unwrap();
+        uploads.remove(&oldest);
+        debug_println!("Dropped the unfinished upload {}", oldest);
+    }
+    *next_handle += 1;
+    uploads.insert(*next_handle, Upload { total_len, bytes: Vec::with_capacity(total_len) });
+    Ok(*next_handle)
+}
+
+/// Appends the next chunk, an upload that would grow past its length is dropped.
+pub(crate) fn chunk(handle: u64, data: &[u8]) -> Result<(), EnclaveError> {
+    let mut guard = UPLOADS.lock_expect("Uploads");
+    let uploads = &mut guard.1;
+    let upload = uploads.get_mut(&handle).ok_or_else(|| upload_error("Unknown upload handle"))?;
+    if upload.bytes.len() + data.len() > upload.total_len {
+        uploads.remove(&handle);
+        return Err(upload_error("The chunks are longer than the bytecode"));
+    }
+    upload.bytes.extend_from_slice(data);
+    Ok(())
+}
+
+/// Ends the upload and returns the assembled bytecode if it's complete and hashes to `expected_hash`.
+pub(crate) fn finish(handle: u64, expected_hash: &Hash256) -> Result<Vec<u8>, EnclaveError> {
+    let upload = UPLOADS.lock_expect("Uploads").1.remove(&handle).ok_or_else(|| upload_error("Unknown upload handle"))?;
+    if upload.bytes.len() != upload.total_len {
+        return Err(upload_error("The bytecode upload is truncated"));
+    }
+    if upload.bytes.keccak256() != *expected_hash {
+        return Err(upload_error("The uploaded bytecode doesn't match its hash"));
+    }
+    Ok(upload.bytes)
+}
+
+#[cfg(debug_assertions)]
+pub mod tests {
+    use super::*;
+
+    fn upload(chunks: &[&[u8]]) -> u64 {
+        let handle = begin(chunks.iter().map(|c| c.len()).sum()).unwrap();
+        for c in chunks {
+            chunk(handle, c).unwrap();
+        }
+        handle
+    }
+
+    pub fn test_upload_assembles() {
+        let bytecode: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let handle = upload(&[&bytecode[..300], &bytecode[300..301], &bytecode[301..]]);
+        assert_eq!(finish(handle, &bytecode.keccak256()).unwrap(), bytecode);
+        // The handle can't be reused.
+        assert!(finish(handle, &bytecode.keccak256()).is_err());
+    }
+
+    pub fn test_upload_integrity() {
+        let bytecode: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let hash = bytecode.keccak256();
+
+        let reordered = upload(&[&bytecode[500..], &bytecode[..500]]);
+        assert!(finish(reordered, &hash).is_err());
+
+        let truncated = begin(bytecode.len()).unwrap();
+        chunk(truncated, &bytecode[..999]).unwrap();
+        assert!(finish(truncated, &hash).is_err());
+
+        let overflow = begin(10).unwrap();
+        assert!(chunk(overflow, &bytecode[..11]).is_err());
+        assert!(chunk(overflow, &bytecode[..1]).is_err());
+
+        assert!(begin(0).is_err());
+        assert!(begin(MAX_BYTECODE_LEN + 1).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 pub(crate) mod principal;
 pub(crate) mod users;
 
-pub(crate) use self::principal::{ecall_build_state_internal, ecall_ptt_req_internal, ecall_ptt_res_internal};
+pub(crate) use self::principal::{ecall_build_state_internal, ecall_get_provisioned_addresses_internal, ecall_ptt_req_internal, ecall_ptt_res_internal};
 pub(crate) use self::users::ecall_get_user_key_internal;
 
 use enigma_runtime_t::data::{ContractState, EncryptedContractState};
@@ -1,4 +1,5 @@
 use super::STATE_KEYS;
+use crate::audit_log;
 use crate::SIGNING_KEY;
 use enigma_runtime_t::data::{ContractState, DeltasInterface};
 use enigma_runtime_t::ocalls_t as runtime_ocalls_t;
@@ -18,18 +19,21 @@ lazy_static! {
     pub static ref DH_KEYS: SgxMutex<HashMap<MsgID, KeyPair>> = SgxMutex::new(HashMap::new());
 }
 
-pub(crate) unsafe fn ecall_ptt_req_internal(sig: &mut [u8; 65]) -> Result<Vec<u8>, EnclaveError> {
+/// The block number is part of the signed message, so the principal can't be made to answer for another epoch.
+pub(crate) unsafe fn ecall_ptt_req_internal(block_number: Option<u64>, sig: &mut [u8; 65]) -> Result<Vec<u8>, EnclaveError> {
     let keys = KeyPair::new()?;
     let data = PrincipalMessageType::Request;
-    let req = PrincipalMessage::new(data, keys.get_pubkey())?;
+    let req = PrincipalMessage::new_at_block(data, keys.get_pubkey(), block_number)?;
     let id = req.get_id();
-    *sig = SIGNING_KEY.sign(&req.to_sign()?)?;
+    *sig = SIGNING_KEY.sign_recoverable(&req.to_sign()?)?;
     let msg = req.into_message()?;
     DH_KEYS.lock_expect("DH Keys").insert(id, keys);
     Ok(msg)
 }
 
-pub(crate) fn ecall_ptt_res_internal(msg_slice: &[u8]) -> Result<(), EnclaveError> {
+/// Keeps the keys of the response, only those of the contracts in `hosted` if it's given.
+/// The KM node sends every key of the worker, so a worker hosting a few contracts drops the others here.
+pub(crate) fn ecall_ptt_res_internal(msg_slice: &[u8], hosted: Option<&[ContractAddress]>) -> Result<(), EnclaveError> {
     let res = PrincipalMessage::from_message(msg_slice)?;
 
     let mut guard = DH_KEYS.lock_expect("DH Keys");
@@ -42,7 +46,11 @@ pub(crate) fn ecall_ptt_res_internal(msg_slice: &[u8]) -> Result<(), EnclaveErro
     }
     if let PrincipalMessageType::Response(v) = msg.data {
         for (addr, key) in v {
+            if hosted.map_or(false, |hosted| !hosted.contains(&addr)) {
+                continue;
+            }
             STATE_KEYS.lock_expect("state keys").insert(addr, key);
+            audit_log::record_state_key(addr);
         }
     } else {
         unreachable!() // This should never execute. // TODO: Replace with an error.
@@ -51,6 +59,11 @@ pub(crate) fn ecall_ptt_res_internal(msg_slice: &[u8]) -> Result<(), EnclaveErro
     Ok(())
 }
 
+/// The addresses of all the contracts the enclave holds a state key for.
+pub(crate) fn ecall_get_provisioned_addresses_internal() -> Vec<ContractAddress> {
+    STATE_KEYS.lock_expect("State Keys").keys().copied().collect()
+}
+
 pub(crate) unsafe fn ecall_build_state_internal(db_ptr: *const RawPointer) -> Result<Vec<ContractAddress>, EnclaveError> {
     let guard = STATE_KEYS.lock_expect("State Keys");
     let mut failed_contracts = Vec::with_capacity(guard.len());
@@ -160,8 +173,9 @@ pub mod tests {
         runtime_ocalls_t::save_state(db_ptr, &gibrish_state).unwrap();
         // Generating the request
         let mut _sig = [0u8; 65];
-        let req_msg = ecall_ptt_req_internal(&mut _sig).unwrap();
+        let req_msg = ecall_ptt_req_internal(Some(250), &mut _sig).unwrap();
         let req_obj = PrincipalMessage::from_message(&req_msg).unwrap();
+        assert_eq!(req_obj.get_block_number(), Some(250));
 
         // Mimicking the Principal/KM Node
         let km_node_keys = KeyPair::new().unwrap();
@@ -174,7 +188,7 @@ pub mod tests {
         let enc_res_slice = enc_req.into_message().unwrap();
 
         // Enclave Process Response
-        ecall_ptt_res_internal(&enc_res_slice).unwrap();
+        ecall_ptt_res_internal(&enc_res_slice, None).unwrap();
 
         // Initiate the building
         assert_eq!(ecall_build_state_internal(db_ptr).unwrap(), vec![address[2]])
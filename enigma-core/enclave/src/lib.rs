@@ -24,38 +24,50 @@ extern crate serde_json;
 #[macro_use]
 extern crate lazy_static;
 
+mod audit_log;
+mod deploy_upload;
 mod km_t;
+mod replay_key;
 
 use crate::{
-    km_t::{ecall_build_state_internal, ecall_get_user_key_internal, ecall_ptt_req_internal, ecall_ptt_res_internal},
+    km_t::{ecall_build_state_internal, ecall_get_provisioned_addresses_internal, ecall_get_user_key_internal, ecall_ptt_req_internal,
+           ecall_ptt_res_internal},
 };
-use enigma_crypto::{asymmetric, hash::Keccak256, symmetric, CryptoError};
+use enigma_crypto::{asymmetric, hash::Keccak256, symmetric, CryptoError, Encryption};
 use enigma_runtime_t::{
-    data::{ContractState, EncryptedPatch},
+    data::{ContractState, EncryptedPatch, StatePatch},
     wasm_execution::WasmEngine,
     EthereumData,
 };
+use enigma_tools_m::envelope::OutputEnvelope;
+use enigma_tools_m::signable::{DeployReceipt, ExecuteReceipt, FailureReceipt, Signable};
+use enigma_tools_m::trace::ExecutionTrace;
 use enigma_tools_m::utils::{EthereumAddress, LockExpectMutex};
 use enigma_tools_t::{
     build_arguments_g::*,
     common::errors_t::{
         EnclaveError::{self, *},
+        EnclaveSystemError::{MessagingError, StateError},
         FailedTaskError::*,
     },
     esgx::ocalls_t,
     quote_t, storage_t,
 };
 use enigma_types::{
-    ContractAddress, DhKey, EnclaveReturn, ExecuteResult, Hash256, PubKey, RawPointer, ResultStatus,
+    ContractAddress, DhKey, EnclaveReturn, ExecuteResult, Hash256, PubKey, RawPointer, ReplayReceipt, ReplayResult,
 };
 
 use sgx_types::*;
 use std::{
-    boxed::Box, slice, str,
-    string::String,
+    mem, slice, str,
+    string::{String, ToString},
+    sync::atomic::{AtomicU64, Ordering},
     vec::Vec,
 };
 
+// The chain id the node was configured for, it's part of the registration report data. 0 means unset.
+static NETWORK_ID: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     pub(crate) static ref SIGNING_KEY: asymmetric::KeyPair = get_sealed_keys_wrapper();
     pub(crate) static ref ETHEREUM_KEY: asymmetric::KeyPair = get_ethereum_keys_wrapper();
@@ -63,9 +75,21 @@ lazy_static! {
 
 #[no_mangle]
 pub extern "C" fn ecall_get_registration_quote(target_info: &sgx_target_info_t, real_report: &mut sgx_report_t) -> sgx_status_t {
-    quote_t::create_report_with_data(&target_info, real_report, &SIGNING_KEY.get_pubkey().address())
+    // The report data is the signing address followed by the chain id (big endian),
+    // so the contract can reject a registration that was made for another network.
+    let mut report_data = [0u8; 28];
+    report_data[..20].copy_from_slice(&SIGNING_KEY.get_pubkey().address());
+    report_data[20..].copy_from_slice(&NETWORK_ID.load(Ordering::SeqCst).to_be_bytes());
+    quote_t::create_report_with_data(&target_info, real_report, &report_data)
 }
 
+#[no_mangle]
+pub extern "C" fn ecall_set_network(chain_id: u64) { NETWORK_ID.store(chain_id, Ordering::SeqCst); }
+
+#[no_mangle]
+/// Does nothing, the app times it to notice when entering the enclave gets slow (see `esgx::watchdog` in the app).
+pub extern "C" fn ecall_ping() {}
+
 #[no_mangle]
 pub extern "C" fn ecall_get_signing_address(pubkey: &mut [u8; 20]) { pubkey.copy_from_slice(&SIGNING_KEY.get_pubkey().address()); }
 
@@ -81,6 +105,8 @@ pub extern "C" fn ecall_get_signing_address(pubkey: &mut [u8; 20]) { pubkey.copy
 /// * `user_key` - the DH key of the user to decrypt `callable` and `args`
 /// * `contract_address` - the address of the deployed contract with code `bytecode`
 /// * `gas_limit` - the gas limit for the function execution
+/// * `debug_trace` - non zero to record the host calls of the contract, see `trace_requested`
+/// * `output_envelope` - non zero to also seal the encrypted output in an envelope addressed to `user_key`
 /// * `result` - the result of the function invocation
 // TODO: add arguments of callable.
 pub unsafe extern "C" fn ecall_execute(
@@ -93,6 +119,8 @@ pub unsafe extern "C" fn ecall_execute(
     user_key: &[u8; 64],
     contract_address: &ContractAddress,
     gas_limit: *const u64,
+    debug_trace: u8,
+    output_envelope: u8,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> EnclaveReturn
@@ -102,6 +130,7 @@ pub unsafe extern "C" fn ecall_execute(
     let args = slice::from_raw_parts(args, args_len);
 
     let mut pre_execution_data = vec![];
+    let mut trace = None;
     let io_key = match get_io_key(user_key) {
         Ok(v) => v,
         Err(e) => return e.into(),
@@ -117,6 +146,9 @@ pub unsafe extern "C" fn ecall_execute(
         &io_key,
         (*contract_address).into(),
         *gas_limit,
+        trace_requested(debug_trace),
+        output_envelope != 0,
+        &mut trace,
         db_ptr,
         result,
     );
@@ -124,7 +156,85 @@ pub unsafe extern "C" fn ecall_execute(
         debug_println!("Error in execution of secret contract function: {}", e);
         internal_result = output_task_failure(&pre_execution_data, *gas_limit, e, result, &io_key);
     }
-    internal_result.into()
+    output_trace(trace, result, internal_result).into()
+}
+
+#[no_mangle]
+/// Ecall for executing a compute task again and comparing it with what it produced the first time, see `ReplayResult`.
+/// Nothing is stored and nothing is signed, the state is built from what's behind `db_ptr` (a sandbox on the untrusted side).
+/// The task is decrypted with the IO key it ran with, which the enclave sealed for it (see `replay_key`), and only
+/// executed on the bytecode and the delta of the receipt this enclave signed for it, anything else is refused.
+/// arguments:
+/// * `bytecode` to `contract_address` - the task as it was executed, like for `ecall_execute`.
+/// * `sealed_io_key` - the IO key of the task, as `ecall_execute` sealed it
+/// * `sealed_io_key_len` - the length of the `sealed_io_key`
+/// * `receipt` - the receipt of the task, its gas limit is the one the task is executed with again
+/// * `receipt_sig` - the signature of the receipt, `r || s || v` with `v` 27/28
+/// * `ethereum_payload` - the Ethereum payload of the receipt
+/// * `ethereum_payload_len` - the length of the `ethereum_payload`
+/// * `output` - the encrypted output the task produced
+/// * `output_len` - the length of the `output`
+/// * `delta` - the encrypted delta the task produced, empty if it didn't produce one
+/// * `delta_len` - the length of the `delta`
+/// * `result` - the comparison
+pub unsafe extern "C" fn ecall_replay(
+    bytecode: *const u8,
+    bytecode_len: usize,
+    callable: *const u8,
+    callable_len: usize,
+    args: *const u8,
+    args_len: usize,
+    user_key: &[u8; 64],
+    contract_address: &ContractAddress,
+    sealed_io_key: *const u8,
+    sealed_io_key_len: usize,
+    receipt: &ReplayReceipt,
+    receipt_sig: &[u8; 65],
+    ethereum_payload: *const u8,
+    ethereum_payload_len: usize,
+    output: *const u8,
+    output_len: usize,
+    delta: *const u8,
+    delta_len: usize,
+    db_ptr: *const RawPointer,
+    result: &mut ReplayResult,
+) -> EnclaveReturn
+{
+    let bytecode = slice::from_raw_parts(bytecode, bytecode_len);
+    let callable = slice::from_raw_parts(callable, callable_len);
+    let args = slice::from_raw_parts(args, args_len);
+    let sealed_io_key = slice::from_raw_parts(sealed_io_key, sealed_io_key_len);
+    let ethereum_payload = slice::from_raw_parts(ethereum_payload, ethereum_payload_len);
+    let output = slice::from_raw_parts(output, output_len);
+    let delta = slice::from_raw_parts(delta, delta_len);
+
+    let receipt = ExecuteReceipt {
+        exe_code_hash: receipt.exe_code_hash.into(),
+        inputs_hash: receipt.inputs_hash.into(),
+        prev_delta_hash: receipt.prev_delta_hash.into(),
+        delta_hash: receipt.delta_hash.into(),
+        output_hash: receipt.output_hash.into(),
+        gas_limit: receipt.gas_limit,
+        used_gas: receipt.used_gas,
+        ethereum_payload: ethereum_payload.to_vec(),
+        ethereum_address: receipt.ethereum_address,
+        envelope_hash: if receipt.has_envelope != 0 { Some(receipt.envelope_hash.into()) } else { None },
+    };
+    let replay = ReplayedTask { bytecode, callable, args, user_key, address: *contract_address, sealed_io_key, receipt, receipt_sig };
+    match ecall_replay_internal(&replay, output, delta, db_ptr, result) {
+        Ok(()) => EnclaveReturn::Success,
+        // A task that fails the same way it did the first time is still a replay, the comparison says it failed.
+        Err(FailedTaskError(_)) => {
+            result.failed = 1;
+            EnclaveReturn::Success
+        }
+        Err(FailedTaskErrorWithGas { used_gas, .. }) => {
+            result.used_gas = used_gas;
+            result.failed = 1;
+            EnclaveReturn::Success
+        }
+        Err(e) => e.into(),
+    }
 }
 
 #[no_mangle]
@@ -139,6 +249,7 @@ pub unsafe extern "C" fn ecall_execute(
 /// * `address` - the address of the contract to be deployed
 /// * `user_key` - the DH key of the user to decrypt `constructor` and `args`
 /// * `gas_limit` - the gas limit for the constructor execution
+/// * `debug_trace` - non zero to record the host calls of the constructor, see `trace_requested`
 /// * `result` - the result of the deployment
 pub unsafe extern "C" fn ecall_deploy(
     bytecode: *const u8,
@@ -150,6 +261,7 @@ pub unsafe extern "C" fn ecall_deploy(
     address: &ContractAddress,
     user_key: &PubKey,
     gas_limit: *const u64,
+    debug_trace: u8,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> EnclaveReturn
@@ -157,7 +269,74 @@ pub unsafe extern "C" fn ecall_deploy(
     let args = slice::from_raw_parts(args, args_len);
     let bytecode = slice::from_raw_parts(bytecode, bytecode_len);
     let constructor = slice::from_raw_parts(constructor, constructor_len);
+    deploy(bytecode, constructor, args, address, user_key, gas_limit, debug_trace, db_ptr, result)
+}
+
+#[no_mangle]
+/// Starts a chunked upload of the bytecode of a deployment, for bytecode too big to pass to `ecall_deploy` at once.
+/// arguments:
+/// * `total_len` - the length of the whole bytecode.
+/// * `handle` - the handle to pass to `ecall_deploy_chunk` and `ecall_deploy_finish`.
+pub unsafe extern "C" fn ecall_deploy_begin(total_len: usize, handle: *mut u64) -> EnclaveReturn {
+    match deploy_upload::begin(total_len) {
+        Ok(h) => {
+            *handle = h;
+            EnclaveReturn::Success
+        }
+        Err(e) => e.into(),
+    }
+}
+
+#[no_mangle]
+/// Appends the next `chunk_len` bytes of the bytecode to the upload.
+pub unsafe extern "C" fn ecall_deploy_chunk(handle: u64, chunk: *const u8, chunk_len: usize) -> EnclaveReturn {
+    let chunk = slice::from_raw_parts(chunk, chunk_len);
+    deploy_upload::chunk(handle, chunk).into()
+}
+
+#[no_mangle]
+/// Deploys the uploaded bytecode, the same as `ecall_deploy` from `constructor` on.
+/// arguments:
+/// * `handle` - the handle `ecall_deploy_begin` returned.
+/// * `bytecode_hash` - the keccak256 of the whole bytecode, the deployment fails if the uploaded bytes don't match it.
+pub unsafe extern "C" fn ecall_deploy_finish(
+    handle: u64,
+    bytecode_hash: &Hash256,
+    constructor: *const u8,
+    constructor_len: usize,
+    args: *const u8,
+    args_len: usize,
+    address: &ContractAddress,
+    user_key: &PubKey,
+    gas_limit: *const u64,
+    debug_trace: u8,
+    db_ptr: *const RawPointer,
+    result: &mut ExecuteResult,
+) -> EnclaveReturn
+{
+    let bytecode = match deploy_upload::finish(handle, bytecode_hash) {
+        Ok(bytecode) => bytecode,
+        Err(e) => return e.into(),
+    };
+    let args = slice::from_raw_parts(args, args_len);
+    let constructor = slice::from_raw_parts(constructor, constructor_len);
+    deploy(&bytecode, constructor, args, address, user_key, gas_limit, debug_trace, db_ptr, result)
+}
+
+unsafe fn deploy(
+    bytecode: &[u8],
+    constructor: &[u8],
+    args: &[u8],
+    address: &ContractAddress,
+    user_key: &PubKey,
+    gas_limit: *const u64,
+    debug_trace: u8,
+    db_ptr: *const RawPointer,
+    result: &mut ExecuteResult,
+) -> EnclaveReturn
+{
     let mut pre_execution_data = vec![];
+    let mut trace = None;
     let io_key;
     match get_io_key(user_key) {
         Ok(v) => io_key = v,
@@ -172,6 +351,8 @@ pub unsafe extern "C" fn ecall_deploy(
         user_key,
         &io_key,
         *gas_limit,
+        trace_requested(debug_trace),
+        &mut trace,
         db_ptr,
         result,
     );
@@ -179,12 +360,14 @@ pub unsafe extern "C" fn ecall_deploy(
         debug_println!("Error in deployment of secret contract function: {}", e);
         internal_result = output_task_failure(&pre_execution_data, *gas_limit, e, result, &io_key);
     }
-    internal_result.into()
+    output_trace(trace, result, internal_result).into()
 }
 
+/// Generates a signed request for the state keys, pinned to the epoch of `block_number` if `has_block_number` is non zero.
 #[no_mangle]
-pub unsafe extern "C" fn ecall_ptt_req(sig: &mut [u8; 65], serialized_ptr: *mut u64) -> EnclaveReturn {
-    let msg = match ecall_ptt_req_internal(sig) {
+pub unsafe extern "C" fn ecall_ptt_req(block_number: u64, has_block_number: u8, sig: &mut [u8; 65], serialized_ptr: *mut u64) -> EnclaveReturn {
+    let block_number = if has_block_number != 0 { Some(block_number) } else { None };
+    let msg = match ecall_ptt_req_internal(block_number, sig) {
         Ok(msg) => msg,
         Err(e) => return e.into(),
     };
@@ -195,10 +378,16 @@ pub unsafe extern "C" fn ecall_ptt_req(sig: &mut [u8; 65], serialized_ptr: *mut
     EnclaveReturn::Success
 }
 
+/// Keeps the state keys of the response, only those of the contracts in `addrs` if `has_addrs` is non zero.
 #[no_mangle]
-pub unsafe extern "C" fn ecall_ptt_res(msg_ptr: *const u8, msg_len: usize) -> EnclaveReturn {
+pub unsafe extern "C" fn ecall_ptt_res(msg_ptr: *const u8, msg_len: usize, addrs: *const u8, addrs_len: usize, has_addrs: u8) -> EnclaveReturn {
     let msg_slice = slice::from_raw_parts(msg_ptr, msg_len);
-    ecall_ptt_res_internal(msg_slice).into()
+    let hosted = if has_addrs != 0 {
+        Some(slice::from_raw_parts(addrs as *const ContractAddress, addrs_len / mem::size_of::<ContractAddress>()))
+    } else {
+        None
+    };
+    ecall_ptt_res_internal(msg_slice, hosted).into()
 }
 
 #[no_mangle]
@@ -215,6 +404,16 @@ pub unsafe extern "C" fn ecall_build_state(db_ptr: *const RawPointer, failed_ptr
     EnclaveReturn::Success
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ecall_get_provisioned_addresses(addrs_ptr: *mut u64) -> EnclaveReturn {
+    let flatten = ecall_get_provisioned_addresses_internal().iter().flat_map(|a| a.iter()).cloned().collect::<Vec<u8>>();
+    *addrs_ptr = match ocalls_t::save_to_untrusted_memory(&flatten) {
+        Ok(ptr) => ptr,
+        Err(e) => return e.into(),
+    };
+    EnclaveReturn::Success
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ecall_get_user_key(sig: &mut [u8; 65], user_pubkey: &PubKey, serialized_ptr: *mut u64) -> EnclaveReturn {
     let msg = match ecall_get_user_key_internal(sig, user_pubkey) {
@@ -228,6 +427,23 @@ pub unsafe extern "C" fn ecall_get_user_key(sig: &mut [u8; 65], user_pubkey: &Pu
     EnclaveReturn::Success
 }
 
+#[no_mangle]
+/// Exports the key derivation audit log, see `audit_log`.
+/// arguments:
+/// * `sig` - the signature of the `AuditDigest` of the exported log.
+/// * `serialized_ptr` - the serialized `AuditExport`.
+pub unsafe extern "C" fn ecall_export_audit_digest(sig: &mut [u8; 65], serialized_ptr: *mut u64) -> EnclaveReturn {
+    let export = match audit_log::ecall_export_audit_digest_internal(sig) {
+        Ok(export) => export,
+        Err(e) => return e.into(),
+    };
+    *serialized_ptr = match ocalls_t::save_to_untrusted_memory(&export[..]) {
+        Ok(ptr) => ptr,
+        Err(e) => return e.into(),
+    };
+    EnclaveReturn::Success
+}
+
 fn get_io_key(user_key: &PubKey) -> Result<DhKey, EnclaveError> {
     let io_key = km_t::users::DH_KEYS
         .lock_expect("User DH Key")
@@ -281,14 +497,14 @@ fn create_eth_data_to_sign(input: Option<EthereumData>) -> (Vec<u8>, [u8; 20]) {
 }
 
 fn output_task_failure(
-    pre_execution_data: &[Box<[u8]>],
+    pre_execution_data: &[Hash256],
     gas_limit: u64,
     err: &EnclaveError,
     result: &mut ExecuteResult,
     key: &DhKey,
 ) -> Result<(), EnclaveError>
 {
-    // Signing: S(pre-execution data, usedGas, Failure)
+    // Signing: S(pre-execution data, usedGas, Failure | Forbidden)
     result.used_gas = 0;
     let return_error = match err {
         FailedTaskError(_) => err.clone(),
@@ -298,23 +514,42 @@ fn output_task_failure(
         }
         SystemError(e) => return Err(SystemError(e.clone())),
     };
-    let used_gas = result.used_gas.to_be_bytes();
-    let serialised_gas_limit = gas_limit.to_be_bytes();
-    let failure = [ResultStatus::Failure as u8];
-    let mut to_sign: Vec<&[u8]> = Vec::with_capacity(pre_execution_data.len() + 2);
-    pre_execution_data.into_iter().for_each(|x| to_sign.push(&x));
-    to_sign.push(&serialised_gas_limit);
-    to_sign.push(&used_gas);
-    to_sign.push(&failure);
-    result.signature = SIGNING_KEY.sign_multiple(&to_sign)?;
+    let forbidden = match return_error {
+        FailedTaskError(Forbidden) => true,
+        _ => false,
+    };
+    let receipt = FailureReceipt {
+        pre_execution_data: pre_execution_data.to_vec(),
+        gas_limit,
+        used_gas: result.used_gas,
+        forbidden,
+    };
+    result.signature = SIGNING_KEY.sign_recoverable(&receipt.to_signable_bytes())?;
     let error_text = format!("{}", return_error);
     let encrypted_result = symmetric::encrypt(error_text.as_bytes(), &key)?;
     result.output = ocalls_t::save_to_untrusted_memory(&encrypted_result)? as *const u8;
+    // A failure has no envelope, the error isn't sealed.
+    result.envelope_ptr = std::ptr::null();
+    result.envelope_hash = [0u8; 32];
+    result.sealed_io_key_ptr = std::ptr::null();
     Err(return_error)
 }
 
+/// Traces are only recorded by debug enclaves, a release enclave ignores the flag whatever the host asks for.
+/// The app already refuses the flag unless it runs in dev mode, but only this check holds against a compromised host.
+fn trace_requested(debug_trace: u8) -> bool { debug_trace != 0 && cfg!(debug_assertions) }
+
+/// Passes the trace to the app whether the task succeeded or not, it's only meant for the contract's author.
+fn output_trace(trace: Option<ExecutionTrace>, result: &mut ExecuteResult, internal_result: Result<(), EnclaveError>) -> Result<(), EnclaveError> {
+    if let Some(trace) = trace {
+        let serialized = serde_json::to_vec(&trace).map_err(|e| SystemError(MessagingError { err: e.to_string() }))?;
+        result.trace_ptr = ocalls_t::save_to_untrusted_memory(&serialized)? as *const u8;
+    }
+    internal_result
+}
+
 unsafe fn ecall_execute_internal(
-    pre_execution_data: &mut Vec<Box<[u8]>>,
+    pre_execution_data: &mut Vec<Hash256>,
     bytecode: &[u8],
     callable: &[u8],
     args: &[u8],
@@ -322,6 +557,9 @@ unsafe fn ecall_execute_internal(
     io_key: &DhKey,
     address: ContractAddress,
     gas_limit: u64,
+    debug_trace: bool,
+    output_envelope: bool,
+    trace: &mut Option<ExecutionTrace>,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> Result<(), EnclaveError>
@@ -331,9 +569,13 @@ unsafe fn ecall_execute_internal(
 
     let inputs_hash = enigma_crypto::hash::prepare_hash_multiple(&[callable, args, &*address, user_key]).keccak256();
     let exe_code_hash = bytecode.keccak256();
-    pre_execution_data.push(Box::new(*inputs_hash));
-    pre_execution_data.push(Box::new(*exe_code_hash));
+    pre_execution_data.push(inputs_hash);
+    pre_execution_data.push(exe_code_hash);
     let pre_execution_state = km_t::get_state(db_ptr, address)?;
+    // Refused after the pre-execution data is known, so the failure is signed and can be settled like any other.
+    if !pre_execution_state.is_allowed(user_key) {
+        return Err(FailedTaskError(Forbidden));
+    }
 
     let (decrypted_args, function_name) =
         decrypt_inputs(callable, args, io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
@@ -341,36 +583,126 @@ unsafe fn ecall_execute_internal(
     let state_key = km_t::get_state_key(address)?;
     let mut engine =
         WasmEngine::new_compute(&bytecode, gas_limit, decrypted_args.clone(), pre_execution_state.clone(), function_name, state_key)?;
-    engine.compute()?;
-    let exec_res = engine.into_result()?;
+    if debug_trace {
+        engine.runtime.enable_trace();
+    }
+    let computed = engine.compute();
+    if computed.is_err() {
+        *trace = engine.runtime.take_trace();
+    }
+    computed?;
+    let mut exec_res = engine.into_result()?;
+    *trace = exec_res.trace.take();
 
     let delta_hash = get_enc_delta(&exec_res.state_delta);
     let encrypted_output = symmetric::encrypt(&exec_res.result, io_key)?;
     prepare_wasm_result(&exec_res.state_delta, &encrypted_output, exec_res.ethereum_bridge.clone(), exec_res.used_gas, result)?;
+    // The same encrypted output, addressed to the key the task was submitted with.
+    let envelope = if output_envelope { Some(OutputEnvelope::seal(&encrypted_output, user_key)?) } else { None };
+    let envelope_hash = envelope.as_ref().map(OutputEnvelope::hash);
 
     let (ethereum_payload, ethereum_address) = create_eth_data_to_sign(exec_res.ethereum_bridge);
-    // Signing: S(exeCodeHash, inputsHash, delta(X-1)Hash, deltaXHash, outputHash, gasLimit, usedGas, optionalEthereumData, Success)
-    let used_gas = result.used_gas.to_be_bytes();
-    let output_hash = encrypted_output.keccak256();
-    let to_sign: &[&[u8]] = &[
-        &*exe_code_hash,
-        &*inputs_hash,
-        &*pre_execution_state.delta_hash,
-        &*delta_hash,
-        &*output_hash,
-        &gas_limit.to_be_bytes(),
-        &used_gas,
-        &ethereum_payload,
-        &ethereum_address,
-        &[ResultStatus::Ok as u8],
-    ];
-    result.signature = SIGNING_KEY.sign_multiple(to_sign)?;
+    // Signing: S(exeCodeHash, inputsHash, delta(X-1)Hash, deltaXHash, outputHash, gasLimit, usedGas, optionalEthereumData, [envelopeHash], Success)
+    let receipt = ExecuteReceipt {
+        exe_code_hash,
+        inputs_hash,
+        prev_delta_hash: pre_execution_state.delta_hash,
+        delta_hash,
+        output_hash: encrypted_output.keccak256(),
+        gas_limit,
+        used_gas: result.used_gas,
+        ethereum_payload,
+        ethereum_address,
+        envelope_hash,
+    };
+    result.signature = SIGNING_KEY.sign_recoverable(&receipt.to_signable_bytes())?;
+    result.inputs_hash = *receipt.inputs_hash;
+    result.exe_code_hash = *receipt.exe_code_hash;
+    result.prev_delta_hash = *receipt.prev_delta_hash;
+    result.delta_hash = *receipt.delta_hash;
+    result.output_hash = *receipt.output_hash;
+    result.envelope_hash = envelope_hash.map_or([0u8; 32], |hash| *hash);
+    result.gas_limit = gas_limit;
+    let sealed_io_key = replay_key::seal_io_key(io_key, &receipt.inputs_hash)?;
     store_delta_and_state(db_ptr, &exec_res.state_delta, &exec_res.updated_state)?;
+    // Handed to the app last, it only frees them for a successful task.
+    result.sealed_io_key_ptr = ocalls_t::save_to_untrusted_memory(&sealed_io_key)? as *const u8;
+    if let Some(envelope) = envelope {
+        result.envelope_ptr = ocalls_t::save_to_untrusted_memory(&envelope.to_bytes())? as *const u8;
+    }
+    Ok(())
+}
+
+struct ReplayedTask<'a> {
+    bytecode: &'a [u8],
+    callable: &'a [u8],
+    args: &'a [u8],
+    user_key: &'a PubKey,
+    address: ContractAddress,
+    sealed_io_key: &'a [u8],
+    receipt: ExecuteReceipt,
+    receipt_sig: &'a [u8; 65],
+}
+
+unsafe fn ecall_replay_internal(
+    task: &ReplayedTask,
+    output: &[u8],
+    delta: &[u8],
+    db_ptr: *const RawPointer,
+    result: &mut ReplayResult,
+) -> Result<(), EnclaveError>
+{
+    let address = task.address;
+    if !task.receipt.verify(task.receipt_sig, &SIGNING_KEY.get_pubkey().address())? {
+        return Err(SystemError(StateError { err: "The receipt of the replayed task wasn't signed by this enclave".to_string() }));
+    }
+    result.inputs_hash = *enigma_crypto::hash::prepare_hash_multiple(&[task.callable, task.args, &*address, task.user_key]).keccak256();
+    if result.inputs_hash != *task.receipt.inputs_hash {
+        return Err(SystemError(StateError { err: "The replayed inputs aren't the ones of the receipt".to_string() }));
+    }
+    let io_key = replay_key::unseal_io_key(task.sealed_io_key, &task.receipt.inputs_hash)?;
+    result.exe_code_hash = *task.bytecode.keccak256();
+    if result.exe_code_hash != *task.receipt.exe_code_hash {
+        return Err(SystemError(StateError { err: "The replayed bytecode isn't the one of the receipt".to_string() }));
+    }
+    let pre_execution_state = km_t::get_state(db_ptr, address)?;
+    result.prev_delta_hash = *pre_execution_state.delta_hash;
+    if result.prev_delta_hash != *task.receipt.prev_delta_hash {
+        return Err(SystemError(StateError { err: "The replayed state isn't the one of the receipt".to_string() }));
+    }
+    if !pre_execution_state.is_allowed(task.user_key) {
+        return Err(FailedTaskError(Forbidden));
+    }
+
+    let (decrypted_args, function_name) =
+        decrypt_inputs(task.callable, task.args, &io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
+    let state_key = km_t::get_state_key(address)?;
+    let mut engine = WasmEngine::new_compute(task.bytecode, task.receipt.gas_limit, decrypted_args, pre_execution_state, function_name, state_key)?;
+    engine.compute()?;
+    let exec_res = engine.into_result()?;
+    result.used_gas = exec_res.used_gas;
+
+    // Both are encrypted with a random IV, they can only be compared decrypted.
+    result.output_matches = match symmetric::decrypt(output, &io_key) {
+        Ok(recorded) => (recorded == exec_res.result) as u8,
+        Err(_) => 0,
+    };
+    result.delta_matches = match exec_res.state_delta {
+        None => delta.is_empty() as u8,
+        Some(_) if delta.is_empty() => 0,
+        Some(replayed) => {
+            let recorded = EncryptedPatch { data: delta.to_vec(), contract_address: address, index: replayed.index };
+            match (StatePatch::decrypt(recorded, &state_key), StatePatch::decrypt(replayed, &state_key)) {
+                (Ok(recorded), Ok(replayed)) => (recorded == replayed) as u8,
+                _ => 0,
+            }
+        }
+    };
     Ok(())
 }
 
 unsafe fn ecall_deploy_internal(
-    pre_execution_data: &mut Vec<Box<[u8]>>,
+    pre_execution_data: &mut Vec<Hash256>,
     bytecode: &[u8],
     constructor: &[u8],
     args: &[u8],
@@ -378,13 +710,15 @@ unsafe fn ecall_deploy_internal(
     user_key: &PubKey,
     io_key: &DhKey,
     gas_limit: u64,
+    debug_trace: bool,
+    trace: &mut Option<ExecutionTrace>,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> Result<(), EnclaveError>
 {
     let pre_code_hash = bytecode.keccak256();
     let inputs_hash = enigma_crypto::hash::prepare_hash_multiple(&[constructor, args, &pre_code_hash[..], user_key][..]).keccak256();
-    pre_execution_data.push(Box::new(*inputs_hash));
+    pre_execution_data.push(inputs_hash);
 
     let (decrypted_args, function_name) =
         decrypt_inputs(constructor, args, io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
@@ -393,8 +727,16 @@ unsafe fn ecall_deploy_internal(
 
     let state_key = km_t::get_state_key(address)?;
     let mut engine = WasmEngine::new_deploy(bytecode, gas_limit, decrypted_args.clone(), state, function_name, state_key)?;
-    engine.deploy()?;
-    let exec_res = engine.into_result()?;
+    if debug_trace {
+        engine.runtime.enable_trace();
+    }
+    let deployed = engine.deploy();
+    if deployed.is_err() {
+        *trace = engine.runtime.take_trace();
+    }
+    deployed?;
+    let mut exec_res = engine.into_result()?;
+    *trace = exec_res.trace.take();
 
     let exe_code = &exec_res.result[..];
 
@@ -403,19 +745,23 @@ unsafe fn ecall_deploy_internal(
     prepare_wasm_result(&exec_res.state_delta, exe_code, exec_res.ethereum_bridge.clone(), exec_res.used_gas, result)?;
 
     // Signing: S(inputsHash, exeCodeHash, delta0Hash, gasLimit, usedGas, optionalEthereumData, Success)
-    let used_gas = result.used_gas.to_be_bytes();
     let (ethereum_payload, ethereum_address) = create_eth_data_to_sign(exec_res.ethereum_bridge);
-    let to_sign: &[&[u8]] = &[
-        &*inputs_hash,
-        &*exec_res.result.keccak256(),
-        &*delta_hash,
-        &gas_limit.to_be_bytes(),
-        &used_gas,
-        &ethereum_payload,
-        &ethereum_address,
-        &[ResultStatus::Ok as u8],
-    ];
-    result.signature = SIGNING_KEY.sign_multiple(to_sign)?;
+    let receipt = DeployReceipt {
+        inputs_hash,
+        exe_code_hash: exec_res.result.keccak256(),
+        delta_hash,
+        gas_limit,
+        used_gas: result.used_gas,
+        ethereum_payload,
+        ethereum_address,
+    };
+    result.signature = SIGNING_KEY.sign_recoverable(&receipt.to_signable_bytes())?;
+    // Not signed itself, it's attested through the inputs hash.
+    result.pre_code_hash = *pre_code_hash;
+    result.inputs_hash = *receipt.inputs_hash;
+    result.exe_code_hash = *receipt.exe_code_hash;
+    result.delta_hash = *receipt.delta_hash;
+    result.gas_limit = gas_limit;
     store_delta_and_state(db_ptr, &exec_res.state_delta, &exec_res.updated_state)?;
     Ok(())
 }
@@ -491,7 +837,10 @@ pub mod tests {
         extern crate sgx_tunittest;
 
         use self::sgx_tunittest::*;
+        use crate::audit_log::tests::*;
+        use crate::replay_key::tests::*;
         use crate::km_t::principal::tests::*;
+        use crate::deploy_upload::tests::*;
         use enigma_runtime_t::{data::tests::*, ocalls_t::tests::*, wasm_execution::tests::*};
         use enigma_tools_t::storage_t::tests::*;
         use enigma_types::{RawPointer, ResultStatus};
@@ -509,6 +858,7 @@ pub mod tests {
             core_unitests(&mut ctr, &mut failures, test_encrypt_decrypt_state, "test_encrypt_decrypt_state");
             core_unitests(&mut ctr, &mut failures, test_write_state, "test_write_state");
             core_unitests(&mut ctr, &mut failures, test_read_state, "test_read_state");
+            core_unitests(&mut ctr, &mut failures, test_access_list, "test_access_list");
             core_unitests(&mut ctr, &mut failures, test_diff_patch, "test_diff_patch");
             core_unitests(&mut ctr, &mut failures, test_encrypt_patch, "test_encrypt_patch");
             core_unitests(&mut ctr, &mut failures, test_decrypt_patch, "test_decrypt_patch");
@@ -522,6 +872,10 @@ pub mod tests {
             core_unitests(&mut ctr, &mut failures, || test_state_internal(db_ptr), "test_state_internal");
             core_unitests(&mut ctr, &mut failures, || test_state(db_ptr), "test_state");
             core_unitests(&mut ctr, &mut failures, || {test_remove_delta(db_ptr)}, "test_remove_delta");
+            core_unitests(&mut ctr, &mut failures, test_upload_assembles, "test_upload_assembles");
+            core_unitests(&mut ctr, &mut failures, test_upload_integrity, "test_upload_integrity");
+            core_unitests(&mut ctr, &mut failures, test_audit_chain, "test_audit_chain");
+            core_unitests(&mut ctr, &mut failures, test_seal_io_key, "test_seal_io_key");
             let result = failures.is_empty();
             rsgx_unit_test_end(ctr, failures);
             result.into()
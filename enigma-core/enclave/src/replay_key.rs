@@ -0,0 +1,84 @@
+//! The IO keys of the compute tasks, sealed for `ecall_replay`.
+//! The key a task ran with is removed once the task used it, so the enclave hands it out sealed with the result and the
+//! app journals it next to the task. It's encrypted with a key of its own that only this enclave has, created the
+//! first time and sealed to disk, together with the inputs hash of the task so it only opens for that task's inputs.
+
+use enigma_crypto::{rand, symmetric, CryptoError};
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::common::errors_t::{EnclaveError::{self, SystemError}, EnclaveSystemError::StateError};
+use enigma_tools_t::document_storage_t::{is_document, load_sealed_document, save_sealed_document, SealedDocumentStorage, SEAL_LOG_SIZE};
+use enigma_tools_t::esgx::ocalls_t;
+use enigma_types::{DhKey, Hash256, SymmetricKey};
+use std::path::PathBuf;
+use std::string::ToString;
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+const REPLAY_KEY_FILE: &str = "replay-key.sealed";
+
+lazy_static! { static ref REPLAY_KEY: SgxMutex<Option<SymmetricKey>> = SgxMutex::new(None); }
+
+fn path() -> Result<PathBuf, EnclaveError> { Ok(ocalls_t::get_home_path()?.join(REPLAY_KEY_FILE)) }
+
+// A document that doesn't unseal isn't replaced, the keys sealed with it would be lost for good.
+fn replay_key() -> Result<SymmetricKey, EnclaveError> {
+    let mut slot = REPLAY_KEY.lock_expect("Replay Key");
+    if let Some(key) = *slot {
+        return Ok(key);
+    }
+    let path = path()?;
+    let key = if is_document(&path) {
+        let mut sealed_log = [0u8; SEAL_LOG_SIZE];
+        load_sealed_document(&path, &mut sealed_log)?;
+        match SealedDocumentStorage::<SymmetricKey>::unseal(&mut sealed_log)? {
+            Some(doc) => doc.data,
+            None => return Err(SystemError(StateError { err: "Failed to unseal the replay key".to_string() })),
+        }
+    } else {
+        let mut key = SymmetricKey::default();
+        rand::random(&mut key)?;
+        let doc = SealedDocumentStorage { version: 1, data: key };
+        let mut sealed_log = [0u8; SEAL_LOG_SIZE];
+        doc.seal(&mut sealed_log)?;
+        save_sealed_document(&path, &sealed_log)?;
+        key
+    };
+    *slot = Some(key);
+    Ok(key)
+}
+
+/// Seals the IO key a task with `inputs_hash` ran with.
+pub(crate) fn seal_io_key(io_key: &DhKey, inputs_hash: &Hash256) -> Result<Vec<u8>, EnclaveError> {
+    let mut plain = io_key.to_vec();
+    plain.extend_from_slice(&inputs_hash[..]);
+    Ok(symmetric::encrypt(&plain, &replay_key()?)?)
+}
+
+/// The IO key in `sealed`, only if it was sealed for a task with `inputs_hash`.
+pub(crate) fn unseal_io_key(sealed: &[u8], inputs_hash: &Hash256) -> Result<DhKey, EnclaveError> {
+    let plain = symmetric::decrypt(sealed, &replay_key()?)?;
+    if plain.len() != 64 || plain[32..] != inputs_hash[..] {
+        return Err(CryptoError::MissingKeyError { key_type: "DH Key" }.into());
+    }
+    let mut io_key = DhKey::default();
+    io_key.copy_from_slice(&plain[..32]);
+    Ok(io_key)
+}
+
+#[cfg(debug_assertions)]
+pub mod tests {
+    use super::*;
+    use enigma_crypto::hash::Keccak256;
+
+    pub fn test_seal_io_key() {
+        let io_key = [7u8; 32];
+        let inputs_hash = b"inputs".keccak256();
+        let sealed = seal_io_key(&io_key, &inputs_hash).unwrap();
+        assert_eq!(unseal_io_key(&sealed, &inputs_hash).unwrap(), io_key);
+        // Not for the inputs of another task, nor once tampered with.
+        assert!(unseal_io_key(&sealed, &b"other".keccak256()).is_err());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(unseal_io_key(&tampered, &inputs_hash).is_err());
+    }
+}
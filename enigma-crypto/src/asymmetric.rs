@@ -14,6 +14,46 @@ use secp256k1::{PublicKey, SecretKey, SharedSecret,  RecoveryId, Signature};
 use crate::hash::Keccak256;
 use enigma_types::{DhKey, PubKey};
 
+/// The order `n` of the secp256k1 curve, big endian.
+const SECP256K1_N: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE,
+    0xBA, 0xAE, 0xDC, 0xE6, 0xAF, 0x48, 0xA0, 0x3B, 0xBF, 0xD2, 0x5E, 0x8C, 0xD0, 0x36, 0x41, 0x41,
+];
+
+/// `n / 2`, the highest `s` that is still canonical (see EIP-2), big endian.
+const SECP256K1_HALF_N: [u8; 32] = [
+    0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0x5D, 0x57, 0x6E, 0x73, 0x57, 0xA4, 0x50, 0x1D, 0xDF, 0xE9, 0x2F, 0x46, 0x68, 0x1B, 0x20, 0xA0,
+];
+
+/// Checks that a 65 bytes signature is in the form the Ethereum contracts accept:
+/// `s` is non zero and in the lower half of the curve order, and `v` is 27 or 28.
+///
+/// A signature with a high `s` is still mathematically valid,
+/// but it's malleable and will be rejected on-chain, so we never produce or accept one.
+pub fn is_canonical(sig: &[u8; 65]) -> bool {
+    let s = &sig[32..64];
+    // Big endian byte arrays of the same length compare like the numbers they represent.
+    let low_s = s.iter().any(|b| *b != 0) && s <= &SECP256K1_HALF_N[..];
+    low_s && (sig[64] == 27 || sig[64] == 28)
+}
+
+/// Replaces a high `s` with `n - s` and flips the recovery id accordingly,
+/// so the signature still recovers to the same public key. A low `s` is left as is.
+pub fn normalize_s(sig: &mut [u8; 65]) {
+    if &sig[32..64] <= &SECP256K1_HALF_N[..] {
+        return;
+    }
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let mut diff = i16::from(SECP256K1_N[i]) - i16::from(sig[32 + i]) - borrow;
+        borrow = if diff < 0 { diff += 256; 1 } else { 0 };
+        sig[32 + i] = diff as u8;
+    }
+    // 27 <-> 28
+    sig[64] = 55 - sig[64];
+}
+
 
 /// The `KeyPair` struct is used to hold a Private and Public keys.
 /// you can use it to sign a message, to derive shared secrets(ECDH) etc.
@@ -110,10 +150,27 @@ impl KeyPair {
     ///
     /// The `v` variable or so called `Recovery ID` is to tell you if the public key that's needed to verify is even or odd. <br>
     /// Ususally that byte is just 0/1 for some reasons these are represented as 0/1 so we just add 27 to it.
+    /// So `v` is always 27 or 28, the same as Ethereum's `ecrecover` expects.
+    ///
+    /// The signature is always canonical (low `s`), see [`is_canonical`](fn.is_canonical.html).
     pub fn sign(&self, message: &[u8]) -> Result<[u8; 65], CryptoError> {
         self.sign_hashed(&message.keccak256().into())
     }
 
+    /// The same as `sign()` but the last byte is the recovery id itself (0/1), not 27/28.
+    /// This is what the enclaves hand out, the untrusted side converts it to the form its consumer wants,
+    /// see `enigma_tools_m::signable::SigFormat`.
+    pub fn sign_recoverable(&self, message: &[u8]) -> Result<[u8; 65], CryptoError> {
+        self.sign_hashed_recoverable(&message.keccak256().into())
+    }
+
+    /// The same as `sign_hashed()` but the last byte is the recovery id (0/1), see `sign_recoverable()`.
+    pub fn sign_hashed_recoverable(&self, message: &[u8; 32]) -> Result<[u8; 65], CryptoError> {
+        let mut sig = self.sign_hashed(message)?;
+        sig[64] -= 27;
+        Ok(sig)
+    }
+
     /// Interface for usage without forcing a keccak hash of the input. However, the input must be 32 bytes long.
     /// Mainly useful for when the data is created already hashed and we just want to sign it
     pub fn sign_hashed(&self, message: &[u8; 32]) -> Result<[u8; 65], CryptoError> {
@@ -126,10 +183,13 @@ impl KeyPair {
         let mut returnvalue = [0u8; 65];
         returnvalue[..64].copy_from_slice(&sig.serialize());
         returnvalue[64] = v + 27;
+        // The backend should already return a low `s`, but the contracts revert on a high one, so we make sure.
+        normalize_s(&mut returnvalue);
         Ok(returnvalue)
     }
 
     /// Recover the pubkey using the message and it's signature.
+    /// The recovery id can be in either form, 27/28 like `sign()` returns it or 0/1 like `sign_recoverable()` does.
     /// # Examples
     /// Simple Message recovering:
     /// ```
@@ -140,7 +200,12 @@ impl KeyPair {
     /// let recovered_pubkey = KeyPair::recover(msg, sig).unwrap();
     /// ```
     pub fn recover(message: &[u8], sig: [u8;65]) -> Result<[u8; 64], CryptoError> {
-        let recovery = RecoveryId::parse(sig[64] -27)
+        let v = match sig[64] {
+            0 | 1 => sig[64],
+            27 | 28 => sig[64] - 27,
+            _ => return Err(CryptoError::ParsingError { sig }),
+        };
+        let recovery = RecoveryId::parse(v)
             .map_err(|_| CryptoError::ParsingError { sig })?;
         let signature = Signature::parse_slice(&sig[..64])
             .map_err(|_| CryptoError::ParsingError { sig } )?;
@@ -153,6 +218,15 @@ impl KeyPair {
         Ok(KeyPair::pubkey_object_to_pubkey(&recovered_pub))
     }
 
+    /// The same as `recover()` but rejects signatures that aren't canonical (see [`is_canonical`](fn.is_canonical.html)).
+    /// Use this when verifying signatures that are going to be checked on-chain.
+    pub fn recover_canonical(message: &[u8], sig: [u8;65]) -> Result<[u8; 64], CryptoError> {
+        if !is_canonical(&sig) {
+            return Err(CryptoError::NonCanonicalSignature { sig });
+        }
+        KeyPair::recover(message, sig)
+    }
+
     /// The same as sign() but for multiple arguments.
     /// What this does is appends the length of the messages before each message and make one big slice from all of them.
     /// e.g.: `S(H(len(a)+a, len(b)+b...))`
@@ -173,7 +247,9 @@ impl KeyPair {
 
 #[cfg(test)]
 mod tests {
-    use super::KeyPair;
+    use super::{is_canonical, normalize_s, KeyPair, SECP256K1_HALF_N, SECP256K1_N};
+    use crate::error::CryptoError;
+    use crate::rand;
 
     #[test]
     fn test_signing() {
@@ -211,4 +287,81 @@ mod tests {
             [139, 184, 212, 39, 0, 146, 97, 243, 63, 65, 81, 130, 96, 208, 43, 150, 229, 90, 132, 202, 235, 168, 86, 59, 141, 19, 200, 38, 242, 55, 203, 15]
         );
     }
+
+    #[test]
+    fn test_signatures_are_canonical() {
+        for _ in 0..500 {
+            let keys = KeyPair::new().unwrap();
+            let mut msg = [0u8; 64];
+            rand::random(&mut msg).unwrap();
+            let sig = keys.sign(&msg).unwrap();
+            assert!(is_canonical(&sig), "non canonical signature: {:?}", &sig[..]);
+            assert!(sig[64] == 27 || sig[64] == 28);
+            let recovered = KeyPair::recover_canonical(&msg, sig).unwrap();
+            assert_eq!(&recovered[..], &keys.get_pubkey()[..]);
+        }
+    }
+
+    #[test]
+    fn test_sign_recoverable() {
+        let keys = KeyPair::new().unwrap();
+        let msg = b"EnigmaMPC";
+        let sig = keys.sign_recoverable(msg).unwrap();
+        assert!(sig[64] == 0 || sig[64] == 1);
+        let mut eth = sig;
+        eth[64] += 27;
+        // Signing is deterministic (RFC 6979), only `v` differs.
+        assert_eq!(&eth[..], &keys.sign(msg).unwrap()[..]);
+        // Both forms recover to the same key
+        assert_eq!(&KeyPair::recover(msg, sig).unwrap()[..], &keys.get_pubkey()[..]);
+        assert_eq!(&KeyPair::recover(msg, eth).unwrap()[..], &keys.get_pubkey()[..]);
+        for &v in &[2u8, 26, 29, 255] {
+            let mut invalid = sig;
+            invalid[64] = v;
+            match KeyPair::recover(msg, invalid) {
+                Err(CryptoError::ParsingError { .. }) => (),
+                other => panic!("Expected ParsingError for v = {}, got: {:?}", v, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_high_s() {
+        let keys = KeyPair::new().unwrap();
+        let msg = b"EnigmaMPC";
+        let sig = keys.sign(msg).unwrap();
+        // Build the malleated twin: (r, n - s, v ^ 1)
+        let mut high = sig;
+        let mut borrow = 0i16;
+        for i in (0..32).rev() {
+            let mut diff = i16::from(SECP256K1_N[i]) - i16::from(sig[32 + i]) - borrow;
+            borrow = if diff < 0 { diff += 256; 1 } else { 0 };
+            high[32 + i] = diff as u8;
+        }
+        high[64] = if sig[64] == 27 { 28 } else { 27 };
+        assert!(&high[32..64] > &SECP256K1_HALF_N[..]);
+        assert!(!is_canonical(&high));
+        match KeyPair::recover_canonical(msg, high) {
+            Err(CryptoError::NonCanonicalSignature { .. }) => (),
+            Err(e) => panic!("Expected NonCanonicalSignature, got: {:?}", e),
+            Ok(_) => panic!("A high s signature was accepted"),
+        }
+        normalize_s(&mut high);
+        assert_eq!(&high[..], &sig[..]);
+    }
+
+    #[test]
+    fn test_canonical_bounds() {
+        let mut sig = [0u8; 65];
+        sig[64] = 27;
+        // s = 0 is invalid.
+        assert!(!is_canonical(&sig));
+        sig[32..64].copy_from_slice(&SECP256K1_HALF_N);
+        assert!(is_canonical(&sig));
+        sig[63] += 1;
+        assert!(!is_canonical(&sig));
+        sig[32..64].copy_from_slice(&SECP256K1_HALF_N);
+        sig[64] = 1;
+        assert!(!is_canonical(&sig));
+    }
 }
@@ -43,6 +43,10 @@ pub enum CryptoError {
     ///
     /// This error means that the public key can't be recovered from that message & signature.
     RecoveryError { sig: [u8; 65] },
+    /// The `NonCanonicalSignature` error.
+    ///
+    /// This error means that the signature has a high `s` (malleable) or a `v` that isn't 27/28.
+    NonCanonicalSignature { sig: [u8; 65] },
     /// The `KeyError` error.
     ///
     /// This error means that a key wasn't vaild.
@@ -73,6 +77,7 @@ impl fmt::Display for CryptoError {
             SigningError { hashed_msg } => write!(f, "Signing the message failed, msg hash: {:?}", hashed_msg),
             ParsingError { sig } => write!(f, "Parsing the signature failed, sig: {:?}", &sig[..]),
             RecoveryError { sig } => write!(f, "Recovering the pubkey failed using the sig: {:?}", &sig[..]),
+            NonCanonicalSignature { sig } => write!(f, "The signature isn't canonical, sig: {:?}", &sig[..]),
             #[cfg(any(feature = "std", feature = "sgx"))]
             RandomError{ err } => write!(f, "Failed Generating a random. Error: {:?}", err),
         }
@@ -127,6 +132,11 @@ impl fmt::Debug for CryptoError {
                 debug_builder.field("self_key", &&sig[..]);
                 debug_builder.finish()
             },
+            NonCanonicalSignature { ref sig } => {
+                let mut debug_builder = f.debug_struct("NonCanonicalSignature");
+                debug_builder.field("sig", &&sig[..]);
+                debug_builder.finish()
+            },
             #[cfg(any(feature = "std", feature = "sgx"))]
             RandomError{ ref err } => {
                 let mut debug_builder = f.debug_struct("RandomError");
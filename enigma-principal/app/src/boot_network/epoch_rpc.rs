@@ -0,0 +1,439 @@
+//! # Epoch JSON-RPC server.
+//! A read only JSON-RPC server for the network explorers and the JS SDK, separate from the one the workers get
+//! their state keys from since it's meant to be reachable from outside. It is only started if `epoch_rpc_bind`
+//! is configured. <br>
+//! Every answer carries the enclave signature over the canonical encoding of its payload
+//! (see `enigma_tools_m::signable`) and the KM signing address, so the callers don't have to trust the node serving it.
+//!
+//! The requests are rate limited for all the callers together, and their params are validated before
+//! anything reaches the enclave.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use enigma_tools_m::{keeper_types::InputWorkerParams, signable::{EpochSeed, EpochSeedCommitment, Signable, WorkerSelection}};
+use enigma_types::ContractAddress;
+use failure::Error;
+use jsonrpc_http_server::{
+    jsonrpc_core::{Error as ServerError, ErrorCode, IoHandler, Params, Value},
+    Server, ServerBuilder,
+};
+use rustc_hex::FromHex;
+use sgx_types::sgx_enclave_id_t;
+use web3::types::{Bytes, H160, H256, U256};
+
+use boot_network::keys_provider_http::PrincipalHttpServer;
+use common_u::errors::{EnclaveFailError, EpochStateTransitionErr, EpochStateUndefinedErr, RequestValueErr,
+                       JSON_RPC_ERROR_ILLEGAL_STATE, JSON_RPC_ERROR_RATE_LIMITED};
+use epoch_u::{epoch_provider::EpochProvider, epoch_types::EpochState};
+use esgx::{epoch_keeper_u::get_selection_proof, equote::get_register_signing_address};
+
+const METHOD_GET_WORKER_PARAMS: &str = "getWorkerParams";
+const METHOD_GET_EPOCH_STATE: &str = "getEpochState";
+const METHOD_GET_SELECTION_PROOF: &str = "getSelectionProof";
+
+/// The largest request body accepted, the biggest valid request is well under it.
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+/// The requests per second answered if `epoch_rpc_rate_limit` isn't configured.
+pub const DEFAULT_RATE_LIMIT: u32 = 20;
+
+/// The epochs the server answers about, the `EpochProvider` of the node.
+pub trait EpochSource: Send + Sync + 'static {
+    fn find_epoch(&self, block_number: U256) -> Result<EpochState, Error>;
+    fn find_last_epoch(&self) -> Result<EpochState, Error>;
+    fn find_epoch_by_nonce(&self, nonce: U256) -> Result<Option<EpochState>, Error>;
+    /// The worker params the enclave signed the epoch with.
+    fn worker_params(&self, epoch_state: &EpochState) -> Result<InputWorkerParams, Error>;
+    fn eid(&self) -> sgx_enclave_id_t;
+}
+
+impl EpochSource for EpochProvider {
+    fn find_epoch(&self, block_number: U256) -> Result<EpochState, Error> { EpochProvider::find_epoch(self, block_number) }
+
+    fn find_last_epoch(&self) -> Result<EpochState, Error> { EpochProvider::find_last_epoch(self) }
+
+    fn find_epoch_by_nonce(&self, nonce: U256) -> Result<Option<EpochState>, Error> { EpochProvider::find_epoch_by_nonce(self, nonce) }
+
+    fn worker_params(&self, epoch_state: &EpochState) -> Result<InputWorkerParams, Error> { EpochProvider::worker_params(self, epoch_state) }
+
+    fn eid(&self) -> sgx_enclave_id_t { *self.eid }
+}
+
+/// A confirmed epoch and the signature the enclave gave it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedEpoch {
+    pub seed: U256,
+    pub nonce: U256,
+    /// The block the active workers were taken from
+    pub km_block_number: U256,
+    /// The block the epoch started at on Ethereum
+    pub ether_block_number: Option<U256>,
+    pub workers: Vec<H160>,
+    pub stakes: Vec<U256>,
+    /// The enclave signature over `signable()`
+    pub sig: Bytes,
+    /// The KM signing address
+    pub signer: H160,
+    /// `keccak256(seed)`, for the epochs created with a commitment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commitment: Option<H256>,
+}
+
+impl SignedEpoch {
+    /// The payload `sig` is over, the commitment if the epoch was created with one.
+    pub fn signable(&self) -> Box<dyn Signable> {
+        match self.commitment {
+            Some(commitment) => Box::new(EpochSeedCommitment { commitment, nonce: self.nonce, workers: self.workers.clone(), stakes: self.stakes.clone() }),
+            None => Box::new(EpochSeed { seed: self.seed, nonce: self.nonce, workers: self.workers.clone(), stakes: self.stakes.clone() }),
+        }
+    }
+}
+
+/// The worker selected for a secret contract in an epoch, signed by the enclave.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectionProof {
+    pub seed: U256,
+    pub nonce: U256,
+    pub sc_addr: H256,
+    pub worker: H160,
+    /// The enclave signature over `signable()`
+    pub sig: Bytes,
+    /// The KM signing address
+    pub signer: H160,
+}
+
+impl SelectionProof {
+    /// The payload `sig` is over.
+    pub fn signable(&self) -> WorkerSelection {
+        WorkerSelection { seed: self.seed, nonce: self.nonce, contract_address: ContractAddress::from(self.sc_addr.0), worker: self.worker }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// A token bucket of `rate` requests a second, shared by all the callers.
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        let rate = f64::from(rate.max(1));
+        RateLimiter { rate, bucket: Mutex::new(Bucket { tokens: rate, refilled_at: Instant::now() }) }
+    }
+
+    /// Takes a token if there's one left.
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.refilled_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+pub struct EpochRpcServer<S: EpochSource> {
+    source: Arc<S>,
+    bind: SocketAddr,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S: EpochSource> EpochRpcServer<S> {
+    pub fn new(source: Arc<S>, bind: SocketAddr, rate_limit: u32) -> Self {
+        EpochRpcServer { source, bind, limiter: Arc::new(RateLimiter::new(rate_limit)) }
+    }
+
+    fn invalid(method: &str, message: String) -> Error { RequestValueErr { request: method.to_string(), message }.into() }
+
+    /// The positional params of the request, there must be exactly `count` of them.
+    fn positional(method: &str, params: Params, count: usize) -> Result<Vec<Value>, Error> {
+        let values = match params {
+            Params::None => Vec::new(),
+            Params::Array(values) => values,
+            Params::Map(_) => return Err(Self::invalid(method, "The params must be positional".to_string())),
+        };
+        if values.len() != count {
+            return Err(Self::invalid(method, format!("Expected {} params, got {}", count, values.len())));
+        }
+        Ok(values)
+    }
+
+    /// A number given either as a JSON number or as a decimal string.
+    fn parse_number(method: &str, name: &str, value: &Value) -> Result<U256, Error> {
+        let number = match value {
+            Value::Number(number) => number.as_u64(),
+            Value::String(number) => number.parse::<u64>().ok(),
+            _ => None,
+        };
+        number.map(U256::from).ok_or_else(|| Self::invalid(method, format!("{} must be a decimal number", name)))
+    }
+
+    /// A 32 bytes hex string, with or without the 0x prefix.
+    fn parse_contract_address(method: &str, name: &str, value: &Value) -> Result<ContractAddress, Error> {
+        let hex = value.as_str().map(|s| s.trim_start_matches("0x")).unwrap_or_default();
+        let bytes: Vec<u8> = match hex.len() {
+            64 => hex.from_hex().map_err(|_| Self::invalid(method, format!("{} must be a hex string", name)))?,
+            _ => return Err(Self::invalid(method, format!("{} must be a 32 bytes hex string", name))),
+        };
+        let mut address = [0u8; 32];
+        address.copy_from_slice(&bytes);
+        Ok(address.into())
+    }
+
+    fn sign_epoch(source: &S, epoch_state: EpochState) -> Result<Value, Error> {
+        let worker_params = source.worker_params(&epoch_state)?;
+        let signer = get_register_signing_address(source.eid())?;
+        let epoch = SignedEpoch {
+            seed: epoch_state.seed,
+            nonce: epoch_state.nonce,
+            km_block_number: epoch_state.km_block_number,
+            ether_block_number: epoch_state.confirmed_state.map(|state| state.ether_block_number),
+            workers: worker_params.workers,
+            stakes: worker_params.stakes,
+            sig: epoch_state.sig,
+            signer: signer.into(),
+            commitment: epoch_state.commitment,
+        };
+        Ok(serde_json::to_value(&epoch)?)
+    }
+
+    /// The last confirmed epoch.
+    /// curl -X POST -d '{"jsonrpc": "2.0", "id": "1", "method": "getEpochState", "params": []}' -H "Content-Type: application/json" 127.0.0.1:3041
+    pub fn get_epoch_state(source: &S, params: Params) -> Result<Value, Error> {
+        Self::positional(METHOD_GET_EPOCH_STATE, params, 0)?;
+        Self::sign_epoch(source, source.find_last_epoch()?)
+    }
+
+    /// The epoch of the block, with the workers and stakes it was created with.
+    /// curl -X POST -d '{"jsonrpc": "2.0", "id": "1", "method": "getWorkerParams", "params": ["1200"]}' -H "Content-Type: application/json" 127.0.0.1:3041
+    pub fn get_worker_params(source: &S, params: Params) -> Result<Value, Error> {
+        let params = Self::positional(METHOD_GET_WORKER_PARAMS, params, 1)?;
+        let block_number = Self::parse_number(METHOD_GET_WORKER_PARAMS, "blockNumber", &params[0])?;
+        Self::sign_epoch(source, source.find_epoch(block_number)?)
+    }
+
+    /// The worker selected for the secret contract in the epoch of the nonce.
+    /// curl -X POST -d '{"jsonrpc": "2.0", "id": "1", "method": "getSelectionProof", "params": ["0x<scAddr>", "3"]}' -H "Content-Type: application/json" 127.0.0.1:3041
+    pub fn get_selection_proof(source: &S, params: Params) -> Result<Value, Error> {
+        let params = Self::positional(METHOD_GET_SELECTION_PROOF, params, 2)?;
+        let sc_addr = Self::parse_contract_address(METHOD_GET_SELECTION_PROOF, "scAddr", &params[0])?;
+        let nonce = Self::parse_number(METHOD_GET_SELECTION_PROOF, "nonce", &params[1])?;
+        // The enclave would refuse it too, but it's not an authorization error to the caller
+        if source.find_epoch_by_nonce(nonce)?.is_none() {
+            return Err(Self::invalid(METHOD_GET_SELECTION_PROOF, format!("Unknown epoch nonce: {}", nonce)));
+        }
+        let (selection, sig) = get_selection_proof(source.eid(), sc_addr, nonce)?;
+        let signer = get_register_signing_address(source.eid())?;
+        let proof = SelectionProof {
+            seed: selection.seed,
+            nonce: selection.nonce,
+            sc_addr: H256(*selection.contract_address),
+            worker: selection.worker,
+            sig: Bytes(sig.to_vec()),
+            signer: signer.into(),
+        };
+        Ok(serde_json::to_value(&proof)?)
+    }
+
+    /// Unlike `PrincipalHttpServer`, the internal errors aren't detailed to the callers.
+    fn handle_error(internal_err: Error) -> ServerError {
+        if let Some(err) = internal_err.downcast_ref::<RequestValueErr>() {
+            return ServerError { code: ErrorCode::InvalidParams, message: err.message.clone(), data: None };
+        }
+        if internal_err.downcast_ref::<EpochStateUndefinedErr>().is_some() {
+            return ServerError { code: ErrorCode::ServerError(JSON_RPC_ERROR_ILLEGAL_STATE), message: "No epoch yet.".to_string(), data: None };
+        }
+        if internal_err.downcast_ref::<EnclaveFailError>().is_some() || internal_err.downcast_ref::<EpochStateTransitionErr>().is_some() {
+            return PrincipalHttpServer::handle_error(internal_err);
+        }
+        error!("Epoch JSON-RPC request failed: {:?}", internal_err);
+        ServerError { code: ErrorCode::InternalError, message: "Internal error".to_string(), data: None }
+    }
+
+    fn add_method<F>(&self, io: &mut IoHandler, name: &str, handler: F)
+    where F: Fn(&S, Params) -> Result<Value, Error> + Send + Sync + 'static {
+        let source = Arc::clone(&self.source);
+        let limiter = Arc::clone(&self.limiter);
+        io.add_method(name, move |params: Params| {
+            if !limiter.try_acquire() {
+                return Err(ServerError {
+                    code: ErrorCode::ServerError(JSON_RPC_ERROR_RATE_LIMITED),
+                    message: "Too many requests, try again later.".to_string(),
+                    data: None,
+                });
+            }
+            handler(&source, params).map_err(Self::handle_error)
+        });
+    }
+
+    pub fn io_handler(&self) -> IoHandler {
+        let mut io = IoHandler::default();
+        self.add_method(&mut io, METHOD_GET_EPOCH_STATE, Self::get_epoch_state);
+        self.add_method(&mut io, METHOD_GET_WORKER_PARAMS, Self::get_worker_params);
+        self.add_method(&mut io, METHOD_GET_SELECTION_PROOF, Self::get_selection_proof);
+        io
+    }
+
+    /// Starts listening, the requests are served until the returned `Server` is closed.
+    pub fn start(&self) -> Result<Server, Error> {
+        let server = ServerBuilder::new(self.io_handler()).max_request_body_size(MAX_REQUEST_SIZE).start_http(&self.bind)?;
+        info!("Epoch JSON-RPC listening on: {}", server.address());
+        Ok(server)
+    }
+}
+
+//////////////////////// TESTS  /////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use rustc_hex::ToHex;
+    use enigma_tools_m::signable::Signable;
+    use epoch_u::epoch_types::ConfirmedEpochState;
+    use esgx::epoch_keeper_u::{set_or_verify_worker_params, tests::get_worker_params};
+    use esgx::general::init_enclave_wrapper;
+
+    use super::*;
+
+    /// A node with a single confirmed epoch.
+    struct SingleEpoch {
+        eid: sgx_enclave_id_t,
+        epoch_state: EpochState,
+        worker_params: InputWorkerParams,
+    }
+
+    impl EpochSource for SingleEpoch {
+        fn find_epoch(&self, _block_number: U256) -> Result<EpochState, Error> { Ok(self.epoch_state.clone()) }
+
+        fn find_last_epoch(&self) -> Result<EpochState, Error> { Ok(self.epoch_state.clone()) }
+
+        fn find_epoch_by_nonce(&self, nonce: U256) -> Result<Option<EpochState>, Error> {
+            Ok(Some(self.epoch_state.clone()).filter(|epoch_state| epoch_state.nonce == nonce))
+        }
+
+        fn worker_params(&self, _epoch_state: &EpochState) -> Result<InputWorkerParams, Error> { Ok(self.worker_params.clone()) }
+
+        fn eid(&self) -> sgx_enclave_id_t { self.eid }
+    }
+
+    fn start_server(source: SingleEpoch, rate_limit: u32) -> Server {
+        EpochRpcServer::new(Arc::new(source), "127.0.0.1:0".parse().unwrap(), rate_limit).start().unwrap()
+    }
+
+    fn post(server: &Server, method: &str, params: &str) -> Value {
+        let body = format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "{}", "params": {}}}"#, method, params);
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        write!(stream, "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               server.address(), body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        serde_json::from_str(body).unwrap()
+    }
+
+    fn error_code(response: &Value) -> i64 { response["error"]["code"].as_i64().unwrap() }
+
+    fn sig(bytes: &Bytes) -> [u8; 65] {
+        let mut sig = [0u8; 65];
+        sig.copy_from_slice(&bytes.0);
+        sig
+    }
+
+    #[test]
+    fn test_epoch_rpc_signatures() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let eid = enclave.geteid();
+        let workers: Vec<[u8; 20]> = vec![[1u8; 20], [2u8; 20], [3u8; 20]];
+        let worker_params = get_worker_params(10, workers, vec![10000000000, 20000000000, 30000000000]);
+        let mut epoch_state = set_or_verify_worker_params(eid, &worker_params, None, true).unwrap();
+        epoch_state.confirmed_state = Some(ConfirmedEpochState { selected_workers: HashMap::new(), ether_block_number: U256::from(12) });
+        let nonce = epoch_state.nonce;
+        let server = start_server(SingleEpoch { eid, epoch_state, worker_params: worker_params.clone() }, 100);
+        let signer = get_register_signing_address(eid).unwrap();
+
+        for (method, params) in vec![(METHOD_GET_EPOCH_STATE, "[]".to_string()), (METHOD_GET_WORKER_PARAMS, r#"["12"]"#.to_string())] {
+            let epoch: SignedEpoch = serde_json::from_value(post(&server, method, &params)["result"].clone()).unwrap();
+            assert_eq!(epoch.signer, H160(signer));
+            assert_eq!((epoch.workers.clone(), epoch.stakes.clone()), (worker_params.workers.clone(), worker_params.stakes.clone()));
+            assert_eq!(epoch.ether_block_number, Some(U256::from(12)));
+            assert!(epoch.signable().verify(&sig(&epoch.sig), &signer).unwrap());
+        }
+
+        let sc_addr = [7u8; 32];
+        let params = format!(r#"["0x{}", {}]"#, sc_addr[..].to_hex(), nonce);
+        let proof: SelectionProof = serde_json::from_value(post(&server, METHOD_GET_SELECTION_PROOF, &params)["result"].clone()).unwrap();
+        assert_eq!(proof.sc_addr, H256(sc_addr));
+        assert_eq!(proof.signable().worker, worker_params.get_selected_worker(ContractAddress::from(sc_addr), proof.seed).unwrap());
+        assert!(proof.signable().verify(&sig(&proof.sig), &signer).unwrap());
+
+        // The proof is for this contract and nonce only
+        let mut other = proof.signable();
+        other.contract_address = ContractAddress::from([8u8; 32]);
+        assert!(!other.verify(&sig(&proof.sig), &signer).unwrap());
+
+        server.close();
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_epoch_rpc_validation() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let eid = enclave.geteid();
+        let worker_params = get_worker_params(1, vec![[1u8; 20]], vec![10000000000]);
+        let epoch_state = set_or_verify_worker_params(eid, &worker_params, None, true).unwrap();
+        let nonce = epoch_state.nonce;
+        let server = start_server(SingleEpoch { eid, epoch_state, worker_params }, 100);
+
+        let invalid = vec![
+            (METHOD_GET_WORKER_PARAMS, "[]".to_string()),
+            (METHOD_GET_WORKER_PARAMS, r#"["0x10"]"#.to_string()),
+            (METHOD_GET_WORKER_PARAMS, r#"["-1"]"#.to_string()),
+            (METHOD_GET_WORKER_PARAMS, r#"{"blockNumber": 1}"#.to_string()),
+            (METHOD_GET_EPOCH_STATE, "[1]".to_string()),
+            (METHOD_GET_SELECTION_PROOF, format!(r#"["0x0707", {}]"#, nonce)),
+            (METHOD_GET_SELECTION_PROOF, format!(r#"["{}", {}]"#, "zz".repeat(32), nonce)),
+            (METHOD_GET_SELECTION_PROOF, format!(r#"["{}", {}]"#, "07".repeat(32), nonce + U256::from(1))),
+        ];
+        for (method, params) in invalid {
+            let response = post(&server, method, &params);
+            assert_eq!(error_code(&response), -32602, "{} {}: {}", method, params, response);
+        }
+
+        server.close();
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_epoch_rpc_rate_limit() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let eid = enclave.geteid();
+        let worker_params = get_worker_params(1, vec![[1u8; 20]], vec![10000000000]);
+        let epoch_state = set_or_verify_worker_params(eid, &worker_params, None, true).unwrap();
+        let server = start_server(SingleEpoch { eid, epoch_state, worker_params }, 2);
+
+        assert!(post(&server, METHOD_GET_EPOCH_STATE, "[]").get("result").is_some());
+        assert!(post(&server, METHOD_GET_EPOCH_STATE, "[]").get("result").is_some());
+        assert_eq!(error_code(&post(&server, METHOD_GET_EPOCH_STATE, "[]")), JSON_RPC_ERROR_RATE_LIMITED);
+
+        server.close();
+        enclave.destroy();
+    }
+}
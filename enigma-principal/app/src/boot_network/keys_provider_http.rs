@@ -18,12 +18,14 @@ use enigma_tools_u::web3_utils::enigma_contract::ContractQueries;
 use epoch_u::{epoch_provider::EpochProvider, epoch_types::EpochState};
 use esgx::keys_keeper_u::get_enc_state_keys;
 use esgx;
-use common_u::errors::{RequestValueErr, EnclaveFailError, EpochStateTransitionErr, JSON_RPC_ERROR_ILLEGAL_STATE, JSON_RPC_ERROR_WORKER_NOT_AUTHORIZED};
+use common_u::errors::{RequestValueErr, EnclaveFailError, EpochStateTransitionErr, JSON_RPC_ERROR_ILLEGAL_STATE,
+                       JSON_RPC_ERROR_INVALID_WORKER_PARAMS, JSON_RPC_ERROR_NO_WORKERS_IN_EPOCH, JSON_RPC_ERROR_WORKER_NOT_AUTHORIZED};
 use web3::types::{U256, H160};
 
 
 const METHOD_GET_STATE_KEYS: &str = "getStateKeys";
 const METHOD_GET_HEALTH_CHECK: &str = "getHealthCheck";
+const METHOD_GET_EPOCH_TRANSITION: &str = "getEpochTransition";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StringWrapper(pub String);
@@ -78,6 +80,7 @@ pub struct StateKeyRequest {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StateKeyResponse {
     pub data: StringWrapper,
+    /// The KM signature over `data`, `r || s || v` with a low `s` and `v` of 27/28
     pub sig: StringWrapper,
 }
 
@@ -113,8 +116,24 @@ impl PrincipalHttpServer {
 
     #[logfn(DEBUG)]
     pub fn get_state_keys(epoch_provider: &EpochProvider, request: StateKeyRequest) -> Result<Value, Error> {
-        let epoch_state = match request.block_number.clone() {
-            Some(block_number) => epoch_provider.find_epoch(block_number.try_into()?)?,
+        let msg = PrincipalMessage::from_message(&request.get_data()?)?;
+        // A worker can pin its request to the epoch of a block, that block is signed so it wins over an unsigned one.
+        let signed_block = msg.get_block_number().map(U256::from);
+        let block_number = match request.block_number.clone() {
+            Some(block_number) => {
+                let block_number: U256 = block_number.try_into()?;
+                if let Some(signed) = signed_block.filter(|signed| *signed != block_number) {
+                    return Err(RequestValueErr {
+                        request: METHOD_GET_STATE_KEYS.to_string(),
+                        message: format!("The block number {} isn't the one signed by the worker: {}.", block_number, signed),
+                    }.into());
+                }
+                Some(block_number)
+            }
+            None => signed_block,
+        };
+        let epoch_state = match block_number {
+            Some(block_number) => epoch_provider.find_epoch(block_number)?,
             None => epoch_provider.find_last_epoch()?,
         };
         let addresses = &request.addresses;
@@ -124,7 +143,6 @@ impl PrincipalHttpServer {
                 res?
             }
             else{
-                let msg = PrincipalMessage::from_message(&request.get_data()?)?;
                 Self::find_epoch_contract_addresses(&request, &msg, &epoch_state)?
             }
         };
@@ -133,7 +151,7 @@ impl PrincipalHttpServer {
         Ok(response_data)
     }
 
-    fn handle_error(internal_err: Error) -> ServerError {
+    pub(crate) fn handle_error(internal_err: Error) -> ServerError {
         if let Some(err) = internal_err.downcast_ref::<EnclaveFailError>() {
             error!("{:?}", internal_err.as_fail());
             let server_err = match &err.err {
@@ -144,6 +162,20 @@ impl PrincipalHttpServer {
                         data: None,
                     }
                 }
+                EnclaveReturn::NoWorkersInEpoch => {
+                    ServerError {
+                        code: ErrorCode::ServerError(JSON_RPC_ERROR_NO_WORKERS_IN_EPOCH),
+                        message: format!("There are no workers in the epoch: {:?}.", err),
+                        data: None,
+                    }
+                }
+                EnclaveReturn::InvalidWorkerParams => {
+                    ServerError {
+                        code: ErrorCode::ServerError(JSON_RPC_ERROR_INVALID_WORKER_PARAMS),
+                        message: format!("Invalid worker params: {:?}.", err),
+                        data: None,
+                    }
+                }
                 _ => {
                     ServerError {
                         code: ErrorCode::InternalError,
@@ -185,6 +217,12 @@ impl PrincipalHttpServer {
         return Value::Bool(contract_signing_address == enclave_signing_address)
     }
 
+    /// The record of the last epoch transition, `null` if there was none.
+    /// curl -X POST -d '{"jsonrpc": "2.0", "id": "1", "method": "getEpochTransition", "params": []}' -H "Content-Type: application/json" 127.0.0.1:3040
+    pub fn get_epoch_transition(epoch_provider: &EpochProvider) -> Result<Value, Error> {
+        Ok(serde_json::to_value(&epoch_provider.transition_store.last()?)?)
+    }
+
     /// Endpoint for the get_state_keys and the health check method
     ///
     /// Example:
@@ -204,6 +242,11 @@ impl PrincipalHttpServer {
             let body = Self::health_check(&hc_epoch_provider);
             Ok(body)
         });
+        let et_epoch_provider = Arc::clone(&self.epoch_provider);
+        io.add_method(METHOD_GET_EPOCH_TRANSITION, move |_| {
+            let body = Self::get_epoch_transition(&et_epoch_provider).map_err(Self::handle_error)?;
+            Ok(body)
+        });
         let server =
             ServerBuilder::new(io).start_http(&format!("0.0.0.0:{}", port).parse().unwrap()).expect("Unable to start RPC server");
         info!("JSON-RPC listening on port: {}", port);
@@ -247,7 +290,7 @@ mod test {
         let stakes: Vec<u64> = vec![10000000000];
         let km_block_number = 1;
         let worker_params = get_worker_params(km_block_number, workers, stakes);
-        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None).unwrap();
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
         let rpc = {
             let mut io = IoHandler::new();
             let eid = enclave.geteid();
@@ -281,7 +324,7 @@ mod test {
         let sig = Bytes::from(REF_SIG.from_hex().unwrap());
         let nonce = U256::from(0);
         let km_block_number = U256::from(1);
-        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state };
+        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state, commitment: None };
         let msg = PrincipalMessage::from_message(&request.get_data().unwrap()).unwrap();
         let results = PrincipalHttpServer::find_epoch_contract_addresses(&request, &msg, &epoch_state).unwrap();
         assert_eq!(results, vec![address])
@@ -1,4 +1,5 @@
 pub mod deploy_scripts;
+pub mod epoch_rpc;
 pub mod keys_provider_http;
 pub mod principal_manager;
 pub mod principal_utils;
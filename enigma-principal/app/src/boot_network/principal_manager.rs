@@ -1,4 +1,4 @@
-use std::{fs::File, io::prelude::*, str, sync::Arc, thread};
+use std::{fs::File, io::prelude::*, net::SocketAddr, str, sync::Arc, thread};
 
 use failure::Error;
 use rustc_hex::ToHex;
@@ -14,7 +14,7 @@ use web3::{
 use envy;
 
 use enigma_crypto::EcdsaSign;
-use boot_network::{deploy_scripts, keys_provider_http::PrincipalHttpServer, principal_utils::Principal};
+use boot_network::{deploy_scripts, epoch_rpc::{self, EpochRpcServer}, keys_provider_http::PrincipalHttpServer, principal_utils::Principal};
 use enigma_tools_u::{
     attestation_service::service,
     esgx::equote::retry_quote,
@@ -65,6 +65,16 @@ pub struct PrincipalConfig {
     pub http_port: u16,
     // Number of confirmations on-chain before accepting a transaction as complete
     pub confirmations: u64,
+    // Maximum number of workers accepted in a single epoch, the enclave's default is used if not set
+    pub max_workers: Option<u32>,
+    // Address of the epoch JSON-RPC server for external tooling, e.g. "0.0.0.0:3041". The server is disabled if not set
+    pub epoch_rpc_bind: Option<String>,
+    // Requests per second the epoch JSON-RPC server answers, for all the callers together
+    pub epoch_rpc_rate_limit: Option<u32>,
+    // Submit the raw seed of the new epochs instead of keccak256(seed), for the Enigma contract versions that take the seed
+    pub raw_epoch_seed: Option<bool>,
+    // Have the enclave draw the seed of the next epoch ahead of its transition, so the transition doesn't wait for it
+    pub stage_epoch_seed: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -298,8 +308,9 @@ impl Sampler for PrincipalManager {
     /// Warms up the application.
     /// 1. Register the worker if not already registered
     /// 2. Create an `EpochProvider` which loads the local `EpochState` if available
-    /// 3. Start the JSON-RPC server
-    /// 4. Watch the blocks for new epochs
+    /// 3. Start the JSON-RPC servers
+    /// 4. Resume the epoch transition that didn't finish, if any
+    /// 5. Watch the blocks for new epochs
     ///
     /// # Arguments
     ///
@@ -313,9 +324,10 @@ impl Sampler for PrincipalManager {
         // get enigma contract
         // Start the WorkerParameterized Web3 log filter
         let eid: Arc<sgx_enclave_id_t> = Arc::new(self.eid);
-        let epoch_provider = Arc::new(EpochProvider::new(eid, path, self.contract.clone())?);
+        let epoch_provider = Arc::new(EpochProvider::new(eid, path, self.contract.clone(), self.config.raw_epoch_seed.unwrap_or(false),
+                                                        self.config.stage_epoch_seed.unwrap_or(false))?);
         if reset_epoch {
-            epoch_provider.epoch_state_manager.reset()?;
+            epoch_provider.reset()?;
         }
 
         // Start the JSON-RPC Server
@@ -326,6 +338,18 @@ impl Sampler for PrincipalManager {
             server.start();
         });
 
+        // Start the epoch JSON-RPC server for external tooling, if configured
+        if let Some(bind) = &self.config.epoch_rpc_bind {
+            let rate_limit = self.config.epoch_rpc_rate_limit.unwrap_or(epoch_rpc::DEFAULT_RATE_LIMIT);
+            let server = EpochRpcServer::new(Arc::clone(&epoch_provider), bind.parse::<SocketAddr>()?, rate_limit).start()?;
+            thread::spawn(move || server.wait());
+        }
+
+        // Finish the epoch transition we were in the middle of when we stopped
+        if let Some(tx) = epoch_provider.resume_transition(gas_limit, self.config.confirmations as usize)? {
+            info!("Resumed the epoch transition, setWorkersParams tx: {:?}", tx);
+        }
+
         // watch blocks
         let polling_interval = self.config.polling_interval;
         let epoch_size = self.config.epoch_size;
@@ -351,7 +375,7 @@ pub fn run_miner(account: Address, w3: Arc<Web3<Http>>, interval: u64) -> thread
 //////////////////////// TESTS  /////////////////////////////////////////
 
 #[cfg(test)]
-mod test {
+pub mod test {
     extern crate tempfile;
     use std::{env, path::Path, sync::Arc, sync::Mutex, thread, time};
     use self::tempfile::TempDir;
@@ -422,8 +446,8 @@ mod test {
 
         let block_number = principal.get_block_number().unwrap();
         let eid_safe = Arc::new(eid);
-        let epoch_provider = EpochProvider::new(eid_safe, tempdir.into_path(), principal.contract.clone()).unwrap();
-        epoch_provider.epoch_state_manager.reset().unwrap();
+        let epoch_provider = EpochProvider::new(eid_safe, tempdir.into_path(), principal.contract.clone(), true, false).unwrap();
+        epoch_provider.reset().unwrap();
         epoch_provider.set_worker_params(block_number, gas_limit, 0).unwrap();
     }
 
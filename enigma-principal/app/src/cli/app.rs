@@ -14,7 +14,7 @@ use cli;
 use enigma_crypto::EcdsaSign;
 use enigma_tools_u::{esgx::general::storage_dir, web3_utils::enigma_contract::EnigmaContract};
 use epoch_u::epoch_provider::EpochProvider;
-use esgx::general::ENCLAVE_DIR;
+use esgx::{epoch_keeper_u::set_max_workers, general::ENCLAVE_DIR};
 use failure::Error;
 use sgx_types::sgx_enclave_id_t;
 use std::{fs::File, io::prelude::*, path::Path, sync::Arc};
@@ -37,6 +37,9 @@ pub fn create_signer(eid: sgx_enclave_id_t, with_private_key: bool, private_key:
 pub fn start(eid: sgx_enclave_id_t) -> Result<(), Error> {
     let opt = cli::options::Opt::from_args();
     let mut principal_config = PrincipalConfig::load_config(opt.principal_config.as_str())?;
+    if let Some(max_workers) = principal_config.max_workers {
+        set_max_workers(eid, max_workers)?;
+    }
     let report_manager = ReportManager::new(principal_config.clone(), eid)?;
     let signing_address = report_manager.get_signing_address()?;
     let ethereum_address = report_manager.get_ethereum_address()?;
@@ -94,9 +97,10 @@ pub fn start(eid: sgx_enclave_id_t) -> Result<(), Error> {
 
         let eid_safe = Arc::new(eid);
         //TODO: Ugly, refactor to instantiate only once, consider passing to the run method
-        let epoch_provider = EpochProvider::new(eid_safe, path.clone(), principal.contract.clone())?;
+        let epoch_provider = EpochProvider::new(eid_safe, path.clone(), principal.contract.clone(), principal_config.raw_epoch_seed.unwrap_or(false),
+                                               principal_config.stage_epoch_seed.unwrap_or(false))?;
         if opt.reset_epoch_state {
-            epoch_provider.epoch_state_manager.reset()?;
+            epoch_provider.reset()?;
         }
         // step3 : run the principal manager
         if opt.register {
@@ -109,9 +113,11 @@ pub fn start(eid: sgx_enclave_id_t) -> Result<(), Error> {
             let tx = epoch_provider.set_worker_params(block_number, gas_limit, principal_config.confirmations as usize)?;
             println!("The setWorkersParams tx: {:?}", tx);
         } else if opt.confirm_worker_params {
-            let block_number = principal.get_block_number()?;
-            let tx = epoch_provider.confirm_worker_params(block_number, gas_limit, principal_config.confirmations as usize)?;
+            let tx = epoch_provider.confirm_worker_params(gas_limit, principal_config.confirmations as usize)?;
             println!("The setWorkersParams tx: {:?}", tx);
+        } else if opt.epoch_status {
+            let status = epoch_provider.epoch_status()?;
+            println!("{}", serde_json::to_string_pretty(&status)?);
         } else if opt.get_state_keys.is_some() {
             let request: StateKeyRequest = serde_json::from_str(&opt.get_state_keys.unwrap())?;
             let response = PrincipalHttpServer::get_state_keys(&epoch_provider, request)?;
@@ -24,6 +24,10 @@ pub struct Opt {
     #[structopt(short = "f", long = "confirm-worker-params")]
     pub confirm_worker_params: bool,
 
+    /// Print the records of the last epoch transitions, with the origins of their epochs, and shutdown
+    #[structopt(short = "e", long = "epoch-status")]
+    pub epoch_status: bool,
+
     /// Get state keys and shutdown
     #[structopt(short = "k", long = "get-state-keys")]
     pub get_state_keys: Option<String>,
@@ -111,6 +115,7 @@ pub fn print_info(signing_address: &str, ethereum_address: &str) {
     green!("--register                             => Run the Register procedure and shutdown.\n");
     green!("--set-worker-params                    => Run the Set Worker Params procedure and shutdown.\n");
     green!("--confirm-worker-params                => Confirm the Worker Params in the local state and shutdown.\n");
+    green!("--epoch-status                         => Print the last epoch transitions and the origins of their epochs, and shutdown.\n");
     green!("--get-state-keys                       => Get the state keys from the message and shutdown.\n");
     green!("--contract-address                     => The Enigma contract address, use the config if not provided.\n");
     green!("--reset-epoch-state                    => Optional: Reset the Epoch state in storage.\n");
@@ -4,6 +4,9 @@ use sgx_types::*;
 
 pub const JSON_RPC_ERROR_WORKER_NOT_AUTHORIZED: i64  =-32001;
 pub const JSON_RPC_ERROR_ILLEGAL_STATE: i64  =-32002;
+pub const JSON_RPC_ERROR_NO_WORKERS_IN_EPOCH: i64  =-32003;
+pub const JSON_RPC_ERROR_INVALID_WORKER_PARAMS: i64  =-32004;
+pub const JSON_RPC_ERROR_RATE_LIMITED: i64  =-32005;
 
 // error while requesting to produce a quote (registration)
 #[derive(Fail, Debug)]
@@ -57,3 +60,12 @@ pub struct RequestValueErr {
     pub request: String,
     pub message: String,
 }
+
+// the worker parameters are bigger than what can be sent to the enclave
+#[derive(Fail, Debug)]
+#[fail(display = "The worker parameters are {} bytes, over the limit of {} bytes ({})", len, limit, limit_name)]
+pub struct WorkerParamsTooLargeErr {
+    pub len: usize,
+    pub limit: usize,
+    pub limit_name: &'static str,
+}
@@ -8,8 +8,9 @@ use std::{
 use std::clone::Clone;
 use std::sync::MutexGuard;
 
-use enigma_tools_m::keeper_types::{InputWorkerParams, EPOCH_CAP};
-use ethabi::{Log, RawLog};
+use enigma_tools_m::eth_proof::Checkpoint;
+use enigma_tools_m::keeper_types::{EpochParams, InputWorkerParams, EPOCH_CAP};
+use ethabi::RawLog;
 use failure::Error;
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
@@ -18,12 +19,20 @@ use web3::types::{H256, TransactionReceipt, U256};
 use rustc_hex::ToHex;
 
 use common_u::errors::{EpochStateIOErr, EpochStateTransitionErr, EpochStateUndefinedErr};
+use enigma_tools_m::eth_hash::eth_hash;
 use enigma_tools_u::web3_utils::enigma_contract::{ContractFuncs, ContractQueries, EnigmaContract};
 use enigma_tools_u::common_u::errors::Web3Error;
+use epoch_u::epoch_transition::{EpochTransition, TransitionStage, TransitionStore};
 use epoch_u::epoch_types::{ConfirmedEpochState, EPOCH_STATE_UNCONFIRMED, EpochState, WORKER_PARAMETERIZED_EVENT, WorkersParameterizedEvent};
-use esgx::epoch_keeper_u::set_or_verify_worker_params;
+use esgx::epoch_keeper_u::{advance_chain, anchor_chain, dump_epoch, EpochOrigin, reveal_epoch_seed, set_or_verify_worker_params, stage_epoch_seed};
 use esgx::general::{EPOCH_DIR, EPOCH_FILE};
 use std::mem::replace;
+use std::time::Duration;
+
+/// How often the node is asked for the receipt of the `setWorkersParams` transaction
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// The headers the checkpoint of the enclave is moved along at once, their RLP must fit in an ECALL
+const ANCHOR_HEADERS_LEN: u64 = 64;
 
 #[derive(Debug)]
 pub struct EpochStateManager {
@@ -217,17 +226,36 @@ impl EpochStateManager {
     }
 }
 
+/// The transition to an epoch, with where the enclave says the epoch comes from.
+#[derive(Debug, Clone, Serialize)]
+pub struct EpochStatus {
+    #[serde(flatten)]
+    pub transition: EpochTransition,
+    /// `None` if the enclave has no origin for the epoch, it was sealed before they were recorded.
+    /// Its `sequence` orders the epochs, the enclave keeps it sealed. The `host_created_at` is only host-reported: the enclave
+    /// takes it from the host clock, only making sure it doesn't go back before the epochs it has cached. The SGX trusted
+    /// time needs the platform services, which most machines don't have.
+    pub origin: Option<EpochOrigin>,
+}
+
 pub struct EpochProvider {
     pub contract: Arc<EnigmaContract>,
     pub epoch_state_manager: Arc<EpochStateManager>,
+    pub transition_store: Arc<TransitionStore>,
     pub eid: Arc<sgx_enclave_id_t>,
+    /// Submit the seed of the new epochs instead of a commitment, for the Enigma contract versions that take the seed
+    pub raw_seed: bool,
+    /// Have the enclave draw the seed of the next epoch on startup and after each transition, see `stage_epoch_seed`
+    pub stage_seeds: bool,
 }
 
 impl EpochProvider {
-    pub fn new(eid: Arc<sgx_enclave_id_t>, dir_path: PathBuf, contract: Arc<EnigmaContract>) -> Result<EpochProvider, Error> {
-        let epoch_state_manager = Arc::new(EpochStateManager::new(dir_path, EPOCH_CAP)?);
-        let epoch_provider = Self { contract, epoch_state_manager, eid };
+    pub fn new(eid: Arc<sgx_enclave_id_t>, dir_path: PathBuf, contract: Arc<EnigmaContract>, raw_seed: bool, stage_seeds: bool) -> Result<EpochProvider, Error> {
+        let epoch_state_manager = Arc::new(EpochStateManager::new(dir_path.clone(), EPOCH_CAP)?);
+        let transition_store = Arc::new(TransitionStore::new(dir_path, EPOCH_CAP)?);
+        let epoch_provider = Self { contract, epoch_state_manager, transition_store, eid, raw_seed, stage_seeds };
         epoch_provider.verify_worker_params()?;
+        epoch_provider.stage_next_seed();
         Ok(epoch_provider)
     }
 
@@ -247,17 +275,43 @@ impl EpochProvider {
         self.epoch_state_manager.last(true)
     }
 
+    /// Find the confirmed `EpochState` of the nonce, if it's still stored
+    pub fn find_epoch_by_nonce(&self, nonce: U256) -> Result<Option<EpochState>, Error> {
+        Ok(self.epoch_state_manager.get_all_confirmed()?.into_iter().find(|epoch_state| epoch_state.nonce == nonce))
+    }
+
+    /// The `InputWorkerParams` the enclave signed the epoch with, the active workers at its `km_block_number`
+    pub fn worker_params(&self, epoch_state: &EpochState) -> Result<InputWorkerParams, Error> {
+        let km_block_number = epoch_state.km_block_number;
+        let (workers, stakes) = self.contract.get_active_workers(km_block_number)?;
+        Ok(InputWorkerParams { km_block_number, workers, stakes })
+    }
+
+    /// Empty both the `EpochState` list and the transition records
+    pub fn reset(&self) -> Result<(), Error> {
+        self.epoch_state_manager.reset()?;
+        self.transition_store.reset()
+    }
+
+    /// The kept transitions, newest first, each with the origin the enclave recorded for its epoch
+    pub fn epoch_status(&self) -> Result<Vec<EpochStatus>, Error> {
+        self.transition_store.all()?.into_iter().map(|transition| {
+            let origin = match dump_epoch(*self.eid, transition.nonce) {
+                Ok(origin) => origin,
+                Err(err) => {
+                    warn!("No origin for epoch {}: {}", transition.nonce, err);
+                    None
+                }
+            };
+            Ok(EpochStatus { transition, origin })
+        }).collect()
+    }
+
     #[logfn(DEBUG)]
-    fn parse_worker_parameterized(&self, receipt: &TransactionReceipt) -> Result<Log, Error> {
+    fn parse_worker_parameterized(&self, receipt: &TransactionReceipt) -> Result<EpochParams, Error> {
         let log = receipt.logs[0].clone();
         let raw_log = RawLog { topics: log.topics, data: log.data.0 };
-        let event = WorkersParameterizedEvent::new();
-        let result = match event.0.parse_log(raw_log) {
-            Ok(result) => result,
-            Err(err) => return Err(Web3Error {
-                message: format!("Unable to parse {} event: {:?}", WORKER_PARAMETERIZED_EVENT, err),
-            }.into()),
-        };
+        let result = WorkersParameterizedEvent::new().parse(&raw_log)?;
         debug!("Parsed the {} event: {:?}", WORKER_PARAMETERIZED_EVENT, result);
         Ok(result)
     }
@@ -267,17 +321,16 @@ impl EpochProvider {
         for epoch_state in self.epoch_state_manager.get_all_confirmed()?.iter() {
             // if the epoch is confirmed by the Enigma Contract
             if let Some(_) = &epoch_state.confirmed_state {
-                // Get the km_block_number which indicates where to take the list of active workers from
-                let km_block_number = epoch_state.km_block_number;
-                let (workers, stakes) = self.contract.get_active_workers(km_block_number)?;
-                let worker_params = InputWorkerParams { km_block_number, workers, stakes };
-                set_or_verify_worker_params(*self.eid, &worker_params, Some(epoch_state.clone()))?;
+                let worker_params = self.worker_params(epoch_state)?;
+                set_or_verify_worker_params(*self.eid, &worker_params, Some(epoch_state.clone()), self.raw_seed)?;
             }
         }
         Ok(())
     }
 
     /// Seal the epoch data in the enclave, get a random seed and submit to the Enigma contract
+    /// Unless `raw_seed` is set only `keccak256(seed)` is submitted, the enclave reveals the seed once the transaction
+    /// is confirmed and the `EpochState` is confirmed with it.
     /// The enclave signs on:
     ///  - The worker parameters active at the specified block number
     ///  - The random seed generated by the enclave
@@ -290,60 +343,209 @@ impl EpochProvider {
     ///    the enclave operator from tempering with worker parameters in order to modify the
     ///    result of the worker selection.
     ///
+    /// If a previous transition didn't finish it's resumed instead, and no new seed is generated.
+    ///
     /// # Arguments
     ///
     /// * `block_number` - The block number marking the active worker list
     /// * `gas_limit` - The gas limit of the `setWorkersParams` transaction
     /// * `confirmations` - The number of blocks required to confirm the `setWorkersParams` transaction
     pub fn set_worker_params<G: Into<U256>>(&self, block_number: U256, gas_limit: G, confirmations: usize) -> Result<H256, Error> {
-        self.set_worker_params_internal(block_number, gas_limit, confirmations, None)
+        let gas_limit: U256 = gas_limit.into();
+        if let Some(tx_hash) = self.resume_transition(gas_limit, confirmations)? {
+            warn!("Resumed an unfinished epoch transition instead of starting the one of block {}", block_number);
+            return Ok(tx_hash);
+        }
+        let transition = self.start_transition(block_number, confirmations)?;
+        self.drive_transition(transition, gas_limit, confirmations)
     }
 
-    /// Similar to `set_worker_params` but using the EpochState in storage
+    /// Confirm the unconfirmed `EpochState` in storage, resuming its transition
     ///
     /// # Arguments
     ///
-    /// * `block_number` - The block number marking the active worker list
     /// * `gas_limit` - The gas limit of the `setWorkersParams` transaction
     /// * `confirmations` - The number of blocks required to confirm the `setWorkersParams` transaction
     #[logfn(DEBUG)]
-    pub fn confirm_worker_params<G: Into<U256>>(&self, block_number: U256, gas_limit: G, confirmations: usize) -> Result<H256, Error> {
-        if !self.epoch_state_manager.is_last_unconfirmed()? {
-            bail!("The last EpochState is already confirmed");
+    pub fn confirm_worker_params<G: Into<U256>>(&self, gas_limit: G, confirmations: usize) -> Result<H256, Error> {
+        match self.resume_transition(gas_limit, confirmations)? {
+            Some(tx_hash) => Ok(tx_hash),
+            None => bail!("The last EpochState is already confirmed"),
         }
-        let epoch_state = self.epoch_state_manager.last(false)?;
-        info!("Confirming EpochState by verifying with the enclave and calling setWorkerParams: {:?}", epoch_state);
-        self.set_worker_params_internal(block_number, gas_limit, confirmations, Some(epoch_state))
     }
 
+    /// Drive the unfinished transition, if any, to its end. Called on startup so a crash in the middle of a transition
+    /// picks up where it stopped: a stored transaction is sent again as is and waited for, a stored seed is submitted.
+    /// Returns the hash of the `setWorkersParams` transaction if there was something to resume.
+    ///
+    /// # Arguments
+    ///
+    /// * `gas_limit` - The gas limit of the `setWorkersParams` transaction
+    /// * `confirmations` - The number of blocks required to confirm the `setWorkersParams` transaction
+    #[logfn(DEBUG)]
+    pub fn resume_transition<G: Into<U256>>(&self, gas_limit: G, confirmations: usize) -> Result<Option<H256>, Error> {
+        let gas_limit: U256 = gas_limit.into();
+        let transition = match self.transition_store.in_progress()? {
+            Some(transition) => transition,
+            // The seed was generated but we stopped before recording it.
+            None if self.epoch_state_manager.is_last_unconfirmed()? => {
+                let epoch_state = self.epoch_state_manager.last(false)?;
+                if let Some(failed) = self.transition_store.last()?.filter(|t| t.nonce == epoch_state.nonce) {
+                    bail!("The transition to epoch {} failed ({}), the epoch state must be recovered manually", failed.nonce, failed.error.unwrap_or_default());
+                }
+                let epoch_state = if epoch_state.awaiting_reveal() {
+                    // Without its seed the enclave verifies the epoch when it reveals it
+                    info!("Confirming EpochState by calling setWorkerParams with its commitment: {:?}", epoch_state);
+                    epoch_state
+                } else {
+                    info!("Confirming EpochState by verifying with the enclave and calling setWorkerParams: {:?}", epoch_state);
+                    let (workers, stakes) = self.contract.get_active_workers(epoch_state.km_block_number)?;
+                    let worker_params = InputWorkerParams { km_block_number: epoch_state.km_block_number, workers, stakes };
+                    set_or_verify_worker_params(*self.eid, &worker_params, Some(epoch_state), self.raw_seed)?
+                };
+                let transition = EpochTransition::new(epoch_state.nonce, epoch_state.km_block_number);
+                self.transition_store.save(&transition)?;
+                transition
+            }
+            None => return Ok(None),
+        };
+        info!("Resuming the transition to epoch {} from {:?}", transition.nonce, transition.stage);
+        self.drive_transition(transition, gas_limit, confirmations).map(Some)
+    }
+
+    /// Generate the seed of a new epoch and record the transition
     #[logfn(DEBUG)]
-    fn set_worker_params_internal<G: Into<U256>>(&self, km_block_number: U256, gas_limit: G, confirmations: usize, epoch_state: Option<EpochState>) -> Result<H256, Error> {
+    fn start_transition(&self, km_block_number: U256, confirmations: usize) -> Result<EpochTransition, Error> {
+        if !self.raw_seed {
+            // Before the first commitment is sent, the enclave only takes the receipts of the blocks after its anchor
+            self.anchor_chain(km_block_number.low_u64(), confirmations)?;
+        }
         let (workers, stakes) = self.contract.get_active_workers(km_block_number)?;
         let worker_params = InputWorkerParams { km_block_number, workers, stakes };
-        let mut epoch_state = set_or_verify_worker_params(*self.eid, &worker_params, epoch_state)?;
+        let epoch_state = set_or_verify_worker_params(*self.eid, &worker_params, None, self.raw_seed)?;
 
         debug!("Storing unconfirmed EpochState: {:?}", epoch_state);
         self.epoch_state_manager.append_unconfirmed(epoch_state.clone())?;
+        let transition = EpochTransition::new(epoch_state.nonce, km_block_number);
+        self.transition_store.save(&transition)?;
+        Ok(transition)
+    }
 
-        debug!("Waiting for setWorkerParams({:?}, {:?}, {:?})", km_block_number, epoch_state.seed, epoch_state.sig);
-        let receipt = self.contract.set_workers_params(km_block_number, epoch_state.seed, epoch_state.sig.clone(), gas_limit, confirmations)?;
-        debug!("Got the receipt: {:?}", receipt);
+    /// Run the transition from its current stage until it's done
+    fn drive_transition(&self, mut transition: EpochTransition, gas_limit: U256, confirmations: usize) -> Result<H256, Error> {
+        while !transition.stage.is_done() {
+            transition = self.transition_step(transition, gas_limit, confirmations)?;
+        }
+        self.stage_next_seed();
+        match transition.stage {
+            TransitionStage::Failed => Err(Web3Error {
+                message: format!("The transition to epoch {} failed: {}", transition.nonce, transition.error.unwrap_or_default()),
+            }.into()),
+            _ => Ok(transition.tx_hash.unwrap_or_default()),
+        }
+    }
+
+    // A seed that can't be staged is drawn by the transition that needs it, like without staging.
+    fn stage_next_seed(&self) {
+        if !self.stage_seeds {
+            return;
+        }
+        match stage_epoch_seed(*self.eid) {
+            Ok(nonce) => debug!("Staged the seed of epoch {}", nonce),
+            Err(err) => warn!("Failed staging the seed of the next epoch: {}", err),
+        }
+    }
 
-        let log = self.parse_worker_parameterized(&receipt)?;
-        match log.params.into_iter().find(|x| x.name == "firstBlockNumber") {
-            Some(param) => {
-                let ether_block_number = param.value.to_uint().unwrap();
-                if ether_block_number < km_block_number {
-                    return Err(Web3Error { message: "The block number given by the Enigma Contract is smaller than the one defined by the KM".to_string() }.into());
+    /// Move the transition to its next stage, the new stage is stored before it's returned
+    pub fn transition_step(&self, mut transition: EpochTransition, gas_limit: U256, confirmations: usize) -> Result<EpochTransition, Error> {
+        match transition.stage {
+            TransitionStage::SeedGenerated => {
+                let epoch_state = self.unconfirmed_state(&transition)?;
+                // The contracts taking a commitment get it in place of the seed
+                let seed = match epoch_state.commitment {
+                    Some(commitment) => U256::from_big_endian(&commitment.0),
+                    None => epoch_state.seed,
+                };
+                let signed_tx = self.contract.sign_workers_params(transition.km_block_number, seed, epoch_state.sig.clone(), gas_limit)?;
+                let tx_hash = eth_hash(&signed_tx.0);
+                // Stored before it's sent, so a restart sends this transaction again instead of signing another one.
+                transition.tx_sent(tx_hash, signed_tx);
+                self.transition_store.save(&transition)?;
+                self.contract.send_raw_transaction_once(transition.signed_tx.as_ref().unwrap())?;
+            }
+            TransitionStage::TxSent => {
+                let signed_tx = transition.signed_tx.clone().ok_or_else(|| EpochStateIOErr { message: "The sent transaction isn't stored".to_string() })?;
+                let tx_hash = self.contract.send_raw_transaction_once(&signed_tx)?;
+                debug!("Waiting for setWorkerParams {:?}", tx_hash);
+                let receipt = self.contract.wait_for_receipt(tx_hash, confirmations, RECEIPT_POLL_INTERVAL)?;
+                debug!("Got the receipt: {:?}", receipt);
+                if receipt.status == Some(0.into()) {
+                    transition.failed(receipt.block_number, "The setWorkersParams transaction was reverted".to_string());
+                } else {
+                    let ether_block_number = self.confirm_transition(&transition, &receipt, confirmations)?;
+                    transition.confirmed(receipt.block_number, ether_block_number);
                 }
-                self.confirm_epoch(&mut epoch_state, ether_block_number, worker_params)?;
-                debug!("Storing confirmed epoch state: {:?}", epoch_state);
+                self.transition_store.save(&transition)?;
+            }
+            TransitionStage::Confirmed | TransitionStage::Failed => (),
+        }
+        Ok(transition)
+    }
 
-                self.epoch_state_manager.confirm_last(epoch_state)?;
-                Ok(receipt.transaction_hash)
+    // The stored `EpochState` the transition is for.
+    fn unconfirmed_state(&self, transition: &EpochTransition) -> Result<EpochState, Error> {
+        let epoch_state = self.epoch_state_manager.last(false)?;
+        if epoch_state.nonce != transition.nonce {
+            return Err(EpochStateIOErr {
+                message: format!("The last EpochState is of epoch {}, not of the transition to {}", epoch_state.nonce, transition.nonce),
+            }.into());
+        }
+        Ok(epoch_state)
+    }
+
+    /// Anchors the enclave to the Enigma contract unless it is already, with a confirmed block before `block_number`
+    /// as its checkpoint, and moves the checkpoint along the headers until a receipt in `block_number` is at most
+    /// `ANCHOR_HEADERS_LEN` blocks after it, returns the checkpoint.
+    /// A checkpoint at or after `block_number` is returned as it is, the receipts of that block can't be proven with it.
+    fn anchor_chain(&self, block_number: u64, confirmations: usize) -> Result<Checkpoint, Error> {
+        let proposed = self.contract.checkpoint(block_number.saturating_sub(confirmations as u64 + 1))?;
+        let mut checkpoint = anchor_chain(*self.eid, self.contract.address(), proposed, confirmations as u64)?;
+        while block_number > checkpoint.number + ANCHOR_HEADERS_LEN {
+            let headers = self.contract.block_headers(checkpoint.number + 1, checkpoint.number + ANCHOR_HEADERS_LEN)?;
+            let next = advance_chain(*self.eid, &headers)?;
+            if next == checkpoint {
+                bail!("The checkpoint of the enclave can't move along {} headers with {} confirmations", ANCHOR_HEADERS_LEN, confirmations);
+            }
+            debug!("Moved the checkpoint of the enclave to block {}", next.number);
+            checkpoint = next;
+        }
+        Ok(checkpoint)
+    }
+
+    // Verify the receipt and confirm the `EpochState`, returns the first block of the epoch.
+    fn confirm_transition(&self, transition: &EpochTransition, receipt: &TransactionReceipt, confirmations: usize) -> Result<U256, Error> {
+        let ether_block_number = self.parse_worker_parameterized(receipt)?.first_block_number;
+        if ether_block_number < transition.km_block_number {
+            return Err(Web3Error { message: "The block number given by the Enigma Contract is smaller than the one defined by the KM".to_string() }.into());
+        }
+        let mut epoch_state = self.unconfirmed_state(transition)?;
+        // We may have stopped after confirming it but before recording it.
+        if epoch_state.confirmed_state.is_none() {
+            let (workers, stakes) = self.contract.get_active_workers(transition.km_block_number)?;
+            let worker_params = InputWorkerParams { km_block_number: transition.km_block_number, workers, stakes };
+            if epoch_state.awaiting_reveal() {
+                // The commitment has its confirmations, the enclave checks them itself before it reveals the seed
+                let block_number = receipt.block_number.ok_or_else(|| Web3Error { message: "The receipt isn't mined".to_string() })?;
+                let checkpoint = self.anchor_chain(block_number.low_u64(), confirmations)?;
+                let proof = self.contract.receipt_proof(receipt.transaction_hash, &checkpoint, confirmations as u64)?;
+                let seed = reveal_epoch_seed(*self.eid, &epoch_state, &worker_params, &proof)?;
+                epoch_state.reveal(seed)?;
             }
-            None => return Err(Web3Error { message: "firstBlockNumber not found in receipt log".to_string() }.into()),
+            self.confirm_epoch(&mut epoch_state, ether_block_number, worker_params)?;
+            debug!("Storing confirmed epoch state: {:?}", epoch_state);
+            self.epoch_state_manager.confirm_last(epoch_state)?;
         }
+        Ok(ether_block_number)
     }
 
     /// Build a local mapping of smart contract address => selected worker for the epoch
@@ -374,8 +576,13 @@ pub mod test {
 
     use web3::types::{Bytes, H160};
 
+    use web3::types::{BlockNumber, FilterBuilder};
+    use web3::futures::Future;
+
     use enigma_tools_u::{esgx::general::storage_dir};
     use enigma_types::ContractAddress;
+    use boot_network::principal_manager::{Sampler, test::init_no_deploy};
+    use esgx::general::init_enclave_wrapper;
 
     use super::*;
 
@@ -407,7 +614,7 @@ pub mod test {
         let nonce = U256::from(0);
         let km_block_number = U256::from(2);
 
-        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state };
+        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state, commitment: None };
         epoch_manager_calculated.append_unconfirmed(epoch_state.clone()).unwrap();
 
         let epoch_manager_accepted = EpochStateManager::new(path, cap).unwrap();
@@ -432,7 +639,7 @@ pub mod test {
         let nonce = U256::from(0);
         let km_block_number = U256::from(4);
 
-        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state };
+        let epoch_state = EpochState { seed, sig, nonce, km_block_number, confirmed_state, commitment: None };
         epoch_manager_calculated.append_unconfirmed(epoch_state.clone()).unwrap();
 
         epoch_manager_calculated.reset().unwrap();
@@ -442,4 +649,90 @@ pub mod test {
 
 
     }
+
+    /// Where the principal is stopped in the middle of a transition
+    #[derive(Debug, Clone, Copy)]
+    enum Crash {
+        /// The enclave generated the seed but the transition wasn't recorded yet
+        BeforeRecord,
+        AfterSeedGenerated,
+        /// The transaction was signed and recorded but never reached the node
+        BeforeSend,
+        AfterSend,
+    }
+
+    fn count_parameterized_events(contract: &EnigmaContract) -> usize {
+        let filter = FilterBuilder::default()
+            .address(vec![contract.address()])
+            .topics(Some(vec![WorkersParameterizedEvent::new().0.signature().into()]), None, None, None)
+            .from_block(BlockNumber::Earliest)
+            .build();
+        contract.web3.eth().logs(filter).wait().unwrap().len()
+    }
+
+    // Runs a new transition up to the crash, returns the nonce of its epoch and the hash of the transaction if it was signed.
+    fn run_until(provider: &EpochProvider, crash: Crash, block_number: U256, gas_limit: U256) -> (U256, Option<H256>) {
+        if let Crash::BeforeRecord = crash {
+            let (workers, stakes) = provider.contract.get_active_workers(block_number).unwrap();
+            let worker_params = InputWorkerParams { km_block_number: block_number, workers, stakes };
+            let epoch_state = set_or_verify_worker_params(*provider.eid, &worker_params, None, provider.raw_seed).unwrap();
+            provider.epoch_state_manager.append_unconfirmed(epoch_state.clone()).unwrap();
+            return (epoch_state.nonce, None);
+        }
+        let mut transition = provider.start_transition(block_number, 0).unwrap();
+        match crash {
+            Crash::BeforeSend => {
+                let epoch_state = provider.unconfirmed_state(&transition).unwrap();
+                let signed_tx = provider.contract.sign_workers_params(block_number, epoch_state.seed, epoch_state.sig, gas_limit).unwrap();
+                transition.tx_sent(eth_hash(&signed_tx.0), signed_tx);
+                provider.transition_store.save(&transition).unwrap();
+            }
+            Crash::AfterSend => transition = provider.transition_step(transition, gas_limit, 0).unwrap(),
+            _ => (),
+        }
+        (transition.nonce, transition.tx_hash)
+    }
+
+    /// Stops a transition at each stage and resumes it with a new `EpochProvider` over the same storage, like a restart.
+    /// Every transition must end up confirmed with exactly one `setWorkersParams` mined, and the same one if it was already signed.
+    /// Requires the Enigma contract to be deployed, like the tests of `principal_manager`.
+    #[test]
+    #[ignore]
+    fn test_transition_resumes_exactly_once() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let eid = enclave.geteid();
+        let principal = init_no_deploy(eid).unwrap();
+        let gas_limit: U256 = 5_999_999.into();
+        principal.verify_identity_or_register(gas_limit).unwrap();
+        let path = setup_epoch_storage_dir();
+        let restart = || EpochProvider::new(Arc::new(eid), path.clone(), principal.contract.clone(), true, false).unwrap();
+        restart().reset().unwrap();
+
+        for &crash in &[Crash::BeforeRecord, Crash::AfterSeedGenerated, Crash::BeforeSend, Crash::AfterSend] {
+            let events = count_parameterized_events(&principal.contract);
+            let block_number = principal.get_block_number().unwrap();
+            let (nonce, tx_hash) = run_until(&restart(), crash, block_number, gas_limit);
+
+            let provider = restart();
+            let resumed = provider.resume_transition(gas_limit, 0).unwrap();
+            let record = provider.transition_store.last().unwrap().unwrap();
+            assert_eq!(record.stage, TransitionStage::Confirmed, "{:?}", crash);
+            assert_eq!(record.nonce, nonce, "{:?}", crash);
+            assert_eq!(resumed, record.tx_hash, "{:?}", crash);
+            if tx_hash.is_some() {
+                assert_eq!(record.tx_hash, tx_hash, "{:?}", crash);
+            }
+            assert_eq!(provider.find_last_epoch().unwrap().nonce, nonce, "{:?}", crash);
+            assert_eq!(count_parameterized_events(&principal.contract), events + 1, "{:?}", crash);
+
+            // Nothing is left to resume.
+            assert_eq!(restart().resume_transition(gas_limit, 0).unwrap(), None, "{:?}", crash);
+        }
+        let provider = restart();
+        let nonce = provider.transition_store.last().unwrap().unwrap().nonce;
+        provider.set_worker_params(principal.get_block_number().unwrap(), gas_limit, 0).unwrap();
+        assert_eq!(provider.transition_store.last().unwrap().unwrap().nonce, nonce + 1);
+        assert_eq!(provider.transition_store.all().unwrap().len(), EPOCH_CAP);
+        enclave.destroy();
+    }
 }
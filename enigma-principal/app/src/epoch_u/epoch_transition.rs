@@ -0,0 +1,215 @@
+use std::{
+    fs::{self, File},
+    io::prelude::*,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use failure::Error;
+use web3::types::{Bytes, H256, U256};
+
+use common_u::errors::EpochStateIOErr;
+use esgx::general::{EPOCH_DIR, EPOCH_TRANSITIONS_FILE};
+
+/// Where the transition to a new epoch got to.
+/// Every stage is persisted before the step that follows it, so a restart picks up from the last one stored:
+/// `SeedGenerated` -> `TxSent` -> `Confirmed` (or `Failed`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransitionStage {
+    /// The enclave sealed the new seed and the unconfirmed `EpochState` is stored, the transaction isn't signed yet.
+    SeedGenerated,
+    /// The `setWorkersParams` transaction is signed and stored, it may or may not have reached the node.
+    /// A restart sends the same transaction again if the node doesn't know it, and waits for its confirmations.
+    TxSent,
+    /// The receipt was verified and the `EpochState` is confirmed.
+    Confirmed,
+    /// The transaction was mined but reverted, the epoch state has to be recovered manually.
+    Failed,
+}
+
+impl TransitionStage {
+    pub fn is_done(self) -> bool { self == TransitionStage::Confirmed || self == TransitionStage::Failed }
+}
+
+/// The record of the transition to the epoch of `nonce`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EpochTransition {
+    pub nonce: U256,
+    pub km_block_number: U256,
+    pub stage: TransitionStage,
+    pub tx_hash: Option<H256>,
+    /// The signed `setWorkersParams` transaction, never signed twice so its nonce can only be used once.
+    pub signed_tx: Option<Bytes>,
+    /// The block the transaction was mined in.
+    pub tx_block_number: Option<U256>,
+    /// The first block of the epoch, as given by the `WorkersParameterized` event.
+    pub ether_block_number: Option<U256>,
+    /// Why the transition failed.
+    pub error: Option<String>,
+    /// Seconds since the unix epoch.
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+fn now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default() }
+
+impl EpochTransition {
+    pub fn new(nonce: U256, km_block_number: U256) -> Self {
+        let started_at = now();
+        EpochTransition {
+            nonce,
+            km_block_number,
+            stage: TransitionStage::SeedGenerated,
+            tx_hash: None,
+            signed_tx: None,
+            tx_block_number: None,
+            ether_block_number: None,
+            error: None,
+            started_at,
+            updated_at: started_at,
+        }
+    }
+
+    pub fn tx_sent(&mut self, tx_hash: H256, signed_tx: Bytes) {
+        self.tx_hash = Some(tx_hash);
+        self.signed_tx = Some(signed_tx);
+        self.advance(TransitionStage::TxSent);
+    }
+
+    pub fn confirmed(&mut self, tx_block_number: Option<U256>, ether_block_number: U256) {
+        self.tx_block_number = tx_block_number;
+        self.ether_block_number = Some(ether_block_number);
+        self.advance(TransitionStage::Confirmed);
+    }
+
+    pub fn failed(&mut self, tx_block_number: Option<U256>, error: String) {
+        self.tx_block_number = tx_block_number;
+        self.error = Some(error);
+        self.advance(TransitionStage::Failed);
+    }
+
+    fn advance(&mut self, stage: TransitionStage) {
+        debug!("Epoch transition {} moved from {:?} to {:?}", self.nonce, self.stage, stage);
+        self.stage = stage;
+        self.updated_at = now();
+    }
+}
+
+/// The transition records of the last `cap` epochs, persisted as JSON next to the `EpochState` list.
+#[derive(Debug)]
+pub struct TransitionStore {
+    records: Mutex<Vec<EpochTransition>>,
+    cap: usize,
+    path: PathBuf,
+}
+
+impl TransitionStore {
+    pub fn new(mut path: PathBuf, cap: usize) -> Result<Self, Error> {
+        path.push(EPOCH_DIR);
+        fs::create_dir_all(&path)?;
+        path.push(EPOCH_TRANSITIONS_FILE);
+        let records = match File::open(&path) {
+            Ok(mut f) => {
+                let mut buf = Vec::new();
+                f.read_to_end(&mut buf)?;
+                serde_json::from_slice(&buf)
+                    .map_err(|e| EpochStateIOErr { message: format!("Unable to read the epoch transitions: {}", e) })?
+            }
+            Err(_) => {
+                trace!("No existing epoch transitions");
+                vec![]
+            }
+        };
+        Ok(TransitionStore { records: Mutex::new(records), cap, path })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Vec<EpochTransition>>, Error> {
+        self.records.lock().map_err(|err| EpochStateIOErr { message: format!("Cannot lock the epoch transitions: {:?}", err) }.into())
+    }
+
+    /// The most recent transition, if any.
+    pub fn last(&self) -> Result<Option<EpochTransition>, Error> { Ok(self.lock()?.last().cloned()) }
+
+    /// The most recent transition if it isn't done.
+    pub fn in_progress(&self) -> Result<Option<EpochTransition>, Error> {
+        Ok(self.last()?.filter(|transition| !transition.stage.is_done()))
+    }
+
+    /// All the kept transitions, newest first.
+    pub fn all(&self) -> Result<Vec<EpochTransition>, Error> { Ok(self.lock()?.iter().rev().cloned().collect()) }
+
+    /// Stores the record, replacing the one of the same epoch.
+    pub fn save(&self, transition: &EpochTransition) -> Result<(), Error> {
+        let mut guard = self.lock()?;
+        match guard.iter_mut().find(|t| t.nonce == transition.nonce) {
+            Some(existing) => *existing = transition.clone(),
+            None => {
+                if guard.len() == self.cap {
+                    guard.remove(0);
+                }
+                guard.push(transition.clone());
+            }
+        }
+        self.store(&guard)
+    }
+
+    pub fn reset(&self) -> Result<(), Error> {
+        let mut guard = self.lock()?;
+        guard.clear();
+        self.store(&guard)
+    }
+
+    // Written to a temporary file and renamed, a crash in the middle can't leave a truncated record behind.
+    fn store(&self, records: &[EpochTransition]) -> Result<(), Error> {
+        let to_io_err = |e: &dyn ::std::fmt::Display| EpochStateIOErr { message: format!("Unable to write the epoch transitions: {}", e) };
+        let tmp_path = self.path.with_extension("tmp");
+        let buf = serde_json::to_vec_pretty(records).map_err(|e| to_io_err(&e))?;
+        let mut file = File::create(&tmp_path).map_err(|e| to_io_err(&e))?;
+        file.write_all(&buf).and_then(|_| file.sync_all()).map_err(|e| to_io_err(&e))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| to_io_err(&e))?;
+        trace!("Saved {} epoch transitions to {:?}", records.len(), self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use epoch_u::epoch_provider::test::setup_epoch_storage_dir;
+
+    #[test]
+    fn test_transition_persisted() {
+        let path = setup_epoch_storage_dir();
+        let store = TransitionStore::new(path.clone(), 2).unwrap();
+        assert_eq!(store.last().unwrap(), None);
+
+        let mut transition = EpochTransition::new(U256::from(1), U256::from(10));
+        store.save(&transition).unwrap();
+        transition.tx_sent(H256::from([7u8; 32]), Bytes::from(vec![1, 2, 3]));
+        store.save(&transition).unwrap();
+
+        // A restart finds the transition where it was left.
+        let reloaded = TransitionStore::new(path.clone(), 2).unwrap();
+        assert_eq!(reloaded.in_progress().unwrap(), Some(transition.clone()));
+
+        transition.confirmed(Some(U256::from(11)), U256::from(11));
+        reloaded.save(&transition).unwrap();
+        let reloaded = TransitionStore::new(path, 2).unwrap();
+        assert_eq!(reloaded.in_progress().unwrap(), None);
+        assert_eq!(reloaded.all().unwrap(), vec![transition]);
+    }
+
+    #[test]
+    fn test_transitions_capped() {
+        let store = TransitionStore::new(setup_epoch_storage_dir(), 2).unwrap();
+        for nonce in 0..3u64 {
+            store.save(&EpochTransition::new(U256::from(nonce), U256::from(nonce * 10))).unwrap();
+        }
+        let nonces: Vec<U256> = store.all().unwrap().iter().map(|t| t.nonce).collect();
+        assert_eq!(nonces, vec![U256::from(2), U256::from(1)]);
+
+        store.reset().unwrap();
+        assert!(store.all().unwrap().is_empty());
+    }
+}
@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use rustc_hex::ToHex;
 
-use enigma_tools_m::keeper_types::InputWorkerParams;
-use ethabi::{Event, EventParam, ParamType};
+use enigma_tools_m::{eth_hash::eth_hash, keeper_types::{EpochParams, InputWorkerParams, WORKERS_PARAMETERIZED_PARAMS}, ToolsError};
+use enigma_tools_u::common_u::errors::Web3Error;
+use ethabi::{Event, EventParam, RawLog};
 use failure::Error;
 pub use rlp::{decode, Encodable, encode, RlpStream};
 use serde::{Deserialize, Serialize};
-use web3::types::{Address, Bytes, H160, U256};
+use web3::types::{Address, Bytes, H160, H256, U256};
 
 use enigma_types::ContractAddress;
 use enigma_types::Hash256;
@@ -25,7 +26,10 @@ pub struct ConfirmedEpochState {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EpochState {
+    /// Zero until it's revealed if the epoch was created with a commitment
     pub seed: U256,
+    /// The enclave signature over the epoch (see `EpochSeed`), or over its commitment (see `EpochSeedCommitment`),
+    /// with a low `s` and `v` of 27/28
     pub sig: Bytes,
     pub nonce: U256,
     /// The km_block_number is the block in which the KM decided to start a new epoch and
@@ -33,11 +37,33 @@ pub struct EpochState {
     /// (It might differ from the ether_block_number due to latency in networks)
     pub km_block_number: U256,
     pub confirmed_state: Option<ConfirmedEpochState>,
+    /// `keccak256(seed)`, for the epochs created with a commitment instead of the raw seed
+    #[serde(default)]
+    pub commitment: Option<H256>,
 }
 
 impl EpochState {
     pub fn new(seed: U256, sig: Bytes, nonce: U256, km_block_number: U256) -> Self {
-        Self { seed, sig, nonce, km_block_number, confirmed_state: None }
+        Self { seed, sig, nonce, km_block_number, confirmed_state: None, commitment: None }
+    }
+
+    /// An epoch created with a commitment, its seed is unknown until the enclave reveals it
+    pub fn committed(commitment: H256, sig: Bytes, nonce: U256, km_block_number: U256) -> Self {
+        Self { seed: U256::zero(), sig, nonce, km_block_number, confirmed_state: None, commitment: Some(commitment) }
+    }
+
+    /// True if the epoch was created with a commitment and its seed wasn't revealed yet
+    pub fn awaiting_reveal(&self) -> bool { self.commitment.is_some() && self.seed.is_zero() }
+
+    /// Sets the seed revealed by the enclave, after checking it against the commitment
+    pub fn reveal(&mut self, seed: U256) -> Result<(), Error> {
+        let commitment = self.commitment.ok_or_else(|| failure::err_msg("The epoch wasn't created with a commitment"))?;
+        let seed_bytes: [u8; 32] = seed.into();
+        if eth_hash(&seed_bytes) != commitment {
+            bail!("The seed revealed for epoch {} doesn't match its commitment", self.nonce);
+        }
+        self.seed = seed;
+        Ok(())
     }
 
     /// Build a local mapping of smart contract address => selected worker for the epoch
@@ -54,16 +80,18 @@ impl EpochState {
         let mut selected_workers: HashMap<ContractAddress, Address> = HashMap::new();
         for sc_address in sc_addresses {
             match worker_params.get_selected_worker(sc_address, self.seed) {
-                Some(worker) => {
+                Ok(worker) => {
                     trace!("Found selected worker: {:?} for contract: {:?}", worker, sc_address.to_hex());
                     match selected_workers.insert(sc_address, worker) {
                         Some(prev) => trace!("Selected worker inserted after: {:?}", prev),
                         None => trace!("First selected worker inserted"),
                     }
                 }
-                None => {
-                    trace!("Selected worker not found for contract: {:?}", sc_address.to_hex());
+                Err(ToolsError::NoWorkersInEpoch) => {
+                    warn!("No workers in the epoch, no contract will have a selected worker");
+                    break;
                 }
+                Err(e) => return Err(e.into()),
             }
         }
         self.confirmed_state = Some(ConfirmedEpochState { selected_workers, ether_block_number });
@@ -97,18 +125,97 @@ impl EpochState {
 pub struct WorkersParameterizedEvent(pub Event);
 
 impl WorkersParameterizedEvent {
+    /// The params are the ones of `EpochParams`, the enclave decodes the event with the same definition.
     pub fn new() -> Self {
-        WorkersParameterizedEvent(Event {
-            name: WORKER_PARAMETERIZED_EVENT.to_string(),
-            inputs: vec![
-                EventParam { name: "seed".to_string(), kind: ParamType::Uint(256), indexed: false },
-                EventParam { name: "firstBlockNumber".to_string(), kind: ParamType::Uint(256), indexed: false },
-                EventParam { name: "inclusionBlockNumber".to_string(), kind: ParamType::Uint(256), indexed: false },
-                EventParam { name: "workers".to_string(), kind: ParamType::Array(Box::new(ParamType::Address)), indexed: false },
-                EventParam { name: "stakes".to_string(), kind: ParamType::Array(Box::new(ParamType::Uint(256))), indexed: false },
-                EventParam { name: "nonce".to_string(), kind: ParamType::Uint(256), indexed: false },
-            ],
-            anonymous: false,
-        })
+        let inputs = WORKERS_PARAMETERIZED_PARAMS
+            .iter()
+            .zip(EpochParams::param_types())
+            .map(|(name, kind)| EventParam { name: name.to_string(), kind, indexed: false })
+            .collect();
+        WorkersParameterizedEvent(Event { name: WORKER_PARAMETERIZED_EVENT.to_string(), inputs, anonymous: false })
+    }
+
+    /// Decodes a `WorkersParameterized` log of the Enigma contract
+    pub fn parse(&self, log: &RawLog) -> Result<EpochParams, Error> {
+        if log.topics.first() != Some(&self.0.signature()) {
+            return Err(Web3Error { message: format!("The log isn't a {} event: {:?}", WORKER_PARAMETERIZED_EVENT, log.topics) }.into());
+        }
+        let params = EpochParams::decode(&log.data)
+            .map_err(|err| Web3Error { message: format!("Unable to parse {} event: {}", WORKER_PARAMETERIZED_EVENT, err) })?;
+        Ok(params)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ethabi::Token;
+    use serde_json;
+
+    use super::*;
+
+    fn log(params: &EpochParams) -> RawLog {
+        RawLog { topics: vec![WorkersParameterizedEvent::new().0.signature()], data: params.encode() }
+    }
+
+    fn params() -> EpochParams {
+        EpochParams {
+            seed: U256::from(0xabcd),
+            first_block_number: U256::from(1_000),
+            inclusion_block_number: U256::from(998),
+            workers: vec![H160([1; 20]), H160([2; 20]), H160([3; 20])],
+            stakes: vec![U256::from(30), U256::from(10), U256::from(20)],
+            nonce: U256::from(4),
+        }
+    }
+
+    /// The event parsed by its names, like web3 does, and with `EpochParams` like the enclave does must agree.
+    #[test]
+    fn test_parse_matches_named_params() {
+        let params = params();
+        let event = WorkersParameterizedEvent::new();
+        let named = event.0.parse_log(log(&params)).unwrap();
+        let value = |name: &str| named.params.iter().find(|param| param.name == name).unwrap().value.clone();
+        let uints = |token: Token| token.to_array().unwrap().into_iter().map(|token| token.to_uint().unwrap()).collect::<Vec<_>>();
+        let addresses = |token: Token| token.to_array().unwrap().into_iter().map(|token| token.to_address().unwrap()).collect::<Vec<_>>();
+
+        // The enclave finds the log in a receipt by its topic
+        assert_eq!(event.0.signature(), EpochParams::topic());
+        let parsed = event.parse(&log(&params)).unwrap();
+        assert_eq!(parsed, params);
+        assert_eq!(value("seed").to_uint(), Some(parsed.seed));
+        assert_eq!(value("firstBlockNumber").to_uint(), Some(parsed.first_block_number));
+        assert_eq!(value("inclusionBlockNumber").to_uint(), Some(parsed.inclusion_block_number));
+        assert_eq!(addresses(value("workers")), parsed.workers);
+        assert_eq!(uints(value("stakes")), parsed.stakes);
+        assert_eq!(value("nonce").to_uint(), Some(parsed.nonce));
+
+        let worker_params = InputWorkerParams { km_block_number: U256::from(990), workers: params.workers.clone(), stakes: params.stakes.clone() };
+        assert!(parsed.matches(&worker_params));
+    }
+
+    #[test]
+    fn test_parse_rejects_other_logs() {
+        let event = WorkersParameterizedEvent::new();
+        let mut other = log(&params());
+        other.topics = vec![H256([7; 32])];
+        assert!(event.parse(&other).is_err());
+        let mut truncated = log(&params());
+        truncated.data.truncate(64);
+        assert!(event.parse(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_epoch_params_fixture() {
+        let params = EpochParams { workers: vec![H160([1; 20])], stakes: vec![U256::from(30)], ..params() };
+        let json = serde_json::json!({
+            "seed": format!("0x{:064x}", 0xabcd),
+            "first_block_number": format!("0x{:064x}", 1_000),
+            "inclusion_block_number": format!("0x{:064x}", 998),
+            "workers": ["0x0101010101010101010101010101010101010101"],
+            "stakes": [format!("0x{:064x}", 30)],
+            "nonce": format!("0x{:064x}", 4)
+        });
+        assert_eq!(serde_json::to_value(&params).unwrap(), json);
+        assert_eq!(serde_json::from_value::<EpochParams>(json).unwrap(), params);
     }
 }
@@ -1,2 +1,3 @@
 pub mod epoch_provider;
+pub mod epoch_transition;
 pub mod epoch_types;
@@ -1,39 +1,186 @@
+use enigma_tools_m::eth_proof::{headers_to_rlp, Checkpoint, ReceiptProof};
 use enigma_tools_m::keeper_types::InputWorkerParams;
+use enigma_tools_m::signable::{to_ethereum, WorkerSelection};
 use failure::Error;
 use rustc_hex::ToHex;
 use sgx_types::{sgx_enclave_id_t, sgx_status_t};
-use web3::types::{Bytes, U256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::types::{Bytes, H160, H256, U256};
 
-use common_u::errors::EnclaveFailError;
-use enigma_types::{EnclaveReturn, traits::SliceCPtr};
+use common_u::errors::{EnclaveFailError, WorkerParamsTooLargeErr};
+use enigma_crypto::hash::Keccak256;
+use enigma_types::{ContractAddress, EnclaveReturn, Hash256, traits::SliceCPtr};
 use epoch_u::epoch_types::{encode, EpochState};
 
 extern "C" {
+    fn ecall_set_max_workers(eid: sgx_enclave_id_t, max_workers: u32) -> sgx_status_t;
+
     fn ecall_set_worker_params(
         eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, worker_params_rlp: *const u8, worker_params_rlp_len: usize,
-        seed_in: &[u8; 32], nonce_in: &[u8; 32],
+        seed_in: &[u8; 32], nonce_in: &[u8; 32], raw_seed: u8,
         rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32], sig_out: &mut [u8; 65],
     ) -> sgx_status_t;
+
+    fn ecall_stage_epoch_seed(eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, nonce_out: &mut [u8; 32]) -> sgx_status_t;
+
+    fn ecall_set_worker_params_begin(eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, total_len: usize, handle_out: &mut u64) -> sgx_status_t;
+
+    fn ecall_set_worker_params_chunk(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, handle: u64, chunk: *const u8, chunk_len: usize,
+    ) -> sgx_status_t;
+
+    fn ecall_set_worker_params_finish(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, handle: u64, worker_params_hash: &[u8; 32],
+        seed_in: &[u8; 32], nonce_in: &[u8; 32], raw_seed: u8,
+        rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32], sig_out: &mut [u8; 65],
+    ) -> sgx_status_t;
+
+    fn ecall_anchor_chain(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, contract: &[u8; 20], block_hash: &[u8; 32], block_number: u64,
+        confirmations: u64, number_out: &mut u64, hash_out: &mut [u8; 32],
+    ) -> sgx_status_t;
+
+    fn ecall_advance_chain(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, headers_rlp: *const u8, headers_rlp_len: usize,
+        number_out: &mut u64, hash_out: &mut [u8; 32],
+    ) -> sgx_status_t;
+
+    fn ecall_reveal_epoch_seed(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, nonce: &[u8; 32],
+        worker_params_rlp: *const u8, worker_params_rlp_len: usize, receipt_proof_rlp: *const u8, receipt_proof_rlp_len: usize,
+        seed_out: &mut [u8; 32],
+    ) -> sgx_status_t;
+
+    fn ecall_reveal_epoch_seed_finish(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, nonce: &[u8; 32], params_handle: u64, worker_params_hash: &[u8; 32],
+        proof_handle: u64, receipt_proof_hash: &[u8; 32], seed_out: &mut [u8; 32],
+    ) -> sgx_status_t;
+
+    fn ecall_get_selection_proof(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, sc_addr: &[u8; 32], nonce: &[u8; 32],
+        worker_out: &mut [u8; 20], seed_out: &mut [u8; 32], sig_out: &mut [u8; 65],
+    ) -> sgx_status_t;
+
+    fn ecall_dump_epoch(
+        eid: sgx_enclave_id_t, retval: &mut EnclaveReturn, nonce: &[u8; 32],
+        sequence_out: &mut u64, host_created_at_out: &mut u64, boot_id_out: &mut [u8; 16], generation_attempt_out: &mut u32,
+    ) -> sgx_status_t;
+}
+
+/// Seconds since the unix epoch, the enclave dates the epochs it creates with it.
+#[no_mangle]
+pub extern "C" fn ocall_get_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Where an epoch comes from according to the enclave, none of it is signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EpochOrigin {
+    /// Counted by the enclave and sealed, the epochs it created later have higher ones, across restarts too.
+    pub sequence: u64,
+    /// Informative only, seconds since the unix epoch from the host clock when the enclave created the epoch
+    /// (`ocall_get_time`), not a trusted time. Order the epochs by `sequence`.
+    pub host_created_at: u64,
+    /// Drawn once per enclave instance, the epochs created before and after a restart of the enclave have different ones.
+    pub enclave_boot_id: String,
+    /// 1 for the first seed of the nonce, more if the epoch had to be created again after a failed transition.
+    pub generation_attempt: u32,
+}
+
+/// The biggest RLP of worker parameters sent to the enclave in a single ecall, bigger ones are sent in chunks of
+/// `WORKER_PARAMS_CHUNK_LEN` with `ecall_set_worker_params_begin/chunk/finish`.
+pub const MAX_WORKER_PARAMS_ECALL_LEN: usize = 64 * 1024;
+/// The size of the chunks of a chunked upload of worker parameters
+pub const WORKER_PARAMS_CHUNK_LEN: usize = 32 * 1024;
+/// The biggest RLP of worker parameters the enclave accepts at all, it has to fit in the enclave heap
+/// (see `MAX_WORKER_PARAMS_LEN` in the enclave).
+pub const MAX_WORKER_PARAMS_LEN: usize = 2 * 1024 * 1024;
+
+fn check_len(len: usize, limit: usize, limit_name: &'static str) -> Result<(), Error> {
+    if len > limit {
+        return Err(WorkerParamsTooLargeErr { len, limit, limit_name }.into());
+    }
+    Ok(())
+}
+
+fn enclave_result(retval: EnclaveReturn, status: sgx_status_t) -> Result<(), Error> {
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: retval, status }.into());
+    }
+    Ok(())
+}
+
+/// Streams the RLP of the worker parameters (or the receipt proof of a reveal) into the enclave and returns the handle
+/// `ecall_set_worker_params_finish` and `ecall_reveal_epoch_seed_finish` take.
+fn upload_worker_params(eid: sgx_enclave_id_t, worker_params_rlp: &[u8]) -> Result<u64, Error> {
+    let mut retval = EnclaveReturn::Success;
+    let mut handle = 0u64;
+    let status = unsafe { ecall_set_worker_params_begin(eid, &mut retval, worker_params_rlp.len(), &mut handle) };
+    enclave_result(retval, status)?;
+    for chunk in worker_params_rlp.chunks(WORKER_PARAMS_CHUNK_LEN) {
+        let status = unsafe { ecall_set_worker_params_chunk(eid, &mut retval, handle, chunk.as_c_ptr(), chunk.len()) };
+        enclave_result(retval, status)?;
+    }
+    debug!("Uploaded {} bytes of worker parameters to the enclave in chunks", worker_params_rlp.len());
+    Ok(handle)
+}
+
+/// Sets the maximum amount of workers the enclave accepts in a single epoch,
+/// `set_or_verify_worker_params` fails with `EnclaveReturn::InvalidWorkerParams` above it.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `max_workers` - The maximum amount of workers per epoch
+pub fn set_max_workers(eid: sgx_enclave_id_t, max_workers: u32) -> Result<(), Error> {
+    let status = unsafe { ecall_set_max_workers(eid, max_workers) };
+    if status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: EnclaveReturn::SgxError, status }.into());
+    }
+    Ok(())
+}
+
+/// Has the enclave draw the seed of the next new epoch ahead of time and returns the nonce it's staged for.
+/// The staged seed is sealed apart from the epochs, the next `set_or_verify_worker_params` creating an epoch of that
+/// nonce takes it instead of drawing one. Staging again before then keeps the same seed.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+#[logfn(DEBUG)]
+pub fn stage_epoch_seed(eid: sgx_enclave_id_t) -> Result<U256, Error> {
+    let mut retval = EnclaveReturn::Success;
+    let mut nonce_out = [0u8; 32];
+    let status = unsafe { ecall_stage_epoch_seed(eid, &mut retval, &mut nonce_out) };
+    enclave_result(retval, status)?;
+    Ok(U256::from_big_endian(&nonce_out))
 }
 
 /// Returns an EpochState object containing the 32 bytes signed random seed and an incremented account nonce.
 /// If the `epoch_state` param is some, verify the corresponding sealed `Epoch` marker
 /// Otherwise, create a new `Epoch`
 ///
+/// Unless `raw_seed` is set a new epoch comes without its seed: the enclave returns and signs `keccak256(seed)`
+/// (see `EpochSeedCommitment`), and the seed is only given by `reveal_epoch_seed` once the commitment is on-chain.
+/// `raw_seed` is for the Enigma contract versions that take the seed itself.
+///
 /// # Arguments
 /// * `eid` - The Enclave Id
 /// * `worker_params` - The `InputWorkerParams` to store in an `Epoch`
 /// * `epoch_state` - Optional, the existing `EpochState` to verify against sealed `Epoch` marker
+/// * `raw_seed` - Return and sign the seed of a new epoch instead of its commitment
 ///
 /// # Examples
 /// ```
 /// let enclave = esgx::general::init_enclave().unwrap();
 /// let result = self.contract.get_active_workers(block_number)?;
 /// let worker_params: InputWorkerParams = InputWorkerParams { block_number, workers: result.0, stakes: result.1 };
-/// let sig = set_worker_params(enclave.geteid(), worker_params, None).unwrap();
+/// let sig = set_worker_params(enclave.geteid(), worker_params, None, false).unwrap();
 /// ```
 #[logfn(DEBUG)]
-pub fn set_or_verify_worker_params(eid: sgx_enclave_id_t, worker_params: &InputWorkerParams, epoch_state: Option<EpochState>) -> Result<EpochState, Error> {
+pub fn set_or_verify_worker_params(eid: sgx_enclave_id_t, worker_params: &InputWorkerParams, epoch_state: Option<EpochState>,
+                                   raw_seed: bool) -> Result<EpochState, Error> {
+    if let Some(epoch_state) = epoch_state.as_ref().filter(|epoch_state| epoch_state.awaiting_reveal()) {
+        bail!("The seed of epoch {} wasn't revealed, the epoch can't be verified without it", epoch_state.nonce);
+    }
     let mut retval: EnclaveReturn = EnclaveReturn::Success;
     let (nonce_in, seed_in) = match epoch_state.clone() {
         Some(e) => (e.nonce.into(), e.seed.into()),
@@ -44,20 +191,47 @@ pub fn set_or_verify_worker_params(eid: sgx_enclave_id_t, worker_params: &InputW
     let mut sig_out: [u8; 65] = [0; 65];
     // Serialize the InputWorkerParams into RLP
     let worker_params_rlp = encode(worker_params);
-    let status = unsafe {
-        ecall_set_worker_params(
-            eid,
-            &mut retval,
-            worker_params_rlp.as_c_ptr() as *const u8,
-            worker_params_rlp.len(),
-            &seed_in,
-            &nonce_in,
-            &mut rand_out,
-            &mut nonce_out,
-            &mut sig_out,
-        )
+    check_len(worker_params_rlp.len(), MAX_WORKER_PARAMS_LEN, "MAX_WORKER_PARAMS_LEN")?;
+    let status = if worker_params_rlp.len() <= MAX_WORKER_PARAMS_ECALL_LEN {
+        unsafe {
+            ecall_set_worker_params(
+                eid,
+                &mut retval,
+                worker_params_rlp.as_c_ptr() as *const u8,
+                worker_params_rlp.len(),
+                &seed_in,
+                &nonce_in,
+                raw_seed as u8,
+                &mut rand_out,
+                &mut nonce_out,
+                &mut sig_out,
+            )
+        }
+    } else {
+        // The enclave checks the reassembled parameters against the hash of the ones decoded here
+        let worker_params_hash: Hash256 = worker_params_rlp.keccak256();
+        let handle = upload_worker_params(eid, &worker_params_rlp)?;
+        unsafe {
+            ecall_set_worker_params_finish(
+                eid,
+                &mut retval,
+                handle,
+                &*worker_params_hash,
+                &seed_in,
+                &nonce_in,
+                raw_seed as u8,
+                &mut rand_out,
+                &mut nonce_out,
+                &mut sig_out,
+            )
+        }
     };
     if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        match retval {
+            EnclaveReturn::RandUnavailable => warn!("The enclave's random number generator is exhausted, no epoch was created, it can be retried"),
+            EnclaveReturn::SigningKeyUninitialized => error!("The enclave couldn't load its signing key (km_keypair.sealed), no epoch can be created"),
+            _ => (),
+        }
         return Err(EnclaveFailError { err: retval, status }.into());
     }
     // If an `EpochState` was given and the ecall succeeded, it is considered verified
@@ -65,21 +239,178 @@ pub fn set_or_verify_worker_params(eid: sgx_enclave_id_t, worker_params: &InputW
     let epoch_state_out = match epoch_state {
         Some(epoch_state) => epoch_state,
         None => {
-            let seed = U256::from_big_endian(&rand_out);
-            let sig = Bytes(sig_out.to_vec());
+            let sig = Bytes(to_ethereum(&sig_out)?.to_vec());
             let nonce = U256::from_big_endian(&nonce_out);
-            EpochState::new(seed, sig, nonce, worker_params.km_block_number)
+            if raw_seed {
+                EpochState::new(U256::from_big_endian(&rand_out), sig, nonce, worker_params.km_block_number)
+            } else {
+                EpochState::committed(H256(rand_out), sig, nonce, worker_params.km_block_number)
+            }
         }
     };
     Ok(epoch_state_out)
 }
 
+/// Anchors the reveals of the enclave to the Enigma contract and to a checkpoint block, and returns the checkpoint
+/// in effect, a `ReceiptProof` for `reveal_epoch_seed` starts at the block after it.
+/// The enclave takes the first anchor it's given and keeps it, later calls only return its checkpoint (and may raise
+/// its confirmations), and another contract fails with `EnclaveReturn::WorkerAuthError`.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `contract` - The address of the Enigma contract
+/// * `checkpoint` - The block to anchor to, taken only if the enclave isn't anchored yet
+/// * `confirmations` - The blocks a receipt needs on top of its own before the enclave trusts it
+#[logfn(DEBUG)]
+pub fn anchor_chain(eid: sgx_enclave_id_t, contract: H160, checkpoint: Checkpoint, confirmations: u64) -> Result<Checkpoint, Error> {
+    let mut retval: EnclaveReturn = EnclaveReturn::Success;
+    let (mut number_out, mut hash_out) = (0u64, [0u8; 32]);
+    let status = unsafe {
+        ecall_anchor_chain(eid, &mut retval, &contract.0, &checkpoint.hash.0, checkpoint.number, confirmations, &mut number_out,
+                           &mut hash_out)
+    };
+    enclave_result(retval, status)?;
+    Ok(Checkpoint { number: number_out, hash: H256(hash_out) })
+}
+
+/// Moves the checkpoint of the enclave forward along `headers`, the headers that follow it, to the last of them with
+/// the confirmations of the anchor on top of it, and returns the checkpoint in effect.
+/// The headers go in a single ECALL, `MAX_WORKER_PARAMS_ECALL_LEN` bounds their RLP.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `headers` - The RLP of the headers that follow the checkpoint of `anchor_chain`
+#[logfn(DEBUG)]
+pub fn advance_chain(eid: sgx_enclave_id_t, headers: &[Vec<u8>]) -> Result<Checkpoint, Error> {
+    let mut retval: EnclaveReturn = EnclaveReturn::Success;
+    let headers_rlp = headers_to_rlp(headers);
+    check_len(headers_rlp.len(), MAX_WORKER_PARAMS_ECALL_LEN, "MAX_WORKER_PARAMS_ECALL_LEN")?;
+    let (mut number_out, mut hash_out) = (0u64, [0u8; 32]);
+    let status = unsafe {
+        ecall_advance_chain(eid, &mut retval, headers_rlp.as_c_ptr(), headers_rlp.len(), &mut number_out, &mut hash_out)
+    };
+    enclave_result(retval, status)?;
+    Ok(Checkpoint { number: number_out, hash: H256(hash_out) })
+}
+
+/// Returns the seed of an epoch created with a commitment, the enclave only gives it once it's shown the
+/// `WorkersParameterized` event that put the commitment on-chain, in a receipt proven against the checkpoint of
+/// `anchor_chain` with its confirmations (see `ReceiptProof`).
+/// Anything else fails with `EnclaveReturn::SeedNotRevealed`.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `epoch_state` - The `EpochState` of the commitment
+/// * `worker_params` - The `InputWorkerParams` the epoch was created with
+/// * `receipt_proof` - The proof of the receipt of the transaction that emitted the `WorkersParameterized` log
+#[logfn(DEBUG)]
+pub fn reveal_epoch_seed(eid: sgx_enclave_id_t, epoch_state: &EpochState, worker_params: &InputWorkerParams,
+                         receipt_proof: &ReceiptProof) -> Result<U256, Error> {
+    let mut retval: EnclaveReturn = EnclaveReturn::Success;
+    let nonce_in: [u8; 32] = epoch_state.nonce.into();
+    let worker_params_rlp = encode(worker_params);
+    let receipt_proof_rlp = encode(receipt_proof);
+    check_len(worker_params_rlp.len(), MAX_WORKER_PARAMS_LEN, "MAX_WORKER_PARAMS_LEN")?;
+    check_len(receipt_proof_rlp.len(), MAX_WORKER_PARAMS_LEN, "MAX_WORKER_PARAMS_LEN")?;
+    let mut seed_out = [0u8; 32];
+    let status = if worker_params_rlp.len() + receipt_proof_rlp.len() <= MAX_WORKER_PARAMS_ECALL_LEN {
+        unsafe {
+            ecall_reveal_epoch_seed(
+                eid,
+                &mut retval,
+                &nonce_in,
+                worker_params_rlp.as_c_ptr() as *const u8,
+                worker_params_rlp.len(),
+                receipt_proof_rlp.as_c_ptr() as *const u8,
+                receipt_proof_rlp.len(),
+                &mut seed_out,
+            )
+        }
+    } else {
+        // Both are uploaded like the parameters of a new epoch, the receipt grows with the workers too
+        let (worker_params_hash, receipt_proof_hash): (Hash256, Hash256) =
+            (worker_params_rlp.keccak256(), receipt_proof_rlp.keccak256());
+        let params_handle = upload_worker_params(eid, &worker_params_rlp)?;
+        let proof_handle = upload_worker_params(eid, &receipt_proof_rlp)?;
+        unsafe {
+            ecall_reveal_epoch_seed_finish(
+                eid,
+                &mut retval,
+                &nonce_in,
+                params_handle,
+                &*worker_params_hash,
+                proof_handle,
+                &*receipt_proof_hash,
+                &mut seed_out,
+            )
+        }
+    };
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: retval, status }.into());
+    }
+    Ok(U256::from_big_endian(&seed_out))
+}
+
+/// Returns the worker selected for a secret contract in an epoch, with the enclave signature over the selection
+/// (see `WorkerSelection`, the signature is in the Ethereum form), which anyone can verify against the KM signing address.
+/// The enclave only knows the epochs it set or verified since it started, any other nonce fails with `EnclaveReturn::WorkerAuthError`.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `sc_addr` - The secret contract address
+/// * `nonce` - The nonce of the epoch
+#[logfn(DEBUG)]
+pub fn get_selection_proof(eid: sgx_enclave_id_t, sc_addr: ContractAddress, nonce: U256) -> Result<(WorkerSelection, [u8; 65]), Error> {
+    let mut retval: EnclaveReturn = EnclaveReturn::Success;
+    let nonce_in: [u8; 32] = nonce.into();
+    let (mut worker_out, mut seed_out, mut sig_out) = ([0u8; 20], [0u8; 32], [0u8; 65]);
+    let status = unsafe {
+        ecall_get_selection_proof(eid, &mut retval, &sc_addr, &nonce_in, &mut worker_out, &mut seed_out, &mut sig_out)
+    };
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: retval, status }.into());
+    }
+    let selection = WorkerSelection {
+        seed: U256::from_big_endian(&seed_out),
+        nonce,
+        contract_address: sc_addr,
+        worker: H160(worker_out),
+    };
+    Ok((selection, to_ethereum(&sig_out)?))
+}
+
+/// Returns the origin the enclave recorded for the epoch of `nonce`, `None` for an epoch sealed before the origins
+/// were recorded. An epoch the enclave never created fails with `EnclaveReturn::WorkerAuthError`.
+///
+/// # Arguments
+/// * `eid` - The Enclave Id
+/// * `nonce` - The nonce of the epoch
+#[logfn(DEBUG)]
+pub fn dump_epoch(eid: sgx_enclave_id_t, nonce: U256) -> Result<Option<EpochOrigin>, Error> {
+    let mut retval: EnclaveReturn = EnclaveReturn::Success;
+    let nonce_in: [u8; 32] = nonce.into();
+    let (mut sequence, mut host_created_at, mut boot_id, mut generation_attempt) = (0u64, 0u64, [0u8; 16], 0u32);
+    let status = unsafe {
+        ecall_dump_epoch(eid, &mut retval, &nonce_in, &mut sequence, &mut host_created_at, &mut boot_id, &mut generation_attempt)
+    };
+    enclave_result(retval, status)?;
+    if generation_attempt == 0 {
+        return Ok(None);
+    }
+    Ok(Some(EpochOrigin { sequence, host_created_at, enclave_boot_id: boot_id.to_hex(), generation_attempt }))
+}
+
 #[cfg(test)]
 pub mod tests {
     use rustc_hex::{FromHex, ToHex};
     use web3::types::{Address, H160, H256};
 
-    use esgx::general::init_enclave_wrapper;
+    use enigma_crypto::asymmetric::is_canonical;
+    use enigma_tools_m::eth_proof::{test_receipt, test_receipt_proof, BlockHeader, Log};
+    use enigma_tools_m::keeper_types::EpochParams;
+    use enigma_tools_m::signable::{EpochSeed, EpochSeedCommitment, Signable};
+    use ethabi::{self, Token};
+    use esgx::{equote::get_register_signing_address, general::init_enclave_wrapper};
 
     use super::*;
 
@@ -102,8 +433,20 @@ pub mod tests {
         let stakes: Vec<u64> = vec![90000000000];
         let km_block_number = 1;
         let worker_params = get_worker_params(km_block_number, workers, stakes);
-        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None).unwrap();
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
         assert!(epoch_state.confirmed_state.is_none());
+
+        let signer = get_register_signing_address(enclave.geteid()).unwrap();
+        let payload = EpochSeed {
+            seed: epoch_state.seed,
+            nonce: epoch_state.nonce,
+            workers: worker_params.workers.clone(),
+            stakes: worker_params.stakes.clone(),
+        };
+        let mut sig = [0u8; 65];
+        sig.copy_from_slice(&epoch_state.sig.0);
+        assert!(is_canonical(&sig));
+        assert!(payload.verify(&sig, &signer).unwrap());
         enclave.destroy();
     }
 
@@ -117,9 +460,198 @@ pub mod tests {
         let km_block_number = 1;
         let worker_params = get_worker_params(km_block_number, workers, stakes);
         for i in 0..5 {
-            let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None).unwrap();
+            let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
             assert!(epoch_state.confirmed_state.is_none());
         }
         enclave.destroy();
     }
+
+    #[test]
+    fn test_set_worker_params_no_workers() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let worker_params = get_worker_params(1, vec![], vec![]);
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        // The epoch is stored, but nobody can be selected in it
+        let mut epoch_state = epoch_state;
+        epoch_state.confirm(U256::from(1), &worker_params, vec![[1u8; 32].into()]).unwrap();
+        assert!(epoch_state.get_contract_addresses(&H160([0u8; 20])).unwrap().is_empty());
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_stage_epoch_seed() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let worker_params = get_worker_params(1, vec![[1u8; 20]], vec![10]);
+        let staged = stage_epoch_seed(enclave.geteid()).unwrap();
+        assert_eq!(stage_epoch_seed(enclave.geteid()).unwrap(), staged);
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        assert_eq!(epoch_state.nonce, staged);
+        // The staged seed was taken, the next one is for the following epoch
+        assert_eq!(stage_epoch_seed(enclave.geteid()).unwrap(), staged + 1);
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        assert_eq!(epoch_state.nonce, staged + 1);
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_set_worker_params_max_workers() {
+        let enclave = init_enclave_wrapper().unwrap();
+        set_max_workers(enclave.geteid(), 2).unwrap();
+        let worker_params = get_worker_params(1, vec![[1u8; 20], [2u8; 20]], vec![10, 20]);
+        set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_set_worker_params_over_max_workers() {
+        let enclave = init_enclave_wrapper().unwrap();
+        set_max_workers(enclave.geteid(), 2).unwrap();
+        let worker_params = get_worker_params(1, vec![[1u8; 20], [2u8; 20], [3u8; 20]], vec![10, 20, 30]);
+        let err = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap_err();
+        match err.downcast_ref::<EnclaveFailError>() {
+            Some(EnclaveFailError { err: EnclaveReturn::InvalidWorkerParams, .. }) => (),
+            other => panic!("Expected InvalidWorkerParams, got: {:?}", other),
+        }
+        enclave.destroy();
+    }
+
+    fn synthetic_worker_params(size: usize) -> InputWorkerParams {
+        InputWorkerParams {
+            km_block_number: U256::from(1),
+            workers: (0..size as u64).map(|i| {
+                let mut worker = [0u8; 20];
+                worker[12..].copy_from_slice(&(i + 1).to_be_bytes());
+                H160(worker)
+            }).collect(),
+            stakes: (0..size as u64).map(|i| U256::from(i % 97 + 1)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_set_worker_params_chunked() {
+        let enclave = init_enclave_wrapper().unwrap();
+        set_max_workers(enclave.geteid(), 5000).unwrap();
+        let worker_params = synthetic_worker_params(5000);
+        assert!(encode(&worker_params).len() > MAX_WORKER_PARAMS_ECALL_LEN);
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        // The enclave selects like the single-shot path would, which is the selection of the parameters themselves
+        for i in 0..8u8 {
+            let sc_addr = ContractAddress::from([i; 32]);
+            let (selection, _) = get_selection_proof(enclave.geteid(), sc_addr, epoch_state.nonce).unwrap();
+            assert_eq!(selection.worker, worker_params.get_selected_worker(sc_addr, epoch_state.seed).unwrap());
+        }
+        // And the epoch is verified the same way
+        set_or_verify_worker_params(enclave.geteid(), &worker_params, Some(epoch_state), true).unwrap();
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_set_worker_params_too_large() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let worker_params = synthetic_worker_params(100_000);
+        let err = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap_err();
+        match err.downcast_ref::<WorkerParamsTooLargeErr>() {
+            Some(WorkerParamsTooLargeErr { limit, .. }) => assert_eq!(*limit, MAX_WORKER_PARAMS_LEN),
+            other => panic!("Expected WorkerParamsTooLargeErr, got: {:?}", other),
+        }
+        enclave.destroy();
+    }
+
+    #[test]
+    fn test_dump_epoch() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let worker_params = get_worker_params(1, vec![[1u8; 20], [2u8; 20]], vec![10, 20]);
+        let first = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
+        let second = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, false).unwrap();
+        let first_origin = dump_epoch(enclave.geteid(), first.nonce).unwrap().unwrap();
+        // Unrevealed epochs have an origin too
+        let second_origin = dump_epoch(enclave.geteid(), second.nonce).unwrap().unwrap();
+        assert_eq!(first_origin.enclave_boot_id.len(), 32);
+        assert_eq!(first_origin.enclave_boot_id, second_origin.enclave_boot_id);
+        assert!(first_origin.sequence > 0 && first_origin.sequence < second_origin.sequence);
+        assert!(first_origin.host_created_at > 0 && first_origin.host_created_at <= second_origin.host_created_at);
+        assert!(first_origin.generation_attempt >= 1);
+        assert!(dump_epoch(enclave.geteid(), second.nonce + 1000).is_err());
+        enclave.destroy();
+
+        // The origin is sealed, another instance of the enclave still has it
+        let enclave = init_enclave_wrapper().unwrap();
+        assert_eq!(dump_epoch(enclave.geteid(), first.nonce).unwrap(), Some(first_origin));
+        enclave.destroy();
+    }
+
+    const ENIGMA_CONTRACT: [u8; 20] = [0xee; 20];
+    const CONFIRMATIONS: u64 = 2;
+
+    /// The event of the commitment of `epoch_state`, in a proof on top of the checkpoint the enclave is anchored to.
+    fn commitment_proof(eid: sgx_enclave_id_t, epoch_state: &EpochState, worker_params: &InputWorkerParams, blocks: u64) -> ReceiptProof {
+        let checkpoint = anchor_chain(eid, H160(ENIGMA_CONTRACT), Checkpoint::default(), CONFIRMATIONS).unwrap();
+        let data = ethabi::encode(&[
+            Token::Uint(U256::from_big_endian(&epoch_state.commitment.unwrap().0)),
+            Token::Uint(U256::from(2)),
+            Token::Uint(U256::from(2)),
+            Token::Array(worker_params.workers.iter().map(|worker| Token::Address(*worker)).collect()),
+            Token::Array(worker_params.stakes.iter().map(|stake| Token::Uint(*stake)).collect()),
+            Token::Uint(epoch_state.nonce),
+        ]);
+        let log = Log { address: H160(ENIGMA_CONTRACT), topics: vec![EpochParams::topic()], data };
+        test_receipt_proof(&checkpoint, blocks, 0, &[test_receipt(true, &[log])], 0)
+    }
+
+    // One test for both sizes, every reveal moves the checkpoint of the enclave and the proofs must start at it
+    #[test]
+    fn test_commit_reveal_worker_params() {
+        let enclave = init_enclave_wrapper().unwrap();
+        let worker_params = get_worker_params(1, vec![[1u8; 20], [2u8; 20]], vec![10, 20]);
+        let mut epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, false).unwrap();
+        assert!(epoch_state.awaiting_reveal());
+        let commitment = epoch_state.commitment.unwrap();
+
+        let signer = get_register_signing_address(enclave.geteid()).unwrap();
+        let payload = EpochSeedCommitment {
+            commitment,
+            nonce: epoch_state.nonce,
+            workers: worker_params.workers.clone(),
+            stakes: worker_params.stakes.clone(),
+        };
+        let mut sig = [0u8; 65];
+        sig.copy_from_slice(&epoch_state.sig.0);
+        assert!(payload.verify(&sig, &signer).unwrap());
+
+        // Not before the commitment is on-chain and confirmed, and it can't be verified without its seed either
+        let unconfirmed = commitment_proof(enclave.geteid(), &epoch_state, &worker_params, CONFIRMATIONS);
+        for proof in &[ReceiptProof::default(), unconfirmed] {
+            let err = reveal_epoch_seed(enclave.geteid(), &epoch_state, &worker_params, proof).unwrap_err();
+            match err.downcast_ref::<EnclaveFailError>() {
+                Some(EnclaveFailError { err: EnclaveReturn::SeedNotRevealed, .. }) => (),
+                other => panic!("Expected SeedNotRevealed, got: {:?}", other),
+            }
+        }
+        assert!(set_or_verify_worker_params(enclave.geteid(), &worker_params, Some(epoch_state.clone()), false).is_err());
+
+        let proof = commitment_proof(enclave.geteid(), &epoch_state, &worker_params, CONFIRMATIONS + 1);
+        let seed = reveal_epoch_seed(enclave.geteid(), &epoch_state, &worker_params, &proof).unwrap();
+        assert!(epoch_state.clone().reveal(seed + U256::from(1)).is_err());
+        epoch_state.reveal(seed).unwrap();
+        assert!(!epoch_state.awaiting_reveal());
+        // Once revealed it's verified like any other epoch
+        set_or_verify_worker_params(enclave.geteid(), &worker_params, Some(epoch_state), false).unwrap();
+        // The checkpoint moves along headers alone too
+        let checkpoint = anchor_chain(enclave.geteid(), H160(ENIGMA_CONTRACT), Checkpoint::default(), CONFIRMATIONS).unwrap();
+        let headers = test_receipt_proof(&checkpoint, CONFIRMATIONS + 1, 0, &[], 0).headers;
+        assert_eq!(advance_chain(enclave.geteid(), &headers).unwrap(), BlockHeader::from_rlp(&headers[0]).unwrap().checkpoint());
+
+        // The parameters and the proof of a large set go in chunks
+        set_max_workers(enclave.geteid(), 5000).unwrap();
+        let worker_params = synthetic_worker_params(5000);
+        let mut epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, false).unwrap();
+        let proof = commitment_proof(enclave.geteid(), &epoch_state, &worker_params, CONFIRMATIONS + 1);
+        assert!(encode(&worker_params).len() + encode(&proof).len() > MAX_WORKER_PARAMS_ECALL_LEN);
+        let seed = reveal_epoch_seed(enclave.geteid(), &epoch_state, &worker_params, &proof).unwrap();
+        epoch_state.reveal(seed).unwrap();
+        let sc_addr = ContractAddress::from([3u8; 32]);
+        let (selection, _) = get_selection_proof(enclave.geteid(), sc_addr, epoch_state.nonce).unwrap();
+        assert_eq!(selection.worker, worker_params.get_selected_worker(sc_addr, seed).unwrap());
+        enclave.destroy();
+    }
 }
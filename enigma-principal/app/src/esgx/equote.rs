@@ -1,4 +1,5 @@
 use common_u::errors;
+use enigma_tools_m::signable::to_ethereum;
 use failure::Error;
 use sgx_types::*;
 use std::str;
@@ -47,11 +48,12 @@ pub fn get_ethereum_address(eid: sgx_enclave_id_t) -> Result<[u8; 20], Error> {
 }
 
 /// wrapper function for creating a signature using the ethereum key
+/// The enclave returns the recovery id as `v`, it's converted to 27/28, what `calculate_eth_recovery_id` builds on.
 pub fn sign_ethereum(eid: sgx_enclave_id_t, to_sign: &[u8; 32]) -> Result<[u8; 65], Error> {
     let mut sig = [0u8; 65];
     let status = unsafe { ecall_sign_ethereum(eid,to_sign, &mut sig) };
     if status == sgx_status_t::SGX_SUCCESS {
-        Ok(sig)
+        Ok(to_ethereum(&sig)?)
     } else {
         Err(errors::GetRegisterKeyErr { status, message: String::from("error in sign_ethereum") }.into())
     }
@@ -7,6 +7,7 @@ static ENCLAVE_FILE: &'static str = "../bin/enclave.signed.so";
 pub static ENCLAVE_DIR: &'static str = ".enigma";
 pub static EPOCH_DIR: &'static str = "epoch";
 pub static EPOCH_FILE: &'static str = "epoch-state.msgpack";
+pub static EPOCH_TRANSITIONS_FILE: &'static str = "epoch-transitions.json";
 pub static STATE_KEYS_DIR: &'static str = "state-keys";
 
 #[logfn(INFO)]
@@ -6,6 +6,7 @@ use web3::types::U256;
 
 use boot_network::keys_provider_http::{StateKeyRequest, StateKeyResponse, StringWrapper};
 use common_u::errors::EnclaveFailError;
+use enigma_tools_m::signable::to_ethereum;
 use enigma_types::{ContractAddress, EnclaveReturn, traits::SliceCPtr};
 
 extern "C" {
@@ -59,7 +60,7 @@ pub fn get_enc_state_keys(eid: sgx_enclave_id_t, request: StateKeyRequest, epoch
     }
     let box_ptr = response_ptr as *mut Box<[u8]>;
     let response = unsafe { Box::from_raw(box_ptr) };
-    Ok(StateKeyResponse { data: StringWrapper::from(&response[..]), sig: StringWrapper::from(&sig_out[..]) })
+    Ok(StateKeyResponse { data: StringWrapper::from(&response[..]), sig: StringWrapper::from(&to_ethereum(&sig_out)?[..]) })
 }
 
 #[cfg(test)]
@@ -96,7 +97,7 @@ pub mod tests {
         let stakes: Vec<u64> = vec![10000000000];
         let km_block_number = 1;
         let worker_params = get_worker_params(km_block_number, workers, stakes);
-        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None).unwrap();
+        let epoch_state = set_or_verify_worker_params(enclave.geteid(), &worker_params, None, true).unwrap();
 
         // From the km_primitives uint tests
         let msg = StringWrapper("83a464617461a752657175657374a269649cccd763674174cc9b3f300dccd2ccb0cc8ba67075626b6579dc0040ccc90b2205ccf9cc9358661320ccffccb763ccb57614ccf8ccaa1fccb86d6a087869ccd81acce5ccf16fcc9206cc98344136cca4ccefccb105ccbbccca1c5057ccba25067eccc101cc82ccee21445cccf91e79ccb176447239".to_string());
@@ -0,0 +1,117 @@
+//! Differential test of the worker selection: the one the enclave and the principal run (`InputWorkerParams::get_selected_worker`)
+//! against the one of a deployed Enigma contract, on every epoch it was given.
+//! A divergence means the receipts of the affected contracts don't verify on chain, so every difference is a failure,
+//! printed with everything needed to reproduce it.
+//!
+//! The epochs are read back from the `WorkersParameterized` events of the contract, and for each of them the selection
+//! of the contract (`getWorkerGroup(firstBlockNumber, scAddr)`) is compared on the deployed secret contracts and on a few
+//! random addresses. The contract must emit the seed itself, an event carrying a seed commitment can't be replayed.
+//!
+//! Ignored by default, it needs a chain where the principal ran a few epochs, like the one of the integration tests:
+//! ```sh
+//! NODE_URL=http://localhost:8545 ENIGMA_CONTRACT_ADDRESS=<address> cargo test --test worker_selection_diff -- --ignored
+//! ```
+//! `SELECTION_RANDOM_ADDRS` sets the number of random addresses per epoch and `SELECTION_RNG_SEED` replays a failed run.
+
+extern crate enigma_tools_m;
+extern crate enigma_tools_u;
+extern crate enigma_types;
+extern crate ethabi;
+extern crate rand;
+extern crate rustc_hex;
+extern crate serde_json;
+extern crate web3;
+
+use enigma_tools_m::keeper_types::{EpochParams, InputWorkerParams, WORKERS_PARAMETERIZED_PARAMS};
+use enigma_tools_u::web3_utils::w3utils;
+use enigma_types::ContractAddress;
+use ethabi::{Event, EventParam};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustc_hex::ToHex;
+use std::env;
+use web3::contract::{Contract, Options};
+use web3::futures::Future;
+use web3::transports::Http;
+use web3::types::{Address, BlockNumber, FilterBuilder, H256};
+
+const WORKERS_PARAMETERIZED: &str = "WorkersParameterized";
+const DEFAULT_RANDOM_ADDRS: usize = 8;
+/// The views of `Enigma.sol` the test reads, `IEnigma.json` doesn't have the selection.
+const ENIGMA_VIEWS_ABI: &str = r#"[
+    {"constant":true,"inputs":[{"name":"_blockNumber","type":"uint256"},{"name":"_secretContractAddress","type":"bytes32"}],
+     "name":"getWorkerGroup","outputs":[{"name":"","type":"address[]"}],"payable":false,"stateMutability":"view","type":"function"},
+    {"constant":true,"inputs":[],"name":"getAllSecretContractAddresses","outputs":[{"name":"","type":"bytes32[]"}],
+     "payable":false,"stateMutability":"view","type":"function"}
+]"#;
+
+fn get_node_url() -> String { env::var("NODE_URL").unwrap_or_else(|_| "http://localhost:8545".to_string()) }
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+/// The epochs the contract was given, oldest first.
+fn epochs(w3: &web3::Web3<Http>, enigma: Address) -> Vec<EpochParams> {
+    let inputs = WORKERS_PARAMETERIZED_PARAMS
+        .iter()
+        .zip(EpochParams::param_types())
+        .map(|(name, kind)| EventParam { name: name.to_string(), kind, indexed: false })
+        .collect();
+    let event = Event { name: WORKERS_PARAMETERIZED.to_string(), inputs, anonymous: false };
+    let filter = FilterBuilder::default()
+        .address(vec![enigma])
+        .topics(Some(vec![event.signature().into()]), None, None, None)
+        .from_block(BlockNumber::Earliest)
+        .to_block(BlockNumber::Latest)
+        .build();
+    let logs = w3.eth().logs(filter).wait().unwrap();
+    logs.iter().map(|log| EpochParams::decode(&log.data.0).unwrap()).collect()
+}
+
+#[test]
+#[ignore]
+fn test_selection_matches_contract() {
+    let (_eloop, w3) = w3utils::connect(&get_node_url()).unwrap();
+    let enigma: Address = env::var("ENIGMA_CONTRACT_ADDRESS")
+        .expect("Set ENIGMA_CONTRACT_ADDRESS to the deployed Enigma contract")
+        .trim_start_matches("0x")
+        .parse()
+        .unwrap();
+    let contract = Contract::from_json(w3.eth(), enigma, ENIGMA_VIEWS_ABI.as_bytes()).unwrap();
+
+    let rng_seed = env_or("SELECTION_RNG_SEED", rand::random::<u64>());
+    let random_addrs = env_or("SELECTION_RANDOM_ADDRS", DEFAULT_RANDOM_ADDRS);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    let deployed: Vec<H256> = contract.query("getAllSecretContractAddresses", (), None, Options::default(), None).wait().unwrap();
+    let epochs = epochs(&w3, enigma);
+    assert!(!epochs.is_empty(), "The contract at {:?} was never given an epoch", enigma);
+    println!("Comparing {} epochs on {} secret contracts, SELECTION_RNG_SEED={}", epochs.len(), deployed.len(), rng_seed);
+
+    let mut failures = Vec::new();
+    for epoch in &epochs {
+        let params = InputWorkerParams { km_block_number: epoch.first_block_number, workers: epoch.workers.clone(), stakes: epoch.stakes.clone() };
+        let random = (0..random_addrs).map(|_| H256::from(rng.gen::<[u8; 32]>()));
+        for sc_addr in deployed.iter().cloned().chain(random) {
+            let ours = params.get_selected_worker(ContractAddress::from(sc_addr.0), epoch.seed).ok();
+            // The contract reverts where we return an error (no stakes), that's an agreement too.
+            let theirs: Option<Address> = contract
+                .query("getWorkerGroup", (epoch.first_block_number, sc_addr), None, Options::default(), None)
+                .wait()
+                .ok()
+                .and_then(|group: Vec<Address>| group.first().cloned());
+            if ours != theirs {
+                failures.push(format!(
+                    "epoch {}: seed: {:#x}, firstBlockNumber: {}, scAddr: 0x{}, params: {}, shared code: {:?}, contract: {:?}",
+                    epoch.nonce,
+                    epoch.seed,
+                    epoch.first_block_number,
+                    sc_addr.0[..].to_hex(),
+                    serde_json::to_string(&params).unwrap(),
+                    ours,
+                    theirs,
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "The selection diverged from the contract (SELECTION_RNG_SEED={}):\n{}", rng_seed, failures.join("\n"));
+}
@@ -0,0 +1,144 @@
+//! The chain the committed seeds are revealed on, see `ecall_reveal_epoch_seed_internal`.
+//! The anchor is the Enigma contract and a checkpoint block, sealed the first time the untrusted side gives them
+//! (trust on first use, it should be a node the operator trusts) and kept from then on. A seed is revealed for a
+//! `WorkersParameterized` log of that contract, in a receipt proven with the headers that follow the checkpoint
+//! (see `ReceiptProof`), and the checkpoint then moves to the block of the receipt.
+//! The proof of work of the headers isn't checked, the enclave can't follow the chain, only its links.
+
+use enigma_tools_m::eth_proof::{confirmed_checkpoint, headers_from_rlp, Checkpoint, ReceiptProof};
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::{
+    common::errors_t::{EnclaveError::{self, *}, EnclaveSystemError::*},
+    document_storage_t::{is_document, load_sealed_document, save_sealed_document, SEAL_LOG_SIZE, SealedDocumentStorage},
+};
+use ethereum_types::H256;
+use std::{cmp, path, string::{String, ToString}, sync::SgxMutex, untrusted::fs::remove_file, vec::Vec};
+
+use crate::epoch_keeper_t::get_epoch_root_path;
+
+const CHAIN_ANCHOR_FILE: &str = "epoch-chain-anchor.sealed";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChainAnchor {
+    contract: [u8; 20],
+    number: u64,
+    hash: [u8; 32],
+    // The blocks required on top of the block of a receipt, it can only be raised.
+    confirmations: u64,
+}
+
+impl ChainAnchor {
+    fn checkpoint(&self) -> Checkpoint { Checkpoint { number: self.number, hash: H256(self.hash) } }
+}
+
+lazy_static! {
+    // The sealed anchor, loaded from its document after a restart. Always locked after `EPOCH`.
+    static ref ANCHOR: SgxMutex<Option<ChainAnchor>> = SgxMutex::new(None);
+}
+
+fn get_chain_anchor_path() -> path::PathBuf { get_epoch_root_path().join(CHAIN_ANCHOR_FILE) }
+
+fn store_chain_anchor(anchor: ChainAnchor) -> Result<(), EnclaveError> {
+    let anchor_doc = SealedDocumentStorage { version: 0x1234, data: anchor };
+    let mut sealed_log_in = [0u8; SEAL_LOG_SIZE];
+    anchor_doc.seal(&mut sealed_log_in)?;
+    save_sealed_document(&get_chain_anchor_path(), &sealed_log_in)?;
+    debug_println!("Sealed the chain anchor at block {}", anchor.number);
+    Ok(())
+}
+
+// Unlike the staged seed a document that doesn't unseal isn't replaced, that would let the untrusted side anchor anew.
+fn load_chain_anchor(slot: &mut Option<ChainAnchor>) -> Result<Option<ChainAnchor>, EnclaveError> {
+    let path = get_chain_anchor_path();
+    if slot.is_none() && is_document(&path) {
+        let mut sealed_log_out = [0u8; SEAL_LOG_SIZE];
+        load_sealed_document(&path, &mut sealed_log_out)?;
+        match SealedDocumentStorage::<ChainAnchor>::unseal(&mut sealed_log_out)? {
+            Some(doc) => *slot = Some(doc.data),
+            None => return Err(SystemError(WorkerAuthError { err: format!("Failed to unseal the chain anchor: {:?}", path) })),
+        }
+    }
+    Ok(*slot)
+}
+
+/// Anchors the reveals to the Enigma contract at `contract` and to the `checkpoint` block, unless they're anchored already,
+/// and returns the checkpoint in effect, the proofs start at the block after it.
+/// An anchor is kept for good, `checkpoint` is only taken the first time. `confirmations` can raise the ones
+/// of the anchor but not lower them, and another contract is refused.
+pub(crate) fn ecall_anchor_chain_internal(contract: [u8; 20], checkpoint: Checkpoint, confirmations: u64) -> Result<Checkpoint, EnclaveError> {
+    let mut slot = ANCHOR.lock_expect("Chain anchor");
+    let anchor = match load_chain_anchor(&mut slot)? {
+        Some(anchor) if anchor.contract != contract => {
+            return Err(SystemError(WorkerAuthError { err: "The chain is anchored to another Enigma contract".to_string() }));
+        }
+        Some(anchor) => ChainAnchor { confirmations: cmp::max(anchor.confirmations, confirmations), ..anchor },
+        None => ChainAnchor { contract, number: checkpoint.number, hash: checkpoint.hash.0, confirmations },
+    };
+    if *slot != Some(anchor) {
+        store_chain_anchor(anchor)?;
+        *slot = Some(anchor);
+    }
+    Ok(anchor.checkpoint())
+}
+
+/// The data of the log with `topic` the anchored contract emitted in the receipt of `proof`, and the checkpoint the
+/// anchor moves to with `advance` once the log was used. Fails with `SeedNotRevealed` unless the receipt is proven
+/// with enough confirmations and its transaction wasn't reverted.
+pub(super) fn proven_log(proof: &ReceiptProof, topic: &H256) -> Result<(Vec<u8>, Checkpoint), EnclaveError> {
+    let refused = |err: String| SystemError(SeedNotRevealed { err });
+    let anchor = load_chain_anchor(&mut ANCHOR.lock_expect("Chain anchor"))?
+        .ok_or_else(|| refused("The chain isn't anchored".to_string()))?;
+    let (receipt, checkpoint) = proof.verify(&anchor.checkpoint(), anchor.confirmations)
+        .map_err(|e| refused(format!("The receipt isn't proven: {}", e)))?;
+    if !receipt.success {
+        return Err(refused("The transaction was reverted".to_string()));
+    }
+    receipt.logs.into_iter()
+        .find(|log| log.address.0 == anchor.contract && log.topics.first() == Some(topic))
+        .map(|log| (log.data, checkpoint))
+        .ok_or_else(|| refused("The receipt has no such log of the Enigma contract".to_string()))
+}
+
+/// Moves the checkpoint of the anchor forward to `checkpoint`, a proven block.
+pub(super) fn advance(checkpoint: Checkpoint) -> Result<(), EnclaveError> {
+    let mut slot = ANCHOR.lock_expect("Chain anchor");
+    match load_chain_anchor(&mut slot)? {
+        Some(anchor) if checkpoint.number > anchor.number => move_checkpoint(&mut slot, anchor, checkpoint),
+        _ => Ok(()),
+    }
+}
+
+fn move_checkpoint(slot: &mut Option<ChainAnchor>, anchor: ChainAnchor, checkpoint: Checkpoint) -> Result<(), EnclaveError> {
+    let anchor = ChainAnchor { number: checkpoint.number, hash: checkpoint.hash.0, ..anchor };
+    store_chain_anchor(anchor)?;
+    *slot = Some(anchor);
+    Ok(())
+}
+
+/// Moves the checkpoint of the anchor forward along `headers_rlp`, the RLP list of the headers that follow it, to the
+/// last of them with the confirmations of the anchor on top of it, and returns the checkpoint in effect.
+/// It keeps the proofs of the next reveal short when the last one is far behind.
+pub(crate) fn ecall_advance_chain_internal(headers_rlp: &[u8]) -> Result<Checkpoint, EnclaveError> {
+    let refused = |err: String| SystemError(WorkerAuthError { err });
+    let mut slot = ANCHOR.lock_expect("Chain anchor");
+    let anchor = load_chain_anchor(&mut slot)?.ok_or_else(|| refused("The chain isn't anchored".to_string()))?;
+    let headers = headers_from_rlp(headers_rlp).map_err(|e| refused(e.to_string()))?;
+    match confirmed_checkpoint(&anchor.checkpoint(), &headers, anchor.confirmations).map_err(|e| refused(e.to_string()))? {
+        Some(checkpoint) => {
+            move_checkpoint(&mut slot, anchor, checkpoint)?;
+            Ok(checkpoint)
+        }
+        None => Ok(anchor.checkpoint()),
+    }
+}
+
+/// Drops the anchor, so the tests can anchor to their own chains.
+pub(super) fn reset() {
+    *ANCHOR.lock_expect("Chain anchor") = None;
+    let path = get_chain_anchor_path();
+    if is_document(&path) {
+        if let Err(e) = remove_file(&path) {
+            debug_println!("Failed removing the chain anchor document {:?}: {:?}", path, e);
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use enigma_tools_m::keeper_types::{InputWorkerParams, RawEncodable};
+use enigma_tools_m::eth_hash::eth_hash;
+use enigma_tools_m::signable::{EpochSeed, EpochSeedCommitment, Signable, WorkerSelection};
 use ethabi::Bytes;
 use ethereum_types::{H160, H256, U256, BigEndianHash};
-use std::string::ToString;
 use std::vec::Vec;
 
 use enigma_tools_t::common::errors_t::{
@@ -9,38 +10,68 @@ use enigma_tools_t::common::errors_t::{
     EnclaveSystemError,
 };
 use enigma_types::ContractAddress;
-use super::nested_encoding::NestedSerialization;
 
 pub type EpochNonce = [u8; 32];
 pub type EpochMarker = [u8; 64];
 
+/// Where an epoch comes from, for the operators going through the epochs of a node after the fact.
+/// None of it is signed or part of the marker, it's sealed next to the epoch (see `store_epoch_origin`).
+/// All zeros is an epoch sealed before its origin was recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EpochOrigin {
+    /// The order the enclave created its epochs in, counted by the enclave itself and sealed next to the markers
+    /// (see `next_epoch_sequence`), a later epoch always has a higher one, across restarts too.
+    pub sequence: u64,
+    /// Host-reported and only informative: seconds since the unix epoch from the host clock, never before an epoch
+    /// created earlier. The enclave has no trusted time to check it against, order epochs by `sequence` instead.
+    pub host_created_at: u64,
+    /// Random, drawn once per enclave instance, the epochs created before and after a restart have different ones.
+    pub enclave_boot_id: [u8; 16],
+    /// 1 for the first seed of a nonce, incremented when a failed transition had the nonce's epoch created again.
+    pub generation_attempt: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Epoch {
     pub nonce: U256,
     pub seed: U256,
     pub worker_params: InputWorkerParams,
+    pub origin: EpochOrigin,
 }
 
 impl Epoch {
     pub fn get_selected_worker(&self, sc_addr: ContractAddress) -> Result<H160, EnclaveError> {
         self.worker_params
             .get_selected_worker(sc_addr, self.seed)
-            .ok_or_else(|| SystemError(EnclaveSystemError::WorkerAuthError { err: "Worker selection returns nothing.".to_string() }))
+            .map_err(|e| SystemError(EnclaveSystemError::WorkerAuthError { err: format!("Worker selection failed: {}", e) }))
     }
 
-    pub fn encode_for_hashing(&self) -> Bytes {
-        let mut encoding: Vec<u8> = Vec::new();
-
-        let seed_encoding = self.seed.hash_encode();
-        let nonce_encoding = self.nonce.hash_encode();
-        let workers_encoding = self.worker_params.workers.hash_encode();
-        let stakes_encoding = self.worker_params.stakes.hash_encode();
+    /// The signed payload of the epoch, see `enigma_tools_m::signable::EpochSeed`.
+    pub fn signable(&self) -> EpochSeed {
+        EpochSeed {
+            seed: self.seed,
+            nonce: self.nonce,
+            workers: self.worker_params.workers.clone(),
+            stakes: self.worker_params.stakes.clone(),
+        }
+    }
 
-        encoding.extend_from_slice(&seed_encoding);
-        encoding.extend_from_slice(&nonce_encoding);
-        encoding.extend_from_slice(&workers_encoding);
-        encoding.extend_from_slice(&stakes_encoding);
+    /// The signed payload of the epoch while its seed is kept in the enclave, see `enigma_tools_m::signable::EpochSeedCommitment`.
+    pub fn commitment(&self) -> EpochSeedCommitment {
+        EpochSeedCommitment {
+            commitment: eth_hash(&H256::from_uint(&self.seed).0),
+            nonce: self.nonce,
+            workers: self.worker_params.workers.clone(),
+            stakes: self.worker_params.stakes.clone(),
+        }
+    }
 
-        encoding
+    /// The signed payload of the worker selected for `sc_addr`, see `enigma_tools_m::signable::WorkerSelection`.
+    pub fn selection(&self, sc_addr: ContractAddress) -> Result<WorkerSelection, EnclaveError> {
+        let worker = self.get_selected_worker(sc_addr)?;
+        Ok(WorkerSelection { seed: self.seed, nonce: self.nonce, contract_address: sc_addr, worker })
     }
+
+    /// Kept for the sealed epoch markers, this is the same image as `self.signable().to_signable_bytes()`.
+    pub fn encode_for_hashing(&self) -> Bytes { self.signable().to_signable_bytes() }
 }
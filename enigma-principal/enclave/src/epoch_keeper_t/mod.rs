@@ -1,12 +1,14 @@
 use core::clone::Clone;
 
-use enigma_tools_m::keeper_types::{decode, EPOCH_CAP, InputWorkerParams, RawEncodable};
+use enigma_tools_m::eth_proof::ReceiptProof;
+use enigma_tools_m::keeper_types::{EpochParams, EPOCH_CAP, InputWorkerParams, RawEncodable};
+use enigma_tools_m::signable::Signable;
 use enigma_tools_m::utils::LockExpectMutex;
 use ethereum_types::{H256, U256, BigEndianHash};
 use rustc_hex::ToHex;
-use sgx_trts::trts::rsgx_read_rand;
 use sgx_types::*;
-use std::{collections::HashMap, path, str, string::String, sync::SgxMutex};
+use std::{boxed::Box, collections::{HashMap, HashSet}, path, str, string::String, sync::SgxMutex, vec::Vec};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use enigma_crypto::hash::Keccak256;
 use enigma_tools_t::{
@@ -19,20 +21,45 @@ use enigma_tools_t::{
     document_storage_t::{is_document, load_sealed_document, save_sealed_document, SEAL_LOG_SIZE, SealedDocumentStorage},
 };
 use enigma_types::{ContractAddress, Hash256};
-use epoch_keeper_t::epoch_t::{Epoch, EpochMarker, EpochNonce};
+use epoch_keeper_t::epoch_t::{Epoch, EpochMarker, EpochNonce, EpochOrigin};
+use epoch_keeper_t::signer::{fill_with_retry, EpochSigner, RandSource, SgxRand};
 use ocalls_t;
 
-use crate::SIGNING_KEY;
-
+pub mod chain_anchor;
 pub mod epoch_t;
 pub mod nested_encoding;
+pub mod params_upload;
+pub mod signer;
+pub mod staged_seed;
 
 const INIT_NONCE: uint32_t = 0;
 const EPOCH_DIR: &str = "epoch";
+const EPOCH_SEQUENCE_FILE: &str = "epoch-sequence.sealed";
+/// The maximum amount of workers in an epoch unless the untrusted side configures otherwise
+pub const DEFAULT_MAX_WORKERS: usize = 2048;
+
+static MAX_WORKERS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_WORKERS);
+
+extern "C" {
+    fn ocall_get_time(retval: *mut u64) -> sgx_status_t;
+}
 
 // The epoch seed contains the seeds + a nonce that must match the Ethereum tx
 lazy_static! {
     pub static ref EPOCH: SgxMutex<HashMap<U256, Epoch>> = SgxMutex::new(HashMap::new());
+    // The cached epochs created with a commitment whose seed isn't revealed yet, nothing is selected in them.
+    // Always locked after `EPOCH`.
+    static ref UNREVEALED: SgxMutex<HashSet<U256>> = SgxMutex::new(HashSet::new());
+    // The `enclave_boot_id` of the epochs created by this instance of the enclave.
+    static ref ENCLAVE_BOOT_ID: [u8; 16] = new_boot_id();
+}
+
+fn new_boot_id() -> [u8; 16] {
+    let mut boot_id = [0u8; 16];
+    if let Err(e) = fill_with_retry(&mut SgxRand, &mut boot_id) {
+        debug_println!("Failed drawing the enclave boot id, the epochs won't tell this boot from others: {:?}", e);
+    }
+    boot_id
 }
 
 /// The epoch root path is guaranteed to exist of the enclave was initialized
@@ -47,6 +74,18 @@ fn get_epoch_marker_path(nonce: U256) -> path::PathBuf {
     get_epoch_root_path().join(&path)
 }
 
+fn get_epoch_seed_path(nonce: U256) -> path::PathBuf {
+    let path = format!("epoch-seed-{:?}.sealed", nonce);
+    get_epoch_root_path().join(&path)
+}
+
+fn get_epoch_origin_path(nonce: U256) -> path::PathBuf {
+    let path = format!("epoch-origin-{:?}.sealed", nonce);
+    get_epoch_root_path().join(&path)
+}
+
+fn get_epoch_sequence_path() -> path::PathBuf { get_epoch_root_path().join(EPOCH_SEQUENCE_FILE) }
+
 /// Get the epoch marker value of H(`Epoch`)
 fn get_epoch_marker(nonce: U256) -> Result<Option<Hash256>, EnclaveError> {
     let path = get_epoch_marker_path(nonce);
@@ -79,7 +118,156 @@ fn get_epoch_marker(nonce: U256) -> Result<Option<Hash256>, EnclaveError> {
     Ok(marker)
 }
 
+/// Checks the epoch against its sealed marker
+fn verify_epoch_marker(epoch: &Epoch) -> Result<(), EnclaveError> {
+    // Get the epoch marker values (nonce + H(`Epoch`) fr
+    match get_epoch_marker(epoch.nonce)? {
+        Some(marker_hash) => {
+            debug_println!("Verifying epoch: {:?}", epoch.nonce);
+            let hash = epoch.encode_for_hashing().keccak256();
+            if hash != marker_hash {
+                return Err(SystemError(WorkerAuthError {
+                    err: format!("Given epoch parameters {:?} do not match the marker's epoch hash {:?}", epoch.nonce, marker_hash),
+                }));
+            }
+            debug_println!("Epoch verified against the marker successfully");
+            Ok(())
+        }
+        None => Err(SystemError(WorkerAuthError {
+            err: format!("Epoch marker requested but not found for nonce {:?}", epoch.nonce),
+        })),
+    }
+}
+
+/// Seal the seed of an epoch created with a commitment, the untrusted side can't give it back to verify the epoch with
+fn store_epoch_seed(epoch: &Epoch) -> Result<(), EnclaveError> {
+    let mut seed_doc: SealedDocumentStorage<EpochMarker> = SealedDocumentStorage {
+        version: 0x1234,
+        data: [0; 64],
+    };
+    seed_doc.data[..32].copy_from_slice(&H256::from_uint(&epoch.nonce).0);
+    seed_doc.data[32..].copy_from_slice(&H256::from_uint(&epoch.seed).0);
+    let mut sealed_log_in = [0u8; SEAL_LOG_SIZE];
+    seed_doc.seal(&mut sealed_log_in)?;
+    let seed_path = get_epoch_seed_path(epoch.nonce);
+    save_sealed_document(&seed_path, &sealed_log_in)?;
+    debug_println!("Sealed the epoch seed: {:?}", seed_path);
+    Ok(())
+}
+
+/// The sealed seed of the epoch, if it was created with a commitment
+fn get_epoch_seed(nonce: U256) -> Result<Option<U256>, EnclaveError> {
+    let path = get_epoch_seed_path(nonce);
+    if !is_document(&path) {
+        return Ok(None);
+    }
+    let mut sealed_log_out = [0u8; SEAL_LOG_SIZE];
+    load_sealed_document(&path, &mut sealed_log_out)?;
+    match SealedDocumentStorage::<EpochMarker>::unseal(&mut sealed_log_out)? {
+        Some(doc) if doc.data[..32] == H256::from_uint(&nonce).0[..] => Ok(Some(U256::from(&doc.data[32..]))),
+        _ => Err(SystemError(WorkerAuthError { err: format!("Failed to unseal the epoch seed: {:?}", path) })),
+    }
+}
+
+// The sealed origin of an epoch, with its nonce so a document can't be passed off as another epoch's.
+#[derive(Clone, Copy)]
+struct SealedOrigin {
+    nonce: EpochNonce,
+    origin: EpochOrigin,
+}
+
+/// Seal the origin of a new epoch, it's kept apart from the marker which only holds what's signed
+fn store_epoch_origin(epoch: &Epoch) -> Result<(), EnclaveError> {
+    let mut origin_doc = SealedDocumentStorage {
+        version: 0x1234,
+        data: SealedOrigin { nonce: H256::from_uint(&epoch.nonce).0, origin: epoch.origin },
+    };
+    let mut sealed_log_in = [0u8; SEAL_LOG_SIZE];
+    origin_doc.seal(&mut sealed_log_in)?;
+    let origin_path = get_epoch_origin_path(epoch.nonce);
+    save_sealed_document(&origin_path, &sealed_log_in)?;
+    debug_println!("Sealed the epoch origin: {:?}", origin_path);
+    Ok(())
+}
+
+/// The sealed origin of the epoch, if one was recorded
+fn get_epoch_origin(nonce: U256) -> Result<Option<EpochOrigin>, EnclaveError> {
+    let path = get_epoch_origin_path(nonce);
+    if !is_document(&path) {
+        return Ok(None);
+    }
+    let mut sealed_log_out = [0u8; SEAL_LOG_SIZE];
+    load_sealed_document(&path, &mut sealed_log_out)?;
+    match SealedDocumentStorage::<SealedOrigin>::unseal(&mut sealed_log_out)? {
+        Some(doc) if doc.data.nonce == H256::from_uint(&nonce).0 => Ok(Some(doc.data.origin)),
+        _ => Err(SystemError(WorkerAuthError { err: format!("Failed to unseal the epoch origin: {:?}", path) })),
+    }
+}
+
+/// The origin of a sealed epoch that's rebuilt, the origin is only informative so a missing or broken one doesn't
+/// stop the epoch from being used.
+fn load_epoch_origin(nonce: U256) -> EpochOrigin {
+    match get_epoch_origin(nonce) {
+        Ok(origin) => origin.unwrap_or_default(),
+        Err(e) => {
+            debug_println!("Ignoring the origin of epoch {:?}: {:?}", nonce, e);
+            EpochOrigin::default()
+        }
+    }
+}
+
+fn host_time() -> u64 {
+    let mut time = 0u64;
+    match unsafe { ocall_get_time(&mut time as *mut u64) } {
+        sgx_status_t::SGX_SUCCESS => time,
+        _ => 0,
+    }
+}
+
+/// The `sequence` of a new epoch, one past the sealed counter and `floor`, the highest sequence the enclave knows of.
+/// The counter is sealed before the sequence is used. A counter that doesn't unseal isn't replaced, that would let the
+/// untrusted side start the sequence over.
+fn next_epoch_sequence(floor: u64) -> Result<u64, EnclaveError> {
+    let path = get_epoch_sequence_path();
+    let sealed = if is_document(&path) {
+        let mut sealed_log_out = [0u8; SEAL_LOG_SIZE];
+        load_sealed_document(&path, &mut sealed_log_out)?;
+        match SealedDocumentStorage::<[u8; 8]>::unseal(&mut sealed_log_out)? {
+            Some(doc) => u64::from_be_bytes(doc.data),
+            None => return Err(SystemError(WorkerAuthError { err: format!("Failed to unseal the epoch sequence: {:?}", path) })),
+        }
+    } else {
+        0
+    };
+    let sequence = sealed.max(floor) + 1;
+    let sequence_doc = SealedDocumentStorage { version: 0x1234, data: sequence.to_be_bytes() };
+    let mut sealed_log_in = [0u8; SEAL_LOG_SIZE];
+    sequence_doc.seal(&mut sealed_log_in)?;
+    save_sealed_document(&path, &sealed_log_in)?;
+    Ok(sequence)
+}
+
+/// The origin of a new epoch of `nonce`, while `EPOCH` is locked.
+/// The sequence is the enclave's and orders the epochs. The date is the host's, see `EpochOrigin::host_created_at`,
+/// the host clock can't be trusted to go forward either, so an epoch is never dated before a cached one.
+/// A nonce that already has a sealed origin had its epoch created before, by a transition that didn't go through.
+fn new_epoch_origin(epoch_map: &HashMap<U256, Epoch>, nonce: U256) -> Result<EpochOrigin, EnclaveError> {
+    let previous = load_epoch_origin(nonce);
+    let sequence = next_epoch_sequence(epoch_map.values().map(|epoch| epoch.origin.sequence).fold(previous.sequence, u64::max))?;
+    let host_created_at = epoch_map.values().map(|epoch| epoch.origin.host_created_at).fold(host_time(), u64::max);
+    let generation_attempt = match previous.generation_attempt {
+        0 if !is_document(&get_epoch_marker_path(nonce)) => 1,
+        // An epoch sealed before the origins were recorded counts as the first attempt
+        0 => 2,
+        attempt => attempt.saturating_add(1),
+    };
+    Ok(EpochOrigin { sequence, host_created_at, enclave_boot_id: *ENCLAVE_BOOT_ID, generation_attempt })
+}
+
 fn get_epoch_from_cache(epoch_map: &HashMap<U256, Epoch>, nonce: U256) -> Result<Epoch, EnclaveError> {
+    if UNREVEALED.lock_expect("Unrevealed").contains(&nonce) {
+        return Err(SystemError(SeedNotRevealed { err: format!("The seed of epoch {:?} wasn't revealed yet", nonce) }));
+    }
     match epoch_map.get(&nonce) {
         Some(epoch) => Ok(epoch.clone()),
         None => Err(SystemError(WorkerAuthError { err: format!("Epoch nonce {:?} not found in cache.", nonce) })),
@@ -107,11 +295,48 @@ fn store_epoch(epoch: Epoch) -> Result<(), EnclaveError> {
     Ok(())
 }
 
-pub(crate) fn ecall_set_worker_params_internal(worker_params_rlp: &[u8], seed_in: &[u8; 32], nonce_in: &[u8; 32],
-                                               rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32],
+pub(crate) fn ecall_set_max_workers_internal(max_workers: u32) {
+    debug_println!("Setting the maximum amount of workers per epoch to: {}", max_workers);
+    MAX_WORKERS.store(max_workers as usize, Ordering::SeqCst);
+}
+
+/// The nonce of the next new epoch: one after the latest cached epoch, or `INIT_NONCE` if there's none.
+fn next_nonce(epoch_map: &HashMap<U256, Epoch>) -> U256 {
+    match epoch_map.keys().max() {
+        Some(nonce) => nonce + 1,
+        None => INIT_NONCE.into(),
+    }
+}
+
+/// Caches the epoch, evicting the oldest one if a new nonce would take the cache over `EPOCH_CAP`.
+/// An epoch that's already cached is replaced in place.
+fn insert_epoch(epoch_map: &mut HashMap<U256, Epoch>, epoch: Epoch) {
+    if epoch_map.len() >= EPOCH_CAP && !epoch_map.contains_key(&epoch.nonce) {
+        // Safe to unwrap because we just verified the size of the `HashMap`
+        let key = *epoch_map.keys().min().unwrap();
+        if let Some(removed_epoch) = epoch_map.remove(&key) {
+           debug_println!("Cache reached its capacity of {}, removed first epoch: {:?}", EPOCH_CAP, removed_epoch.nonce);
+        }
+    }
+    // Add the `Epoch` to the epoch cache regardless of weather it was created or recovered from a sealed marker
+    match epoch_map.insert(epoch.nonce, epoch) {
+        Some(_) => debug_println!("Replaced a cached epoch"),
+        None => debug_println!("Epoch stored successfully"),
+    }
+}
+
+/// Creates a new epoch, or verifies the sealed one of `nonce_in` if `seed_in` isn't empty.
+/// A new epoch gets a fresh seed, which is returned in `rand_out` and signed (see `EpochSeed`) only if `raw_seed` is set,
+/// for the contracts that take the seed itself. Otherwise `rand_out` gets `keccak256(seed)` and the signature is over
+/// the commitment (see `EpochSeedCommitment`), the seed stays sealed until `ecall_reveal_epoch_seed_internal`.
+pub(crate) fn ecall_set_worker_params_internal(signer: &dyn EpochSigner, rand: &mut dyn RandSource,
+                                               worker_params_rlp: &[u8], seed_in: &[u8; 32], nonce_in: &[u8; 32],
+                                               raw_seed: bool, rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32],
                                                sig_out: &mut [u8; 65]) -> Result<(), EnclaveError> {
+    // Nothing is stored for an epoch that couldn't be signed
+    signer.check_ready()?;
     // RLP decoding the necessary data
-    let worker_params: InputWorkerParams = decode(worker_params_rlp);
+    let worker_params = InputWorkerParams::from_rlp(worker_params_rlp)?;
     const EMPTY_SLICE: [u8; 32] = [0; 32];
     let mut existing_epoch: Option<Epoch> = None;
     // If the seed input is not an empty slice, recover an `Epoch` from the sealed marker
@@ -119,64 +344,133 @@ pub(crate) fn ecall_set_worker_params_internal(worker_params_rlp: &[u8], seed_in
     if seed_in != &EMPTY_SLICE {
         let seed = U256::from(seed_in);
         let nonce = U256::from(nonce_in);
-        // Get the epoch marker values (nonce + H(`Epoch`) fr
-        if let Some(marker_hash) = get_epoch_marker(nonce)? {
-            let worker_params = worker_params.clone();
-            let epoch = Epoch { nonce, seed, worker_params };
-            debug_println!("Verifying epoch: {:?}", epoch);
-            let hash = epoch.encode_for_hashing().keccak256();
-            if hash != marker_hash {
-                return Err(SystemError(WorkerAuthError {
-                    err: format!("Given epoch parameters {:?} do not match the marker's epoch hash {:?}", nonce, marker_hash),
-                }));
-            }
-            debug_println!("Epoch verified against the marker successfully");
-            existing_epoch = Some(epoch);
-        } else {
-            return Err(SystemError(WorkerAuthError {
-                err: format!("Epoch marker requested but not found for nonce {:?}", nonce),
-            }));
-        }
+        let epoch = Epoch { nonce, seed, worker_params: worker_params.clone(), origin: load_epoch_origin(nonce) };
+        verify_epoch_marker(&epoch)?;
+        existing_epoch = Some(epoch);
     }
     let mut guard = EPOCH.lock_expect("Epoch");
     // If no seed/nonce inputs were provided, create a new epoch
-    let epoch = match existing_epoch {
-        Some(epoch) => epoch,
+    // A verified epoch came with its seed, so it's revealed already
+    let (epoch, msg, revealed) = match existing_epoch {
+        Some(epoch) => {
+            let msg = epoch.signable().to_signable_bytes();
+            (epoch, msg, true)
+        }
         None => {
-            // If the `Epoch` cache is not empty, increment the last nonce that exists in the hashmap by 1
-            let nonce = match guard.keys().max() {
-                Some(nonce) => nonce + 1,
-                None => INIT_NONCE.into(),
+            // Only new epochs are checked against the limit, a sealed epoch was already accepted once
+            worker_params.validate(MAX_WORKERS.load(Ordering::SeqCst))?;
+            if worker_params.workers.is_empty() {
+                debug_println!("Storing an epoch without workers, the worker selection will fail until the next epoch");
+            }
+            let nonce = next_nonce(&guard);
+            // A seed staged for this epoch saves the draw, without one the seed is read before anything is stored,
+            // a failure leaves no trace of the epoch
+            let seed_bytes = match staged_seed::take(nonce, signer) {
+                Some(seed_bytes) => seed_bytes,
+                None => {
+                    let mut seed_bytes = [0u8; 32];
+                    fill_with_retry(rand, &mut seed_bytes[..])?;
+                    seed_bytes
+                }
             };
+            let seed = U256::from(&seed_bytes);
             *nonce_out = EpochNonce::from(nonce);
-            rsgx_read_rand(&mut rand_out[..])?;
-            let seed = U256::from(rand_out.as_ref());
-            let epoch = Epoch { nonce, seed, worker_params };
-            debug_println!("Creating new epoch with nonce {:?} and seed: {:?}", nonce, seed);
+            let origin = new_epoch_origin(&guard, nonce)?;
+            let epoch = Epoch { nonce, seed, worker_params, origin };
+            debug_println!("Creating new epoch with nonce {:?}, origin: {:?}", nonce, origin);
             store_epoch(epoch.clone())?;
-            epoch
+            store_epoch_origin(&epoch)?;
+            if raw_seed {
+                *rand_out = seed_bytes;
+                let msg = epoch.signable().to_signable_bytes();
+                (epoch, msg, true)
+            } else {
+                store_epoch_seed(&epoch)?;
+                let commitment = epoch.commitment();
+                *rand_out = commitment.commitment.0;
+                (epoch, commitment.to_signable_bytes(), false)
+            }
         }
     };
-    // Removing the first item (lower nonce) from the cache if capacity is reached
-    if guard.len() == EPOCH_CAP {
-        // Safe to unwrap because we just verified the size of the `HashMap`
-        // Cloning because I couldn't mutably borrow the key enough to satisfy the borrow checker
-        let key = guard.keys().min().unwrap().clone();
-        if let Some(removed_epoch) = guard.remove(&key) {
-           debug_println!("Cache reached its capacity of {}, removed first epoch: {:?}", EPOCH_CAP, removed_epoch);
-        }
+    *sig_out = signer.sign(&msg)?;
+    let nonce = epoch.nonce;
+    insert_epoch(&mut guard, epoch);
+    let mut unrevealed = UNREVEALED.lock_expect("Unrevealed");
+    if revealed {
+        unrevealed.remove(&nonce);
+    } else {
+        unrevealed.insert(nonce);
     }
-    // Add the `Epoch` to the epoch cache regardless of weather it was created or recovered from a sealed marker
-    match guard.insert(epoch.nonce.clone(), epoch.clone()) {
-        Some(prev) => debug_println!("New epoch stored successfully"),
-        None => debug_println!("Initial epoch stored successfully"),
-    }
-    let msg = epoch.encode_for_hashing();
-    *sig_out = SIGNING_KEY.sign(&msg)?;
     debug_println!("Signed the message : 0x{}", msg.to_hex::<String>());
     Ok(())
 }
 
+/// Like `ecall_set_worker_params_internal`, with the worker parameters of a chunked upload (see `params_upload`).
+/// `worker_params_hash` is the keccak of their RLP, nothing is committed to an epoch unless the upload matches it.
+pub(crate) fn ecall_set_worker_params_finish_internal(signer: &dyn EpochSigner, rand: &mut dyn RandSource, handle: u64,
+                                                      worker_params_hash: &Hash256, seed_in: &[u8; 32], nonce_in: &[u8; 32],
+                                                      raw_seed: bool, rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32],
+                                                      sig_out: &mut [u8; 65]) -> Result<(), EnclaveError> {
+    let worker_params_rlp = params_upload::finish(handle, worker_params_hash)?;
+    ecall_set_worker_params_internal(signer, rand, &worker_params_rlp, seed_in, nonce_in, raw_seed, rand_out, nonce_out, sig_out)
+}
+
+/// `ecall_reveal_epoch_seed_internal` with the worker parameters and the receipt proof uploaded with `params_upload`,
+/// for the epochs too big to be revealed in a single ecall.
+pub(crate) fn ecall_reveal_epoch_seed_finish_internal(nonce: U256, params_handle: u64, worker_params_hash: &Hash256,
+                                                      proof_handle: u64, receipt_proof_hash: &Hash256,
+                                                      seed_out: &mut [u8; 32]) -> Result<(), EnclaveError> {
+    let worker_params_rlp = params_upload::finish(params_handle, worker_params_hash)?;
+    let receipt_proof_rlp = params_upload::finish(proof_handle, receipt_proof_hash)?;
+    ecall_reveal_epoch_seed_internal(nonce, &worker_params_rlp, &receipt_proof_rlp, seed_out)
+}
+
+/// Releases the seed of an epoch created with a commitment, once the untrusted side proves that the
+/// `WorkersParameterized` event of the commitment is in a confirmed receipt of the anchored chain (see `chain_anchor`).
+/// The event is checked against the sealed epoch: its commitment, nonce, workers and stakes.
+/// The epoch is rebuilt from the sealed seed and marker, so it can still be revealed after a restart.
+pub(crate) fn ecall_reveal_epoch_seed_internal(nonce: U256, worker_params_rlp: &[u8], receipt_proof_rlp: &[u8],
+                                               seed_out: &mut [u8; 32]) -> Result<(), EnclaveError> {
+    let seed = match get_epoch_seed(nonce)? {
+        Some(seed) => seed,
+        None => return Err(SystemError(SeedNotRevealed { err: format!("Epoch {:?} wasn't created with a commitment", nonce) })),
+    };
+    let epoch = Epoch { nonce, seed, worker_params: InputWorkerParams::from_rlp(worker_params_rlp)?, origin: load_epoch_origin(nonce) };
+    verify_epoch_marker(&epoch)?;
+    let receipt_proof = ReceiptProof::from_rlp(receipt_proof_rlp)
+        .map_err(|e| SystemError(SeedNotRevealed { err: format!("Malformed receipt proof: {}", e) }))?;
+    let (event_data, checkpoint) = chain_anchor::proven_log(&receipt_proof, &EpochParams::topic())?;
+    verify_commitment_event(&epoch, &event_data)?;
+    chain_anchor::advance(checkpoint)?;
+    *seed_out = H256::from_uint(&seed).0;
+    let mut guard = EPOCH.lock_expect("Epoch");
+    insert_epoch(&mut guard, epoch);
+    UNREVEALED.lock_expect("Unrevealed").remove(&nonce);
+    debug_println!("Revealed the seed of epoch {:?}", nonce);
+    Ok(())
+}
+
+/// Checks the `WorkersParameterized` event against the epoch, the contracts that take a commitment emit it in place
+/// of the seed. The event is decoded like the principal decodes it, see `EpochParams`.
+fn verify_commitment_event(epoch: &Epoch, event_data: &[u8]) -> Result<(), EnclaveError> {
+    let event = EpochParams::decode(event_data)
+        .map_err(|e| SystemError(SeedNotRevealed { err: format!("Malformed WorkersParameterized event: {}", e) }))?;
+    let expected = epoch.commitment();
+    let mismatch = |field: &str| SystemError(SeedNotRevealed {
+        err: format!("The {} of the WorkersParameterized event doesn't match epoch {:?}", field, epoch.nonce),
+    });
+    if H256::from_uint(&event.seed) != expected.commitment {
+        return Err(mismatch("commitment"));
+    }
+    if event.nonce != expected.nonce {
+        return Err(mismatch("nonce"));
+    }
+    if !event.matches(&epoch.worker_params) {
+        return Err(mismatch("workers or stakes"));
+    }
+    Ok(())
+}
+
 pub(crate) fn ecall_get_epoch_worker_internal(sc_addr: ContractAddress, nonce: U256) -> Result<[u8; 20], EnclaveError> {
     let guard = EPOCH.lock_expect("Epoch");
     let epoch = get_epoch_from_cache(&guard, nonce)?;
@@ -186,13 +480,49 @@ pub(crate) fn ecall_get_epoch_worker_internal(sc_addr: ContractAddress, nonce: U
     Ok(worker.0)
 }
 
+/// Signs the worker selected for `sc_addr` in the epoch of `nonce`, so it can be proven to someone without access to the enclave.
+/// Only the cached epochs are known, those set or verified since the enclave started.
+pub(crate) fn ecall_get_selection_proof_internal(signer: &dyn EpochSigner, sc_addr: ContractAddress, nonce: U256,
+                                                 worker_out: &mut [u8; 20], seed_out: &mut [u8; 32],
+                                                 sig_out: &mut [u8; 65]) -> Result<(), EnclaveError> {
+    signer.check_ready()?;
+    let epoch = get_epoch_from_cache(&EPOCH.lock_expect("Epoch"), nonce)?;
+    let selection = epoch.selection(sc_addr)?;
+    *sig_out = signer.sign(&selection.to_signable_bytes())?;
+    *worker_out = selection.worker.0;
+    *seed_out = H256::from_uint(&selection.seed).0;
+    debug_println!("Signed the selection of worker {:?} for contract {:?}", selection.worker, sc_addr);
+    Ok(())
+}
+
+/// The origin of the epoch of `nonce`, from the cache or from its sealed document, for the status of the principal node.
+/// Unlike the selections it doesn't need the seed, an unrevealed epoch or one sealed before a restart has its origin too.
+pub(crate) fn ecall_dump_epoch_internal(nonce: U256) -> Result<EpochOrigin, EnclaveError> {
+    if let Some(epoch) = EPOCH.lock_expect("Epoch").get(&nonce) {
+        return Ok(epoch.origin);
+    }
+    match get_epoch_origin(nonce)? {
+        Some(origin) => Ok(origin),
+        None => Err(SystemError(WorkerAuthError { err: format!("No origin recorded for epoch {:?}", nonce) })),
+    }
+}
+
 pub mod tests {
+    use enigma_tools_m::keeper_types::rlpEncode;
+    use ethabi::{self, Token};
     use ethereum_types::{H160, U256};
     use rustc_hex::FromHex;
     use std::prelude::v1::Vec;
     use std::string::String;
 
     use super::*;
+    use enigma_crypto::asymmetric::KeyPair;
+    use enigma_tools_m::eth_hash::eth_hash;
+    use enigma_tools_m::eth_proof::{headers_to_rlp, test_receipt, test_receipt_proof, Checkpoint, Log};
+    use enigma_tools_m::signable::to_ethereum;
+    use epoch_keeper_t::chain_anchor::{self, ecall_advance_chain_internal, ecall_anchor_chain_internal};
+    use epoch_keeper_t::signer::{EnclaveSigner, ScriptedRand, SgxRand, RAND_ATTEMPTS};
+    use epoch_keeper_t::staged_seed::ecall_stage_epoch_seed_internal;
 
     // noinspection RsTypeCheck
     pub fn test_get_epoch_worker_internal() {
@@ -201,11 +531,447 @@ pub mod tests {
             workers: vec![H160::from([0u8;20]), H160::from([1u8;20]), H160::from([2u8;20]), H160::from([3u8;20])],
             stakes: vec![U256::from(1), U256::from(1), U256::from(1), U256::from(1)],
         };
-        let epoch = Epoch { nonce: U256::from(0), seed: U256::from(1), worker_params };
+        let epoch = Epoch { nonce: U256::from(0), seed: U256::from(1), worker_params, origin: EpochOrigin::default() };
         let sc_addr = ContractAddress::from([1u8; 32]);
         let worker = epoch.get_selected_worker(sc_addr).unwrap();
     }
 
+    fn worker_params_of_size(size: usize) -> InputWorkerParams {
+        InputWorkerParams {
+            km_block_number: U256::from(1),
+            workers: (0..size).map(|i| H160::from_low_u64_be(i as u64 + 1)).collect(),
+            stakes: vec![U256::from(1); size],
+        }
+    }
+
+    pub fn test_get_epoch_worker_no_workers() {
+        let epoch = Epoch { nonce: U256::from(0), seed: U256::from(1), worker_params: worker_params_of_size(0), origin: EpochOrigin::default() };
+        match epoch.get_selected_worker(ContractAddress::from([1u8; 32])) {
+            Err(SystemError(NoWorkersInEpoch)) => (),
+            other => panic!("Expected NoWorkersInEpoch, got: {:?}", other),
+        }
+    }
+
+    pub fn test_max_worker_params() {
+        let worker_params = worker_params_of_size(DEFAULT_MAX_WORKERS);
+        assert!(worker_params.validate(DEFAULT_MAX_WORKERS).is_ok());
+        let epoch = Epoch { nonce: U256::from(0), seed: U256::from(1), worker_params, origin: EpochOrigin::default() };
+        epoch.get_selected_worker(ContractAddress::from([1u8; 32])).unwrap();
+    }
+
+    pub fn test_set_worker_params_over_max() {
+        let worker_params_rlp = rlpEncode(&worker_params_of_size(MAX_WORKERS.load(Ordering::SeqCst) + 1)).to_vec();
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        let res = ecall_set_worker_params_internal(&EnclaveSigner, &mut SgxRand, &worker_params_rlp, &[0; 32], &[0; 32], true, &mut rand_out, &mut nonce_out, &mut sig_out);
+        match res {
+            Err(SystemError(WorkerParamsError { .. })) => (),
+            other => panic!("Expected WorkerParamsError, got: {:?}", other),
+        }
+    }
+
+    pub fn test_set_worker_params_malformed_rlp() {
+        let mut worker_params_rlp = rlpEncode(&worker_params_of_size(2)).to_vec();
+        worker_params_rlp.pop();
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        let res = ecall_set_worker_params_internal(&EnclaveSigner, &mut SgxRand, &worker_params_rlp, &[0; 32], &[0; 32], true, &mut rand_out, &mut nonce_out, &mut sig_out);
+        match res {
+            Err(SystemError(WorkerParamsError { .. })) => (),
+            other => panic!("Expected WorkerParamsError, got: {:?}", other),
+        }
+    }
+
+    fn set_worker_params(signer: &dyn EpochSigner, rand: &mut dyn RandSource, worker_params: &InputWorkerParams)
+                         -> Result<(U256, [u8; 32], [u8; 65]), EnclaveError> {
+        let worker_params_rlp = rlpEncode(worker_params).to_vec();
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        ecall_set_worker_params_internal(signer, rand, &worker_params_rlp, &[0; 32], &[0; 32], true, &mut rand_out, &mut nonce_out, &mut sig_out)?;
+        // As the untrusted side hands it on, see `epoch_keeper_u::set_worker_params`.
+        Ok((U256::from(&nonce_out), rand_out, to_ethereum(&sig_out)?))
+    }
+
+    pub fn test_epoch_nonce_sequencing() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let mut seeds = vec![7u8; 32];
+        seeds.extend_from_slice(&[8u8; 32]);
+        let mut rand = ScriptedRand::new(seeds);
+        let worker_params = worker_params_of_size(2);
+
+        let (first_nonce, first_seed, first_sig) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        let (second_nonce, second_seed, second_sig) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        assert_eq!(second_nonce, first_nonce + 1);
+        assert_eq!((first_seed, second_seed), ([7u8; 32], [8u8; 32]));
+        for (nonce, seed, sig) in vec![(first_nonce, first_seed, first_sig), (second_nonce, second_seed, second_sig)] {
+            let epoch = Epoch { nonce, seed: U256::from(&seed), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+            assert!(epoch.signable().verify(&sig, &EpochSigner::address(&signer)).unwrap());
+        }
+        // Without randomness no epoch is created, and the nonce isn't consumed
+        assert!(set_worker_params(&signer, &mut rand, &worker_params).is_err());
+        assert_eq!(next_nonce(&EPOCH.lock_expect("Epoch")), second_nonce + 1);
+    }
+
+    pub fn test_epoch_seed_domain_separation() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let mut rand = ScriptedRand::new(vec![9u8; 64]);
+        let worker_params = worker_params_of_size(2);
+
+        let (first_nonce, first_seed, first_sig) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        let (second_nonce, second_seed, second_sig) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        assert_eq!(first_seed, second_seed);
+        assert_ne!(&first_sig[..], &second_sig[..]);
+        // The same seed under another nonce is a different message, a signature can't be replayed across epochs
+        let first = Epoch { nonce: first_nonce, seed: U256::from(&first_seed), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+        let second = Epoch { nonce: second_nonce, seed: U256::from(&second_seed), worker_params, origin: EpochOrigin::default() };
+        assert_ne!(first.signable().to_signable_bytes(), second.signable().to_signable_bytes());
+        assert!(!second.signable().verify(&first_sig, &EpochSigner::address(&signer)).unwrap());
+        assert!(second.signable().verify(&second_sig, &EpochSigner::address(&signer)).unwrap());
+    }
+
+    pub fn test_epoch_rand_retry() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let worker_params = worker_params_of_size(2);
+        let nonce = next_nonce(&EPOCH.lock_expect("Epoch"));
+
+        // A transient failure is retried
+        let mut rand = ScriptedRand::failing(RAND_ATTEMPTS as usize - 1, vec![5u8; 32]);
+        let (first_nonce, seed, _) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        assert_eq!((first_nonce, seed), (nonce, [5u8; 32]));
+
+        // A lasting one gives up without taking a nonce
+        let mut rand = ScriptedRand::failing(RAND_ATTEMPTS as usize, vec![6u8; 32]);
+        match set_worker_params(&signer, &mut rand, &worker_params) {
+            Err(SystemError(RandUnavailable { attempts })) => assert_eq!(attempts, RAND_ATTEMPTS),
+            other => panic!("Expected RandUnavailable, got: {:?}", other),
+        }
+        assert_eq!(next_nonce(&EPOCH.lock_expect("Epoch")), nonce + 1);
+    }
+
+    pub fn test_staged_seed_consumed() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let worker_params = worker_params_of_size(2);
+        let mut staged_nonce = [0u8; 32];
+        ecall_stage_epoch_seed_internal(&signer, &mut ScriptedRand::new(vec![11u8; 32]), &mut staged_nonce).unwrap();
+        assert_eq!(U256::from(&staged_nonce), next_nonce(&EPOCH.lock_expect("Epoch")));
+        // Staging again keeps the seed, there's nothing left to draw
+        ecall_stage_epoch_seed_internal(&signer, &mut ScriptedRand::new(vec![]), &mut staged_nonce).unwrap();
+        assert!(staged_seed::is_staged());
+
+        // The new epoch takes the staged seed without reading any randomness
+        let (nonce, seed, sig) = set_worker_params(&signer, &mut ScriptedRand::new(vec![]), &worker_params).unwrap();
+        assert_eq!((nonce, seed), (U256::from(&staged_nonce), [11u8; 32]));
+        let epoch = Epoch { nonce, seed: U256::from(&seed), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+        assert!(epoch.signable().verify(&sig, &EpochSigner::address(&signer)).unwrap());
+        verify_epoch_marker(&epoch).unwrap();
+        assert!(!staged_seed::is_staged());
+
+        // It's used once, the next epoch draws its own
+        let (next, seed, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![12u8; 32]), &worker_params).unwrap();
+        assert_eq!((next, seed), (nonce + 1, [12u8; 32]));
+    }
+
+    pub fn test_staged_seed_key_rotation() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let rotated = KeyPair::from_slice(&[2u8; 32]).unwrap();
+        let worker_params = worker_params_of_size(2);
+        let mut staged_nonce = [0u8; 32];
+        ecall_stage_epoch_seed_internal(&signer, &mut ScriptedRand::new(vec![13u8; 32]), &mut staged_nonce).unwrap();
+
+        // The seed staged under the old key is dropped, the epoch keeps its nonce with a fresh seed
+        let (nonce, seed, sig) = set_worker_params(&rotated, &mut ScriptedRand::new(vec![14u8; 32]), &worker_params).unwrap();
+        assert_eq!((nonce, seed), (U256::from(&staged_nonce), [14u8; 32]));
+        let epoch = Epoch { nonce, seed: U256::from(&seed), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+        assert!(epoch.signable().verify(&sig, &EpochSigner::address(&rotated)).unwrap());
+        assert!(!staged_seed::is_staged());
+
+        // Staging under the new key draws again even though the nonce would match an older staging
+        ecall_stage_epoch_seed_internal(&signer, &mut ScriptedRand::new(vec![15u8; 32]), &mut staged_nonce).unwrap();
+        ecall_stage_epoch_seed_internal(&rotated, &mut ScriptedRand::new(vec![16u8; 32]), &mut staged_nonce).unwrap();
+        let (next, seed, _) = set_worker_params(&rotated, &mut ScriptedRand::new(vec![]), &worker_params).unwrap();
+        assert_eq!((next, seed), (nonce + 1, [16u8; 32]));
+    }
+
+    pub fn test_staged_seed_fallback() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let worker_params = worker_params_of_size(2);
+        assert!(!staged_seed::is_staged());
+        // Nothing staged, the seed is drawn on demand
+        let nonce = next_nonce(&EPOCH.lock_expect("Epoch"));
+        let (first, seed, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![17u8; 32]), &worker_params).unwrap();
+        assert_eq!((first, seed), (nonce, [17u8; 32]));
+
+        // The nonce was taken by an epoch the seed wasn't staged for, like a sealed one verified in the meantime
+        let mut staged_nonce = [0u8; 32];
+        ecall_stage_epoch_seed_internal(&signer, &mut ScriptedRand::new(vec![18u8; 32]), &mut staged_nonce).unwrap();
+        let stale = U256::from(&staged_nonce);
+        assert_eq!(stale, first + 1);
+        insert_epoch(&mut EPOCH.lock_expect("Epoch"), Epoch { nonce: stale, seed: U256::from(1), worker_params: worker_params.clone(), origin: EpochOrigin::default() });
+        let (second, seed, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![19u8; 32]), &worker_params).unwrap();
+        assert_eq!((second, seed), (stale + 1, [19u8; 32]));
+        assert!(!staged_seed::is_staged());
+
+        // Without a staged seed nor randomness no epoch is created, and the nonce isn't consumed
+        assert!(set_worker_params(&signer, &mut ScriptedRand::new(vec![]), &worker_params).is_err());
+        assert_eq!(next_nonce(&EPOCH.lock_expect("Epoch")), second + 1);
+    }
+
+    struct UninitializedSigner;
+
+    impl EpochSigner for UninitializedSigner {
+        fn check_ready(&self) -> Result<(), EnclaveError> { Err(SystemError(SigningKeyUninitialized)) }
+
+        fn sign(&self, _msg: &[u8]) -> Result<[u8; 65], EnclaveError> { panic!("Signing with an uninitialized key") }
+
+        fn address(&self) -> [u8; 20] { [0u8; 20] }
+    }
+
+    pub fn test_epoch_signing_key_uninitialized() {
+        let nonce = next_nonce(&EPOCH.lock_expect("Epoch"));
+        let mut rand = ScriptedRand::new(vec![7u8; 32]);
+        match set_worker_params(&UninitializedSigner, &mut rand, &worker_params_of_size(2)) {
+            Err(SystemError(SigningKeyUninitialized)) => (),
+            other => panic!("Expected SigningKeyUninitialized, got: {:?}", other),
+        }
+        // Neither the randomness nor the nonce were used
+        assert_eq!(next_nonce(&EPOCH.lock_expect("Epoch")), nonce);
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        assert_eq!(set_worker_params(&signer, &mut rand, &worker_params_of_size(2)).unwrap().1, [7u8; 32]);
+    }
+
+    pub fn test_selection_proof() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let mut rand = ScriptedRand::new(vec![4u8; 32]);
+        let worker_params = worker_params_of_size(3);
+        let (nonce, seed, _) = set_worker_params(&signer, &mut rand, &worker_params).unwrap();
+        let sc_addr = ContractAddress::from([2u8; 32]);
+        let (mut worker_out, mut seed_out, mut sig_out) = ([0u8; 20], [0u8; 32], [0u8; 65]);
+        ecall_get_selection_proof_internal(&signer, sc_addr, nonce, &mut worker_out, &mut seed_out, &mut sig_out).unwrap();
+        assert_eq!(seed_out, seed);
+
+        let epoch = Epoch { nonce, seed: U256::from(&seed), worker_params, origin: EpochOrigin::default() };
+        let selection = epoch.selection(sc_addr).unwrap();
+        assert_eq!(selection.worker, H160(worker_out));
+        assert!(selection.verify(&to_ethereum(&sig_out).unwrap(), &EpochSigner::address(&signer)).unwrap());
+        // Another contract's selection can't be passed off with this signature
+        let other = epoch.selection(ContractAddress::from([3u8; 32])).unwrap();
+        assert!(!other.verify(&to_ethereum(&sig_out).unwrap(), &EpochSigner::address(&signer)).unwrap());
+
+        // An epoch that isn't cached has nothing to prove
+        let res = ecall_get_selection_proof_internal(&signer, sc_addr, nonce + 1000, &mut worker_out, &mut seed_out, &mut sig_out);
+        assert!(res.is_err());
+    }
+
+    pub fn test_chunked_worker_params_selection() {
+        const WORKERS: usize = 5000;
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let mut worker_params = worker_params_of_size(WORKERS);
+        worker_params.stakes = (0..WORKERS).map(|i| U256::from(i % 97 + 1)).collect();
+        let worker_params_rlp = rlpEncode(&worker_params).to_vec();
+        let hash = worker_params_rlp.keccak256();
+        let max_workers = MAX_WORKERS.swap(WORKERS, Ordering::SeqCst);
+
+        let (single_nonce, single_seed, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![6u8; 32]), &worker_params).unwrap();
+        let upload = |rlp: &[u8]| {
+            let handle = params_upload::begin(rlp.len()).unwrap();
+            for part in rlp.chunks(16 * 1024) {
+                params_upload::chunk(handle, part).unwrap();
+            }
+            handle
+        };
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        let mut rand = ScriptedRand::new(vec![6u8; 32]);
+        ecall_set_worker_params_finish_internal(&signer, &mut rand, upload(&worker_params_rlp), &hash, &[0; 32], &[0; 32], true,
+                                                &mut rand_out, &mut nonce_out, &mut sig_out).unwrap();
+        let chunked_nonce = U256::from(&nonce_out);
+        assert_eq!(rand_out, single_seed);
+        let epoch = Epoch { nonce: chunked_nonce, seed: U256::from(&rand_out), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+        assert!(epoch.signable().verify(&to_ethereum(&sig_out).unwrap(), &EpochSigner::address(&signer)).unwrap());
+        for i in 0..8u8 {
+            let sc_addr = ContractAddress::from([i; 32]);
+            assert_eq!(ecall_get_epoch_worker_internal(sc_addr, single_nonce).unwrap(), ecall_get_epoch_worker_internal(sc_addr, chunked_nonce).unwrap());
+        }
+
+        // Parameters that aren't the ones hashed don't make an epoch
+        let mut other_params = worker_params;
+        other_params.stakes[0] = U256::from(1000);
+        let other_rlp = rlpEncode(&other_params).to_vec();
+        let res = ecall_set_worker_params_finish_internal(&signer, &mut ScriptedRand::new(vec![6u8; 32]), upload(&other_rlp), &hash,
+                                                          &[0; 32], &[0; 32], true, &mut rand_out, &mut nonce_out, &mut sig_out);
+        match res {
+            Err(SystemError(WorkerParamsError { .. })) => (),
+            other => panic!("Expected WorkerParamsError, got: {:?}", other),
+        }
+        assert_eq!(next_nonce(&EPOCH.lock_expect("Epoch")), chunked_nonce + 1);
+        MAX_WORKERS.store(max_workers, Ordering::SeqCst);
+    }
+
+    fn commitment_event(epoch: &Epoch, commitment: [u8; 32]) -> Vec<u8> {
+        let uint = |value: &U256| ethabi::Uint::from(&H256::from_uint(value).0[..]);
+        ethabi::encode(&[
+            Token::Uint(ethabi::Uint::from(&commitment[..])),
+            Token::Uint(ethabi::Uint::from(10u64)),
+            Token::Uint(ethabi::Uint::from(11u64)),
+            Token::Array(epoch.worker_params.workers.iter().map(|worker| Token::Address(ethabi::Address::from_slice(&worker.0))).collect()),
+            Token::Array(epoch.worker_params.stakes.iter().map(|stake| Token::Uint(uint(stake))).collect()),
+            Token::Uint(uint(&epoch.nonce)),
+        ])
+    }
+
+    const ENIGMA_CONTRACT: [u8; 20] = [0xee; 20];
+    const CONFIRMATIONS: u64 = 2;
+
+    fn commitment_log(event: Vec<u8>) -> Log { Log { address: H160(ENIGMA_CONTRACT), topics: vec![EpochParams::topic()], data: event } }
+
+    // The RLP of a proof of a receipt with `logs`, included in the block after the anchored checkpoint
+    // with `blocks` blocks in all.
+    fn commitment_proof(blocks: u64, success: bool, logs: &[Log]) -> Vec<u8> {
+        let checkpoint = ecall_anchor_chain_internal(ENIGMA_CONTRACT, Checkpoint::default(), CONFIRMATIONS).unwrap();
+        rlpEncode(&test_receipt_proof(&checkpoint, blocks, 0, &[test_receipt(success, logs)], 0)).to_vec()
+    }
+
+    pub fn test_epoch_seed_commit_reveal() {
+        chain_anchor::reset();
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let mut rand = ScriptedRand::new(vec![3u8; 32]);
+        let worker_params = worker_params_of_size(3);
+        let worker_params_rlp = rlpEncode(&worker_params).to_vec();
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        ecall_set_worker_params_internal(&signer, &mut rand, &worker_params_rlp, &[0; 32], &[0; 32], false, &mut rand_out, &mut nonce_out, &mut sig_out).unwrap();
+
+        // Only the commitment leaves the enclave, and that's what is signed
+        let nonce = U256::from(&nonce_out);
+        let epoch = Epoch { nonce, seed: U256::from(&[3u8; 32]), worker_params: worker_params.clone(), origin: EpochOrigin::default() };
+        let commitment = eth_hash(&[3u8; 32]).0;
+        assert_eq!(rand_out, commitment);
+        assert!(epoch.commitment().verify(&to_ethereum(&sig_out).unwrap(), &EpochSigner::address(&signer)).unwrap());
+        assert!(!epoch.signable().verify(&to_ethereum(&sig_out).unwrap(), &EpochSigner::address(&signer)).unwrap());
+
+        // Nothing that depends on the seed is answered before it's revealed
+        let sc_addr = ContractAddress::from([2u8; 32]);
+        let (mut worker_out, mut seed_out, mut proof_sig) = ([0u8; 20], [0u8; 32], [0u8; 65]);
+        match ecall_get_selection_proof_internal(&signer, sc_addr, nonce, &mut worker_out, &mut seed_out, &mut proof_sig) {
+            Err(SystemError(SeedNotRevealed { .. })) => (),
+            other => panic!("Expected SeedNotRevealed, got: {:?}", other),
+        }
+        assert!(ecall_get_epoch_worker_internal(sc_addr, nonce).is_err());
+
+        // Not before the receipt of the commitment is proven with its confirmations
+        let event = || commitment_event(&epoch, commitment);
+        let other_contract = Log { address: H160([0xaa; 20]), ..commitment_log(event()) };
+        let refused = vec![
+            vec![],
+            commitment_proof(CONFIRMATIONS, true, &[commitment_log(event())]),
+            commitment_proof(CONFIRMATIONS + 1, false, &[commitment_log(event())]),
+            commitment_proof(CONFIRMATIONS + 1, true, &[other_contract]),
+            commitment_proof(CONFIRMATIONS + 1, true, &[commitment_log(commitment_event(&epoch, [4u8; 32]))]),
+        ];
+        let mut seed_out = [0u8; 32];
+        for proof in refused {
+            match ecall_reveal_epoch_seed_internal(nonce, &worker_params_rlp, &proof, &mut seed_out) {
+                Err(SystemError(SeedNotRevealed { .. })) => (),
+                other => panic!("Expected SeedNotRevealed, got: {:?}", other),
+            }
+        }
+        // Nor on a chain that doesn't start at the checkpoint
+        let checkpoint = ecall_anchor_chain_internal(ENIGMA_CONTRACT, Checkpoint::default(), CONFIRMATIONS).unwrap();
+        let forked = Checkpoint { number: checkpoint.number, hash: eth_hash(b"fork") };
+        let proof = rlpEncode(&test_receipt_proof(&forked, 3, 0, &[test_receipt(true, &[commitment_log(event())])], 0)).to_vec();
+        assert!(ecall_reveal_epoch_seed_internal(nonce, &worker_params_rlp, &proof, &mut seed_out).is_err());
+        let other_workers = rlpEncode(&worker_params_of_size(2)).to_vec();
+        let proof = commitment_proof(CONFIRMATIONS + 1, true, &[commitment_log(event())]);
+        assert!(ecall_reveal_epoch_seed_internal(nonce, &other_workers, &proof, &mut seed_out).is_err());
+        assert_eq!(seed_out, [0u8; 32]);
+        // Another contract can't take over the anchor
+        assert!(ecall_anchor_chain_internal([0xaa; 20], Checkpoint::default(), CONFIRMATIONS).is_err());
+
+        let other_log = Log { topics: vec![eth_hash(b"Other()")], ..commitment_log(vec![]) };
+        let proof = commitment_proof(CONFIRMATIONS + 1, true, &[other_log, commitment_log(event())]);
+        ecall_reveal_epoch_seed_internal(nonce, &worker_params_rlp, &proof, &mut seed_out).unwrap();
+        assert_eq!(seed_out, [3u8; 32]);
+        ecall_get_selection_proof_internal(&signer, sc_addr, nonce, &mut worker_out, &mut seed_out, &mut proof_sig).unwrap();
+        assert_eq!(H160(worker_out), epoch.get_selected_worker(sc_addr).unwrap());
+        // The next proofs start at the block of the receipt
+        let next = ecall_anchor_chain_internal(ENIGMA_CONTRACT, Checkpoint::default(), CONFIRMATIONS).unwrap();
+        assert_eq!(next.number, checkpoint.number + 1);
+        // And they move along headers alone, up to the last with the confirmations on top of it
+        let headers = test_receipt_proof(&next, CONFIRMATIONS + 3, 0, &[], 0).headers;
+        let forked = test_receipt_proof(&forked, CONFIRMATIONS + 3, 0, &[], 0).headers;
+        assert!(ecall_advance_chain_internal(&headers_to_rlp(&forked)).is_err());
+        assert_eq!(ecall_advance_chain_internal(&headers_to_rlp(&headers[..CONFIRMATIONS as usize])).unwrap(), next);
+        let advanced = ecall_advance_chain_internal(&headers_to_rlp(&headers)).unwrap();
+        assert_eq!(advanced.number, next.number + 3);
+        assert_eq!(ecall_anchor_chain_internal(ENIGMA_CONTRACT, Checkpoint::default(), CONFIRMATIONS).unwrap(), advanced);
+
+        // An epoch created with its raw seed has nothing to reveal
+        let (raw_nonce, _, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![5u8; 32]), &worker_params).unwrap();
+        let proof = commitment_proof(CONFIRMATIONS + 1, true, &[commitment_log(event())]);
+        assert!(ecall_reveal_epoch_seed_internal(raw_nonce, &worker_params_rlp, &proof, &mut seed_out).is_err());
+    }
+
+    pub fn test_epoch_cache_insert() {
+        let mut cache = HashMap::new();
+        assert_eq!(next_nonce(&cache), U256::from(INIT_NONCE));
+        let epoch = |nonce: usize, seed: u64| Epoch { nonce: U256::from(nonce), seed: U256::from(seed), worker_params: worker_params_of_size(1), origin: EpochOrigin::default() };
+        for nonce in 0..EPOCH_CAP {
+            insert_epoch(&mut cache, epoch(nonce, 1));
+        }
+        assert_eq!(cache.len(), EPOCH_CAP);
+        assert_eq!(next_nonce(&cache), U256::from(EPOCH_CAP));
+
+        // A new nonce at capacity evicts the oldest epoch
+        insert_epoch(&mut cache, epoch(EPOCH_CAP, 1));
+        assert_eq!(cache.len(), EPOCH_CAP);
+        assert!(!cache.contains_key(&U256::from(0)));
+        assert_eq!(next_nonce(&cache), U256::from(EPOCH_CAP + 1));
+
+        // Re-inserting a cached nonce replaces it without evicting anything
+        insert_epoch(&mut cache, epoch(1, 2));
+        assert_eq!(cache.len(), EPOCH_CAP);
+        assert_eq!(cache[&U256::from(1)].seed, U256::from(2));
+        assert!(cache.contains_key(&U256::from(1)));
+    }
+
+    pub fn test_epoch_origin() {
+        let signer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let worker_params = worker_params_of_size(2);
+        let (nonce, seed, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![2u8; 32]), &worker_params).unwrap();
+        let origin = ecall_dump_epoch_internal(nonce).unwrap();
+        assert_eq!(origin.enclave_boot_id, *ENCLAVE_BOOT_ID);
+        assert!(origin.host_created_at > 0);
+        assert!(origin.generation_attempt >= 1);
+        assert!(EPOCH.lock_expect("Epoch").values().all(|epoch| epoch.nonce == nonce || epoch.origin.sequence < origin.sequence));
+        assert!(EPOCH.lock_expect("Epoch").values().all(|epoch| epoch.origin.host_created_at <= origin.host_created_at));
+
+        // The origin isn't signed, the epoch matches its marker with or without it
+        let epoch = Epoch { nonce, seed: U256::from(&seed), worker_params: worker_params.clone(), origin };
+        assert_eq!(epoch.encode_for_hashing(), Epoch { origin: EpochOrigin::default(), ..epoch.clone() }.encode_for_hashing());
+        verify_epoch_marker(&epoch).unwrap();
+
+        // It survives the seal, an epoch that isn't cached is dumped from its sealed origin
+        EPOCH.lock_expect("Epoch").remove(&nonce);
+        assert_eq!(get_epoch_origin(nonce).unwrap(), Some(origin));
+        assert_eq!(ecall_dump_epoch_internal(nonce).unwrap(), origin);
+        // And a verified epoch gets it back
+        let worker_params_rlp = rlpEncode(&worker_params).to_vec();
+        let (mut rand_out, mut nonce_out, mut sig_out) = ([0u8; 32], [0u8; 32], [0u8; 65]);
+        ecall_set_worker_params_internal(&signer, &mut SgxRand, &worker_params_rlp, &seed, &EpochNonce::from(nonce), true,
+                                         &mut rand_out, &mut nonce_out, &mut sig_out).unwrap();
+        assert_eq!(EPOCH.lock_expect("Epoch")[&nonce].origin, origin);
+
+        // Creating the epoch of the nonce again, like after a failed transition, is another attempt
+        EPOCH.lock_expect("Epoch").remove(&nonce);
+        let (again, _, _) = set_worker_params(&signer, &mut ScriptedRand::new(vec![3u8; 32]), &worker_params).unwrap();
+        assert_eq!(again, nonce);
+        let regenerated = ecall_dump_epoch_internal(nonce).unwrap();
+        assert_eq!(regenerated.generation_attempt, origin.generation_attempt + 1);
+        assert_eq!(regenerated.enclave_boot_id, origin.enclave_boot_id);
+        assert!(regenerated.sequence > origin.sequence);
+        assert!(regenerated.host_created_at >= origin.host_created_at);
+
+        // The sequence is sealed, it goes on from the counter without the cached origins
+        assert_eq!(next_epoch_sequence(0).unwrap(), regenerated.sequence + 1);
+
+        assert!(ecall_dump_epoch_internal(nonce + 1000).is_err());
+    }
+
     pub fn test_create_epoch_image() {
         let expected_image1: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 98, 42, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
         let worker_params1 = InputWorkerParams {
@@ -213,7 +979,7 @@ pub mod tests {
             workers: vec![],
             stakes: vec![],
         };
-        let epoch1 = Epoch { nonce: U256::from(0), seed: U256::from(90666), worker_params: worker_params1 };
+        let epoch1 = Epoch { nonce: U256::from(0), seed: U256::from(90666), worker_params: worker_params1, origin: EpochOrigin::default() };
         let image1 = epoch1.encode_for_hashing();
         assert_eq!(image1, expected_image1);
         let expected_image2: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 182, 69, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 203, 0, 0, 0, 0, 0, 0, 0, 0, 20, 156, 26, 193, 252, 165, 167, 191, 244, 251, 126, 53, 154, 158, 14, 64, 194, 164, 48, 231, 179, 0, 0, 0, 0, 0, 0, 0, 0, 20, 21, 29, 28, 170, 62, 58, 28, 11, 49, 209, 253, 100, 182, 213, 32, 239, 97, 11, 249, 156, 0, 0, 0, 0, 0, 0, 0, 0, 20, 27, 236, 232, 58, 193, 161, 149, 205, 246, 186, 143, 153, 223, 185, 176, 167, 192, 91, 75, 155, 0, 0, 0, 0, 0, 0, 0, 0, 20, 190, 73, 169, 38, 220, 62, 57, 23, 61, 133, 200, 11, 135, 183, 140, 211, 151, 28, 177, 111, 0, 0, 0, 0, 0, 0, 0, 0, 20, 144, 60, 213, 194, 162, 159, 108, 49, 159, 88, 199, 249, 198, 173, 105, 3, 161, 54, 96, 226, 0, 0, 0, 0, 0, 0, 0, 0, 20, 143, 123, 253, 113, 133, 173, 215, 156, 68, 228, 91, 227, 191, 31, 114, 35, 142, 245, 179, 32, 0, 0, 0, 0, 0, 0, 0, 0, 20, 254, 173, 30, 180, 40, 191, 132, 182, 28, 203, 170, 219, 45, 62, 0, 62, 150, 140, 40, 71, 1, 0, 0, 0, 0, 0, 0, 1, 31, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 20, 244, 107, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 84, 11, 228, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 59, 154, 202, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 119, 53, 148, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 84, 11, 228, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 168, 23, 200, 0, 0, 0, 0, 0, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 238, 107, 40, 0];
@@ -240,7 +1006,7 @@ pub mod tests {
             workers: workers.into_iter().map(|a| H160(a)).collect(),
             stakes: stakes.into_iter().map(|s| U256::from(s.clone())).collect(),
         };
-        let epoch2 = Epoch { nonce: U256::from(1), seed: U256::from(46661), worker_params: worker_params2 };
+        let epoch2 = Epoch { nonce: U256::from(1), seed: U256::from(46661), worker_params: worker_params2, origin: EpochOrigin::default() };
         let image2 = epoch2.encode_for_hashing();
         assert_eq!(image2, expected_image2);
     }
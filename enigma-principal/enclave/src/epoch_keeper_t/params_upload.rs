@@ -0,0 +1,109 @@
+//! Chunked transfer of the worker parameters into the enclave.
+//! With thousands of workers the RLP of the `InputWorkerParams` is too big for a single ecall, it's sent with
+//! `ecall_set_worker_params_begin`, a few `ecall_set_worker_params_chunk` and `ecall_set_worker_params_finish`.
+//! `finish` only hands out the RLP if it's exactly `total_len` bytes and matches the keccak the untrusted side
+//! computed over the parameters it decoded, nothing is committed to an epoch from a lost, duplicated or reordered chunk.
+//! The reveal of a committed seed uploads the `WorkersParameterized` event data the same way, it grows with the workers too.
+
+use enigma_crypto::hash::Keccak256;
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::common::errors_t::{EnclaveError::{self, SystemError}, EnclaveSystemError::WorkerParamsError};
+use enigma_types::Hash256;
+use std::collections::HashMap;
+use std::string::ToString;
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+/// An upload that isn't finished is dropped when this many newer ones were started.
+const MAX_UPLOADS: usize = 2;
+/// The biggest worker parameters that can be uploaded, they have to fit in the enclave heap (`HeapMaxSize`)
+/// next to their decoded copy and the cached epochs.
+pub(crate) const MAX_WORKER_PARAMS_LEN: usize = 2 * 1024 * 1024;
+
+struct Upload {
+    total_len: usize,
+    bytes: Vec<u8>,
+}
+
+lazy_static! {
+    static ref UPLOADS: SgxMutex<(u64, HashMap<u64, Upload>)> = SgxMutex::new((0, HashMap::new()));
+}
+
+fn upload_error(err: &str) -> EnclaveError { SystemError(WorkerParamsError { err: err.to_string() }) }
+
+/// Starts an upload of `total_len` bytes and returns its handle.
+pub(crate) fn begin(total_len: usize) -> Result<u64, EnclaveError> {
+    if total_len == 0 || total_len > MAX_WORKER_PARAMS_LEN {
+        return Err(upload_error("Invalid worker parameters length"));
+    }
+    let mut guard = UPLOADS.lock_expect("Worker params uploads");
+    let (next_handle, uploads) = &mut *guard;
+    if uploads.len() >= MAX_UPLOADS {
+        // Handles only grow, so the smallest one is the oldest.
+        let oldest = *uploads.keys().min().unwrap();
+        uploads.remove(&oldest);
+        debug_println!("Dropped the unfinished worker params upload {}", oldest);
+    }
+    *next_handle += 1;
+    uploads.insert(*next_handle, Upload { total_len, bytes: Vec::with_capacity(total_len) });
+    Ok(*next_handle)
+}
+
+/// Appends the next chunk, an upload that would grow past its length is dropped.
+pub(crate) fn chunk(handle: u64, data: &[u8]) -> Result<(), EnclaveError> {
+    let mut guard = UPLOADS.lock_expect("Worker params uploads");
+    let uploads = &mut guard.1;
+    let upload = uploads.get_mut(&handle).ok_or_else(|| upload_error("Unknown worker params upload handle"))?;
+    if upload.bytes.len() + data.len() > upload.total_len {
+        uploads.remove(&handle);
+        return Err(upload_error("The chunks are longer than the worker parameters"));
+    }
+    upload.bytes.extend_from_slice(data);
+    Ok(())
+}
+
+/// Ends the upload and returns the assembled RLP if it's complete and hashes to `expected_hash`.
+pub(crate) fn finish(handle: u64, expected_hash: &Hash256) -> Result<Vec<u8>, EnclaveError> {
+    let upload = UPLOADS.lock_expect("Worker params uploads").1.remove(&handle)
+        .ok_or_else(|| upload_error("Unknown worker params upload handle"))?;
+    if upload.bytes.len() != upload.total_len {
+        return Err(upload_error("The worker parameters upload is truncated"));
+    }
+    if upload.bytes.keccak256() != *expected_hash {
+        return Err(upload_error("The uploaded worker parameters don't match their hash"));
+    }
+    Ok(upload.bytes)
+}
+
+pub mod tests {
+    use super::*;
+
+    pub fn test_params_upload_integrity() {
+        let rlp: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let hash = rlp.keccak256();
+
+        let handle = begin(rlp.len()).unwrap();
+        for part in rlp.chunks(300) {
+            chunk(handle, part).unwrap();
+        }
+        assert_eq!(finish(handle, &hash).unwrap(), rlp);
+        // The handle can't be reused.
+        assert!(finish(handle, &hash).is_err());
+
+        let reordered = begin(rlp.len()).unwrap();
+        chunk(reordered, &rlp[500..]).unwrap();
+        chunk(reordered, &rlp[..500]).unwrap();
+        assert!(finish(reordered, &hash).is_err());
+
+        let truncated = begin(rlp.len()).unwrap();
+        chunk(truncated, &rlp[..999]).unwrap();
+        assert!(finish(truncated, &hash).is_err());
+
+        let overflow = begin(10).unwrap();
+        assert!(chunk(overflow, &rlp[..11]).is_err());
+        assert!(chunk(overflow, &rlp[..1]).is_err());
+
+        assert!(begin(0).is_err());
+        assert!(begin(MAX_WORKER_PARAMS_LEN + 1).is_err());
+    }
+}
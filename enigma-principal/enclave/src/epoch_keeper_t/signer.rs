@@ -0,0 +1,106 @@
+//! The signing key and the randomness the epoch keeper depends on.
+//! The ecalls pass `EnclaveSigner` and `SgxRand`, which wrap `SIGNING_KEY` and `rsgx_read_rand`,
+//! while the unit tests pass a fixed key and scripted randomness so the results are deterministic.
+//! The randomness is read with `fill_with_retry`, RDRAND can run dry for a moment under heavy load.
+
+use enigma_crypto::asymmetric::KeyPair;
+use enigma_tools_m::utils::EthereumAddress;
+use core::sync::atomic::spin_loop_hint;
+use enigma_tools_t::common::errors_t::{EnclaveError::{self, SystemError}, EnclaveSystemError::RandUnavailable};
+use sgx_trts::trts::rsgx_read_rand;
+use sgx_types::sgx_status_t;
+use std::vec::Vec;
+
+use crate::{signing_key_ready, SIGNING_KEY};
+
+/// How many times the random bytes are read before giving up.
+pub const RAND_ATTEMPTS: u32 = 3;
+/// How long to spin between two reads.
+const RAND_RETRY_SPINS: u32 = 1_000;
+
+/// Signs the messages the KM node sends out.
+pub trait EpochSigner {
+    /// Fails if `sign` can't succeed, checked before anything is changed.
+    fn check_ready(&self) -> Result<(), EnclaveError> { Ok(()) }
+    /// `r || s || recovery id`, the untrusted side converts it, see `enigma_tools_m::signable::SigFormat`.
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 65], EnclaveError>;
+    /// The Ethereum address of the key, what the signatures are verified against.
+    fn address(&self) -> [u8; 20];
+}
+
+/// A source of random bytes.
+pub trait RandSource {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EnclaveError>;
+}
+
+/// The enclave's sealed signing key.
+pub struct EnclaveSigner;
+
+impl EpochSigner for EnclaveSigner {
+    fn check_ready(&self) -> Result<(), EnclaveError> { signing_key_ready() }
+
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 65], EnclaveError> { Ok(SIGNING_KEY.sign_recoverable(msg)?) }
+
+    fn address(&self) -> [u8; 20] { EpochSigner::address(*SIGNING_KEY) }
+}
+
+impl EpochSigner for KeyPair {
+    fn sign(&self, msg: &[u8]) -> Result<[u8; 65], EnclaveError> { Ok(KeyPair::sign_recoverable(self, msg)?) }
+
+    fn address(&self) -> [u8; 20] { self.get_pubkey().address() }
+}
+
+/// The SGX hardware random number generator.
+pub struct SgxRand;
+
+impl RandSource for SgxRand {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EnclaveError> {
+        rsgx_read_rand(buf)?;
+        Ok(())
+    }
+}
+
+/// Fills `buf` from `rand`, reading again up to `RAND_ATTEMPTS` times if it fails.
+pub fn fill_with_retry(rand: &mut dyn RandSource, buf: &mut [u8]) -> Result<(), EnclaveError> {
+    for attempt in 1..=RAND_ATTEMPTS {
+        match rand.fill(buf) {
+            Ok(()) => return Ok(()),
+            Err(err) => debug_println!("Failed reading random bytes ({}/{}): {}", attempt, RAND_ATTEMPTS, err),
+        }
+        if attempt < RAND_ATTEMPTS {
+            for _ in 0..RAND_RETRY_SPINS {
+                spin_loop_hint();
+            }
+        }
+    }
+    Err(SystemError(RandUnavailable { attempts: RAND_ATTEMPTS }))
+}
+
+/// Hands out the given bytes in order, and fails once they run out.
+pub struct ScriptedRand {
+    bytes: Vec<u8>,
+    failures: usize,
+}
+
+impl ScriptedRand {
+    pub fn new(bytes: Vec<u8>) -> Self { ScriptedRand { bytes, failures: 0 } }
+
+    /// Fails the first `failures` reads before handing out `bytes`.
+    pub fn failing(failures: usize, bytes: Vec<u8>) -> Self { ScriptedRand { bytes, failures } }
+}
+
+impl RandSource for ScriptedRand {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EnclaveError> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(sgx_status_t::SGX_ERROR_UNEXPECTED.into());
+        }
+        if self.bytes.len() < buf.len() {
+            return Err(sgx_status_t::SGX_ERROR_UNEXPECTED.into());
+        }
+        let rest = self.bytes.split_off(buf.len());
+        buf.copy_from_slice(&self.bytes);
+        self.bytes = rest;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! A seed drawn for the next epoch ahead of its transition, so the transition doesn't wait on the randomness.
+//! The staged seed isn't an epoch: it's kept out of `EPOCH` and sealed in a document of its own, next to the markers.
+//! The next new epoch takes it only if it has the nonce the seed was staged for and is signed with the key it was
+//! staged under, any other staged seed is dropped and the epoch draws its seed like it would without one.
+
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_tools_t::{
+    common::errors_t::EnclaveError,
+    document_storage_t::{is_document, load_sealed_document, save_sealed_document, SEAL_LOG_SIZE, SealedDocumentStorage},
+};
+use ethereum_types::{H256, U256, BigEndianHash};
+use std::{path, sync::SgxMutex, untrusted::fs::remove_file};
+
+use crate::epoch_keeper_t::{epoch_t::EpochNonce, get_epoch_root_path, next_nonce, signer::{fill_with_retry, EpochSigner, RandSource}, EPOCH};
+
+const STAGED_SEED_FILE: &str = "epoch-staged-seed.sealed";
+
+#[derive(Clone, Copy)]
+struct StagedSeed {
+    nonce: EpochNonce,
+    seed: [u8; 32],
+    // The address of the signing key when the seed was staged, the seed is dropped if the key changed since.
+    signer: [u8; 20],
+}
+
+lazy_static! {
+    // The staged seed, loaded from its document after a restart. Always locked after `EPOCH`.
+    static ref STAGED: SgxMutex<Option<StagedSeed>> = SgxMutex::new(None);
+}
+
+fn get_staged_seed_path() -> path::PathBuf { get_epoch_root_path().join(STAGED_SEED_FILE) }
+
+fn store_staged_seed(staged: StagedSeed) -> Result<(), EnclaveError> {
+    let staged_doc = SealedDocumentStorage { version: 0x1234, data: staged };
+    let mut sealed_log_in = [0u8; SEAL_LOG_SIZE];
+    staged_doc.seal(&mut sealed_log_in)?;
+    save_sealed_document(&get_staged_seed_path(), &sealed_log_in)?;
+    Ok(())
+}
+
+// The sealed staged seed, a document that doesn't unseal is as good as none.
+fn load_staged_seed() -> Option<StagedSeed> {
+    let path = get_staged_seed_path();
+    if !is_document(&path) {
+        return None;
+    }
+    let mut sealed_log_out = [0u8; SEAL_LOG_SIZE];
+    let unsealed = load_sealed_document(&path, &mut sealed_log_out)
+        .and_then(|_| SealedDocumentStorage::<StagedSeed>::unseal(&mut sealed_log_out));
+    match unsealed {
+        Ok(Some(doc)) => Some(doc.data),
+        other => {
+            debug_println!("Ignoring the staged seed document {:?}: {:?}", path, other.err());
+            None
+        }
+    }
+}
+
+// Empties the slot and removes its document.
+fn discard(slot: &mut Option<StagedSeed>) {
+    *slot = None;
+    let path = get_staged_seed_path();
+    if is_document(&path) {
+        if let Err(e) = remove_file(&path) {
+            debug_println!("Failed removing the staged seed document {:?}: {:?}", path, e);
+        }
+    }
+}
+
+/// Stages a seed for the epoch that `ecall_set_worker_params_internal` would create next and returns its nonce.
+/// A seed already staged for that nonce under the same key is kept, staging twice doesn't draw again.
+pub(crate) fn ecall_stage_epoch_seed_internal(signer: &dyn EpochSigner, rand: &mut dyn RandSource,
+                                              nonce_out: &mut [u8; 32]) -> Result<(), EnclaveError> {
+    signer.check_ready()?;
+    let guard = EPOCH.lock_expect("Epoch");
+    let nonce = H256::from_uint(&next_nonce(&guard)).0;
+    let mut slot = STAGED.lock_expect("Staged seed");
+    if slot.is_none() {
+        *slot = load_staged_seed();
+    }
+    match *slot {
+        Some(staged) if staged.nonce == nonce && staged.signer == signer.address() => (),
+        _ => {
+            let mut seed = [0u8; 32];
+            fill_with_retry(rand, &mut seed)?;
+            let staged = StagedSeed { nonce, seed, signer: signer.address() };
+            store_staged_seed(staged)?;
+            *slot = Some(staged);
+            debug_println!("Staged the seed of epoch {:?}", U256::from(&nonce));
+        }
+    }
+    *nonce_out = nonce;
+    Ok(())
+}
+
+/// Takes the staged seed for the new epoch of `nonce` signed by `signer`, while `EPOCH` is locked.
+/// The slot is emptied either way, a seed staged for another nonce or under another key can't be used anymore.
+pub(super) fn take(nonce: U256, signer: &dyn EpochSigner) -> Option<[u8; 32]> {
+    let mut slot = STAGED.lock_expect("Staged seed");
+    let staged = slot.take().or_else(load_staged_seed);
+    discard(&mut slot);
+    match staged {
+        Some(staged) if staged.nonce == H256::from_uint(&nonce).0 && staged.signer == signer.address() => Some(staged.seed),
+        Some(staged) => {
+            debug_println!("Dropped the seed staged for epoch {:?}, it doesn't match the new epoch {:?} or its key",
+                           U256::from(&staged.nonce), nonce);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Whether a seed is staged, for the tests.
+pub(super) fn is_staged() -> bool { STAGED.lock_expect("Staged seed").is_some() || is_document(&get_staged_seed_path()) }
@@ -21,7 +21,7 @@ use ocalls_t;
 use ethereum_types::U256;
 use rustc_hex::ToHex;
 
-use crate::SIGNING_KEY;
+use epoch_keeper_t::signer::EpochSigner;
 use sgx_types::uint8_t;
 
 const STATE_KEYS_DIR: &str = "state-keys";
@@ -133,7 +133,7 @@ fn build_get_state_keys_response(sc_addrs: Vec<ContractAddress>) -> Result<Vec<(
 
 /// Get encrypted state keys
 pub(crate) fn ecall_get_enc_state_keys_internal(
-    msg_bytes: &[u8], sc_addrs: Vec<ContractAddress>, sig: [u8; 65], epoch_nonce: [u8; 32],
+    signer: &dyn EpochSigner, msg_bytes: &[u8], sc_addrs: Vec<ContractAddress>, sig: [u8; 65], epoch_nonce: [u8; 32],
     sig_out: &mut [u8; 65]) -> Result<Vec<u8>, EnclaveError> {
     let msg = PrincipalMessage::from_message(msg_bytes)?;
     let user_pubkey = msg.get_pubkey();
@@ -165,7 +165,7 @@ pub(crate) fn ecall_get_enc_state_keys_internal(
     let response = response_msg.encrypt(&derived_key)?.into_message()?;
     // Signing the encrypted response
     // This is important because the response might be delivered by an intermediary
-    *sig_out = SIGNING_KEY.sign(&response)?;
+    *sig_out = signer.sign(&response)?;
     debug_println!("Get state key response requested for secret contract {:?}: {:?}", recovered_addr.to_hex::<String>(), response.to_hex::<String>());
     Ok(response)
 }
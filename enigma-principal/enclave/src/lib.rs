@@ -28,15 +28,23 @@ use sgx_types::{sgx_report_t, sgx_status_t, sgx_target_info_t, uint8_t};
 use std::{mem, slice};
 
 use enigma_crypto::asymmetric;
-use enigma_tools_t::{esgx::ocalls_t, quote_t, storage_t};
-use enigma_types::{ContractAddress, EnclaveReturn};
+use enigma_tools_t::{common::errors_t::{EnclaveError, EnclaveSystemError}, esgx::ocalls_t, quote_t, storage_t};
+use enigma_types::{ContractAddress, EnclaveReturn, Hash256};
+use enigma_tools_m::eth_proof::Checkpoint;
+use ethereum_types::{H256, U256};
 
-use crate::{epoch_keeper_t::ecall_set_worker_params_internal, keys_keeper_t::ecall_get_enc_state_keys_internal};
+use crate::{epoch_keeper_t::{chain_anchor::{ecall_advance_chain_internal, ecall_anchor_chain_internal}, ecall_dump_epoch_internal, ecall_get_selection_proof_internal, ecall_reveal_epoch_seed_finish_internal, ecall_reveal_epoch_seed_internal,
+                             ecall_set_max_workers_internal,
+                             ecall_set_worker_params_finish_internal, ecall_set_worker_params_internal, params_upload,
+                             signer::{EnclaveSigner, SgxRand}, staged_seed::ecall_stage_epoch_seed_internal},
+            keys_keeper_t::ecall_get_enc_state_keys_internal};
 
 mod epoch_keeper_t;
 mod keys_keeper_t;
 lazy_static! {
-    static ref SIGNING_KEY: asymmetric::KeyPair = get_sealed_keys_wrapper();
+    static ref LOADED_SIGNING_KEY: Result<asymmetric::KeyPair, EnclaveError> = load_signing_key();
+    static ref SIGNING_KEY: &'static asymmetric::KeyPair =
+        LOADED_SIGNING_KEY.as_ref().unwrap_or_else(|err| panic!("Failed obtaining keys: {:?}", err));
 }
 
 lazy_static! {
@@ -57,21 +65,27 @@ pub extern "C" fn ecall_get_ethereum_address(pubkey: &mut [u8; 20]) { pubkey.cop
 
 #[no_mangle]
 pub unsafe extern "C" fn ecall_sign_ethereum(data: &[u8; 32], sig: &mut [u8; 65]) {
-        sig.copy_from_slice(&ETHEREUM_KEY.sign_hashed(data).unwrap())
+        sig.copy_from_slice(&ETHEREUM_KEY.sign_hashed_recoverable(data).unwrap())
 }
 
-fn get_sealed_keys_wrapper() -> asymmetric::KeyPair {
+fn load_signing_key() -> Result<asymmetric::KeyPair, EnclaveError> {
     // Get Home path via Ocall
-    let mut path_buf = ocalls_t::get_home_path().unwrap();
+    let mut path_buf = ocalls_t::get_home_path()?;
     // add the filename to the path: `km_keypair.sealed`,
     // in order to distinguish from core's enclave in a local build
     path_buf.push("km_keypair.sealed");
     let sealed_path = path_buf.to_str().unwrap();
+    storage_t::get_sealed_keys(&sealed_path)
+}
 
-    // TODO: Decide what to do if failed to obtain keys.
-    match storage_t::get_sealed_keys(&sealed_path) {
-        Ok(key) => key,
-        Err(err) => panic!("Failed obtaining keys: {:?}", err),
+/// Loads the signing key if it wasn't yet, and fails instead of panicking if it can't be.
+pub(crate) fn signing_key_ready() -> Result<(), EnclaveError> {
+    match *LOADED_SIGNING_KEY {
+        Ok(_) => Ok(()),
+        Err(ref err) => {
+            debug_println!("Failed obtaining the signing key: {:?}", err);
+            Err(EnclaveError::SystemError(EnclaveSystemError::SigningKeyUninitialized))
+        }
     }
 }
 
@@ -90,20 +104,140 @@ fn get_ethereum_keys_wrapper() -> asymmetric::KeyPair {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn ecall_set_max_workers(max_workers: u32) { ecall_set_max_workers_internal(max_workers) }
+
 #[no_mangle]
 pub unsafe extern "C" fn ecall_set_worker_params(worker_params_rlp: *const u8, worker_params_rlp_len: usize,
-                                                 seed_in: &[u8; 32], nonce_in: &[u8; 32],
+                                                 seed_in: &[u8; 32], nonce_in: &[u8; 32], raw_seed: u8,
                                                  rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32],
                                                  sig_out: &mut [u8; 65]) -> EnclaveReturn {
     // Assembling byte arrays with the RLP data
     let worker_params_rlp = slice::from_raw_parts(worker_params_rlp, worker_params_rlp_len);
 
-    match ecall_set_worker_params_internal(worker_params_rlp, seed_in, nonce_in, rand_out, nonce_out, sig_out) {
+    match ecall_set_worker_params_internal(&EnclaveSigner, &mut SgxRand, worker_params_rlp, seed_in, nonce_in, raw_seed != 0, rand_out, nonce_out, sig_out) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_stage_epoch_seed(nonce_out: &mut [u8; 32]) -> EnclaveReturn {
+    match ecall_stage_epoch_seed_internal(&EnclaveSigner, &mut SgxRand, nonce_out) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_set_worker_params_begin(total_len: usize, handle_out: &mut u64) -> EnclaveReturn {
+    match params_upload::begin(total_len) {
+        Ok(handle) => {
+            *handle_out = handle;
+            EnclaveReturn::Success
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ecall_set_worker_params_chunk(handle: u64, chunk: *const u8, chunk_len: usize) -> EnclaveReturn {
+    let chunk = slice::from_raw_parts(chunk, chunk_len);
+    match params_upload::chunk(handle, chunk) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_set_worker_params_finish(handle: u64, worker_params_hash: &[u8; 32],
+                                                 seed_in: &[u8; 32], nonce_in: &[u8; 32], raw_seed: u8,
+                                                 rand_out: &mut [u8; 32], nonce_out: &mut [u8; 32],
+                                                 sig_out: &mut [u8; 65]) -> EnclaveReturn {
+    let worker_params_hash = Hash256::from(*worker_params_hash);
+    match ecall_set_worker_params_finish_internal(&EnclaveSigner, &mut SgxRand, handle, &worker_params_hash, seed_in, nonce_in,
+                                                  raw_seed != 0, rand_out, nonce_out, sig_out) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ecall_reveal_epoch_seed(nonce: &[u8; 32], worker_params_rlp: *const u8, worker_params_rlp_len: usize,
+                                                 receipt_proof_rlp: *const u8, receipt_proof_rlp_len: usize,
+                                                 seed_out: &mut [u8; 32]) -> EnclaveReturn {
+    let worker_params_rlp = slice::from_raw_parts(worker_params_rlp, worker_params_rlp_len);
+    let receipt_proof_rlp = slice::from_raw_parts(receipt_proof_rlp, receipt_proof_rlp_len);
+    match ecall_reveal_epoch_seed_internal(U256::from(nonce), worker_params_rlp, receipt_proof_rlp, seed_out) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_reveal_epoch_seed_finish(nonce: &[u8; 32], params_handle: u64, worker_params_hash: &[u8; 32],
+                                                 proof_handle: u64, receipt_proof_hash: &[u8; 32],
+                                                 seed_out: &mut [u8; 32]) -> EnclaveReturn {
+    let (worker_params_hash, receipt_proof_hash) = (Hash256::from(*worker_params_hash), Hash256::from(*receipt_proof_hash));
+    match ecall_reveal_epoch_seed_finish_internal(U256::from(nonce), params_handle, &worker_params_hash, proof_handle,
+                                                  &receipt_proof_hash, seed_out) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_anchor_chain(contract: &[u8; 20], block_hash: &[u8; 32], block_number: u64, confirmations: u64,
+                                     number_out: &mut u64, hash_out: &mut [u8; 32]) -> EnclaveReturn {
+    let checkpoint = Checkpoint { number: block_number, hash: H256(*block_hash) };
+    match ecall_anchor_chain_internal(*contract, checkpoint, confirmations) {
+        Ok(anchored) => {
+            *number_out = anchored.number;
+            *hash_out = anchored.hash.0;
+            EnclaveReturn::Success
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ecall_advance_chain(headers_rlp: *const u8, headers_rlp_len: usize,
+                                             number_out: &mut u64, hash_out: &mut [u8; 32]) -> EnclaveReturn {
+    let headers_rlp = slice::from_raw_parts(headers_rlp, headers_rlp_len);
+    match ecall_advance_chain_internal(headers_rlp) {
+        Ok(checkpoint) => {
+            *number_out = checkpoint.number;
+            *hash_out = checkpoint.hash.0;
+            EnclaveReturn::Success
+        }
+        Err(err) => err.into(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ecall_get_selection_proof(sc_addr: &[u8; 32], nonce: &[u8; 32], worker_out: &mut [u8; 20],
+                                            seed_out: &mut [u8; 32], sig_out: &mut [u8; 65]) -> EnclaveReturn {
+    match ecall_get_selection_proof_internal(&EnclaveSigner, ContractAddress::from(*sc_addr), U256::from(nonce), worker_out, seed_out, sig_out) {
         Ok(_) => EnclaveReturn::Success,
         Err(err) => err.into(),
     }
 }
 
+#[no_mangle]
+pub extern "C" fn ecall_dump_epoch(nonce: &[u8; 32], sequence_out: &mut u64, host_created_at_out: &mut u64,
+                                   boot_id_out: &mut [u8; 16], generation_attempt_out: &mut u32) -> EnclaveReturn {
+    match ecall_dump_epoch_internal(U256::from(nonce)) {
+        Ok(origin) => {
+            *sequence_out = origin.sequence;
+            *host_created_at_out = origin.host_created_at;
+            *boot_id_out = origin.enclave_boot_id;
+            *generation_attempt_out = origin.generation_attempt;
+            EnclaveReturn::Success
+        }
+        Err(err) => err.into(),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ecall_get_enc_state_keys(msg: *const u8, msg_len: usize,
                                                   addrs: *const u8, addrs_len: usize, sig: &[u8; 65],
@@ -111,7 +245,7 @@ pub unsafe extern "C" fn ecall_get_enc_state_keys(msg: *const u8, msg_len: usize
                                                   sig_out: &mut [u8; 65]) -> EnclaveReturn {
     let msg_bytes = slice::from_raw_parts(msg, msg_len);
     let addrs_bytes = slice::from_raw_parts(addrs as *const ContractAddress, addrs_len / mem::size_of::<ContractAddress>()).to_vec();
-    let response = match ecall_get_enc_state_keys_internal(msg_bytes, addrs_bytes, *sig, *epoch_nonce, sig_out) {
+    let response = match ecall_get_enc_state_keys_internal(&EnclaveSigner, msg_bytes, addrs_bytes, *sig, *epoch_nonce, sig_out) {
         Ok(response) => response,
         Err(err) => {
             debug_println!("get_enc_state_keys error: {:?}", err);
@@ -132,7 +266,8 @@ pub mod tests {
 
     use enigma_tools_t::{document_storage_t::tests::*, storage_t::tests::*};
 
-    use crate::{epoch_keeper_t::tests::*, keys_keeper_t::tests::*, epoch_keeper_t::nested_encoding::tests::*};
+    use crate::{epoch_keeper_t::tests::*, keys_keeper_t::tests::*, epoch_keeper_t::nested_encoding::tests::*,
+                epoch_keeper_t::params_upload::tests::*};
 
     #[no_mangle]
     pub extern "C" fn ecall_run_tests() {
@@ -140,6 +275,23 @@ pub mod tests {
             test_full_sealing_storage,
             test_document_sealing_storage,
             test_get_epoch_worker_internal,
+            test_get_epoch_worker_no_workers,
+            test_max_worker_params,
+            test_set_worker_params_over_max,
+            test_set_worker_params_malformed_rlp,
+            test_epoch_nonce_sequencing,
+            test_epoch_seed_domain_separation,
+            test_epoch_rand_retry,
+            test_epoch_signing_key_uninitialized,
+            test_staged_seed_consumed,
+            test_staged_seed_key_rotation,
+            test_staged_seed_fallback,
+            test_selection_proof,
+            test_params_upload_integrity,
+            test_chunked_worker_params_selection,
+            test_epoch_seed_commit_reveal,
+            test_epoch_cache_insert,
+            test_epoch_origin,
             test_state_keys_storage,
             test_create_epoch_image,
             test_u256_nested,
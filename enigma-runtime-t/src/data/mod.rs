@@ -2,7 +2,7 @@ mod delta;
 mod state;
 
 pub use data::delta::{EncryptedPatch, StatePatch};
-pub use data::state::{ContractState, EncryptedContractState};
+pub use data::state::{ContractState, EncryptedContractState, ACCESS_LIST_KEY};
 use serde::Deserialize;
 use serde_json::{Error, Value};
 
@@ -84,6 +84,19 @@ pub mod tests {
         assert_eq!(con, cmp);
     }
 
+    pub fn test_access_list() {
+        let mut con = ContractState::new(b"Enigma".sha256());
+        let (allowed, other) = ([1u8; 64], [2u8; 64]);
+        assert!(con.access_list().is_none());
+        assert!(con.is_allowed(&other));
+
+        con.allow_caller(&allowed);
+        con.allow_caller(&allowed);
+        assert_eq!(con.access_list().unwrap(), vec![allowed.to_vec()]);
+        assert!(con.is_allowed(&allowed));
+        assert!(!con.is_allowed(&other));
+    }
+
     pub fn test_read_state() {
         let con = ContractState {
             contract_address: b"Enigma".sha256(),
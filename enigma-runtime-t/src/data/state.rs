@@ -1,6 +1,6 @@
 use crate::data::{DeltasInterface, IOInterface, StatePatch};
 use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*};
-use enigma_types::{ContractAddress, StateKey};
+use enigma_types::{ContractAddress, PubKey, StateKey};
 use enigma_crypto::{symmetric, Encryption};
 use enigma_types::Hash256;
 use json_patch;
@@ -20,6 +20,9 @@ pub struct ContractState {
     pub delta_index: u32,
 }
 
+/// The key the access list is kept under in the state, contracts can only change it with `allow_caller`.
+pub const ACCESS_LIST_KEY: &str = "__enigma_access_list";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct EncryptedContractState<T> {
     pub contract_address: ContractAddress,
@@ -35,6 +38,25 @@ impl ContractState {
     pub fn is_initial(&self) -> bool{
         self.delta_index == 0 && self.delta_hash.is_zero()
     }
+
+    /// The pubkeys allowed to call the contract, `None` if anyone can.
+    pub fn access_list(&self) -> Option<Vec<Vec<u8>>> {
+        from_value(self.json[ACCESS_LIST_KEY].clone()).ok()
+    }
+
+    /// Any user can call a contract without an access list.
+    pub fn is_allowed(&self, user_key: &PubKey) -> bool {
+        self.access_list().map_or(true, |list| list.iter().any(|allowed| allowed[..] == user_key[..]))
+    }
+
+    /// Adds `user_key` to the access list, creating the list if the contract didn't have one.
+    pub fn allow_caller(&mut self, user_key: &PubKey) {
+        let mut list = self.access_list().unwrap_or_default();
+        if list.iter().all(|allowed| allowed[..] != user_key[..]) {
+            list.push(user_key.to_vec());
+        }
+        self.json[ACCESS_LIST_KEY] = json!(list);
+    }
 }
 
 impl IOInterface<EnclaveError, u8> for ContractState {
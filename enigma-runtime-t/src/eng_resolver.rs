@@ -22,6 +22,7 @@ pub mod ids {
     pub const RAND_FUNC: usize = 15;
     pub const ENCRYPT_FUNC: usize = 16;
     pub const DECRYPT_FUNC: usize = 17;
+    pub const ALLOW_CALLER_FUNC: usize = 18;
 }
 
 pub mod signatures {
@@ -60,6 +61,8 @@ pub mod signatures {
 
     pub const DECRYPT: StaticSignature = StaticSignature(&[I32, I32, I32, I32], None);
 
+    pub const ALLOW_CALLER: StaticSignature = StaticSignature(&[I32], None);
+
     impl Into<wasmi::Signature> for StaticSignature {
         fn into(self) -> wasmi::Signature { wasmi::Signature::new(self.0, self.1) }
     }
@@ -119,6 +122,7 @@ impl ModuleImportResolver for ImportResolver {
             "rand" => (signatures::RAND, ids::RAND_FUNC),
             "encrypt" => (signatures::ENCRYPT, ids::ENCRYPT_FUNC),
             "decrypt" => (signatures::DECRYPT, ids::DECRYPT_FUNC),
+            "allow_caller" => (signatures::ALLOW_CALLER, ids::ALLOW_CALLER_FUNC),
             _ => return Err(wasmi::Error::Instantiation(format!("Export {} not found", field_name))),
         };
 
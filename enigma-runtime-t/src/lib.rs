@@ -11,6 +11,7 @@ extern crate sgx_trts;
 extern crate serde_json;
 #[macro_use]
 extern crate enigma_tools_t;
+extern crate enigma_tools_m;
 extern crate enigma_crypto;
 extern crate enigma_types;
 extern crate json_patch;
@@ -23,9 +24,11 @@ extern crate parity_wasm;
 /// The code is based on Parity wasm_utils::cli.
 extern crate pwasm_utils;
 
-use crate::data::{ContractState, DeltasInterface, IOInterface, EncryptedPatch};
-use enigma_types::{StateKey, SymmetricKey, SYMMETRIC_KEY_SIZE};
-use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*, WasmError};
+use crate::data::{ContractState, DeltasInterface, IOInterface, EncryptedPatch, ACCESS_LIST_KEY};
+use enigma_types::{Hash256, PubKey, StateKey, SymmetricKey, SYMMETRIC_KEY_SIZE};
+use enigma_tools_m::trace::ExecutionTrace;
+use enigma_crypto::hash::Keccak256;
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*, FailedTaskError, WasmError};
 
 use std::{str, vec::Vec};
 use std::string::{String, ToString};
@@ -54,6 +57,8 @@ pub struct RuntimeResult {
     pub result: Vec<u8>,
     pub ethereum_bridge: Option<EthereumData>,
     pub used_gas: u64,
+    /// The host calls of the execution, only recorded after `Runtime::enable_trace`.
+    pub trace: Option<ExecutionTrace>,
 }
 
 #[derive(Debug, Clone)]
@@ -66,6 +71,9 @@ pub struct Runtime {
     post_execution_state: ContractState,
     key: StateKey,
     gas : RuntimeGas,
+    /// Set while running a constructor, the access list can only be set then.
+    pub deploying: bool,
+    trace: Option<ExecutionTrace>,
 }
 
 type Result<T> = ::std::result::Result<T, WasmError>;
@@ -82,6 +90,7 @@ impl Runtime {
             updated_state: Default::default(),
             ethereum_bridge: Default::default(),
             used_gas: 0,
+            trace: None,
         };
         let gas = RuntimeGas{
             counter: 0,
@@ -89,17 +98,37 @@ impl Runtime {
             refund: 0,
             costs,
         };
-        Runtime { memory, function_name, args, result, pre_execution_state, post_execution_state, key, gas }
+        Runtime { memory, function_name, args, result, pre_execution_state, post_execution_state, key, gas, deploying: false, trace: None }
     }
 
     pub fn get_used_gas(&self) -> u64 {
         self.gas.counter
     }
 
+    /// Records the host calls of the contract from now on, see `enigma_tools_m::trace`.
+    pub fn enable_trace(&mut self) { self.trace = Some(ExecutionTrace::default()); }
+
+    /// Takes the trace recorded so far, for an execution that failed and won't reach `into_result`.
+    pub fn take_trace(&mut self) -> Option<ExecutionTrace> {
+        let used_gas = self.get_used_gas();
+        self.trace.take().map(|trace| ExecutionTrace { used_gas, ..trace })
+    }
+
+    // Only the sizes and the hash of the key are kept, the values can be decrypted arguments or state.
+    fn trace_call(&mut self, name: &str, sizes: &[u64], key: Option<&str>) {
+        let gas = self.gas.counter;
+        if let Some(trace) = self.trace.as_mut() {
+            let key_hash: Option<Hash256> = key.map(|key| key.as_bytes().keccak256());
+            trace.record(name, sizes, key_hash, gas);
+        }
+    }
+
     fn fetch_args_length(&mut self) -> RuntimeValue { RuntimeValue::I32(self.args.len() as i32) }
 
     fn fetch_args(&mut self, args: RuntimeArgs) -> Result<()> {
         let ptr: u32 = args.nth_checked(0)?;
+        let args_len = self.args.len() as u64;
+        self.trace_call("fetch_args", &[args_len], None);
 
         self.memory.set(ptr, &self.args)?;
         Ok(())
@@ -109,6 +138,8 @@ impl Runtime {
 
     fn fetch_function_name(&mut self, args: RuntimeArgs) -> Result<()> {
         let ptr: u32 = args.nth_checked(0)?;
+        let name_len = self.function_name.len() as u64;
+        self.trace_call("fetch_function_name", &[name_len], None);
 
         self.memory.set(ptr, &self.function_name.as_bytes())?;
         Ok(())
@@ -125,6 +156,15 @@ impl Runtime {
         Ok(key_str.to_string())
     }
 
+    /// The access list is part of the state, but a contract can't change it like the rest of its state.
+    fn check_writable(key: &str) -> Result<()> {
+        if key == ACCESS_LIST_KEY {
+            let err = FailedTaskError::WasmCodeExecutionError { err: format!("{} can only be changed with allow_caller", key) };
+            return Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(err)));
+        }
+        Ok(())
+    }
+
     pub fn read_state_len(&self, args: RuntimeArgs) -> Result<i32> {
         // TODO: Handle the error here, should we return len=0?;
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
@@ -147,7 +187,8 @@ impl Runtime {
         let value_holder: u32 = args.nth_checked(2)?;
 
         let value_vec =
-            serde_json::to_vec(&self.post_execution_state.json[key]).expect("Failed converting Value to vec in Runtime while reading state");
+            serde_json::to_vec(&self.post_execution_state.json[&key]).expect("Failed converting Value to vec in Runtime while reading state");
+        self.trace_call("read_state", &[key.len() as u64, value_vec.len() as u64], Some(&key));
         self.memory.set(value_holder, &value_vec)?;
         Ok(())
     }
@@ -159,6 +200,8 @@ impl Runtime {
     /// Read `key` from the memory, then remove the `key` from the state
     pub fn remove_from_state(&mut self, args: RuntimeArgs) -> Result<()> {
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
+        Self::check_writable(&key)?;
+        self.trace_call("remove_from_state", &[key.len() as u64], Some(&key));
 
         self.post_execution_state.remove_key(&key);
         Ok(())
@@ -174,8 +217,10 @@ impl Runtime {
     /// the cost of writing into the state is calculated by `calculate_gas_for_writing`
     pub fn write_state(&mut self, args: RuntimeArgs) -> Result<()> {
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
+        Self::check_writable(&key)?;
         let value: u32 = args.nth_checked(2)?;
         let value_len: u32 = args.nth_checked(3)?;
+        self.trace_call("write_state", &[key.len() as u64, value_len as u64], Some(&key));
 
         let mut val = vec![0u8; value_len as usize];
         let gas_amount = self.calculate_gas_for_writing(value_len as u64, &key)?;
@@ -255,6 +300,7 @@ impl Runtime {
         let payload = args.nth_checked(0)?;
         let payload_len: u32 = args.nth_checked(1)?;
         let address = args.nth_checked(2)?;
+        self.trace_call("write_eth_bridge", &[payload_len as u64], None);
 
         let mut bridge = EthereumData { ethereum_payload: vec![0u8; payload_len as usize], ethereum_contract_addr: Default::default() };
 
@@ -272,14 +318,37 @@ impl Runtime {
     pub fn ret(&mut self, args: RuntimeArgs) -> Result<()> {
         let ptr: u32 = args.nth_checked(0)?;
         let len: u32 = args.nth_checked(1)?;
+        self.trace_call("ret", &[len as u64], None);
 
         self.result.result = self.memory.get(ptr, len as usize)?;
         Ok(())
     }
 
+    /// args:
+    /// * `pubkey` - the start address of a 64 bytes user pubkey in memory
+    ///
+    /// Adds the pubkey to the contract's access list, once a contract has one only the users in it can call it.
+    /// Only the constructor can call this, it's charged like writing a new 64 bytes value to the state.
+    pub fn allow_caller(&mut self, args: RuntimeArgs) -> Result<()> {
+        if !self.deploying {
+            let err = FailedTaskError::WasmCodeExecutionError { err: "allow_caller can only be called by the constructor".to_string() };
+            return Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(err)));
+        }
+        let ptr: u32 = args.nth_checked(0)?;
+        self.trace_call("allow_caller", &[64], None);
+        let mut user_key: PubKey = [0u8; 64];
+        self.memory.get_into(ptr, &mut user_key)?;
+
+        let gas_amount = self.gas.costs.write_value + user_key.len() as u64 * self.gas.costs.write_additional_byte;
+        self.charge_gas(gas_amount)?;
+        self.post_execution_state.allow_caller(&user_key);
+        Ok(())
+    }
+
     pub fn rand(&mut self, args: RuntimeArgs) -> Result<()> {
         let ptr: u32 = args.nth_checked(0)?;
         let len: u32 = args.nth_checked(1)?;
+        self.trace_call("rand", &[len as u64], None);
 
         let mut buf = vec![0u8; len as usize];
         match rsgx_read_rand(&mut buf[..]) {
@@ -308,12 +377,16 @@ impl Runtime {
             }
         };
         self.result.updated_state = self.post_execution_state;
+        let delta_size = self.result.state_delta.as_ref().map(|delta| delta.data.len() as u64);
+        let used_gas = self.result.used_gas;
+        self.result.trace = self.trace.map(|trace| ExecutionTrace { used_gas, delta_size, ..trace });
         Ok(self.result)
     }
 
     pub fn eprint(&mut self, args: RuntimeArgs) -> Result<()> {
         let msg_ptr: u32 = args.nth_checked(0)?;
         let msg_len: u32 = args.nth_checked(1)?;
+        self.trace_call("eprint", &[msg_len as u64], None);
         let res = self.memory.get(msg_ptr, msg_len as usize)?;
         // This should not fail if printing is done properly through eng_wasm eprint!
         let st = str::from_utf8(&res).unwrap_or_default();
@@ -361,6 +434,7 @@ impl Runtime {
     pub fn encrypt(&mut self, args: RuntimeArgs) -> Result<()> {
         let message_ptr: u32 = args.nth_checked(0)?;
         let message_len: u32 = args.nth_checked(1)?;
+        self.trace_call("encrypt", &[message_len as u64], None);
         let message = self.memory.get(message_ptr, message_len as usize)?;
         debug_println!("In encrypt: {:?}", message);
 
@@ -378,6 +452,7 @@ impl Runtime {
     pub fn decrypt(&mut self, args: RuntimeArgs) -> Result<()> {
         let cipheriv_ptr: u32 = args.nth_checked(0)?;
         let cipheriv_len: u32 = args.nth_checked(1)?;
+        self.trace_call("decrypt", &[cipheriv_len as u64], None);
         let cipheriv = self.memory.get(cipheriv_ptr, cipheriv_len as usize)?;
 
         let key_ptr: u32 = args.nth_checked(2)?;
@@ -462,6 +537,11 @@ mod ext_impl {
                     Ok(None)
                 }
 
+                eng_resolver::ids::ALLOW_CALLER_FUNC => {
+                    Runtime::allow_caller(self, args)?;
+                    Ok(None)
+                }
+
                 _ => unimplemented!("Unimplemented function at {}", index),
             }
         }
@@ -32,7 +32,9 @@ impl WasmEngine {
 
     pub fn new_deploy(code: &[u8], gas_limit: u64, args: Vec<u8>, state: ContractState, function_name: String, key: StateKey) -> Result<WasmEngine, EnclaveError>{
         let deploy_bytecode = Self::build_constructor(code)?;
-        Self::new(&deploy_bytecode, gas_limit, args, state, function_name, key)
+        let mut engine = Self::new(&deploy_bytecode, gas_limit, args, state, function_name, key)?;
+        engine.runtime.deploying = true;
+        Ok(engine)
     }
 
     pub fn new_compute(code: &[u8], gas_limit: u64, args: Vec<u8>, state: ContractState, function_name: String,key: StateKey) -> Result<WasmEngine, EnclaveError>{
@@ -0,0 +1,18 @@
+// The checks of the `keeper_types` fuzz target, `tests/fuzz_corpus.rs` runs them on the corpus too.
+
+use enigma_tools_m::keeper_types::{rlpEncode, EpochParams, InputWorkerParams};
+
+/// The bytes are decoded as the RLP worker params the enclave gets from the untrusted side,
+/// and as the data of a `WorkersParameterized` log relayed from the chain.
+/// Whatever they are the decoders return an error instead of panicking,
+/// and what decodes is encoded back to the same value and can be used for the worker selection.
+pub fn check(data: &[u8]) {
+    if let Ok(params) = InputWorkerParams::from_rlp(data) {
+        assert_eq!(InputWorkerParams::from_rlp(&rlpEncode(&params)).unwrap(), params);
+        let _ = params.validate(usize::max_value());
+        let _ = params.get_selected_worker([1u8; 32].into(), params.km_block_number);
+    }
+    if let Ok(event) = EpochParams::decode(data) {
+        assert_eq!(EpochParams::decode(&event.encode()).unwrap(), event);
+    }
+}
@@ -0,0 +1,11 @@
+//! Feeds arbitrary bytes to the decoders of the worker params and of the `WorkersParameterized` event,
+//! see `checks/keeper_types.rs`.
+//! `cargo +nightly fuzz run keeper_types -- -malloc_limit_mb=256` from `enigma-tools-m/`, the inputs it finds worth
+//! keeping go to `corpus/keeper_types/` and are replayed by `tests/fuzz_corpus.rs`.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../checks/keeper_types.rs"]
+mod checks;
+
+fuzz_target!(|data: &[u8]| checks::check(data));
@@ -0,0 +1,180 @@
+//! # Key Derivation Audit Log.
+//! The events the core enclave records when it gets hold of a key, and the hash chain linking them. <br>
+//! An event never contains the key, only what it belongs to: the address of the contract of a state key,
+//! or the keccak256 of the public key of the user a DH key was derived with. <br>
+//! Every event is chained to the one before it, so an export that was edited (an event dropped, changed or reordered)
+//! doesn't end at the head the enclave signed with [`AuditDigest`].
+
+use crate::localstd::vec::Vec;
+use crate::serde::{Deserialize, Serialize};
+use crate::signable::Signable;
+use enigma_crypto::hash::{prepare_hash_multiple, Keccak256};
+use enigma_types::Hash256;
+
+const DIGEST_PREFIX: &[u8; 19] = b"Enigma Audit Digest";
+
+/// What kind of key an event is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub enum AuditEventKind {
+    /// A contract state key provisioned by the KM node, the subject is the contract address.
+    StateKey,
+    /// A DH key derived with a user, the subject is the keccak256 of the user's public key.
+    UserKey,
+}
+
+impl AuditEventKind {
+    fn code(self) -> u8 {
+        match self {
+            AuditEventKind::StateKey => 1,
+            AuditEventKind::UserKey => 2,
+        }
+    }
+}
+
+/// A single key derivation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct AuditEvent {
+    /// The position of the event in the log, starting at 0.
+    pub counter: u64,
+    /// The kind of key.
+    pub kind: AuditEventKind,
+    /// What the key belongs to, see [`AuditEventKind`].
+    pub subject: Hash256,
+    /// Seconds since the unix epoch as the host reported them, the enclave has no clock of its own.
+    /// Only `counter` orders the events in a way the host can't change.
+    pub timestamp: u64,
+    /// The head of the chain after this event.
+    pub chain: Hash256,
+}
+
+impl AuditEvent {
+    /// Creates the event following `prev` (the head of the chain before it) and links it.
+    pub fn new(prev: &Hash256, counter: u64, kind: AuditEventKind, subject: Hash256, timestamp: u64) -> Self {
+        let mut event = AuditEvent { counter, kind, subject, timestamp, chain: Hash256::default() };
+        event.chain = event.link(prev);
+        event
+    }
+
+    /// `keccak256(prepare_hash_multiple(prev, counter, kind, subject, timestamp))`, integers are big endian.
+    pub fn link(&self, prev: &Hash256) -> Hash256 {
+        let counter = self.counter.to_be_bytes();
+        let timestamp = self.timestamp.to_be_bytes();
+        prepare_hash_multiple(&[&prev[..], &counter[..], &[self.kind.code()][..], &self.subject[..], &timestamp[..]]).keccak256()
+    }
+}
+
+/// The last events of the log and where the chain was before them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct AuditExport {
+    /// The number of events that came before `events`, the enclave doesn't keep them anymore.
+    pub base_count: u64,
+    /// The head of the chain before the first of `events`, zeros if the log starts with them.
+    pub base: Hash256,
+    /// The events, oldest first.
+    pub events: Vec<AuditEvent>,
+}
+
+impl AuditExport {
+    /// The number of events and the head of the chain after the last one.
+    pub fn digest(&self) -> AuditDigest {
+        let head = self.events.last().map(|event| event.chain).unwrap_or(self.base);
+        AuditDigest { count: self.base_count + self.events.len() as u64, head }
+    }
+
+    /// Returns true if every event is linked to the one before it and their counters follow each other.
+    pub fn verify(&self) -> bool {
+        let mut prev = self.base;
+        for (i, event) in self.events.iter().enumerate() {
+            if event.counter != self.base_count + i as u64 || event.link(&prev) != event.chain {
+                return false;
+            }
+            prev = event.chain;
+        }
+        true
+    }
+
+    /// Returns true if the chain of this export goes through the head of `earlier`, an older digest of the same log.
+    /// It can't be told if the events up to `earlier` were already dropped by the enclave.
+    pub fn extends(&self, earlier: &AuditDigest) -> bool {
+        if earlier.count < self.base_count {
+            return false;
+        }
+        if earlier.count == self.base_count {
+            return earlier.head == self.base;
+        }
+        match self.events.get((earlier.count - self.base_count - 1) as usize) {
+            Some(event) => event.chain == earlier.head,
+            None => false,
+        }
+    }
+}
+
+/// The state of the log the enclave signs on every export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct AuditDigest {
+    /// The number of events recorded so far.
+    pub count: u64,
+    /// The head of the chain.
+    pub head: Hash256,
+}
+
+impl Signable for AuditDigest {
+    /// `prepare_hash_multiple("Enigma Audit Digest", u64_be(count), head)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        prepare_hash_multiple(&[&DIGEST_PREFIX[..], &self.count.to_be_bytes()[..], &self.head[..]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(events: usize) -> AuditExport {
+        let mut export = AuditExport { base_count: 0, base: Hash256::default(), events: Vec::new() };
+        for i in 0..events as u64 {
+            let prev = export.digest().head;
+            let kind = if i % 2 == 0 { AuditEventKind::StateKey } else { AuditEventKind::UserKey };
+            export.events.push(AuditEvent::new(&prev, i, kind, [i as u8; 32].into(), 1_560_000_000 + i));
+        }
+        export
+    }
+
+    #[test]
+    fn test_chain_verifies() {
+        let export = export(4);
+        assert!(export.verify());
+        assert_eq!(export.digest().count, 4);
+
+        let mut changed = export.clone();
+        changed.events[1].subject = [9u8; 32].into();
+        assert!(!changed.verify());
+
+        let mut dropped = export.clone();
+        dropped.events.remove(1);
+        assert!(!dropped.verify());
+    }
+
+    #[test]
+    fn test_extends() {
+        let full = export(5);
+        let first = AuditExport { events: full.events[..3].to_vec(), ..full.clone() };
+        assert!(full.extends(&first.digest()));
+        assert!(full.extends(&AuditExport { events: vec![], ..full.clone() }.digest()));
+
+        // The enclave dropped the first two events, the rest still goes through the older head.
+        let folded = AuditExport { base_count: 2, base: full.events[1].chain, events: full.events[2..].to_vec() };
+        assert!(folded.verify());
+        assert!(folded.extends(&first.digest()));
+
+        // A log that was rewritten from the fourth event on, even with valid links, doesn't go through the older head.
+        let earlier = export(4);
+        let mut rewritten = full.clone();
+        rewritten.events[3].subject = [7u8; 32].into();
+        rewritten.events[3].chain = rewritten.events[3].link(&rewritten.events[2].chain);
+        assert!(!rewritten.extends(&earlier.digest()));
+    }
+}
@@ -17,4 +17,25 @@ pub enum ToolsError {
         /// `Err` is the custom message that should explain what and where was the problem.
         err: &'static str
     },
+    /// The `NoWorkersInEpoch` error.
+    ///
+    /// This error means that the epoch has no registered workers, so there's no worker to select.
+    #[fail(display = "There are no workers in the epoch")]
+    NoWorkersInEpoch,
+    /// The `WorkerParamsError` error.
+    ///
+    /// This error means that the worker params are malformed (e.g. more workers than allowed, or a stake missing).
+    #[fail(display = "Invalid worker params: {}", err)]
+    WorkerParamsError {
+        /// `Err` is the custom message that should explain what was wrong with the params.
+        err: &'static str
+    },
+    /// The `ProofError` error.
+    ///
+    /// This error means that a proof of Ethereum data (e.g. a receipt and the headers above it) didn't verify.
+    #[fail(display = "Invalid proof: {}", err)]
+    ProofError {
+        /// `Err` is the custom message that should explain which part of the proof failed.
+        err: &'static str
+    },
 }
@@ -0,0 +1,173 @@
+//! # Output Envelopes.
+//! The output of a compute task is encrypted with the DH key the user derived with the worker, but nothing in it says
+//! who that user is, and whoever relays it can't tell it apart from any other blob. <br>
+//! An [`OutputEnvelope`] addresses the encrypted output to the public key the task was submitted with:
+//! the enclave derives a one-time key from a fresh ephemeral key pair and the recipient's public key, and seals the output
+//! with it (AES-256-GCM). Only the holder of the recipient's secret key can open it. <br>
+//! The hash of the envelope ([`OutputEnvelope::hash`]) is part of the signed `ExecuteReceipt`,
+//! so an envelope that was swapped or changed on its way doesn't match the receipt, even before its MAC is checked.
+
+use crate::localstd::vec::Vec;
+use enigma_crypto::{hash::{prepare_hash_multiple, Keccak256}, symmetric, CryptoError, KeyPair};
+use enigma_types::{DhKey, Hash256, PubKey, SymmetricKey};
+
+/// Tags the derivation of the envelope key, so it's never the plain DH key of the two public keys.
+const ENVELOPE_KEY_TAG: &[u8] = b"Enigma Output Envelope";
+/// The AES-GCM nonce size.
+pub const NONCE_SIZE: usize = 12;
+/// The AES-GCM tag size.
+pub const MAC_SIZE: usize = 16;
+/// The size of an envelope around an empty output.
+pub const HEADER_SIZE: usize = 64 + 64 + NONCE_SIZE + MAC_SIZE;
+
+/// An output sealed for the key of the task submitter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputEnvelope {
+    /// The public key the task was submitted with, the only one that can open the envelope.
+    pub recipient_pubkey: PubKey,
+    /// The public half of the one-time key pair the envelope was sealed with.
+    pub ephemeral_pubkey: PubKey,
+    /// The AES-GCM nonce.
+    pub nonce: [u8; NONCE_SIZE],
+    /// The sealed output.
+    pub ciphertext: Vec<u8>,
+    /// The AES-GCM tag over `ciphertext`.
+    pub mac: [u8; MAC_SIZE],
+}
+
+/// `keccak256(prepare_hash_multiple(ENVELOPE_KEY_TAG, dh, recipient, ephemeral))`, binding the key to both public keys.
+fn envelope_key(dh: &DhKey, recipient: &PubKey, ephemeral: &PubKey) -> SymmetricKey {
+    *prepare_hash_multiple(&[ENVELOPE_KEY_TAG, &dh[..], &recipient[..], &ephemeral[..]]).keccak256()
+}
+
+impl OutputEnvelope {
+    /// Seals `output` for `recipient` with a fresh ephemeral key pair.
+    pub fn seal(output: &[u8], recipient: &PubKey) -> Result<Self, CryptoError> {
+        Self::seal_with(output, recipient, &KeyPair::new()?)
+    }
+
+    /// Seals `output` for `recipient` with the given ephemeral key pair, which must never be used again.
+    pub fn seal_with(output: &[u8], recipient: &PubKey, ephemeral: &KeyPair) -> Result<Self, CryptoError> {
+        let ephemeral_pubkey = ephemeral.get_pubkey();
+        let key = envelope_key(&ephemeral.derive_key(recipient)?, recipient, &ephemeral_pubkey);
+        // `ciphertext || mac || nonce`
+        let mut sealed = symmetric::encrypt(output, &key)?;
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&sealed[sealed.len() - NONCE_SIZE..]);
+        sealed.truncate(sealed.len() - NONCE_SIZE);
+        let mut mac = [0u8; MAC_SIZE];
+        mac.copy_from_slice(&sealed[sealed.len() - MAC_SIZE..]);
+        sealed.truncate(sealed.len() - MAC_SIZE);
+        Ok(OutputEnvelope { recipient_pubkey: *recipient, ephemeral_pubkey, nonce, ciphertext: sealed, mac })
+    }
+
+    /// Opens the envelope with the secret key of its recipient, an envelope addressed to another key or that doesn't
+    /// pass the MAC check is an error.
+    pub fn open(&self, recipient: &KeyPair) -> Result<Vec<u8>, CryptoError> {
+        if recipient.get_pubkey()[..] != self.recipient_pubkey[..] {
+            return Err(CryptoError::KeyError { key_type: "Envelope Recipient", err: None });
+        }
+        let key = envelope_key(&recipient.derive_key(&self.ephemeral_pubkey)?, &self.recipient_pubkey, &self.ephemeral_pubkey);
+        let mut sealed = Vec::with_capacity(self.ciphertext.len() + MAC_SIZE + NONCE_SIZE);
+        sealed.extend_from_slice(&self.ciphertext);
+        sealed.extend_from_slice(&self.mac);
+        sealed.extend_from_slice(&self.nonce);
+        symmetric::decrypt(&sealed, &key)
+    }
+
+    /// The canonical encoding: `recipient_pubkey || ephemeral_pubkey || nonce || mac || ciphertext`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.ciphertext.len());
+        bytes.extend_from_slice(&self.recipient_pubkey);
+        bytes.extend_from_slice(&self.ephemeral_pubkey);
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.mac);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    /// Decodes [`OutputEnvelope::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(CryptoError::ImproperEncryption);
+        }
+        let mut envelope = OutputEnvelope {
+            recipient_pubkey: [0u8; 64],
+            ephemeral_pubkey: [0u8; 64],
+            nonce: [0u8; NONCE_SIZE],
+            ciphertext: bytes[HEADER_SIZE..].to_vec(),
+            mac: [0u8; MAC_SIZE],
+        };
+        envelope.recipient_pubkey.copy_from_slice(&bytes[..64]);
+        envelope.ephemeral_pubkey.copy_from_slice(&bytes[64..128]);
+        envelope.nonce.copy_from_slice(&bytes[128..128 + NONCE_SIZE]);
+        envelope.mac.copy_from_slice(&bytes[128 + NONCE_SIZE..HEADER_SIZE]);
+        Ok(envelope)
+    }
+
+    /// `keccak256(to_bytes())`, what the receipt signs.
+    pub fn hash(&self) -> Hash256 { self.to_bytes().keccak256() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPIENT: [u8; 32] = [205, 189, 133, 79, 16, 70, 59, 246, 123, 227, 66, 64, 244, 188, 188, 147, 233, 252, 213, 133, 44, 157, 173, 141, 50, 93, 40, 130, 44, 99, 43, 205];
+
+    fn recipient() -> KeyPair { KeyPair::from_slice(&RECIPIENT).unwrap() }
+
+    #[test]
+    fn test_round_trip() {
+        let keys = recipient();
+        let envelope = OutputEnvelope::seal(b"the encrypted output", &keys.get_pubkey()).unwrap();
+        assert_eq!(&envelope.recipient_pubkey[..], &keys.get_pubkey()[..]);
+        assert_eq!(envelope.open(&keys).unwrap(), b"the encrypted output".to_vec());
+
+        let decoded = OutputEnvelope::from_bytes(&envelope.to_bytes()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.hash(), envelope.hash());
+        assert_eq!(envelope.to_bytes().len(), HEADER_SIZE + envelope.ciphertext.len());
+
+        let empty = OutputEnvelope::seal(&[], &keys.get_pubkey()).unwrap();
+        assert!(empty.open(&keys).unwrap().is_empty());
+        assert!(OutputEnvelope::from_bytes(&empty.to_bytes()[..HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_ephemeral_keys_differ() {
+        let keys = recipient();
+        let first = OutputEnvelope::seal(b"output", &keys.get_pubkey()).unwrap();
+        let second = OutputEnvelope::seal(b"output", &keys.get_pubkey()).unwrap();
+        assert_ne!(&first.ephemeral_pubkey[..], &second.ephemeral_pubkey[..]);
+        assert_ne!(first.hash(), second.hash());
+    }
+
+    #[test]
+    fn test_wrong_recipient() {
+        let keys = recipient();
+        let other = KeyPair::new().unwrap();
+        let envelope = OutputEnvelope::seal(b"output", &keys.get_pubkey()).unwrap();
+        assert!(envelope.open(&other).is_err());
+        // Readdressed to the other key, the derived key doesn't match anymore.
+        let readdressed = OutputEnvelope { recipient_pubkey: other.get_pubkey(), ..envelope };
+        assert!(readdressed.open(&other).is_err());
+    }
+
+    #[test]
+    fn test_tampering_breaks_mac() {
+        let keys = recipient();
+        let envelope = OutputEnvelope::seal(b"the encrypted output", &keys.get_pubkey()).unwrap();
+        let bytes = envelope.to_bytes();
+        // Every byte after the recipient key: the ephemeral key, the nonce, the mac and the ciphertext.
+        for i in 64..bytes.len() {
+            let mut tampered = bytes.clone();
+            tampered[i] ^= 1;
+            let tampered = OutputEnvelope::from_bytes(&tampered).unwrap();
+            assert!(tampered.open(&keys).is_err(), "byte {}", i);
+            assert_ne!(tampered.hash(), envelope.hash());
+        }
+        let truncated = OutputEnvelope { ciphertext: envelope.ciphertext[1..].to_vec(), ..envelope.clone() };
+        assert!(truncated.open(&keys).is_err());
+    }
+}
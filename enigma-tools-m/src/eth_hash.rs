@@ -0,0 +1,64 @@
+//! # Ethereum hashing.
+//! Ethereum hashes with the original Keccak-256, not with the standardized SHA3-256 (they differ in their padding),
+//! so mixing the two up silently produces hashes nobody on-chain agrees with. <br>
+//! Every Ethereum structure we hash, block headers, receipts, transactions, trie nodes and log topics,
+//! on both sides of the SGX, goes through [`eth_hash`] instead of picking a hash function at the call site.
+
+use crate::ethereum_types::H256;
+use enigma_crypto::hash::Keccak256;
+
+/// Hash `data` the way Ethereum does (Keccak-256).
+/// `data` should already be in its Ethereum encoding, e.g. the RLP of a block header or a signed transaction.
+pub fn eth_hash(data: &[u8]) -> H256 { H256::from(*data.keccak256()) }
+
+/// The log topic of an event, the hash of its canonical signature. e.g. `Transfer(address,address,uint256)`
+pub fn event_topic(signature: &str) -> H256 { eth_hash(signature.as_bytes()) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::FromHex;
+
+    fn h256(hex: &str) -> H256 { H256::from_slice(&hex.from_hex::<Vec<u8>>().unwrap()) }
+
+    // The RLP of the mainnet genesis block header.
+    const GENESIS_HEADER: [&str; 11] = [
+        "f90214a00000000000000000000000000000000000000000000000000000000000000000a01dcc4de8dec75d7aab85b567b6",
+        "ccd41ad312451b948a7413f0a142fd40d49347940000000000000000000000000000000000000000a0d7f8974fb5ac78d9ac",
+        "099b9ad5018bedc2ce0a72dad1827a1709da30580f0544a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc00162",
+        "2fb5e363b421a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421b901000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000008504",
+        "00000000808213888080a011bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82faa0000000000000",
+        "0000000000000000000000000000000000000000000000000000880000000000000042",
+    ];
+
+    #[test]
+    fn test_keccak_not_sha3() {
+        // SHA3-256("") is a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a
+        assert_eq!(eth_hash(b""), h256("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"));
+    }
+
+    #[test]
+    fn test_mainnet_genesis_header_hash() {
+        let header: Vec<u8> = GENESIS_HEADER.concat().from_hex().unwrap();
+        assert_eq!(eth_hash(&header), h256("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"));
+    }
+
+    #[test]
+    fn test_mainnet_empty_roots() {
+        // The receipts (and transactions) root of a block without transactions is the root of the empty trie, `rlp("")`.
+        assert_eq!(eth_hash(&[0x80]), h256("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"));
+        // The uncles hash of a block without uncles, `rlp([])`.
+        assert_eq!(eth_hash(&[0xc0]), h256("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347"));
+    }
+
+    #[test]
+    fn test_event_topic() {
+        let topic = event_topic("Transfer(address,address,uint256)");
+        assert_eq!(topic, h256("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"));
+    }
+}
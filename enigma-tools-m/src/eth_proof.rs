@@ -0,0 +1,552 @@
+//! # Ethereum receipt proofs.
+//! An enclave has no view of the chain, so whatever it's told about a transaction comes from the untrusted side. <br>
+//! A [`ReceiptProof`] lets it check for itself that a receipt is in a block of the chain it knows: the receipt is
+//! proven against the receipts root of its block with the nodes of the receipts trie, and the block is linked by its
+//! parent hashes to a [`Checkpoint`] the enclave trusts, with the blocks mined on top of it. <br>
+//! The seal of the headers (the proof of work) isn't checked, the chain is only as good as its checkpoint and
+//! whoever hands the headers in still has to build them on top of it.
+
+use crate::common::errors::ToolsError::{self, ProofError};
+use crate::eth_hash::eth_hash;
+use crate::ethereum_types::{H160, H256};
+use crate::localstd::{vec, vec::Vec};
+use rlp::{self, Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+/// The position of the parent hash in the RLP list of a block header.
+const HEADER_PARENT_HASH: usize = 0;
+/// The position of the receipts root in the RLP list of a block header.
+const HEADER_RECEIPTS_ROOT: usize = 5;
+/// The position of the block number in the RLP list of a block header.
+const HEADER_NUMBER: usize = 8;
+/// The fields of a header before the London fork, the later forks only append to them.
+const HEADER_MIN_FIELDS: usize = 15;
+
+/// A block of the chain, known by its number and hash.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    /// The number of the block.
+    pub number: u64,
+    /// The hash of the block.
+    pub hash: H256,
+}
+
+/// What a proof needs from a block header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    /// The hash of the header, which is the hash of the block.
+    pub hash: H256,
+    /// The hash of the parent block.
+    pub parent_hash: H256,
+    /// The root of the receipts trie of the block.
+    pub receipts_root: H256,
+    /// The number of the block.
+    pub number: u64,
+}
+
+impl BlockHeader {
+    /// Decodes the RLP of a block header, as it's hashed into the hash of the block.
+    pub fn from_rlp(bytes: &[u8]) -> Result<Self, ToolsError> {
+        const MALFORMED: ToolsError = ProofError { err: "malformed block header" };
+        let rlp = UntrustedRlp::new(bytes);
+        if !rlp.is_list() || rlp.item_count().map_err(|_| MALFORMED)? < HEADER_MIN_FIELDS {
+            return Err(MALFORMED);
+        }
+        let hash_at = |index: usize| rlp.at(index).and_then(|item| item.data()).map_err(|_| MALFORMED).and_then(h256);
+        Ok(BlockHeader {
+            hash: eth_hash(bytes),
+            parent_hash: hash_at(HEADER_PARENT_HASH)?,
+            receipts_root: hash_at(HEADER_RECEIPTS_ROOT)?,
+            number: rlp.val_at(HEADER_NUMBER).map_err(|_| MALFORMED)?,
+        })
+    }
+
+    /// The header as a checkpoint, for the proofs of the blocks that follow it.
+    pub fn checkpoint(&self) -> Checkpoint { Checkpoint { number: self.number, hash: self.hash } }
+}
+
+/// A log of a receipt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    /// The contract that emitted the log.
+    pub address: H160,
+    /// The topic of the event (unless it's anonymous) followed by its indexed params.
+    pub topics: Vec<H256>,
+    /// The non-indexed params of the event.
+    pub data: Vec<u8>,
+}
+
+/// What a proof needs from a transaction receipt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receipt {
+    /// False if the transaction was reverted.
+    pub success: bool,
+    /// The logs emitted by the transaction.
+    pub logs: Vec<Log>,
+}
+
+impl Receipt {
+    /// Decodes a receipt as it's stored in the receipts trie.
+    /// A typed receipt (EIP-2718) is its type followed by the RLP of the receipt, a legacy one is only the RLP. <br>
+    /// The receipts from before the Byzantium fork have the state root in place of the status, they can't be proven.
+    pub fn from_trie_value(bytes: &[u8]) -> Result<Self, ToolsError> {
+        const MALFORMED: ToolsError = ProofError { err: "malformed receipt" };
+        let payload = match bytes.first() {
+            Some(&kind) if kind < 0x80 => &bytes[1..],
+            Some(_) => bytes,
+            None => return Err(MALFORMED),
+        };
+        let rlp = UntrustedRlp::new(payload);
+        if !rlp.is_list() || rlp.item_count().map_err(|_| MALFORMED)? != 4 {
+            return Err(MALFORMED);
+        }
+        let success = match rlp.at(0).and_then(|status| status.data()).map_err(|_| MALFORMED)? {
+            [] => false,
+            [1] => true,
+            _ => return Err(ProofError { err: "the receipt has no status" }),
+        };
+        let logs = rlp.at(3).map_err(|_| MALFORMED)?;
+        let logs = logs.iter().map(|log| Log::decode(&log).map_err(|_| MALFORMED)).collect::<Result<_, _>>()?;
+        Ok(Receipt { success, logs })
+    }
+}
+
+impl Decodable for Log {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let address: Vec<u8> = rlp.val_at(0)?;
+        if address.len() != 20 {
+            return Err(DecoderError::RlpInvalidLength);
+        }
+        let topics: Vec<Vec<u8>> = rlp.list_at(1)?;
+        if topics.iter().any(|topic| topic.len() != 32) {
+            return Err(DecoderError::RlpInvalidLength);
+        }
+        Ok(Log {
+            address: H160::from_slice(&address),
+            topics: topics.iter().map(|topic| H256::from_slice(topic)).collect(),
+            data: rlp.val_at(2)?,
+        })
+    }
+}
+
+impl Encodable for Log {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.address.0.to_vec());
+        s.append_list::<Vec<u8>, _>(&self.topics.iter().map(|topic| topic.0.to_vec()).collect::<Vec<_>>());
+        s.append(&self.data);
+    }
+}
+
+/// Proves that the receipt of a transaction is in a block that descends from a checkpoint, with a given amount of
+/// blocks mined on top of it. The untrusted side builds it (see [`receipts_trie_proof`]), and the enclave checks it
+/// with [`ReceiptProof::verify`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReceiptProof {
+    /// The RLP of the headers that follow the checkpoint, each the parent of the next.
+    pub headers: Vec<Vec<u8>>,
+    /// The position in `headers` of the block that includes the transaction.
+    pub inclusion: u64,
+    /// The index of the transaction in its block, its receipt is stored under `rlp(tx_index)` in the receipts trie.
+    pub tx_index: u64,
+    /// The nodes of the receipts trie from its root to the receipt, see [`verify_trie_proof`].
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl ReceiptProof {
+    /// Decodes the RLP form of the proof, as the untrusted side sends it to the enclave.
+    pub fn from_rlp(bytes: &[u8]) -> Result<Self, ToolsError> {
+        UntrustedRlp::new(bytes).as_val().map_err(|_| ProofError { err: "malformed RLP" })
+    }
+
+    /// Checks the proof and returns the receipt, with the block that includes it as the next checkpoint.
+    /// The headers must start at the child of `checkpoint`, and the last one must have at least `confirmations`
+    /// blocks between it and the block of the receipt, like `EnigmaContract::wait_for_receipt` counts them. <br>
+    /// The next checkpoint isn't the last header, the blocks mined on top of the receipt's aren't confirmed themselves.
+    pub fn verify(&self, checkpoint: &Checkpoint, confirmations: u64) -> Result<(Receipt, Checkpoint), ToolsError> {
+        let headers = verify_headers(checkpoint, &self.headers)?;
+        let inclusion = headers.get(self.inclusion as usize)
+            .ok_or(ProofError { err: "the block of the receipt isn't in the headers" })?;
+        let tip = headers.last().map(|header| header.number).unwrap_or(checkpoint.number);
+        match inclusion.number.checked_add(confirmations) {
+            Some(confirmed) if tip >= confirmed => (),
+            _ => return Err(ProofError { err: "the block of the receipt doesn't have enough confirmations" }),
+        }
+        let value = verify_trie_proof(&inclusion.receipts_root, &rlp::encode(&self.tx_index), &self.nodes)?;
+        Ok((Receipt::from_trie_value(&value)?, inclusion.checkpoint()))
+    }
+}
+
+/// Decodes `headers`, the RLP of the headers that follow `checkpoint`, and checks that each is the child of the one
+/// before it, the first being the child of `checkpoint`.
+pub fn verify_headers(checkpoint: &Checkpoint, headers: &[Vec<u8>]) -> Result<Vec<BlockHeader>, ToolsError> {
+    let mut tip = *checkpoint;
+    let mut decoded = Vec::with_capacity(headers.len());
+    for header in headers {
+        let header = BlockHeader::from_rlp(header)?;
+        if header.parent_hash != tip.hash || Some(header.number) != tip.number.checked_add(1) {
+            return Err(ProofError { err: "the headers don't descend from the checkpoint" });
+        }
+        tip = header.checkpoint();
+        decoded.push(header);
+    }
+    Ok(decoded)
+}
+
+/// The RLP list of headers the untrusted side sends to move a checkpoint forward, see [`confirmed_checkpoint`].
+pub fn headers_to_rlp(headers: &[Vec<u8>]) -> Vec<u8> { rlp::encode_list::<Vec<u8>, _>(headers).to_vec() }
+
+/// Decodes the RLP of [`headers_to_rlp`].
+pub fn headers_from_rlp(bytes: &[u8]) -> Result<Vec<Vec<u8>>, ToolsError> {
+    UntrustedRlp::new(bytes).as_list().map_err(|_| ProofError { err: "malformed RLP" })
+}
+
+/// The checkpoint `headers` lead to with `confirmations` blocks on top of it, `None` if there are too few of them.
+/// Decodes and checks the headers like [`verify_headers`].
+pub fn confirmed_checkpoint(checkpoint: &Checkpoint, headers: &[Vec<u8>], confirmations: u64) -> Result<Option<Checkpoint>, ToolsError> {
+    let headers = verify_headers(checkpoint, headers)?;
+    let confirmed = (headers.len() as u64).checked_sub(confirmations).and_then(|count| count.checked_sub(1));
+    Ok(confirmed.map(|index| headers[index as usize].checkpoint()))
+}
+
+impl Encodable for ReceiptProof {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append_list::<Vec<u8>, _>(&self.headers);
+        s.append(&self.inclusion);
+        s.append(&self.tx_index);
+        s.append_list::<Vec<u8>, _>(&self.nodes);
+    }
+}
+
+impl Decodable for ReceiptProof {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(Self { headers: rlp.list_at(0)?, inclusion: rlp.val_at(1)?, tx_index: rlp.val_at(2)?, nodes: rlp.list_at(3)? })
+    }
+}
+
+fn h256(bytes: &[u8]) -> Result<H256, ToolsError> {
+    if bytes.len() != 32 {
+        return Err(ProofError { err: "a hash isn't 32 bytes long" });
+    }
+    Ok(H256::from_slice(bytes))
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> { key.iter().flat_map(|byte| vec![byte >> 4, byte & 0x0f]).collect() }
+
+// The hex-prefix encoding of the path of a leaf or an extension node.
+fn encode_path(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 0x20 } else { 0x00 };
+    let (mut path, rest) = match nibbles.split_first() {
+        Some((first, rest)) if nibbles.len() % 2 == 1 => (vec![flag | 0x10 | first], rest),
+        _ => (vec![flag], nibbles),
+    };
+    path.extend(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+    path
+}
+
+// The nibbles of a hex-prefix encoded path, and whether it's the path of a leaf.
+fn decode_path(path: &[u8]) -> Result<(Vec<u8>, bool), ToolsError> {
+    const MALFORMED: ToolsError = ProofError { err: "malformed trie node path" };
+    let (first, rest) = path.split_first().ok_or(MALFORMED)?;
+    let leaf = match first >> 4 {
+        0 | 1 => false,
+        2 | 3 => true,
+        _ => return Err(MALFORMED),
+    };
+    let mut nibbles = Vec::with_capacity(rest.len() * 2 + 1);
+    if first & 0x10 != 0 {
+        nibbles.push(first & 0x0f);
+    } else if first & 0x0f != 0 {
+        return Err(MALFORMED);
+    }
+    nibbles.extend(to_nibbles(rest));
+    Ok((nibbles, leaf))
+}
+
+/// Returns the value stored under `key` in the trie of `root`. <br>
+/// The proof is the nodes on the way from the root to the value, in that order, without the nodes shorter than 32 bytes
+/// which are embedded in their parent. A missing value is an error, there's nothing to prove about it.
+pub fn verify_trie_proof(root: &H256, key: &[u8], proof: &[Vec<u8>]) -> Result<Vec<u8>, ToolsError> {
+    let key = to_nibbles(key);
+    let mut proof = proof.iter();
+    let mut node = next_node(&mut proof, root)?;
+    let mut at = 0;
+    loop {
+        let rlp = UntrustedRlp::new(&node);
+        let malformed = |_| ProofError { err: "malformed trie node" };
+        let child = match rlp.item_count().map_err(malformed)? {
+            17 if at == key.len() => return value(&rlp.at(16).map_err(malformed)?),
+            17 => {
+                at += 1;
+                rlp.at(usize::from(key[at - 1])).map_err(malformed)?
+            }
+            2 => {
+                let (path, leaf) = decode_path(rlp.at(0).and_then(|path| path.data()).map_err(malformed)?)?;
+                if !key[at..].starts_with(&path) {
+                    return Err(ProofError { err: "the key isn't in the trie" });
+                }
+                at += path.len();
+                match (leaf, at == key.len()) {
+                    (true, true) => return value(&rlp.at(1).map_err(malformed)?),
+                    (true, false) => return Err(ProofError { err: "the key isn't in the trie" }),
+                    (false, _) => rlp.at(1).map_err(malformed)?,
+                }
+            }
+            _ => return Err(ProofError { err: "malformed trie node" }),
+        };
+        node = if child.is_list() {
+            child.as_raw().to_vec()
+        } else {
+            let hash = child.data().map_err(malformed)?;
+            if hash.is_empty() {
+                return Err(ProofError { err: "the key isn't in the trie" });
+            }
+            next_node(&mut proof, &h256(hash)?)?
+        };
+    }
+}
+
+fn next_node<'a, I: Iterator<Item = &'a Vec<u8>>>(proof: &mut I, hash: &H256) -> Result<Vec<u8>, ToolsError> {
+    match proof.next() {
+        Some(node) if eth_hash(node) == *hash => Ok(node.clone()),
+        Some(_) => Err(ProofError { err: "a trie node doesn't match its hash" }),
+        None => Err(ProofError { err: "the trie proof is missing nodes" }),
+    }
+}
+
+fn value(rlp: &UntrustedRlp) -> Result<Vec<u8>, ToolsError> {
+    match rlp.data() {
+        Ok(value) if !value.is_empty() => Ok(value.to_vec()),
+        Ok(_) => Err(ProofError { err: "the key isn't in the trie" }),
+        Err(_) => Err(ProofError { err: "malformed trie node" }),
+    }
+}
+
+/// Builds the receipts trie of a block and returns its root, with the proof of the receipt of `tx_index`
+/// for [`verify_trie_proof`]. `receipts` are in the form of [`Receipt::from_trie_value`], in the order of the block.
+pub fn receipts_trie_proof(receipts: &[Vec<u8>], tx_index: u64) -> (H256, Vec<Vec<u8>>) {
+    let entries: Vec<_> = receipts.iter().enumerate().map(|(i, receipt)| (rlp::encode(&(i as u64)).to_vec(), receipt.clone())).collect();
+    trie_proof(&entries, &rlp::encode(&tx_index))
+}
+
+/// Builds the trie of `entries` and returns its root, with the proof of the value of `key`
+/// (empty if it's not in the trie). The keys must be unique.
+pub fn trie_proof(entries: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> (H256, Vec<Vec<u8>>) {
+    if entries.is_empty() {
+        return (eth_hash(&rlp::NULL_RLP), Vec::new());
+    }
+    let mut items: Vec<_> = entries.iter().map(|(key, value)| (to_nibbles(key), &value[..])).collect();
+    items.sort();
+    let target = to_nibbles(key);
+    let mut proof = Vec::new();
+    let root = build_node(&items, 0, &target, &mut proof);
+    // The root is hashed even when it's short enough to be embedded
+    if root.len() < 32 && items.iter().any(|(key, _)| *key == target) {
+        proof.push(root.clone());
+    }
+    proof.reverse();
+    (eth_hash(&root), proof)
+}
+
+// The RLP of the node of the sorted `items` below `depth` nibbles. The nodes on the way to `target` that are
+// referenced by their hash are added to `proof`, children first.
+fn build_node(items: &[(Vec<u8>, &[u8])], depth: usize, target: &[u8], proof: &mut Vec<Vec<u8>>) -> Vec<u8> {
+    let mut s;
+    if items.len() == 1 {
+        s = RlpStream::new_list(2);
+        s.append(&encode_path(&items[0].0[depth..], true));
+        s.append(&items[0].1.to_vec());
+    } else {
+        let (first, last) = (&items[0].0[depth..], &items[items.len() - 1].0[depth..]);
+        let shared = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+        if shared > 0 {
+            s = RlpStream::new_list(2);
+            s.append(&encode_path(&first[..shared], false));
+            append_child(&mut s, &build_node(items, depth + shared, target, proof));
+        } else {
+            s = RlpStream::new_list(17);
+            // Sorted, so a key that ends here comes first
+            let (value, mut rest) = if items[0].0.len() == depth { (Some(items[0].1), &items[1..]) } else { (None, items) };
+            for nibble in 0..16 {
+                let count = rest.iter().take_while(|(key, _)| key[depth] == nibble).count();
+                match count {
+                    0 => { s.append_empty_data(); }
+                    _ => append_child(&mut s, &build_node(&rest[..count], depth + 1, target, proof)),
+                }
+                rest = &rest[count..];
+            }
+            match value {
+                Some(value) => { s.append(&value.to_vec()); }
+                None => { s.append_empty_data(); }
+            }
+        }
+    }
+    let node = s.out().to_vec();
+    if node.len() >= 32 && items.iter().any(|(key, _)| key[..] == target[..]) {
+        proof.push(node.clone());
+    }
+    node
+}
+
+fn append_child(s: &mut RlpStream, child: &[u8]) {
+    if child.len() < 32 {
+        s.append_raw(child, 1);
+    } else {
+        s.append(&eth_hash(child).0.to_vec());
+    }
+}
+
+/// A receipt for [`test_receipt_proof`], with only its status and logs.
+pub fn test_receipt(success: bool, logs: &[Log]) -> Vec<u8> {
+    let mut s = RlpStream::new_list(4);
+    s.append(&(success as u8));
+    s.append_empty_data();
+    s.append_empty_data();
+    s.append_list::<Log, _>(logs);
+    s.out().to_vec()
+}
+
+/// A proof for the tests that don't have a chain: `blocks` headers on top of `checkpoint`, the one at `inclusion`
+/// with `receipts` (see [`test_receipt`]), and the proof of the receipt of `tx_index` in it.
+/// The headers only have the fields a proof reads.
+pub fn test_receipt_proof(checkpoint: &Checkpoint, blocks: u64, inclusion: u64, receipts: &[Vec<u8>], tx_index: u64) -> ReceiptProof {
+    let (root, nodes) = receipts_trie_proof(receipts, tx_index);
+    let mut tip = *checkpoint;
+    let mut headers = Vec::new();
+    for i in 0..blocks {
+        let mut s = RlpStream::new_list(HEADER_MIN_FIELDS);
+        for field in 0..HEADER_MIN_FIELDS {
+            match field {
+                HEADER_PARENT_HASH => { s.append(&tip.hash.0.to_vec()); }
+                HEADER_RECEIPTS_ROOT if i == inclusion => { s.append(&root.0.to_vec()); }
+                HEADER_RECEIPTS_ROOT => { s.append(&eth_hash(&rlp::NULL_RLP).0.to_vec()); }
+                HEADER_NUMBER => { s.append(&(tip.number + 1)); }
+                _ => { s.append_empty_data(); }
+            }
+        }
+        let header = s.out().to_vec();
+        tip = Checkpoint { number: tip.number + 1, hash: eth_hash(&header) };
+        headers.push(header);
+    }
+    ReceiptProof { headers, inclusion, tx_index, nodes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::FromHex;
+
+    fn h256(hex: &str) -> H256 { H256::from_slice(&hex.from_hex::<Vec<u8>>().unwrap()) }
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs.iter().map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn test_trie_root() {
+        // The roots of the `puppy` and `dogs` tries of the Ethereum trie tests
+        let tries = vec![
+            (entries(&[("do", "verb"), ("dog", "puppy"), ("doge", "coin"), ("horse", "stallion")]),
+             h256("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84")),
+            (entries(&[("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]),
+             h256("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3")),
+        ];
+        for (trie, root) in &tries {
+            for (key, value) in trie {
+                let (proven_root, proof) = trie_proof(trie, key);
+                assert_eq!(proven_root, *root);
+                assert_eq!(&verify_trie_proof(root, key, &proof).unwrap(), value);
+            }
+        }
+        assert_eq!(trie_proof(&[], b"do").0, h256("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"));
+    }
+
+    #[test]
+    fn test_trie_proof_mismatch() {
+        let trie = entries(&[("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]);
+        let (root, proof) = trie_proof(&trie, b"dog");
+        // Another key, or the right key against another root
+        assert!(verify_trie_proof(&root, b"doge", &proof).is_err());
+        assert!(verify_trie_proof(&root, b"do", &proof).is_err());
+        assert!(verify_trie_proof(&eth_hash(b"root"), b"dog", &proof).is_err());
+        // A value that was changed doesn't hash to the node above it
+        let mut tampered = proof.clone();
+        let last = tampered.len() - 1;
+        let at = tampered[last].len() - 1;
+        tampered[last][at] ^= 1;
+        assert!(verify_trie_proof(&root, b"dog", &tampered).is_err());
+        assert!(verify_trie_proof(&root, b"dog", &proof[..proof.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_verify_receipt_proof() {
+        let contract = H160::from([5u8; 20]);
+        let log = |i: u8| Log { address: contract, topics: vec![H256::from([7u8; 32])], data: vec![i; 40] };
+        let receipts: Vec<_> = (0..200u8).map(|i| test_receipt(i != 3, &[log(i)])).collect();
+        let checkpoint = Checkpoint { number: 100, hash: eth_hash(b"checkpoint") };
+        for &tx_index in &[0u64, 3, 127, 128, 199] {
+            let proof = test_receipt_proof(&checkpoint, 8, 2, &receipts, tx_index);
+            let (receipt, next) = ReceiptProof::from_rlp(&rlp::encode(&proof)).unwrap().verify(&checkpoint, 5).unwrap();
+            assert_eq!(receipt.success, tx_index != 3);
+            assert_eq!(receipt.logs, vec![log(tx_index as u8)]);
+            assert_eq!(next, BlockHeader::from_rlp(&proof.headers[2]).unwrap().checkpoint());
+            assert_eq!(next.number, 103);
+        }
+    }
+
+    #[test]
+    fn test_receipt_proof_refused() {
+        let receipts = vec![test_receipt(true, &[])];
+        let checkpoint = Checkpoint { number: 100, hash: eth_hash(b"checkpoint") };
+        let proof = test_receipt_proof(&checkpoint, 8, 2, &receipts, 0);
+        assert!(proof.verify(&checkpoint, 5).is_ok());
+        // Not enough blocks on top of the receipt's
+        assert!(proof.verify(&checkpoint, 6).is_err());
+        // Not from the checkpoint
+        assert!(proof.verify(&Checkpoint { number: 100, hash: eth_hash(b"other") }, 5).is_err());
+        assert!(proof.verify(&Checkpoint { number: 99, hash: checkpoint.hash }, 5).is_err());
+        // A header missing in the middle
+        let mut gapped = proof.clone();
+        gapped.headers.remove(4);
+        assert!(gapped.verify(&checkpoint, 1).is_err());
+        // The receipt against another block
+        let mut moved = proof.clone();
+        moved.inclusion = 3;
+        assert!(moved.verify(&checkpoint, 1).is_err());
+        moved.inclusion = 8;
+        assert!(moved.verify(&checkpoint, 0).is_err());
+        // Another receipt than the one proven
+        let mut other = proof;
+        other.tx_index = 1;
+        assert!(other.verify(&checkpoint, 5).is_err());
+    }
+
+    #[test]
+    fn test_confirmed_checkpoint() {
+        let checkpoint = Checkpoint { number: 100, hash: eth_hash(b"checkpoint") };
+        let headers = test_receipt_proof(&checkpoint, 8, 0, &[], 0).headers;
+        let confirmed = confirmed_checkpoint(&checkpoint, &headers, 5).unwrap().unwrap();
+        assert_eq!(confirmed, BlockHeader::from_rlp(&headers[2]).unwrap().checkpoint());
+        assert_eq!(confirmed.number, 103);
+        assert_eq!(confirmed_checkpoint(&checkpoint, &headers, 7).unwrap().unwrap().number, 101);
+        assert_eq!(confirmed_checkpoint(&checkpoint, &headers, 8).unwrap(), None);
+        assert_eq!(confirmed_checkpoint(&checkpoint, &[], 0).unwrap(), None);
+        assert_eq!(headers_from_rlp(&headers_to_rlp(&headers)).unwrap(), headers);
+        assert!(confirmed_checkpoint(&Checkpoint { number: 100, hash: eth_hash(b"other") }, &headers, 0).is_err());
+    }
+
+    #[test]
+    fn test_typed_receipt() {
+        let legacy = test_receipt(true, &[Log { address: H160::from([5u8; 20]), topics: vec![], data: b"data".to_vec() }]);
+        let mut typed = vec![0x02];
+        typed.extend(&legacy);
+        assert_eq!(Receipt::from_trie_value(&typed).unwrap(), Receipt::from_trie_value(&legacy).unwrap());
+        // A receipt from before Byzantium has a state root instead of a status
+        let mut s = RlpStream::new_list(4);
+        s.append(&vec![1u8; 32]);
+        s.append_empty_data();
+        s.append_empty_data();
+        s.begin_list(0);
+        assert!(Receipt::from_trie_value(&s.out()).is_err());
+    }
+}
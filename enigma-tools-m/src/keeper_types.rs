@@ -1,13 +1,15 @@
 #![allow(missing_docs)] // This should be removed after @fredfortier will document this module.
 
-use crate::localstd::{vec, vec::Vec};
+use crate::localstd::{boxed::Box, vec, vec::Vec};
 use log::debug;
 use log_derive::logfn;
 
 use bigint;
-use crate::ethabi::{encode, Address, Bytes, Token};
-use crate::ethereum_types::{H160, U256};
-use enigma_crypto::hash::Keccak256;
+use crate::ethabi::{self, encode, Address, Bytes, ParamType, Token};
+use crate::serde::{Deserialize, Serialize};
+use crate::ethereum_types::{H160, H256, U256};
+use crate::common::errors::ToolsError::{self, NoWorkersInEpoch, WorkerParamsError};
+use crate::eth_hash::{eth_hash, event_topic};
 use enigma_types::ContractAddress;
 pub use rlp::{decode, encode as rlpEncode, Encodable, Decodable, DecoderError, UntrustedRlp, RlpStream};
 
@@ -44,14 +46,45 @@ impl RawEncodable for WorkerSelectionToken {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The workers of an epoch and their stakes, as the KM read them from the Enigma contract at `km_block_number`.
+/// The principal sends its RLP encoding to the enclave, and the serde form is the same with and without SGX.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
 pub struct InputWorkerParams {
+    #[serde(with = "hex_serde::uint")]
     pub km_block_number: U256,
+    #[serde(with = "hex_serde::addresses")]
     pub workers: Vec<Address>,
+    /// The stakes of `workers`, in the same order.
+    #[serde(with = "hex_serde::uints")]
     pub stakes: Vec<U256>,
 }
 
 impl InputWorkerParams {
+    /// Decodes the RLP form of the params, as the principal sends it to the enclave.
+    /// Unlike [`decode`] it returns an error on malformed bytes instead of panicking, the bytes come from the untrusted side.
+    pub fn from_rlp(bytes: &[u8]) -> Result<Self, ToolsError> {
+        UntrustedRlp::new(bytes).as_val().map_err(|_| WorkerParamsError { err: "malformed RLP" })
+    }
+
+    /// Check the worker params before they are accepted into an epoch.
+    /// An empty worker list is valid (it's flagged later by the worker selection),
+    /// but the stakes must match the workers, and there can't be more than `max_workers` of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_workers` - The maximum amount of workers allowed in a single epoch
+    ///
+    pub fn validate(&self, max_workers: usize) -> Result<(), ToolsError> {
+        if self.workers.len() != self.stakes.len() {
+            return Err(WorkerParamsError { err: "the amount of workers doesn't match the amount of stakes" });
+        }
+        if self.workers.len() > max_workers {
+            return Err(WorkerParamsError { err: "the amount of workers exceeds the maximum allowed" });
+        }
+        Ok(())
+    }
+
     /// Run the worker selection algorithm against the current epoch
     ///
     /// # Arguments
@@ -60,26 +93,30 @@ impl InputWorkerParams {
     /// * `seed` - The random seed for the selected epoch
     ///
     #[logfn(DEBUG)]
-    pub fn get_selected_worker(&self, sc_addr: ContractAddress, seed: U256) -> Option<Address> {
+    pub fn get_selected_worker(&self, sc_addr: ContractAddress, seed: U256) -> Result<Address, ToolsError> {
         debug!("Finding selected worker for sc_addr: {:?} and seed: {:?}", sc_addr, seed);
-        let workers = self.get_selected_workers(sc_addr, seed, None);
-        if workers.is_empty() {
-            None
-        } else {
-            Some(workers[0])
-        }
+        let workers = self.get_selected_workers(sc_addr, seed, None)?;
+        // `get_selected_workers` always selects at least one worker if there are any
+        Ok(workers[0])
     }
 
     #[logfn(DEBUG)]
-    fn get_selected_workers(&self, sc_addr: ContractAddress, seed: U256, group_size: Option<u64>) -> Vec<Address> {
-        let mut selected_workers = Vec::new();
-        if self.workers.is_empty() || self.workers.len() != self.stakes.len() {
+    fn get_selected_workers(&self, sc_addr: ContractAddress, seed: U256, group_size: Option<u64>) -> Result<Vec<Address>, ToolsError> {
+        if self.workers.is_empty() {
+            debug!("No workers in the epoch {:?}", self);
+            return Err(NoWorkersInEpoch);
+        }
+        if self.workers.len() != self.stakes.len() {
             debug!("Invalid worker selection parameters {:?}", self);
-            return selected_workers;
+            return Err(WorkerParamsError { err: "the amount of workers doesn't match the amount of stakes" });
         }
+        let mut selected_workers = Vec::new();
         let mut balance_sum = U256::zero();
         for &balance in &self.stakes {
-            balance_sum += balance;
+            balance_sum = balance_sum.checked_add(balance).ok_or(WorkerParamsError { err: "the sum of the stakes overflows" })?;
+        }
+        if balance_sum.is_zero() {
+            return Err(WorkerParamsError { err: "the workers have no stakes" });
         }
         // Using the same type as the Enigma contract
         let mut nonce = U256::zero();
@@ -88,8 +125,8 @@ impl InputWorkerParams {
         while selected_workers.len() < group_size as usize {
             let token = WorkerSelectionToken { seed, sc_addr, nonce };
             // This is equivalent to encodePacked in Solidity
-            let hash = token.raw_encode().keccak256();
-            let mut rand_val: U256 = U256::from(*hash) % balance_sum;
+            let hash = eth_hash(&token.raw_encode());
+            let mut rand_val: U256 = U256::from(hash.0) % balance_sum;
             debug!("The initial random value: {:?}", rand_val.0);
             let mut selected_worker = self.workers.last().unwrap();
 
@@ -108,7 +145,7 @@ impl InputWorkerParams {
             nonce += 1.into();
         }
         debug!("The selected workers: {:?}", selected_workers);
-        selected_workers
+        Ok(selected_workers)
     }
 }
 
@@ -130,3 +167,263 @@ impl Encodable for InputWorkerParams {
         s.append_list(&self.stakes.iter().map(|b| bigint::U256(b.0)).collect::<Vec<_>>());
     }
 }
+
+/// The names of the non-indexed params of the `WorkersParameterized` event, in the order the Enigma contract emits them.
+pub const WORKERS_PARAMETERIZED_PARAMS: [&str; 6] = ["seed", "firstBlockNumber", "inclusionBlockNumber", "workers", "stakes", "nonce"];
+
+/// The canonical signature of the `WorkersParameterized` event, with the types of [`EpochParams::param_types`].
+pub const WORKERS_PARAMETERIZED_SIGNATURE: &str = "WorkersParameterized(uint256,uint256,uint256,address[],uint256[],uint256)";
+
+/// What the Enigma contract emitted about an epoch in its `WorkersParameterized` event.
+/// The principal reads it from the receipt of `setWorkersParams`, and the enclave checks it against its sealed epoch
+/// before revealing a committed seed, both with [`EpochParams::decode`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct EpochParams {
+    /// The seed of the epoch, or `keccak256(seed)` for the contracts that take a commitment.
+    #[serde(with = "hex_serde::uint")]
+    pub seed: U256,
+    /// The first block of the epoch, decided by the contract.
+    #[serde(with = "hex_serde::uint")]
+    pub first_block_number: U256,
+    /// The block the `setWorkersParams` transaction was included in.
+    #[serde(with = "hex_serde::uint")]
+    pub inclusion_block_number: U256,
+    #[serde(with = "hex_serde::addresses")]
+    pub workers: Vec<Address>,
+    /// The stakes of `workers`, in the same order.
+    #[serde(with = "hex_serde::uints")]
+    pub stakes: Vec<U256>,
+    #[serde(with = "hex_serde::uint")]
+    pub nonce: U256,
+}
+
+impl EpochParams {
+    /// The types of the params of [`WORKERS_PARAMETERIZED_PARAMS`].
+    pub fn param_types() -> Vec<ParamType> {
+        vec![
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+            ParamType::Uint(256),
+        ]
+    }
+
+    /// The topic of the `WorkersParameterized` logs.
+    pub fn topic() -> H256 { event_topic(WORKERS_PARAMETERIZED_SIGNATURE) }
+
+    /// Decodes the data of a `WorkersParameterized` log.
+    pub fn decode(data: &[u8]) -> Result<Self, ToolsError> {
+        const MALFORMED: ToolsError = WorkerParamsError { err: "malformed WorkersParameterized event" };
+        let uint = |token: Token| token.to_uint().ok_or(MALFORMED);
+        let array = |token: Token| token.to_array().ok_or(MALFORMED);
+        let mut tokens = ethabi::decode(&Self::param_types(), data).map_err(|_| MALFORMED)?.into_iter();
+        let mut next = || tokens.next().ok_or(MALFORMED);
+        Ok(EpochParams {
+            seed: uint(next()?)?,
+            first_block_number: uint(next()?)?,
+            inclusion_block_number: uint(next()?)?,
+            workers: array(next()?)?.into_iter().map(|token| token.to_address().ok_or(MALFORMED)).collect::<Result<_, _>>()?,
+            stakes: array(next()?)?.into_iter().map(uint).collect::<Result<_, _>>()?,
+            nonce: uint(next()?)?,
+        })
+    }
+
+    /// The data of the `WorkersParameterized` log of these params, the inverse of [`EpochParams::decode`].
+    pub fn encode(&self) -> Bytes {
+        encode(&[
+            Token::Uint(self.seed),
+            Token::Uint(self.first_block_number),
+            Token::Uint(self.inclusion_block_number),
+            Token::Array(self.workers.iter().map(|worker| Token::Address(*worker)).collect()),
+            Token::Array(self.stakes.iter().map(|stake| Token::Uint(*stake)).collect()),
+            Token::Uint(self.nonce),
+        ])
+    }
+
+    /// True if the event is about the workers and stakes of `worker_params`.
+    pub fn matches(&self, worker_params: &InputWorkerParams) -> bool {
+        self.workers == worker_params.workers && self.stakes == worker_params.stakes
+    }
+}
+
+// The integers and the addresses as `0x` hex strings in the serde form.
+mod hex_serde {
+    use crate::localstd::{string::String, vec::Vec};
+    use crate::serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use rustc_hex::{FromHex, ToHex};
+
+    fn to_hex(bytes: &[u8]) -> String {
+        let mut hex = String::from("0x");
+        hex.push_str(&bytes.to_hex::<String>());
+        hex
+    }
+
+    fn from_hex<E: Error>(hex: &str, len: usize) -> Result<Vec<u8>, E> {
+        let bytes: Vec<u8> = hex.trim_start_matches("0x").from_hex().map_err(|_| E::custom("invalid hex"))?;
+        if bytes.len() > len {
+            return Err(E::invalid_length(bytes.len(), &"at most the size of the type"));
+        }
+        Ok(bytes)
+    }
+
+    pub mod uint {
+        use super::*;
+        use crate::ethereum_types::U256;
+
+        pub(crate) fn to_hex(value: &U256) -> String {
+            let mut bytes = [0u8; 32];
+            value.to_big_endian(&mut bytes);
+            super::to_hex(&bytes)
+        }
+
+        pub(crate) fn from_hex<E: Error>(hex: &str) -> Result<U256, E> { Ok(U256::from_big_endian(&super::from_hex::<E>(hex, 32)?)) }
+
+        pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> { serializer.serialize_str(&to_hex(value)) }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+            from_hex(&String::deserialize(deserializer)?)
+        }
+    }
+
+    pub mod uints {
+        use super::*;
+        use crate::ethereum_types::U256;
+
+        pub fn serialize<S: Serializer>(values: &[U256], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(values.iter().map(uint::to_hex))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<U256>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?.iter().map(|hex| uint::from_hex(hex)).collect()
+        }
+    }
+
+    pub mod addresses {
+        use super::*;
+        use crate::ethabi::Address;
+
+        pub fn serialize<S: Serializer>(addresses: &[Address], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(addresses.iter().map(|address| to_hex(&address.0)))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Address>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?
+                .iter()
+                .map(|hex| {
+                    let bytes = from_hex::<D::Error>(hex, 20)?;
+                    if bytes.len() != 20 {
+                        return Err(D::Error::invalid_length(bytes.len(), &"20 bytes"));
+                    }
+                    Ok(Address::from_slice(&bytes))
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker_params(count: usize) -> InputWorkerParams {
+        InputWorkerParams {
+            km_block_number: U256::from(1),
+            workers: (0..count).map(|i| H160::from([i as u8 + 1; 20])).collect(),
+            stakes: vec![U256::from(10); count],
+        }
+    }
+
+    #[test]
+    fn test_empty_workers() {
+        let params = worker_params(0);
+        assert!(params.validate(10).is_ok());
+        match params.get_selected_worker([1u8; 32].into(), U256::from(1)) {
+            Err(NoWorkersInEpoch) => (),
+            other => panic!("Expected NoWorkersInEpoch, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_max_workers() {
+        let params = worker_params(10);
+        assert!(params.validate(10).is_ok());
+        let worker = params.get_selected_worker([1u8; 32].into(), U256::from(1)).unwrap();
+        assert!(params.workers.contains(&worker));
+    }
+
+    #[test]
+    fn test_over_max_workers() {
+        let params = worker_params(11);
+        match params.validate(10) {
+            Err(WorkerParamsError { .. }) => (),
+            other => panic!("Expected WorkerParamsError, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_worker_params_serde() {
+        let params = InputWorkerParams { km_block_number: U256::from(258), workers: vec![H160::from([0xab; 20])], stakes: vec![U256::from(10)] };
+        let json = r#"{"km_block_number":"0x0000000000000000000000000000000000000000000000000000000000000102","workers":["0xabababababababababababababababababababab"],"stakes":["0x000000000000000000000000000000000000000000000000000000000000000a"]}"#;
+        assert_eq!(crate::serde_json::to_string(&params).unwrap(), json);
+        assert_eq!(crate::serde_json::from_str::<InputWorkerParams>(json).unwrap(), params);
+        // Shorter integers are accepted, addresses must be whole.
+        let short = r#"{"km_block_number":"0x0102","workers":[],"stakes":["0x0a"]}"#;
+        assert_eq!(crate::serde_json::from_str::<InputWorkerParams>(short).unwrap().km_block_number, U256::from(258));
+        assert!(crate::serde_json::from_str::<InputWorkerParams>(r#"{"km_block_number":"0x01","workers":["0xabab"],"stakes":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_epoch_params_event() {
+        let params = EpochParams {
+            seed: U256::from(7),
+            first_block_number: U256::from(100),
+            inclusion_block_number: U256::from(99),
+            workers: vec![H160::from([1; 20]), H160::from([2; 20])],
+            stakes: vec![U256::from(10), U256::from(20)],
+            nonce: U256::from(3),
+        };
+        let data = params.encode();
+        assert_eq!(EpochParams::decode(&data).unwrap(), params);
+        // The arrays are at the end, the stakes after the workers.
+        assert_eq!(&data[data.len() - 32..], &[&[0u8; 31][..], &[20]].concat()[..]);
+        let mut worker_params = worker_params(2);
+        assert!(!params.matches(&worker_params));
+        worker_params.stakes[1] = U256::from(20);
+        assert!(params.matches(&worker_params));
+        assert!(EpochParams::decode(&data[..data.len() - 1]).is_err());
+        assert!(EpochParams::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_worker_params_rlp() {
+        let params = worker_params(3);
+        assert_eq!(InputWorkerParams::from_rlp(&rlpEncode(&params)).unwrap(), params);
+        for bytes in &[&[][..], &[0xc0][..], &[0xc3, 0x01, 0xc0][..], &[0xf8, 0xff, 0x01][..]] {
+            assert!(InputWorkerParams::from_rlp(bytes).is_err(), "{:?}", bytes);
+        }
+        // A truncated list isn't taken for a shorter one.
+        let encoded = rlpEncode(&params);
+        assert!(InputWorkerParams::from_rlp(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_stakes_overflow() {
+        let mut params = worker_params(2);
+        params.stakes = vec![U256::max_value(), U256::from(1)];
+        match params.get_selected_worker([1u8; 32].into(), U256::from(1)) {
+            Err(WorkerParamsError { .. }) => (),
+            other => panic!("Expected WorkerParamsError, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_mismatched_stakes() {
+        let mut params = worker_params(3);
+        params.stakes.pop();
+        assert!(params.validate(10).is_err());
+        assert!(params.get_selected_worker([1u8; 32].into(), U256::from(1)).is_err());
+    }
+}
@@ -7,9 +7,15 @@
 //! This crate is Rust 2018 Edition,
 //! meaning there's no `extern crate` and `use` statements need to start with `crate`/`self`/`super`.
 
+pub mod audit;
 mod common;
+pub mod envelope;
+pub mod eth_hash;
+pub mod eth_proof;
 pub mod keeper_types;
 pub mod primitives;
+pub mod signable;
+pub mod trace;
 pub use crate::common::errors::ToolsError;
 pub use crate::common::utils;
 
@@ -51,22 +51,31 @@ pub struct PrincipalMessage {
     pub data: PrincipalMessageType,
     pub(crate) pubkey: Vec<u8>,
     pub(crate) id: MsgID,
+    /// The block whose epoch a Request is for, the latest epoch when it's missing.
+    /// It's left out of the message and of the signature when it's missing, so older messages stay valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) block_number: Option<u64>,
 }
 
 impl PrincipalMessage {
     /// This will create a new Message with a random MsgID.
     pub fn new(data: PrincipalMessageType, pubkey: PubKey) -> Result<Self, CryptoError> {
+        Self::new_at_block(data, pubkey, None)
+    }
+
+    /// This will create a new Message with a random MsgID, for the epoch that was active at `block_number`.
+    pub fn new_at_block(data: PrincipalMessageType, pubkey: PubKey, block_number: Option<u64>) -> Result<Self, CryptoError> {
         let mut id = [0u8; 12];
         rand::random(&mut id)?;
         let pubkey = pubkey.to_vec();
-        Ok(Self { data, pubkey, id })
+        Ok(Self { data, pubkey, id, block_number })
     }
 
     /// This should be used only by the KeyManagement node to create a response that will contain the same ID
     /// as the request.
     pub fn new_id(data: PrincipalMessageType, id: [u8; 12], pubkey: PubKey) -> Self {
         let pubkey = pubkey.to_vec();
-        Self { data, pubkey, id }
+        Self { data, pubkey, id, block_number: None }
     }
 
     /// This should serialize the struct for it to be signed, using [`enigma_crypto::hash::prepare_hash_multiple()`]
@@ -82,6 +91,9 @@ impl PrincipalMessage {
         }
         to_sign.push(self.pubkey.to_vec());
         to_sign.push(self.id.to_vec());
+        if let Some(block_number) = self.block_number {
+            to_sign.push(block_number.to_be_bytes().to_vec());
+        }
         Ok(hash::prepare_hash_multiple(&to_sign))
     }
 
@@ -116,6 +128,9 @@ impl PrincipalMessage {
     /// Will return the MsgID
     pub fn get_id(&self) -> MsgID { self.id }
 
+    /// Will return the block the Request is for, if it's pinned to one.
+    pub fn get_block_number(&self) -> Option<u64> { self.block_number }
+
     /// Check if the Message's data is a Request or not
     pub fn is_request(&self) -> bool {
         if let PrincipalMessageType::Request = self.data {
@@ -151,7 +166,7 @@ impl<'a> Encryption<&'a DhKey, CryptoError, Self, [u8; 12]> for PrincipalMessage
                 let mut buf = Vec::new();
                 response.serialize(&mut Serializer::new(&mut buf)).map_err(|_| CryptoError::EncryptionError)?;
                 let enc = symmetric::encrypt_with_nonce(&buf, key, _iv)?;
-                Ok(Self { data: PrincipalMessageType::EncryptedResponse(enc), pubkey: self.pubkey, id: self.id })
+                Ok(Self { data: PrincipalMessageType::EncryptedResponse(enc), pubkey: self.pubkey, id: self.id, block_number: self.block_number })
             }
             _ => Err(CryptoError::EncryptionError),
         }
@@ -164,7 +179,7 @@ impl<'a> Encryption<&'a DhKey, CryptoError, Self, [u8; 12]> for PrincipalMessage
                 let mut des = Deserializer::new(&dec[..]);
                 let data =
                     PrincipalMessageType::Response(Deserialize::deserialize(&mut des).map_err(|_| CryptoError::DecryptionError)?);
-                Ok(Self { data, pubkey: enc.pubkey, id: enc.id })
+                Ok(Self { data, pubkey: enc.pubkey, id: enc.id, block_number: enc.block_number })
             }
             _ => Err(CryptoError::EncryptionError),
         }
@@ -253,6 +268,20 @@ mod tests {
         assert_eq!(PrincipalMessage::from_message(&msg).unwrap(), res);
     }
 
+    #[test]
+    fn test_block_number() {
+        let req = get_request();
+        let mut at_block = req.clone();
+        at_block.block_number = Some(250);
+        assert_ne!(req.to_sign().unwrap(), at_block.to_sign().unwrap());
+
+        let msg = at_block.clone().into_message().unwrap();
+        let parsed = PrincipalMessage::from_message(&msg).unwrap();
+        assert_eq!(parsed.get_block_number(), Some(250));
+        assert_eq!(parsed, at_block);
+        assert_eq!(PrincipalMessage::from_message(&req.into_message().unwrap()).unwrap().get_block_number(), None);
+    }
+
     #[test]
     fn test_encrypt_response() {
         let enc = vec![195, 38, 192, 74, 88, 16, 137, 135, 207, 55, 231, 118, 249, 61, 195, 224, 63, 196, 241, 106, 78, 168, 173, 219, 207, 22, 170, 96, 122, 179, 196, 113, 182, 144, 124, 131, 226, 232, 197, 171, 8, 246, 211, 64, 243, 184, 206, 230, 208, 207, 182, 72, 131, 6, 120, 95, 206, 187, 5, 93, 183, 180, 62, 183, 196, 11, 161, 203, 226, 45, 171, 108, 240, 120, 203, 145, 26, 247, 128, 9, 133, 13, 233, 105, 131, 99, 154, 6, 136, 88, 112, 186, 196, 210, 190, 247, 96, 113, 70, 241, 163, 162, 242, 40, 207, 117, 148, 38, 133, 234, 100, 9, 6, 238, 251, 81, 181, 13, 139, 88, 187, 66, 195, 170, 245, 237, 230, 180, 217, 83, 84, 177, 247, 58, 173, 30, 222, 194, 21, 38, 221, 165, 196, 101, 20, 147, 103, 149, 3, 254, 248, 85, 234, 40, 48, 99, 143, 202, 4, 136, 97, 99, 71, 199, 145, 211, 106, 211, 10, 13, 212, 56, 205, 83, 38, 26, 172, 102, 146, 188, 97, 216, 195, 40, 65, 11, 156, 142, 206, 109, 224, 203, 26, 246, 51, 228, 203, 16, 143, 0, 224, 169, 119, 107, 133, 160, 125, 6, 57, 215, 241, 69, 189, 70, 30, 133, 117, 163, 77, 46, 166, 104, 204, 131, 247, 184, 139, 199, 104, 247, 72, 236, 187, 239, 245, 221, 81, 177, 206, 226, 9, 213, 226, 55, 119, 203, 44, 11, 47, 4, 152, 92, 202, 63, 68, 13, 34, 247, 12, 194, 170, 198, 35, 158, 95, 2, 22, 10, 128, 65, 254, 105, 194, 211, 14, 40, 248, 180, 84, 74, 147, 235, 226, 101, 81, 94, 57, 158, 3, 225, 145, 164, 141, 134, 157, 235, 199, 203, 180, 58, 131, 20, 41, 12, 202, 137, 49, 164, 239, 209, 182, 86, 146, 218, 12, 167, 211, 41, 216, 162, 24, 109, 136, 221, 234, 253, 193, 114, 145, 15, 188, 218, 48, 221, 247, 157, 210, 57, 238, 19, 209, 251, 102, 142, 100, 57, 221, 85, 38, 88, 191, 169, 128, 230, 8, 181, 156, 210, 190, 118, 13, 68, 47, 138, 4, 130, 174, 77, 76, 232, 70, 181, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, ];
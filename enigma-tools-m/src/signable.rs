@@ -0,0 +1,560 @@
+//! # Signable payloads.
+//! The canonical byte encodings of everything an enclave signs and someone else verifies.
+//! Both sides of the SGX (and the on-chain verifiers) must agree on these bytes exactly,
+//! so they are defined once here instead of at each call site. <br>
+//! Every payload implements [`Signable`], it is signed with `KeyPair::sign(&payload.to_signable_bytes())`
+//! and verified against an Ethereum address with [`Signable::verify`].
+//!
+//! The encodings are pinned by golden-byte tests, changing any of them is a protocol change.
+//!
+//! The enclaves hand out their signatures as `r || s || recovery id` ([`SigFormat::Recoverable`]),
+//! the untrusted side converts them with [`to_ethereum`] and [`to_compact`] to what its consumer expects.
+
+use crate::common::utils::EthereumAddress;
+use crate::ethereum_types::{H160, H256, U256};
+use crate::localstd::vec::Vec;
+use crate::serde::{Deserialize, Serialize};
+use enigma_crypto::{hash::prepare_hash_multiple, CryptoError, KeyPair};
+use enigma_types::{ContractAddress, Hash256, ResultStatus};
+
+/// Prefix of a single nested element.
+pub const ZERO: u8 = 0;
+/// Prefix of a nested list.
+pub const ONE: u8 = 1;
+
+/// Serialization used by the KM node for the epoch and worker selection payloads,
+/// according to this proof: https://github.com/enigmampc/protocol-discovery/blob/master/docs/hash_mul_nested.pdf <br>
+/// Every element is `prefix || u64_be(len) || data`, where lists are prefixed by [`ONE`] and leaves by [`ZERO`].
+pub trait NestedSerialization {
+    /// Encode `self` into its nested representation.
+    fn hash_encode(&self) -> Vec<u8>;
+}
+
+fn encode_leaf(msg: &[u8]) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::with_capacity(1 + 8 + msg.len());
+    res.push(ZERO);
+    res.extend_from_slice(&(msg.len() as u64).to_be_bytes());
+    res.extend_from_slice(msg);
+    res
+}
+
+impl NestedSerialization for U256 {
+    fn hash_encode(&self) -> Vec<u8> {
+        let mut msg = [0u8; 32];
+        self.to_big_endian(&mut msg);
+        encode_leaf(&msg)
+    }
+}
+
+impl NestedSerialization for H160 {
+    fn hash_encode(&self) -> Vec<u8> { encode_leaf(self.as_ref()) }
+}
+
+impl NestedSerialization for H256 {
+    fn hash_encode(&self) -> Vec<u8> { encode_leaf(self.as_ref()) }
+}
+
+impl<T: NestedSerialization> NestedSerialization for Vec<T> {
+    fn hash_encode(&self) -> Vec<u8> {
+        let messages: Vec<u8> = self.iter().flat_map(|value| value.hash_encode()).collect();
+        let mut res: Vec<u8> = Vec::with_capacity(1 + 8 + messages.len());
+        res.push(ONE);
+        res.extend_from_slice(&(messages.len() as u64).to_be_bytes());
+        res.extend_from_slice(&messages);
+        res
+    }
+}
+
+/// A payload with a single canonical encoding that gets signed by an enclave.
+pub trait Signable {
+    /// The exact bytes that are passed to `KeyPair::sign`.
+    fn to_signable_bytes(&self) -> Vec<u8>;
+
+    /// Returns true if `sig` is a signature over [`Signable::to_signable_bytes`] by the key behind the `signer` address.
+    /// Signatures the contracts would reject (high `s`, `v` other than 27/28) are an error.
+    fn verify(&self, sig: &[u8; 65], signer: &[u8; 20]) -> Result<bool, CryptoError> {
+        let pubkey = KeyPair::recover_canonical(&self.to_signable_bytes(), *sig)?;
+        Ok(&pubkey.address() == signer)
+    }
+}
+
+/// What `v`, the last byte of a 65 bytes `r || s || v` signature, holds. `r` and `s` are the same in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde", rename_all = "camelCase")]
+pub enum SigFormat {
+    /// The recovery id, 0 or 1. What the enclaves produce, see `KeyPair::sign_recoverable`.
+    Recoverable,
+    /// The recovery id + 27, what `ecrecover` and the contracts expect, and what `KeyPair::sign` returns.
+    Ethereum,
+}
+
+/// What [`SigFormat::Ethereum`] adds to the recovery id.
+pub const ETHEREUM_V_OFFSET: u8 = 27;
+
+impl SigFormat {
+    /// The format of `sig`, `None` if its `v` is neither a recovery id nor 27/28.
+    pub fn of(sig: &[u8; 65]) -> Option<SigFormat> {
+        match sig[64] {
+            0 | 1 => Some(SigFormat::Recoverable),
+            27 | 28 => Some(SigFormat::Ethereum),
+            _ => None,
+        }
+    }
+}
+
+/// Converts a [`SigFormat::Recoverable`] signature to the [`SigFormat::Ethereum`] one.
+pub fn to_ethereum(sig: &[u8; 65]) -> Result<[u8; 65], CryptoError> {
+    if SigFormat::of(sig) != Some(SigFormat::Recoverable) {
+        return Err(CryptoError::ParsingError { sig: *sig });
+    }
+    let mut eth = *sig;
+    eth[64] += ETHEREUM_V_OFFSET;
+    Ok(eth)
+}
+
+/// Converts a [`SigFormat::Ethereum`] signature to the [`SigFormat::Recoverable`] one.
+pub fn to_recoverable(sig: &[u8; 65]) -> Result<[u8; 65], CryptoError> {
+    if SigFormat::of(sig) != Some(SigFormat::Ethereum) {
+        return Err(CryptoError::ParsingError { sig: *sig });
+    }
+    let mut recoverable = *sig;
+    recoverable[64] -= ETHEREUM_V_OFFSET;
+    Ok(recoverable)
+}
+
+/// Splits a signature of either format into the 64 bytes `r || s` and the recovery id.
+pub fn to_compact(sig: &[u8; 65]) -> Result<([u8; 64], u8), CryptoError> {
+    let recovery_id = match SigFormat::of(sig) {
+        Some(SigFormat::Recoverable) => sig[64],
+        Some(SigFormat::Ethereum) => sig[64] - ETHEREUM_V_OFFSET,
+        None => return Err(CryptoError::ParsingError { sig: *sig }),
+    };
+    let mut compact = [0u8; 64];
+    compact.copy_from_slice(&sig[..64]);
+    Ok((compact, recovery_id))
+}
+
+/// Joins `r || s` and the recovery id (0/1) back into a [`SigFormat::Recoverable`] signature.
+pub fn from_compact(compact: &[u8; 64], recovery_id: u8) -> Result<[u8; 65], CryptoError> {
+    let mut sig = [0u8; 65];
+    sig[..64].copy_from_slice(&compact[..]);
+    sig[64] = recovery_id;
+    if recovery_id > 1 {
+        return Err(CryptoError::ParsingError { sig });
+    }
+    Ok(sig)
+}
+
+/// The epoch the KM node commits to when it sets the worker params: the seed, its nonce and the workers/stakes it applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpochSeed {
+    /// The random seed of the epoch.
+    pub seed: U256,
+    /// The nonce of the epoch, it must match the Ethereum tx.
+    pub nonce: U256,
+    /// The registered workers at the epoch's block.
+    pub workers: Vec<H160>,
+    /// The stakes of `workers`, in the same order.
+    pub stakes: Vec<U256>,
+}
+
+impl Signable for EpochSeed {
+    /// `nested(seed) || nested(nonce) || nested(workers) || nested(stakes)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let mut encoding: Vec<u8> = Vec::new();
+        encoding.extend_from_slice(&self.seed.hash_encode());
+        encoding.extend_from_slice(&self.nonce.hash_encode());
+        encoding.extend_from_slice(&self.workers.hash_encode());
+        encoding.extend_from_slice(&self.stakes.hash_encode());
+        encoding
+    }
+}
+
+/// Tags the [`EpochSeedCommitment`] encoding, so it can't be mistaken for an [`EpochSeed`] of `seed = commitment`.
+pub const EPOCH_SEED_COMMITMENT_TAG: &[u8] = b"EpochSeedCommitment";
+
+/// What the KM node commits to instead of the [`EpochSeed`] in the commit-reveal flow: `keccak256(seed)` in place of the seed,
+/// which the enclave only reveals once the commitment is on-chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpochSeedCommitment {
+    /// `keccak256` of the big-endian seed.
+    pub commitment: H256,
+    /// The nonce of the epoch, it must match the Ethereum tx.
+    pub nonce: U256,
+    /// The registered workers at the epoch's block.
+    pub workers: Vec<H160>,
+    /// The stakes of `workers`, in the same order.
+    pub stakes: Vec<U256>,
+}
+
+impl Signable for EpochSeedCommitment {
+    /// `leaf(EPOCH_SEED_COMMITMENT_TAG) || nested(commitment) || nested(nonce) || nested(workers) || nested(stakes)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let mut encoding: Vec<u8> = encode_leaf(EPOCH_SEED_COMMITMENT_TAG);
+        encoding.extend_from_slice(&self.commitment.hash_encode());
+        encoding.extend_from_slice(&self.nonce.hash_encode());
+        encoding.extend_from_slice(&self.workers.hash_encode());
+        encoding.extend_from_slice(&self.stakes.hash_encode());
+        encoding
+    }
+}
+
+/// The worker selected for a contract in a given epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerSelection {
+    /// The seed of the epoch the selection ran against.
+    pub seed: U256,
+    /// The nonce of that epoch.
+    pub nonce: U256,
+    /// The secret contract the worker was selected for.
+    pub contract_address: ContractAddress,
+    /// The selected worker.
+    pub worker: H160,
+}
+
+impl Signable for WorkerSelection {
+    /// `nested(seed) || nested(nonce) || nested(contract_address) || nested(worker)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let mut encoding: Vec<u8> = Vec::new();
+        encoding.extend_from_slice(&self.seed.hash_encode());
+        encoding.extend_from_slice(&self.nonce.hash_encode());
+        encoding.extend_from_slice(&H256(*self.contract_address).hash_encode());
+        encoding.extend_from_slice(&self.worker.hash_encode());
+        encoding
+    }
+}
+
+/// The receipt of a successful compute task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecuteReceipt {
+    /// Hash of the deployed bytecode that was executed.
+    pub exe_code_hash: Hash256,
+    /// Hash of the (encrypted) callable, args, contract address and user key.
+    pub inputs_hash: Hash256,
+    /// Hash of the delta the execution started from.
+    pub prev_delta_hash: Hash256,
+    /// Hash of the delta the execution produced.
+    pub delta_hash: Hash256,
+    /// Hash of the encrypted output.
+    pub output_hash: Hash256,
+    /// The gas limit of the task.
+    pub gas_limit: u64,
+    /// The gas used by the task.
+    pub used_gas: u64,
+    /// The Ethereum bridge payload, empty if there isn't one.
+    pub ethereum_payload: Vec<u8>,
+    /// The Ethereum bridge contract address, zeroed if there isn't one.
+    pub ethereum_address: [u8; 20],
+    /// Hash of the [`crate::envelope::OutputEnvelope`] of the output, if the task asked for one.
+    pub envelope_hash: Option<Hash256>,
+}
+
+impl Signable for ExecuteReceipt {
+    /// `prepare_hash_multiple(exeCodeHash, inputsHash, delta(X-1)Hash, deltaXHash, outputHash, gasLimit, usedGas, ethPayload, ethAddress, Ok)`,
+    /// with `envelopeHash` right before `Ok` when there's an envelope. Without one the encoding is the one the contracts verify.
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let gas_limit = self.gas_limit.to_be_bytes();
+        let used_gas = self.used_gas.to_be_bytes();
+        let ok = [ResultStatus::Ok as u8];
+        let mut to_sign: Vec<&[u8]> = Vec::with_capacity(11);
+        to_sign.extend_from_slice(&[
+            &self.exe_code_hash[..],
+            &self.inputs_hash[..],
+            &self.prev_delta_hash[..],
+            &self.delta_hash[..],
+            &self.output_hash[..],
+            &gas_limit[..],
+            &used_gas[..],
+            &self.ethereum_payload[..],
+            &self.ethereum_address[..],
+        ]);
+        if let Some(envelope_hash) = &self.envelope_hash {
+            to_sign.push(&envelope_hash[..]);
+        }
+        to_sign.push(&ok[..]);
+        prepare_hash_multiple(&to_sign)
+    }
+}
+
+/// The receipt of a successful deployment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeployReceipt {
+    /// Hash of the (encrypted) constructor, args, pre-code hash and user key.
+    pub inputs_hash: Hash256,
+    /// Hash of the resulting deployed bytecode.
+    pub exe_code_hash: Hash256,
+    /// Hash of the first delta.
+    pub delta_hash: Hash256,
+    /// The gas limit of the task.
+    pub gas_limit: u64,
+    /// The gas used by the constructor.
+    pub used_gas: u64,
+    /// The Ethereum bridge payload, empty if there isn't one.
+    pub ethereum_payload: Vec<u8>,
+    /// The Ethereum bridge contract address, zeroed if there isn't one.
+    pub ethereum_address: [u8; 20],
+}
+
+impl Signable for DeployReceipt {
+    /// `prepare_hash_multiple(inputsHash, exeCodeHash, delta0Hash, gasLimit, usedGas, ethPayload, ethAddress, Ok)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let to_sign: &[&[u8]] = &[
+            &self.inputs_hash[..],
+            &self.exe_code_hash[..],
+            &self.delta_hash[..],
+            &self.gas_limit.to_be_bytes(),
+            &self.used_gas.to_be_bytes(),
+            &self.ethereum_payload,
+            &self.ethereum_address,
+            &[ResultStatus::Ok as u8],
+        ];
+        prepare_hash_multiple(to_sign)
+    }
+}
+
+/// The receipt of a failed compute task or deployment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureReceipt {
+    /// Whatever was hashed before the failure, in order: the inputs hash, then the bytecode hash on compute.
+    pub pre_execution_data: Vec<Hash256>,
+    /// The gas limit of the task.
+    pub gas_limit: u64,
+    /// The gas used until the failure.
+    pub used_gas: u64,
+    /// The task was refused by the contract's access list, it's signed with `Forbidden` instead of `Failure`.
+    pub forbidden: bool,
+}
+
+impl Signable for FailureReceipt {
+    /// `prepare_hash_multiple(preExecutionData..., gasLimit, usedGas, Failure | Forbidden)`
+    fn to_signable_bytes(&self) -> Vec<u8> {
+        let gas_limit = self.gas_limit.to_be_bytes();
+        let used_gas = self.used_gas.to_be_bytes();
+        let status = if self.forbidden { ResultStatus::Forbidden } else { ResultStatus::Failure };
+        let failure = [status as u8];
+        let mut parts: Vec<&[u8]> = self.pre_execution_data.iter().map(|hash| &hash[..]).collect();
+        parts.push(&gas_limit);
+        parts.push(&used_gas);
+        parts.push(&failure);
+        prepare_hash_multiple(&parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::FromHex;
+
+    const PRIVKEY: [u8; 32] = [205, 189, 133, 79, 16, 70, 59, 246, 123, 227, 66, 64, 244, 188, 188, 147, 233, 252, 213, 133, 44, 157, 173, 141, 50, 93, 40, 130, 44, 99, 43, 205];
+
+    fn golden(hex: &str) -> Vec<u8> { hex.from_hex().unwrap() }
+
+    fn execute_receipt() -> ExecuteReceipt {
+        ExecuteReceipt {
+            exe_code_hash: [1u8; 32].into(),
+            inputs_hash: [2u8; 32].into(),
+            prev_delta_hash: [3u8; 32].into(),
+            delta_hash: [4u8; 32].into(),
+            output_hash: [5u8; 32].into(),
+            gas_limit: 100,
+            used_gas: 42,
+            ethereum_payload: Vec::new(),
+            ethereum_address: [0u8; 20],
+            envelope_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_nested_leaves() {
+        assert_eq!(U256::from(24).hash_encode(), golden("0000000000000000200000000000000000000000000000000000000000000000000000000000000018"));
+        assert_eq!(H160::from([2u8; 20]).hash_encode(), golden("0000000000000000140202020202020202020202020202020202020202"));
+        assert_eq!(Vec::<U256>::new().hash_encode(), golden("010000000000000000"));
+    }
+
+    #[test]
+    fn test_epoch_seed_golden() {
+        let empty = EpochSeed { seed: U256::from(90666), nonce: U256::from(0), workers: vec![], stakes: vec![] };
+        assert_eq!(
+            empty.to_signable_bytes(),
+            golden("000000000000000020000000000000000000000000000000000000000000000000000000000001622a0000000000000000200000000000000000000000000000000000000000000000000000000000000000010000000000000000010000000000000000")
+        );
+
+        let epoch = EpochSeed {
+            seed: U256::from(46661),
+            nonce: U256::from(1),
+            workers: vec![H160::from([0x11; 20]), H160::from([0x22; 20])],
+            stakes: vec![U256::from(10), U256::from(20)],
+        };
+        assert_eq!(
+            epoch.to_signable_bytes(),
+            golden("000000000000000020000000000000000000000000000000000000000000000000000000000000b645000000000000000020000000000000000000000000000000000000000000000000000000000000000101000000000000003a00000000000000001411111111111111111111111111111111111111110000000000000000142222222222222222222222222222222222222222010000000000000052000000000000000020000000000000000000000000000000000000000000000000000000000000000a0000000000000000200000000000000000000000000000000000000000000000000000000000000014")
+        );
+    }
+
+    #[test]
+    fn test_epoch_seed_commitment_golden() {
+        let commitment = EpochSeedCommitment {
+            commitment: H256::from([0xab; 32]),
+            nonce: U256::from(1),
+            workers: vec![H160::from([0x11; 20])],
+            stakes: vec![U256::from(10)],
+        };
+        assert_eq!(
+            commitment.to_signable_bytes(),
+            golden("00000000000000001345706f636853656564436f6d6d69746d656e74000000000000000020abababababababababababababababababababababababababababababababab000000000000000020000000000000000000000000000000000000000000000000000000000000000101000000000000001d0000000000000000141111111111111111111111111111111111111111010000000000000029000000000000000020000000000000000000000000000000000000000000000000000000000000000a")
+        );
+        // The same values as a seed aren't the same message
+        let seed = EpochSeed { seed: U256::from(&[0xab; 32]), nonce: commitment.nonce, workers: commitment.workers.clone(), stakes: commitment.stakes.clone() };
+        assert_ne!(seed.to_signable_bytes(), commitment.to_signable_bytes());
+    }
+
+    #[test]
+    fn test_worker_selection_golden() {
+        let selection = WorkerSelection {
+            seed: U256::from(1),
+            nonce: U256::from(2),
+            contract_address: [3u8; 32].into(),
+            worker: H160::from([4u8; 20]),
+        };
+        assert_eq!(
+            selection.to_signable_bytes(),
+            golden("0000000000000000200000000000000000000000000000000000000000000000000000000000000001000000000000000020000000000000000000000000000000000000000000000000000000000000000200000000000000002003030303030303030303030303030303030303030303030303030303030303030000000000000000140404040404040404040404040404040404040404")
+        );
+    }
+
+    #[test]
+    fn test_execute_receipt_golden() {
+        assert_eq!(
+            execute_receipt().to_signable_bytes(),
+            golden("0000000000000020010101010101010101010101010101010101010101010101010101010101010100000000000000200202020202020202020202020202020202020202020202020202020202020202000000000000002003030303030303030303030303030303030303030303030303030303030303030000000000000020040404040404040404040404040404040404040404040404040404040404040400000000000000200505050505050505050505050505050505050505050505050505050505050505000000000000000800000000000000640000000000000008000000000000002a000000000000000000000000000000140000000000000000000000000000000000000000000000000000000101")
+        );
+    }
+
+    #[test]
+    fn test_execute_receipt_envelope_golden() {
+        let receipt = ExecuteReceipt { envelope_hash: Some([6u8; 32].into()), ..execute_receipt() };
+        assert_eq!(
+            receipt.to_signable_bytes(),
+            golden("0000000000000020010101010101010101010101010101010101010101010101010101010101010100000000000000200202020202020202020202020202020202020202020202020202020202020202000000000000002003030303030303030303030303030303030303030303030303030303030303030000000000000020040404040404040404040404040404040404040404040404040404040404040400000000000000200505050505050505050505050505050505050505050505050505050505050505000000000000000800000000000000640000000000000008000000000000002a00000000000000000000000000000014000000000000000000000000000000000000000000000000000000200606060606060606060606060606060606060606060606060606060606060606000000000000000101")
+        );
+        // Not the same message as the receipt without an envelope, nor as one with another envelope.
+        assert_ne!(receipt.to_signable_bytes(), execute_receipt().to_signable_bytes());
+        let other = ExecuteReceipt { envelope_hash: Some([7u8; 32].into()), ..execute_receipt() };
+        assert_ne!(receipt.to_signable_bytes(), other.to_signable_bytes());
+    }
+
+    #[test]
+    fn test_deploy_receipt_golden() {
+        let receipt = DeployReceipt {
+            inputs_hash: [1u8; 32].into(),
+            exe_code_hash: [2u8; 32].into(),
+            delta_hash: [3u8; 32].into(),
+            gas_limit: 100,
+            used_gas: 42,
+            ethereum_payload: vec![0xaa, 0xbb],
+            ethereum_address: [9u8; 20],
+        };
+        assert_eq!(
+            receipt.to_signable_bytes(),
+            golden("000000000000002001010101010101010101010101010101010101010101010101010101010101010000000000000020020202020202020202020202020202020202020202020202020202020202020200000000000000200303030303030303030303030303030303030303030303030303030303030303000000000000000800000000000000640000000000000008000000000000002a0000000000000002aabb00000000000000140909090909090909090909090909090909090909000000000000000101")
+        );
+    }
+
+    #[test]
+    fn test_failure_receipt_golden() {
+        let receipt = FailureReceipt { pre_execution_data: vec![[1u8; 32].into(), [2u8; 32].into()], gas_limit: 100, used_gas: 42, forbidden: false };
+        assert_eq!(
+            receipt.to_signable_bytes(),
+            golden("0000000000000020010101010101010101010101010101010101010101010101010101010101010100000000000000200202020202020202020202020202020202020202020202020202020202020202000000000000000800000000000000640000000000000008000000000000002a000000000000000100")
+        );
+        let forbidden = FailureReceipt { forbidden: true, ..receipt };
+        assert_eq!(
+            forbidden.to_signable_bytes(),
+            golden("0000000000000020010101010101010101010101010101010101010101010101010101010101010100000000000000200202020202020202020202020202020202020202020202020202020202020202000000000000000800000000000000640000000000000008000000000000002a000000000000000102")
+        );
+    }
+
+    #[test]
+    fn test_receipt_matches_sign_multiple() {
+        // Existing verifiers hash the parts with `prepare_hash_multiple`, the receipt must sign the exact same message.
+        let keys = KeyPair::from_slice(&PRIVKEY).unwrap();
+        let receipt = execute_receipt();
+        let to_sign: &[&[u8]] = &[
+            &receipt.exe_code_hash[..], &receipt.inputs_hash[..], &receipt.prev_delta_hash[..], &receipt.delta_hash[..],
+            &receipt.output_hash[..], &receipt.gas_limit.to_be_bytes(), &receipt.used_gas.to_be_bytes(),
+            &receipt.ethereum_payload, &receipt.ethereum_address, &[ResultStatus::Ok as u8],
+        ];
+        let legacy = keys.sign_multiple(to_sign).unwrap();
+        assert_eq!(&keys.sign(&receipt.to_signable_bytes()).unwrap()[..], &legacy[..]);
+    }
+
+    #[test]
+    fn test_sig_format_round_trip() {
+        let keys = KeyPair::from_slice(&PRIVKEY).unwrap();
+        let msg = execute_receipt().to_signable_bytes();
+        let recoverable = keys.sign_recoverable(&msg).unwrap();
+        assert_eq!(SigFormat::of(&recoverable), Some(SigFormat::Recoverable));
+
+        let eth = to_ethereum(&recoverable).unwrap();
+        assert_eq!(SigFormat::of(&eth), Some(SigFormat::Ethereum));
+        assert_eq!(&eth[..], &keys.sign(&msg).unwrap()[..]);
+        assert_eq!(&to_recoverable(&eth).unwrap()[..], &recoverable[..]);
+
+        let (compact, recovery_id) = to_compact(&recoverable).unwrap();
+        assert_eq!(to_compact(&eth).unwrap(), (compact, recovery_id));
+        assert_eq!(&compact[..], &recoverable[..64]);
+        assert_eq!(&from_compact(&compact, recovery_id).unwrap()[..], &recoverable[..]);
+
+        // Converting twice, or from the wrong format, is refused instead of producing a bad `v`.
+        assert!(to_ethereum(&eth).is_err());
+        assert!(to_recoverable(&recoverable).is_err());
+        assert!(from_compact(&compact, 27).is_err());
+        let mut bad = eth;
+        bad[64] = 2;
+        assert!(SigFormat::of(&bad).is_none() && to_compact(&bad).is_err());
+    }
+
+    #[test]
+    fn test_sig_formats_recover() {
+        // Every payload an enclave signs: converted to the Ethereum form it recovers like `ecrecover` would.
+        let keys = KeyPair::from_slice(&PRIVKEY).unwrap();
+        let signer = keys.get_pubkey().address();
+        let epoch = EpochSeed { seed: U256::from(7), nonce: U256::from(1), workers: vec![H160::from([0x11; 20])], stakes: vec![U256::from(10)] };
+        let payloads: Vec<Vec<u8>> = vec![
+            epoch.to_signable_bytes(),
+            EpochSeedCommitment { commitment: H256::from([0xab; 32]), nonce: epoch.nonce, workers: epoch.workers.clone(), stakes: epoch.stakes.clone() }
+                .to_signable_bytes(),
+            WorkerSelection { seed: epoch.seed, nonce: epoch.nonce, contract_address: [3u8; 32].into(), worker: H160::from([4u8; 20]) }
+                .to_signable_bytes(),
+            execute_receipt().to_signable_bytes(),
+            DeployReceipt { inputs_hash: [1u8; 32].into(), exe_code_hash: [2u8; 32].into(), delta_hash: [3u8; 32].into(), gas_limit: 100,
+                            used_gas: 42, ethereum_payload: vec![], ethereum_address: [0u8; 20] }.to_signable_bytes(),
+            FailureReceipt { pre_execution_data: vec![[1u8; 32].into()], gas_limit: 100, used_gas: 42, forbidden: true }.to_signable_bytes(),
+            crate::audit::AuditDigest { count: 3, head: [5u8; 32].into() }.to_signable_bytes(),
+        ];
+        for msg in payloads {
+            let recoverable = keys.sign_recoverable(&msg).unwrap();
+            let eth = to_ethereum(&recoverable).unwrap();
+            assert_eq!(&KeyPair::recover_canonical(&msg, eth).unwrap()[..], &keys.get_pubkey()[..]);
+            let (compact, recovery_id) = to_compact(&eth).unwrap();
+            let rebuilt = to_ethereum(&from_compact(&compact, recovery_id).unwrap()).unwrap();
+            assert_eq!(KeyPair::recover_canonical(&msg, rebuilt).unwrap().address(), signer);
+            // The enclave form isn't what `ecrecover` takes.
+            assert!(KeyPair::recover_canonical(&msg, recoverable).is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let keys = KeyPair::from_slice(&PRIVKEY).unwrap();
+        let signer = keys.get_pubkey().address();
+        let receipt = execute_receipt();
+        let sig = keys.sign(&receipt.to_signable_bytes()).unwrap();
+        assert!(receipt.verify(&sig, &signer).unwrap());
+        assert!(!receipt.verify(&sig, &[0u8; 20]).unwrap());
+
+        let mut tampered = receipt.clone();
+        tampered.used_gas += 1;
+        assert!(!tampered.verify(&sig, &signer).unwrap());
+    }
+}
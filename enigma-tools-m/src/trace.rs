@@ -0,0 +1,67 @@
+//! # Execution Traces.
+//! What a contract did while a task ran, recorded by the core enclave for the contract's author when asked to in dev mode. <br>
+//! A trace never holds the decrypted arguments, state or output, only their sizes,
+//! and the keccak256 of the state keys that were read or written.
+
+use crate::localstd::string::String;
+use crate::localstd::vec::Vec;
+use crate::serde::{Deserialize, Serialize};
+use enigma_types::Hash256;
+
+/// The calls recorded after this many are only counted, so a contract looping over the host functions can't exhaust the enclave.
+pub const MAX_TRACE_CALLS: usize = 1024;
+
+/// A call of the contract to a host function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct HostCall {
+    /// The name of the host function (`write_state`, `read_state`, `ret`, ...).
+    pub name: String,
+    /// The sizes of the buffers the contract passed or got back, in the order of the function's arguments.
+    pub sizes: Vec<u64>,
+    /// The keccak256 of the state key, for the functions that take one.
+    pub key_hash: Option<Hash256>,
+    /// The gas used when the function was called, the gas of a section is the difference between two calls.
+    pub gas: u64,
+}
+
+/// The host calls of a task and what it produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "crate::serde")]
+pub struct ExecutionTrace {
+    /// The first `MAX_TRACE_CALLS` calls, in order.
+    pub calls: Vec<HostCall>,
+    /// How many calls came after them.
+    pub dropped: u64,
+    /// The gas used by the whole task, the last section ends there.
+    pub used_gas: u64,
+    /// The size of the encrypted delta, `None` if the task didn't produce one (it failed or didn't change the state).
+    pub delta_size: Option<u64>,
+}
+
+impl ExecutionTrace {
+    /// Records a call, or only counts it if the trace is full.
+    pub fn record(&mut self, name: &str, sizes: &[u64], key_hash: Option<Hash256>, gas: u64) {
+        if self.calls.len() >= MAX_TRACE_CALLS {
+            self.dropped += 1;
+            return;
+        }
+        self.calls.push(HostCall { name: name.into(), sizes: sizes.to_vec(), key_hash, gas });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_bounded() {
+        let mut trace = ExecutionTrace::default();
+        for i in 0..MAX_TRACE_CALLS as u64 + 3 {
+            trace.record("gas", &[i], None, i);
+        }
+        assert_eq!(trace.calls.len(), MAX_TRACE_CALLS);
+        assert_eq!(trace.dropped, 3);
+        assert_eq!(trace.calls[MAX_TRACE_CALLS - 1].gas, MAX_TRACE_CALLS as u64 - 1);
+    }
+}
@@ -0,0 +1,18 @@
+use std::fs;
+use std::path::PathBuf;
+
+#[path = "../fuzz/checks/keeper_types.rs"]
+mod checks;
+
+// The inputs the fuzzing sessions kept, so a regression is caught by the tests without fuzzing.
+#[test]
+fn test_keeper_types_corpus() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/keeper_types");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "No corpus in {:?}", dir);
+    for path in inputs {
+        println!("{:?}", path);
+        checks::check(&fs::read(&path).unwrap());
+    }
+}
@@ -141,6 +141,9 @@ pub enum FailedTaskError {
 
     #[fail(display = "Invocation resulted in gas limit violated")]
     GasLimitError,
+
+    #[fail(display = "The caller isn't in the access list of the contract")]
+    Forbidden,
 }
 
 #[derive(Debug, Fail, Clone)]
@@ -168,6 +171,21 @@ pub enum EnclaveSystemError {
 
     #[fail(display = "Failed to provide state key: {}", err)]
     KeyProvisionError { err: String },
+
+    #[fail(display = "There are no workers in the epoch")]
+    NoWorkersInEpoch,
+
+    #[fail(display = "Invalid worker params: {}", err)]
+    WorkerParamsError { err: String },
+
+    #[fail(display = "The random number generator failed {} times in a row", attempts)]
+    RandUnavailable { attempts: u32 },
+
+    #[fail(display = "The signing key isn't initialized")]
+    SigningKeyUninitialized,
+
+    #[fail(display = "The epoch seed can't be revealed: {}", err)]
+    SeedNotRevealed { err: String },
 }
 
 impl From<CryptoError> for EnclaveError {
@@ -179,7 +197,10 @@ impl From<CryptoError> for EnclaveError {
 impl From<ToolsError> for EnclaveError {
     fn from(err: ToolsError) -> Self {
         match err {
-            ToolsError::MessagingError {err} => EnclaveError::SystemError(EnclaveSystemError::MessagingError { err: err.to_string() })
+            ToolsError::MessagingError {err} => EnclaveError::SystemError(EnclaveSystemError::MessagingError { err: err.to_string() }),
+            ToolsError::NoWorkersInEpoch => EnclaveError::SystemError(EnclaveSystemError::NoWorkersInEpoch),
+            ToolsError::WorkerParamsError {err} => EnclaveError::SystemError(EnclaveSystemError::WorkerParamsError { err: err.to_string() }),
+            ToolsError::ProofError { .. } => EnclaveError::SystemError(EnclaveSystemError::MessagingError { err: err.to_string() }),
         }
     }
 }
@@ -223,6 +244,8 @@ impl Into<EnclaveReturn> for EnclaveError {
         debug_println!("creating EnclaveReturn from EnclaveError: {:?}", self);
         use self::EnclaveError::*;
         match self {
+            FailedTaskError(self::FailedTaskError::Forbidden)
+            | FailedTaskErrorWithGas { err: self::FailedTaskError::Forbidden, .. } => EnclaveReturn::Forbidden,
             FailedTaskError {..} => EnclaveReturn::TaskFailure,
             FailedTaskErrorWithGas {..} => EnclaveReturn::TaskFailure,
             SystemError(e) => {
@@ -246,10 +269,16 @@ impl Into<EnclaveReturn> for EnclaveError {
                         | ImproperEncryption
                         | ParsingError { ..}
                         | RecoveryError { .. }
+                        | NonCanonicalSignature { .. }
                         => EnclaveReturn::EncryptionError,
                     }
                     WorkerAuthError { .. } => EnclaveReturn::WorkerAuthError,
                     KeyProvisionError { .. } => EnclaveReturn::KeyProvisionError,
+                    NoWorkersInEpoch => EnclaveReturn::NoWorkersInEpoch,
+                    WorkerParamsError { .. } => EnclaveReturn::InvalidWorkerParams,
+                    RandUnavailable { .. } => EnclaveReturn::RandUnavailable,
+                    SigningKeyUninitialized => EnclaveReturn::SigningKeyUninitialized,
+                    SeedNotRevealed { .. } => EnclaveReturn::SeedNotRevealed,
                  }
 
              }
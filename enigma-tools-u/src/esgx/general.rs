@@ -1,8 +1,11 @@
 use sgx_types::{sgx_attributes_t, sgx_launch_token_t, sgx_misc_attribute_t, SgxResult};
 use sgx_urts::SgxEnclave;
 use std::path::{PathBuf, Path};
+use std::sync::RwLock;
 use failure::Error;
 
+lazy_static! { static ref STORAGE_NAMESPACE: RwLock<Option<String>> = RwLock::new(None); }
+
 pub fn storage_dir<P: AsRef<Path>>(dir_name: P) -> Result<PathBuf, Error> {
     let mut path = dirs::home_dir().ok_or_else(|| {
         format_err!("Missing home directory")
@@ -12,6 +15,22 @@ pub fn storage_dir<P: AsRef<Path>>(dir_name: P) -> Result<PathBuf, Error> {
     Ok(path)
 }
 
+/// Sets a sub directory for everything the enclave seals (e.g. the network name),
+/// so two nodes configured differently don't overwrite each other's keys.
+/// This has to be called before the enclave is created.
+pub fn set_storage_namespace(namespace: Option<String>) {
+    *STORAGE_NAMESPACE.write().unwrap_or_else(|e| e.into_inner()) = namespace;
+}
+
+/// The directory the enclave seals into: `storage_dir(dir_name)`, under the storage namespace if one was set.
+pub fn enclave_storage_dir<P: AsRef<Path>>(dir_name: P) -> Result<PathBuf, Error> {
+    let mut path = storage_dir(dir_name)?;
+    if let Some(namespace) = &*STORAGE_NAMESPACE.read().unwrap_or_else(|e| e.into_inner()) {
+        path.push(namespace);
+    }
+    Ok(path)
+}
+
 pub fn init_enclave(enclave_location: &str)
     -> SgxResult<(SgxEnclave)> {
     let mut launch_token: sgx_launch_token_t = [0; 1024];
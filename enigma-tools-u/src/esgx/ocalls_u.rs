@@ -8,7 +8,7 @@ pub static ENCLAVE_DIR: &'static str = ".enigma";
 
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_home(output: *mut u8, result_len: &mut usize) {
-    let path = general::storage_dir(ENCLAVE_DIR).unwrap(); // TODO: Handle the Error here. it wasn't handled before.
+    let path = general::enclave_storage_dir(ENCLAVE_DIR).unwrap(); // TODO: Handle the Error here. it wasn't handled before.
     let path_str = path.to_str().unwrap();
     ptr::copy_nonoverlapping(path_str.as_c_ptr(), output, path_str.len());
     *result_len = path_str.len();
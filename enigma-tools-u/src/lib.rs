@@ -3,6 +3,7 @@
 
 extern crate enigma_crypto;
 extern crate enigma_types;
+extern crate enigma_tools_m;
 #[macro_use]
 extern crate failure;
 extern crate reqwest;
@@ -21,12 +22,13 @@ extern crate sgx_urts;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
+#[macro_use]
+extern crate lazy_static;
 
 #[macro_use]
 extern crate log_derive;
 extern crate ethabi;
 extern crate ethereum_types;
-extern crate tiny_keccak;
 
 extern crate gethostname;
 
@@ -0,0 +1,174 @@
+//! Builds the block headers and `ReceiptProof`s the KM enclave checks the chain with, see `enigma_tools_m::eth_proof`.
+//! web3 doesn't give every field of a header, so the blocks and receipts are taken as JSON from the node and
+//! encoded again the way they're hashed into the chain.
+
+use failure::Error;
+use hex::FromHex;
+use rlp::RlpStream;
+use serde_json::{self, Value};
+use web3::futures::Future;
+use web3::transports::Http;
+use web3::types::{H256, U256};
+use web3::{Transport, Web3};
+
+use enigma_tools_m::eth_hash::eth_hash;
+use enigma_tools_m::eth_proof::{receipts_trie_proof, BlockHeader, Checkpoint, ReceiptProof};
+
+use crate::common_u::errors::Web3Error;
+
+// The fields of a header in the order they're hashed, the quantities are marked, the rest are data.
+const HEADER_FIELDS: &[(&str, bool)] = &[
+    ("parentHash", false),
+    ("sha3Uncles", false),
+    ("miner", false),
+    ("stateRoot", false),
+    ("transactionsRoot", false),
+    ("receiptsRoot", false),
+    ("logsBloom", false),
+    ("difficulty", true),
+    ("number", true),
+    ("gasLimit", true),
+    ("gasUsed", true),
+    ("timestamp", true),
+    ("extraData", false),
+    ("mixHash", false),
+    ("nonce", false),
+];
+
+// The fields the forks since London append to a header, a block has a prefix of them.
+const FORK_HEADER_FIELDS: &[(&str, bool)] = &[
+    ("baseFeePerGas", true),
+    ("withdrawalsRoot", false),
+    ("blobGasUsed", true),
+    ("excessBlobGas", true),
+    ("parentBeaconBlockRoot", false),
+    ("requestsHash", false),
+];
+
+fn web3_error(message: String) -> Error { Web3Error { message }.into() }
+
+fn rpc(web3: &Web3<Http>, method: &str, params: Vec<Value>) -> Result<Value, Error> {
+    let result = web3.transport().execute(method, params).wait()
+        .map_err(|e| web3_error(format!("{} failed: {:?}", method, e)))?;
+    if result.is_null() {
+        return Err(web3_error(format!("{} found nothing", method)));
+    }
+    Ok(result)
+}
+
+fn field<'a>(json: &'a Value, name: &str) -> Result<&'a str, Error> {
+    json[name].as_str().ok_or_else(|| web3_error(format!("The node didn't give the {} field", name)))
+}
+
+fn data(hex: &str) -> Result<Vec<u8>, Error> {
+    hex.trim_start_matches("0x").from_hex().map_err(|e| web3_error(format!("Malformed data {}: {:?}", hex, e)))
+}
+
+fn quantity(hex: &str) -> Result<U256, Error> {
+    hex.trim_start_matches("0x").parse().map_err(|e| web3_error(format!("Malformed quantity {}: {:?}", hex, e)))
+}
+
+fn hash(hex: &str) -> Result<H256, Error> {
+    let bytes = data(hex)?;
+    if bytes.len() != 32 {
+        return Err(web3_error(format!("Malformed hash {}", hex)));
+    }
+    Ok(H256::from_slice(&bytes))
+}
+
+fn block(web3: &Web3<Http>, number: u64) -> Result<Value, Error> {
+    rpc(web3, "eth_getBlockByNumber", vec![Value::String(format!("0x{:x}", number)), Value::Bool(false)])
+}
+
+// The RLP of the header of `block`, checked against the hash the node gave for it.
+fn header_rlp(block: &Value) -> Result<Vec<u8>, Error> {
+    let forks = FORK_HEADER_FIELDS.iter().take_while(|(name, _)| !block[*name].is_null()).count();
+    let mut s = RlpStream::new_list(HEADER_FIELDS.len() + forks);
+    for &(name, is_quantity) in HEADER_FIELDS.iter().chain(&FORK_HEADER_FIELDS[..forks]) {
+        let value = field(block, name)?;
+        if is_quantity {
+            s.append(&quantity(value)?);
+        } else {
+            s.append(&data(value)?);
+        }
+    }
+    let header = s.out();
+    if eth_hash(&header) != hash(field(block, "hash")?)? {
+        return Err(web3_error(format!("The header of block {} doesn't hash to the block", field(block, "number")?)));
+    }
+    Ok(header)
+}
+
+// A receipt as it's stored in the receipts trie, see `Receipt::from_trie_value`.
+fn receipt_rlp(receipt: &Value) -> Result<Vec<u8>, Error> {
+    let mut s = RlpStream::new_list(4);
+    match receipt["status"].as_str() {
+        Some(status) => { s.append(&quantity(status)?); }
+        None => { s.append(&data(field(receipt, "root")?)?); }
+    }
+    s.append(&quantity(field(receipt, "cumulativeGasUsed")?)?);
+    s.append(&data(field(receipt, "logsBloom")?)?);
+    let logs = receipt["logs"].as_array().ok_or_else(|| web3_error("The node didn't give the logs field".to_string()))?;
+    s.begin_list(logs.len());
+    for log in logs {
+        let topics = log["topics"].as_array().ok_or_else(|| web3_error("The node didn't give the topics field".to_string()))?;
+        s.begin_list(3);
+        s.append(&data(field(log, "address")?)?);
+        s.begin_list(topics.len());
+        for topic in topics {
+            let topic = topic.as_str().ok_or_else(|| web3_error("Malformed topic".to_string()))?;
+            s.append(&data(topic)?);
+        }
+        s.append(&data(field(log, "data")?)?);
+    }
+    let mut encoded = s.out();
+    let kind = match receipt["type"].as_str() {
+        Some(kind) => quantity(kind)?.low_u64(),
+        None => 0,
+    };
+    if kind != 0 {
+        encoded.insert(0, kind as u8);
+    }
+    Ok(encoded)
+}
+
+/// The RLP of the headers of the blocks `from` to `to`, both included.
+pub fn block_headers(web3: &Web3<Http>, from: u64, to: u64) -> Result<Vec<Vec<u8>>, Error> {
+    (from..=to).map(|number| header_rlp(&block(web3, number)?)).collect()
+}
+
+/// The block `number` as a checkpoint.
+pub fn checkpoint(web3: &Web3<Http>, number: u64) -> Result<Checkpoint, Error> {
+    let header = header_rlp(&block(web3, number)?)?;
+    Ok(BlockHeader::from_rlp(&header)?.checkpoint())
+}
+
+/// The proof of the receipt of `tx_hash` on top of `checkpoint`, with the headers up to `confirmations` blocks
+/// past the block of the receipt, which must be mined already.
+pub fn receipt_proof(web3: &Web3<Http>, tx_hash: H256, checkpoint: &Checkpoint, confirmations: u64) -> Result<ReceiptProof, Error> {
+    let receipt = rpc(web3, "eth_getTransactionReceipt", vec![serde_json::to_value(tx_hash)?])?;
+    let block_number = quantity(field(&receipt, "blockNumber")?)?.low_u64();
+    let tx_index = quantity(field(&receipt, "transactionIndex")?)?.low_u64();
+    if block_number <= checkpoint.number {
+        return Err(web3_error(format!("The receipt of {:?} is in block {}, not after the checkpoint {}", tx_hash, block_number, checkpoint.number)));
+    }
+    let included = block(web3, block_number)?;
+    let transactions = included["transactions"].as_array()
+        .ok_or_else(|| web3_error("The node didn't give the transactions field".to_string()))?;
+    let receipts = transactions.iter()
+        .map(|tx| receipt_rlp(&rpc(web3, "eth_getTransactionReceipt", vec![tx.clone()])?))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let (receipts_root, nodes) = receipts_trie_proof(&receipts, tx_index);
+
+    let headers = block_headers(web3, checkpoint.number + 1, block_number + confirmations)?;
+    let inclusion = block_number - checkpoint.number - 1;
+    let header = BlockHeader::from_rlp(&headers[inclusion as usize])?;
+    // The chain may have been reorganized between the calls
+    if header.hash != hash(field(&receipt, "blockHash")?)? || header.hash != hash(field(&included, "hash")?)? {
+        return Err(web3_error(format!("Block {} changed while the proof of {:?} was built", block_number, tx_hash)));
+    }
+    if header.receipts_root != receipts_root {
+        return Err(web3_error(format!("The receipts of block {} don't match its receipts root", block_number)));
+    }
+    Ok(ReceiptProof { headers, inclusion, tx_index, nodes })
+}
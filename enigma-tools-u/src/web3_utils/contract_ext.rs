@@ -106,7 +106,7 @@ pub fn signed_call_with_confirmations<T: Transport, P>(
     func: &str,
     params: P,
     // network details
-    mut options: Options,
+    options: Options,
     chain_id: u64,
     confirmations: usize,
     signer: &Box<dyn EcdsaSign + Send + Sync>,
@@ -115,7 +115,32 @@ pub fn signed_call_with_confirmations<T: Transport, P>(
         P: Tokenize,
 {
     let poll_interval = std::time::Duration::from_secs(1);
+    let signed_tx = signed_call(web3, contract_abi, contract_address, from, func, params, options, chain_id, signer)?;
 
+    Ok(confirm::send_raw_transaction_with_confirmation(
+        web3.eth().transport().clone(),
+        signed_tx,
+        poll_interval,
+        confirmations,
+    ))
+}
+
+/// Builds and signs the same transaction as `signed_call_with_confirmations` without sending it,
+/// so the caller can keep it and send it again as is (the nonce is taken when signing).
+pub fn signed_call<T: Transport, P>(
+    web3: &web3::api::Web3<T>,
+    contract_abi: &ethabi::Contract,
+    contract_address: Address,
+    from: Address,
+    func: &str,
+    params: P,
+    mut options: Options,
+    chain_id: u64,
+    signer: &Box<dyn EcdsaSign + Send + Sync>,
+) -> Result<Bytes, Error>
+    where
+        P: Tokenize,
+{
     let function = contract_abi.function(func)?;
     let fn_data = function.encode_input(&params.into_tokens())?;
 
@@ -144,16 +169,8 @@ pub fn signed_call_with_confirmations<T: Transport, P>(
     hashed.copy_from_slice(&tx_hash[..]);
 
     let sig: [u8; 65] = signer.sign_hashed(&hashed);
-
     let v = calculate_eth_recovery_id(sig[64], chain_id);
-    let signed_tx = tx.raw_sign(sig[0..32].to_vec(), sig[32..64].to_vec(), v);
-
-    Ok(confirm::send_raw_transaction_with_confirmation(
-        web3.eth().transport().clone(),
-        Bytes::from(signed_tx),
-        poll_interval,
-        confirmations,
-    ))
+    Ok(Bytes::from(tx.raw_sign(sig[0..32].to_vec(), sig[32..64].to_vec(), v)))
 }
 
 // The actual calculation of V is [rec_id + chain * 2 + 35], but we expect v to already
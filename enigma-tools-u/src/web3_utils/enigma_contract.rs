@@ -1,21 +1,25 @@
 use std::path::Path;
 use std::str;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use failure::Error;
 use hex::{FromHex};
 use web3::contract::{Contract, Options};
 use web3::futures::Future;
 use web3::transports::{EventLoopHandle, Http};
-use web3::types::{Address, Bytes, H160, H256, TransactionReceipt, U256};
+use web3::types::{Address, Bytes, H160, H256, TransactionId, TransactionReceipt, U256};
 use web3::Web3;
 
 use enigma_crypto::EcdsaSign;
+use enigma_tools_m::eth_hash::eth_hash;
+use enigma_tools_m::eth_proof::{Checkpoint, ReceiptProof};
 use enigma_types::ContractAddress;
 
 use crate::common_u::errors;
-use crate::web3_utils::w3utils;
-use super::contract_ext::signed_call_with_confirmations;
+use crate::web3_utils::{chain_proof, w3utils};
+use super::contract_ext::{signed_call, signed_call_with_confirmations};
 
 // This should be used as the main Web3/EventLoop
 // Creating another one means more threads and more things to handle.
@@ -67,12 +71,62 @@ impl EnigmaContract {
     }
 
     pub fn address(&self) -> Address { self.w3_contract.address() }
+
+    /// Sends a transaction signed with `ContractFuncs::sign_workers_params` (or the like) unless the node already has it.
+    /// Sending the same signed transaction any number of times can only get it mined once.
+    pub fn send_raw_transaction_once(&self, signed_tx: &Bytes) -> Result<H256, Error> {
+        let tx_hash = eth_hash(&signed_tx.0);
+        let known = self.web3.eth().transaction(TransactionId::Hash(tx_hash)).wait().map_err(|e|
+            errors::Web3Error { message: format!("Unable to fetch the transaction {:?}: {:?}", tx_hash, e) }
+        )?;
+        if known.is_none() {
+            self.web3.eth().send_raw_transaction(signed_tx.clone()).wait().map_err(|e|
+                errors::Web3Error { message: format!("Unable to send the transaction {:?}: {:?}", tx_hash, e) }
+            )?;
+        }
+        Ok(tx_hash)
+    }
+
+    /// Waits until the transaction is mined and `confirmations` blocks were mined on top of it.
+    pub fn wait_for_receipt(&self, tx_hash: H256, confirmations: usize, poll_interval: Duration) -> Result<TransactionReceipt, Error> {
+        loop {
+            let receipt = self.web3.eth().transaction_receipt(tx_hash).wait().map_err(|e|
+                errors::Web3Error { message: format!("Unable to fetch the receipt of {:?}: {:?}", tx_hash, e) }
+            )?;
+            if let Some(receipt) = receipt {
+                if let Some(mined) = receipt.block_number {
+                    let block_number = self.web3.eth().block_number().wait().map_err(|e|
+                        errors::Web3Error { message: format!("Current block number not available: {:?}", e) }
+                    )?;
+                    if block_number >= mined + U256::from(confirmations) {
+                        return Ok(receipt);
+                    }
+                }
+            }
+            thread::sleep(poll_interval);
+        }
+    }
+
+    /// The block `number` as a checkpoint of the KM enclave, see `chain_proof`.
+    pub fn checkpoint(&self, number: u64) -> Result<Checkpoint, Error> { chain_proof::checkpoint(&self.web3, number) }
+
+    /// The RLP of the headers of the blocks `from` to `to`, both included.
+    pub fn block_headers(&self, from: u64, to: u64) -> Result<Vec<Vec<u8>>, Error> { chain_proof::block_headers(&self.web3, from, to) }
+
+    /// The proof for the KM enclave that the transaction `tx_hash` is in a block after `checkpoint`,
+    /// with `confirmations` blocks mined on top of it.
+    pub fn receipt_proof(&self, tx_hash: H256, checkpoint: &Checkpoint, confirmations: u64) -> Result<ReceiptProof, Error> {
+        chain_proof::receipt_proof(&self.web3, tx_hash, checkpoint, confirmations)
+    }
 }
 
 pub trait ContractFuncs<G> {
     fn register(&self, staking_address: H160, signing_address: H160, report: String, signature: String, gas: G, confirmations: usize) -> Result<TransactionReceipt, Error>;
 
     fn set_workers_params(&self, block_number: U256, seed: U256, sig: Bytes, gas: G, confirmations: usize) -> Result<TransactionReceipt, Error>;
+
+    // setWorkersParams, signed but not sent, see `EnigmaContract::send_raw_transaction_once`
+    fn sign_workers_params(&self, block_number: U256, seed: U256, sig: Bytes, gas: G) -> Result<Bytes, Error>;
 }
 
 impl<G: Into<U256>> ContractFuncs<G> for EnigmaContract {
@@ -125,6 +179,24 @@ impl<G: Into<U256>> ContractFuncs<G> for EnigmaContract {
         )?;
         Ok(receipt)
     }
+
+    #[logfn(DEBUG)]
+    fn sign_workers_params(&self, block_number: U256, seed: U256, sig: Bytes, gas: G) -> Result<Bytes, Error> {
+        let mut opts: Options = Options::default();
+        opts.gas = Some(gas.into());
+        let signed_tx = signed_call(
+            &self.web3,
+            &self.ethabi_contract,
+            self.w3_contract.address(),
+            self.account,
+            "setWorkersParams",
+            (block_number, seed, sig.0),
+            opts,
+            self.chain_id,
+            &self.signer,
+        )?;
+        Ok(signed_tx)
+    }
 }
 
 pub trait ContractQueries {
@@ -1,4 +1,5 @@
 mod raw_transaction;
 mod contract_ext;
+pub mod chain_proof;
 pub mod enigma_contract;
 pub mod w3utils;
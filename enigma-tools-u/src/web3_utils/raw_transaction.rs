@@ -1,6 +1,6 @@
 use ethereum_types::{H160, U256};
 use rlp::RlpStream;
-use tiny_keccak::keccak256;
+use enigma_tools_m::eth_hash::eth_hash;
 
 /// Description of a Transaction, pending or in the chain.
 #[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
@@ -54,7 +54,7 @@ impl RawTransaction {
         hash.append(&mut U256::zero());
         hash.append(&mut U256::zero());
         hash.finalize_unbounded_list();
-        keccak256_hash(&hash.out())
+        eth_hash(&hash.out()).0.to_vec()
     }
 
     fn encode(&self, s: &mut RlpStream) {
@@ -71,10 +71,6 @@ impl RawTransaction {
     }
 }
 
-fn keccak256_hash(bytes: &[u8]) -> Vec<u8> {
-    keccak256(bytes).iter().cloned().collect()
-}
-
 mod test {
     #[test]
     fn test_signs_transaction_eth() {
@@ -19,7 +19,7 @@ use web3::types::BlockNumber;
 use web3::types::FilterBuilder;
 use web3::Web3;
 
-use enigma_crypto::hash::Keccak256;
+use enigma_tools_m::eth_hash::event_topic;
 
 // files
 use crate::common_u::errors;
@@ -123,7 +123,7 @@ pub fn deploy_contract<P>(web3: &Web3<Http>, tx_params: &DeployParams, ctor_para
 
 fn build_event_filter(event_name: &str, contract_addr: Option<&str>) -> web3::types::Filter {
     let filter = FilterBuilder::default()
-        .topics(Some(vec![(*event_name.as_bytes().keccak256()).into()]), None, None, None)
+        .topics(Some(vec![event_topic(event_name)]), None, None, None)
         .from_block(BlockNumber::Earliest)
         .to_block(BlockNumber::Latest);
     match contract_addr {
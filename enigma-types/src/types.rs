@@ -64,6 +64,18 @@ pub enum EnclaveReturn {
     // TODO: should consider merging with a different error.
     /// Missing StateKeys in the KM node.
     KeyProvisionError,
+    /// NoWorkersInEpoch, the epoch has no registered workers so there's no one to select, this is specific to the KM node.
+    NoWorkersInEpoch,
+    /// InvalidWorkerParams, the worker params are malformed (i.e. too many workers, workers/stakes mismatch), this is specific to the KM node.
+    InvalidWorkerParams,
+    /// Forbidden, the caller isn't in the contract's access list. The task failed and is signed like a `TaskFailure`, with the `Forbidden` status.
+    Forbidden,
+    /// RandUnavailable, the hardware random number generator kept failing (i.e. RDRAND exhausted under load), the call can be retried.
+    RandUnavailable,
+    /// SigningKeyUninitialized, the enclave's signing key couldn't be loaded, nothing was signed or stored.
+    SigningKeyUninitialized,
+    /// SeedNotRevealed, the epoch seed was asked for before its commitment was proven on-chain, this is specific to the KM node.
+    SeedNotRevealed,
     /// Something went really wrong.
     Other
 }
@@ -77,6 +89,8 @@ pub enum ResultStatus {
     Ok = 1,
     /// Failure = Error = 0.
     Failure = 0,
+    /// Forbidden = 2, a task refused by the contract's access list, it's only signed in failure receipts.
+    Forbidden = 2,
 }
 
 
@@ -94,10 +108,80 @@ pub struct ExecuteResult {
     pub ethereum_payload_ptr: *const u8,
     /// The ethereum address that the payload belongs to.
     pub ethereum_address: [u8; 20],
-    /// A signature by the enclave on all of the results.
+    /// A signature by the enclave on all of the results, `r || s || recovery id` (0/1).
     pub signature: [u8; 65],
     /// The gas used by the execution.
     pub used_gas: u64,
+    /// A pointer to the serialized trace of the execution if one was asked for (on the untrusted stack), null otherwise.
+    pub trace_ptr: *const u8,
+    // The fields of the receipt the signature is over, so the untrusted side doesn't have to recompute any of them.
+    /// Hash of the (encrypted) inputs.
+    pub inputs_hash: [u8; 32],
+    /// Hash of the executed bytecode, or of the deployed one for a deployment.
+    pub exe_code_hash: [u8; 32],
+    /// Hash of the bytecode that was deployed, zeroed for a compute task.
+    pub pre_code_hash: [u8; 32],
+    /// Hash of the delta the execution started from, zeroed for a deployment.
+    pub prev_delta_hash: [u8; 32],
+    /// Hash of the delta the execution produced.
+    pub delta_hash: [u8; 32],
+    /// Hash of the encrypted output, zeroed for a deployment.
+    pub output_hash: [u8; 32],
+    /// The gas limit of the task.
+    pub gas_limit: u64,
+    /// A pointer to the output envelope addressed to the user if one was asked for (on the untrusted stack), null otherwise.
+    pub envelope_ptr: *const u8,
+    /// Hash of the output envelope, zeroed if there isn't one.
+    pub envelope_hash: [u8; 32],
+    /// A pointer to the IO key of a compute task sealed by the enclave (on the untrusted stack), for the replay ecall. Null otherwise.
+    pub sealed_io_key_ptr: *const u8,
+}
+
+/// The receipt a compute task was executed with, as it was signed, for the replay ecall to check before executing the task again.
+/// The signature is passed next to it, and the Ethereum payload apart since it isn't of a fixed size.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayReceipt {
+    /// Hash of the (encrypted) inputs.
+    pub inputs_hash: [u8; 32],
+    /// Hash of the executed bytecode.
+    pub exe_code_hash: [u8; 32],
+    /// Hash of the delta the execution started from.
+    pub prev_delta_hash: [u8; 32],
+    /// Hash of the delta the execution produced.
+    pub delta_hash: [u8; 32],
+    /// Hash of the encrypted output.
+    pub output_hash: [u8; 32],
+    /// The gas limit of the task.
+    pub gas_limit: u64,
+    /// The gas used by the task.
+    pub used_gas: u64,
+    /// The Ethereum bridge contract address, zeroed if there isn't one.
+    pub ethereum_address: [u8; 20],
+    /// Hash of the output envelope, only signed if `has_envelope` is non zero.
+    pub envelope_hash: [u8; 32],
+    pub has_envelope: u8,
+}
+
+/// This struct is what the replay ecall returns, the comparison of a task executed again with what it produced the first time.
+/// Nothing in it is secret, the enclave compares the output and the delta itself and only says whether they match.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayResult {
+    /// Hash of the (encrypted) inputs, as executed again.
+    pub inputs_hash: [u8; 32],
+    /// Hash of the executed bytecode.
+    pub exe_code_hash: [u8; 32],
+    /// Hash of the delta the execution started from.
+    pub prev_delta_hash: [u8; 32],
+    /// The gas used by the execution.
+    pub used_gas: u64,
+    /// Non zero if the decrypted output is the recorded one.
+    pub output_matches: u8,
+    /// Non zero if the delta is the recorded one once decrypted, or if neither execution produced one.
+    pub delta_matches: u8,
+    /// Non zero if the task failed this time, the other fields are only set up to where it failed.
+    pub failed: u8,
 }
 
 /// This struct is a wrapper to a raw pointer.
@@ -182,6 +266,9 @@ impl Default for ExecuteResult {
             output: ptr::null(),
             delta_ptr: ptr::null(),
             ethereum_payload_ptr: ptr::null(),
+            trace_ptr: ptr::null(),
+            envelope_ptr: ptr::null(),
+            sealed_io_key_ptr: ptr::null(),
             .. unsafe { mem::zeroed() }
         }
     }
@@ -197,6 +284,17 @@ impl fmt::Debug for ExecuteResult {
         debug_trait_builder.field("ethereum_address", &(self.ethereum_address));
         debug_trait_builder.field("signature", &(&self.signature[..]));
         debug_trait_builder.field("used_gas", &(self.used_gas));
+        debug_trait_builder.field("trace_ptr", &(self.trace_ptr));
+        debug_trait_builder.field("inputs_hash", &(self.inputs_hash));
+        debug_trait_builder.field("exe_code_hash", &(self.exe_code_hash));
+        debug_trait_builder.field("pre_code_hash", &(self.pre_code_hash));
+        debug_trait_builder.field("prev_delta_hash", &(self.prev_delta_hash));
+        debug_trait_builder.field("delta_hash", &(self.delta_hash));
+        debug_trait_builder.field("output_hash", &(self.output_hash));
+        debug_trait_builder.field("gas_limit", &(self.gas_limit));
+        debug_trait_builder.field("envelope_ptr", &(self.envelope_ptr));
+        debug_trait_builder.field("envelope_hash", &(self.envelope_hash));
+        debug_trait_builder.field("sealed_io_key_ptr", &(self.sealed_io_key_ptr));
         debug_trait_builder.finish()
     }
 }
@@ -223,6 +321,12 @@ impl fmt::Display for EnclaveReturn {
             MessagingError => "EnclaveReturn: MessagingError",
             WorkerAuthError => "EnclaveReturn: WorkerAuthError",
             KeyProvisionError => "EnclaveReturn: KeyProvisionError",
+            NoWorkersInEpoch => "EnclaveReturn: NoWorkersInEpoch",
+            InvalidWorkerParams => "EnclaveReturn: InvalidWorkerParams",
+            Forbidden => "EnclaveReturn: Forbidden",
+            RandUnavailable => "EnclaveReturn: RandUnavailable",
+            SigningKeyUninitialized => "EnclaveReturn: SigningKeyUninitialized",
+            SeedNotRevealed => "EnclaveReturn: SeedNotRevealed",
             Other => "EnclaveReturn: Other",
         };
         write!(f, "{}", p)
@@ -0,0 +1,30 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+#[pub_interface]
+pub trait ContractInterface{
+    fn construct(allowed: Vec<u8>);
+    fn increment() -> U256;
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    /// Only the user with the `allowed` pubkey can call the contract.
+    fn construct(allowed: Vec<u8>) {
+        let mut pubkey = [0u8; 64];
+        pubkey.copy_from_slice(&allowed);
+        allow_caller(&pubkey);
+    }
+
+    fn increment() -> U256 {
+        let counter: u64 = read_state!("counter").unwrap_or_default();
+        write_state!("counter" => counter + 1);
+        (counter + 1).into()
+    }
+}